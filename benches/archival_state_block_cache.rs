@@ -0,0 +1,80 @@
+use divan::Bencher;
+use neptune_core::config_models::data_directory::DataDirectory;
+use neptune_core::config_models::network::Network;
+use neptune_core::models::blockchain::block::Block;
+use neptune_core::models::state::archival_state::ArchivalState;
+use neptune_core::models::state::block_cache::DEFAULT_BLOCK_CACHE_CAPACITY;
+use rand::distributions::{Alphanumeric, DistString};
+
+fn main() {
+    divan::main();
+}
+
+/// Resynchronizing the membership proofs of many UTXOs across a fork reads
+/// the same blocks from the archival state once per UTXO. These benchmarks
+/// mimic that access pattern, with and without the block cache.
+mod get_same_blocks_repeatedly {
+    use super::*;
+
+    // number of UTXOs that are resynchronized across the same blocks
+    const NUM_UTXOS: usize = 100;
+
+    async fn archival_state_with_stored_block(block_cache_capacity: usize) -> ArchivalState {
+        let network = Network::RegTest;
+        let data_dir = DataDirectory::get(
+            Some(
+                std::env::temp_dir()
+                    .join("neptune-benchmarks")
+                    .join(Alphanumeric.sample_string(&mut rand::thread_rng(), 16)),
+            ),
+            network,
+        )
+        .unwrap();
+        let block_index_db = ArchivalState::initialize_block_index_database(&data_dir)
+            .await
+            .unwrap();
+        let archival_mutator_set = ArchivalState::initialize_mutator_set(&data_dir)
+            .await
+            .unwrap();
+        let mut archival_state = ArchivalState::new(
+            data_dir,
+            block_index_db,
+            archival_mutator_set,
+            network,
+            block_cache_capacity,
+        )
+        .await;
+
+        // store a block on disk, such that reading it requires deserialization
+        archival_state
+            .write_block_as_tip(&Block::genesis_block(network))
+            .await
+            .unwrap();
+
+        archival_state
+    }
+
+    fn get_block_impl(bencher: Bencher, block_cache_capacity: usize) {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let archival_state = rt.block_on(archival_state_with_stored_block(block_cache_capacity));
+        let block_digest = Block::genesis_block(Network::RegTest).hash();
+
+        bencher.bench_local(|| {
+            rt.block_on(async {
+                for _ in 0..NUM_UTXOS {
+                    let _ = archival_state.get_block(block_digest).await.unwrap();
+                }
+            });
+        });
+    }
+
+    #[divan::bench]
+    fn without_block_cache(bencher: Bencher) {
+        get_block_impl(bencher, 0);
+    }
+
+    #[divan::bench]
+    fn with_block_cache(bencher: Bencher) {
+        get_block_impl(bencher, DEFAULT_BLOCK_CACHE_CAPACITY);
+    }
+}
@@ -0,0 +1,46 @@
+use divan::Bencher;
+use neptune_core::config_models::network::Network;
+use neptune_core::models::blockchain::block::block_header::BlockHeaderHasher;
+use neptune_core::models::blockchain::block::Block;
+use neptune_core::models::consensus::mast_hash::MastHash;
+use neptune_core::models::consensus::timestamp::Timestamp;
+use neptune_core::prelude::twenty_first::math::b_field_element::BFieldElement;
+
+fn main() {
+    divan::main();
+}
+
+/// The miner hashes the block header once per attempted nonce. These
+/// benchmarks compare hashing the full header with hashing only the parts
+/// that change between attempts.
+mod hash_header_per_nonce {
+    use super::*;
+
+    // number of attempted nonces per iteration
+    const NUM_NONCES: u64 = 100;
+
+    #[divan::bench]
+    fn full_mast_hash(bencher: Bencher) {
+        let mut header = Block::genesis_block(Network::RegTest).kernel.header.clone();
+
+        bencher.bench_local(|| {
+            for i in 0..NUM_NONCES {
+                header.nonce = [BFieldElement::new(i); 3];
+                header.timestamp = Timestamp::now();
+                let _ = header.mast_hash();
+            }
+        });
+    }
+
+    #[divan::bench]
+    fn block_header_hasher(bencher: Bencher) {
+        let header = Block::genesis_block(Network::RegTest).kernel.header.clone();
+        let mut hasher = BlockHeaderHasher::new(&header);
+
+        bencher.bench_local(|| {
+            for i in 0..NUM_NONCES {
+                let _ = hasher.mast_hash([BFieldElement::new(i); 3], Timestamp::now());
+            }
+        });
+    }
+}
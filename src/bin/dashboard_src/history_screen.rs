@@ -150,7 +150,7 @@ impl HistoryScreen {
                     let bh = rpc_client.history(context::current()).await.unwrap();
                     let mut history_builder = Vec::with_capacity(bh.len());
                     let mut balance = NeptuneCoins::zero();
-                    for (_, block_height, timestamp, amount) in bh.iter() {
+                    for (_, block_height, timestamp, amount, _) in bh.iter() {
                         if amount.is_negative() {
                             balance = match balance.checked_sub(amount) {
                                 Some(b) => b,
@@ -38,6 +38,7 @@ pub struct OverviewData {
 
     network: Network,
     syncing: bool,
+    quiesced: bool,
     is_mining: Option<bool>,
     tip_digest: Option<Digest>,
     block_header: Option<BlockHeader>,
@@ -72,6 +73,7 @@ impl OverviewData {
             synchronization_percentage: Default::default(),
             network,
             syncing: Default::default(),
+            quiesced: Default::default(),
             is_mining: Default::default(),
             listen_address,
             tip_digest: Default::default(),
@@ -104,6 +106,7 @@ impl OverviewData {
             network: Network::Testnet,
             is_mining: Some(false),
             syncing: false,
+            quiesced: false,
             tip_digest: Some(
                 neptune_core::models::blockchain::block::Block::genesis_block(Network::Testnet)
                     .hash(),
@@ -211,6 +214,7 @@ impl OverviewScreen {
                                 own_overview_data.peer_count=resp.peer_count;
                                 own_overview_data.authenticated_peer_count=Some(0);
                                 own_overview_data.syncing=resp.syncing;
+                                own_overview_data.quiesced=resp.quiesced;
                                 own_overview_data.available_balance = Some(resp.available_balance);
                                 own_overview_data.timelocked_balance = Some(resp.timelocked_balance);
                                 own_overview_data.is_mining = resp.is_mining;
@@ -397,6 +401,8 @@ impl Widget for OverviewScreen {
 
         lines.push(format!("synchronizing: {}", data.syncing));
 
+        lines.push(format!("quiesced: {}", data.quiesced));
+
         lines.push(format!("mining: {}", dashifnotset!(data.is_mining)));
 
         let tip_digest_hex = data.tip_digest.map(|d| d.to_hex());
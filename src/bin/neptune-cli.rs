@@ -1,5 +1,8 @@
 use neptune_core::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
+use neptune_core::models::consensus::timestamp::Timestamp;
 use neptune_core::models::state::wallet::coin_with_possible_timelock::CoinWithPossibleTimeLock;
+use neptune_core::models::state::wallet::transaction_label::{validate_label, LabelTarget};
+use neptune_core::prelude::twenty_first::math::digest::Digest;
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
@@ -13,6 +16,7 @@ use std::io;
 use std::io::Write;
 use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
 use tarpc::{client, context, tokio_serde::formats::Json};
 
 use neptune_core::models::blockchain::block::block_selector::BlockSelector;
@@ -50,8 +54,31 @@ enum Command {
     WalletStatus,
     OwnReceivingAddress,
     ListCoins,
+    ListMonitoredUtxos {
+        #[clap(long, default_value_t = 0)]
+        offset: u64,
+        #[clap(long, default_value_t = 100)]
+        limit: u64,
+    },
     MempoolTxCount,
     MempoolSize,
+    MutatorSetStatistics,
+    ChunkDictionaryStatistics,
+    /// Verify the membership proofs of all unspent UTXOs against the tip, and
+    /// print the failures with suggested repairs as JSON
+    VerifyAllProofs,
+    /// Show the outcome of the background verification of stored records
+    StorageScrubReport,
+    /// Show the size of the peer databases and their latest garbage collection
+    DbStats,
+    WatchedAddresses,
+    FindHistory {
+        label_substring: String,
+    },
+    /// Find the known objects whose digest starts with the given hex prefix
+    ResolveDigest {
+        hex_prefix: String,
+    },
 
     /******** CHANGE STATE ********/
     Shutdown,
@@ -63,10 +90,38 @@ enum Command {
         amount: NeptuneCoins,
         address: String,
         fee: NeptuneCoins,
+        /// Label for the transaction, stored in the wallet only
+        #[clap(long)]
+        label: Option<String>,
+    },
+    /// Label a transaction by its kernel hash, or a monitored UTXO by its
+    /// index
+    SetLabel {
+        #[clap(long, required_unless_present = "utxo", conflicts_with = "utxo")]
+        transaction: Option<String>,
+        #[clap(long)]
+        utxo: Option<u64>,
+        label: String,
     },
     PauseMiner,
     RestartMiner,
+    /// Stop mining, peer messages that change the state, and the periodic
+    /// jobs, and flush all databases, e.g. before taking a disk snapshot
+    Quiesce {
+        /// Seconds to wait for the block being applied
+        #[clap(long, default_value = "60")]
+        timeout: u64,
+    },
+    /// Resume after `quiesce`
+    Resume,
     PruneAbandonedMonitoredUtxos,
+    AddWatchAddress {
+        address: String,
+        label: String,
+    },
+    RemoveWatchAddress {
+        address: String,
+    },
 
     /******** WALLET ********/
     GenerateWallet {
@@ -272,6 +327,35 @@ async fn main() -> Result<()> {
             let list = client.list_own_coins(ctx).await?;
             println!("{}", CoinWithPossibleTimeLock::report(&list));
         }
+        Command::ListMonitoredUtxos { offset, limit } => {
+            let infos = client.list_monitored_utxos(ctx, offset, limit).await?;
+            for info in infos {
+                let confirmed = match info.confirmed_in_block {
+                    Some((digest, height)) => format!("{height} ({digest})"),
+                    None => "-".to_string(),
+                };
+                let confirmations = match info.confirmations {
+                    Some(confirmations) => confirmations.to_string(),
+                    None => "-".to_string(),
+                };
+                let label = match info.label {
+                    Some(label) => format!(" label: \"{label}\""),
+                    None => String::default(),
+                };
+                println!(
+                    "#{} {}: {} confirmed: {} confirmations: {} synced: {} spent: {} abandoned: {}{}",
+                    info.index,
+                    info.utxo_digest,
+                    info.amount,
+                    confirmed,
+                    confirmations,
+                    info.synced_to_tip,
+                    info.spent,
+                    info.abandoned,
+                    label
+                );
+            }
+        }
         Command::Network => {
             let network = client.network(ctx).await?;
             println!("{network}")
@@ -373,6 +457,120 @@ async fn main() -> Result<()> {
             let size_in_bytes: usize = client.mempool_size(ctx).await?;
             println!("{} bytes", size_in_bytes);
         }
+        Command::MutatorSetStatistics => {
+            let (height, statistics) = client.mutator_set_statistics(ctx).await?;
+            println!("computed at block height: {height}");
+            println!("AOCL leaf count: {}", statistics.aocl_leaf_count);
+            println!("batch index: {}", statistics.batch_index);
+            println!("inactive chunks: {}", statistics.chunk_count);
+            println!("inactive set bits: {}", statistics.inactive_set_bits);
+            println!("max set bits in a chunk: {}", statistics.max_chunk_set_bits);
+            println!(
+                "active window set bits: {}",
+                statistics.active_window_set_bits
+            );
+        }
+        Command::VerifyAllProofs => {
+            let report = client.verify_all_proofs(ctx).await?;
+            println!("{}", serde_json::to_string(&report)?);
+        }
+        Command::ChunkDictionaryStatistics => {
+            let statistics = client.chunk_dictionary_statistics(ctx).await?;
+            println!("maintained membership proofs: {}", statistics.proof_count);
+            println!(
+                "average chunk dictionary entries: {:.2}",
+                statistics.average_entries()
+            );
+            println!("max chunk dictionary entries: {}", statistics.max_entries);
+            println!(
+                "proofs restored from archival state when spent: {}",
+                statistics.archival_restore_count
+            );
+        }
+        Command::StorageScrubReport => {
+            let report = client.storage_scrub_report(ctx).await?;
+            println!("scrub rounds: {}", report.rounds);
+            println!("blocks verified: {}", report.blocks_checked);
+            println!("chunks verified: {}", report.chunks_checked);
+            if report.is_healthy() {
+                println!("No corrupt records found.");
+            }
+            for key in report.corrupt_keys {
+                println!("corrupt: {key}");
+            }
+        }
+        Command::DbStats => {
+            let stats = client.db_stats(ctx).await?;
+            println!("peer standings: {}", stats.peer_standings_count);
+            println!("known peers: {}", stats.known_peers_count);
+            println!("address book entries: {}", stats.address_book_count);
+            match stats.latest_peer_database_gc {
+                Some(report) => {
+                    let collected_at = report
+                        .collected_at
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default();
+                    println!(
+                        "garbage collected at {} with a retention of {} days",
+                        Timestamp::millis(collected_at.as_millis() as u64).standard_format(),
+                        report.retention.as_secs() / (24 * 60 * 60)
+                    );
+                    println!(
+                        "  peer standings removed: {}",
+                        report.peer_standings_removed
+                    );
+                    println!("  known peers removed: {}", report.known_peers_removed);
+                    println!(
+                        "  address book entries removed: {}",
+                        report.addresses_removed
+                    );
+                }
+                None => println!("Peer databases were not garbage collected."),
+            }
+        }
+        Command::FindHistory { label_substring } => {
+            let history = client.find_history(ctx, label_substring).await?;
+            for (block_digest, block_height, timestamp, amount, label) in history {
+                println!(
+                    "{} at height {} in block {} ({}): \"{}\"",
+                    amount,
+                    block_height,
+                    block_digest,
+                    timestamp.standard_format(),
+                    label.unwrap_or_default()
+                );
+            }
+        }
+        Command::ResolveDigest { hex_prefix } => {
+            let candidates = client.resolve_digest(ctx, hex_prefix).await?;
+            if candidates.is_empty() {
+                println!("No matches.");
+            }
+            for candidate in candidates {
+                println!("{:?}: {}", candidate.kind, candidate.digest.to_hex());
+            }
+        }
+        Command::WatchedAddresses => {
+            let watched_addresses = client.watched_addresses(ctx).await?;
+            for watched_address in watched_addresses {
+                println!(
+                    "{} ({}): {} sightings",
+                    watched_address.label,
+                    watched_address.address.to_bech32m(args.network)?,
+                    watched_address.sightings.len()
+                );
+                for sighting in watched_address.sightings {
+                    let amount = match sighting.amount {
+                        Some(amount) => amount.to_string(),
+                        None => "unknown amount".to_string(),
+                    };
+                    println!(
+                        "  {amount} at height {} in block {}",
+                        sighting.block_height, sighting.block_digest
+                    );
+                }
+            }
+        }
 
         /******** CHANGE STATE ********/
         Command::Shutdown => {
@@ -392,12 +590,18 @@ async fn main() -> Result<()> {
             amount,
             address,
             fee,
+            label,
         } => {
             // Parse on client
             let receiving_address =
                 generation_address::ReceivingAddress::from_bech32m(address.clone(), args.network)?;
+            if let Some(label) = &label {
+                validate_label(label)?;
+            }
 
-            client.send(ctx, amount, receiving_address, fee).await?;
+            client
+                .send(ctx, amount, receiving_address, fee, label)
+                .await?;
             println!("Send-command issues. Recipient: {address}; amount: {amount}");
         }
         Command::PauseMiner => {
@@ -410,11 +614,69 @@ async fn main() -> Result<()> {
             client.restart_miner(ctx).await?;
             println!("Command completed successfully");
         }
+        Command::Quiesce { timeout } => {
+            // Leave the node time to flush after the timeout
+            let timeout = Duration::from_secs(timeout);
+            let mut quiesce_ctx = ctx;
+            quiesce_ctx.deadline = SystemTime::now() + timeout + Duration::from_secs(60);
+            if client.quiesce(quiesce_ctx, timeout).await? {
+                println!("Node is quiesced");
+            } else {
+                bail!("Could not quiesce. See the node's log for details.");
+            }
+        }
+        Command::Resume => {
+            if client.resume(ctx).await? {
+                println!("Node resumed");
+            } else {
+                println!("Node was not quiesced");
+            }
+        }
 
         Command::PruneAbandonedMonitoredUtxos => {
             let prunt_res_count = client.prune_abandoned_monitored_utxos(ctx).await?;
             println!("{prunt_res_count} monitored UTXOs marked as abandoned");
         }
+        Command::AddWatchAddress { address, label } => {
+            let receiving_address =
+                generation_address::ReceivingAddress::from_bech32m(address.clone(), args.network)?;
+            if client
+                .add_watch_address(ctx, receiving_address, label.clone())
+                .await?
+            {
+                println!("Watching address {address} as \"{label}\"");
+            } else {
+                println!("Relabeled watched address {address} as \"{label}\"");
+            }
+        }
+        Command::SetLabel {
+            transaction,
+            utxo,
+            label,
+        } => {
+            validate_label(&label)?;
+            let target = match (transaction, utxo) {
+                (Some(kernel_hash), _) => {
+                    LabelTarget::Transaction(Digest::try_from_hex(&kernel_hash)?)
+                }
+                (None, Some(index)) => LabelTarget::MonitoredUtxo(index),
+                (None, None) => unreachable!("Argument parser requires a target."),
+            };
+            if client.set_label(ctx, target, label.clone()).await? {
+                println!("Labeled as \"{label}\"");
+            } else {
+                println!("Unknown transaction or UTXO; nothing was labeled");
+            }
+        }
+        Command::RemoveWatchAddress { address } => {
+            let receiving_address =
+                generation_address::ReceivingAddress::from_bech32m(address.clone(), args.network)?;
+            if client.remove_watch_address(ctx, receiving_address).await? {
+                println!("No longer watching address {address}");
+            } else {
+                println!("Address {address} was not watched");
+            }
+        }
     }
 
     Ok(())
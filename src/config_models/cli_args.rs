@@ -1,9 +1,18 @@
 use super::network::Network;
+use crate::models::blockchain::block::checkpoint::Checkpoint;
+use crate::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
+use crate::models::consensus::timestamp::Timestamp;
+use crate::models::peer::BlockServingPolicy;
+use crate::peer_codec::PeerCodec;
+use crate::prelude::twenty_first;
+use crate::socks5::Socks5Target;
 use bytesize::ByteSize;
 use clap::builder::RangedI64ValueParser;
 use clap::Parser;
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::time::Duration;
+use twenty_first::math::digest::Digest;
 
 /// The `neptune-core` command-line program starts a Neptune node.
 #[derive(Parser, Debug, Clone)]
@@ -39,11 +48,58 @@ pub struct Args {
     #[clap(long, default_value = "100", value_name = "VALUE")]
     pub peer_tolerance: u16,
 
+    /// Forget the standing of a peer once this many hours have passed since
+    /// its latest sanction, such that banned peers are eventually admitted
+    /// again. Set to 0 to keep peers banned until their standing is cleared.
+    ///
+    /// E.g. --peer-ban-duration 24
+    #[clap(long, default_value = "24", value_name = "HOURS")]
+    pub peer_ban_duration: u64,
+
+    /// Forget peer standings, known peers, and address book entries that were
+    /// not updated for this many days, on startup. Active bans and the peers
+    /// given with `--peers` are never forgotten. Set to 0 to keep all entries.
+    ///
+    /// E.g. --peer-database-retention 30
+    #[clap(long, default_value = "90", value_name = "DAYS")]
+    pub peer_database_retention: u64,
+
     /// Maximum number of peers to accept connections from.
     ///
-    /// Will not prevent outgoing connections made with `--peers`.
+    /// Inbound connections are refused once this number is reached, even if
+    /// outbound slots remain.
     #[clap(long, default_value = "10", value_name = "COUNT")]
-    pub max_peers: u16,
+    pub max_inbound_peers: u16,
+
+    /// Maximum number of peers to connect to, including those given with
+    /// `--peers`.
+    ///
+    /// Outbound slots are never taken by inbound connections, such that this
+    /// node always chooses some of its peers itself.
+    #[clap(long, default_value = "8", value_name = "COUNT")]
+    pub max_outbound_peers: u16,
+
+    /// Warn when this node has been without peers for this many minutes,
+    /// which is also reported by the `peerless_alert` RPC. Set to 0 to never
+    /// warn, e.g. for a node that is meant to run on its own.
+    ///
+    /// E.g. --peerless-warning-threshold 30
+    #[clap(long, default_value = "5", value_name = "MINUTES")]
+    pub peerless_warning_threshold: u64,
+
+    /// Maximum number of inbound connections from a single IP address.
+    ///
+    /// Connections from the loopback interface are exempt, such that several
+    /// local nodes can connect to each other.
+    #[clap(long, default_value = "2", value_name = "COUNT")]
+    pub max_connections_per_ip: u16,
+
+    /// Maximum number of inbound connections from a single subnet, i.e. the
+    /// /16 of IPv4 addresses and the /32 of IPv6 addresses.
+    ///
+    /// Connections from the loopback interface are exempt.
+    #[clap(long, default_value = "4", value_name = "COUNT")]
+    pub max_connections_per_subnet: u16,
 
     /// Should this node participate in competitive mining?
     ///
@@ -51,6 +107,11 @@ pub struct Args {
     #[clap(long)]
     pub mine: bool,
 
+    /// Never mine, not even when `--mine` is set or when mining is restarted
+    /// over RPC. Intended for nodes that must not spend resources on mining.
+    #[clap(long)]
+    pub disable_mining: bool,
+
     /// If mining, use all available CPU power. Ignored if mine flag not set.
     #[clap(long)]
     pub unrestricted_mining: bool,
@@ -63,6 +124,31 @@ pub struct Args {
     #[clap(long, default_value = "1G", value_name = "SIZE")]
     pub max_mempool_size: ByteSize,
 
+    /// Remove transactions from the mempool once their timestamp is this many
+    /// hours old, such that transactions with too low fees do not linger
+    /// forever.
+    ///
+    /// E.g. --mempool-transaction-ttl 24
+    #[clap(long, default_value = "72", value_name = "HOURS")]
+    pub mempool_transaction_ttl: usize,
+
+    /// A transaction that spends the same UTXO as transactions in the mempool
+    /// replaces them if it has a higher fee density. With a nonzero margin,
+    /// its fee must also exceed their combined fee by more than this amount,
+    /// such that replacements cannot be spammed for free.
+    ///
+    /// E.g. --replace-by-fee-margin 0.5
+    #[clap(long, default_value = "0", value_name = "AMOUNT")]
+    pub replace_by_fee_margin: NeptuneCoins,
+
+    /// Maximum number of transactions to fetch from the mempool of a peer
+    /// after connecting to it, such that a freshly started node does not have
+    /// to wait for transactions to be relayed. Set to 0 to disable.
+    ///
+    /// E.g. --max-mempool-sync-transactions 100
+    #[clap(long, default_value = "1000", value_name = "COUNT")]
+    pub max_mempool_sync_transactions: usize,
+
     /// Prune the pool of UTXO notification when it exceeds this size in RAM.
     ///
     /// Units: B (bytes), K (kilobytes), M (megabytes), G (gigabytes)
@@ -80,6 +166,12 @@ pub struct Args {
     #[clap(long, default_value = "1000", value_name = "COUNT")]
     pub max_unconfirmed_utxo_notification_count_per_peer: usize,
 
+    /// Maximum number of addresses accepted from a single peer list sent by a
+    /// peer. Peers that send longer lists are sanctioned, and the excess is
+    /// ignored. Peer lists sent to peers are limited to the same length.
+    #[clap(long, default_value = "10", value_name = "COUNT")]
+    pub max_peer_list_response_length: usize,
+
     /// Port on which to listen for peer connections.
     #[clap(long, default_value = "9798", value_name = "PORT")]
     pub peer_port: u16,
@@ -100,18 +192,130 @@ pub struct Args {
     #[clap(long, default_value = "100", value_parser(RangedI64ValueParser::<usize>::new().range(2..100000)))]
     pub max_number_of_blocks_before_syncing: usize,
 
-    /// IPs of nodes to connect to, e.g.: --peers 8.8.8.8:9798 --peers 8.8.4.4:1337.
+    /// Maximum number of blocks returned in response to a peer's request for
+    /// a range of blocks by height.
+    #[clap(long, default_value = "50", value_name = "COUNT")]
+    pub max_block_batch_response_length: u16,
+
+    /// Maximum combined size of the blocks returned in response to a peer's
+    /// request for a batch of blocks. Responses are cut short once the next
+    /// block would exceed it, such that they stay well within the maximum
+    /// message size.
+    ///
+    /// Units: B (bytes), K (kilobytes), M (megabytes), G (gigabytes)
+    #[clap(long, default_value = "100M", value_name = "SIZE")]
+    pub max_block_batch_response_size: ByteSize,
+
+    /// Maximum number of blocks served to a single peer within the block
+    /// serving window. Further block requests of the peer are answered with
+    /// the time to wait before requesting again, such that a syncing peer
+    /// cannot keep the disk busy for hours.
+    ///
+    /// E.g. --max-served-blocks-per-peer 2000
+    #[clap(long, default_value = "10000", value_name = "COUNT")]
+    pub max_served_blocks_per_peer: u64,
+
+    /// Maximum combined size of the blocks served to a single peer within the
+    /// block serving window.
+    ///
+    /// Units: B (bytes), K (kilobytes), M (megabytes), G (gigabytes)
+    #[clap(long, default_value = "8G", value_name = "SIZE")]
+    pub max_served_block_bytes_per_peer: ByteSize,
+
+    /// Length of the rolling window over which the blocks served to a peer are
+    /// limited, in seconds.
+    #[clap(long, default_value = "600", value_name = "SECONDS")]
+    pub block_serving_window: u64,
+
+    /// Maximum number of seconds that the timestamp of a block may be ahead of
+    /// the local clock, to tolerate clock skew. Blocks timestamped further in
+    /// the future are rejected, and the peers relaying them are sanctioned.
+    #[clap(long, default_value = "120", value_name = "SECONDS")]
+    pub max_block_timestamp_drift: u64,
+
+    /// IPs or host names of nodes to connect to, e.g.: --peers 8.8.8.8:9798
+    /// --peers example.onion:9798. Onion services are only reachable through
+    /// --socks5-proxy, which then resolves the host names.
     #[structopt(long)]
-    pub peers: Vec<SocketAddr>,
+    pub peers: Vec<Socks5Target>,
+
+    /// SOCKS5 proxy through which all outbound peer connections are made,
+    /// e.g. --socks5-proxy 127.0.0.1:9050 to connect over Tor. The peer port
+    /// is then not announced to peers, unless --advertise-listen-port is set.
+    #[clap(long, value_name = "ADDRESS")]
+    pub socks5_proxy: Option<SocketAddr>,
+
+    /// Announce the peer port to peers also when connecting through
+    /// --socks5-proxy, e.g. when the node is reachable as an onion service.
+    #[clap(long)]
+    pub advertise_listen_port: bool,
 
     /// Specify network, `alpha`, `testnet`, or `regtest`
     #[structopt(long, short, default_value = "alpha")]
     pub network: Network,
 
+    /// Number of recently read blocks kept in memory, to avoid repeatedly
+    /// reading them from disk. Set to 0 to disable the cache.
+    #[clap(long, default_value = "64", value_name = "COUNT")]
+    pub block_cache_size: usize,
+
+    /// Checkpoint file holding a snapshot of the mutator set after some
+    /// block. The checkpoint must match the trusted checkpoint hash. A node
+    /// without any blocks starts from the checkpoint rather than from genesis,
+    /// and only downloads the blocks after it. Otherwise, the canonical block
+    /// at its height must match the checkpoint once the node has it.
+    #[clap(long, value_name = "PATH")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Hash of the checkpoint to trust, in hex. Defaults to the checkpoint
+    /// published for the network, if any.
+    #[clap(long, value_name = "HASH", value_parser = |hash: &str| Digest::try_from_hex(hash))]
+    pub checkpoint_hash: Option<Digest>,
+
     /// Max number of membership proofs stored per owned UTXO
     #[structopt(long, default_value = "3")]
     pub number_of_mps_per_utxo: usize,
 
+    /// Stop maintaining the membership proof of an owned UTXO once its chunk
+    /// dictionary holds more than this many entries. The proof is restored
+    /// from the archival mutator set when the UTXO is spent.
+    ///
+    /// Only applies to archival nodes. Disabled by default. UTXOs without a
+    /// maintained membership proof count as unsynced in the wallet status
+    /// until they are restored.
+    #[clap(long, value_name = "COUNT")]
+    pub max_chunk_dictionary_entries: Option<usize>,
+
+    /// Drop the transaction witnesses of stored blocks once this many blocks
+    /// have been mined on top of them, retaining the transaction kernels.
+    ///
+    /// Only applies to archival nodes. Disabled by default, such that all
+    /// witnesses are kept.
+    ///
+    /// E.g. --witness-prune-depth 1000
+    #[clap(long, value_name = "DEPTH")]
+    pub witness_prune_depth: Option<usize>,
+
+    /// Number of blocks that must be mined on top of the block that confirmed
+    /// a UTXO before the wallet spends it.
+    ///
+    /// E.g. --spend-confirmation-depth 6
+    #[clap(long, default_value = "0", value_name = "COUNT")]
+    pub spend_confirmation_depth: usize,
+
+    /// Number of blocks that must be mined on top of a block mined by this
+    /// node before the wallet spends its coinbase UTXO. Applies in addition
+    /// to `--spend-confirmation-depth`.
+    ///
+    /// E.g. --coinbase-maturity-depth 100
+    #[clap(long, default_value = "0", value_name = "COUNT")]
+    pub coinbase_maturity_depth: usize,
+
+    /// Resynchronize the membership proofs of owned UTXOs as soon as the
+    /// chain reorganizes, rather than waiting for the periodic resync job.
+    #[clap(long)]
+    pub resync_on_reorg: bool,
+
     /// Whether to enable privacy when initiating transactions. If this flag
     /// is set to false, when the client initiates a transaction it will
     /// supply the raw witness for the mutator set removal record integrity
@@ -128,6 +332,128 @@ pub struct Args {
     /// note: this will attempt to connect to localhost:6669
     #[structopt(long, name = "tokio-console", default_value = "false")]
     pub tokio_console: bool,
+
+    /// Refuse to export or import the wallet secret over RPC, for hardened
+    /// deployments in which the secret must never leave the node.
+    #[clap(long)]
+    pub disable_spending_key_export: bool,
+
+    /// Encoding of the messages exchanged with peers after the handshake,
+    /// `bincode` or `json`. JSON is only used with peers that chose it too,
+    /// and is meant for inspecting the traffic between two nodes.
+    #[clap(long, default_value = "bincode", value_name = "CODEC")]
+    pub peer_codec: PeerCodec,
+
+    /// Record a transcript of the messages exchanged with each peer, for
+    /// debugging. Transcripts are stored in the `peer_traffic` directory
+    /// within the data directory. Payloads of transactions and handshakes
+    /// are never recorded.
+    #[clap(long)]
+    pub capture_peer_traffic: bool,
+
+    /// Delete the oldest peer traffic transcripts when their combined size
+    /// would exceed this size on disk.
+    ///
+    /// Units: B (bytes), K (kilobytes), M (megabytes), G (gigabytes)
+    #[clap(long, default_value = "100M", value_name = "SIZE")]
+    pub capture_peer_traffic_max_size: ByteSize,
+
+    /// Stop recording the traffic of a connection when its transcript
+    /// exceeds this size on disk.
+    #[clap(long, default_value = "10M", value_name = "SIZE")]
+    pub capture_peer_traffic_max_connection_size: ByteSize,
+
+    /// Also record the encoding of captured messages, such that transcripts
+    /// can be replayed, for messages no larger than this size. Set to 0 to
+    /// record message summaries only.
+    #[clap(long, default_value = "0B", value_name = "SIZE")]
+    pub capture_peer_traffic_raw_frame_size: ByteSize,
+}
+
+impl Args {
+    /// The time after its latest sanction at which the standing of a peer is
+    /// forgotten, or None if standings never expire.
+    pub fn peer_ban_duration(&self) -> Option<Duration> {
+        (self.peer_ban_duration > 0)
+            .then(|| Duration::from_secs(self.peer_ban_duration.saturating_mul(60 * 60)))
+    }
+
+    /// The time without peers after which the node warns, or None if it never
+    /// does.
+    pub fn peerless_warning_threshold(&self) -> Option<Duration> {
+        (self.peerless_warning_threshold > 0)
+            .then(|| Duration::from_secs(self.peerless_warning_threshold.saturating_mul(60)))
+    }
+
+    /// The time after which entries of the peer databases that were not
+    /// updated are forgotten, or None if they are kept.
+    pub fn peer_database_retention(&self) -> Option<Duration> {
+        (self.peer_database_retention > 0)
+            .then(|| Duration::from_secs(self.peer_database_retention.saturating_mul(24 * 60 * 60)))
+    }
+
+    /// The hash of the checkpoint to trust: the one given with
+    /// --checkpoint-hash, if any, or else the one published for the network.
+    pub fn trusted_checkpoint_hash(&self) -> Option<Digest> {
+        self.checkpoint_hash
+            .or_else(|| Checkpoint::trusted_hash(self.network))
+    }
+
+    /// The limits on the blocks served to a single peer.
+    pub fn block_serving_policy(&self) -> BlockServingPolicy {
+        BlockServingPolicy {
+            max_blocks: self.max_served_blocks_per_peer,
+            max_bytes: self.max_served_block_bytes_per_peer.as_u64(),
+            window: Duration::from_secs(self.block_serving_window),
+        }
+    }
+
+    /// How far the timestamp of a block may be ahead of the local clock.
+    pub fn max_block_timestamp_drift(&self) -> Timestamp {
+        Timestamp::seconds(self.max_block_timestamp_drift)
+    }
+
+    /// The port announced to peers in the handshake. It is not announced when
+    /// connecting through a proxy, as it would reveal that the node accepts
+    /// connections at its own address, unless explicitly asked for.
+    pub fn advertised_listen_port(&self) -> Option<u16> {
+        (self.socks5_proxy.is_none() || self.advertise_listen_port).then_some(self.peer_port)
+    }
+
+    /// The addresses under which the peers given with --peers are tracked,
+    /// see [Socks5Target::stand_in_address].
+    pub fn peer_addresses(&self) -> Vec<SocketAddr> {
+        self.peers
+            .iter()
+            .map(Socks5Target::stand_in_address)
+            .collect()
+    }
+
+    /// The target to dial for the peer tracked under `peer_address`, which is
+    /// the host name given with --peers, if any, or the address itself.
+    pub fn peer_target(&self, peer_address: SocketAddr) -> Socks5Target {
+        self.peers
+            .iter()
+            .find(|peer| peer.stand_in_address() == peer_address)
+            .cloned()
+            .unwrap_or(Socks5Target::Address(peer_address))
+    }
+
+    /// Whether the miner task is started, i.e., mining was requested and is
+    /// not disabled.
+    pub fn spawns_miner(&self) -> bool {
+        self.mine && !self.disable_mining
+    }
+
+    /// The maximum number of inbound peers if `inbound`, or the maximum
+    /// number of outbound peers otherwise.
+    pub fn max_peers(&self, inbound: bool) -> usize {
+        if inbound {
+            self.max_inbound_peers as usize
+        } else {
+            self.max_outbound_peers as usize
+        }
+    }
 }
 
 impl Default for Args {
@@ -148,12 +474,99 @@ mod cli_args_tests {
         let default_args = Args::default();
 
         assert_eq!(100, default_args.peer_tolerance);
-        assert_eq!(10, default_args.max_peers);
+        assert_eq!(
+            Some(Duration::from_secs(90 * 24 * 60 * 60)),
+            default_args.peer_database_retention()
+        );
+        assert_eq!(
+            Some(Duration::from_secs(5 * 60)),
+            default_args.peerless_warning_threshold()
+        );
+        assert_eq!(
+            BlockServingPolicy {
+                max_blocks: 10_000,
+                max_bytes: 8_000_000_000,
+                window: Duration::from_secs(600),
+            },
+            default_args.block_serving_policy()
+        );
+        assert_eq!(10, default_args.max_inbound_peers);
+        assert_eq!(8, default_args.max_outbound_peers);
+        assert_eq!(2, default_args.max_connections_per_ip);
+        assert_eq!(4, default_args.max_connections_per_subnet);
         assert_eq!(9798, default_args.peer_port);
         assert_eq!(9799, default_args.rpc_port);
+        assert_eq!(
+            Timestamp::minutes(2),
+            default_args.max_block_timestamp_drift()
+        );
+        assert_eq!(None, default_args.socks5_proxy);
+        assert_eq!(PeerCodec::Bincode, default_args.peer_codec);
+        assert_eq!(Some(9798), default_args.advertised_listen_port());
         assert_eq!(
             IpAddr::from(Ipv6Addr::UNSPECIFIED),
             default_args.listen_addr
         );
     }
+
+    #[test]
+    fn configured_checkpoint_hash_overrides_published_one_test() {
+        assert_eq!(None, Args::default().trusted_checkpoint_hash());
+        let regtest_args = Args::parse_from(["neptune-core", "--network", "regtest"]);
+        assert_eq!(
+            Checkpoint::trusted_hash(Network::RegTest),
+            regtest_args.trusted_checkpoint_hash()
+        );
+
+        let hash: Digest = rand::random();
+        let configured_args =
+            Args::parse_from(["neptune-core", "--checkpoint-hash", &hash.to_hex()]);
+        assert_eq!(Some(hash), configured_args.trusted_checkpoint_hash());
+        assert!(Args::try_parse_from(["neptune-core", "--checkpoint-hash", "0xabc"]).is_err());
+    }
+
+    #[test]
+    fn listen_port_is_not_advertised_through_proxy_test() {
+        let proxied_args = Args::parse_from(["neptune-core", "--socks5-proxy", "127.0.0.1:9050"]);
+        assert_eq!(
+            Some("127.0.0.1:9050".parse().unwrap()),
+            proxied_args.socks5_proxy
+        );
+        assert_eq!(None, proxied_args.advertised_listen_port());
+
+        let advertising_args = Args::parse_from([
+            "neptune-core",
+            "--socks5-proxy",
+            "127.0.0.1:9050",
+            "--advertise-listen-port",
+        ]);
+        assert_eq!(Some(9798), advertising_args.advertised_listen_port());
+    }
+
+    #[test]
+    fn peers_are_given_by_address_or_host_name_test() {
+        let args = Args::parse_from([
+            "neptune-core",
+            "--peers",
+            "8.8.8.8:9798",
+            "--peers",
+            "example.onion:9798",
+        ]);
+        let address: SocketAddr = "8.8.8.8:9798".parse().unwrap();
+        let onion = Socks5Target::DomainName("example.onion".to_string(), 9798);
+        assert_eq!(
+            vec![Socks5Target::Address(address), onion.clone()],
+            args.peers
+        );
+        assert_eq!(
+            vec![address, onion.stand_in_address()],
+            args.peer_addresses()
+        );
+        assert_eq!(onion, args.peer_target(onion.stand_in_address()));
+        let other_address: SocketAddr = "8.8.4.4:9798".parse().unwrap();
+        assert_eq!(
+            Socks5Target::Address(other_address),
+            args.peer_target(other_address)
+        );
+    }
 }
@@ -5,11 +5,15 @@ use std::path::{Path, PathBuf};
 use crate::config_models::network::Network;
 use crate::models::database::DATABASE_DIRECTORY_ROOT_NAME;
 use crate::models::state::archival_state::{BLOCK_INDEX_DB_NAME, MUTATOR_SET_DIRECTORY_NAME};
-use crate::models::state::networking_state::BANNED_IPS_DB_NAME;
+use crate::models::state::mempool::MEMPOOL_FILE_NAME;
+use crate::models::state::networking_state::{
+    ADDRESS_BOOK_DB_NAME, BANNED_IPS_DB_NAME, KNOWN_PEERS_DB_NAME,
+};
 use crate::models::state::shared::{
     BLOCK_FILENAME_EXTENSION, BLOCK_FILENAME_PREFIX, DIR_NAME_FOR_BLOCKS,
 };
 use crate::models::state::wallet::{WALLET_DB_NAME, WALLET_DIRECTORY, WALLET_OUTPUT_COUNT_DB_NAME};
+use crate::peer_traffic_capture::PEER_TRAFFIC_DIRECTORY_NAME;
 
 // TODO: Add `rusty_leveldb::Options` and `fs::OpenOptions` here too, since they keep being repeated.
 #[derive(Debug, Clone)]
@@ -85,6 +89,26 @@ impl DataDirectory {
         self.database_dir_path().join(Path::new(BANNED_IPS_DB_NAME))
     }
 
+    ///////////////////////////////////////////////////////////////////////////
+    ///
+    /// The known peers database directory path.
+    ///
+    /// This directory lives within `DataDirectory::database_dir_path()`.
+    pub fn known_peers_database_dir_path(&self) -> PathBuf {
+        self.database_dir_path()
+            .join(Path::new(KNOWN_PEERS_DB_NAME))
+    }
+
+    ///////////////////////////////////////////////////////////////////////////
+    ///
+    /// The address book database directory path.
+    ///
+    /// This directory lives within `DataDirectory::database_dir_path()`.
+    pub fn address_book_database_dir_path(&self) -> PathBuf {
+        self.database_dir_path()
+            .join(Path::new(ADDRESS_BOOK_DB_NAME))
+    }
+
     ///////////////////////////////////////////////////////////////////////////
     ///
     /// The wallet file path
@@ -146,6 +170,20 @@ impl DataDirectory {
 
         self.block_dir_path().join(Path::new(&block_file_name))
     }
+
+    /// The directory holding transcripts of captured peer traffic.
+    ///
+    /// This directory lives within `DataDirectory::root_dir_path()`.
+    pub fn peer_traffic_dir_path(&self) -> PathBuf {
+        self.data_dir.join(Path::new(PEER_TRAFFIC_DIRECTORY_NAME))
+    }
+
+    /// The file holding the mempool's transactions across restarts.
+    ///
+    /// This file lives within `DataDirectory::root_dir_path()`.
+    pub fn mempool_file_path(&self) -> PathBuf {
+        self.data_dir.join(Path::new(MEMPOOL_FILE_NAME))
+    }
 }
 
 impl std::fmt::Display for DataDirectory {
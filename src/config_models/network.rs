@@ -5,6 +5,11 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use strum::EnumIter;
 use tasm_lib::twenty_first::math::b_field_element::BFieldElement;
 
+use crate::models::blockchain::block::block_header::MAX_DIFFICULTY_ADJUSTMENT_FACTOR;
+use crate::models::blockchain::block::block_height::{
+    BLOCKS_PER_GENERATION, REGTEST_BLOCKS_PER_GENERATION,
+};
+use crate::models::blockchain::block::MAX_BLOCK_SIZE;
 use crate::models::consensus::timestamp::Timestamp;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Default, EnumIter)]
@@ -48,6 +53,52 @@ impl Network {
             }
         }
     }
+
+    /// The block interval that the difficulty control aims for. RegTest aims
+    /// for a very short interval, such that tests can mine blocks quickly.
+    pub(crate) fn target_block_interval(&self) -> Timestamp {
+        match self {
+            Network::RegTest => Timestamp::millis(100),
+            // 9.8 minutes
+            Network::Alpha | Network::Beta | Network::Testnet => Timestamp::millis(588_000),
+            Network::Main => Timestamp::minutes(10),
+        }
+    }
+
+    /// The factor by which the difficulty of a block is at most higher or
+    /// lower than that of its predecessor.
+    pub(crate) fn max_difficulty_adjustment_factor(&self) -> u32 {
+        match self {
+            Network::Alpha
+            | Network::Beta
+            | Network::Main
+            | Network::Testnet
+            | Network::RegTest => MAX_DIFFICULTY_ADJUSTMENT_FACTOR,
+        }
+    }
+
+    /// The number of blocks after which the mining reward is halved.
+    pub(crate) fn blocks_per_generation(&self) -> u64 {
+        match self {
+            // The test networks follow the halving schedule of main net
+            Network::Alpha | Network::Beta | Network::Main | Network::Testnet => {
+                BLOCKS_PER_GENERATION
+            }
+            Network::RegTest => REGTEST_BLOCKS_PER_GENERATION,
+        }
+    }
+
+    /// The maximum size in bytes of a serialized block. Every block header
+    /// must declare exactly this value.
+    pub(crate) fn max_block_size(&self) -> u32 {
+        match self {
+            Network::Alpha
+            | Network::Beta
+            | Network::Main
+            | Network::Testnet
+            | Network::RegTest => MAX_BLOCK_SIZE,
+        }
+    }
 }
 
 impl fmt::Display for Network {
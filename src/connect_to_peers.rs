@@ -1,14 +1,11 @@
 use anyhow::{bail, Result};
-use futures::{FutureExt, SinkExt, TryStreamExt};
-use std::{fmt::Debug, net::SocketAddr};
+use futures::{FutureExt, SinkExt, TryStream, TryStreamExt};
+use std::{fmt::Debug, net::SocketAddr, time::Duration};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
     sync::{broadcast, mpsc},
 };
-use tokio_serde::{
-    formats::{Bincode, SymmetricalBincode},
-    SymmetricallyFramed,
-};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use tracing::{debug, error, info, warn};
 
@@ -16,17 +13,24 @@ use crate::{
     models::{
         channel::{MainToPeerThread, PeerThreadToMain},
         peer::{
-            ConnectionRefusedReason, ConnectionStatus, HandshakeData, PeerMessage, PeerStanding,
+            ConnectionRefusedReason, ConnectionStatus, HandshakeData, PeerMessage,
+            PeerSanctionReason, PeerStanding,
         },
         state::GlobalStateLock,
     },
+    peer_codec::{PeerCodec, PeerMessageCodec},
     peer_loop::PeerLoopHandler,
+    peer_traffic_capture::{CapturingPeerStream, PeerTrafficCapture},
+    socks5::{self, Socks5Target},
     MAGIC_STRING_REQUEST, MAGIC_STRING_RESPONSE,
 };
 
 // Max peer message size is 2000MB
 pub const MAX_PEER_FRAME_LENGTH_IN_BYTES: usize = 2000 * 1024 * 1024;
 
+/// Time a peer has to send each of its handshake messages
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Use this function to ensure that the same rules apply for both
 /// ingoing and outgoing connections. This limits the size of messages
 /// peers can send.
@@ -36,7 +40,8 @@ fn get_codec_rules() -> LengthDelimitedCodec {
     codec_rules
 }
 
-/// Check if connection is allowed. Used for both ingoing and outgoing connections.
+/// Check if connection is allowed. Used for both ingoing and outgoing connections,
+/// as indicated by `inbound`.
 ///
 /// Locking:
 ///   * acquires `global_state_lock` for read
@@ -45,29 +50,9 @@ async fn check_if_connection_is_allowed(
     own_handshake: &HandshakeData,
     other_handshake: &HandshakeData,
     peer_address: &SocketAddr,
+    inbound: bool,
 ) -> ConnectionStatus {
     let global_state = global_state_lock.lock_guard().await;
-    fn versions_are_compatible(own_version: &str, other_version: &str) -> bool {
-        let own_version = semver::Version::parse(own_version)
-            .expect("Must be able to parse own version string. Got: {own_version}");
-        let other_version = match semver::Version::parse(other_version) {
-            Ok(version) => version,
-            Err(err) => {
-                warn!("Peer version is not a valid semver version. Got error: {err}",);
-                return false;
-            }
-        };
-
-        // All alphanet versions are incompatible with each other. Alphanet has versions
-        // "0.0.n". Alphanet is also incompatible with mainnet or any other versions.
-        if own_version.major == 0 && own_version.minor == 0
-            || other_version.major == 0 && other_version.minor == 0
-        {
-            return own_version == other_version;
-        }
-
-        true
-    }
 
     // Disallow connection if peer is banned via CLI arguments
     if global_state.cli().ban.contains(&peer_address.ip()) {
@@ -79,20 +64,16 @@ async fn check_if_connection_is_allowed(
     }
 
     // Disallow connection if peer is in bad standing
-    let standing = global_state
-        .net
-        .get_peer_standing_from_database(peer_address.ip())
-        .await;
+    // Standings are forgotten once the ban duration has passed
+    let standing = global_state.peer_standing(peer_address.ip()).await;
 
-    if standing.is_some()
-        && standing.unwrap().standing < -(global_state.cli().peer_tolerance as i32)
-    {
+    if standing.standing < -(global_state.cli().peer_tolerance as i32) {
         return ConnectionStatus::Refused(ConnectionRefusedReason::BadStanding);
     }
 
     if let Some(status) = {
-        // Disallow connection if max number of &peers has been attained
-        if (global_state.cli().max_peers as usize) <= global_state.net.peer_map.len() {
+        // Disallow connection if max number of peers in this direction has been attained
+        if global_state.cli().max_peers(inbound) <= global_state.net.peer_count(inbound) {
             Some(ConnectionStatus::Refused(
                 ConnectionRefusedReason::MaxPeerNumberExceeded,
             ))
@@ -113,19 +94,33 @@ async fn check_if_connection_is_allowed(
     }
 
     // Disallow connection to self
-    if own_handshake.instance_id == other_handshake.instance_id {
+    if own_handshake.instance_id == other_handshake.instance_id
+        || global_state.net.instance_id == other_handshake.instance_id
+    {
         return ConnectionStatus::Refused(ConnectionRefusedReason::SelfConnect);
     }
 
     // Disallow connection if versions are incompatible
-    if !versions_are_compatible(&own_handshake.version, &other_handshake.version) {
+    if !own_handshake.is_compatible_with(other_handshake) {
         warn!(
-            "Attempting to connect to incompatible version. You might have to upgrade, or the other node does. Own version: {}, other version: {}",
-            own_handshake.version,
+            "Attempting to connect to incompatible protocol version. You might have to upgrade, or the other node does. Own protocol versions: {}..={}, other protocol versions: {}..={} (release {})",
+            own_handshake.min_protocol_version,
+            own_handshake.protocol_version,
+            other_handshake.min_protocol_version,
+            other_handshake.protocol_version,
             other_handshake.version);
         return ConnectionStatus::Refused(ConnectionRefusedReason::IncompatibleVersion);
     }
 
+    // Disallow connection to peer on another network
+    if own_handshake.network != other_handshake.network {
+        warn!(
+            "Peer runs {}, this client runs {}. Disallowing.",
+            other_handshake.network, own_handshake.network
+        );
+        return ConnectionStatus::Refused(ConnectionRefusedReason::BadNetwork);
+    }
+
     info!("ConnectionStatus::Accepted");
     ConnectionStatus::Accepted
 }
@@ -172,6 +167,12 @@ where
         }
     }
 
+    // The connection was counted towards the limits per host and subnet
+    // since the main thread accepted it
+    state_lock
+        .lock_mut(|s| s.net.release_inbound_connection(peer_address))
+        .await;
+
     inner_ret
 }
 
@@ -190,15 +191,22 @@ where
 
     // Build the communication/serialization/frame handler
     let length_delimited = Framed::new(stream, get_codec_rules());
-    let mut peer: tokio_serde::Framed<
+    let (codec, codec_switch) = PeerMessageCodec::new();
+    let byte_counts = codec.byte_counts();
+    let peer: tokio_serde::Framed<
         Framed<S, LengthDelimitedCodec>,
         PeerMessage,
         PeerMessage,
-        Bincode<PeerMessage, PeerMessage>,
-    > = SymmetricallyFramed::new(length_delimited, SymmetricalBincode::default());
+        PeerMessageCodec,
+    > = tokio_serde::Framed::new(length_delimited, codec);
+    let mut peer = CapturingPeerStream::new(
+        peer,
+        PeerTrafficCapture::from_cli(state.cli(), peer_address).await,
+    );
 
     // Complete Neptune handshake
-    let peer_handshake_data: HandshakeData = match peer.try_next().await? {
+    let handshake_message = next_handshake_message(&mut peer, &state, peer_address).await?;
+    let peer_handshake_data: HandshakeData = match handshake_message {
         Some(PeerMessage::Handshake(payload)) => {
             let (v, hsd) = *payload;
             if v != crate::MAGIC_STRING_REQUEST {
@@ -227,16 +235,33 @@ where
                 &own_handshake_data,
                 &hsd,
                 &peer_address,
+                true,
             )
             .await;
 
             peer.send(PeerMessage::ConnectionStatus(connection_status))
                 .await?;
+            if connection_status == ConnectionStatus::Refused(ConnectionRefusedReason::SelfConnect)
+            {
+                // The address that we would dial to reach this "peer" leads back to us
+                if let Some(listen_port) = hsd.listen_port {
+                    let own_address = SocketAddr::new(peer_address.ip(), listen_port);
+                    state
+                        .lock_mut(|s| s.net.do_not_dial.insert(own_address))
+                        .await;
+                }
+            }
             if let ConnectionStatus::Refused(refused_reason) = connection_status {
                 warn!("Incoming connection refused: {:?}", refused_reason);
                 bail!("Refusing incoming connection. Reason: {:?}", refused_reason);
             }
 
+            // The connection status is the last message of the handshake. Both
+            // sides encode all following messages with the negotiated codec.
+            let codec = PeerCodec::negotiate(&own_handshake_data, &hsd);
+            debug!("Using {codec} codec with {peer_address}");
+            codec_switch.switch_to(codec);
+
             debug!("Got correct magic value request!");
             hsd
         }
@@ -255,7 +280,8 @@ where
         peer_handshake_data,
         true,
         peer_distance,
-    );
+    )
+    .with_byte_counts(byte_counts);
 
     peer_loop_handler
         .run_wrapper(peer, main_to_peer_thread_rx)
@@ -264,6 +290,59 @@ where
     Ok(())
 }
 
+/// Receive the next handshake message from the peer. A peer that does not
+/// send it within [`HANDSHAKE_TIMEOUT`] is sanctioned, as it holds on to a
+/// connection slot without becoming a peer.
+async fn next_handshake_message<S>(
+    peer: &mut S,
+    state: &GlobalStateLock,
+    peer_address: SocketAddr,
+) -> Result<Option<PeerMessage>>
+where
+    S: TryStream<Ok = PeerMessage> + Unpin,
+    <S as TryStream>::Error: std::error::Error + Sync + Send + 'static,
+{
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, peer.try_next()).await {
+        Ok(message) => Ok(message?),
+        Err(_elapsed) => {
+            let mut global_state_mut = state.lock_guard_mut().await;
+            let mut standing = global_state_mut.peer_standing(peer_address.ip()).await;
+            standing.sanction(PeerSanctionReason::HandshakeTimeout);
+            global_state_mut
+                .write_peer_standing_on_decrease(peer_address.ip(), standing)
+                .await;
+            bail!("Peer {peer_address} did not complete the handshake in time");
+        }
+    }
+}
+
+/// Back off from redialing the given address, and count the failure against
+/// it in the address book.
+async fn record_connection_failure(state: &GlobalStateLock, peer_address: SocketAddr) {
+    let mut global_state_mut = state.lock_guard_mut().await;
+    global_state_mut.net.record_dial_failure(peer_address);
+    global_state_mut
+        .net
+        .record_address_failure(peer_address)
+        .await;
+}
+
+/// Open a TCP connection to the peer, through the SOCKS5 proxy if one is
+/// configured. Peers given by host name are dialed by that name.
+async fn connect_to_peer_address(
+    state: &GlobalStateLock,
+    peer_address: SocketAddr,
+) -> Result<TcpStream> {
+    let target = state.cli().peer_target(peer_address);
+    match (state.cli().socks5_proxy, target) {
+        (Some(proxy), target) => Ok(socks5::connect(proxy, &target).await?),
+        (None, Socks5Target::Address(address)) => Ok(TcpStream::connect(address).await?),
+        (None, Socks5Target::DomainName(name, port)) => {
+            Ok(TcpStream::connect((name.as_str(), port)).await?)
+        }
+    }
+}
+
 /// Perform handshake and establish connection to a new peer while handling any panics in the peer
 /// thread gracefully.
 pub async fn call_peer_wrapper(
@@ -274,18 +353,27 @@ pub async fn call_peer_wrapper(
     own_handshake_data: HandshakeData,
     distance: u8,
 ) {
+    if state
+        .lock(|s| s.net.do_not_dial.contains(&peer_address))
+        .await
+    {
+        info!("Not connecting to {peer_address}, since it leads back to this node");
+        return;
+    }
+
     let state_clone = state.clone();
     let peer_thread_to_main_tx_clone = peer_thread_to_main_tx.clone();
     let panic_result = std::panic::AssertUnwindSafe(async {
         debug!("Attempting to initiate connection");
-        match tokio::net::TcpStream::connect(peer_address).await {
+        match connect_to_peer_address(&state, peer_address).await {
             Err(e) => {
                 warn!("Failed to establish connection: {}", e);
+                record_connection_failure(&state, peer_address).await;
             }
             Ok(stream) => {
                 match call_peer(
                     stream,
-                    state,
+                    state.clone(),
                     peer_address,
                     main_to_peer_thread_rx,
                     peer_thread_to_main_tx,
@@ -295,7 +383,10 @@ pub async fn call_peer_wrapper(
                 .await
                 {
                     Ok(()) => (),
-                    Err(e) => error!("An error occurred: {}. Connection closing", e),
+                    Err(e) => {
+                        error!("An error occurred: {}. Connection closing", e);
+                        record_connection_failure(&state, peer_address).await;
+                    }
                 }
             }
         };
@@ -335,12 +426,18 @@ where
 
     // Build the communication/serialization/frame handler
     let length_delimited = Framed::new(stream, get_codec_rules());
-    let mut peer: tokio_serde::Framed<
+    let (codec, codec_switch) = PeerMessageCodec::new();
+    let byte_counts = codec.byte_counts();
+    let peer: tokio_serde::Framed<
         Framed<S, LengthDelimitedCodec>,
         PeerMessage,
         PeerMessage,
-        Bincode<PeerMessage, PeerMessage>,
-    > = SymmetricallyFramed::new(length_delimited, SymmetricalBincode::default());
+        PeerMessageCodec,
+    > = tokio_serde::Framed::new(length_delimited, codec);
+    let mut peer = CapturingPeerStream::new(
+        peer,
+        PeerTrafficCapture::from_cli(state.cli(), peer_address).await,
+    );
 
     // Make Neptune handshake
     peer.send(PeerMessage::Handshake(Box::new((
@@ -350,7 +447,8 @@ where
     .await?;
     debug!("Awaiting connection status response from {}", peer_address);
 
-    let other_handshake: HandshakeData = match peer.try_next().await? {
+    let handshake_message = next_handshake_message(&mut peer, &state, peer_address).await?;
+    let other_handshake: HandshakeData = match handshake_message {
         Some(PeerMessage::Handshake(payload)) => {
             let (v, hsd) = *payload;
             if v != MAGIC_STRING_RESPONSE {
@@ -372,11 +470,22 @@ where
         }
     };
 
-    match peer.try_next().await? {
+    match next_handshake_message(&mut peer, &state, peer_address).await? {
         Some(PeerMessage::ConnectionStatus(ConnectionStatus::Accepted)) => {
             info!("Outgoing connection accepted by {peer_address}");
+
+            // The connection status is the last message of the handshake. Both
+            // sides encode all following messages with the negotiated codec.
+            let codec = PeerCodec::negotiate(own_handshake, &other_handshake);
+            debug!("Using {codec} codec with {peer_address}");
+            codec_switch.switch_to(codec);
         }
         Some(PeerMessage::ConnectionStatus(ConnectionStatus::Refused(reason))) => {
+            if reason == ConnectionRefusedReason::SelfConnect {
+                state
+                    .lock_mut(|s| s.net.do_not_dial.insert(peer_address))
+                    .await;
+            }
             bail!("Outgoing connection attempt refused. Reason: {:?}", reason);
         }
         _ => {
@@ -392,6 +501,7 @@ where
         own_handshake,
         &other_handshake,
         &peer_address,
+        false,
     )
     .await;
     if let ConnectionStatus::Refused(refused_reason) = connection_status {
@@ -399,6 +509,11 @@ where
             "Outgoing connection refused. Reason: {:?}\nNow hanging up.",
             refused_reason
         );
+        if refused_reason == ConnectionRefusedReason::SelfConnect {
+            state
+                .lock_mut(|s| s.net.do_not_dial.insert(peer_address))
+                .await;
+        }
         peer.send(PeerMessage::Bye).await?;
         bail!("Attempted to connect to peer that was not allowed. This connection attempt should not have been made.");
     }
@@ -410,7 +525,8 @@ where
         other_handshake,
         false,
         peer_distance,
-    );
+    )
+    .with_byte_counts(byte_counts);
     peer_loop_handler
         .run_wrapper(peer, main_to_peer_thread_rx)
         .await?;
@@ -432,6 +548,10 @@ pub async fn close_peer_connected_callback(
     let mut global_state_mut = global_state_lock.lock_guard_mut().await;
     // Store any new peer-standing to database
     let peer_info_writeback = global_state_mut.net.peer_map.remove(&peer_address);
+    global_state_mut
+        .net
+        .chain_split_detector
+        .forget_peer(peer_address);
 
     let new_standing = match peer_info_writeback {
         Some(new) => new.standing,
@@ -442,7 +562,6 @@ pub async fn close_peer_connected_callback(
     };
     debug!("Fetched peer info standing for {}", peer_address);
     global_state_mut
-        .net
         .write_peer_standing_on_decrease(peer_address.ip(), new_standing)
         .await;
     debug!("Stored peer info standing for {}", peer_address);
@@ -459,22 +578,27 @@ pub async fn close_peer_connected_callback(
 mod connect_tests {
     use crate::prelude::twenty_first;
 
-    use std::time::SystemTime;
+    use std::net::IpAddr;
+    use std::time::{Duration, SystemTime};
 
     use super::*;
 
     use anyhow::{bail, Result};
+    use itertools::Itertools;
     use tokio_test::io::Builder;
     use tracing_test::traced_test;
     use twenty_first::math::digest::Digest;
+    use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 
     use crate::config_models::network::Network;
+    use crate::models::blockchain::shared::Hash;
     use crate::models::peer::{
         ConnectionStatus, PeerInfo, PeerMessage, PeerSanctionReason, PeerStanding,
     };
     use crate::tests::shared::{
-        get_dummy_handshake_data_for_genesis, get_dummy_peer_connection_data_genesis,
-        get_dummy_socket_address, get_test_genesis_setup, to_bytes,
+        get_dummy_handshake_data_for_genesis, get_dummy_peer,
+        get_dummy_peer_connection_data_genesis, get_dummy_socket_address, get_test_genesis_setup,
+        make_mock_transaction, to_bytes,
     };
     use crate::{MAGIC_STRING_REQUEST, MAGIC_STRING_RESPONSE};
 
@@ -543,6 +667,7 @@ mod connect_tests {
             &own_handshake,
             &other_handshake,
             &peer_sa,
+            false,
         )
         .await;
         if status != ConnectionStatus::Accepted {
@@ -554,15 +679,16 @@ mod connect_tests {
             &own_handshake,
             &own_handshake,
             &peer_sa,
+            false,
         )
         .await;
         if status != ConnectionStatus::Refused(ConnectionRefusedReason::SelfConnect) {
             bail!("Must return ConnectionStatus::Refused(ConnectionRefusedReason::SelfConnect))");
         }
 
-        // pretend --max_peers is 1.
+        // pretend --max-outbound-peers is 1.
         let mut cli = state_lock.cli().clone();
-        cli.max_peers = 1;
+        cli.max_outbound_peers = 1;
         state_lock.set_cli(cli.clone()).await;
 
         status = check_if_connection_is_allowed(
@@ -570,6 +696,7 @@ mod connect_tests {
             &own_handshake,
             &other_handshake,
             &peer_sa,
+            false,
         )
         .await;
         if status != ConnectionStatus::Refused(ConnectionRefusedReason::MaxPeerNumberExceeded) {
@@ -578,8 +705,8 @@ mod connect_tests {
             );
         }
 
-        // pretend --max-peers is 100
-        cli.max_peers = 100;
+        // pretend --max-outbound-peers is 100
+        cli.max_outbound_peers = 100;
         state_lock.set_cli(cli.clone()).await;
 
         // Attempt to connect to already connected peer
@@ -593,6 +720,7 @@ mod connect_tests {
             &own_handshake,
             &mutated_other_handshake,
             &peer_sa,
+            false,
         )
         .await;
         if status != ConnectionStatus::Refused(ConnectionRefusedReason::AlreadyConnected) {
@@ -612,6 +740,7 @@ mod connect_tests {
             &own_handshake,
             &other_handshake,
             &peer_sa,
+            false,
         )
         .await;
         if status != ConnectionStatus::Refused(ConnectionRefusedReason::BadStanding) {
@@ -627,6 +756,7 @@ mod connect_tests {
             &own_handshake,
             &other_handshake,
             &peer_sa,
+            false,
         )
         .await;
         if status != ConnectionStatus::Accepted {
@@ -646,7 +776,6 @@ mod connect_tests {
         state_lock
             .lock_guard_mut()
             .await
-            .net
             .write_peer_standing_on_decrease(peer_sa.ip(), bad_standing)
             .await;
 
@@ -655,6 +784,7 @@ mod connect_tests {
             &own_handshake,
             &other_handshake,
             &peer_sa,
+            false,
         )
         .await;
         if status != ConnectionStatus::Refused(ConnectionRefusedReason::BadStanding) {
@@ -664,6 +794,118 @@ mod connect_tests {
         Ok(())
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn banned_peer_is_readmitted_after_ban_duration_test() -> Result<()> {
+        let network = Network::Alpha;
+        let (
+            _peer_broadcast_tx,
+            _from_main_rx_clone,
+            _to_main_tx,
+            _to_main_rx1,
+            mut state_lock,
+            _hsd,
+        ) = get_test_genesis_setup(network, 0).await?;
+        let (other_handshake, peer_sa) = get_dummy_peer_connection_data_genesis(network, 1).await;
+        let own_handshake = get_dummy_handshake_data_for_genesis(network).await;
+
+        // Ban the peer two hours ago
+        let old_sanction = PeerStanding {
+            standing: i32::MIN,
+            latest_sanction: Some(PeerSanctionReason::InvalidBlock((
+                7u64.into(),
+                Digest::default(),
+            ))),
+            timestamp_of_latest_sanction: Some(
+                SystemTime::now() - Duration::from_secs(2 * 60 * 60),
+            ),
+        };
+        state_lock
+            .lock_guard_mut()
+            .await
+            .write_peer_standing_on_decrease(peer_sa.ip(), old_sanction)
+            .await;
+
+        // A ban duration of zero keeps the peer banned
+        let mut cli = state_lock.cli().clone();
+        cli.peer_ban_duration = 0;
+        state_lock.set_cli(cli.clone()).await;
+        let permanent_ban_status = check_if_connection_is_allowed(
+            state_lock.clone(),
+            &own_handshake,
+            &other_handshake,
+            &peer_sa,
+            false,
+        )
+        .await;
+        assert_eq!(
+            ConnectionStatus::Refused(ConnectionRefusedReason::BadStanding),
+            permanent_ban_status
+        );
+
+        // A later, milder sanction does not replace a ban that is still active
+        cli.peer_ban_duration = 3;
+        state_lock.set_cli(cli.clone()).await;
+        let mild_sanction = PeerStanding {
+            standing: -1,
+            timestamp_of_latest_sanction: Some(SystemTime::now()),
+            ..old_sanction
+        };
+        state_lock
+            .lock_guard_mut()
+            .await
+            .write_peer_standing_on_decrease(peer_sa.ip(), mild_sanction)
+            .await;
+        assert_eq!(
+            old_sanction,
+            state_lock
+                .lock_guard()
+                .await
+                .net
+                .get_peer_standing_from_database(peer_sa.ip())
+                .await
+        );
+
+        // The ban has expired after one hour
+        cli.peer_ban_duration = 1;
+        state_lock.set_cli(cli).await;
+        let expired_ban_status = check_if_connection_is_allowed(
+            state_lock.clone(),
+            &own_handshake,
+            &other_handshake,
+            &peer_sa,
+            false,
+        )
+        .await;
+        assert_eq!(ConnectionStatus::Accepted, expired_ban_status);
+
+        // A new sanction bans the peer again, despite the lower standing on disk
+        let new_sanction = PeerStanding {
+            standing: i32::MIN / 2,
+            timestamp_of_latest_sanction: Some(SystemTime::now()),
+            ..old_sanction
+        };
+        state_lock
+            .lock_guard_mut()
+            .await
+            .write_peer_standing_on_decrease(peer_sa.ip(), new_sanction)
+            .await;
+        let new_ban_status = check_if_connection_is_allowed(
+            state_lock.clone(),
+            &own_handshake,
+            &other_handshake,
+            &peer_sa,
+            false,
+        )
+        .await;
+        assert_eq!(
+            ConnectionStatus::Refused(ConnectionRefusedReason::BadStanding),
+            new_ban_status
+        );
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn test_incoming_connection_succeed() -> Result<()> {
@@ -713,6 +955,45 @@ mod connect_tests {
         Ok(())
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn incoming_connection_without_handshake_is_sanctioned_test() -> Result<()> {
+        let network = Network::Alpha;
+        let own_handshake = get_dummy_handshake_data_for_genesis(network).await;
+        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, _hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+
+        // The clock advances on its own while the peer keeps silent
+        tokio::time::pause();
+        let mock = Builder::new()
+            .wait(HANDSHAKE_TIMEOUT + Duration::from_secs(1))
+            .build();
+        assert!(answer_peer(
+            mock,
+            state_lock.clone(),
+            peer_address,
+            from_main_rx_clone,
+            to_main_tx,
+            own_handshake,
+        )
+        .await
+        .is_err());
+
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .peer_standing(peer_address.ip())
+            .await;
+        assert_eq!(
+            Some(PeerSanctionReason::HandshakeTimeout),
+            standing.latest_sanction
+        );
+        assert!(state_lock.lock(|s| s.net.peer_map.is_empty()).await);
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn test_incoming_connection_fail_bad_magic_value() -> Result<()> {
@@ -785,9 +1066,10 @@ mod connect_tests {
         let state = state_lock.lock_guard().await;
         let mut own_handshake = state.get_own_handshakedata().await;
 
-        // Set reported versions to something incompatible
-        "0.0.3".clone_into(&mut own_handshake.version);
-        "0.0.0".clone_into(&mut other_handshake.version);
+        // Set reported protocol versions to something incompatible
+        own_handshake.min_protocol_version = 2;
+        own_handshake.protocol_version = 3;
+        other_handshake.protocol_version = 1;
 
         let peer_address = get_dummy_socket_address(55);
         let connection_status = check_if_connection_is_allowed(
@@ -795,6 +1077,7 @@ mod connect_tests {
             &own_handshake,
             &other_handshake,
             &peer_address,
+            true,
         )
         .await;
         assert_eq!(
@@ -836,6 +1119,114 @@ mod connect_tests {
         );
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn test_connection_status_bad_network() -> Result<()> {
+        let (_peer_broadcast_tx, _from_main_rx_clone, _to_main_tx, _to_main_rx1, state_lock, _hsd) =
+            get_test_genesis_setup(Network::Alpha, 0).await?;
+        let own_handshake = get_dummy_handshake_data_for_genesis(Network::Alpha).await;
+        let other_handshake = get_dummy_handshake_data_for_genesis(Network::Testnet).await;
+        assert!(
+            own_handshake.is_compatible_with(&other_handshake),
+            "versions must be compatible for this test"
+        );
+
+        let connection_status = check_if_connection_is_allowed(
+            state_lock,
+            &own_handshake,
+            &other_handshake,
+            &get_dummy_socket_address(55),
+            true,
+        )
+        .await;
+        assert_eq!(
+            ConnectionStatus::Refused(ConnectionRefusedReason::BadNetwork),
+            connection_status,
+            "Connection status must be refused for peer on other network"
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn too_many_connections_from_host_are_refused_test() -> Result<()> {
+        let network = Network::Alpha;
+        let (_peer_broadcast_tx, _from_main_rx_clone, _to_main_tx, _to_main_rx1, state_lock, _hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let accept = |peer_address: SocketAddr| {
+            let state_lock = state_lock.clone();
+            async move {
+                state_lock
+                    .lock_mut(|s| s.accept_inbound_connection(peer_address))
+                    .await
+            }
+        };
+
+        // Two connections from the same IP address are accepted, not three,
+        // even while the first two are still handshaking
+        let host: IpAddr = "203.0.113.7".parse()?;
+        assert!(accept(SocketAddr::new(host, 9798)).await);
+        assert!(accept(SocketAddr::new(host, 9799)).await);
+        assert!(!accept(SocketAddr::new(host, 9800)).await);
+
+        // Mapped IPv6 addresses count towards the same host
+        let mapped_host: IpAddr = "::ffff:203.0.113.7".parse()?;
+        assert!(!accept(SocketAddr::new(mapped_host, 9798)).await);
+
+        // A closed connection frees its slot
+        state_lock
+            .lock_mut(|s| {
+                s.net
+                    .release_inbound_connection(SocketAddr::new(host, 9798))
+            })
+            .await;
+        assert!(accept(SocketAddr::new(host, 9800)).await);
+
+        // Connected peers count as well. Four connections from the same
+        // subnet are accepted, not five.
+        let mut peer = get_dummy_peer(SocketAddr::new("203.0.200.1".parse()?, 9798));
+        peer.inbound = true;
+        state_lock
+            .lock_mut(|s| s.net.peer_map.insert(peer.connected_address, peer))
+            .await;
+        assert!(accept(SocketAddr::new("203.0.200.2".parse()?, 9798)).await);
+        assert!(!accept(SocketAddr::new("203.0.100.1".parse()?, 9798)).await);
+
+        // Other subnets and the loopback interface are unaffected
+        assert!(accept(SocketAddr::new("198.51.100.1".parse()?, 9798)).await);
+        for port in [9798, 9799, 9800, 9801] {
+            assert!(accept(SocketAddr::new(get_dummy_socket_address(1).ip(), port)).await);
+        }
+
+        // Answering a connection releases it when the connection ends
+        let peer_address = SocketAddr::new("198.51.100.1".parse()?, 9798);
+        let mock = Builder::new().read(&to_bytes(&PeerMessage::Bye)?).build();
+        let (_broadcast_tx, from_main_rx, to_main_tx, _to_main_rx, _state_lock, _hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let own_handshake = get_dummy_handshake_data_for_genesis(network).await;
+        assert!(answer_peer_wrapper(
+            mock,
+            state_lock.clone(),
+            peer_address,
+            from_main_rx,
+            to_main_tx,
+            own_handshake,
+        )
+        .await
+        .is_err());
+        assert_eq!(
+            (0, 0),
+            state_lock
+                .lock_guard()
+                .await
+                .net
+                .inbound_peer_counts_from(peer_address.ip())
+        );
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn test_incoming_connection_fail_max_peers_exceeded() -> Result<()> {
@@ -867,9 +1258,18 @@ mod connect_tests {
             _hsd,
         ) = get_test_genesis_setup(Network::Alpha, 2).await?;
 
-        // set max_peers to 2 to ensure failure on next connection attempt
+        // let both peers be inbound and set max_inbound_peers to 2 to ensure
+        // failure on next connection attempt
+        state_lock
+            .lock_mut(|s| {
+                s.net
+                    .peer_map
+                    .values_mut()
+                    .for_each(|peer| peer.inbound = true)
+            })
+            .await;
         let mut cli = state_lock.cli().clone();
-        cli.max_peers = 2;
+        cli.max_inbound_peers = 2;
         state_lock.set_cli(cli).await;
 
         let answer = answer_peer(
@@ -886,6 +1286,139 @@ mod connect_tests {
         Ok(())
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn inbound_connections_cannot_take_outbound_slots_test() -> Result<()> {
+        let network = Network::Alpha;
+        let (
+            _peer_broadcast_tx,
+            from_main_rx_clone,
+            to_main_tx,
+            _to_main_rx1,
+            mut state_lock,
+            _hsd,
+        ) = get_test_genesis_setup(network, 2).await?;
+        let own_handshake = get_dummy_handshake_data_for_genesis(network).await;
+        let (other_handshake, peer_sa) = get_dummy_peer_connection_data_genesis(network, 3).await;
+
+        // Both connected peers are inbound, which exhausts the inbound slots
+        // while all outbound slots remain free
+        state_lock
+            .lock_mut(|s| {
+                s.net
+                    .peer_map
+                    .values_mut()
+                    .for_each(|peer| peer.inbound = true)
+            })
+            .await;
+        let mut cli = state_lock.cli().clone();
+        cli.max_inbound_peers = 2;
+        cli.max_outbound_peers = 1;
+        state_lock.set_cli(cli).await;
+
+        assert_eq!(
+            ConnectionStatus::Refused(ConnectionRefusedReason::MaxPeerNumberExceeded),
+            check_if_connection_is_allowed(
+                state_lock.clone(),
+                &own_handshake,
+                &other_handshake,
+                &peer_sa,
+                true,
+            )
+            .await
+        );
+        assert_eq!(
+            ConnectionStatus::Accepted,
+            check_if_connection_is_allowed(
+                state_lock.clone(),
+                &own_handshake,
+                &other_handshake,
+                &peer_sa,
+                false,
+            )
+            .await
+        );
+
+        // An incoming connection is refused during the handshake
+        let mock = Builder::new()
+            .read(&to_bytes(&PeerMessage::Handshake(Box::new((
+                MAGIC_STRING_REQUEST.to_vec(),
+                other_handshake,
+            ))))?)
+            .write(&to_bytes(&PeerMessage::Handshake(Box::new((
+                MAGIC_STRING_RESPONSE.to_vec(),
+                own_handshake.clone(),
+            ))))?)
+            .write(&to_bytes(&PeerMessage::ConnectionStatus(
+                ConnectionStatus::Refused(ConnectionRefusedReason::MaxPeerNumberExceeded),
+            ))?)
+            .build();
+        let answer = answer_peer(
+            mock,
+            state_lock.clone(),
+            peer_sa,
+            from_main_rx_clone,
+            to_main_tx,
+            own_handshake,
+        )
+        .await;
+        assert!(answer.is_err(), "inbound connection must be refused");
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn self_connection_is_refused_and_never_dialed_again_test() -> Result<()> {
+        // In this scenario the node connects to itself, e.g. through its own
+        // advertised address. The connecting side presents the same instance
+        // ID as the node's networking state.
+        let network = Network::Alpha;
+        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, _hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let own_handshake = state_lock.lock_guard().await.get_own_handshakedata().await;
+        let mut other_handshake = get_dummy_handshake_data_for_genesis(network).await;
+        other_handshake.instance_id = state_lock.lock(|s| s.net.instance_id).await;
+
+        let mock = Builder::new()
+            .read(&to_bytes(&PeerMessage::Handshake(Box::new((
+                MAGIC_STRING_REQUEST.to_vec(),
+                other_handshake.clone(),
+            ))))?)
+            .write(&to_bytes(&PeerMessage::Handshake(Box::new((
+                MAGIC_STRING_RESPONSE.to_vec(),
+                own_handshake.clone(),
+            ))))?)
+            .write(&to_bytes(&PeerMessage::ConnectionStatus(
+                ConnectionStatus::Refused(ConnectionRefusedReason::SelfConnect),
+            ))?)
+            .build();
+
+        let peer_address = get_dummy_socket_address(0);
+        let answer = answer_peer(
+            mock,
+            state_lock.clone(),
+            peer_address,
+            from_main_rx_clone,
+            to_main_tx,
+            own_handshake,
+        )
+        .await;
+        assert!(answer.is_err(), "self-connection must result in error");
+
+        // The address on which the "peer" listens is this node itself
+        let own_listen_address =
+            SocketAddr::new(peer_address.ip(), other_handshake.listen_port.unwrap());
+        assert!(
+            state_lock
+                .lock(|s| s.net.do_not_dial.contains(&own_listen_address))
+                .await
+        );
+        assert!(state_lock.lock(|s| s.net.peer_map.is_empty()).await);
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn disallow_ingoing_connections_from_banned_peers_test() -> Result<()> {
@@ -928,7 +1461,6 @@ mod connect_tests {
         state_lock
             .lock_guard_mut()
             .await
-            .net
             .write_peer_standing_on_decrease(peer_address.ip(), bad_standing)
             .await;
 
@@ -954,4 +1486,191 @@ mod connect_tests {
 
         Ok(())
     }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn transaction_is_relayed_between_two_nodes_test() -> Result<()> {
+        // Node A holds a transaction in its mempool, e.g. because a wallet
+        // submitted it there, and announces it to its peer, node B.
+        let network = Network::Alpha;
+        let (a_to_peers_tx, a_from_main_rx, a_to_main_tx, _a_to_main_rx, a_state_lock, _) =
+            get_test_genesis_setup(network, 0).await?;
+        let (_b_to_peers_tx, b_from_main_rx, b_to_main_tx, mut b_to_main_rx, b_state_lock, _) =
+            get_test_genesis_setup(network, 0).await?;
+        let a_handshake = a_state_lock
+            .lock_guard()
+            .await
+            .get_own_handshakedata()
+            .await;
+        let b_handshake = b_state_lock
+            .lock_guard()
+            .await
+            .get_own_handshakedata()
+            .await;
+
+        let transaction = make_mock_transaction(vec![], vec![]);
+        let transaction_id = Hash::hash(&transaction);
+        a_state_lock
+            .lock_mut(|s| s.mempool.insert(&transaction))
+            .await
+            .unwrap();
+
+        // Connect the nodes through an in-memory stream
+        let (a_stream, b_stream) = tokio::io::duplex(1 << 20);
+        let a_connection = tokio::spawn(answer_peer(
+            a_stream,
+            a_state_lock.clone(),
+            get_dummy_socket_address(1),
+            a_from_main_rx,
+            a_to_main_tx,
+            a_handshake,
+        ));
+        let b_state_lock_clone = b_state_lock.clone();
+        let b_connection = tokio::spawn(async move {
+            call_peer(
+                b_stream,
+                b_state_lock_clone,
+                get_dummy_socket_address(0),
+                b_from_main_rx,
+                b_to_main_tx,
+                &b_handshake,
+                1,
+            )
+            .await
+        });
+        a_to_peers_tx.send(MainToPeerThread::TransactionNotification(
+            transaction.clone().into(),
+        ))?;
+
+        // Node B requests the transaction, and its peer thread hands it to the
+        // main loop, which inserts it into the mempool
+        let relayed = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match b_to_main_rx.recv().await {
+                    Some(PeerThreadToMain::Transaction(relayed)) => return Some(relayed),
+                    Some(_) => continue,
+                    None => return None,
+                }
+            }
+        })
+        .await?
+        .expect("node B must hand the transaction to its main loop");
+        assert_eq!(transaction, relayed.transaction);
+        assert!(b_state_lock
+            .lock_guard_mut()
+            .await
+            .insert_relayed_transaction(&relayed));
+        assert!(b_state_lock
+            .lock_guard()
+            .await
+            .mempool
+            .contains(transaction_id));
+
+        // Relaying the transaction again is suppressed
+        assert!(!b_state_lock
+            .lock_guard_mut()
+            .await
+            .insert_relayed_transaction(&relayed));
+        assert!(
+            b_state_lock
+                .lock(|s| s.net.is_recently_seen_transaction(transaction_id))
+                .await
+        );
+
+        a_connection.abort();
+        b_connection.abort();
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn new_node_fetches_mempool_of_peer_test() -> Result<()> {
+        // Node A has been running for a while and holds transactions in its
+        // mempool. Node B was just started and connects to node A.
+        let network = Network::Alpha;
+        let (_a_to_peers_tx, a_from_main_rx, a_to_main_tx, _a_to_main_rx, a_state_lock, _) =
+            get_test_genesis_setup(network, 0).await?;
+        let (b_to_peers_tx, b_from_main_rx, b_to_main_tx, mut b_to_main_rx, b_state_lock, _) =
+            get_test_genesis_setup(network, 0).await?;
+        let a_handshake = a_state_lock
+            .lock_guard()
+            .await
+            .get_own_handshakedata()
+            .await;
+        let b_handshake = b_state_lock
+            .lock_guard()
+            .await
+            .get_own_handshakedata()
+            .await;
+
+        let transactions = (0..3)
+            .map(|_| make_mock_transaction(vec![], vec![]))
+            .collect_vec();
+        a_state_lock
+            .lock_mut(|s| {
+                for transaction in transactions.iter() {
+                    s.mempool.insert(transaction).unwrap();
+                }
+            })
+            .await;
+        assert!(b_state_lock.lock(|s| s.mempool.is_empty()).await);
+
+        let (a_stream, b_stream) = tokio::io::duplex(1 << 20);
+        let a_connection = tokio::spawn(answer_peer(
+            a_stream,
+            a_state_lock.clone(),
+            get_dummy_socket_address(1),
+            a_from_main_rx,
+            a_to_main_tx,
+            a_handshake,
+        ));
+        let b_state_lock_clone = b_state_lock.clone();
+        let b_connection = tokio::spawn(async move {
+            call_peer(
+                b_stream,
+                b_state_lock_clone,
+                get_dummy_socket_address(0),
+                b_from_main_rx,
+                b_to_main_tx,
+                &b_handshake,
+                1,
+            )
+            .await
+        });
+
+        // Node B's main loop asks node A for its mempool once connected, and
+        // inserts the transactions that its peer thread fetches
+        b_to_peers_tx.send(MainToPeerThread::RequestMempoolInventory(
+            get_dummy_socket_address(0),
+        ))?;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            let mut fetched_count = 0;
+            while fetched_count < transactions.len() {
+                match b_to_main_rx.recv().await {
+                    Some(PeerThreadToMain::Transaction(relayed)) => {
+                        assert!(b_state_lock
+                            .lock_guard_mut()
+                            .await
+                            .insert_relayed_transaction(&relayed));
+                        fetched_count += 1;
+                    }
+                    Some(_) => continue,
+                    None => panic!("node B's peer thread must not stop"),
+                }
+            }
+        })
+        .await?;
+
+        let a_transaction_ids = a_state_lock.lock(|s| s.mempool.id_set()).await;
+        assert!(b_state_lock
+            .lock(|s| s.mempool.diff(&a_transaction_ids))
+            .await
+            .is_empty());
+
+        a_connection.abort();
+        b_connection.abort();
+
+        Ok(())
+    }
 }
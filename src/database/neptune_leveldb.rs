@@ -101,6 +101,14 @@ where
         value_bytes.map(|bytes| bincode::deserialize(&bytes).unwrap())
     }
 
+    fn try_get(&self, key: Key) -> Result<Option<Value>> {
+        let key_bytes: Vec<u8> = bincode::serialize(&key)?;
+        let value_bytes: Option<Vec<u8>> = self.database.get(&key_bytes)?;
+        Ok(value_bytes
+            .map(|bytes| bincode::deserialize(&bytes))
+            .transpose()?)
+    }
+
     fn get_u8(&mut self, key: &[u8]) -> Option<Vec<u8>> {
         self.database.get_u8(key).unwrap()
     }
@@ -151,6 +159,17 @@ where
             .write(&WriteBatch::new(), true)
             .expect("Database flushing to disk must succeed");
     }
+
+    fn compact_range_of(&mut self, keys: &[Key]) {
+        let key_bytes: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|key| bincode::serialize(key).unwrap())
+            .collect();
+        let (Some(start), Some(limit)) = (key_bytes.iter().min(), key_bytes.iter().max()) else {
+            return;
+        };
+        self.database.compact(start, limit);
+    }
 }
 
 /// `NeptuneLevelDb` provides an async-friendly and clone-friendly wrapper
@@ -237,6 +256,13 @@ where
         task::spawn_blocking(move || inner.get(key)).await.unwrap()
     }
 
+    /// Get database value asynchronously, or an error if it cannot be read or
+    /// decoded, e.g. because it was corrupted on disk
+    pub async fn try_get(&self, key: Key) -> Result<Option<Value>> {
+        let inner = self.0.clone();
+        task::spawn_blocking(move || inner.try_get(key)).await?
+    }
+
     pub async fn get_u8(&self, key: Vec<u8>) -> Option<Vec<u8>> {
         let mut inner = self.0.clone();
         task::spawn_blocking(move || inner.get_u8(&key))
@@ -281,6 +307,15 @@ where
         task::spawn_blocking(move || inner.flush()).await.unwrap()
     }
 
+    /// Compact the smallest key range containing all the given keys
+    /// asynchronously, e.g. to reclaim the space of deleted entries.
+    pub async fn compact_range_of(&mut self, keys: Vec<Key>) {
+        let mut inner = self.0.clone();
+        task::spawn_blocking(move || inner.compact_range_of(&keys))
+            .await
+            .unwrap()
+    }
+
     /// returns the directory path of the database files on disk.
     #[inline]
     pub fn path(&self) -> &std::path::PathBuf {
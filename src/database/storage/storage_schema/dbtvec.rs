@@ -57,6 +57,11 @@ where
         self.inner.get(index).await
     }
 
+    #[inline]
+    async fn try_get(&self, index: Index) -> anyhow::Result<V> {
+        self.inner.try_get(index).await
+    }
+
     #[inline]
     async fn get_many(&self, indices: &[Index]) -> Vec<V> {
         self.inner.get_many(indices).await
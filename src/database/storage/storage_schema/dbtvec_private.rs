@@ -2,6 +2,7 @@ use super::super::storage_vec::Index;
 use super::RustyKey;
 use super::{traits::StorageReader, PendingWrites, RustyValue, SimpleRustyReader, WriteOperation};
 use crate::locks::tokio::AtomicRw;
+use anyhow::bail;
 use itertools::Itertools;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
@@ -66,6 +67,28 @@ impl<V: Clone + Serialize + DeserializeOwned> DbtVecPrivate<V> {
         val.into_any()
     }
 
+    /// Like [Self::get], but returns an error rather than panicking if the
+    /// element is out of bounds, missing, or cannot be decoded.
+    pub(super) async fn try_get(&self, index: Index) -> anyhow::Result<V> {
+        let length = self.len().await;
+        if index >= length {
+            bail!(
+                "Out-of-bounds. Got {index} but length was {length}. persisted vector name: {}",
+                self.name
+            );
+        }
+
+        if let Some(value) = self.cache.get(&index) {
+            return Ok(value.clone());
+        }
+
+        let key: RustyKey = self.get_index_key(index);
+        let Some(val) = self.reader.try_get(key).await? else {
+            bail!("Element with index {index} does not exist in {}", self.name);
+        };
+        Ok(val.try_into_any()?)
+    }
+
     #[inline]
     pub(super) async fn set(&mut self, index: Index, value: V) {
         // Disallow setting values out-of-bounds
@@ -93,6 +93,13 @@ impl RustyValue {
     pub fn into_any<T: DeserializeOwned>(&self) -> T {
         deserialize(&self.0)
     }
+
+    /// Like [Self::into_any], but returns an error rather than panicking if
+    /// the bytes do not decode to a `T`, e.g. because they were corrupted.
+    #[inline]
+    pub fn try_into_any<T: DeserializeOwned>(&self) -> Result<T, bincode::Error> {
+        bincode::deserialize(&self.0)
+    }
 }
 
 /// serialize a value T that implements serde::Serialize into bytes
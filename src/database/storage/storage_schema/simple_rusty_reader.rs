@@ -10,6 +10,15 @@ pub struct SimpleRustyReader {
     pub(super) db: NeptuneLevelDb<RustyKey, RustyValue>,
 }
 
+impl SimpleRustyReader {
+    /// Return a single value from storage, or an error if it cannot be read
+    /// or decoded
+    #[inline]
+    pub(super) async fn try_get(&self, key: RustyKey) -> anyhow::Result<Option<RustyValue>> {
+        self.db.try_get(key).await
+    }
+}
+
 impl StorageReader for SimpleRustyReader {
     #[inline]
     async fn get(&self, key: RustyKey) -> Option<RustyValue> {
@@ -33,6 +33,11 @@ impl<T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static> StorageVec
         self.0.get(index)
     }
 
+    #[inline]
+    async fn try_get(&self, index: Index) -> anyhow::Result<T> {
+        self.0.try_get(index)
+    }
+
     #[inline]
     async fn get_many(&self, indices: &[Index]) -> Vec<T> {
         self.0.get_many(indices)
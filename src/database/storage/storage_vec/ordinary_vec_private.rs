@@ -20,6 +20,17 @@ impl<T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static> OrdinaryVe
             .clone()
     }
 
+    #[inline]
+    pub(super) fn try_get(&self, index: Index) -> anyhow::Result<T> {
+        self.0.get(index as usize).cloned().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Out-of-bounds. Got index {} but length was {}.",
+                index,
+                self.0.len(),
+            )
+        })
+    }
+
     pub(super) fn get_many(&self, indices: &[Index]) -> Vec<T> {
         indices.iter().map(|i| self.get(*i)).collect()
     }
@@ -25,6 +25,10 @@ pub trait StorageVecBase<T: Send> {
     /// get single element at index
     async fn get(&self, index: Index) -> T;
 
+    /// get single element at index, or an error if it is out of bounds or
+    /// cannot be decoded from storage, e.g. because it was corrupted on disk
+    async fn try_get(&self, index: Index) -> anyhow::Result<T>;
+
     /// get multiple elements matching indices
     ///
     /// This is a convenience method. For large collections
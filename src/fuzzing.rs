@@ -0,0 +1,661 @@
+//! Harness for fuzzing the handling of messages received from a peer.
+//!
+//! Fuzzed input is decoded into a short sequence of [FuzzMessage]s, which are
+//! turned into [PeerMessage]s built around the genesis block and fed to a
+//! [PeerLoopHandler] without a connection. After each message, the state of
+//! the node is checked against a simple oracle: the standing of the peer may
+//! only get worse, any change of standing must be recorded as a sanction, and
+//! the peer must remain known to the node. Panics and deadlocks, which are
+//! caught by a timeout, count as failures as well.
+//!
+//! Only compiled with the `fuzzing` feature. The cargo-fuzz target lives in
+//! the `fuzz` directory:
+//!
+//! ```text
+//! cargo +nightly fuzz run peer_messages
+//! ```
+
+use crate::config_models::cli_args;
+use crate::config_models::data_directory::DataDirectory;
+use crate::config_models::network::Network;
+use crate::models::blockchain::block::block_header::BlockHeader;
+use crate::models::blockchain::block::block_height::BlockHeight;
+use crate::models::blockchain::block::transfer_block::{ProofType, TransferBlock};
+use crate::models::blockchain::block::Block;
+use crate::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
+use crate::models::channel::PeerThreadToMain;
+use crate::models::consensus::mast_hash::MastHash;
+use crate::models::consensus::timestamp::Timestamp;
+use crate::models::peer::{
+    BlockServingPolicy, ConnectionRefusedReason, ConnectionStatus, HandshakeData, MutablePeerState,
+    PeerBlockNotification, PeerFeatures, PeerInfo, PeerMessage, PeerStanding, PeerStatistics,
+    TransactionNotification, TransactionNotificationWithFee, MIN_SUPPORTED_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
+};
+use crate::models::state::archival_state::ArchivalState;
+use crate::models::state::block_cache::DEFAULT_BLOCK_CACHE_CAPACITY;
+use crate::models::state::blockchain_state::{BlockchainArchivalState, BlockchainState};
+use crate::models::state::header_chain::block_digest;
+use crate::models::state::light_state::LightState;
+use crate::models::state::mempool::Mempool;
+use crate::models::state::networking_state::NetworkingState;
+use crate::models::state::wallet::wallet_state::WalletState;
+use crate::models::state::wallet::WalletSecret;
+use crate::models::state::GlobalStateLock;
+use crate::peer_loop::PeerLoopHandler;
+use crate::prelude::twenty_first;
+use anyhow::{bail, Result};
+use arbitrary::{Arbitrary, Unstructured};
+use bytesize::ByteSize;
+use futures::{Sink, Stream};
+use rand::distributions::{Alphanumeric, DistString};
+use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use twenty_first::math::b_field_element::BFieldElement;
+use twenty_first::math::digest::Digest;
+
+/// Maximum number of messages decoded from a single fuzzed input
+pub const MAX_FUZZ_MESSAGES: usize = 16;
+
+/// Maximum length of the collections in a fuzzed message, such that the
+/// fuzzer explores sequences of messages rather than large messages
+pub const MAX_FUZZ_COLLECTION_LENGTH: usize = 8;
+
+/// Time allowed for handling a single message before it is considered a
+/// deadlock
+const MESSAGE_HANDLING_TIMEOUT: Duration = Duration::from_secs(30);
+
+const FUZZ_NETWORK: Network = Network::RegTest;
+
+/// A digest that is either that of the genesis block, which the node knows,
+/// or an arbitrary one, which it most likely does not.
+#[derive(Debug, Clone, Copy, Arbitrary)]
+pub enum FuzzDigest {
+    Genesis,
+    Other(Digest),
+}
+
+impl FuzzDigest {
+    fn to_digest(self, genesis_block: &Block) -> Digest {
+        match self {
+            FuzzDigest::Genesis => genesis_block.hash(),
+            FuzzDigest::Other(digest) => digest,
+        }
+    }
+}
+
+/// A block that is the genesis block with a tweaked header. Such blocks are
+/// invalid, but they exercise the code that handles blocks from peers.
+#[derive(Debug, Clone, Copy, Arbitrary)]
+pub struct FuzzBlock {
+    pub height: u8,
+    pub builds_on_genesis: bool,
+    pub timestamp_offset_secs: u16,
+    /// Multiples of the genesis difficulty added to the proof-of-work family
+    pub added_proof_of_work: u8,
+    pub nonce: u64,
+}
+
+impl FuzzBlock {
+    fn to_header(self, genesis_block: &Block) -> BlockHeader {
+        let mut header = genesis_block.kernel.header.clone();
+        header.height = BlockHeight::from(self.height as u64);
+        header.prev_block_digest = if self.builds_on_genesis {
+            genesis_block.hash()
+        } else {
+            Digest::default()
+        };
+        header.timestamp = header.timestamp + Timestamp::seconds(self.timestamp_offset_secs as u64);
+        for _ in 0..self.added_proof_of_work {
+            header.proof_of_work_family = header.proof_of_work_family + header.difficulty;
+        }
+        header.nonce[0] = BFieldElement::new(self.nonce);
+
+        header
+    }
+
+    fn to_transfer_block(self, genesis_block: &Block) -> TransferBlock {
+        TransferBlock {
+            header: self.to_header(genesis_block),
+            body: genesis_block.kernel.body.clone(),
+            proof_type: ProofType::Unimplemented,
+        }
+    }
+}
+
+/// A peer message with bounded, mostly small, fields. Messages that carry
+/// blocks or transactions are built from the genesis block.
+#[derive(Debug, Clone, Arbitrary)]
+pub enum FuzzMessage {
+    Handshake,
+    Block(FuzzBlock),
+    BlockNotificationRequest,
+    BlockNotification(FuzzBlock),
+    BlockRequestByHeight(u8),
+    BlockRequestByHash(FuzzDigest),
+    BlockRequestBatch(Vec<FuzzDigest>, u16),
+    BlockResponseBatch(Vec<FuzzBlock>),
+    Transaction {
+        fee: u32,
+    },
+    TransactionNotification(FuzzDigest),
+    TransactionRequest(FuzzDigest),
+    PeerListRequest,
+    PeerListResponse(Vec<(u32, u16, u128)>),
+    Bye,
+    ConnectionStatus(bool),
+    BlockRequestBatchByHeight {
+        start_height: u8,
+        count: u16,
+    },
+    CompactBlock {
+        block: FuzzBlock,
+        digest: FuzzDigest,
+        txids: Vec<FuzzDigest>,
+    },
+    GetBlockTransactions(Vec<FuzzDigest>),
+    BlockTransactions(u8),
+    MempoolInventoryRequest,
+    MempoolInventoryResponse(Vec<(FuzzDigest, u32, u16)>),
+    BlockHeaderRequest {
+        start_height: u8,
+        length: u8,
+    },
+    BlockHeadersResponse(Vec<FuzzBlock>),
+    Ping(u64),
+    Pong(u64),
+    BlockRequestThrottled {
+        retry_after_in_secs: u64,
+        max_blocks: u64,
+        max_bytes: u64,
+        window_in_secs: u64,
+    },
+    TransactionNotificationWithFee {
+        transaction: FuzzDigest,
+        fee: u32,
+    },
+}
+
+fn bounded<T: Clone>(items: &[T]) -> impl Iterator<Item = T> + '_ {
+    items.iter().take(MAX_FUZZ_COLLECTION_LENGTH).cloned()
+}
+
+impl FuzzMessage {
+    pub fn to_peer_message(&self, genesis_block: &Block) -> PeerMessage {
+        let transaction_with_fee = |fee: u32| {
+            let mut transaction = genesis_block.kernel.body.transaction.clone();
+            transaction.kernel.fee = NeptuneCoins::new(fee);
+            transaction
+        };
+
+        match self {
+            FuzzMessage::Handshake => {
+                let handshake = HandshakeData {
+                    tip_header: genesis_block.kernel.header.clone(),
+                    listen_port: None,
+                    network: FUZZ_NETWORK,
+                    instance_id: 0,
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    protocol_version: PROTOCOL_VERSION,
+                    min_protocol_version: MIN_SUPPORTED_PROTOCOL_VERSION,
+                    features: PeerFeatures::ARCHIVAL,
+                };
+                PeerMessage::Handshake(Box::new((vec![], handshake)))
+            }
+            FuzzMessage::Block(block) => {
+                PeerMessage::Block(Box::new(block.to_transfer_block(genesis_block)))
+            }
+            FuzzMessage::BlockNotificationRequest => PeerMessage::BlockNotificationRequest,
+            FuzzMessage::BlockNotification(block) => {
+                let header = block.to_header(genesis_block);
+                PeerMessage::BlockNotification(PeerBlockNotification {
+                    hash: block_digest(&header, genesis_block.kernel.body.mast_hash()),
+                    height: header.height,
+                    proof_of_work_family: header.proof_of_work_family,
+                })
+            }
+            FuzzMessage::BlockRequestByHeight(height) => {
+                PeerMessage::BlockRequestByHeight(BlockHeight::from(*height as u64))
+            }
+            FuzzMessage::BlockRequestByHash(digest) => {
+                PeerMessage::BlockRequestByHash(digest.to_digest(genesis_block))
+            }
+            FuzzMessage::BlockRequestBatch(digests, count) => PeerMessage::BlockRequestBatch(
+                bounded(digests)
+                    .map(|digest| digest.to_digest(genesis_block))
+                    .collect(),
+                *count as usize,
+            ),
+            FuzzMessage::BlockResponseBatch(blocks) => PeerMessage::BlockResponseBatch(
+                bounded(blocks)
+                    .map(|block| block.to_transfer_block(genesis_block))
+                    .collect(),
+            ),
+            FuzzMessage::Transaction { fee } => {
+                PeerMessage::Transaction(Box::new(transaction_with_fee(*fee)))
+            }
+            FuzzMessage::TransactionNotification(transaction) => {
+                PeerMessage::TransactionNotification(TransactionNotification {
+                    transaction_digest: transaction.to_digest(genesis_block),
+                })
+            }
+            FuzzMessage::TransactionRequest(digest) => {
+                PeerMessage::TransactionRequest(digest.to_digest(genesis_block))
+            }
+            FuzzMessage::PeerListRequest => PeerMessage::PeerListRequest,
+            FuzzMessage::PeerListResponse(peers) => PeerMessage::PeerListResponse(
+                bounded(peers)
+                    .map(|(ip, port, instance_id)| {
+                        (
+                            SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip)), port),
+                            instance_id,
+                        )
+                    })
+                    .collect(),
+            ),
+            FuzzMessage::Bye => PeerMessage::Bye,
+            FuzzMessage::ConnectionStatus(accepted) => {
+                PeerMessage::ConnectionStatus(if *accepted {
+                    ConnectionStatus::Accepted
+                } else {
+                    ConnectionStatus::Refused(ConnectionRefusedReason::BadStanding)
+                })
+            }
+            FuzzMessage::BlockRequestBatchByHeight {
+                start_height,
+                count,
+            } => PeerMessage::BlockRequestBatchByHeight {
+                start_height: BlockHeight::from(*start_height as u64),
+                count: *count,
+            },
+            FuzzMessage::CompactBlock {
+                block,
+                digest,
+                txids,
+            } => PeerMessage::CompactBlock {
+                digest: digest.to_digest(genesis_block),
+                header: block.to_header(genesis_block),
+                txids: bounded(txids)
+                    .map(|digest| digest.to_digest(genesis_block))
+                    .collect(),
+                coinbase: Box::new(genesis_block.kernel.body.transaction.clone()),
+            },
+            FuzzMessage::GetBlockTransactions(txids) => PeerMessage::GetBlockTransactions(
+                bounded(txids)
+                    .map(|digest| digest.to_digest(genesis_block))
+                    .collect(),
+            ),
+            FuzzMessage::BlockTransactions(count) => PeerMessage::BlockTransactions(
+                (0..*count as usize)
+                    .take(MAX_FUZZ_COLLECTION_LENGTH)
+                    .map(|i| transaction_with_fee(i as u32))
+                    .collect(),
+            ),
+            FuzzMessage::MempoolInventoryRequest => PeerMessage::MempoolInventoryRequest,
+            FuzzMessage::MempoolInventoryResponse(inventory) => {
+                PeerMessage::MempoolInventoryResponse(
+                    bounded(inventory)
+                        .map(|(digest, fee, size)| {
+                            (
+                                digest.to_digest(genesis_block),
+                                NeptuneCoins::new(fee),
+                                size as usize,
+                            )
+                        })
+                        .collect(),
+                )
+            }
+            FuzzMessage::BlockHeaderRequest {
+                start_height,
+                length,
+            } => {
+                let start = *start_height as u64;
+                let end = start + *length as u64;
+                PeerMessage::BlockHeaderRequest(BlockHeight::from(start)..BlockHeight::from(end))
+            }
+            FuzzMessage::BlockHeadersResponse(blocks) => PeerMessage::BlockHeadersResponse(
+                bounded(blocks)
+                    .map(|block| {
+                        (
+                            block.to_header(genesis_block),
+                            genesis_block.kernel.body.mast_hash(),
+                        )
+                    })
+                    .collect(),
+            ),
+            FuzzMessage::Ping(nonce) => PeerMessage::Ping(*nonce),
+            FuzzMessage::Pong(nonce) => PeerMessage::Pong(*nonce),
+            FuzzMessage::BlockRequestThrottled {
+                retry_after_in_secs,
+                max_blocks,
+                max_bytes,
+                window_in_secs,
+            } => PeerMessage::BlockRequestThrottled {
+                retry_after_in_secs: *retry_after_in_secs,
+                policy: BlockServingPolicy {
+                    max_blocks: *max_blocks,
+                    max_bytes: *max_bytes,
+                    window: Duration::from_secs(*window_in_secs),
+                },
+            },
+            FuzzMessage::TransactionNotificationWithFee { transaction, fee } => {
+                PeerMessage::TransactionNotificationWithFee(TransactionNotificationWithFee {
+                    transaction_digest: transaction.to_digest(genesis_block),
+                    fee: NeptuneCoins::new(*fee),
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("fuzzed peer connection failed")]
+pub struct FuzzPeerError;
+
+/// The connection to a fuzzed peer. Records the messages sent to the peer,
+/// and never yields any, since all messages from the peer are fed to the
+/// handler directly.
+#[derive(Debug, Default)]
+pub struct FuzzPeer {
+    pub sent: Vec<PeerMessage>,
+}
+
+impl Sink<PeerMessage> for FuzzPeer {
+    type Error = FuzzPeerError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: PeerMessage) -> Result<(), Self::Error> {
+        self.get_mut().sent.push(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Stream for FuzzPeer {
+    type Item = Result<PeerMessage, FuzzPeerError>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(None)
+    }
+}
+
+/// A node at genesis, connected to a single fuzzed peer.
+pub struct PeerMessageHarness {
+    global_state_lock: GlobalStateLock,
+    handler: PeerLoopHandler,
+    peer: FuzzPeer,
+    peer_state: MutablePeerState,
+    peer_address: SocketAddr,
+    to_main_rx: mpsc::Receiver<PeerThreadToMain>,
+    genesis_block: Block,
+    data_dir_root: PathBuf,
+    disconnected: bool,
+}
+
+impl PeerMessageHarness {
+    pub async fn new() -> Result<Self> {
+        let data_dir_root = env::temp_dir()
+            .join("neptune-fuzzing")
+            .join(Alphanumeric.sample_string(&mut rand::thread_rng(), 16));
+        let data_dir = DataDirectory::get(Some(data_dir_root.clone()), FUZZ_NETWORK)?;
+        let cli = cli_args::Args {
+            network: FUZZ_NETWORK,
+            ..Default::default()
+        };
+
+        let block_index_db = ArchivalState::initialize_block_index_database(&data_dir).await?;
+        let peer_databases = NetworkingState::initialize_peer_databases(&data_dir).await?;
+        let archival_mutator_set = ArchivalState::initialize_mutator_set(&data_dir).await?;
+        let archival_state = ArchivalState::new(
+            data_dir.clone(),
+            block_index_db,
+            archival_mutator_set,
+            FUZZ_NETWORK,
+            DEFAULT_BLOCK_CACHE_CAPACITY,
+        )
+        .await;
+        let genesis_block = archival_state.get_tip().await;
+
+        let peer_address = SocketAddr::from(([127, 0, 0, 1], 8080));
+        let peer_handshake = HandshakeData {
+            tip_header: genesis_block.kernel.header.clone(),
+            listen_port: Some(peer_address.port()),
+            network: FUZZ_NETWORK,
+            instance_id: 1,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_SUPPORTED_PROTOCOL_VERSION,
+            features: PeerFeatures::ARCHIVAL,
+        };
+        let peer_info = PeerInfo {
+            port_for_incoming_connections: peer_handshake.listen_port,
+            connected_address: peer_address,
+            instance_id: peer_handshake.instance_id,
+            inbound: false,
+            last_seen: SystemTime::now(),
+            standing: PeerStanding::default(),
+            version: peer_handshake.version.clone(),
+            protocol_version: peer_handshake.protocol_version,
+            is_archival_node: peer_handshake.features.is_archival_node(),
+            statistics: PeerStatistics::default(),
+        };
+        let peer_map = HashMap::from([(peer_address, peer_info)]);
+
+        let blockchain_state = BlockchainState::Archival(BlockchainArchivalState {
+            light_state: LightState::from(genesis_block.clone()),
+            archival_state,
+        });
+        let networking_state = NetworkingState::new(peer_map, peer_databases, false);
+        let wallet_state =
+            WalletState::new_from_wallet_secret(&data_dir, WalletSecret::devnet_wallet(), &cli)
+                .await;
+        let global_state_lock = GlobalStateLock::new(
+            wallet_state,
+            blockchain_state,
+            networking_state,
+            cli,
+            Mempool::new(ByteSize::mb(100)),
+            false,
+        );
+
+        // Drained after every message, so the handler never waits for the
+        // main loop.
+        let (to_main_tx, to_main_rx) = mpsc::channel(1024);
+        let handler = PeerLoopHandler::new(
+            to_main_tx,
+            global_state_lock.clone(),
+            peer_address,
+            peer_handshake,
+            false,
+            1,
+        );
+
+        Ok(Self {
+            global_state_lock,
+            handler,
+            peer: FuzzPeer::default(),
+            peer_state: MutablePeerState::new(genesis_block.kernel.header.height),
+            peer_address,
+            to_main_rx,
+            genesis_block,
+            data_dir_root,
+            disconnected: false,
+        })
+    }
+
+    /// The messages sent to the peer so far
+    pub fn sent_messages(&self) -> &[PeerMessage] {
+        &self.peer.sent
+    }
+
+    /// Whether the handler closed the connection or failed, after which
+    /// further messages are ignored
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    pub async fn peer_standing(&self) -> Option<PeerStanding> {
+        self.global_state_lock
+            .lock(|s| s.net.peer_map.get(&self.peer_address).map(|p| p.standing))
+            .await
+    }
+
+    /// Feed a message to the handler, and fail if the node violates the
+    /// oracle while handling it.
+    pub async fn feed(&mut self, message: &FuzzMessage) -> Result<()> {
+        if self.disconnected {
+            return Ok(());
+        }
+
+        let peer_message = message.to_peer_message(&self.genesis_block);
+        let peer_count_before = self.global_state_lock.lock(|s| s.net.peer_map.len()).await;
+        let Some(standing_before) = self.peer_standing().await else {
+            bail!("Peer is unknown before handling {message:?}");
+        };
+
+        let handling = self.handler.handle_received_message(
+            peer_message,
+            &mut self.peer,
+            &mut self.peer_state,
+        );
+        let Ok(result) = tokio::time::timeout(MESSAGE_HANDLING_TIMEOUT, handling).await else {
+            bail!("Handling {message:?} did not finish within {MESSAGE_HANDLING_TIMEOUT:?}");
+        };
+        self.disconnected = !matches!(result, Ok(false));
+        while self.to_main_rx.try_recv().is_ok() {}
+
+        let peer_count_after = self.global_state_lock.lock(|s| s.net.peer_map.len()).await;
+        if peer_count_before != peer_count_after {
+            bail!("Handling {message:?} changed the number of peers from {peer_count_before} to {peer_count_after}");
+        }
+        let Some(standing_after) = self.peer_standing().await else {
+            bail!("Handling {message:?} removed the peer");
+        };
+        if standing_after.standing > standing_before.standing {
+            bail!(
+                "Handling {message:?} improved the standing of the peer from {} to {}",
+                standing_before.standing,
+                standing_after.standing
+            );
+        }
+        if standing_after.standing != standing_before.standing
+            && standing_after.latest_sanction.is_none()
+        {
+            bail!("Handling {message:?} changed the standing of the peer without a sanction");
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PeerMessageHarness {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.data_dir_root);
+    }
+}
+
+/// Decode a sequence of messages from fuzzed input and feed them to a fresh
+/// node, panicking if the node violates the oracle. Entry point of the
+/// cargo-fuzz target.
+pub fn fuzz_peer_messages(data: &[u8]) {
+    let mut unstructured = Unstructured::new(data);
+    let Ok(messages) = Vec::<FuzzMessage>::arbitrary(&mut unstructured) else {
+        return;
+    };
+    if messages.is_empty() {
+        return;
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut harness = PeerMessageHarness::new().await.unwrap();
+        for message in messages.iter().take(MAX_FUZZ_MESSAGES) {
+            harness.feed(message).await.unwrap();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fuzz_block(height: u8) -> FuzzBlock {
+        FuzzBlock {
+            height,
+            builds_on_genesis: true,
+            timestamp_offset_secs: 60,
+            added_proof_of_work: 1,
+            nonce: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn regression_seed_sequences_test() {
+        let seeds = [
+            vec![
+                FuzzMessage::Handshake,
+                FuzzMessage::BlockResponseBatch(vec![fuzz_block(1), fuzz_block(2)]),
+                FuzzMessage::BlockNotification(fuzz_block(0)),
+            ],
+            vec![
+                FuzzMessage::BlockHeadersResponse(vec![fuzz_block(1)]),
+                FuzzMessage::MempoolInventoryResponse(vec![(FuzzDigest::Genesis, 1, 1000)]),
+                FuzzMessage::BlockTransactions(2),
+                FuzzMessage::Transaction { fee: 1 },
+            ],
+            vec![
+                FuzzMessage::BlockRequestBatchByHeight {
+                    start_height: 0,
+                    count: u16::MAX,
+                },
+                FuzzMessage::BlockHeaderRequest {
+                    start_height: 200,
+                    length: 0,
+                },
+                FuzzMessage::PeerListResponse(vec![(0, 0, 0); 20]),
+                FuzzMessage::Bye,
+                FuzzMessage::PeerListRequest,
+            ],
+        ];
+
+        for seed in seeds {
+            let mut harness = PeerMessageHarness::new().await.unwrap();
+            for message in seed.iter() {
+                harness.feed(message).await.unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn arbitrary_input_is_bounded_test() {
+        let data = vec![0xffu8; 4096];
+        let messages = Vec::<FuzzMessage>::arbitrary(&mut Unstructured::new(&data)).unwrap();
+        let genesis_block = Block::genesis_block(FUZZ_NETWORK);
+        for message in messages.iter() {
+            let peer_message = message.to_peer_message(&genesis_block);
+            if let PeerMessage::BlockResponseBatch(blocks) = peer_message {
+                assert!(blocks.len() <= MAX_FUZZ_COLLECTION_LENGTH);
+            }
+        }
+    }
+}
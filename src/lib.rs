@@ -7,14 +7,20 @@
 pub mod config_models;
 pub mod connect_to_peers;
 pub mod database;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
 pub mod locks;
 pub mod macros;
 pub mod main_loop;
 pub mod mine_loop;
 pub mod models;
+pub mod peer_codec;
 pub mod peer_loop;
+pub mod peer_traffic_capture;
+pub mod peer_traffic_counter;
 pub mod prelude;
 pub mod rpc_server;
+pub mod socks5;
 pub mod util_types;
 
 // needed by TasmObject derive macro
@@ -27,7 +33,9 @@ pub mod tests;
 use crate::config_models::data_directory::DataDirectory;
 use crate::connect_to_peers::call_peer_wrapper;
 use crate::main_loop::MainLoopHandler;
+use crate::models::blockchain::block::checkpoint::CheckpointSnapshot;
 use crate::models::channel::RPCServerToMain;
+use crate::models::consensus::timestamp::Timestamp;
 
 use crate::models::state::archival_state::ArchivalState;
 use crate::models::state::blockchain_state::{BlockchainArchivalState, BlockchainState};
@@ -38,7 +46,7 @@ use crate::models::state::wallet::wallet_state::WalletState;
 use crate::models::state::wallet::WalletSecret;
 use crate::models::state::GlobalStateLock;
 use crate::rpc_server::RPC;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use config_models::cli_args;
 
 use crate::locks::tokio as sync_tokio;
@@ -54,6 +62,7 @@ use models::peer::PeerInfo;
 use std::collections::HashMap;
 use std::env;
 use std::net::SocketAddr;
+use std::time::SystemTime;
 use tarpc::server;
 use tarpc::server::incoming::Incoming;
 use tarpc::server::Channel;
@@ -61,7 +70,7 @@ use tarpc::tokio_serde::formats::*;
 use tokio::net::TcpListener;
 use tokio::sync::{broadcast, mpsc, watch};
 use tokio::time::Instant;
-use tracing::{info, trace};
+use tracing::{info, trace, warn};
 
 use crate::models::channel::{MainToMiner, MainToPeerThread, MinerToMain, PeerThreadToMain};
 use crate::models::peer::HandshakeData;
@@ -100,13 +109,42 @@ pub async fn initialize(cli_args: cli_args::Args) -> Result<()> {
     let archival_mutator_set = ArchivalState::initialize_mutator_set(&data_dir).await?;
     info!("Got archival mutator set");
 
-    let archival_state = ArchivalState::new(
+    let mempool_file_path = data_dir.mempool_file_path();
+    let mut archival_state = ArchivalState::new(
         data_dir,
         block_index_db,
         archival_mutator_set,
         cli_args.network,
+        cli_args.block_cache_size,
     )
     .await;
+    archival_state
+        .finish_interrupted_block_file_rewrite()
+        .await?;
+    archival_state.complete_hex_prefix_index().await;
+
+    // Start from a trusted checkpoint if there are no blocks yet, and refuse
+    // to run on a chain that disagrees with it otherwise
+    if let Some(checkpoint_path) = cli_args.checkpoint.as_ref() {
+        let Some(trusted_hash) = cli_args.trusted_checkpoint_hash() else {
+            bail!(
+                "No checkpoint is known for network {}, see --checkpoint-hash",
+                cli_args.network
+            );
+        };
+        let snapshot = CheckpointSnapshot::load(checkpoint_path, trusted_hash).await?;
+        let checkpoint = snapshot.checkpoint.clone();
+        let genesis_digest = archival_state.genesis_block().hash();
+        if archival_state.get_tip().await.hash() == genesis_digest
+            && checkpoint.block_digest != genesis_digest
+        {
+            archival_state.bootstrap_from_checkpoint(snapshot).await?;
+            info!("Started from checkpoint at height {}", checkpoint.height);
+        } else {
+            archival_state.check_checkpoint(&checkpoint).await?;
+            info!("Loaded checkpoint at height {}", checkpoint.height);
+        }
+    }
 
     // Get latest block. Use hardcoded genesis block if nothing is in database.
     let latest_block: Block = archival_state.get_tip().await;
@@ -138,7 +176,19 @@ pub async fn initialize(cli_args: cli_args::Args) -> Result<()> {
         archival_state,
     };
     let blockchain_state = BlockchainState::Archival(blockchain_archival_state);
-    let mempool = Mempool::new(cli_args.max_mempool_size);
+    let mempool_transaction_ttl = Timestamp::hours(cli_args.mempool_transaction_ttl);
+    let mempool = Mempool::read_from_file(
+        cli_args.max_mempool_size,
+        mempool_transaction_ttl,
+        &mempool_file_path,
+        &latest_block.kernel.body.mutator_set_accumulator,
+    )
+    .await
+    .unwrap_or_else(|err| {
+        warn!("Starting with an empty mempool: {err}");
+        Mempool::new(cli_args.max_mempool_size).with_transaction_ttl(mempool_transaction_ttl)
+    })
+    .with_replace_by_fee_margin(cli_args.replace_by_fee_margin);
     let global_state_lock = GlobalStateLock::new(
         wallet_state,
         blockchain_state,
@@ -166,9 +216,41 @@ pub async fn initialize(cli_args: cli_args::Args) -> Result<()> {
         .await?;
     info!("UTXO restoration check complete");
 
-    // Connect to peers, and provide each peer thread with a thread-safe copy of the state
+    // Catch up a wallet that was imported into, or restored in, a data
+    // directory that already holds blocks
+    match global_state_lock.rescan_wallet_if_behind().await {
+        Ok(0) => (),
+        Ok(block_count) => info!("Rescanned {block_count} blocks for the wallet"),
+        Err(err) => warn!("Failed to rescan the blocks the wallet has not seen: {err}"),
+    }
+
+    // Forget peers that were not seen for a long time
+    global_state_lock
+        .lock_guard_mut()
+        .await
+        .garbage_collect_peer_databases(SystemTime::now())
+        .await;
+
+    // Connect to peers, and provide each peer thread with a thread-safe copy of the state.
+    // Besides the peers given via the CLI, reconnect to the peers of previous runs.
+    let cli_peers = global_state_lock.cli().peer_addresses();
+    let bootstrap_peers = global_state_lock
+        .lock_guard()
+        .await
+        .get_bootstrap_peers(
+            (global_state_lock.cli().max_outbound_peers as usize).saturating_sub(cli_peers.len()),
+        )
+        .await;
+    let bootstrap_peers = bootstrap_peers
+        .into_iter()
+        .filter(|peer_address| !cli_peers.contains(peer_address))
+        .collect::<Vec<_>>();
+    info!(
+        "Reconnecting to {} peers from previous runs",
+        bootstrap_peers.len()
+    );
     let mut thread_join_handles = vec![];
-    for peer_address in global_state_lock.cli().peers.clone() {
+    for peer_address in cli_peers.into_iter().chain(bootstrap_peers) {
         let peer_state_var = global_state_lock.clone(); // bump arc refcount
         let main_to_peer_broadcast_rx_clone: broadcast::Receiver<MainToPeerThread> =
             main_to_peer_broadcast_tx.subscribe();
@@ -196,7 +278,7 @@ pub async fn initialize(cli_args: cli_args::Args) -> Result<()> {
     let (miner_to_main_tx, miner_to_main_rx) = mpsc::channel::<MinerToMain>(MINER_CHANNEL_CAPACITY);
     let (main_to_miner_tx, main_to_miner_rx) = watch::channel::<MainToMiner>(MainToMiner::Empty);
     let miner_state_lock = global_state_lock.clone(); // bump arc refcount.
-    if global_state_lock.cli().mine {
+    if global_state_lock.cli().spawns_miner() {
         let miner_join_handle = tokio::task::Builder::new()
             .name("miner")
             .spawn(async move {
@@ -211,6 +293,8 @@ pub async fn initialize(cli_args: cli_args::Args) -> Result<()> {
             })?;
         thread_join_handles.push(miner_join_handle);
         info!("Started mining thread");
+    } else if global_state_lock.cli().disable_mining {
+        info!("Mining is disabled");
     }
 
     // Start RPC server for CLI request and more. It's important that this is done as late
@@ -4,17 +4,21 @@ use crate::connect_to_peers::{answer_peer_wrapper, call_peer_wrapper};
 
 use crate::models::blockchain::block::block_header::{BlockHeader, PROOF_OF_WORK_COUNT_U32_SIZE};
 use crate::models::blockchain::block::block_height::BlockHeight;
+use crate::models::consensus::timestamp::Timestamp;
 
 use crate::models::peer::{
-    HandshakeData, PeerInfo, PeerSynchronizationState, TransactionNotification,
+    HandshakeData, PeerInfo, PeerSynchronizationState, TransactionNotificationWithFee,
 };
+use crate::models::state::chain_split_detector::NetworkEvent;
+use crate::models::state::mempool::MempoolEvent;
 
+use crate::models::state::networking_state::NetworkingState;
 use crate::models::state::GlobalStateLock;
 use anyhow::Result;
 use itertools::Itertools;
 use rand::prelude::{IteratorRandom, SliceRandom};
 use rand::thread_rng;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
 use std::thread::sleep;
 use std::time::{Duration, SystemTime};
@@ -32,12 +36,24 @@ use crate::models::channel::{
 const PEER_DISCOVERY_INTERVAL_IN_SECONDS: u64 = 120;
 const SYNC_REQUEST_INTERVAL_IN_SECONDS: u64 = 3;
 const MEMPOOL_PRUNE_INTERVAL_IN_SECS: u64 = 30 * 60; // 30mins
+const MEMPOOL_PERSIST_INTERVAL_IN_SECS: u64 = 10 * 60; // 10mins
 const MP_RESYNC_INTERVAL_IN_SECS: u64 = 59;
 const UTXO_NOTIFICATION_POOL_PRUNE_INTERVAL_IN_SECS: u64 = 19 * 60; // 19 mins
+const WITNESS_PRUNE_INTERVAL_IN_SECS: u64 = 60 * 60; // 1 hour
+const CHAIN_SPLIT_CHECK_INTERVAL_IN_SECS: u64 = 60;
+const STORAGE_SCRUB_INTERVAL_IN_SECS: u64 = 5 * 60; // 5 mins
+const PEER_COUNT_CHECK_INTERVAL_IN_SECS: u64 = 10;
+const SWBF_SATURATION_CHECK_INTERVAL_IN_SECS: u64 = 60 * 60; // 1 hour
+const TRANSACTION_REQUEST_CHECK_INTERVAL_IN_SECS: u64 = 10;
 
 const SANCTION_PEER_TIMEOUT_FACTOR: u64 = 40;
 const POTENTIAL_PEER_MAX_COUNT_AS_A_FACTOR_OF_MAX_PEERS: usize = 20;
 const STANDARD_BATCH_BLOCK_LOOKBEHIND_SIZE: usize = 100;
+const STANDARD_BLOCK_HEADER_BATCH_SIZE: u64 = 1000;
+
+/// Distance assigned to peers dialed from the address book. Their addresses
+/// were reported by other peers, or learned in an earlier session.
+const ADDRESS_BOOK_PEER_DISTANCE: u8 = 2;
 
 /// MainLoop is the immutable part of the input for the main loop function
 pub struct MainLoopHandler {
@@ -66,11 +82,21 @@ impl MainLoopHandler {
     }
 }
 
+/// A message that was received after the node was quiesced, and is handled
+/// once it resumes
+enum DeferredMessage {
+    PeerThread(PeerThreadToMain),
+    Miner(MinerToMain),
+    Mempool(MempoolEvent),
+    Network(NetworkEvent),
+}
+
 /// The mutable part of the main loop function
 struct MutableMainLoopState {
     sync_state: SyncState,
     potential_peers: PotentialPeersState,
     thread_handles: Vec<JoinHandle<()>>,
+    deferred_messages: VecDeque<DeferredMessage>,
 }
 
 impl MutableMainLoopState {
@@ -79,6 +105,7 @@ impl MutableMainLoopState {
             sync_state: SyncState::default(),
             potential_peers: PotentialPeersState::default(),
             thread_handles,
+            deferred_messages: VecDeque::new(),
         }
     }
 }
@@ -87,6 +114,7 @@ impl MutableMainLoopState {
 struct SyncState {
     peer_sync_states: HashMap<SocketAddr, PeerSynchronizationState>,
     last_sync_request: Option<(SystemTime, BlockHeight, SocketAddr)>,
+    last_header_request: Option<(SystemTime, U32s<PROOF_OF_WORK_COUNT_U32_SIZE>, SocketAddr)>,
 }
 
 impl SyncState {
@@ -94,6 +122,47 @@ impl SyncState {
         Self {
             peer_sync_states: HashMap::new(),
             last_sync_request: None,
+            last_header_request: None,
+        }
+    }
+
+    fn record_header_request(
+        &mut self,
+        best_header_pow_family: U32s<PROOF_OF_WORK_COUNT_U32_SIZE>,
+        peer: SocketAddr,
+    ) {
+        self.last_header_request = Some((SystemTime::now(), best_header_pow_family, peer));
+    }
+
+    /// Determine if a peer should be sanctioned for failing to back its claimed
+    /// proof-of-work with headers, i.e., if the best known header did not
+    /// improve within the timeout after requesting headers from it. Also
+    /// determine if a new header request should be made.
+    fn get_status_of_last_header_request(
+        &self,
+        best_header_pow_family: U32s<PROOF_OF_WORK_COUNT_U32_SIZE>,
+        now: SystemTime,
+    ) -> (Option<SocketAddr>, bool) {
+        match self.last_header_request {
+            None => (None, true),
+            Some((_req_time, requested_pow_family, _peer_sa))
+                if best_header_pow_family > requested_pow_family =>
+            {
+                // The last header request improved the best known header
+                (None, true)
+            }
+            Some((req_time, _requested_pow_family, peer_sa)) => {
+                if req_time
+                    + Duration::from_secs(
+                        SANCTION_PEER_TIMEOUT_FACTOR * SYNC_REQUEST_INTERVAL_IN_SECONDS,
+                    )
+                    < now
+                {
+                    (Some(peer_sa), true)
+                } else {
+                    (None, false)
+                }
+            }
         }
     }
 
@@ -101,6 +170,11 @@ impl SyncState {
         self.last_sync_request = Some((SystemTime::now(), requested_block_height, peer));
     }
 
+    /// The peer that the last batch of blocks was requested from, if any
+    fn last_request_peer(&self) -> Option<SocketAddr> {
+        self.last_sync_request.map(|(_time, _height, peer)| peer)
+    }
+
     /// Return a list of peers that have reported to be in possession of blocks with a PoW family
     /// above a threshold.
     fn get_potential_peers_for_sync_request(
@@ -221,13 +295,16 @@ impl PotentialPeersState {
             .insert(potential_peer_socket_address, insert_value);
     }
 
-    /// Return a random peer from the potential peer list that we aren't connected to
-    /// and that isn't our own address. Returns (socket address, peer distance)
+    /// Return a random peer from the potential peer list that we aren't connected to,
+    /// that isn't our own address, and whose redial backoff has expired. Returns
+    /// (socket address, peer distance)
     fn get_distant_candidate(
         &self,
         connected_clients: &[PeerInfo],
         own_instance_id: u128,
+        net: &NetworkingState,
     ) -> Option<(SocketAddr, u8)> {
+        let now = SystemTime::now();
         let peers_instance_ids: Vec<u128> =
             connected_clients.iter().map(|x| x.instance_id).collect();
 
@@ -245,6 +322,10 @@ impl PotentialPeersState {
             // meaning this will allow multiple nodes e.g. runnig on the same computer to form
             // a complete graph.
             .filter(|pp| pp.1.instance_id != own_instance_id)
+            // Prevent connecting to addresses that are known to lead back to self
+            .filter(|pp| !net.do_not_dial.contains(pp.0))
+            // Wait before redialing peers that recently said bye or could not be reached
+            .filter(|pp| net.may_dial(*pp.0, now))
             // Prevent connecting to peer we already are connected to
             .filter(|potential_peer| !peers_instance_ids.contains(&potential_peer.1.instance_id))
             .filter(|potential_peer| !peers_listen_addresses.contains(potential_peer.0))
@@ -297,6 +378,76 @@ fn stay_in_sync_mode(
 }
 
 impl MainLoopHandler {
+    async fn is_quiesced(&self) -> bool {
+        self.global_state_lock.lock(|s| s.net.quiesced).await
+    }
+
+    /// Handle the messages that were received while the node was quiesced, in
+    /// the order they were received, until the node is quiesced again.
+    async fn handle_deferred_messages(
+        &self,
+        main_loop_state: &mut MutableMainLoopState,
+    ) -> Result<()> {
+        while !main_loop_state.deferred_messages.is_empty() && !self.is_quiesced().await {
+            let Some(message) = main_loop_state.deferred_messages.pop_front() else {
+                break;
+            };
+            match message {
+                DeferredMessage::PeerThread(msg) => {
+                    self.handle_peer_thread_message(msg, main_loop_state)
+                        .await?
+                }
+                DeferredMessage::Miner(msg) => self.handle_miner_thread_message(msg).await?,
+                DeferredMessage::Mempool(event) => self.handle_mempool_event(event).await?,
+                DeferredMessage::Network(event) => self.handle_network_event(event)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ask the peers that diverge from the canonical chain for their tips when
+    /// a chain split is suspected, such that this node catches up if it is the
+    /// one that is stale.
+    fn handle_network_event(&self, event: NetworkEvent) -> Result<()> {
+        match event {
+            NetworkEvent::ChainSplitSuspected(alert) => {
+                for diverging_peer in alert.diverging_peers.iter() {
+                    self.main_to_peer_broadcast_tx.send(
+                        MainToPeerThread::RequestBlockNotification(diverging_peer.peer_address),
+                    )?;
+                }
+            }
+            NetworkEvent::ChainSplitResolved => (),
+        }
+
+        Ok(())
+    }
+
+    /// Announce orphans that entered the mempool, and let the wallet react to
+    /// its transactions leaving it.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn handle_mempool_event(&self, event: MempoolEvent) -> Result<()> {
+        let global_state = self.global_state_lock.lock_guard().await;
+        if let MempoolEvent::Promoted { transaction_id } = event {
+            // The transaction may have left the mempool again in the meantime
+            if let Some(transaction) = global_state.mempool.get(transaction_id) {
+                let transaction_notification: TransactionNotificationWithFee =
+                    transaction.clone().into();
+                self.main_to_peer_broadcast_tx
+                    .send(MainToPeerThread::TransactionNotification(
+                        transaction_notification,
+                    ))?;
+            }
+            return Ok(());
+        }
+
+        global_state.wallet_state.handle_mempool_event(&event).await;
+        Ok(())
+    }
+
     /// Locking:
     ///   * acquires `global_state_lock` for write
     async fn handle_miner_thread_message(&self, msg: MinerToMain) -> Result<()> {
@@ -330,6 +481,12 @@ impl MainLoopHandler {
                     && new_block.kernel.header.prev_block_digest == tip_hash;
                 if !block_is_new {
                     warn!("Got new block from miner thread that was not child of tip. Discarding.");
+
+                    // The miner waits for an answer, so tell it to mine on the tip
+                    let tip = global_state_mut.chain.light_state().clone();
+                    drop(global_state_mut);
+                    self.main_to_miner_tx
+                        .send(MainToMiner::NewBlock(Box::new(tip)))?;
                     return Ok(());
                 }
 
@@ -339,6 +496,13 @@ impl MainLoopHandler {
                         new_block_info.coinbase_utxo_info.as_ref().clone(),
                     )
                     .await?;
+
+                // Keep the merged transactions around, such that the block can
+                // be announced to peers as a compact block.
+                global_state_mut.net.record_mined_block_transactions(
+                    new_block.hash(),
+                    *new_block_info.block_transactions,
+                );
                 drop(global_state_mut);
 
                 // Inform miner that mempool has been updated and that it is safe
@@ -406,11 +570,19 @@ impl MainLoopHandler {
                         if !stay_in_sync_mode {
                             info!("Exiting sync mode");
                             global_state_mut.net.syncing = false;
+                            global_state_mut.net.header_chain.clear();
                             self.main_to_miner_tx.send(MainToMiner::StopSyncing)?;
                         }
                     }
 
+                    // If the new blocks belong to another branch, roll back the wallet to
+                    // that branch before applying them.
+                    global_state_mut
+                        .switch_wallet_to_branch_of(&blocks[0])
+                        .await?;
+
                     for new_block in blocks {
+                        global_state_mut.net.header_chain.remove(new_block.hash());
                         debug!(
                             "Storing block {} in database. Height: {}, Mined: {}",
                             new_block.hash(),
@@ -432,6 +604,11 @@ impl MainLoopHandler {
                 self.main_to_peer_broadcast_tx
                     .send(MainToPeerThread::Block(Box::new(last_block)))
                     .expect("Peer handler broadcast was closed. This should never happen");
+
+                // When syncing, request the next batch right away instead of at
+                // the next tick of the sync timer, such that the download is
+                // paced by the peers rather than by the timer.
+                self.block_sync(main_loop_state).await?;
             }
             PeerThreadToMain::AddPeerMaxBlockHeight((
                 socket_addr,
@@ -462,6 +639,15 @@ impl MainLoopHandler {
                     global_state_mut.net.syncing = true;
                     self.main_to_miner_tx.send(MainToMiner::StartSyncing)?;
                 }
+
+                // Fetch the peer's mempool, unless its transactions cannot be
+                // validated yet because we are behind.
+                if !global_state_mut.net.syncing
+                    && global_state_mut.cli().max_mempool_sync_transactions > 0
+                {
+                    self.main_to_peer_broadcast_tx
+                        .send(MainToPeerThread::RequestMempoolInventory(socket_addr))?;
+                }
             }
             PeerThreadToMain::RemovePeerMaxBlockHeight(socket_addr) => {
                 debug!(
@@ -485,11 +671,26 @@ impl MainLoopHandler {
                     if !stay_in_sync_mode {
                         info!("Exiting sync mode");
                         global_state_mut.net.syncing = false;
+                        global_state_mut.net.header_chain.clear();
                     }
                 }
             }
             PeerThreadToMain::PeerDiscoveryAnswer((pot_peers, reported_by, distance)) => {
-                let max_peers = self.global_state_lock.cli().max_peers;
+                // Persist the addresses, such that they can be dialed after a restart
+                let mut global_state_mut = self.global_state_lock.lock_guard_mut().await;
+                let own_instance_id = global_state_mut.net.instance_id;
+                let addresses = pot_peers
+                    .iter()
+                    .filter(|(_address, instance_id)| *instance_id != own_instance_id)
+                    .map(|(address, _instance_id)| *address)
+                    .collect_vec();
+                global_state_mut
+                    .net
+                    .add_to_address_book(addresses, SystemTime::now())
+                    .await;
+                drop(global_state_mut);
+
+                let max_peers = self.global_state_lock.cli().max_outbound_peers;
                 for pot_peer in pot_peers {
                     main_loop_state.potential_peers.add(
                         reported_by,
@@ -507,27 +708,50 @@ impl MainLoopHandler {
                     pt2m_transaction.transaction.kernel.mutator_set_hash
                 );
 
-                let mut global_state_mut = self.global_state_lock.lock_guard_mut().await;
-                if pt2m_transaction.confirmable_for_block
-                    != global_state_mut.chain.light_state().hash()
-                {
-                    warn!("main loop got unmined transaction with bad mutator set data, discarding transaction");
+                // Insert into mempool
+                let is_new = self
+                    .global_state_lock
+                    .lock_guard_mut()
+                    .await
+                    .insert_relayed_transaction(&pt2m_transaction);
+                if !is_new {
                     return Ok(());
                 }
 
-                // Insert into mempool
-                global_state_mut
-                    .mempool
-                    .insert(&pt2m_transaction.transaction);
-
                 // send notification to peers
-                let transaction_notification: TransactionNotification =
+                let transaction_notification: TransactionNotificationWithFee =
                     pt2m_transaction.transaction.into();
                 self.main_to_peer_broadcast_tx
                     .send(MainToPeerThread::TransactionNotification(
                         transaction_notification,
                     ))?;
             }
+            PeerThreadToMain::OrphanTransaction(transaction) => {
+                debug!(
+                    "`peer_loop` received orphan transaction synced to mutator set hash: {}",
+                    transaction.kernel.mutator_set_hash
+                );
+
+                let mut global_state_mut = self.global_state_lock.lock_guard_mut().await;
+                global_state_mut
+                    .mempool
+                    .insert_orphan(*transaction, Timestamp::now());
+
+                // The block that the transaction was built against may have arrived in the
+                // meantime, in which case the transaction can enter the mempool right away.
+                let tip_mutator_set_accumulator = global_state_mut
+                    .chain
+                    .light_state()
+                    .kernel
+                    .body
+                    .mutator_set_accumulator
+                    .clone();
+                // Promoted orphans are announced when their mempool event is
+                // handled.
+                global_state_mut
+                    .mempool
+                    .promote_orphans(&tip_mutator_set_accumulator);
+            }
         }
 
         Ok(())
@@ -546,22 +770,30 @@ impl MainLoopHandler {
 
         let connected_peers: Vec<PeerInfo> = global_state.net.peer_map.values().cloned().collect();
 
-        // Check if we are connected to too many peers
-        if connected_peers.len() > global_state.cli().max_peers as usize {
+        // Check if we are connected to too many peers in either direction
+        let exceeds_max_peers = |inbound: bool| {
+            global_state.net.peer_count(inbound) > global_state.cli().max_peers(inbound)
+        };
+        if exceeds_max_peers(true) || exceeds_max_peers(false) {
             // This would indicate a race-condition on the peer map field in the state which
             // we unfortunately cannot exclude. So we just disconnect from a peer that the user
             // didn't request a connection to.
             warn!(
-                "Max peer parameter is exceeded. max is {} but we are connected to {}. Attempting to fix.",
-                connected_peers.len(),
-                global_state.cli().max_peers
+                "Max peer parameters are exceeded. Max is {} inbound and {} outbound but we are connected to {} inbound and {} outbound. Attempting to fix.",
+                global_state.cli().max_inbound_peers,
+                global_state.cli().max_outbound_peers,
+                global_state.net.peer_count(true),
+                global_state.net.peer_count(false),
             );
             let mut rng = thread_rng();
 
-            // pick a peer that was not specified in the CLI arguments to disconnect from
+            // pick a peer in an exceeded direction that was not specified in the CLI arguments
+            // to disconnect from
+            let cli_peer_addresses = global_state.cli().peer_addresses();
             let peer_to_disconnect = connected_peers
                 .iter()
-                .filter(|peer| !global_state.cli().peers.contains(&peer.connected_address))
+                .filter(|peer| exceeds_max_peers(peer.inbound))
+                .filter(|peer| !cli_peer_addresses.contains(&peer.connected_address))
                 .choose(&mut rng);
             match peer_to_disconnect {
                 Some(peer) => {
@@ -582,21 +814,28 @@ impl MainLoopHandler {
             .collect_vec();
         let peers_with_lost_connection = global_state
             .cli()
-            .peers
-            .iter()
+            .peer_addresses()
+            .into_iter()
             .filter(|peer| !connected_peer_addresses.contains(peer))
-            .cloned()
             .collect_vec();
         for peer_with_lost_connection in peers_with_lost_connection {
+            if !global_state
+                .net
+                .may_dial(peer_with_lost_connection, SystemTime::now())
+            {
+                debug!(
+                    "Not reconnecting to {peer_with_lost_connection} yet, backing off for {:?}",
+                    global_state.net.next_dial_delay(peer_with_lost_connection)
+                );
+                continue;
+            }
+
             // Disallow reconnection if peer is in bad standing
             let standing = global_state
-                .net
-                .get_peer_standing_from_database(peer_with_lost_connection.ip())
+                .peer_standing(peer_with_lost_connection.ip())
                 .await;
 
-            if standing.is_some()
-                && standing.unwrap().standing < -(global_state.cli().peer_tolerance as i32)
-            {
+            if standing.standing < -(global_state.cli().peer_tolerance as i32) {
                 info!("Not reconnecting to peer with lost connection because it was banned: {peer_with_lost_connection}");
             } else {
                 info!(
@@ -630,12 +869,9 @@ impl MainLoopHandler {
                 .retain(|th| !th.is_finished());
         }
 
-        // We don't make an outgoing connection if we've reached the peer limit, *or* if we are
-        // one below the peer limit as we reserve this last slot for an ingoing connection.
-        if connected_peers.len() == global_state.cli().max_peers as usize
-            || connected_peers.len() > 2
-                && connected_peers.len() - 1 == global_state.cli().max_peers as usize
-        {
+        // We don't make an outgoing connection if we've reached the outbound peer limit. Inbound
+        // connections have their own slots.
+        if global_state.net.peer_count(false) >= global_state.cli().max_peers(false) {
             return Ok(());
         }
 
@@ -652,13 +888,23 @@ impl MainLoopHandler {
         self.main_to_peer_broadcast_tx
             .send(MainToPeerThread::MakePeerDiscoveryRequest)?;
 
-        // 1)
-        let (peer_candidate, candidate_distance) = match main_loop_state
+        // 1) Prefer the peers reported by connected peers, and fall back to
+        // the address book. No network bucket may take more than its share of
+        // the outbound slots.
+        let reported_candidate = main_loop_state
             .potential_peers
-            .get_distant_candidate(&connected_peers, global_state.net.instance_id)
-        {
+            .get_distant_candidate(
+                &connected_peers,
+                global_state.net.instance_id,
+                &global_state.net,
+            )
+            .filter(|(candidate, _)| !global_state.net.outbound_bucket_is_full(*candidate));
+        let (peer_candidate, candidate_distance) = match reported_candidate {
             Some(candidate) => candidate,
-            None => return Ok(()),
+            None => match global_state.select_address_to_dial(&mut thread_rng()).await {
+                Some(address) => (address, ADDRESS_BOOK_PEER_DISTANCE),
+                None => return Ok(()),
+            },
         };
 
         // 2)
@@ -716,6 +962,58 @@ impl MainLoopHandler {
 
         info!("Running sync");
 
+        // Sync the headers first, such that only the bodies of the branch with
+        // the most proof-of-work are downloaded. Headers are requested until
+        // the best known header has caught up with what the peers claim.
+        let best_header = match global_state.net.header_chain.best_tip() {
+            Some((_digest, header)) => header.to_owned(),
+            None => global_state.chain.light_state().kernel.header.clone(),
+        };
+
+        // A peer that does not deliver headers with the proof-of-work it
+        // claims is sanctioned, and its claim is dropped, such that it cannot
+        // keep this node in the header phase.
+        let (overclaiming_peer, try_new_header_request) = main_loop_state
+            .sync_state
+            .get_status_of_last_header_request(best_header.proof_of_work_family, SystemTime::now());
+        if let Some(peer) = overclaiming_peer {
+            warn!("Peer {peer} did not back its claimed proof-of-work with headers");
+            main_loop_state.sync_state.peer_sync_states.remove(&peer);
+            main_loop_state.sync_state.last_header_request = None;
+            self.main_to_peer_broadcast_tx
+                .send(MainToPeerThread::PeerSynchronizationTimeout(peer))?;
+        }
+
+        let header_candidate_peers = main_loop_state
+            .sync_state
+            .get_potential_peers_for_sync_request(best_header.proof_of_work_family);
+        if !header_candidate_peers.is_empty() && !try_new_header_request {
+            info!("Waiting for last block header request to complete.");
+            return Ok(());
+        }
+        if let Some(chosen_peer) = header_candidate_peers.choose(&mut thread_rng()) {
+            // Overlap with the known blocks, such that the headers connect to
+            // them even if the peer is on another branch.
+            let best_height: u64 = best_header.height.into();
+            let start_height = best_height
+                .saturating_sub(STANDARD_BATCH_BLOCK_LOOKBEHIND_SIZE as u64)
+                .max(1);
+            let heights = BlockHeight::from(start_height)
+                ..BlockHeight::from(best_height + 1 + STANDARD_BLOCK_HEADER_BATCH_SIZE);
+            info!(
+                "Sending block header request to {chosen_peer} for heights {} to {}",
+                heights.start, heights.end
+            );
+            self.main_to_peer_broadcast_tx
+                .send(MainToPeerThread::RequestBlockHeaders(heights, *chosen_peer))
+                .expect("Sending message to peers must succeed");
+            main_loop_state
+                .sync_state
+                .record_header_request(best_header.proof_of_work_family, *chosen_peer);
+
+            return Ok(());
+        }
+
         // Check when latest batch of blocks was requested
         let (current_block_hash, current_block_height, current_block_proof_of_work_family) = (
             global_state.chain.light_state().hash(),
@@ -732,8 +1030,12 @@ impl MainLoopHandler {
             .sync_state
             .get_status_of_last_request(current_block_height);
 
-        // Sanction peer if they failed to respond
-        if let Some(peer) = peer_to_sanction {
+        // Sanction peer if they failed to respond, unless they asked this node
+        // to pause its block requests
+        let paused_peers = global_state
+            .net
+            .peers_pausing_block_requests(SystemTime::now());
+        if let Some(peer) = peer_to_sanction.filter(|peer| !paused_peers.contains(peer)) {
             self.main_to_peer_broadcast_tx
                 .send(MainToPeerThread::PeerSynchronizationTimeout(peer))?;
         }
@@ -746,16 +1048,30 @@ impl MainLoopHandler {
         // Create the next request from the reported
         info!("Creating new sync request");
 
-        // Pick a random peer that has reported to have relevant blocks
+        // Keep requesting from the peer that answered the last request, such
+        // that the download window slides along its chain. Otherwise, pick a
+        // random peer that has reported to have relevant blocks.
         let candidate_peers = main_loop_state
             .sync_state
             .get_potential_peers_for_sync_request(current_block_proof_of_work_family);
-        let mut rng = thread_rng();
-        let chosen_peer = candidate_peers.choose(&mut rng);
         assert!(
-            chosen_peer.is_some(),
+            !candidate_peers.is_empty(),
             "A synchronization candidate must be available for a request. Otherwise the data structure is in an invalid state and syncing should not be active"
         );
+        let candidate_peers = candidate_peers
+            .into_iter()
+            .filter(|peer| !paused_peers.contains(peer))
+            .collect_vec();
+        let mut rng = thread_rng();
+        let Some(chosen_peer) = main_loop_state
+            .sync_state
+            .last_request_peer()
+            .filter(|peer| peer_to_sanction != Some(*peer) && candidate_peers.contains(peer))
+            .or_else(|| candidate_peers.choose(&mut rng).copied())
+        else {
+            info!("All synchronization candidates paused our block requests. Waiting.");
+            return Ok(());
+        };
 
         // Find the blocks to request
         let tip_digest = current_block_hash;
@@ -766,11 +1082,22 @@ impl MainLoopHandler {
             .await;
 
         // List of digests, ordered after which block we would like to find descendents from,
-        // from highest to lowest.
-        let most_canonical_digests = [vec![tip_digest], most_canonical_digests].concat();
+        // from highest to lowest. If the header chain leads to more proof-of-work than
+        // the tip, descend from the last stored block on its best branch instead.
+        let best_branch_parent = global_state
+            .net
+            .header_chain
+            .best_chain()
+            .first()
+            .and_then(|digest| global_state.net.header_chain.get(*digest))
+            .filter(|_| best_header.proof_of_work_family > current_block_proof_of_work_family)
+            .map(|first_header| first_header.prev_block_digest);
+        let most_canonical_digests = match best_branch_parent {
+            Some(parent_digest) => vec![parent_digest],
+            None => [vec![tip_digest], most_canonical_digests].concat(),
+        };
 
         // Send message to the relevant peer loop to request the blocks
-        let chosen_peer = chosen_peer.unwrap();
         info!(
             "Sending block batch request to {}\nrequesting blocks descending from {}\n height {}",
             chosen_peer, current_block_hash, current_block_height
@@ -778,7 +1105,7 @@ impl MainLoopHandler {
         self.main_to_peer_broadcast_tx
             .send(MainToPeerThread::RequestBlockBatch(
                 most_canonical_digests,
-                *chosen_peer,
+                chosen_peer,
             ))
             .expect("Sending message to peers must succeed");
 
@@ -786,7 +1113,7 @@ impl MainLoopHandler {
         let requested_block_height = current_block_height.next();
         main_loop_state
             .sync_state
-            .record_request(requested_block_height, *chosen_peer);
+            .record_request(requested_block_height, chosen_peer);
 
         Ok(())
     }
@@ -816,6 +1143,11 @@ impl MainLoopHandler {
         let mempool_cleanup_timer = time::sleep(mempool_cleanup_timer_interval);
         tokio::pin!(mempool_cleanup_timer);
 
+        // Set storing of the mempool on disk to run every R seconds
+        let mempool_persist_timer_interval = Duration::from_secs(MEMPOOL_PERSIST_INTERVAL_IN_SECS);
+        let mempool_persist_timer = time::sleep(mempool_persist_timer_interval);
+        tokio::pin!(mempool_persist_timer);
+
         // Set removal of stale notifications for incoming UTXOs
         let utxo_notification_cleanup_timer_interval =
             Duration::from_secs(UTXO_NOTIFICATION_POOL_PRUNE_INTERVAL_IN_SECS);
@@ -827,6 +1159,58 @@ impl MainLoopHandler {
         let mp_resync_timer = time::sleep(mp_resync_timer_interval);
         tokio::pin!(mp_resync_timer);
 
+        // Set dropping of transaction witnesses of deeply buried blocks
+        let witness_prune_timer_interval = Duration::from_secs(WITNESS_PRUNE_INTERVAL_IN_SECS);
+        let witness_prune_timer = time::sleep(witness_prune_timer_interval);
+        tokio::pin!(witness_prune_timer);
+
+        // Set comparison of the tips announced by peers to the canonical chain
+        let chain_split_check_timer_interval =
+            Duration::from_secs(CHAIN_SPLIT_CHECK_INTERVAL_IN_SECS);
+        let chain_split_check_timer = time::sleep(chain_split_check_timer_interval);
+        tokio::pin!(chain_split_check_timer);
+
+        // Set tracking of how long the node has been without peers
+        let peer_count_check_timer_interval =
+            Duration::from_secs(PEER_COUNT_CHECK_INTERVAL_IN_SECS);
+        let peer_count_check_timer = time::sleep(peer_count_check_timer_interval);
+        tokio::pin!(peer_count_check_timer);
+
+        // Set verification of a sample of the stored blocks and chunks
+        let storage_scrub_timer_interval = Duration::from_secs(STORAGE_SCRUB_INTERVAL_IN_SECS);
+        let storage_scrub_timer = time::sleep(storage_scrub_timer_interval);
+        tokio::pin!(storage_scrub_timer);
+
+        // Set monitoring of the false-positive rate of the sliding-window Bloom filter
+        let swbf_saturation_check_timer_interval =
+            Duration::from_secs(SWBF_SATURATION_CHECK_INTERVAL_IN_SECS);
+        let swbf_saturation_check_timer = time::sleep(swbf_saturation_check_timer_interval);
+        tokio::pin!(swbf_saturation_check_timer);
+
+        // Set retrying of transaction requests that peers did not answer
+        let transaction_request_check_timer_interval =
+            Duration::from_secs(TRANSACTION_REQUEST_CHECK_INTERVAL_IN_SECS);
+        let transaction_request_check_timer = time::sleep(transaction_request_check_timer_interval);
+        tokio::pin!(transaction_request_check_timer);
+
+        // Let the wallet react to its transactions being evicted from or
+        // expiring in the mempool, and announce promoted orphans
+        let mut mempool_events = self
+            .global_state_lock
+            .lock_guard()
+            .await
+            .mempool
+            .subscribe_to_events();
+
+        // Follow up on suspected chain splits
+        let mut network_events = self
+            .global_state_lock
+            .lock_guard()
+            .await
+            .net
+            .chain_split_detector
+            .subscribe_to_events();
+
         // Spawn threads to monitor for SIGTERM, SIGINT, and SIGQUIT. These
         // signals are only used on Unix systems.
         let (_tx_term, mut rx_term): (mpsc::Sender<()>, mpsc::Receiver<()>) =
@@ -874,6 +1258,15 @@ impl MainLoopHandler {
         }
 
         loop {
+            // While quiesced, only signals and RPC requests are handled. Peer
+            // connections and messages from peer threads and the miner queue
+            // up, and the timers fire once the node resumes. The node may be
+            // quiesced while waiting in `select!`, so every branch checks
+            // again, and defers the message it received.
+            let quiesced = self.is_quiesced().await;
+            if !quiesced {
+                self.handle_deferred_messages(&mut main_loop_state).await?;
+            }
             select! {
                 Ok(()) = signal::ctrl_c() => {
                     info!("Detected Ctrl+c signal.");
@@ -895,7 +1288,15 @@ impl MainLoopHandler {
                 }
 
                 // Handle incoming connections from peer
-                Ok((stream, peer_address)) = self.incoming_peer_listener.accept() => {
+                Ok((stream, peer_address)) = self.incoming_peer_listener.accept(), if !quiesced => {
+                    if self.is_quiesced().await {
+                        info!("Dropping incoming connection from {peer_address} while quiesced");
+                        continue;
+                    }
+                    if !self.global_state_lock.lock_guard_mut().await.accept_inbound_connection(peer_address) {
+                        warn!("Too many inbound connections from host or subnet of {}. Dropping connection.", peer_address.ip());
+                        continue;
+                    }
                     let state = self.global_state_lock.lock_guard().await;
                     let main_to_peer_broadcast_rx_clone: broadcast::Receiver<MainToPeerThread> = self.main_to_peer_broadcast_tx.subscribe();
                     let peer_thread_to_main_tx_clone: mpsc::Sender<PeerThreadToMain> = self.peer_thread_to_main_tx.clone();
@@ -921,8 +1322,12 @@ impl MainLoopHandler {
                 }
 
                 // Handle messages from peer threads
-                Some(msg) = peer_thread_to_main_rx.recv() => {
+                Some(msg) = peer_thread_to_main_rx.recv(), if !quiesced => {
                     debug!("Received message sent to main thread.");
+                    if self.is_quiesced().await {
+                        main_loop_state.deferred_messages.push_back(DeferredMessage::PeerThread(msg));
+                        continue;
+                    }
                     self.handle_peer_thread_message(
                         msg,
                         &mut main_loop_state,
@@ -931,10 +1336,34 @@ impl MainLoopHandler {
                 }
 
                 // Handle messages from miner thread
-                Some(main_message) = miner_to_main_rx.recv() => {
+                Some(main_message) = miner_to_main_rx.recv(), if !quiesced => {
+                    if self.is_quiesced().await {
+                        main_loop_state.deferred_messages.push_back(DeferredMessage::Miner(main_message));
+                        continue;
+                    }
                     self.handle_miner_thread_message(main_message).await?
                 }
 
+                // Handle transactions entering or leaving the mempool. Events
+                // missed because the receiver lagged behind are skipped.
+                Ok(mempool_event) = mempool_events.recv(), if !quiesced => {
+                    if self.is_quiesced().await {
+                        main_loop_state.deferred_messages.push_back(DeferredMessage::Mempool(mempool_event));
+                        continue;
+                    }
+                    self.handle_mempool_event(mempool_event).await?;
+                }
+
+                // Handle suspected chain splits. Events missed because the
+                // receiver lagged behind are skipped.
+                Ok(network_event) = network_events.recv(), if !quiesced => {
+                    if self.is_quiesced().await {
+                        main_loop_state.deferred_messages.push_back(DeferredMessage::Network(network_event));
+                        continue;
+                    }
+                    self.handle_network_event(network_event)?;
+                }
+
                 // Handle messages from rpc server thread
                 Some(rpc_server_message) = rpc_server_to_main_rx.recv() => {
                     let shutdown_after_execution = self.handle_rpc_server_message(rpc_server_message.clone()).await?;
@@ -944,7 +1373,10 @@ impl MainLoopHandler {
                 }
 
                 // Handle peer discovery
-                _ = &mut peer_discovery_timer => {
+                _ = &mut peer_discovery_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
                     // Check number of peers we are connected to and connect to more peers
                     // if needed.
                     debug!("Timer: peer discovery job");
@@ -955,7 +1387,10 @@ impl MainLoopHandler {
                 }
 
                 // Handle synchronization (i.e. batch-downloading of blocks)
-                _ = &mut synchronization_timer => {
+                _ = &mut synchronization_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
                     debug!("Timer: block-synchronization job");
                     self.block_sync(&mut main_loop_state).await?;
 
@@ -963,17 +1398,36 @@ impl MainLoopHandler {
                     synchronization_timer.as_mut().reset(tokio::time::Instant::now() + sync_timer_interval);
                 }
 
-                // Handle mempool cleanup, i.e. removing stale/too old txs from mempool
-                _ = &mut mempool_cleanup_timer => {
+                // Handle mempool cleanup, i.e. removing expired txs from mempool
+                _ = &mut mempool_cleanup_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
                     debug!("Timer: mempool-cleaner job");
-                    self.global_state_lock.lock_mut(|s| s.mempool.prune_stale_transactions()).await;
+                    self.global_state_lock.lock_mut(|s| s.mempool.prune_expired(Timestamp::now())).await;
 
                     // Reset the timer to run this branch again in P seconds
                     mempool_cleanup_timer.as_mut().reset(tokio::time::Instant::now() + mempool_cleanup_timer_interval);
                 }
 
+                // Handle storing the mempool on disk, such that it survives a crash
+                _ = &mut mempool_persist_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
+                    debug!("Timer: mempool persist job");
+                    if let Err(err) = self.global_state_lock.persist_mempool().await {
+                        warn!("Failed to store mempool: {err}");
+                    }
+
+                    mempool_persist_timer.as_mut().reset(tokio::time::Instant::now() + mempool_persist_timer_interval);
+                }
+
                 // Handle incoming UTXO notification cleanup, i.e. removing stale/too old UTXO notification from pool
-                _ = &mut utxo_notification_cleanup_timer => {
+                _ = &mut utxo_notification_cleanup_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
                     debug!("Timer: UTXO notification pool cleanup job");
                     self.global_state_lock.lock_mut(|s| s.wallet_state.expected_utxos.prune_stale_utxo_notifications()).await;
 
@@ -981,12 +1435,98 @@ impl MainLoopHandler {
                 }
 
                 // Handle membership proof resynchronization
-                _ = &mut mp_resync_timer => {
+                _ = &mut mp_resync_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
                     debug!("Timer: Membership proof resync job");
                     self.global_state_lock.resync_membership_proofs().await?;
 
                     mp_resync_timer.as_mut().reset(tokio::time::Instant::now() + mp_resync_timer_interval);
                 }
+
+                // Handle dropping of transaction witnesses of deeply buried blocks
+                _ = &mut witness_prune_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
+                    debug!("Timer: witness prune job");
+                    if let Err(err) = self.global_state_lock.prune_transaction_witnesses().await {
+                        warn!("Failed to prune transaction witnesses: {err}");
+                    }
+
+                    witness_prune_timer.as_mut().reset(tokio::time::Instant::now() + witness_prune_timer_interval);
+                }
+
+                // Handle detection of chain splits
+                _ = &mut chain_split_check_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
+                    debug!("Timer: chain split check job");
+                    self.global_state_lock.check_for_chain_split().await;
+
+                    chain_split_check_timer.as_mut().reset(tokio::time::Instant::now() + chain_split_check_timer_interval);
+                }
+
+                // Handle tracking of the time without peers
+                _ = &mut peer_count_check_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
+                    debug!("Timer: peer count check job");
+                    self.global_state_lock.check_peer_count().await;
+
+                    // A node without peers looks for new ones right away,
+                    // rather than waiting for the next peer discovery round.
+                    // The dial backoffs still apply.
+                    if self.global_state_lock.lock(|s| s.net.peer_map.is_empty()).await {
+                        peer_discovery_timer.as_mut().reset(tokio::time::Instant::now());
+                    }
+
+                    peer_count_check_timer.as_mut().reset(tokio::time::Instant::now() + peer_count_check_timer_interval);
+                }
+
+                // Handle verification of stored blocks and chunks
+                _ = &mut storage_scrub_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
+                    debug!("Timer: storage scrub job");
+                    self.global_state_lock.scrub_storage().await;
+
+                    storage_scrub_timer.as_mut().reset(tokio::time::Instant::now() + storage_scrub_timer_interval);
+                }
+
+                // Handle check for saturation of the sliding-window Bloom filter
+                _ = &mut swbf_saturation_check_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
+                    debug!("Timer: SWBF saturation check job");
+                    self.global_state_lock.check_swbf_saturation().await;
+
+                    swbf_saturation_check_timer.as_mut().reset(tokio::time::Instant::now() + swbf_saturation_check_timer_interval);
+                }
+
+                // Handle retrying of transaction requests that timed out
+                _ = &mut transaction_request_check_timer, if !quiesced => {
+                    if self.is_quiesced().await {
+                        continue;
+                    }
+                    debug!("Timer: transaction request check job");
+                    let retries = self
+                        .global_state_lock
+                        .lock_mut(|s| s.net.retry_timed_out_transaction_requests(SystemTime::now()))
+                        .await;
+                    for (transaction_id, peer_address) in retries {
+                        debug!("Requesting transaction {transaction_id} from fallback peer {peer_address}");
+                        self.main_to_peer_broadcast_tx
+                            .send(MainToPeerThread::RequestTransaction(transaction_id, peer_address))?;
+                    }
+
+                    transaction_request_check_timer.as_mut().reset(tokio::time::Instant::now() + transaction_request_check_timer_interval);
+                }
             }
         }
 
@@ -1008,27 +1548,60 @@ impl MainLoopHandler {
                     transaction.kernel.mutator_set_hash
                 );
 
-                // send notification to peers
-                let notification: TransactionNotification = transaction.as_ref().clone().into();
-                self.main_to_peer_broadcast_tx
-                    .send(MainToPeerThread::TransactionNotification(notification))?;
-
                 // insert transaction into mempool
-                self.global_state_lock
+                let inserted = self
+                    .global_state_lock
                     .lock_mut(|s| s.mempool.insert(&transaction))
                     .await;
+                if let Err(rejection) = inserted {
+                    warn!("Not sending transaction from RPC server: {rejection}");
+                    return Ok(false);
+                }
+
+                // send notification to peers
+                let notification: TransactionNotificationWithFee =
+                    transaction.as_ref().clone().into();
+                self.main_to_peer_broadcast_tx
+                    .send(MainToPeerThread::TransactionNotification(notification))?;
 
                 // do not shut down
                 Ok(false)
             }
             RPCServerToMain::PauseMiner => {
                 info!("Received RPC request to stop miner");
+                if self.global_state_lock.cli().disable_mining {
+                    // there is no miner to receive the message
+                    return Ok(false);
+                }
 
                 self.main_to_miner_tx.send(MainToMiner::StopMining)?;
                 Ok(false)
             }
             RPCServerToMain::RestartMiner => {
                 info!("Received RPC request to start miner");
+                if self.global_state_lock.cli().disable_mining {
+                    warn!("Ignoring request to start miner because mining is disabled");
+                    return Ok(false);
+                }
+                self.main_to_miner_tx.send(MainToMiner::StartMining)?;
+                Ok(false)
+            }
+            RPCServerToMain::Quiesce => {
+                info!("Received RPC request to quiesce");
+                if self.global_state_lock.cli().disable_mining {
+                    // there is no miner to receive the message
+                    return Ok(false);
+                }
+
+                self.main_to_miner_tx.send(MainToMiner::StopMining)?;
+                Ok(false)
+            }
+            RPCServerToMain::Resume => {
+                info!("Received RPC request to resume");
+                if !self.global_state_lock.cli().spawns_miner() {
+                    return Ok(false);
+                }
+
                 self.main_to_miner_tx.send(MainToMiner::StartMining)?;
                 Ok(false)
             }
@@ -1053,6 +1626,11 @@ impl MainLoopHandler {
             .send(MainToPeerThread::DisconnectAll());
         debug!("sent bye");
 
+        // Store the mempool, such that pending transactions survive the restart
+        if let Err(err) = self.global_state_lock.persist_mempool().await {
+            warn!("Failed to store mempool: {err}");
+        }
+
         // Flush all databases
         self.global_state_lock.flush_databases().await?;
 
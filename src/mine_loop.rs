@@ -1,6 +1,8 @@
-use crate::models::blockchain::block::block_body::BlockBody;
-use crate::models::blockchain::block::block_header::BlockHeader;
+use crate::config_models::network::Network;
+use crate::models::blockchain::block::block_body::{BlockBody, UncleBlock};
+use crate::models::blockchain::block::block_header::{BlockHeader, BlockHeaderHasher};
 use crate::models::blockchain::block::block_height::BlockHeight;
+use crate::models::blockchain::block::block_kernel::BlockKernel;
 use crate::models::blockchain::block::mutator_set_update::*;
 use crate::models::blockchain::block::*;
 use crate::models::blockchain::shared::*;
@@ -12,8 +14,8 @@ use crate::models::blockchain::transaction::*;
 use crate::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
 use crate::models::blockchain::type_scripts::TypeScript;
 use crate::models::channel::*;
+use crate::models::consensus::mast_hash::MastHash;
 use crate::models::consensus::timestamp::Timestamp;
-use crate::models::shared::SIZE_20MB_IN_BYTES;
 use crate::models::state::wallet::utxo_notification_pool::{ExpectedUtxo, UtxoNotifier};
 use crate::models::state::wallet::WalletSecret;
 use crate::models::state::{GlobalState, GlobalStateLock};
@@ -27,7 +29,6 @@ use rand::rngs::StdRng;
 use rand::thread_rng;
 use rand::Rng;
 use rand::SeedableRng;
-use std::ops::Deref;
 use std::time::Duration;
 use tasm_lib::twenty_first::util_types::mmr::mmr_accumulator::MmrAccumulator;
 use tasm_lib::twenty_first::util_types::mmr::mmr_trait::Mmr;
@@ -43,14 +44,99 @@ use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 
 use self::primitive_witness::SaltedUtxos;
 
-const MOCK_MAX_BLOCK_SIZE: u32 = 1_000_000;
+/// Time the miner waits for `main_loop` to process a block it found, before
+/// it continues on the tip in the global state.
+const READY_TO_MINE_TIMEOUT_IN_SECS: u64 = 30;
 
-/// Prepare a Block for mining
-fn make_block_template(
+/// The answer of `main_loop` to a block found by the miner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MainAnswerToFoundBlock {
+    /// The found block was processed, and the mempool is up to date
+    Ready,
+
+    /// `main_loop` switched to another block, e.g. one received from a peer
+    /// before the found block was processed
+    NewBlock(Box<Block>),
+
+    /// Another message, which is left unseen for the mine loop to handle
+    OtherMessage,
+
+    /// No answer in time, e.g. because the found block was discarded
+    TimedOut,
+}
+
+/// Wait for `main_loop` to answer a block found by the miner.
+async fn await_answer_to_found_block(
+    from_main: &mut watch::Receiver<MainToMiner>,
+    timeout: Duration,
+) -> Result<MainAnswerToFoundBlock> {
+    let Ok(changed) = tokio::time::timeout(timeout, from_main.changed()).await else {
+        return Ok(MainAnswerToFoundBlock::TimedOut);
+    };
+    changed.context("Miner failed to read from watch channel")?;
+
+    let msg = from_main.borrow().clone();
+    debug!("Got {:?} msg from main after finding block", msg);
+    match msg {
+        MainToMiner::ReadyToMineNextBlock => Ok(MainAnswerToFoundBlock::Ready),
+        MainToMiner::NewBlock(block) => Ok(MainAnswerToFoundBlock::NewBlock(block)),
+        _ => {
+            // Waiting marked the message as seen, so mark it as unseen again
+            // such that the mine loop handles it, e.g. a shutdown.
+            from_main.mark_changed();
+            Ok(MainAnswerToFoundBlock::OtherMessage)
+        }
+    }
+}
+
+/// Return the block to mine on after `main_loop` answered the block found by
+/// the miner, and count the recoveries from unexpected answers.
+///
+/// Locking:
+///   * acquires `global_state_lock` for write, unless the answer is expected
+async fn block_to_mine_on(
+    answer: MainAnswerToFoundBlock,
+    found_block: Block,
+    global_state_lock: &GlobalStateLock,
+) -> Block {
+    match answer {
+        MainAnswerToFoundBlock::Ready => found_block,
+        MainAnswerToFoundBlock::NewBlock(block) => {
+            warn!(
+                "`main_loop` switched to block of height {} before processing own mined block. Mining on that block.",
+                block.kernel.header.height
+            );
+            global_state_lock
+                .lock_mut(|s| s.miner_recoveries.new_block_while_waiting += 1)
+                .await;
+            *block
+        }
+        MainAnswerToFoundBlock::OtherMessage => {
+            warn!("`main_loop` did not confirm own mined block before sending another message");
+            global_state_lock
+                .lock_mut(|s| s.miner_recoveries.other_message_while_waiting += 1)
+                .await;
+            found_block
+        }
+        MainAnswerToFoundBlock::TimedOut => {
+            warn!("`main_loop` did not confirm own mined block in time. Mining on the tip.");
+            global_state_lock
+                .lock_mut(|s| {
+                    s.miner_recoveries.timeouts += 1;
+                    s.chain.light_state().clone()
+                })
+                .await
+        }
+    }
+}
+
+/// Build the body of a block on top of `previous_block` that contains the
+/// given transaction. Fails if the transaction's removal records cannot be
+/// applied to the previous block's mutator set.
+pub(crate) fn make_block_body(
     previous_block: &Block,
     transaction: Transaction,
-    mut block_timestamp: Timestamp,
-) -> (BlockHeader, BlockBody) {
+) -> Result<BlockBody> {
     let additions = transaction.kernel.outputs.clone();
     let removals = transaction.kernel.inputs.clone();
     let mut next_mutator_set_accumulator: MutatorSetAccumulator =
@@ -60,29 +146,49 @@ fn make_block_template(
     // This function mutates the MS accumulator that is given as argument to
     // the function such that the next mutator set accumulator is calculated.
     let mutator_set_update = MutatorSetUpdate::new(removals, additions);
-    mutator_set_update
-        .apply_to_accumulator(&mut next_mutator_set_accumulator)
-        .expect("Mutator set mutation must work");
+    mutator_set_update.apply_to_accumulator(&mut next_mutator_set_accumulator)?;
 
     let mut block_mmra = previous_block.kernel.body.block_mmr_accumulator.clone();
     block_mmra.append(previous_block.hash());
-    let block_body: BlockBody = BlockBody {
+    Ok(BlockBody {
         transaction,
-        mutator_set_accumulator: next_mutator_set_accumulator.clone(),
+        mutator_set_accumulator: next_mutator_set_accumulator,
         lock_free_mmr_accumulator: MmrAccumulator::<Hash>::new(vec![]),
         block_mmr_accumulator: block_mmra,
         uncle_blocks: vec![],
+    })
+}
+
+/// Prepare a Block for mining
+fn make_block_template(
+    previous_block: &Block,
+    transaction: Transaction,
+    uncle_blocks: Vec<UncleBlock>,
+    mut block_timestamp: Timestamp,
+    network: Network,
+) -> (BlockHeader, BlockBody) {
+    let block_body = BlockBody {
+        uncle_blocks,
+        ..make_block_body(previous_block, transaction).expect("Mutator set mutation must work")
     };
 
     let zero = BFieldElement::zero();
     let new_pow_line: U32s<5> =
         previous_block.kernel.header.proof_of_work_family + previous_block.kernel.header.difficulty;
     let next_block_height = previous_block.kernel.header.height.next();
-    if block_timestamp < previous_block.kernel.header.timestamp {
+    // The timestamp must exceed the median timestamp of the recent blocks,
+    // which is at most that of the previous block, since timestamps do not
+    // decrease along the chain.
+    if block_timestamp <= previous_block.kernel.header.timestamp {
         warn!("Received block is timestamped in the future; mining on future-timestamped block.");
-        block_timestamp = previous_block.kernel.header.timestamp + Timestamp::seconds(1);
+        block_timestamp = previous_block.kernel.header.timestamp + Timestamp::millis(1);
     }
-    let difficulty: U32s<5> = Block::difficulty_control(previous_block, block_timestamp);
+    let difficulty: U32s<5> = Block::difficulty_control(
+        previous_block,
+        block_timestamp,
+        network.target_block_interval(),
+        network.max_difficulty_adjustment_factor(),
+    );
 
     let block_header = BlockHeader {
         version: zero,
@@ -90,7 +196,7 @@ fn make_block_template(
         prev_block_digest: previous_block.hash(),
         timestamp: block_timestamp,
         nonce: [zero, zero, zero],
-        max_block_size: MOCK_MAX_BLOCK_SIZE,
+        max_block_size: network.max_block_size(),
         proof_of_work_line: new_pow_line,
         proof_of_work_family: new_pow_line,
         difficulty,
@@ -105,6 +211,7 @@ async fn mine_block(
     block_body: BlockBody,
     sender: oneshot::Sender<NewBlockFound>,
     coinbase_utxo_info: ExpectedUtxo,
+    block_transactions: MinedBlockTransactions,
     difficulty: U32s<5>,
     unrestricted_mining: bool,
 ) {
@@ -126,6 +233,7 @@ async fn mine_block(
             block_body,
             sender,
             coinbase_utxo_info,
+            block_transactions,
             difficulty,
             unrestricted_mining,
         )
@@ -139,6 +247,7 @@ fn mine_block_worker(
     block_body: BlockBody,
     sender: oneshot::Sender<NewBlockFound>,
     coinbase_utxo_info: ExpectedUtxo,
+    block_transactions: MinedBlockTransactions,
     difficulty: U32s<5>,
     unrestricted_mining: bool,
 ) {
@@ -158,8 +267,20 @@ fn mine_block_worker(
     let block_type = Block::mk_std_block_type(None);
     let mut block = Block::new(block_header, block_body, block_type);
 
+    // Only the nonce and the timestamp change between attempts, so the body
+    // digest and the rest of the header's Merkle tree are computed only once.
+    let body_digest = block.kernel.body.mast_hash();
+    let mut header_hasher = BlockHeaderHasher::new(&block.kernel.header);
+    let mut nonce = block.kernel.header.nonce;
+    let mut timestamp = block.kernel.header.timestamp;
+    let earliest_timestamp = timestamp;
+
     // Mining takes place here
-    while block.hash() >= threshold {
+    while BlockKernel::mast_hash_from_digests(
+        header_hasher.mast_hash(nonce, timestamp),
+        body_digest,
+    ) >= threshold
+    {
         if !unrestricted_mining {
             std::thread::sleep(Duration::from_millis(100));
         }
@@ -176,17 +297,22 @@ fn mine_block_worker(
             return;
         }
 
-        // mutate nonce in the block's header.
-        // Block::hash() will subsequently return a new digest.
-        block.set_header_nonce(rng.gen());
+        nonce = rng.gen();
 
         // See issue #149 and test block_timestamp_represents_time_block_found()
         // this ensures header timestamp represents the moment block is found.
         // this is simplest impl.  Efficiencies can perhaps be gained by only
         // performing every N iterations, or other strategies.
-        block.set_header_timestamp(Timestamp::now());
+        // The template's timestamp is kept if the clock is behind it, as it
+        // must exceed the timestamps of the preceding blocks.
+        timestamp = Timestamp::now().max(earliest_timestamp);
     }
 
+    // mutate nonce and timestamp in the block's header.
+    // Block::hash() will subsequently return the digest found above.
+    block.set_header_nonce(nonce);
+    block.set_header_timestamp(timestamp);
+
     let nonce = block.kernel.header.nonce;
     info!(
         "Found valid block with nonce: ({}, {}, {}).",
@@ -196,6 +322,7 @@ fn mine_block_worker(
     let new_block_found = NewBlockFound {
         block: Box::new(block),
         coinbase_utxo_info: Box::new(coinbase_utxo_info),
+        block_transactions: Box::new(block_transactions),
     };
 
     let timestamp = new_block_found.block.kernel.header.timestamp;
@@ -278,23 +405,89 @@ fn make_coinbase_transaction(
 
 /// Create the transaction that goes into the block template. The transaction is
 /// built from the mempool and from the coinbase transaction. Also returns the
-/// "sender randomness" used in the coinbase transaction.
+/// "sender randomness" used in the coinbase transaction, and the transactions
+/// that were merged.
+///
+/// The transactions are chosen such that the block template does not exceed
+/// its declared maximum size.
 fn create_block_transaction(
     latest_block: &Block,
     global_state: &GlobalState,
+    uncle_blocks: &[UncleBlock],
     timestamp: Timestamp,
-) -> (Transaction, ExpectedUtxo) {
-    let block_capacity_for_transactions = SIZE_20MB_IN_BYTES;
+) -> (Transaction, ExpectedUtxo, MinedBlockTransactions) {
+    let network = global_state.cli().network;
+    let max_block_size = network.max_block_size() as usize;
+    let block_size = |transaction: Transaction| {
+        let (header, body) = make_block_template(
+            latest_block,
+            transaction,
+            uncle_blocks.to_vec(),
+            timestamp,
+            network,
+        );
+        Block::new(header, body, Block::mk_std_block_type(None)).serialized_size()
+    };
+    let merge_most_valuable = |transactions: &[Transaction]| {
+        merge_block_transaction(
+            latest_block,
+            global_state,
+            uncle_blocks,
+            timestamp,
+            transactions.to_vec(),
+        )
+    };
 
-    // Get most valuable transactions from mempool
+    // The header, the accumulators, and the coinbase transaction take up space
+    // too, so only the remainder is available to transactions from the mempool
+    let coinbase_only = merge_most_valuable(&[]);
+    let overhead = block_size(coinbase_only.0.clone());
     let transactions_to_include = global_state
         .mempool
-        .get_transactions_for_block(block_capacity_for_transactions);
+        .get_transactions_for_block(max_block_size.saturating_sub(overhead));
+
+    let all_included = merge_most_valuable(&transactions_to_include);
+    if transactions_to_include.is_empty() || block_size(all_included.0.clone()) <= max_block_size {
+        return all_included;
+    }
+
+    // Merged transactions and the updated mutator set are not exactly the sum
+    // of their parts, so bisect on the number of most valuable transactions
+    // to find the largest number that fits. The first `fitting_count`
+    // transactions fit, and the first `overfull_count` do not.
+    let mut fitting = coinbase_only;
+    let mut fitting_count = 0;
+    let mut overfull_count = transactions_to_include.len();
+    while overfull_count - fitting_count > 1 {
+        let count = (fitting_count + overfull_count) / 2;
+        let merged = merge_most_valuable(&transactions_to_include[..count]);
+        if block_size(merged.0.clone()) <= max_block_size {
+            fitting = merged;
+            fitting_count = count;
+        } else {
+            overfull_count = count;
+        }
+    }
+
+    fitting
+}
 
-    // Build coinbase UTXO
+/// Merge the given transactions from the mempool with a new coinbase
+/// transaction, which pays the reward and the fees to the miner.
+fn merge_block_transaction(
+    latest_block: &Block,
+    global_state: &GlobalState,
+    uncle_blocks: &[UncleBlock],
+    timestamp: Timestamp,
+    transactions_to_include: Vec<Transaction>,
+) -> (Transaction, ExpectedUtxo, MinedBlockTransactions) {
+    // Build coinbase UTXO. The mempool rejects transactions with negative
+    // fees, and the fees of valid transactions cannot add up to more than the
+    // total supply.
     let transaction_fees = transactions_to_include
         .iter()
-        .fold(NeptuneCoins::zero(), |acc, tx| acc + tx.kernel.fee);
+        .try_fold(NeptuneCoins::zero(), |acc, tx| acc.safe_add(tx.kernel.fee))
+        .expect("Fees of the mempool transactions must not overflow");
 
     let coinbase_recipient_spending_key = global_state
         .wallet_state
@@ -304,7 +497,13 @@ fn create_block_transaction(
     let next_block_height: BlockHeight = latest_block.kernel.header.height.next();
 
     let lock_script = receiving_address.lock_script();
-    let coinbase_amount = Block::get_mining_reward(next_block_height) + transaction_fees;
+    let coinbase_amount = Block::maximum_coinbase(
+        next_block_height,
+        global_state.cli().network,
+        uncle_blocks.len(),
+        transaction_fees,
+    )
+    .expect("Coinbase of the block template must not overflow");
     let coinbase_utxo = Utxo::new_native_coin(lock_script, coinbase_amount);
 
     let (coinbase_transaction, coinbase_sender_randomness) = make_coinbase_transaction(
@@ -323,10 +522,15 @@ fn create_block_transaction(
 
     // Merge incoming transactions with the coinbase transaction
     let merged_transaction = transactions_to_include
-        .into_iter()
-        .fold(coinbase_transaction, |acc, transaction| {
+        .iter()
+        .cloned()
+        .fold(coinbase_transaction.clone(), |acc, transaction| {
             Transaction::merge_with(acc, transaction)
         });
+    let block_transactions = MinedBlockTransactions {
+        coinbase: coinbase_transaction,
+        mempool_transactions: transactions_to_include,
+    };
 
     let utxo_info_for_coinbase = ExpectedUtxo::new(
         coinbase_utxo,
@@ -335,7 +539,25 @@ fn create_block_transaction(
         UtxoNotifier::OwnMiner,
     );
 
-    (merged_transaction, utxo_info_for_coinbase)
+    (
+        merged_transaction,
+        utxo_info_for_coinbase,
+        block_transactions,
+    )
+}
+
+/// The uncles that a block mined on top of `latest_block` references. Only
+/// archival nodes know blocks off the canonical chain.
+async fn uncles_to_reference(global_state: &GlobalState, latest_block: &Block) -> Vec<UncleBlock> {
+    if !global_state.chain.is_archival_node() {
+        return vec![];
+    }
+
+    global_state
+        .chain
+        .archival_state()
+        .uncle_candidates(latest_block)
+        .await
 }
 
 /// Locking:
@@ -346,6 +568,12 @@ pub async fn mine(
     mut latest_block: Block,
     global_state_lock: GlobalStateLock,
 ) -> Result<()> {
+    if global_state_lock.cli().disable_mining {
+        info!("Not mining because mining is disabled");
+        global_state_lock.set_mining(false).await;
+        return Ok(());
+    }
+
     // Wait before starting mining thread to ensure that peers have sent us information about
     // their latest blocks. This should prevent the client from finding blocks that will later
     // be orphaned.
@@ -360,6 +588,10 @@ pub async fn mine(
                 info!("Not mining because we are syncing");
                 global_state_lock.set_mining(false).await;
                 None
+            } else if global_state_lock.lock(|s| s.net.quiesced).await {
+                info!("Not mining because the node is quiesced");
+                global_state_lock.set_mining(false).await;
+                None
             } else if pause_mine {
                 info!("Not mining because mining was paused");
                 global_state_lock.set_mining(false).await;
@@ -367,18 +599,24 @@ pub async fn mine(
             } else {
                 // Build the block template and spawn the worker thread to mine on it
                 let now = Timestamp::now();
-                let (transaction, coinbase_utxo_info) = create_block_transaction(
+                let global_state = global_state_lock.lock_guard().await;
+                let uncle_blocks = uncles_to_reference(&global_state, &latest_block).await;
+                let (transaction, coinbase_utxo_info, block_txs) =
+                    create_block_transaction(&latest_block, &global_state, &uncle_blocks, now);
+                drop(global_state);
+                let (block_header, block_body) = make_block_template(
                     &latest_block,
-                    global_state_lock.lock_guard().await.deref(),
+                    transaction,
+                    uncle_blocks,
                     now,
+                    global_state_lock.cli().network,
                 );
-                let (block_header, block_body) =
-                    make_block_template(&latest_block, transaction, now);
                 let miner_task = mine_block(
                     block_header,
                     block_body,
                     worker_thread_tx,
                     coinbase_utxo_info,
+                    block_txs,
                     latest_block.kernel.header.difficulty,
                     global_state_lock.cli().unrestricted_mining,
                 );
@@ -468,28 +706,23 @@ pub async fn mine(
                 // The block, however, *must* be valid on other parameters. So here, we should panic
                 // if it is not.
                 let now = Timestamp::now();
-                assert!(new_block_found.block.is_valid(&latest_block, now), "Own mined block must be valid. Failed validity check after successful PoW check.");
+                assert!(new_block_found.block.is_valid(&latest_block, global_state_lock.cli().network), "Own mined block must be valid. Failed validity check after successful PoW check.");
 
                 info!("Found new {} block with block height {}. Hash: {}", global_state_lock.cli().network, new_block_found.block.kernel.header.height, new_block_found.block.hash());
 
-                latest_block = *new_block_found.block.to_owned();
+                let found_block = *new_block_found.block.to_owned();
                 to_main.send(MinerToMain::NewBlockFound(new_block_found)).await?;
 
                 // Wait until `main_loop` has updated `global_state` before proceding. Otherwise, we would use
-                // a deprecated version of the mempool to build the next block. We don't mark the from-main loop
-                // received value as read yet as this would open up for race conditions if `main_loop` received
-                // a block from a peer at the same time as this block was found.
-                let _wait = from_main.changed().await;
-                let msg = from_main.borrow().clone();
-                debug!("Got {:?} msg from main after finding block", msg);
-                if !matches!(msg, MainToMiner::ReadyToMineNextBlock) {
-                    error!("Got bad message from `main_loop`: {:?}", msg);
-
-                    // TODO: Handle this case
-                    // We found a new block but the main thread updated with a block
-                    // before our could be registered. We should mine on the one
-                    // received from the main loop and not the one we found here.
-                }
+                // a deprecated version of the mempool to build the next block. If `main_loop` received a block
+                // from a peer at the same time as this block was found, mine on that block instead, and if it
+                // does not answer at all, e.g. because it discarded this block, mine on the tip.
+                let answer = await_answer_to_found_block(
+                    &mut from_main,
+                    Duration::from_secs(READY_TO_MINE_TIMEOUT_IN_SECS),
+                )
+                .await?;
+                latest_block = block_to_mine_on(answer, found_block, &global_state_lock).await;
             }
         }
     }
@@ -501,13 +734,165 @@ pub async fn mine(
 mod mine_loop_tests {
     use tracing_test::traced_test;
 
+    use crate::models::blockchain::block::block_header::BlockHeaderField;
+    use crate::models::blockchain::block::transfer_block::TransferBlock;
+    use crate::models::consensus::mast_hash::HasDiscriminant;
     use crate::{
-        config_models::network::Network,
+        config_models::{cli_args, network::Network},
         models::{consensus::timestamp::Timestamp, state::UtxoReceiverData},
-        tests::shared::mock_genesis_global_state,
+        tests::shared::{make_mock_block, mock_genesis_global_state},
     };
 
     use super::*;
+    use crate::models::consensus::ValidityTree;
+    use itertools::Itertools;
+    use rand::random;
+
+    #[traced_test]
+    #[tokio::test]
+    async fn disabled_miner_is_never_spawned_test() -> Result<()> {
+        let network = Network::RegTest;
+        let mut global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mining_cli = cli_args::Args {
+            network,
+            mine: true,
+            ..Default::default()
+        };
+        assert!(mining_cli.spawns_miner());
+
+        // Disabling mining overrides the request to mine
+        global_state_lock
+            .set_cli(cli_args::Args {
+                disable_mining: true,
+                ..mining_cli
+            })
+            .await;
+        assert!(!global_state_lock.cli().spawns_miner());
+        assert!(!global_state_lock.mining().await);
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn miner_resumes_after_any_answer_to_found_block_test() -> Result<()> {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let genesis_block = Block::genesis_block(network);
+        let address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (found_block, _, _) = make_mock_block(network, &genesis_block, None, address, random());
+        let (peer_block, _, _) = make_mock_block(network, &genesis_block, None, address, random());
+        let timeout = Duration::from_millis(100);
+
+        // `main_loop` processed the found block as expected
+        let (main_to_miner_tx, mut main_to_miner_rx) = watch::channel(MainToMiner::Empty);
+        main_to_miner_tx.send(MainToMiner::ReadyToMineNextBlock)?;
+        let answer = await_answer_to_found_block(&mut main_to_miner_rx, timeout).await?;
+        assert_eq!(MainAnswerToFoundBlock::Ready, answer);
+        assert_eq!(
+            found_block,
+            block_to_mine_on(answer, found_block.clone(), &global_state_lock).await
+        );
+
+        // `main_loop` got a block from a peer first
+        main_to_miner_tx.send(MainToMiner::NewBlock(Box::new(peer_block.clone())))?;
+        let answer = await_answer_to_found_block(&mut main_to_miner_rx, timeout).await?;
+        assert_eq!(
+            peer_block,
+            block_to_mine_on(answer, found_block.clone(), &global_state_lock).await
+        );
+
+        // `main_loop` sent another message, which remains to be handled
+        main_to_miner_tx.send(MainToMiner::StopMining)?;
+        let answer = await_answer_to_found_block(&mut main_to_miner_rx, timeout).await?;
+        assert_eq!(MainAnswerToFoundBlock::OtherMessage, answer);
+        assert!(main_to_miner_rx.has_changed()?);
+        assert_eq!(
+            found_block,
+            block_to_mine_on(answer, found_block.clone(), &global_state_lock).await
+        );
+        main_to_miner_rx.borrow_and_update();
+
+        // `main_loop` never answered, e.g. because it discarded the found block
+        let answer = await_answer_to_found_block(&mut main_to_miner_rx, timeout).await?;
+        assert_eq!(MainAnswerToFoundBlock::TimedOut, answer);
+        assert_eq!(
+            genesis_block,
+            block_to_mine_on(answer, found_block.clone(), &global_state_lock).await
+        );
+
+        assert_eq!(
+            MinerRecoveries {
+                new_block_while_waiting: 1,
+                other_message_while_waiting: 1,
+                timeouts: 1,
+            },
+            global_state_lock.lock(|s| s.miner_recoveries).await
+        );
+
+        // A closed channel is an error rather than a timeout
+        drop(main_to_miner_tx);
+        assert!(await_answer_to_found_block(&mut main_to_miner_rx, timeout)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn block_template_header_hashes_in_field_order_test() -> Result<()> {
+        // The header made by the miner has the same fields, hashed in the same
+        // order, as the header received from peers
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let global_state = global_state_lock.lock_guard().await;
+        let genesis_block = Block::genesis_block(network);
+        let now = genesis_block.kernel.header.timestamp + Timestamp::hours(1);
+        let (transaction, _coinbase_sender_randomness, _) =
+            create_block_transaction(&genesis_block, &global_state, &[], now);
+        let (header, body) = make_block_template(&genesis_block, transaction, vec![], now, network);
+
+        assert_eq!(
+            vec![
+                header.version.encode(),
+                header.height.encode(),
+                header.prev_block_digest.encode(),
+                header.timestamp.encode(),
+                header.nonce.encode(),
+                header.max_block_size.encode(),
+                header.proof_of_work_line.encode(),
+                header.proof_of_work_family.encode(),
+                header.difficulty.encode(),
+            ],
+            header.mast_sequences()
+        );
+        for (field, sequence) in [
+            (BlockHeaderField::Timestamp, header.timestamp.encode()),
+            (BlockHeaderField::Nonce, header.nonce.encode()),
+            (BlockHeaderField::Difficulty, header.difficulty.encode()),
+        ] {
+            assert_eq!(sequence, header.mast_sequences()[field.discriminant()]);
+        }
+
+        let header_hash = header.mast_hash();
+        assert_eq!(
+            header_hash,
+            BlockHeaderHasher::new(&header).mast_hash(header.nonce, header.timestamp)
+        );
+
+        let block = Block::new(header, body, Block::mk_std_block_type(None));
+        let transferred_block: Block = TransferBlock::from(block.clone()).into();
+        assert_eq!(header_hash, transferred_block.kernel.header.mast_hash());
+        assert_eq!(block.hash(), transferred_block.hash());
+
+        Ok(())
+    }
 
     #[traced_test]
     #[tokio::test]
@@ -526,8 +911,8 @@ mod mine_loop_tests {
         // Verify constructed coinbase transaction and block template when mempool is empty
         let genesis_block = Block::genesis_block(network);
         let now = genesis_block.kernel.header.timestamp;
-        let (transaction_empty_mempool, _coinbase_sender_randomness) =
-            create_block_transaction(&genesis_block, &premine_receiver_global_state, now);
+        let (transaction_empty_mempool, _coinbase_sender_randomness, _) =
+            create_block_transaction(&genesis_block, &premine_receiver_global_state, &[], now);
         assert_eq!(
             1,
             transaction_empty_mempool.kernel.outputs.len(),
@@ -537,15 +922,20 @@ mod mine_loop_tests {
             transaction_empty_mempool.kernel.inputs.is_empty(),
             "Coinbase transaction with empty mempool must have zero inputs"
         );
-        let (block_header_template_empty_mempool, block_body_empty_mempool) =
-            make_block_template(&genesis_block, transaction_empty_mempool, now);
+        let (block_header_template_empty_mempool, block_body_empty_mempool) = make_block_template(
+            &genesis_block,
+            transaction_empty_mempool,
+            vec![],
+            now,
+            network,
+        );
         let block_template_empty_mempool = Block::new(
             block_header_template_empty_mempool,
             block_body_empty_mempool,
             Block::mk_std_block_type(None),
         );
         assert!(
-            block_template_empty_mempool.is_valid(&genesis_block, now),
+            block_template_empty_mempool.is_valid(&genesis_block, network),
             "Block template created by miner with empty mempool must be valid"
         );
 
@@ -575,14 +965,16 @@ mod mine_loop_tests {
             .unwrap();
         premine_receiver_global_state
             .mempool
-            .insert(&tx_by_preminer);
+            .insert(&tx_by_preminer)
+            .unwrap();
         assert_eq!(1, premine_receiver_global_state.mempool.len());
 
         // Build transaction
-        let (transaction_non_empty_mempool, _new_coinbase_sender_randomness) =
+        let (transaction_non_empty_mempool, _new_coinbase_sender_randomness, _) =
             create_block_transaction(
                 &genesis_block,
                 &premine_receiver_global_state,
+                &[],
                 now + Timestamp::months(7),
             );
         assert_eq!(
@@ -596,7 +988,9 @@ mod mine_loop_tests {
         let (block_header_template, block_body) = make_block_template(
             &genesis_block,
             transaction_non_empty_mempool,
+            vec![],
             now + Timestamp::months(7),
+            network,
         );
         let block_template_non_empty_mempool = Block::new(
             block_header_template,
@@ -604,16 +998,93 @@ mod mine_loop_tests {
             Block::mk_std_block_type(None),
         );
         assert!(
-            block_template_non_empty_mempool.is_valid(
-                &genesis_block,
-                now + Timestamp::months(7) + Timestamp::seconds(2)
-            ),
+            block_template_non_empty_mempool.is_valid(&genesis_block, network),
             "Block template created by miner with non-empty mempool must be valid"
         );
 
         Ok(())
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn block_template_does_not_exceed_max_block_size_test() -> Result<()> {
+        // The mempool holds slightly more than fits in a block, so the miner
+        // must leave out one transaction to stay within the declared limit
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let now = genesis_block.kernel.header.timestamp + Timestamp::hours(1);
+
+        // Transactions of a fifth of the maximum block size each, before
+        // counting the header, accumulators, and coinbase transaction
+        let transaction_size = network.max_block_size() as usize / 5;
+        let large_transaction = |fee: u32| Transaction {
+            kernel: TransactionKernel {
+                inputs: vec![],
+                outputs: vec![],
+                public_announcements: vec![PublicAnnouncement::new(
+                    (0..transaction_size / 8)
+                        .map(|_| random::<BFieldElement>())
+                        .collect(),
+                )],
+                fee: NeptuneCoins::new(fee),
+                timestamp: now,
+                coinbase: None,
+                mutator_set_hash: genesis_block.kernel.body.mutator_set_accumulator.hash(),
+            },
+            witness: TransactionValidationLogic {
+                vast: ValidityTree::axiom(),
+                maybe_primitive_witness: None,
+            },
+        };
+        let mempool_transactions = (1..=5).map(large_transaction).collect_vec();
+        for transaction in mempool_transactions.iter() {
+            global_state.mempool.insert(transaction).unwrap();
+        }
+
+        let (transaction, _, block_transactions) =
+            create_block_transaction(&genesis_block, &global_state, &[], now);
+        let (header, body) =
+            make_block_template(&genesis_block, transaction.clone(), vec![], now, network);
+        let max_block_size = header.max_block_size as usize;
+        let block = Block::new(header, body, Block::mk_std_block_type(None));
+        assert!(block.serialized_size() <= max_block_size);
+        assert!(block.is_valid(&genesis_block, network));
+
+        // The most valuable transactions are included, the least valuable is not
+        assert_eq!(
+            mempool_transactions[1..].iter().rev().collect_vec(),
+            block_transactions.mempool_transactions.iter().collect_vec()
+        );
+
+        // Including the excluded transaction would exceed the limit
+        let overfull_transaction = transaction.merge_with(mempool_transactions[0].clone());
+        let (overfull_header, overfull_body) =
+            make_block_template(&genesis_block, overfull_transaction, vec![], now, network);
+        let overfull_block = Block::new(
+            overfull_header,
+            overfull_body,
+            Block::mk_std_block_type(None),
+        );
+        assert!(overfull_block.serialized_size() > max_block_size);
+        assert!(!overfull_block.is_valid(&genesis_block, network));
+
+        // Declaring a larger maximum size does not make room for it either
+        let inflated_block = Block::new(
+            BlockHeader {
+                max_block_size: u32::MAX,
+                ..overfull_block.kernel.header.clone()
+            },
+            overfull_block.kernel.body.clone(),
+            Block::mk_std_block_type(None),
+        );
+        assert!(!inflated_block.is_valid(&genesis_block, network));
+
+        Ok(())
+    }
+
     /// This test mines a single block at height 1 on the regtest network
     /// and then validates it with `Block::is_valid()` and
     /// `Block::has_proof_of_work()`.
@@ -643,13 +1114,19 @@ mod mine_loop_tests {
         let tip_block_orig = global_state.chain.light_state();
         let now = Timestamp::now();
 
-        let (transaction, coinbase_utxo_info) =
-            create_block_transaction(tip_block_orig, &global_state, now);
+        let (transaction, coinbase_utxo_info, block_transactions) =
+            create_block_transaction(tip_block_orig, &global_state, &[], now);
 
-        let (block_header, block_body) = make_block_template(tip_block_orig, transaction, now);
+        let (block_header, block_body) =
+            make_block_template(tip_block_orig, transaction, vec![], now, network);
 
         let block_timestamp = tip_block_orig.kernel.header.timestamp + Timestamp::seconds(1);
-        let difficulty: U32s<5> = Block::difficulty_control(tip_block_orig, block_timestamp);
+        let difficulty: U32s<5> = Block::difficulty_control(
+            tip_block_orig,
+            block_timestamp,
+            network.target_block_interval(),
+            network.max_difficulty_adjustment_factor(),
+        );
         let unrestricted_mining = false;
 
         mine_block_worker(
@@ -657,13 +1134,14 @@ mod mine_loop_tests {
             block_body,
             worker_thread_tx,
             coinbase_utxo_info,
+            block_transactions,
             difficulty,
             unrestricted_mining,
         );
 
         let mined_block_info = worker_thread_rx.await.unwrap();
 
-        assert!(mined_block_info.block.is_valid(tip_block_orig, now));
+        assert!(mined_block_info.block.is_valid(tip_block_orig, network));
         assert!(mined_block_info.block.has_proof_of_work(tip_block_orig));
 
         Ok(())
@@ -693,24 +1171,35 @@ mod mine_loop_tests {
         // pretend/simulate that it takes at least 10 seconds to mine the block.
         let ten_seconds_ago = Timestamp::now() - Timestamp::seconds(10);
 
-        let (transaction, coinbase_utxo_info) =
-            create_block_transaction(tip_block_orig, &global_state, ten_seconds_ago);
+        let (transaction, coinbase_utxo_info, block_transactions) =
+            create_block_transaction(tip_block_orig, &global_state, &[], ten_seconds_ago);
 
-        let (block_header, block_body) =
-            make_block_template(tip_block_orig, transaction, ten_seconds_ago);
+        let (block_header, block_body) = make_block_template(
+            tip_block_orig,
+            transaction,
+            vec![],
+            ten_seconds_ago,
+            network,
+        );
 
         // sanity check that our initial state is correct.
         assert_eq!(block_header.timestamp, ten_seconds_ago);
 
         let initial_header_timestamp = block_header.timestamp;
         let unrestricted_mining = false;
-        let difficulty: U32s<5> = Block::difficulty_control(tip_block_orig, ten_seconds_ago);
+        let difficulty: U32s<5> = Block::difficulty_control(
+            tip_block_orig,
+            ten_seconds_ago,
+            network.target_block_interval(),
+            network.max_difficulty_adjustment_factor(),
+        );
 
         mine_block_worker(
             block_header,
             block_body,
             worker_thread_tx,
             coinbase_utxo_info,
+            block_transactions,
             difficulty,
             unrestricted_mining,
         );
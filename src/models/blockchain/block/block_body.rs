@@ -9,6 +9,8 @@ use tasm_lib::twenty_first::util_types::mmr::mmr_accumulator::MmrAccumulator;
 use tasm_lib::Digest;
 use twenty_first::math::bfield_codec::BFieldCodec;
 
+use super::block_header::BlockHeader;
+use super::block_kernel::BlockKernel;
 use crate::models::blockchain::shared::Hash;
 use crate::models::blockchain::transaction::Transaction;
 use crate::util_types::mutator_set::mutator_set_accumulator::MutatorSetAccumulator;
@@ -50,7 +52,24 @@ pub struct BlockBody {
     /// All blocks that lost the block race to an ancestor of this block and have not been
     /// listed as uncle before. The miner will need to prove that between his block and
     /// its least common ancestor with the uncle block, it was not listed.
-    pub uncle_blocks: Vec<Digest>,
+    pub uncle_blocks: Vec<UncleBlock>,
+}
+
+/// A block referenced as uncle. The header is carried along, so that the
+/// uncle can be verified by nodes that never received it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, BFieldCodec, GetSize)]
+pub struct UncleBlock {
+    pub header: BlockHeader,
+
+    /// The Merkle root of the uncle's body
+    pub body_digest: Digest,
+}
+
+impl UncleBlock {
+    /// The digest of the uncle, as computed by [Block::hash](super::Block::hash).
+    pub fn digest(&self) -> Digest {
+        BlockKernel::mast_hash_from_digests(self.header.mast_hash(), self.body_digest)
+    }
 }
 
 impl MastHash for BlockBody {
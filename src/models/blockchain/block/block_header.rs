@@ -1,6 +1,7 @@
 use crate::models::consensus::timestamp::Timestamp;
 use crate::prelude::twenty_first;
 
+use crate::models::blockchain::shared::Hash;
 use crate::models::consensus::mast_hash::HasDiscriminant;
 use crate::models::consensus::mast_hash::MastHash;
 use get_size::GetSize;
@@ -8,6 +9,7 @@ use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use twenty_first::math::bfield_codec::BFieldCodec;
 use twenty_first::math::digest::Digest;
+use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 
 use twenty_first::amount::u32s::U32s;
 use twenty_first::math::b_field_element::BFieldElement;
@@ -16,21 +18,29 @@ use super::block_height::BlockHeight;
 
 pub const TARGET_DIFFICULTY_U32_SIZE: usize = 5;
 pub const PROOF_OF_WORK_COUNT_U32_SIZE: usize = 5;
-pub const TARGET_BLOCK_INTERVAL: u64 = 588000; // 9.8 minutes in milliseconds
 pub const MINIMUM_DIFFICULTY: u32 = 2;
 
+/// The difficulty of a block is at most this factor higher or lower than that
+/// of its predecessor, however far its timestamp is from the target interval,
+/// on all networks.
+pub const MAX_DIFFICULTY_ADJUSTMENT_FACTOR: u32 = 4;
+
+/// The timestamp of a block must be greater than the median of the timestamps
+/// of this many preceding blocks.
+pub const MEDIAN_TIMESTAMP_BLOCK_COUNT: usize = 11;
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, BFieldCodec, GetSize)]
 pub struct BlockHeader {
     pub version: BFieldElement,
     pub height: BlockHeight,
     pub prev_block_digest: Digest,
 
-    // TODO: Reject blocks that are more than 10 seconds into the future
     // number of milliseconds since unix epoch
     pub timestamp: Timestamp,
 
     // TODO: Consider making a type for `nonce`
     pub nonce: [BFieldElement; 3],
+    // maximum number of bytes in the serialized block
     pub max_block_size: u32,
 
     // use to compare two forks of different height
@@ -99,6 +109,59 @@ impl MastHash for BlockHeader {
     }
 }
 
+/// Computes the MAST hash of a block header for many nonces and timestamps,
+/// as the miner does, without rehashing the fields that remain the same.
+///
+/// The hasher keeps all nodes of the header's Merkle tree. Changing the nonce
+/// or the timestamp only recomputes the path from the changed leaf to the
+/// root, instead of the entire tree. The resulting digest is identical to
+/// [MastHash::mast_hash] of the header with the given nonce and timestamp.
+#[derive(Debug, Clone)]
+pub struct BlockHeaderHasher {
+    /// The nodes of the Merkle tree in the layout of a binary heap: the root
+    /// lives at index 1, and the children of node `i` at `2i` and `2i + 1`.
+    /// Index 0 is unused.
+    nodes: Vec<Digest>,
+}
+
+impl BlockHeaderHasher {
+    pub fn new(header: &BlockHeader) -> Self {
+        let mut leafs: Vec<Digest> = header
+            .mast_sequences()
+            .into_iter()
+            .map(|seq| Hash::hash_varlen(&seq))
+            .collect();
+
+        // pad until length is a power of two, like `MastHash` does
+        leafs.resize(leafs.len().next_power_of_two(), Digest::default());
+
+        let num_leafs = leafs.len();
+        let mut nodes = vec![Digest::default(); num_leafs];
+        nodes.extend(leafs);
+        for i in (1..num_leafs).rev() {
+            nodes[i] = Hash::hash_pair(nodes[2 * i], nodes[2 * i + 1]);
+        }
+
+        Self { nodes }
+    }
+
+    fn set_leaf(&mut self, field: BlockHeaderField, sequence: &[BFieldElement]) {
+        let mut i = self.nodes.len() / 2 + field.discriminant();
+        self.nodes[i] = Hash::hash_varlen(sequence);
+        while i > 1 {
+            i /= 2;
+            self.nodes[i] = Hash::hash_pair(self.nodes[2 * i], self.nodes[2 * i + 1]);
+        }
+    }
+
+    /// Return the MAST hash of the header with the given nonce and timestamp.
+    pub fn mast_hash(&mut self, nonce: [BFieldElement; 3], timestamp: Timestamp) -> Digest {
+        self.set_leaf(BlockHeaderField::Nonce, &nonce.encode());
+        self.set_leaf(BlockHeaderField::Timestamp, &timestamp.encode());
+        self.nodes[1]
+    }
+}
+
 #[cfg(test)]
 mod block_header_tests {
     use rand::{thread_rng, Rng};
@@ -126,4 +189,38 @@ mod block_header_tests {
         let decoded = *BlockHeader::decode(&encoded).unwrap();
         assert_eq!(block_header, decoded);
     }
+
+    #[test]
+    fn block_header_display_test() {
+        let block_header = random_block_header();
+        let displayed = block_header.to_string();
+        assert!(displayed.contains(&format!("Height: {}", block_header.height)));
+        assert!(displayed.contains(&format!(
+            "Proof-of-work-line: {}",
+            block_header.proof_of_work_line
+        )));
+        assert!(displayed.contains(&format!(
+            "Proof-of-work-family: {}",
+            block_header.proof_of_work_family
+        )));
+        assert!(!block_header.proof_of_work_line.to_string().is_empty());
+        assert!(!displayed.contains("IMPLEMENT"));
+    }
+
+    #[test]
+    pub fn block_header_hasher_agrees_with_mast_hash_test() {
+        let mut rng = thread_rng();
+        for _ in 0..10 {
+            let mut block_header = random_block_header();
+            let mut hasher = BlockHeaderHasher::new(&block_header);
+            for _ in 0..10 {
+                block_header.nonce = rng.gen();
+                block_header.timestamp = rng.gen();
+                assert_eq!(
+                    block_header.mast_hash(),
+                    hasher.mast_hash(block_header.nonce, block_header.timestamp)
+                );
+            }
+        }
+    }
 }
@@ -1,3 +1,4 @@
+use crate::config_models::network::Network;
 use crate::prelude::twenty_first;
 
 use get_size::GetSize;
@@ -19,9 +20,14 @@ pub struct BlockHeight(BFieldElement);
 // the number of blocks per halving cycle is 157680.
 pub const BLOCKS_PER_GENERATION: u64 = 157680;
 
+/// The number of blocks per halving cycle on RegTest, short enough for tests
+/// to mine across a halving.
+pub const REGTEST_BLOCKS_PER_GENERATION: u64 = 1_000;
+
 impl BlockHeight {
-    pub fn get_generation(&self) -> u64 {
-        self.0.value() / BLOCKS_PER_GENERATION
+    /// The number of halvings of the mining reward that precede this height.
+    pub fn get_generation(&self, network: Network) -> u64 {
+        self.0.value() / network.blocks_per_generation()
     }
 
     pub fn next(&self) -> Self {
@@ -3,6 +3,7 @@
 
 use super::block_header::PROOF_OF_WORK_COUNT_U32_SIZE;
 use super::block_header::TARGET_DIFFICULTY_U32_SIZE;
+use crate::config_models::network::Network;
 use crate::models::blockchain::block::block_height::BlockHeight;
 use crate::models::blockchain::block::Block;
 use crate::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
@@ -59,6 +60,7 @@ impl BlockInfo {
         block: &Block,
         genesis_digest: Digest,
         tip_digest: Digest,
+        network: Network,
     ) -> Self {
         let body = block.body();
         let header = block.header();
@@ -75,7 +77,7 @@ impl BlockInfo {
             num_outputs: body.transaction.kernel.outputs.len(),
             num_uncle_blocks: body.uncle_blocks.len(),
             fee: body.transaction.kernel.fee,
-            mining_reward: crate::Block::get_mining_reward(header.height),
+            mining_reward: crate::Block::get_mining_reward(header.height, network),
             is_genesis: digest == genesis_digest,
             is_tip: digest == tip_digest,
         }
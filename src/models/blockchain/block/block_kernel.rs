@@ -1,7 +1,15 @@
 use get_size::GetSize;
 use serde::{Deserialize, Serialize};
-use tasm_lib::twenty_first::math::{b_field_element::BFieldElement, bfield_codec::BFieldCodec};
+use tasm_lib::twenty_first::math::{
+    b_field_element::BFieldElement, bfield_codec::BFieldCodec, digest::Digest,
+};
+use tasm_lib::twenty_first::util_types::{
+    algebraic_hasher::AlgebraicHasher,
+    merkle_tree::{CpuParallel, MerkleTree},
+    merkle_tree_maker::MerkleTreeMaker,
+};
 
+use crate::models::blockchain::shared::Hash;
 use crate::models::consensus::mast_hash::{HasDiscriminant, MastHash};
 
 use super::{block_body::BlockBody, block_header::BlockHeader};
@@ -13,6 +21,17 @@ pub struct BlockKernel {
     pub body: BlockBody,
 }
 
+impl BlockKernel {
+    /// Compute the MAST hash of a kernel from the MAST hashes of its header
+    /// and body. Equivalent to, but cheaper than, [MastHash::mast_hash] when
+    /// the digest of the body is already known.
+    pub fn mast_hash_from_digests(header_digest: Digest, body_digest: Digest) -> Digest {
+        let leafs = [header_digest, body_digest].map(|digest| Hash::hash_varlen(&digest.encode()));
+        let tree: MerkleTree<Hash> = CpuParallel::from_digests(&leafs).unwrap();
+        tree.root()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum BlockKernelField {
     Header,
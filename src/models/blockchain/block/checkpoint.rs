@@ -0,0 +1,260 @@
+//! A checkpoint is a trusted snapshot of the mutator set accumulator after a
+//! given block, from which a new node can follow the chain without replaying
+//! the mutator set from genesis.
+
+use crate::config_models::network::Network;
+use crate::models::blockchain::block::block_height::BlockHeight;
+use crate::models::blockchain::block::transfer_block::TransferBlock;
+use crate::models::blockchain::block::Block;
+use crate::models::blockchain::shared::Hash;
+use crate::prelude::twenty_first;
+use crate::util_types::mutator_set::chunk::Chunk;
+use crate::util_types::mutator_set::mutator_set_accumulator::MutatorSetAccumulator;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use twenty_first::math::bfield_codec::BFieldCodec;
+use twenty_first::math::digest::Digest;
+use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
+
+/// The mutator set accumulator after the block with digest `block_digest` at
+/// height `height`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, BFieldCodec)]
+pub struct Checkpoint {
+    pub height: BlockHeight,
+    pub mutator_set_accumulator: MutatorSetAccumulator,
+    pub block_digest: Digest,
+}
+
+impl Checkpoint {
+    pub fn from_block(block: &Block) -> Self {
+        Self {
+            height: block.kernel.header.height,
+            mutator_set_accumulator: block.kernel.body.mutator_set_accumulator.clone(),
+            block_digest: block.hash(),
+        }
+    }
+
+    pub fn hash(&self) -> Digest {
+        Hash::hash(self)
+    }
+
+    /// Fail unless this checkpoint hashes to the trusted hash.
+    pub fn verify(&self, trusted_hash: Digest) -> Result<()> {
+        let hash = self.hash();
+        if hash != trusted_hash {
+            bail!(
+                "Checkpoint at height {} has hash {hash}, expected {trusted_hash}",
+                self.height
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The hash of the trusted checkpoint of the network, if one has been
+    /// published. RegTest trusts the checkpoint of its genesis block, such
+    /// that checkpoints can be tried out locally. Other checkpoints can be
+    /// trusted through the `--checkpoint-hash` argument.
+    pub fn trusted_hash(network: Network) -> Option<Digest> {
+        match network {
+            Network::RegTest => Some(Self::from_block(&Block::genesis_block(network)).hash()),
+
+            // No checkpoints have been published yet
+            Network::Alpha | Network::Beta | Network::Main | Network::Testnet => None,
+        }
+    }
+
+    /// Advance the checkpoint by the child of its block, applying the block's
+    /// mutator set update to the accumulator. Fails if the block does not
+    /// build on the checkpoint, or if its mutator set accumulator does not
+    /// match the result.
+    pub fn apply_block(&mut self, block: &Block) -> Result<()> {
+        if block.kernel.header.prev_block_digest != self.block_digest
+            || block.kernel.header.height != self.height.next()
+        {
+            bail!(
+                "Block {} at height {} is not a child of the checkpoint",
+                block.hash(),
+                block.kernel.header.height
+            );
+        }
+        if !block.verify_mutator_set_transition(&self.mutator_set_accumulator) {
+            bail!(
+                "Block {} does not apply to the checkpoint's mutator set",
+                block.hash()
+            );
+        }
+
+        *self = Self::from_block(block);
+
+        Ok(())
+    }
+}
+
+/// A checkpoint along with what a node needs to start from it rather than
+/// from genesis: the checkpointed block, and the contents of the archival
+/// mutator set after that block. Only the checkpoint has to be trusted, since
+/// the block and the contents are verified against it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointSnapshot {
+    pub checkpoint: Checkpoint,
+    pub block: TransferBlock,
+    pub aocl_leafs: Vec<Digest>,
+    pub chunks: Vec<Chunk>,
+}
+
+impl CheckpointSnapshot {
+    /// Read a snapshot from disk, and verify its checkpoint against the
+    /// trusted checkpoint hash.
+    pub async fn load(path: &Path, trusted_hash: Digest) -> Result<Self> {
+        let bytes = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read checkpoint file {}", path.display()))?;
+        let snapshot: Self = bincode::deserialize(&bytes)
+            .with_context(|| format!("Failed to decode checkpoint file {}", path.display()))?;
+        snapshot.checkpoint.verify(trusted_hash)?;
+
+        Ok(snapshot)
+    }
+
+    pub async fn write_to_file(&self, path: &Path) -> Result<()> {
+        let bytes = bincode::serialize(self)?;
+        tokio::fs::write(path, bytes)
+            .await
+            .with_context(|| format!("Failed to write checkpoint file {}", path.display()))
+    }
+
+    /// The checkpointed block. Fails if the block is not the one that the
+    /// checkpoint commits to.
+    pub fn block(&self) -> Result<Block> {
+        let block = self
+            .block
+            .clone()
+            .into_block_with_digest(self.checkpoint.block_digest)?;
+        if Checkpoint::from_block(&block) != self.checkpoint {
+            bail!(
+                "Block {} does not match the checkpoint",
+                self.checkpoint.block_digest
+            );
+        }
+
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::random;
+
+    use crate::models::blockchain::block::mutator_set_update::MutatorSetUpdate;
+    use crate::models::state::wallet::WalletSecret;
+    use crate::tests::shared::make_mock_block;
+
+    #[test]
+    fn checkpoint_then_one_block_matches_full_replay_test() {
+        let network = Network::RegTest;
+        let address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let genesis_block = Block::genesis_block(network);
+        let mut blocks = vec![genesis_block];
+        for _ in 0..3 {
+            let (block, _, _) =
+                make_mock_block(network, blocks.last().unwrap(), None, address, random());
+            blocks.push(block);
+        }
+
+        // Replay the mutator set from genesis
+        let mut replayed_accumulator = MutatorSetAccumulator::default();
+        for block in blocks.iter() {
+            let transaction_kernel = &block.kernel.body.transaction.kernel;
+            MutatorSetUpdate::new(
+                transaction_kernel.inputs.clone(),
+                transaction_kernel.outputs.clone(),
+            )
+            .apply_to_accumulator(&mut replayed_accumulator)
+            .unwrap();
+        }
+
+        // Start from the checkpoint of the second to last block
+        let mut checkpoint = Checkpoint::from_block(&blocks[2]);
+        checkpoint.verify(checkpoint.hash()).unwrap();
+        checkpoint.apply_block(&blocks[3]).unwrap();
+
+        assert_eq!(replayed_accumulator, checkpoint.mutator_set_accumulator);
+        assert_eq!(Checkpoint::from_block(&blocks[3]), checkpoint);
+    }
+
+    #[test]
+    fn checkpoint_rejects_foreign_blocks_and_hashes_test() {
+        let network = Network::RegTest;
+        let address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let genesis_block = Block::genesis_block(network);
+        let (block_1, _, _) = make_mock_block(network, &genesis_block, None, address, random());
+        let (block_2, _, _) = make_mock_block(network, &block_1, None, address, random());
+
+        // Blocks must build on the checkpoint
+        let mut checkpoint = Checkpoint::from_block(&genesis_block);
+        assert!(checkpoint.apply_block(&block_2).is_err());
+        assert_eq!(Checkpoint::from_block(&genesis_block), checkpoint);
+
+        // The accumulator must match that of the block
+        let mut tampered_checkpoint = Checkpoint::from_block(&block_1);
+        tampered_checkpoint.mutator_set_accumulator = MutatorSetAccumulator::default();
+        assert!(tampered_checkpoint.apply_block(&block_2).is_err());
+
+        // A tampered checkpoint no longer matches the trusted hash
+        let trusted_hash = Checkpoint::from_block(&block_1).hash();
+        assert!(tampered_checkpoint.verify(trusted_hash).is_err());
+        let decoded: Checkpoint =
+            bincode::deserialize(&bincode::serialize(&Checkpoint::from_block(&block_1)).unwrap())
+                .unwrap();
+        decoded.verify(trusted_hash).unwrap();
+    }
+
+    #[tokio::test]
+    async fn snapshot_is_loaded_only_if_trusted_test() {
+        let network = Network::RegTest;
+        let address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let genesis_block = Block::genesis_block(network);
+        let (block_1, _, _) = make_mock_block(network, &genesis_block, None, address, random());
+        let snapshot = |block: &Block| CheckpointSnapshot {
+            checkpoint: Checkpoint::from_block(block),
+            block: block.clone().into(),
+            aocl_leafs: vec![],
+            chunks: vec![],
+        };
+
+        let path = std::env::temp_dir().join(format!("checkpoint-{}", random::<u64>()));
+        let genesis_snapshot = snapshot(&genesis_block);
+        genesis_snapshot.write_to_file(&path).await.unwrap();
+        let trusted_hash = Checkpoint::trusted_hash(network).unwrap();
+        let loaded_snapshot = CheckpointSnapshot::load(&path, trusted_hash).await.unwrap();
+        assert_eq!(genesis_snapshot, loaded_snapshot);
+        assert_eq!(
+            genesis_block.hash(),
+            loaded_snapshot.block().unwrap().hash()
+        );
+
+        // Networks without a published checkpoint trust none
+        assert!(Checkpoint::trusted_hash(Network::Alpha).is_none());
+
+        // Checkpoints other than the trusted one are rejected
+        snapshot(&block_1).write_to_file(&path).await.unwrap();
+        assert!(CheckpointSnapshot::load(&path, trusted_hash).await.is_err());
+
+        // So are blocks other than the checkpointed one
+        let mut mismatched_snapshot = snapshot(&genesis_block);
+        mismatched_snapshot.block = block_1.into();
+        assert!(mismatched_snapshot.block().is_err());
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
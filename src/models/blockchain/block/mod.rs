@@ -10,7 +10,7 @@ use num_bigint::BigUint;
 use num_traits::{abs, Zero};
 
 use serde::{Deserialize, Serialize};
-use std::cmp::max;
+use std::cmp::{max, min};
 use std::sync::OnceLock;
 use tasm_lib::triton_vm::proof::Proof;
 use tasm_lib::twenty_first::util_types::mmr::mmr_accumulator::MmrAccumulator;
@@ -31,13 +31,14 @@ pub mod block_height;
 pub mod block_info;
 pub mod block_kernel;
 pub mod block_selector;
+pub mod checkpoint;
 pub mod mutator_set_update;
 pub mod transfer_block;
 pub mod validity;
 
 use self::block_body::BlockBody;
 use self::block_header::{
-    BlockHeader, MINIMUM_DIFFICULTY, TARGET_BLOCK_INTERVAL, TARGET_DIFFICULTY_U32_SIZE,
+    BlockHeader, MEDIAN_TIMESTAMP_BLOCK_COUNT, MINIMUM_DIFFICULTY, TARGET_DIFFICULTY_U32_SIZE,
 };
 use self::block_height::BlockHeight;
 use self::block_kernel::BlockKernel;
@@ -50,11 +51,26 @@ use super::transaction::Transaction;
 use super::type_scripts::neptune_coins::NeptuneCoins;
 use super::type_scripts::time_lock::TimeLock;
 use crate::models::blockchain::shared::Hash;
+use crate::models::state::header_chain::validate_child_header;
 use crate::models::state::wallet::address::generation_address::{self, ReceivingAddress};
 use crate::models::state::wallet::WalletSecret;
 use crate::util_types::mutator_set::commit;
 use crate::util_types::mutator_set::mutator_set_accumulator::MutatorSetAccumulator;
 
+/// Maximum number of bytes in the serialization of a block, on all networks.
+pub const MAX_BLOCK_SIZE: u32 = 1_000_000;
+
+/// Maximum number of uncles a block may reference.
+pub const MAX_UNCLES_PER_BLOCK: usize = 2;
+
+/// Uncles must be children of one of this many most recent ancestors of the
+/// block referencing them, excluding its parent.
+pub const MAX_UNCLE_GENERATIONS: usize = 6;
+
+/// The reward for referencing an uncle is the mining reward halved this many
+/// times.
+const UNCLE_REWARD_HALVINGS: usize = 3;
+
 /// All blocks have proofs except the genesis block
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, BFieldCodec, GetSize)]
 pub enum BlockType {
@@ -62,6 +78,33 @@ pub enum BlockType {
     Standard(ProofType),
 }
 
+/// A recent ancestor of a block, with what is needed to verify the uncles
+/// that the block references. See [Block::has_valid_uncles].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecentAncestor {
+    pub digest: Digest,
+    pub header: BlockHeader,
+
+    /// The digests of the uncles that the ancestor references
+    pub uncles: Vec<Digest>,
+}
+
+impl From<&Block> for RecentAncestor {
+    fn from(block: &Block) -> Self {
+        Self {
+            digest: block.hash(),
+            header: block.kernel.header.clone(),
+            uncles: block
+                .kernel
+                .body
+                .uncle_blocks
+                .iter()
+                .map(|uncle| uncle.digest())
+                .collect(),
+        }
+    }
+}
+
 /// Public fields of `Block` are read-only, enforced by #[readonly::make].
 /// Modifications are possible only through `Block` methods.
 ///
@@ -114,6 +157,10 @@ pub enum BlockType {
 // We likewise skip the field for `BFieldCodec`, and `GetSize` because there
 // exist no impls for `OnceLock<_>` so derive fails.
 //
+// The `body_digest` field caches the digest of the body in the same way. It
+// must be reset whenever the body is modified, but not when only the header
+// is, which saves recomputing the body digest for every nonce while mining.
+//
 // A unit test-suite exists in module tests::digest_encapsulation.
 #[readonly::make]
 #[derive(Clone, Debug, Serialize, Deserialize, BFieldCodec, GetSize)]
@@ -130,6 +177,13 @@ pub struct Block {
     #[bfield_codec(ignore)]
     #[get_size(ignore)]
     digest: OnceLock<Digest>,
+
+    // likewise for the digest of the body, which is expensive to compute and
+    // which, unlike the block digest, survives changes to the header.
+    #[serde(skip)]
+    #[bfield_codec(ignore)]
+    #[get_size(ignore)]
+    body_digest: OnceLock<Digest>,
 }
 
 impl PartialEq for Block {
@@ -151,7 +205,8 @@ impl From<TransferBlock> for Block {
             body: t_block.body,
         };
         Self {
-            digest: Default::default(), // calc'd in hash()
+            digest: Default::default(),      // calc'd in hash()
+            body_digest: Default::default(), // calc'd in hash()
             kernel,
             block_type: BlockType::Standard(t_block.proof_type),
         }
@@ -184,15 +239,39 @@ impl Block {
     /// will not recompute it unless the Block was modified since the last call.
     #[inline]
     pub fn hash(&self) -> Digest {
-        *self.digest.get_or_init(|| self.kernel.mast_hash())
+        *self.digest.get_or_init(|| {
+            BlockKernel::mast_hash_from_digests(
+                self.kernel.header.mast_hash(),
+                *self
+                    .body_digest
+                    .get_or_init(|| self.kernel.body.mast_hash()),
+            )
+        })
+    }
+
+    /// The number of bytes in the canonical (bincode) serialization of the
+    /// block, i.e., the size it occupies on the wire and on disk. This is what
+    /// the header's `max_block_size` limits, which must be that of the network.
+    pub fn serialized_size(&self) -> usize {
+        bincode::serialized_size(self).unwrap() as usize
     }
 
+    /// Invalidate the block digest, but not that of the body. Must be called
+    /// after modifying the header.
     #[inline]
     fn unset_digest(&mut self) {
         // note: this replaces the OnceLock so the digest will be calc'd in hash()
         self.digest = Default::default();
     }
 
+    /// Invalidate the block digest and that of the body. Must be called after
+    /// modifying the body.
+    #[inline]
+    fn unset_digests(&mut self) {
+        self.body_digest = Default::default();
+        self.unset_digest();
+    }
+
     /// sets header header nonce.
     ///
     /// note: this causes block digest to change.
@@ -211,6 +290,28 @@ impl Block {
         self.unset_digest();
     }
 
+    /// Drop the witness of the block's transaction, retaining its kernel.
+    ///
+    /// note: the block digest does not change, as it only commits to the
+    /// transaction kernel.
+    pub fn strip_transaction_witness(&mut self) {
+        self.kernel.body.transaction.witness = TransactionValidationLogic {
+            vast: ValidityTree::none(),
+            maybe_primitive_witness: None,
+        };
+    }
+
+    /// Determine whether the witness of the block's transaction was dropped.
+    pub fn has_stripped_transaction_witness(&self) -> bool {
+        self.kernel
+            .body
+            .transaction
+            .witness
+            .maybe_primitive_witness
+            .is_none()
+            && self.kernel.body.transaction.witness.vast == ValidityTree::none()
+    }
+
     #[inline]
     pub fn header(&self) -> &BlockHeader {
         &self.kernel.header
@@ -227,11 +328,18 @@ impl Block {
         self.kernel.header = block.kernel.header;
         self.kernel.body = block.kernel.body;
         self.digest = block.digest;
+        self.body_digest = block.body_digest;
     }
 
-    pub fn get_mining_reward(block_height: BlockHeight) -> NeptuneCoins {
+    /// The mining reward of the block at the given height, excluding fees and
+    /// rewards for uncles. The reward is halved every
+    /// [`Network::blocks_per_generation`] blocks.
+    pub fn get_mining_reward(block_height: BlockHeight, network: Network) -> NeptuneCoins {
         let mut reward: NeptuneCoins = NeptuneCoins::new(100);
-        let generation = block_height.get_generation();
+
+        // The reward is zero long before the number of halvings reaches the
+        // bit width of the amount, so there is no need to halve any further
+        let generation = min(block_height.get_generation(network), u128::BITS as u64);
         for _ in 0..generation {
             reward.div_two()
         }
@@ -239,6 +347,64 @@ impl Block {
         reward
     }
 
+    /// The additional mining reward for each uncle referenced by the block at
+    /// the given height.
+    pub fn get_uncle_reward(block_height: BlockHeight, network: Network) -> NeptuneCoins {
+        let mut reward = Self::get_mining_reward(block_height, network);
+        for _ in 0..UNCLE_REWARD_HALVINGS {
+            reward.div_two()
+        }
+
+        reward
+    }
+
+    /// Return the sum of the mining rewards of all blocks from height 1 up to
+    /// and including `up_to_height`, following the halving schedule of
+    /// [`Self::get_mining_reward`]. The genesis block carries the premine rather
+    /// than a mining reward, so it does not contribute. Rewards for uncles,
+    /// see [`Self::get_uncle_reward`], are not included.
+    pub fn cumulative_supply(up_to_height: BlockHeight, network: Network) -> NeptuneCoins {
+        let up_to_height: u64 = up_to_height.into();
+        let mut supply = NeptuneCoins::zero();
+
+        // Sum one generation at a time, as the reward is constant within a generation
+        let mut height = 1u64;
+        while height <= up_to_height {
+            let reward = Self::get_mining_reward(height.into(), network);
+            if reward.is_zero() {
+                break;
+            }
+
+            let blocks_per_generation = network.blocks_per_generation();
+            let last_height_in_generation =
+                (height / blocks_per_generation + 1) * blocks_per_generation - 1;
+            let last_height = min(last_height_in_generation, up_to_height);
+            let num_blocks = last_height - height + 1;
+            supply = supply + reward.scalar_mul(num_blocks as u32);
+
+            height = last_height + 1;
+        }
+
+        supply
+    }
+
+    /// Return the largest coinbase that the transaction of a block at the
+    /// given height may claim: the mining reward, plus the reward for each
+    /// referenced uncle, plus the fees of the transactions included in the
+    /// block. Returns `None` if the sum does not fit in a non-negative amount,
+    /// which is never the case for valid fees.
+    pub fn maximum_coinbase(
+        block_height: BlockHeight,
+        network: Network,
+        uncle_count: usize,
+        fee: NeptuneCoins,
+    ) -> Option<NeptuneCoins> {
+        let uncle_count = u32::try_from(uncle_count).ok()?;
+        Self::get_mining_reward(block_height, network)
+            .safe_add(Self::get_uncle_reward(block_height, network).scalar_mul(uncle_count))?
+            .safe_add(fee)
+    }
+
     pub fn genesis_block(network: Network) -> Self {
         let mut genesis_mutator_set = MutatorSetAccumulator::default();
         let mut ms_update = MutatorSetUpdate::default();
@@ -348,7 +514,8 @@ impl Block {
     pub fn new(header: BlockHeader, body: BlockBody, block_type: BlockType) -> Self {
         let kernel = BlockKernel { body, header };
         Self {
-            digest: Default::default(), // calc'd in hash()
+            digest: Default::default(),      // calc'd in hash()
+            body_digest: Default::default(), // calc'd in hash()
             kernel,
             block_type,
         }
@@ -427,13 +594,47 @@ impl Block {
 
         self.kernel.body = block_body;
         self.kernel.header = block_header;
-        self.unset_digest();
+        self.unset_digests();
+    }
+
+    /// Verify that applying the removal and addition records of the block's
+    /// transaction to the given mutator set accumulator, which must be that of
+    /// the previous block, yields exactly the block's mutator set accumulator.
+    pub fn verify_mutator_set_transition(
+        &self,
+        previous_mutator_set_accumulator: &MutatorSetAccumulator,
+    ) -> bool {
+        // Construct all the addition records for all the transaction outputs. Then
+        // use these addition records to insert into the mutator set.
+        let mutator_set_update = MutatorSetUpdate::new(
+            self.kernel.body.transaction.kernel.inputs.clone(),
+            self.kernel.body.transaction.kernel.outputs.clone(),
+        );
+        let mut ms = previous_mutator_set_accumulator.clone();
+        if let Err(err) = mutator_set_update.apply_to_accumulator(&mut ms) {
+            warn!("Failed to apply mutator set update: {}", err);
+            return false;
+        }
+
+        // Verify that the locally constructed mutator set matches that in the
+        // block's body.
+        if ms.hash() != self.kernel.body.mutator_set_accumulator.hash() {
+            warn!("Reported mutator set does not match calculated object.");
+            debug!(
+                "From Block\n{:?}. \n\n\nCalculated\n{:?}",
+                self.kernel.body.mutator_set_accumulator, ms
+            );
+            return false;
+        }
+
+        true
     }
 
     /// Verify a block. It is assumed that `previous_block` is valid.
-    /// Note that this function does **not** check that the PoW digest is below the threshold.
-    /// That must be done separately by the caller.
-    pub(crate) fn is_valid(&self, previous_block: &Block, now: Timestamp) -> bool {
+    /// Note that this function does **not** check that the PoW digest is below the threshold,
+    /// nor the timestamp against the clock and the blocks before `previous_block`. That must be
+    /// done separately by the caller, the latter with [Block::has_valid_timestamp].
+    pub(crate) fn is_valid(&self, previous_block: &Block, network: Network) -> bool {
         // The block value doesn't actually change. Some function calls just require
         // mutable references because that's how the interface was defined for them.
         let block_copy = self.to_owned();
@@ -445,7 +646,7 @@ impl Block {
         //   b) Block header points to previous block
         //   d) Block timestamp is greater than previous block timestamp
         //   e) Target difficulty, and other control parameters, were adjusted correctly
-        //   f) Block timestamp is less than host-time (utc) + 2 hours.
+        //   f) Block declares the maximum size of the network and does not exceed it
         // 1. The transaction is valid.
         // 1'. All transactions are valid.
         //   a) verify that MS membership proof is valid, done against previous `mutator_set_accumulator`,
@@ -494,16 +695,32 @@ impl Block {
 
         // 0.e) Target difficulty, and other control parameters, were updated correctly
         if block_copy.kernel.header.difficulty
-            != Self::difficulty_control(previous_block, block_copy.kernel.header.timestamp)
+            != Self::difficulty_control(
+                previous_block,
+                block_copy.kernel.header.timestamp,
+                network.target_block_interval(),
+                network.max_difficulty_adjustment_factor(),
+            )
         {
             warn!("Value for new difficulty is incorrect.");
             return false;
         }
 
-        // 0.f) Block timestamp is less than host-time (utc) + 2 hours.
-        let future_limit = now + Timestamp::hours(2);
-        if block_copy.kernel.header.timestamp >= future_limit {
-            warn!("block time is too far in the future");
+        // 0.f) Block declares the maximum size of the network and does not exceed it
+        if block_copy.kernel.header.max_block_size != network.max_block_size() {
+            warn!(
+                "Block declares a maximum size of {} bytes instead of {} bytes",
+                block_copy.kernel.header.max_block_size,
+                network.max_block_size()
+            );
+            return false;
+        }
+        let block_size = block_copy.serialized_size();
+        if block_size > block_copy.kernel.header.max_block_size as usize {
+            warn!(
+                "Block size ({block_size} bytes) exceeds the declared maximum of {} bytes",
+                block_copy.kernel.header.max_block_size
+            );
             return false;
         }
 
@@ -540,30 +757,9 @@ impl Block {
 
         // 1.d) Verify that the two mutator sets, the one from the current block and the
         // one from the previous, are consistent with the transactions.
-        // Construct all the addition records for all the transaction outputs. Then
-        // use these addition records to insert into the mutator set.
-        let mutator_set_update = MutatorSetUpdate::new(
-            block_copy.kernel.body.transaction.kernel.inputs.clone(),
-            block_copy.kernel.body.transaction.kernel.outputs.clone(),
-        );
-        let mut ms = previous_block.kernel.body.mutator_set_accumulator.clone();
-        let ms_update_result = mutator_set_update.apply_to_accumulator(&mut ms);
-        match ms_update_result {
-            Ok(()) => (),
-            Err(err) => {
-                warn!("Failed to apply mutator set update: {}", err);
-                return false;
-            }
-        };
-
-        // Verify that the locally constructed mutator set matches that in the received
-        // block's body.
-        if ms.hash() != block_copy.kernel.body.mutator_set_accumulator.hash() {
-            warn!("Reported mutator set does not match calculated object.");
-            debug!(
-                "From Block\n{:?}. \n\n\nCalculated\n{:?}",
-                block_copy.kernel.body.mutator_set_accumulator, ms
-            );
+        if !block_copy
+            .verify_mutator_set_transition(&previous_block.kernel.body.mutator_set_accumulator)
+        {
             return false;
         }
 
@@ -579,9 +775,32 @@ impl Block {
         }
 
         // 1.f) Verify that the coinbase claimed by the transaction does not exceed
-        // the allowed coinbase based on block height, epoch, etc., and fee
-        let miner_reward: NeptuneCoins = Self::get_mining_reward(block_copy.kernel.header.height)
-            + self.kernel.body.transaction.kernel.fee;
+        // the allowed coinbase based on block height, epoch, etc., uncles, and fee
+        let uncle_count = block_copy.kernel.body.uncle_blocks.len();
+        if uncle_count > MAX_UNCLES_PER_BLOCK {
+            warn!("Block references {uncle_count} uncles, more than allowed");
+            return false;
+        }
+        if !block_copy
+            .kernel
+            .body
+            .uncle_blocks
+            .iter()
+            .map(|uncle| uncle.digest())
+            .all_unique()
+        {
+            warn!("Block references the same uncle more than once");
+            return false;
+        }
+        let Some(miner_reward) = Self::maximum_coinbase(
+            block_copy.kernel.header.height,
+            network,
+            uncle_count,
+            block_copy.kernel.body.transaction.kernel.fee,
+        ) else {
+            warn!("Block is invalid because its fee is negative or overflows the miner reward.");
+            return false;
+        };
         if let Some(claimed_reward) = block_copy.kernel.body.transaction.kernel.coinbase {
             if claimed_reward > miner_reward {
                 warn!("Block is invalid because the claimed miner reward is too high relative to current network parameters.");
@@ -614,6 +833,109 @@ impl Block {
         true
     }
 
+    /// Verify the uncles referenced by the block, which [Self::is_valid] cannot
+    /// do since it only knows the parent. `ancestors` are the most recent
+    /// ancestors of the block, newest first, starting with its parent.
+    ///
+    /// An uncle must validly extend one of the ancestors within
+    /// [MAX_UNCLE_GENERATIONS] generations, excluding the parent of the block,
+    /// with enough proof-of-work. It must not be an ancestor, and must not have
+    /// been referenced as an uncle by those ancestors. Since uncles carry their
+    /// headers, this does not depend on which stale blocks a node has seen.
+    pub fn has_valid_uncles(
+        &self,
+        ancestors: &[RecentAncestor],
+        now: Timestamp,
+        network: Network,
+    ) -> bool {
+        let ancestors = &ancestors[..min(ancestors.len(), MAX_UNCLE_GENERATIONS + 1)];
+        for uncle in self.kernel.body.uncle_blocks.iter() {
+            let uncle_digest = uncle.digest();
+            if ancestors
+                .iter()
+                .any(|ancestor| ancestor.digest == uncle_digest)
+            {
+                warn!("Uncle {uncle_digest} is an ancestor");
+                return false;
+            }
+            let Some(uncle_parent) = ancestors
+                .iter()
+                .skip(1)
+                .find(|ancestor| ancestor.digest == uncle.header.prev_block_digest)
+            else {
+                warn!("Uncle {uncle_digest} is not the child of a recent ancestor");
+                return false;
+            };
+            if let Err(error) = validate_child_header(
+                uncle_parent.digest,
+                &uncle_parent.header,
+                uncle_digest,
+                &uncle.header,
+                now,
+                network,
+            ) {
+                warn!("Uncle {uncle_digest} has an invalid header: {error}");
+                return false;
+            }
+            if ancestors
+                .iter()
+                .any(|ancestor| ancestor.uncles.contains(&uncle_digest))
+            {
+                warn!("Uncle {uncle_digest} was referenced before");
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Verify the block's timestamp against the local clock and the blocks
+    /// before it, whose timestamps are given most recent first. The timestamp
+    /// may be ahead of `now` by at most `max_drift`, to tolerate clock skew.
+    pub(crate) fn has_valid_timestamp(
+        &self,
+        recent_timestamps: &[Timestamp],
+        now: Timestamp,
+        max_drift: Timestamp,
+    ) -> bool {
+        let timestamp = self.kernel.header.timestamp;
+        if timestamp > now + max_drift {
+            warn!(
+                "Block's timestamp ({}) is more than {} ms ahead of the local time ({})",
+                timestamp,
+                max_drift.0.value(),
+                now
+            );
+            return false;
+        }
+
+        if let Some(median) = Self::median_timestamp(recent_timestamps) {
+            if timestamp <= median {
+                warn!(
+                    "Block's timestamp ({}) is not later than the median timestamp ({}) of the preceding blocks",
+                    timestamp, median
+                );
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// The median of the timestamps of the [MEDIAN_TIMESTAMP_BLOCK_COUNT] most
+    /// recent blocks, given most recent first, or None if there are none. The
+    /// timestamp of the next block must be greater.
+    pub fn median_timestamp(recent_timestamps: &[Timestamp]) -> Option<Timestamp> {
+        let mut sorted_timestamps = recent_timestamps
+            .iter()
+            .take(MEDIAN_TIMESTAMP_BLOCK_COUNT)
+            .copied()
+            .collect_vec();
+        sorted_timestamps.sort();
+
+        sorted_timestamps.get(sorted_timestamps.len() / 2).copied()
+    }
+
     /// Determine if the the proof-of-work puzzle was solved correctly. Specifically,
     /// compare the hash of the current block against the difficulty determined by
     /// the previous.
@@ -637,36 +959,79 @@ impl Block {
 
     /// Control system for block difficulty. This function computes the new block's
     /// difficulty from its timestamp and the previous block. It is a PID controller
-    /// (with i=d=0) regulating the block interval by tuning the difficulty.
-    /// We assume that the block timestamp is valid.
+    /// (with i=d=0) regulating the block interval towards `target_block_interval`
+    /// by tuning the difficulty, which changes by at most `max_adjustment_factor`
+    /// per block. We assume that the block timestamp is valid.
     pub fn difficulty_control(
         old_block: &Block,
         new_timestamp: Timestamp,
+        target_block_interval: Timestamp,
+        max_adjustment_factor: u32,
     ) -> U32s<TARGET_DIFFICULTY_U32_SIZE> {
-        // no adjustment if the previous block is the genesis block
-        if old_block.kernel.header.height.is_genesis() {
-            return old_block.kernel.header.difficulty;
-        }
+        Self::difficulty_control_from_header(
+            &old_block.kernel.header,
+            new_timestamp,
+            target_block_interval,
+            max_adjustment_factor,
+        )
+    }
 
-        // otherwise, compute PID control signal
-        let t = new_timestamp - old_block.kernel.header.timestamp;
+    /// Like [Block::difficulty_control], but from the header of the previous
+    /// block only, such that headers can be validated without their bodies.
+    pub fn difficulty_control_from_header(
+        old_header: &BlockHeader,
+        new_timestamp: Timestamp,
+        target_block_interval: Timestamp,
+        max_adjustment_factor: u32,
+    ) -> U32s<TARGET_DIFFICULTY_U32_SIZE> {
+        // No adjustment if the previous block is the genesis block. Its
+        // timestamp is the network's launch date, so the time until the first
+        // block says nothing about the hash rate.
+        if old_header.height.is_genesis() {
+            return old_header.difficulty;
+        }
 
-        let new_error = t.0.value() as i64 - TARGET_BLOCK_INTERVAL as i64;
+        // otherwise, compute PID control signal. A timestamp before that of
+        // the previous block counts as an interval of zero.
+        let t = new_timestamp
+            .0
+            .value()
+            .saturating_sub(old_header.timestamp.0.value());
+        let new_error = t as i64 - target_block_interval.0.value() as i64;
 
         let adjustment = -new_error / 100;
-        let absolute_adjustment = abs(adjustment) as u64;
-        let adjustment_is_positive = adjustment >= 0;
-        let adj_hi = (absolute_adjustment >> 32) as u32;
-        let adj_lo = absolute_adjustment as u32;
-        let adjustment_u32s =
-            U32s::<TARGET_DIFFICULTY_U32_SIZE>::new([adj_lo, adj_hi, 0u32, 0u32, 0u32]);
-        if adjustment_is_positive {
-            old_block.kernel.header.difficulty + adjustment_u32s
-        } else if adjustment_u32s > old_block.kernel.header.difficulty - MINIMUM_DIFFICULTY.into() {
-            MINIMUM_DIFFICULTY.into()
+        let absolute_adjustment = BigUint::from(abs(adjustment) as u64);
+        let old_difficulty: BigUint = old_header.difficulty.into();
+        let unclamped_difficulty = if adjustment >= 0 {
+            old_difficulty.clone() + absolute_adjustment
+        } else if absolute_adjustment < old_difficulty {
+            old_difficulty.clone() - absolute_adjustment
         } else {
-            old_block.kernel.header.difficulty - adjustment_u32s
+            BigUint::zero()
+        };
+
+        // Bound the change per block, such that manipulated timestamps can
+        // neither collapse nor explode the difficulty
+        let lower_bound = max(
+            old_difficulty.clone() / max_adjustment_factor,
+            BigUint::from(MINIMUM_DIFFICULTY),
+        );
+        let upper_bound = old_difficulty * max_adjustment_factor;
+        let new_difficulty = min(max(unclamped_difficulty, lower_bound), upper_bound);
+
+        Self::biguint_to_difficulty(new_difficulty)
+    }
+
+    /// Convert a difficulty to [U32s], saturating if it does not fit.
+    fn biguint_to_difficulty(difficulty: BigUint) -> U32s<TARGET_DIFFICULTY_U32_SIZE> {
+        let limbs = difficulty.to_u32_digits();
+        if limbs.len() > TARGET_DIFFICULTY_U32_SIZE {
+            return U32s::new([u32::MAX; TARGET_DIFFICULTY_U32_SIZE]);
         }
+
+        let mut difficulty_limbs = [0u32; TARGET_DIFFICULTY_U32_SIZE];
+        difficulty_limbs[..limbs.len()].copy_from_slice(&limbs);
+        U32s::new(difficulty_limbs)
     }
 }
 
@@ -678,19 +1043,22 @@ mod block_tests {
         database::storage::storage_schema::SimpleRustyStorage,
         database::NeptuneLevelDb,
         models::{
-            blockchain::transaction::PublicAnnouncement, state::wallet::WalletSecret,
-            state::UtxoReceiverData,
+            blockchain::block::block_body::UncleBlock, blockchain::transaction::PublicAnnouncement,
+            state::wallet::WalletSecret, state::UtxoReceiverData,
         },
         tests::shared::{
             make_mock_block, make_mock_block_with_valid_pow, mock_genesis_global_state,
         },
+        util_types::mutator_set::addition_record::pseudorandom_addition_record,
         util_types::mutator_set::archival_mmr::ArchivalMmr,
     };
     use strum::IntoEnumIterator;
 
+    use super::block_header::MAX_DIFFICULTY_ADJUSTMENT_FACTOR;
     use super::*;
 
-    use rand::{random, thread_rng, Rng};
+    use rand::{random, seq::SliceRandom, thread_rng, Rng};
+    use test_strategy::proptest;
     use tracing_test::traced_test;
 
     async fn merge_transaction() -> (Block, Block, Block) {
@@ -713,11 +1081,11 @@ mod block_tests {
             .to_address();
         let genesis_block = Block::genesis_block(network);
 
-        let (block_1, _, _) = make_mock_block(&genesis_block, None, address, rng.gen());
+        let (block_1, _, _) = make_mock_block(network, &genesis_block, None, address, rng.gen());
         let now = genesis_block.kernel.header.timestamp;
         let seven_months = Timestamp::months(7);
         assert!(
-            block_1.is_valid(&genesis_block, now),
+            block_1.is_valid(&genesis_block, network),
             "Block 1 must be valid with only coinbase output"
         );
 
@@ -753,12 +1121,11 @@ mod block_tests {
     #[traced_test]
     #[tokio::test]
     async fn merge_transaction_test() {
+        let network = Network::RegTest;
         let (genesis_block, _, block_1) = merge_transaction().await;
-        let now = genesis_block.kernel.header.timestamp;
-        let seven_months = Timestamp::months(7);
 
         assert!(
-            block_1.is_valid(&genesis_block, now + seven_months),
+            block_1.is_valid(&genesis_block, network),
             "Block 1 must be valid after adding a transaction; previous mutator set hash: {} and next mutator set hash: {}",
             genesis_block.kernel
                 .body
@@ -813,6 +1180,167 @@ mod block_tests {
         assert_eq!(bfe_max_elem, some_threshold_actual.values()[3]);
     }
 
+    #[test]
+    fn cumulative_supply_test() {
+        let network = Network::Main;
+        let blocks_per_generation = network.blocks_per_generation();
+        assert!(Block::cumulative_supply(BlockHeight::genesis(), network).is_zero());
+        assert_eq!(
+            Block::get_mining_reward(1u64.into(), network),
+            Block::cumulative_supply(1u64.into(), network)
+        );
+
+        // Compare against the sum of individually computed rewards, across the
+        // first halving boundary
+        let mut expected = NeptuneCoins::zero();
+        for height in 1..=blocks_per_generation + 1 {
+            expected = expected + Block::get_mining_reward(height.into(), network);
+
+            if height >= blocks_per_generation - 1 {
+                assert_eq!(expected, Block::cumulative_supply(height.into(), network));
+            }
+        }
+
+        // The reward is halved at the boundary
+        let boundary: BlockHeight = blocks_per_generation.into();
+        let mut halved_reward = Block::get_mining_reward(boundary.previous(), network);
+        halved_reward.div_two();
+        assert_eq!(halved_reward, Block::get_mining_reward(boundary, network));
+    }
+
+    #[test]
+    fn mining_reward_halves_at_generation_boundaries_test() {
+        assert_eq!(1_000, Network::RegTest.blocks_per_generation());
+        for network in Network::iter() {
+            let blocks_per_generation = network.blocks_per_generation();
+            let mut expected_reward = NeptuneCoins::new(100);
+            for generation in 0..5 {
+                let first_height: BlockHeight = (generation * blocks_per_generation).into();
+                let last_height: BlockHeight =
+                    ((generation + 1) * blocks_per_generation - 1).into();
+                assert_eq!(
+                    expected_reward,
+                    Block::get_mining_reward(first_height, network)
+                );
+                assert_eq!(
+                    expected_reward,
+                    Block::get_mining_reward(last_height, network)
+                );
+
+                expected_reward.div_two();
+                assert_eq!(
+                    expected_reward,
+                    Block::get_mining_reward(last_height.next(), network)
+                );
+            }
+
+            // Eventually, the reward vanishes, also for heights far beyond
+            let late_height: BlockHeight = (128 * blocks_per_generation).into();
+            assert!(Block::get_mining_reward(late_height, network).is_zero());
+            let very_late_height: BlockHeight = BFieldElement::new(BFieldElement::MAX).into();
+            assert!(Block::get_mining_reward(very_late_height, network).is_zero());
+        }
+    }
+
+    #[test]
+    fn maximum_coinbase_test() {
+        let network = Network::Main;
+        let height: BlockHeight = network.blocks_per_generation().into();
+        let fee = NeptuneCoins::new(3);
+        assert_eq!(
+            Some(Block::get_mining_reward(height, network) + fee),
+            Block::maximum_coinbase(height, network, 0, fee)
+        );
+        assert_eq!(
+            Some(
+                Block::get_mining_reward(height, network)
+                    + Block::get_uncle_reward(height, network).scalar_mul(2)
+                    + fee
+            ),
+            Block::maximum_coinbase(height, network, 2, fee)
+        );
+
+        // A negative fee overflows the sum, and does not yield a coinbase
+        assert!(Block::maximum_coinbase(height, network, 0, -NeptuneCoins::one()).is_none());
+    }
+
+    #[test]
+    fn uncle_validation_test() {
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let child_of = |parent: &Block| {
+            make_mock_block_with_valid_pow(network, parent, None, address, random()).0
+        };
+        let as_uncle = |block: &Block| UncleBlock {
+            header: block.kernel.header.clone(),
+            body_digest: block.kernel.body.mast_hash(),
+        };
+        let block_with_uncles = |uncle_blocks: Vec<UncleBlock>| {
+            Block::new(
+                genesis_block.kernel.header.clone(),
+                BlockBody {
+                    uncle_blocks,
+                    ..genesis_block.kernel.body.clone()
+                },
+                Block::mk_std_block_type(None),
+            )
+        };
+
+        // The ancestors of the block, newest first, starting with its parent
+        let mut chain = vec![genesis_block.clone()];
+        for _ in 0..MAX_UNCLE_GENERATIONS + 1 {
+            let block = child_of(chain.last().unwrap());
+            chain.push(block);
+        }
+        chain.reverse();
+        let ancestors = chain.iter().map(RecentAncestor::from).collect_vec();
+        let now = chain[0].kernel.header.timestamp;
+        assert!(block_with_uncles(vec![]).has_valid_uncles(&ancestors, now, network));
+
+        // A valid uncle validly extends a recent ancestor other than the
+        // parent, and need not be known to the node
+        for generation in 1..=MAX_UNCLE_GENERATIONS {
+            let uncle = as_uncle(&child_of(&chain[generation]));
+            let block = block_with_uncles(vec![uncle]);
+            assert!(block.has_valid_uncles(&ancestors, now, network));
+        }
+
+        // Too old an uncle
+        let too_old_uncle = as_uncle(&child_of(&chain[MAX_UNCLE_GENERATIONS + 1]));
+        assert!(!block_with_uncles(vec![too_old_uncle]).has_valid_uncles(&ancestors, now, network));
+
+        // An ancestor is not an uncle, and neither is a sibling of the block
+        let ancestor_as_uncle = block_with_uncles(vec![as_uncle(&chain[1])]);
+        assert!(!ancestor_as_uncle.has_valid_uncles(&ancestors, now, network));
+        let sibling = block_with_uncles(vec![as_uncle(&child_of(&chain[0]))]);
+        assert!(!sibling.has_valid_uncles(&ancestors, now, network));
+
+        // An uncle must have a valid header
+        let mut invalid_uncle = as_uncle(&child_of(&chain[1]));
+        invalid_uncle.header.proof_of_work_family =
+            invalid_uncle.header.proof_of_work_family + invalid_uncle.header.difficulty;
+        let block = block_with_uncles(vec![invalid_uncle]);
+        assert!(!block.has_valid_uncles(&ancestors, now, network));
+
+        // An uncle must not have been referenced by an ancestor already
+        let uncle = as_uncle(&child_of(&chain[1]));
+        let block = block_with_uncles(vec![uncle.clone()]);
+        assert!(block.has_valid_uncles(&ancestors, now, network));
+        let mut ancestors_referencing_uncle = ancestors.clone();
+        ancestors_referencing_uncle[0].uncles.push(uncle.digest());
+        assert!(!block.has_valid_uncles(&ancestors_referencing_uncle, now, network));
+
+        // Uncles are rewarded with a share of the mining reward
+        let height: BlockHeight = 1u64.into();
+        assert_eq!(
+            Block::get_mining_reward(height, network),
+            Block::get_uncle_reward(height, network).scalar_mul(8)
+        );
+    }
+
     #[test]
     fn block_with_wrong_mmra_is_invalid() {
         let mut rng = thread_rng();
@@ -821,13 +1349,135 @@ mod block_tests {
 
         let a_wallet_secret = WalletSecret::new_random();
         let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
-        let (mut block_1, _, _) =
-            make_mock_block_with_valid_pow(&genesis_block, None, a_recipient_address, rng.gen());
+        let (mut block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
 
         block_1.kernel.body.block_mmr_accumulator = MmrAccumulator::new(vec![]);
-        let timestamp = genesis_block.kernel.header.timestamp;
 
-        assert!(!block_1.is_valid(&genesis_block, timestamp));
+        assert!(!block_1.is_valid(&genesis_block, network));
+    }
+
+    #[traced_test]
+    #[test]
+    fn block_with_duplicate_or_too_many_uncles_is_invalid() {
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let child_of = |parent: &Block| {
+            make_mock_block_with_valid_pow(network, parent, None, address, random()).0
+        };
+        let as_uncle = |block: &Block| UncleBlock {
+            header: block.kernel.header.clone(),
+            body_digest: block.kernel.body.mast_hash(),
+        };
+
+        // Uncles of a block at height 2 are siblings of its parent
+        let block_1 = child_of(&genesis_block);
+        let block_2 = child_of(&block_1);
+        let ancestors = [RecentAncestor::from(&block_1), (&genesis_block).into()];
+        let now = block_2.kernel.header.timestamp;
+        let with_uncles = |uncle_blocks: Vec<UncleBlock>| {
+            let mut block = block_2.clone();
+            block.kernel.body.uncle_blocks = uncle_blocks;
+            block.unset_digests();
+            block
+        };
+        let is_fully_valid = |block: &Block| {
+            block.is_valid(&block_1, network) && block.has_valid_uncles(&ancestors, now, network)
+        };
+        assert!(is_fully_valid(&with_uncles(vec![])));
+
+        let uncle = as_uncle(&child_of(&genesis_block));
+        let block_with_uncle = with_uncles(vec![uncle.clone()]);
+        assert_ne!(block_2.hash(), block_with_uncle.hash());
+        assert!(is_fully_valid(&block_with_uncle));
+        assert!(!is_fully_valid(&with_uncles(vec![uncle.clone(), uncle])));
+
+        let too_many_uncles = (0..=MAX_UNCLES_PER_BLOCK)
+            .map(|_| as_uncle(&child_of(&genesis_block)))
+            .collect_vec();
+        assert!(!is_fully_valid(&with_uncles(too_many_uncles)));
+
+        // An uncle whose parent is unknown is not valid
+        let unknown_uncle = UncleBlock {
+            header: genesis_block.kernel.header.clone(),
+            body_digest: random(),
+        };
+        assert!(!is_fully_valid(&with_uncles(vec![unknown_uncle])));
+    }
+
+    #[traced_test]
+    #[test]
+    fn block_with_inflated_coinbase_is_invalid() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (mut block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        assert!(block_1.is_valid(&genesis_block, network));
+
+        // The mock block claims exactly the maximum coinbase
+        let maximum_coinbase = Block::maximum_coinbase(
+            block_1.kernel.header.height,
+            network,
+            0,
+            block_1.kernel.body.transaction.kernel.fee,
+        )
+        .unwrap();
+        assert_eq!(
+            Some(maximum_coinbase),
+            block_1.kernel.body.transaction.kernel.coinbase
+        );
+
+        block_1.kernel.body.transaction.kernel.coinbase =
+            Some(maximum_coinbase + NeptuneCoins::one());
+        assert!(!block_1.is_valid(&genesis_block, network));
+        assert!(logs_contain("claimed miner reward is too high"));
+    }
+
+    #[traced_test]
+    #[test]
+    fn block_with_tampered_mutator_set_is_invalid() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let previous_msa = &genesis_block.kernel.body.mutator_set_accumulator;
+
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (mut block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        assert!(block_1.verify_mutator_set_transition(previous_msa));
+
+        // add an addition record that is not in the block's transaction
+        block_1
+            .kernel
+            .body
+            .mutator_set_accumulator
+            .add(&pseudorandom_addition_record(rng.gen()));
+
+        assert!(!block_1.verify_mutator_set_transition(previous_msa));
+        assert!(!block_1.is_valid(&genesis_block, network));
     }
 
     #[traced_test]
@@ -840,30 +1490,348 @@ mod block_tests {
 
         let a_wallet_secret = WalletSecret::new_random();
         let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
-        let (mut block_1, _, _) =
-            make_mock_block_with_valid_pow(&genesis_block, None, a_recipient_address, rng.gen());
+        let (mut block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+
+        let recent_timestamps = [genesis_block.kernel.header.timestamp];
+        let max_drift = Timestamp::minutes(2);
 
-        // Set block timestamp 1 hour in the future.  (is valid)
-        let future_time1 = now + Timestamp::hours(1);
+        // Set block timestamp 1 minute in the future.  (is valid)
+        let future_time1 = now + Timestamp::minutes(1);
         block_1.kernel.header.timestamp = future_time1;
-        assert!(block_1.is_valid(&genesis_block, now));
+        assert!(block_1.has_valid_timestamp(&recent_timestamps, now, max_drift));
 
         now = block_1.kernel.header.timestamp;
 
-        // Set block timestamp 2 hours - 1 sec in the future.  (is valid)
-        let future_time2 = now + Timestamp::hours(2) - Timestamp::seconds(1);
+        // Set block timestamp exactly the allowed drift in the future.  (is valid)
+        let future_time2 = now + max_drift;
         block_1.kernel.header.timestamp = future_time2;
-        assert!(block_1.is_valid(&genesis_block, now));
+        assert!(block_1.has_valid_timestamp(&recent_timestamps, now, max_drift));
 
-        // Set block timestamp 2 hours + 10 secs in the future. (not valid)
-        let future_time3 = now + Timestamp::hours(2) + Timestamp::seconds(10);
+        // Set block timestamp 1 sec more than the allowed drift in the future. (not valid)
+        let future_time3 = now + max_drift + Timestamp::seconds(1);
         block_1.kernel.header.timestamp = future_time3;
-        assert!(!block_1.is_valid(&genesis_block, now));
+        assert!(!block_1.has_valid_timestamp(&recent_timestamps, now, max_drift));
 
         // Set block timestamp 2 days in the future. (not valid)
         let future_time4 = now + Timestamp::seconds(86400 * 2);
         block_1.kernel.header.timestamp = future_time4;
-        assert!(!block_1.is_valid(&genesis_block, now));
+        assert!(!block_1.has_valid_timestamp(&recent_timestamps, now, max_drift));
+    }
+
+    #[test]
+    fn block_timestamp_must_exceed_median_of_recent_blocks_test() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let a_recipient_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (mut block, _, _) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let genesis_timestamp = genesis_block.kernel.header.timestamp;
+        let now = genesis_timestamp + Timestamp::hours(1);
+        let max_drift = Timestamp::minutes(2);
+
+        // The timestamps of the most recent blocks need not be ordered, and
+        // those of older blocks do not count
+        let mut minutes = (1..=MEDIAN_TIMESTAMP_BLOCK_COUNT).collect_vec();
+        minutes.shuffle(&mut rng);
+        minutes.extend([1000, 1000]);
+        let recent_timestamps = minutes
+            .into_iter()
+            .map(|minute| genesis_timestamp + Timestamp::minutes(minute))
+            .collect_vec();
+        let median = genesis_timestamp + Timestamp::minutes(MEDIAN_TIMESTAMP_BLOCK_COUNT / 2 + 1);
+        assert_eq!(Some(median), Block::median_timestamp(&recent_timestamps));
+
+        block.kernel.header.timestamp = median;
+        assert!(!block.has_valid_timestamp(&recent_timestamps, now, max_drift));
+        block.kernel.header.timestamp = median + Timestamp::millis(1);
+        assert!(block.has_valid_timestamp(&recent_timestamps, now, max_drift));
+
+        // Without preceding blocks, only the clock counts
+        assert_eq!(None, Block::median_timestamp(&[]));
+        block.kernel.header.timestamp = genesis_timestamp;
+        assert!(block.has_valid_timestamp(&[], now, max_drift));
+    }
+
+    /// A header at height one with the given difficulty, such that the
+    /// difficulty of its successor is adjusted.
+    fn header_with_difficulty(difficulty: &BigUint) -> BlockHeader {
+        let genesis_header = Block::genesis_block(Network::RegTest).kernel.header;
+        BlockHeader {
+            height: genesis_header.height.next(),
+            difficulty: Block::biguint_to_difficulty(difficulty.clone()),
+            ..genesis_header
+        }
+    }
+
+    /// The successor of `header`, timestamped `interval` after it.
+    fn next_header(
+        header: &BlockHeader,
+        interval: Timestamp,
+        target_block_interval: Timestamp,
+    ) -> BlockHeader {
+        let timestamp = header.timestamp + interval;
+        BlockHeader {
+            height: header.height.next(),
+            timestamp,
+            difficulty: Block::difficulty_control_from_header(
+                header,
+                timestamp,
+                target_block_interval,
+                MAX_DIFFICULTY_ADJUSTMENT_FACTOR,
+            ),
+            ..header.clone()
+        }
+    }
+
+    fn assert_difficulty_change_is_bounded(old_header: &BlockHeader, new_header: &BlockHeader) {
+        let old_difficulty: BigUint = old_header.difficulty.into();
+        let new_difficulty: BigUint = new_header.difficulty.into();
+        assert!(new_difficulty <= old_difficulty.clone() * MAX_DIFFICULTY_ADJUSTMENT_FACTOR);
+        assert!(new_difficulty >= old_difficulty / MAX_DIFFICULTY_ADJUSTMENT_FACTOR);
+        assert!(new_difficulty >= BigUint::from(MINIMUM_DIFFICULTY));
+    }
+
+    #[proptest]
+    fn difficulty_adjustment_is_bounded_test(
+        #[strategy(u64::from(MINIMUM_DIFFICULTY)..)] old_difficulty: u64,
+        #[strategy(0u64..1 << 40)] interval_millis: u64,
+        timestamp_precedes_previous: bool,
+    ) {
+        let network = Network::Main;
+        let mut header = header_with_difficulty(&BigUint::from(old_difficulty));
+        header.timestamp = Timestamp::millis(1 << 41);
+        let new_timestamp = if timestamp_precedes_previous {
+            header.timestamp - Timestamp::millis(interval_millis)
+        } else {
+            header.timestamp + Timestamp::millis(interval_millis)
+        };
+        let new_header = BlockHeader {
+            height: header.height.next(),
+            timestamp: new_timestamp,
+            difficulty: Block::difficulty_control_from_header(
+                &header,
+                new_timestamp,
+                network.target_block_interval(),
+                network.max_difficulty_adjustment_factor(),
+            ),
+            ..header.clone()
+        };
+
+        assert_difficulty_change_is_bounded(&header, &new_header);
+    }
+
+    #[test]
+    fn difficulty_of_first_block_is_that_of_genesis_test() {
+        // The genesis block is timestamped with the launch date, so the first
+        // block's timestamp says nothing about the hash rate
+        let network = Network::Main;
+        let genesis_header = Block::genesis_block(network).kernel.header;
+        for time_since_launch in [Timestamp::millis(1), Timestamp::days(365)] {
+            assert_eq!(
+                genesis_header.difficulty,
+                Block::difficulty_control_from_header(
+                    &genesis_header,
+                    genesis_header.timestamp + time_since_launch,
+                    network.target_block_interval(),
+                    network.max_difficulty_adjustment_factor(),
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn manipulated_timestamps_move_difficulty_boundedly_test() {
+        let target_block_interval = Timestamp::seconds(60);
+        let initial_difficulty = BigUint::from(1_000_000u32);
+
+        // Timestamps far apart lower the difficulty by the maximum factor per
+        // block, down to the minimum, rather than collapsing it at once
+        let mut header = header_with_difficulty(&initial_difficulty);
+        for _ in 0..20 {
+            let new_header = next_header(&header, Timestamp::days(7), target_block_interval);
+            assert_difficulty_change_is_bounded(&header, &new_header);
+            let old_difficulty: BigUint = header.difficulty.into();
+            let new_difficulty: BigUint = new_header.difficulty.into();
+            assert_eq!(
+                max(
+                    old_difficulty / MAX_DIFFICULTY_ADJUSTMENT_FACTOR,
+                    BigUint::from(MINIMUM_DIFFICULTY)
+                ),
+                new_difficulty
+            );
+            header = new_header;
+        }
+        assert_eq!(
+            U32s::<TARGET_DIFFICULTY_U32_SIZE>::from(MINIMUM_DIFFICULTY),
+            header.difficulty
+        );
+
+        // Timestamps close together raise the difficulty, but boundedly, also
+        // from the minimum
+        for _ in 0..20 {
+            let new_header = next_header(&header, Timestamp::millis(1), target_block_interval);
+            assert_difficulty_change_is_bounded(&header, &new_header);
+            assert!(new_header.difficulty > header.difficulty);
+            header = new_header;
+        }
+
+        // Oscillating timestamps
+        let mut header = header_with_difficulty(&initial_difficulty);
+        for i in 0..100 {
+            let interval = if i % 2 == 0 {
+                Timestamp::millis(1)
+            } else {
+                Timestamp::days(7)
+            };
+            let new_header = next_header(&header, interval, target_block_interval);
+            assert_difficulty_change_is_bounded(&header, &new_header);
+            header = new_header;
+        }
+    }
+
+    #[test]
+    fn difficulty_change_is_clamped_by_the_given_factor_test() {
+        let target_block_interval = Timestamp::seconds(60);
+        let header = header_with_difficulty(&BigUint::from(100u32));
+        let difficulty_after = |interval: Timestamp, max_adjustment_factor: u32| -> BigUint {
+            Block::difficulty_control_from_header(
+                &header,
+                header.timestamp + interval,
+                target_block_interval,
+                max_adjustment_factor,
+            )
+            .into()
+        };
+
+        // A block found at once would raise the difficulty from 100 to 700,
+        // unless the factor clamps it
+        assert_eq!(
+            BigUint::from(700u32),
+            difficulty_after(Timestamp::millis(0), 8)
+        );
+        assert_eq!(
+            BigUint::from(200u32),
+            difficulty_after(Timestamp::millis(0), 2)
+        );
+        assert_eq!(
+            BigUint::from(300u32),
+            difficulty_after(Timestamp::millis(0), 3)
+        );
+
+        // A block found after a week would drop it to the minimum, unless the
+        // factor clamps it
+        assert_eq!(
+            BigUint::from(50u32),
+            difficulty_after(Timestamp::days(7), 2)
+        );
+        assert_eq!(
+            BigUint::from(33u32),
+            difficulty_after(Timestamp::days(7), 3)
+        );
+        assert_eq!(
+            BigUint::from(MINIMUM_DIFFICULTY),
+            difficulty_after(Timestamp::days(7), 100)
+        );
+
+        // Right at the bound, the difficulty is not clamped
+        let interval_tripling_difficulty = target_block_interval - Timestamp::millis(200 * 100);
+        assert_eq!(
+            BigUint::from(300u32),
+            difficulty_after(interval_tripling_difficulty, 3)
+        );
+        assert_eq!(
+            BigUint::from(200u32),
+            difficulty_after(interval_tripling_difficulty, 2)
+        );
+    }
+
+    #[proptest(cases = 10)]
+    fn block_interval_converges_to_target_test(
+        #[strategy(200u64..2_000)] hash_rate: u64,
+        #[strategy(u64::from(MINIMUM_DIFFICULTY)..10_000_000)] initial_difficulty: u64,
+    ) {
+        // Miners with timestamps that reflect the hash rate, in hashes per
+        // second. The adjustment is proportional to the deviation from the
+        // target, and the deviation to the difficulty over the hash rate, so
+        // the hash rates are chosen such that the difficulty settles within a
+        // few thousand blocks.
+        let target_block_interval = Timestamp::seconds(60);
+        let mut header = header_with_difficulty(&BigUint::from(initial_difficulty));
+        let mut intervals = vec![];
+        for _ in 0..3_000 {
+            let difficulty: BigUint = header.difficulty.into();
+            let expected_millis: u64 = (difficulty * 1_000u32 / hash_rate).try_into().unwrap();
+            let interval = Timestamp::millis(expected_millis);
+            let new_header = next_header(&header, interval, target_block_interval);
+            assert_difficulty_change_is_bounded(&header, &new_header);
+            intervals.push(expected_millis);
+            header = new_header;
+        }
+
+        let recent_intervals = &intervals[intervals.len() - 500..];
+        let average_interval = recent_intervals.iter().sum::<u64>() / recent_intervals.len() as u64;
+        let target_millis = target_block_interval.0.value();
+        assert!(average_interval.abs_diff(target_millis) < target_millis / 100);
+    }
+
+    #[test]
+    fn difficulty_follows_target_block_interval_test() {
+        let target_block_interval = Timestamp::seconds(10);
+        let mut header = Block::genesis_block(Network::RegTest).kernel.header;
+        header.height = header.height.next();
+        header.difficulty = 1_000_000u32.into();
+
+        // Blocks found faster than the target raise the difficulty
+        for _ in 0..5 {
+            let timestamp = header.timestamp + Timestamp::seconds(2);
+            let difficulty = Block::difficulty_control_from_header(
+                &header,
+                timestamp,
+                target_block_interval,
+                MAX_DIFFICULTY_ADJUSTMENT_FACTOR,
+            );
+            assert!(difficulty > header.difficulty);
+            header = BlockHeader {
+                height: header.height.next(),
+                timestamp,
+                difficulty,
+                ..header
+            };
+        }
+
+        // Blocks found slower than the target lower it
+        for _ in 0..5 {
+            let timestamp = header.timestamp + Timestamp::seconds(30);
+            let difficulty = Block::difficulty_control_from_header(
+                &header,
+                timestamp,
+                target_block_interval,
+                MAX_DIFFICULTY_ADJUSTMENT_FACTOR,
+            );
+            assert!(difficulty < header.difficulty);
+            header = BlockHeader {
+                height: header.height.next(),
+                timestamp,
+                difficulty,
+                ..header
+            };
+        }
+
+        assert!(Network::RegTest.target_block_interval() < Network::Main.target_block_interval());
     }
 
     #[tokio::test]
@@ -885,8 +1853,13 @@ mod block_tests {
         for i in 0..55 {
             let wallet_secret = WalletSecret::new_random();
             let recipient_address = wallet_secret.nth_generation_spending_key(0).to_address();
-            let (new_block, _, _) =
-                make_mock_block(blocks.last().unwrap(), None, recipient_address, rng.gen());
+            let (new_block, _, _) = make_mock_block(
+                network,
+                blocks.last().unwrap(),
+                None,
+                recipient_address,
+                rng.gen(),
+            );
             if i != 54 {
                 ammr.append(new_block.hash()).await;
                 mmra.append(new_block.hash());
@@ -947,6 +1920,8 @@ mod block_tests {
     mod digest_encapsulation {
         use super::*;
 
+        use crate::models::blockchain::block::transfer_block::TransferBlockError;
+
         // test: verify clone + modify does not change original.
         //
         // note: a naive impl that derives Clone on `Block` containing
@@ -1019,15 +1994,51 @@ mod block_tests {
             let address = spending_key.to_address();
             let mut rng = thread_rng();
 
-            let gblock = Block::genesis_block(Network::RegTest);
+            let network = Network::RegTest;
+            let gblock = Block::genesis_block(network);
 
-            let (source_block, _, _) = make_mock_block(&gblock, None, address, rng.gen());
+            let (source_block, _, _) = make_mock_block(network, &gblock, None, address, rng.gen());
 
             let transfer_block = TransferBlock::from(source_block.clone());
             let new_block = Block::from(transfer_block);
             assert_eq!(source_block.hash(), new_block.hash());
         }
 
+        // test: verify that a TransferBlock is only accepted as the block
+        //       with the advertised digest if neither header nor body were
+        //       tampered with.
+        #[tokio::test]
+        async fn from_transfer_block_with_digest() {
+            let mut rng = thread_rng();
+            let address = WalletSecret::new_random()
+                .nth_generation_spending_key(0)
+                .to_address();
+            let network = Network::RegTest;
+            let gblock = Block::genesis_block(network);
+            let (source_block, _, _) = make_mock_block(network, &gblock, None, address, rng.gen());
+            let digest = source_block.hash();
+
+            let transfer_block = TransferBlock::from(source_block.clone());
+            assert_eq!(
+                Ok(source_block),
+                transfer_block.clone().into_block_with_digest(digest)
+            );
+
+            let mut tampered_header = transfer_block.clone();
+            tampered_header.header.nonce = rng.gen();
+            let mut tampered_body = transfer_block;
+            tampered_body.body.uncle_blocks.push(UncleBlock {
+                header: tampered_body.header.clone(),
+                body_digest: random(),
+            });
+            for tampered_transfer_block in [tampered_header, tampered_body] {
+                assert!(matches!(
+                    tampered_transfer_block.into_block_with_digest(digest),
+                    Err(TransferBlockError::DigestMismatch { advertised, .. }) if advertised == digest
+                ));
+            }
+        }
+
         // test: verify digest is correct after deserializing
         #[test]
         fn deserialize() {
@@ -1049,6 +2060,42 @@ mod block_tests {
             assert_ne!(block1.hash(), block1_merged.hash());
         }
 
+        // test: verify the cached digest equals a freshly computed one after
+        //       every kind of modification.
+        #[tokio::test]
+        async fn cached_digest_matches_fresh_digest() {
+            let mut rng = thread_rng();
+            let mut block = Block::genesis_block(Network::RegTest);
+            assert_eq!(block.kernel.mast_hash(), block.hash());
+
+            block.set_header_nonce(rng.gen());
+            assert_eq!(block.kernel.mast_hash(), block.hash());
+
+            block.set_header_timestamp(rng.gen());
+            assert_eq!(block.kernel.mast_hash(), block.hash());
+
+            let (_, block1, mut block1_merged) = merge_transaction().await;
+            assert_eq!(block1_merged.kernel.mast_hash(), block1_merged.hash());
+
+            block1_merged.set_header_nonce(rng.gen());
+            assert_eq!(block1_merged.kernel.mast_hash(), block1_merged.hash());
+
+            block.set_block(block1.clone());
+            assert_eq!(block1.kernel.mast_hash(), block.hash());
+        }
+
+        #[test]
+        fn mast_hash_from_digests_matches_mast_hash() {
+            let gblock = Block::genesis_block(Network::RegTest);
+            assert_eq!(
+                gblock.kernel.mast_hash(),
+                BlockKernel::mast_hash_from_digests(
+                    gblock.kernel.header.mast_hash(),
+                    gblock.kernel.body.mast_hash()
+                )
+            );
+        }
+
         // test: verify block digest matches after BFieldCodec encode+decode
         //       round trip.
         #[test]
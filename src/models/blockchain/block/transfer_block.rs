@@ -1,8 +1,12 @@
+use crate::prelude::twenty_first;
+
 use get_size::GetSize;
 use serde::{Deserialize, Serialize};
 use tasm_lib::triton_vm::proof::Proof;
+use thiserror::Error;
+use twenty_first::math::digest::Digest;
 
-use super::{block_body::BlockBody, block_header::BlockHeader};
+use super::{block_body::BlockBody, block_header::BlockHeader, Block};
 use crate::models::blockchain::block::BFieldCodec;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq, BFieldCodec, GetSize)]
@@ -19,3 +23,33 @@ pub struct TransferBlock {
     pub body: BlockBody,
     pub proof_type: ProofType,
 }
+
+/// Returned when a block received from a peer is not the block that it was
+/// advertised as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum TransferBlockError {
+    #[error("Block was advertised with digest {advertised}, but has digest {actual}")]
+    DigestMismatch { advertised: Digest, actual: Digest },
+}
+
+impl TransferBlock {
+    /// Reconstruct the block, and check that its digest is the advertised
+    /// one, e.g. the digest with which it was requested. The digest commits to
+    /// the header as well as to the body, so a transfer with either of them
+    /// tampered with is rejected.
+    pub fn into_block_with_digest(
+        self,
+        advertised_digest: Digest,
+    ) -> Result<Block, TransferBlockError> {
+        let block = Block::from(self);
+        let actual_digest = block.hash();
+        if actual_digest != advertised_digest {
+            return Err(TransferBlockError::DigestMismatch {
+                advertised: advertised_digest,
+                actual: actual_digest,
+            });
+        }
+
+        Ok(block)
+    }
+}
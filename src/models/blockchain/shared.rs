@@ -1,3 +1,47 @@
 use crate::prelude::twenty_first::math::tip5::Tip5;
 
+/// The hash function of blocks, of the mutator set, and of the membership
+/// proofs into both. Swapping the hash function is a change to this line only.
 pub type Hash = Tip5;
+
+#[cfg(test)]
+mod shared_tests {
+    use super::*;
+
+    use crate::config_models::network::Network;
+    use crate::models::blockchain::block::Block;
+    use crate::prelude::twenty_first;
+    use crate::util_types::mutator_set::mutator_set_accumulator::MutatorSetAccumulator;
+    use crate::util_types::mutator_set::removal_record::RemovalRecord;
+    use crate::util_types::test_shared::mutator_set::insert_mock_item;
+    use twenty_first::util_types::mmr::mmr_accumulator::MmrAccumulator;
+    use twenty_first::util_types::mmr::mmr_membership_proof::MmrMembershipProof;
+
+    fn accumulator_uses_hash(_accumulator: &MmrAccumulator<Hash>) {}
+
+    fn membership_proof_uses_hash(_membership_proof: &MmrMembershipProof<Hash>) {}
+
+    /// Compiles only if blocks, the mutator set, and removal records agree on
+    /// [Hash], such that membership proofs made in one module verify in another.
+    #[test]
+    fn blocks_and_mutator_set_agree_on_hash_test() {
+        let genesis_block = Block::genesis_block(Network::RegTest);
+        let body = &genesis_block.kernel.body;
+        accumulator_uses_hash(&body.block_mmr_accumulator);
+        accumulator_uses_hash(&body.lock_free_mmr_accumulator);
+
+        let mut mutator_set: MutatorSetAccumulator = body.mutator_set_accumulator.clone();
+        accumulator_uses_hash(&mutator_set.aocl);
+        accumulator_uses_hash(&mutator_set.swbf_inactive);
+
+        let (membership_proof, item) = insert_mock_item(&mut mutator_set);
+        membership_proof_uses_hash(&membership_proof.auth_path_aocl);
+        assert!(mutator_set.verify(item, &membership_proof));
+
+        let removal_record: RemovalRecord = mutator_set.drop(item, &membership_proof);
+        for (chunk_membership_proof, _chunk) in removal_record.target_chunks.dictionary.values() {
+            membership_proof_uses_hash(chunk_membership_proof);
+        }
+        assert!(mutator_set.can_remove(&removal_record));
+    }
+}
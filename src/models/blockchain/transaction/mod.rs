@@ -19,6 +19,7 @@ use std::cmp::max;
 use std::collections::HashMap;
 use std::hash::{Hash as StdHash, Hasher as StdHasher};
 use tasm_lib::Digest;
+use thiserror::Error;
 use tracing::{debug, error, warn};
 use triton_vm::prelude::NonDeterminism;
 use twenty_first::math::b_field_element::BFieldElement;
@@ -27,9 +28,11 @@ use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 
 use self::primitive_witness::PrimitiveWitness;
 use self::transaction_kernel::TransactionKernel;
+use self::utxo::Utxo;
 use self::validity::TransactionValidationLogic;
 use super::block::Block;
 use super::shared::Hash;
+use super::type_scripts::neptune_coins::NeptuneCoins;
 use super::type_scripts::TypeScript;
 use crate::util_types::mutator_set::addition_record::AdditionRecord;
 use crate::util_types::mutator_set::ms_membership_proof::MsMembershipProof;
@@ -68,6 +71,35 @@ impl StdHash for Transaction {
     }
 }
 
+/// A wallet's view of a transaction, for display purposes.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TransactionSummary {
+    /// Value of the outputs to self minus the value of the owned inputs.
+    /// Negative if the transaction decreases the wallet's balance.
+    pub net_amount: NeptuneCoins,
+    pub fee: NeptuneCoins,
+    pub inputs_owned: Vec<Utxo>,
+    pub outputs_to_self: Vec<Utxo>,
+    pub outputs_to_others: Vec<AdditionRecord>,
+}
+
+/// Returned when a transaction's inputs cannot be removed from a given mutator
+/// set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum TransactionValidationError {
+    #[error("Transaction is synced to mutator set {transaction}, not to {mutator_set}")]
+    MutatorSetMismatch {
+        transaction: Digest,
+        mutator_set: Digest,
+    },
+
+    #[error("Removal record of input {0} is not synced to the mutator set")]
+    InvalidRemovalRecord(usize),
+
+    #[error("Input {input} spends the same UTXO as input {earlier_input}")]
+    DoubleSpend { input: usize, earlier_input: usize },
+}
+
 impl Transaction {
     /// Create a new `Transaction`` from a `PrimitiveWitness` (which defines an old
     /// `Transaction`) by updating the mutator set records according to a new
@@ -80,10 +112,9 @@ impl Transaction {
             old_primitive_witness.mutator_set_accumulator.clone();
         let block_addition_records: Vec<AdditionRecord> =
             block.kernel.body.transaction.kernel.outputs.clone();
-        let mut transaction_removal_records: Vec<RemovalRecord> =
-            old_primitive_witness.kernel.inputs.clone();
+        let mut updated_inputs: Vec<RemovalRecord> = old_primitive_witness.kernel.inputs.clone();
         let mut transaction_removal_records: Vec<&mut RemovalRecord> =
-            transaction_removal_records.iter_mut().collect();
+            updated_inputs.iter_mut().collect();
         let mut block_removal_records = block.kernel.body.transaction.kernel.inputs.clone();
         block_removal_records.reverse();
         let mut block_removal_records: Vec<&mut RemovalRecord> =
@@ -153,6 +184,12 @@ impl Transaction {
             "Internal MSA state must match that from block"
         );
 
+        // Make the kernel refer to the new mutator set, such that the updated
+        // transaction can be updated again with the next block
+        primitive_witness.kernel.inputs = updated_inputs;
+        primitive_witness.kernel.mutator_set_hash = block_msa_hash;
+        primitive_witness.mutator_set_accumulator = msa_state;
+
         let kernel = primitive_witness.kernel.clone();
         let witness = TransactionValidationLogic::from(primitive_witness);
         Ok(Transaction { kernel, witness })
@@ -282,6 +319,23 @@ impl Transaction {
         }
     }
 
+    /// Determine whether [`merge_with`](Self::merge_with) supports merging
+    /// this transaction with `other`, i.e., whether their mutator sets agree
+    /// and their witness types can be combined.
+    pub fn can_merge_with(&self, other: &Transaction) -> bool {
+        self.kernel.mutator_set_hash == other.kernel.mutator_set_hash
+            && matches!(
+                (
+                    &self.witness.vast.witness_type,
+                    &other.witness.vast.witness_type
+                ),
+                (WitnessType::Decomposition, WitnessType::Decomposition)
+                    | (WitnessType::Proof(_), WitnessType::Proof(_))
+                    | (WitnessType::Faith, _)
+                    | (_, WitnessType::Faith)
+            )
+    }
+
     /// Merge two transactions. Both input transactions must have a valid
     /// Proof witness for this operation to work. The mutator sets are
     /// assumed to be identical; this is the responsibility of the caller.
@@ -377,15 +431,59 @@ impl Transaction {
         }
     }
 
+    /// The number of bytes in the canonical (bincode) serialization of the
+    /// transaction, i.e., the size it occupies on the wire and in a block.
+    pub fn serialized_size(&self) -> usize {
+        bincode::serialized_size(self).unwrap() as usize
+    }
+
     /// Calculates a fraction representing the fee-density, defined as:
-    /// `transaction_fee/transaction_size`.
+    /// `transaction_fee/transaction_size`, where the size is the
+    /// [serialized size](Self::serialized_size). The fraction is exact, so
+    /// transactions can be ranked consistently by the mempool, the block
+    /// builder, and RPC clients alike.
     pub fn fee_density(&self) -> BigRational {
-        let transaction_as_bytes = bincode::serialize(&self).unwrap();
-        let transaction_size = BigInt::from(transaction_as_bytes.get_size());
+        let transaction_size = BigInt::from(self.serialized_size());
         let transaction_fee = self.kernel.fee.to_nau();
         BigRational::new_raw(transaction_fee, transaction_size)
     }
 
+    /// Summarize the transaction from the point of view of a wallet, given the
+    /// UTXOs that the wallet owns among the inputs, and the UTXOs that the
+    /// wallet owns among the outputs indexed by their addition records. See
+    /// [`WalletState::summarize_transaction`](crate::models::state::wallet::wallet_state::WalletState::summarize_transaction).
+    pub fn summary(
+        &self,
+        inputs_owned: Vec<Utxo>,
+        own_outputs: &HashMap<AdditionRecord, Utxo>,
+    ) -> TransactionSummary {
+        let mut outputs_to_self = vec![];
+        let mut outputs_to_others = vec![];
+        for addition_record in self.kernel.outputs.iter() {
+            match own_outputs.get(addition_record) {
+                Some(utxo) => outputs_to_self.push(utxo.to_owned()),
+                None => outputs_to_others.push(*addition_record),
+            }
+        }
+
+        let received: NeptuneCoins = outputs_to_self
+            .iter()
+            .map(|utxo| utxo.get_native_currency_amount())
+            .sum();
+        let spent: NeptuneCoins = inputs_owned
+            .iter()
+            .map(|utxo| utxo.get_native_currency_amount())
+            .sum();
+
+        TransactionSummary {
+            net_amount: received + -spent,
+            fee: self.kernel.fee,
+            inputs_owned,
+            outputs_to_self,
+            outputs_to_others,
+        }
+    }
+
     /// Determine if the transaction can be validly confirmed if the block has
     /// the given mutator set accumulator. Specifically, test whether the
     /// removal records determine indices absent in the mutator set sliding
@@ -400,6 +498,42 @@ impl Transaction {
             .all(|rr| rr.validate(mutator_set_accumulator))
     }
 
+    /// Check that all inputs can be removed from the given mutator set: the
+    /// transaction must be synced to it, every removal record must validate
+    /// against it, and no two inputs may remove the same indices, which would
+    /// spend the same UTXO twice. Returns the error for the first offending
+    /// input.
+    pub fn validate_against_mutator_set(
+        &self,
+        mutator_set_accumulator: &MutatorSetAccumulator,
+    ) -> Result<(), TransactionValidationError> {
+        let mutator_set_hash = mutator_set_accumulator.hash();
+        if self.kernel.mutator_set_hash != mutator_set_hash {
+            return Err(TransactionValidationError::MutatorSetMismatch {
+                transaction: self.kernel.mutator_set_hash,
+                mutator_set: mutator_set_hash,
+            });
+        }
+
+        let mut input_by_indices = HashMap::new();
+        for (input, removal_record) in self.kernel.inputs.iter().enumerate() {
+            if !removal_record.validate(mutator_set_accumulator) {
+                return Err(TransactionValidationError::InvalidRemovalRecord(input));
+            }
+
+            if let Some(earlier_input) =
+                input_by_indices.insert(removal_record.absolute_indices.to_array(), input)
+            {
+                return Err(TransactionValidationError::DoubleSpend {
+                    input,
+                    earlier_input,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     /// Verify the transaction directly from the primitive witness, without proofs or
     /// decomposing into subclaims.
     pub async fn validate_primitive_witness(
@@ -628,13 +762,85 @@ mod transaction_tests {
 
     use super::*;
     use crate::{
+        config_models::network::Network,
+        models::state::wallet::WalletSecret,
+        models::state::UtxoReceiverData,
         models::{
             blockchain::type_scripts::neptune_coins::NeptuneCoins, consensus::timestamp::Timestamp,
         },
-        tests::shared::make_mock_transaction,
+        tests::shared::{make_mock_transaction, mock_genesis_global_state},
         util_types::mutator_set::commit,
+        util_types::test_shared::mutator_set::make_item_and_randomnesses,
     };
 
+    /// A mutator set with `count` items, and a transaction spending all of
+    /// them that is synced to it.
+    fn mutator_set_and_transaction_spending(count: usize) -> (MutatorSetAccumulator, Transaction) {
+        let mut mutator_set_accumulator = MutatorSetAccumulator::default();
+        let mut items_and_membership_proofs: Vec<(Digest, MsMembershipProof)> = vec![];
+        for _ in 0..count {
+            let (item, sender_randomness, receiver_preimage) = make_item_and_randomnesses();
+            let addition_record = commit(item, sender_randomness, receiver_preimage.hash::<Hash>());
+            for (own_item, membership_proof) in items_and_membership_proofs.iter_mut() {
+                membership_proof
+                    .update_from_addition(*own_item, &mutator_set_accumulator, &addition_record)
+                    .unwrap();
+            }
+            items_and_membership_proofs.push((
+                item,
+                mutator_set_accumulator.prove(item, sender_randomness, receiver_preimage),
+            ));
+            mutator_set_accumulator.add(&addition_record);
+        }
+
+        let inputs = items_and_membership_proofs
+            .iter()
+            .map(|(item, membership_proof)| mutator_set_accumulator.drop(*item, membership_proof))
+            .collect_vec();
+        let mut transaction = make_mock_transaction(inputs, vec![]);
+        transaction.kernel.mutator_set_hash = mutator_set_accumulator.hash();
+
+        (mutator_set_accumulator, transaction)
+    }
+
+    #[test]
+    fn synced_transaction_validates_against_mutator_set_test() {
+        let (mutator_set_accumulator, transaction) = mutator_set_and_transaction_spending(3);
+        assert_eq!(
+            Ok(()),
+            transaction.validate_against_mutator_set(&mutator_set_accumulator)
+        );
+        assert!(transaction.is_confirmable_relative_to(&mutator_set_accumulator));
+    }
+
+    #[test]
+    fn double_spend_within_transaction_is_detected_test() {
+        let (mutator_set_accumulator, mut transaction) = mutator_set_and_transaction_spending(3);
+        let spent_twice = transaction.kernel.inputs[1].clone();
+        transaction.kernel.inputs.push(spent_twice);
+        assert_eq!(
+            Err(TransactionValidationError::DoubleSpend {
+                input: 3,
+                earlier_input: 1
+            }),
+            transaction.validate_against_mutator_set(&mutator_set_accumulator)
+        );
+    }
+
+    #[test]
+    fn transaction_synced_to_stale_mutator_set_is_rejected_test() {
+        let (mut mutator_set_accumulator, transaction) = mutator_set_and_transaction_spending(2);
+        let stale_mutator_set_hash = mutator_set_accumulator.hash();
+        mutator_set_accumulator.add(&commit(random(), random(), random()));
+        assert_eq!(
+            Err(TransactionValidationError::MutatorSetMismatch {
+                transaction: stale_mutator_set_hash,
+                mutator_set: mutator_set_accumulator.hash(),
+            }),
+            transaction.validate_against_mutator_set(&mutator_set_accumulator)
+        );
+    }
+
     #[traced_test]
     #[test]
     fn tx_get_timestamp_test() {
@@ -650,6 +856,76 @@ mod transaction_tests {
         assert!(Timestamp::now() - coinbase_transaction.kernel.timestamp < Timestamp::seconds(10));
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn summary_of_self_payment_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let premine_utxo = Block::premine_utxos(network)[0].clone();
+
+        // Pay 20 coins to own address, after the premine's time lock has expired
+        let own_address = global_state
+            .wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0)
+            .to_address();
+        let utxo = Utxo::new_native_coin(own_address.lock_script(), NeptuneCoins::new(20));
+        let sender_randomness = random();
+        let receiver_data = vec![UtxoReceiverData {
+            utxo: utxo.clone(),
+            sender_randomness,
+            receiver_privacy_digest: own_address.privacy_digest,
+            public_announcement: own_address
+                .generate_public_announcement(&utxo, sender_randomness)
+                .unwrap(),
+        }];
+        let fee = NeptuneCoins::new(1);
+        let timestamp = genesis_block.kernel.header.timestamp + Timestamp::months(7);
+        let transaction = global_state
+            .create_transaction(receiver_data, fee, timestamp)
+            .await
+            .unwrap();
+
+        let summary = global_state
+            .wallet_state
+            .summarize_transaction(&transaction)
+            .await;
+        assert_eq!(fee, summary.fee);
+        assert_eq!(vec![premine_utxo], summary.inputs_owned);
+        assert_eq!(
+            2,
+            summary.outputs_to_self.len(),
+            "Both the payment and the change must be recognized as outputs to self"
+        );
+        assert!(summary.outputs_to_self.contains(&utxo));
+        assert!(summary.outputs_to_others.is_empty());
+        assert_eq!(
+            -fee, summary.net_amount,
+            "Self-payment must only cost the fee"
+        );
+    }
+
+    #[test]
+    fn fee_density_depends_on_serialized_size_test() {
+        let random_output = || commit(random(), random(), random());
+        let small_transaction = make_mock_transaction(vec![], vec![random_output()]);
+        let large_transaction = make_mock_transaction(
+            vec![],
+            vec![random_output(), random_output(), random_output()],
+        );
+        assert_eq!(small_transaction.kernel.fee, large_transaction.kernel.fee);
+        assert!(small_transaction.serialized_size() < large_transaction.serialized_size());
+
+        assert_eq!(
+            bincode::serialize(&small_transaction).unwrap().len(),
+            small_transaction.serialized_size()
+        );
+        assert!(small_transaction.fee_density() > large_transaction.fee_density());
+    }
+
     #[test]
     fn encode_decode_empty_tx_test() {
         let empty_tx = make_mock_transaction(vec![], vec![]);
@@ -657,283 +933,4 @@ mod transaction_tests {
         let decoded = *Transaction::decode(&encoded).unwrap();
         assert_eq!(empty_tx, decoded);
     }
-
-    // #[traced_test]
-    // #[test]
-    // fn merged_transaction_is_devnet_valid_test() {
-    //     let wallet_1 = new_random_wallet();
-    //     let output_amount_1: Amount = 42.into();
-    //     let output_1 = Utxo {
-    //         amount: output_amount_1,
-    //         public_key: wallet_1.get_public_key(),
-    //     };
-    //     let randomness: Digest = Digest::new(random_elements_array());
-
-    //     let coinbase_transaction = make_mock_transaction(vec![], vec![(output_1, randomness)]);
-    //     let coinbase_amount = Some(output_amount_1);
-
-    //     assert!(coinbase_transaction.is_valid_for_devnet(coinbase_amount));
-
-    //     let input_1 = make_mock_unsigned_devnet_input(<i32 as Into<Amount>>::into(42), &wallet_1);
-    //     let mut transaction_1 = make_mock_transaction(vec![input_1], vec![(output_1, randomness)]);
-
-    //     assert!(!transaction_1.is_valid_for_devnet(None));
-    //     transaction_1.sign(&wallet_1);
-    //     assert!(transaction_1.is_valid_for_devnet(None));
-
-    //     let input_2 = make_mock_unsigned_devnet_input(42.into(), &wallet_1);
-    //     let mut transaction_2 = make_mock_transaction(vec![input_2], vec![(output_1, randomness)]);
-
-    //     assert!(!transaction_2.is_valid_for_devnet(None));
-    //     transaction_2.sign(&wallet_1);
-    //     assert!(transaction_2.is_valid_for_devnet(None));
-
-    //     let mut merged_transaction = transaction_1.merge_with(transaction_2);
-    //     assert!(
-    //         merged_transaction.is_valid_for_devnet(coinbase_amount),
-    //         "Merged transaction must be valid because of authority proof"
-    //     );
-
-    //     merged_transaction.authority_proof = None;
-    //     assert!(
-    //         !merged_transaction.is_valid_for_devnet(coinbase_amount),
-    //         "Merged transaction must not be valid without authority proof"
-    //     );
-
-    //     // Make an authority sign with a wrong secret key and verify failure
-    //     let kernel: TransactionKernel = merged_transaction.get_kernel();
-    //     let kernel_digest: Digest = Hash::hash(&kernel);
-    //     let bad_authority_signature = wallet_1.sign_digest(kernel_digest);
-    //     merged_transaction.authority_proof = Some(bad_authority_signature);
-    //     assert!(
-    //         !merged_transaction.is_valid_for_devnet(coinbase_amount),
-    //         "Merged transaction must not be valid with wrong authority proof"
-    //     );
-
-    //     // Restore valid proof
-    //     merged_transaction.devnet_authority_sign();
-    //     assert!(
-    //         merged_transaction.is_valid_for_devnet(coinbase_amount),
-    //         "Merged transaction must be valid because of authority proof, 2"
-    //     );
-    // }
-
-    // #[traced_test]
-    // #[tokio::test]
-    // async fn transaction_is_valid_after_block_update_simple_test() -> Result<()> {
-    //     // We need the global state to construct a transaction. This global state
-    //     // has a wallet which receives a premine-UTXO.
-    //     let global_state = get_mock_global_state(Network::Main, 2, None).await;
-    //     let other_wallet = wallet::WalletSecret::new(wallet::generate_secret_key());
-
-    //     // Create a transaction that's valid after the Genesis block
-    //     let receiver_data = vec![UtxoReceiverData {
-    //         utxo: Utxo {
-    //             lock_script: LockScript(vec![]),
-    //             coins: Into::<Amount>::into(5).to_native_coins(),
-    //         },
-    //         sender_randomness: random(),
-    //         receiver_privacy_digest: random(),
-    //         pubscript: PubScript::default(),
-    //         pubscript_input: vec![],
-    //     }];
-    //     let mut transaction = global_state
-    //         .create_transaction(receiver_data, Amount::one())
-    //         .await
-    //         .unwrap();
-
-    //     let genesis_block = Block::genesis_block();
-    //     let (block_1, _, _) = make_mock_block(
-    //         &genesis_block,
-    //         None,
-    //         other_wallet.nth_generation_spending_key(0).to_address(),
-    //     );
-    //     assert!(
-    //         block_1.is_valid_for_devnet(&genesis_block),
-    //         "Block 1 must be valid with only coinbase output"
-    //     );
-
-    //     assert!(transaction.is_valid(None));
-    //     transaction.update_mutator_set_records(&block_1).unwrap();
-
-    //     // Insert the updated transaction into block 2 and verify that this block is valid
-    //     let mut block_2 = make_mock_block(&block_1, None, other_wallet.get_public_key());
-    //     block_2.authority_merge_transaction(updated_tx.clone());
-    //     assert!(block_2.is_valid_for_devnet(&block_1));
-
-    //     // Mine 26 blocks, keep the transaction updated, and verify that it is valid after
-    //     // all blocks
-    //     let mut next_block = block_1.clone();
-    //     let mut _previous_block = next_block.clone();
-    //     for _ in 0..26 {
-    //         _previous_block = next_block;
-    //         next_block = make_mock_block(&_previous_block, None, other_wallet.get_public_key());
-    //         updated_tx.update_ms_data(&next_block).unwrap();
-    //     }
-
-    //     _previous_block = next_block.clone();
-    //     next_block = make_mock_block(&next_block, None, other_wallet.get_public_key());
-    //     next_block.authority_merge_transaction(updated_tx.clone());
-    //     assert!(next_block.is_valid_for_devnet(&_previous_block));
-
-    //     Ok(())
-    // }
-
-    // #[traced_test]
-    // #[tokio::test]
-    // async fn transaction_is_valid_after_block_update_multiple_ios_test() -> Result<()> {
-    //     // We need the global state to construct a transaction. This global state
-    //     // has a wallet which receives a premine-UTXO.
-    //     let own_global_state = get_mock_global_state(Network::Main, 2, None).await;
-    //     let own_wallet_secret = &own_global_state.wallet_state.wallet_secret;
-
-    //     // Create a transaction that's valid after the Genesis block
-    //     let mut output_utxos: Vec<Utxo> = vec![];
-    //     for i in 0..7 {
-    //         let new_utxo = Utxo {
-    //             amount: i.into(),
-    //             public_key: own_wallet_secret.get_public_key(),
-    //         };
-    //         output_utxos.push(new_utxo);
-    //     }
-
-    //     // Create a transaction that's valid after genesis block
-    //     let mut tx = own_global_state
-    //         .create_transaction(output_utxos, 1.into())
-    //         .await
-    //         .unwrap();
-    //     let original_tx = tx.clone();
-
-    //     // Create next block and verify that transaction is not valid with this block as tip
-    //     let genesis_block = Block::genesis_block();
-    //     let other_wallet = WalletSecret::new(generate_secret_key());
-    //     let block_1 = make_mock_block(&genesis_block, None, own_wallet_secret.get_public_key());
-    //     let block_2 = make_mock_block(&block_1, None, other_wallet.get_public_key());
-    //     assert!(
-    //         block_1.is_valid_for_devnet(&genesis_block),
-    //         "Block 1 must be valid with only coinbase output"
-    //     );
-    //     assert!(
-    //         block_2.is_valid_for_devnet(&block_1),
-    //         "Block 2 must be valid with only coinbase output"
-    //     );
-
-    //     let mut block_2_with_deprecated_tx = block_2.clone();
-    //     block_2_with_deprecated_tx.authority_merge_transaction(tx.clone());
-    //     assert!(
-    //         !block_2_with_deprecated_tx.is_valid_for_devnet(&block_1),
-    //         "Block with transaction with deprecated mutator set data must be invalid"
-    //     );
-
-    //     // Update the transaction with mutator set data from block 1. Verify that this
-    //     // gives rise to a valid block.
-    //     tx.update_ms_data(&block_1).unwrap();
-    //     let mut block_2_with_updated_tx = block_2.clone();
-    //     block_2_with_updated_tx.authority_merge_transaction(tx.clone());
-    //     assert!(
-    //         block_2_with_updated_tx.is_valid_for_devnet(&block_1),
-    //         "Block with transaction with updated mutator set data must be valid"
-    //     );
-
-    //     // We would like to use more advanced blocks, that have multiple inputs and outputs.
-    //     // Problem: If we start making with my own wallet, we consume the same inputs that are
-    //     // consumed in `updated_tx`. Solution: Create another global state object, containing
-    //     // another wallet, and use this to generate the transactions that go into these
-    //     // blocks. This should keep the `updated_tx` valid as its inputs are not being spent.
-    //     let other_global_state =
-    //         get_mock_global_state(Network::Main, 2, Some(other_wallet.clone())).await;
-    //     other_global_state
-    //         .wallet_state
-    //         .update_wallet_state_with_new_block(
-    //             &block_1,
-    //             &mut other_global_state.wallet_state.wallet_db.lock().await,
-    //         )
-    //         .unwrap();
-    //     *other_global_state
-    //         .chain
-    //         .light_state
-    //         .latest_block
-    //         .lock()
-    //         .await = block_1.clone();
-    //     other_global_state
-    //         .wallet_state
-    //         .update_wallet_state_with_new_block(
-    //             &block_2,
-    //             &mut other_global_state.wallet_state.wallet_db.lock().await,
-    //         )
-    //         .unwrap();
-    //     *other_global_state
-    //         .chain
-    //         .light_state
-    //         .latest_block
-    //         .lock()
-    //         .await = block_2.clone();
-    //     let mut updated_tx = original_tx;
-    //     updated_tx.update_ms_data(&block_1).unwrap();
-    //     updated_tx.update_ms_data(&block_2).unwrap();
-
-    //     // Mine 12 blocks with non-trivial transactions, keep the transaction updated,
-    //     // and verify that it is valid after all blocks.
-    //     let mut next_block = block_2.clone();
-    //     let mut _previous_block = next_block.clone();
-    //     for i in 0..12 {
-    //         _previous_block = next_block.clone();
-    //         let utxo_a = Utxo {
-    //             amount: (3 * i).into(),
-    //             public_key: other_wallet.get_public_key(),
-    //         };
-    //         let utxo_b = Utxo {
-    //             amount: (3 * i + 1).into(),
-    //             public_key: other_wallet.get_public_key(),
-    //         };
-    //         let utxo_c = Utxo {
-    //             amount: (3 * i + 2).into(),
-    //             public_key: other_wallet.get_public_key(),
-    //         };
-    //         let other_transaction = other_global_state
-    //             .create_transaction(vec![utxo_a, utxo_b, utxo_c], 1.into())
-    //             .await
-    //             .unwrap();
-    //         next_block = make_mock_block(&_previous_block, None, other_wallet.get_public_key());
-
-    //         next_block.authority_merge_transaction(other_transaction);
-    //         assert!(
-    //             next_block.is_valid_for_devnet(&_previous_block),
-    //             "Produced block must be valid after merging new transaction"
-    //         );
-
-    //         // Update other's global state with this transaction, such that a new transaction
-    //         // can be made in the next iteration of the loop.
-    //         {
-    //             let mut light_state = other_global_state
-    //                 .chain
-    //                 .light_state
-    //                 .latest_block
-    //                 .lock()
-    //                 .await;
-    //             *light_state = next_block.clone();
-    //             other_global_state
-    //                 .wallet_state
-    //                 .update_wallet_state_with_new_block(
-    //                     &next_block,
-    //                     &mut other_global_state.wallet_state.wallet_db.lock().await,
-    //                 )
-    //                 .unwrap();
-    //         }
-
-    //         // After each new block, "our" transaction is updated with the information
-    //         // from that block such that its mutator set data is kept up-to-date.
-    //         updated_tx.update_ms_data(&next_block).unwrap();
-    //     }
-
-    //     _previous_block = next_block.clone();
-    //     next_block = make_mock_block(&next_block, None, other_wallet.get_public_key());
-    //     next_block.authority_merge_transaction(updated_tx.clone());
-    //     assert!(
-    //         next_block.is_valid_for_devnet(&_previous_block),
-    //         "Block is valid when merged transaction is updated"
-    //     );
-
-    //     Ok(())
-    // }
 }
@@ -147,10 +147,10 @@ impl NeptuneCoins {
         NeptuneCoins(factor_as_u128 * self.0)
     }
 
-    /// Add two amounts of Neptune coins but return None if the top bit in the sum is set
-    /// (which would make the sum negative)
+    /// Add two amounts of Neptune coins but return None if the sum overflows, or if the
+    /// top bit in the sum is set (which would make the sum negative)
     pub fn safe_add(&self, other: NeptuneCoins) -> Option<NeptuneCoins> {
-        let number = self.0 + other.0;
+        let number = self.0.checked_add(other.0)?;
         if number & (1u128 << 127) == 0 {
             Some(NeptuneCoins(number))
         } else {
@@ -541,4 +541,12 @@ mod amount_tests {
         let a1 = NeptuneCoins(1u128 << 126);
         assert!(a0.safe_add(a1).is_none());
     }
+
+    #[test]
+    fn overflowing_amounts_fail() {
+        let a0 = NeptuneCoins(u128::MAX);
+        let a1 = NeptuneCoins(1u128 << 127);
+        assert!(a0.safe_add(a1).is_none());
+        assert!(a1.safe_add(a0).is_none());
+    }
 }
@@ -1,6 +1,8 @@
 use crate::prelude::twenty_first;
 
+use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
+use std::ops::Range;
 
 use twenty_first::amount::u32s::U32s;
 use twenty_first::math::digest::Digest;
@@ -8,7 +10,7 @@ use twenty_first::math::digest::Digest;
 use super::blockchain::block::block_header::PROOF_OF_WORK_COUNT_U32_SIZE;
 use super::blockchain::block::{block_height::BlockHeight, Block};
 use super::blockchain::transaction::Transaction;
-use super::peer::TransactionNotification;
+use super::peer::TransactionNotificationWithFee;
 use super::state::wallet::utxo_notification_pool::ExpectedUtxo;
 
 #[derive(Clone, Debug)]
@@ -30,10 +32,21 @@ pub enum MainToMiner {
     // SetCoinbasePubkey,
 }
 
+/// The transactions that the miner merged into the transaction of a block:
+/// the coinbase transaction, and the transactions taken from the mempool in
+/// the order in which they were merged. Lets peers rebuild the block from
+/// their own mempools.
+#[derive(Clone, Debug)]
+pub struct MinedBlockTransactions {
+    pub coinbase: Transaction,
+    pub mempool_transactions: Vec<Transaction>,
+}
+
 #[derive(Clone, Debug)]
 pub struct NewBlockFound {
     pub block: Box<Block>,
     pub coinbase_utxo_info: Box<ExpectedUtxo>,
+    pub block_transactions: Box<MinedBlockTransactions>,
 }
 
 #[derive(Clone, Debug)]
@@ -41,16 +54,34 @@ pub enum MinerToMain {
     NewBlockFound(NewBlockFound),
 }
 
+/// How often the miner did not get the expected `ReadyToMineNextBlock` after
+/// handing a found block to `main_loop`, by how it recovered.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MinerRecoveries {
+    /// `main_loop` sent another block, which the miner continued on
+    pub new_block_while_waiting: u64,
+
+    /// `main_loop` sent another message, which the miner handled as usual
+    pub other_message_while_waiting: u64,
+
+    /// `main_loop` did not answer in time, and the miner continued on the tip
+    pub timeouts: u64,
+}
+
 #[derive(Clone, Debug)]
 pub enum MainToPeerThread {
     Block(Box<Block>),
     RequestBlockBatch(Vec<Digest>, SocketAddr), // (most canonical known digests, peer_socket_to_request)
+    RequestBlockHeaders(Range<BlockHeight>, SocketAddr), // (heights of headers, peer_socket_to_request)
     PeerSynchronizationTimeout(SocketAddr), // sanction a peer for failing to respond to sync request
     MakePeerDiscoveryRequest,               // Request peer list from connected peers
     MakeSpecificPeerDiscoveryRequest(SocketAddr), // Request peers from a specific peer to get peers further away
-    TransactionNotification(TransactionNotification), // Publish knowledge of a transaction
+    TransactionNotification(TransactionNotificationWithFee), // Publish knowledge of a transaction
     Disconnect(SocketAddr),                       // Disconnect from a specific peer
-    DisconnectAll(),                              // Disconnect from all peers
+    RequestMempoolInventory(SocketAddr), // Ask a specific peer for the transactions in its mempool
+    RequestBlockNotification(SocketAddr), // Ask a specific peer for its tip
+    RequestTransaction(Digest, SocketAddr), // Ask a specific peer for an announced transaction
+    DisconnectAll(),                     // Disconnect from all peers
 }
 
 impl MainToPeerThread {
@@ -58,6 +89,7 @@ impl MainToPeerThread {
         match self {
             MainToPeerThread::Block(_) => "block".to_string(),
             MainToPeerThread::RequestBlockBatch(_, _) => "req block batch".to_string(),
+            MainToPeerThread::RequestBlockHeaders(_, _) => "req block headers".to_string(),
             MainToPeerThread::PeerSynchronizationTimeout(_) => "peer sync timeout".to_string(),
             MainToPeerThread::MakePeerDiscoveryRequest => "make peer discovery req".to_string(),
             MainToPeerThread::MakeSpecificPeerDiscoveryRequest(_) => {
@@ -65,6 +97,9 @@ impl MainToPeerThread {
             }
             MainToPeerThread::TransactionNotification(_) => "transaction notification".to_string(),
             MainToPeerThread::Disconnect(_) => "disconnect".to_string(),
+            MainToPeerThread::RequestMempoolInventory(_) => "req mempool inventory".to_string(),
+            MainToPeerThread::RequestBlockNotification(_) => "req block notification".to_string(),
+            MainToPeerThread::RequestTransaction(..) => "req transaction".to_string(),
             MainToPeerThread::DisconnectAll() => "disconnect all".to_string(),
         }
     }
@@ -77,6 +112,9 @@ pub enum PeerThreadToMain {
     RemovePeerMaxBlockHeight(SocketAddr),
     PeerDiscoveryAnswer((Vec<(SocketAddr, u128)>, SocketAddr, u8)), // ([(peer_listen_address)], reported_by, distance)
     Transaction(Box<PeerThreadToMainTransaction>),
+
+    /// A transaction built against a mutator set that is not known yet
+    OrphanTransaction(Box<Transaction>),
 }
 
 #[derive(Clone, Debug)]
@@ -95,6 +133,7 @@ impl PeerThreadToMain {
             }
             PeerThreadToMain::PeerDiscoveryAnswer(_) => "peer discovery answer".to_string(),
             PeerThreadToMain::Transaction(_) => "transaction".to_string(),
+            PeerThreadToMain::OrphanTransaction(_) => "orphan transaction".to_string(),
         }
     }
 }
@@ -105,6 +144,8 @@ pub enum RPCServerToMain {
     Shutdown,
     PauseMiner,
     RestartMiner,
+    Quiesce,
+    Resume,
 }
 
 impl RPCServerToMain {
@@ -114,6 +155,8 @@ impl RPCServerToMain {
             RPCServerToMain::Shutdown => "shutdown".to_string(),
             RPCServerToMain::PauseMiner => "pause miner".to_owned(),
             RPCServerToMain::RestartMiner => "restart miner".to_owned(),
+            RPCServerToMain::Quiesce => "quiesce".to_owned(),
+            RPCServerToMain::Resume => "resume".to_owned(),
         }
     }
 }
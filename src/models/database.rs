@@ -1,13 +1,18 @@
 use crate::prelude::twenty_first;
 
 use serde::{Deserialize, Serialize};
-use std::{fmt, net::IpAddr};
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime},
+};
 use twenty_first::math::digest::Digest;
 
 use super::blockchain::block::block_header::BlockHeader;
 use super::blockchain::block::block_height::BlockHeight;
 use super::consensus::timestamp::Timestamp;
-use super::peer::PeerStanding;
+use super::peer::{KnownPeer, PeerStanding};
+use super::state::address_book::AddressRecord;
 use crate::database::NeptuneLevelDb;
 
 pub const DATABASE_DIRECTORY_ROOT_NAME: &str = "databases";
@@ -78,11 +83,17 @@ pub struct LastFileRecord {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum BlockIndexKey {
-    Block(Digest),       // points to block headers and file locations
-    File(u32),           // points to file information
-    Height(BlockHeight), // Maps from block height to list of blocks
-    LastFile,            // points to last file used
-    BlockTipDigest,      // points to block digest of most canonical block known
+    Block(Digest),           // points to block headers and file locations
+    File(u32),               // points to file information
+    Height(BlockHeight),     // Maps from block height to list of blocks
+    LastFile,                // points to last file used
+    BlockTipDigest,          // points to block digest of most canonical block known
+    WitnessPruneHeight,      // points to height up to which transaction witnesses were pruned
+    PendingBlockFileRewrite, // points to block file whose rewritten version is to replace it
+    BodyDigest(Digest),      // points to the Merkle root of the block body
+    HexPrefix(u16),          // Maps from leading hex digits to digests of blocks starting with them
+    HexPrefixIndexComplete,  // present once blocks stored before the hex prefix index are in it
+    Uncles(Digest),          // points to the digests of the uncles a block references
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -92,6 +103,12 @@ pub enum BlockIndexValue {
     Height(Vec<Digest>),
     LastFile(LastFileRecord),
     BlockTipDigest(Digest),
+    WitnessPruneHeight(BlockHeight),
+    PendingBlockFileRewrite(u32),
+    BodyDigest(Digest),
+    HexPrefix(Vec<Digest>),
+    HexPrefixIndexComplete,
+    Uncles(Vec<Digest>),
 }
 
 impl BlockIndexValue {
@@ -129,11 +146,48 @@ impl BlockIndexValue {
             _ => panic!("Requested BlockTipDigest, found {:?}", self),
         }
     }
+
+    pub fn as_witness_prune_height(&self) -> BlockHeight {
+        match self {
+            BlockIndexValue::WitnessPruneHeight(height) => height.to_owned(),
+            _ => panic!("Requested WitnessPruneHeight, found {:?}", self),
+        }
+    }
+
+    pub fn as_pending_block_file_rewrite(&self) -> u32 {
+        match self {
+            BlockIndexValue::PendingBlockFileRewrite(file_index) => *file_index,
+            _ => panic!("Requested PendingBlockFileRewrite, found {:?}", self),
+        }
+    }
+
+    pub fn as_body_digest(&self) -> Digest {
+        match self {
+            BlockIndexValue::BodyDigest(digest) => digest.to_owned(),
+            _ => panic!("Requested BodyDigest, found {:?}", self),
+        }
+    }
+
+    pub fn as_hex_prefix_record(&self) -> Vec<Digest> {
+        match self {
+            BlockIndexValue::HexPrefix(digests) => digests.to_owned(),
+            _ => panic!("Requested HexPrefix, found {:?}", self),
+        }
+    }
+
+    pub fn as_uncles(&self) -> Vec<Digest> {
+        match self {
+            BlockIndexValue::Uncles(digests) => digests.to_owned(),
+            _ => panic!("Requested Uncles, found {:?}", self),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct PeerDatabases {
     pub peer_standings: NeptuneLevelDb<IpAddr, PeerStanding>,
+    pub known_peers: NeptuneLevelDb<SocketAddr, KnownPeer>,
+    pub address_book: NeptuneLevelDb<SocketAddr, AddressRecord>,
 }
 
 impl fmt::Debug for PeerDatabases {
@@ -141,3 +195,24 @@ impl fmt::Debug for PeerDatabases {
         f.debug_struct("").finish()
     }
 }
+
+/// The outcome of a garbage collection of the peer databases, which removes
+/// the entries that were not updated within the retention window.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerDatabaseGcReport {
+    pub collected_at: SystemTime,
+    pub retention: Duration,
+    pub peer_standings_removed: usize,
+    pub known_peers_removed: usize,
+    pub addresses_removed: usize,
+}
+
+/// Number of entries in each peer database, and the outcome of their latest
+/// garbage collection.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DbStats {
+    pub peer_standings_count: usize,
+    pub known_peers_count: usize,
+    pub address_book_count: usize,
+    pub latest_peer_database_gc: Option<PeerDatabaseGcReport>,
+}
@@ -1,9 +1,11 @@
 use crate::prelude::twenty_first;
 
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt::Display;
 use std::net::SocketAddr;
-use std::time::SystemTime;
+use std::ops::Range;
+use std::time::{Duration, Instant, SystemTime};
 use twenty_first::math::digest::Digest;
 
 use twenty_first::amount::u32s::U32s;
@@ -15,6 +17,7 @@ use super::blockchain::block::transfer_block::TransferBlock;
 use super::blockchain::block::Block;
 use super::blockchain::shared::Hash;
 use super::blockchain::transaction::Transaction;
+use super::blockchain::type_scripts::neptune_coins::NeptuneCoins;
 use crate::config_models::network::Network;
 
 const BAD_BLOCK_BATCH_REQUEST_SEVERITY: u16 = 10;
@@ -28,6 +31,15 @@ const UNKNOWN_BLOCK_HEIGHT: u16 = 1;
 const INVALID_TRANSACTION: u16 = 10;
 const UNCONFIRMABLE_TRANSACTION: u16 = 2;
 const NO_STANDING_FOUND_MAYBE_CRASH: u16 = 10;
+const FALSE_PROOF_OF_WORK_CLAIM_SEVERITY: u16 = 50;
+const UNREQUESTED_MESSAGE_SEVERITY: u16 = 1;
+const HANDSHAKE_TIMEOUT_SEVERITY: u16 = 5;
+const INVALID_BLOCK_TIMESTAMP_SEVERITY: u16 = 5;
+const INVALID_COMPACT_BLOCK_SEVERITY: u16 = 5;
+const FLOODED_MEMPOOL_INVENTORY_REQUEST_SEVERITY: u16 = 2;
+const FLOODED_PING_SEVERITY: u16 = 2;
+const INVALID_BLOCK_REQUEST_THROTTLE_SEVERITY: u16 = 5;
+const BLOCK_DIGEST_MISMATCH_SEVERITY: u16 = 10;
 
 pub type InstanceId = u128;
 
@@ -40,7 +52,9 @@ pub struct PeerInfo {
     pub last_seen: SystemTime,
     pub standing: PeerStanding,
     pub version: String,
+    pub protocol_version: u32,
     pub is_archival_node: bool,
+    pub statistics: PeerStatistics,
 }
 
 impl PeerInfo {
@@ -52,6 +66,145 @@ impl PeerInfo {
     }
 }
 
+/// Counters of the messages exchanged with a connected peer.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct PeerTraffic {
+    /// Number of messages sent, by message type
+    pub messages_sent: BTreeMap<String, u64>,
+
+    /// Number of messages received, by message type
+    pub messages_received: BTreeMap<String, u64>,
+
+    /// Size of the encoding of the messages sent, excluding framing, as
+    /// counted by the codec
+    pub bytes_sent: u64,
+
+    /// Size of the encoding of the messages received, excluding framing, as
+    /// counted by the codec
+    pub bytes_received: u64,
+    pub blocks_received: u64,
+    pub transactions_received: u64,
+}
+
+impl PeerTraffic {
+    pub fn record_sent(&mut self, message: &PeerMessage) {
+        *self.messages_sent.entry(message.get_type()).or_insert(0) += 1;
+    }
+
+    pub fn record_received(&mut self, message: &PeerMessage) {
+        *self
+            .messages_received
+            .entry(message.get_type())
+            .or_insert(0) += 1;
+        match message {
+            PeerMessage::Block(_) => self.blocks_received += 1,
+            PeerMessage::BlockResponseBatch(blocks) => self.blocks_received += blocks.len() as u64,
+            PeerMessage::Transaction(_) => self.transactions_received += 1,
+            PeerMessage::BlockTransactions(transactions) => {
+                self.transactions_received += transactions.len() as u64
+            }
+            _ => (),
+        }
+    }
+}
+
+/// What this node observed of a connected peer, for operators to judge how
+/// useful the peer is.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct PeerStatistics {
+    /// Published by the peer thread every ping interval
+    pub traffic: PeerTraffic,
+
+    /// Round-trip time of the latest answered ping
+    pub latest_round_trip: Option<Duration>,
+
+    /// Number of sanctions during this connection
+    pub sanction_count: u64,
+
+    /// Blocks served to the peer, and their size
+    pub blocks_served: u64,
+    pub block_bytes_served: u64,
+
+    /// Block requests of the peer that exceeded its serving quota
+    pub block_requests_throttled: u64,
+
+    /// Until when the peer asked this node not to request blocks from it
+    pub block_requests_paused_until: Option<SystemTime>,
+}
+
+/// Limits on the blocks that a node serves to a single peer within a rolling
+/// window, such that a syncing peer cannot saturate its disk reads.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct BlockServingPolicy {
+    pub max_blocks: u64,
+    pub max_bytes: u64,
+    pub window: Duration,
+}
+
+impl BlockServingPolicy {
+    /// The policy with its window capped at `max_window`, for policies
+    /// announced by peers.
+    pub fn with_window_capped_at(self, max_window: Duration) -> Self {
+        Self {
+            window: self.window.min(max_window),
+            ..self
+        }
+    }
+}
+
+/// The blocks exchanged with a peer within the window of a
+/// [BlockServingPolicy]. Tracks the blocks served to a peer, as well as the
+/// blocks received from a peer, to pace the requests to it.
+///
+/// Measured on the tokio clock, such that tests can advance it.
+#[derive(Clone, Debug, Default)]
+pub struct BlockServingQuota {
+    // Time and size of each block, oldest first
+    blocks: VecDeque<(tokio::time::Instant, u64)>,
+}
+
+impl BlockServingQuota {
+    pub fn record(&mut self, now: tokio::time::Instant, size: u64) {
+        self.blocks.push_back((now, size));
+    }
+
+    /// Forget the blocks that left the window of the given policy at time
+    /// `now`. Returns true iff no blocks are left.
+    pub fn expire(&mut self, policy: &BlockServingPolicy, now: tokio::time::Instant) -> bool {
+        while self
+            .blocks
+            .front()
+            .is_some_and(|(exchanged_at, _size)| now.duration_since(*exchanged_at) >= policy.window)
+        {
+            self.blocks.pop_front();
+        }
+
+        self.blocks.is_empty()
+    }
+
+    /// Return how long to wait at time `now` before more blocks can be
+    /// exchanged under the given policy, or None if the quota is not used up.
+    pub fn retry_after(
+        &mut self,
+        policy: &BlockServingPolicy,
+        now: tokio::time::Instant,
+    ) -> Option<Duration> {
+        self.expire(policy, now);
+
+        let bytes: u64 = self.blocks.iter().map(|(_exchanged_at, size)| size).sum();
+        if (self.blocks.len() as u64) < policy.max_blocks && bytes < policy.max_bytes {
+            return None;
+        }
+
+        // Wait for the oldest block to leave the window
+        let retry_after = match self.blocks.front() {
+            Some((oldest, _size)) => policy.window.saturating_sub(now.duration_since(*oldest)),
+            None => policy.window,
+        };
+        Some(retry_after)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum PeerSanctionReason {
     InvalidBlock((BlockHeight, Digest)),
@@ -64,11 +217,26 @@ pub enum PeerSanctionReason {
     InvalidMessage,
     NonMinedTransactionHasCoinbase,
     TooShortBlockBatch,
+    TooLongBlockBatch,
     ReceivedBatchBlocksOutsideOfSync,
     BatchBlocksInvalidStartHeight,
     BatchBlocksUnknownRequest,
+    BatchBlocksNotConnected,
     InvalidTransaction,
     UnconfirmableTransaction,
+    InvalidBlockHeader(BlockHeight),
+    BatchBlocksNotInHeaderChain,
+    FalseProofOfWorkClaim(BlockHeight),
+    UnrequestedMessage,
+    HandshakeTimeout,
+    InvalidBlockTimestamp(BlockHeight),
+    InvalidCompactBlock(BlockHeight),
+    TooLongBlockTransactionsRequest,
+    FloodMempoolInventoryRequest,
+    FloodPing,
+    InvalidBlockRequestThrottle,
+    BlockDigestMismatch(BlockHeight),
+    NegativeFeeTransaction,
 
     NoStandingFoundMaybeCrash,
 }
@@ -84,6 +252,7 @@ impl Display for PeerSanctionReason {
             PeerSanctionReason::BlockRequestUnknownHeight => "block request unknown height",
             PeerSanctionReason::InvalidMessage => "invalid message",
             PeerSanctionReason::TooShortBlockBatch => "too short block batch",
+            PeerSanctionReason::TooLongBlockBatch => "too long block batch",
             PeerSanctionReason::ReceivedBatchBlocksOutsideOfSync => {
                 "received block batch outside of sync"
             }
@@ -91,8 +260,24 @@ impl Display for PeerSanctionReason {
                 "invalid start height of batch blocks"
             }
             PeerSanctionReason::BatchBlocksUnknownRequest => "batch blocks unkonwn request",
+            PeerSanctionReason::BatchBlocksNotConnected => "batch blocks not connected",
             PeerSanctionReason::InvalidTransaction => "invalid transaction",
             PeerSanctionReason::UnconfirmableTransaction => "unconfirmable transaction",
+            PeerSanctionReason::InvalidBlockHeader(_) => "invalid block header",
+            PeerSanctionReason::BatchBlocksNotInHeaderChain => "batch blocks not in header chain",
+            PeerSanctionReason::FalseProofOfWorkClaim(_) => "false proof-of-work claim",
+            PeerSanctionReason::UnrequestedMessage => "unrequested message",
+            PeerSanctionReason::HandshakeTimeout => "handshake timeout",
+            PeerSanctionReason::InvalidBlockTimestamp(_) => "invalid block timestamp",
+            PeerSanctionReason::InvalidCompactBlock(_) => "invalid compact block",
+            PeerSanctionReason::TooLongBlockTransactionsRequest => {
+                "too long block transactions request"
+            }
+            PeerSanctionReason::FloodMempoolInventoryRequest => "flood mempool inventory request",
+            PeerSanctionReason::FloodPing => "flood ping",
+            PeerSanctionReason::InvalidBlockRequestThrottle => "invalid block request throttle",
+            PeerSanctionReason::BlockDigestMismatch(_) => "block digest mismatch",
+            PeerSanctionReason::NegativeFeeTransaction => "transaction with negative fee",
             PeerSanctionReason::NonMinedTransactionHasCoinbase => {
                 "non-mined transaction has coinbase"
             }
@@ -141,12 +326,31 @@ impl PeerSanctionReason {
             PeerSanctionReason::FloodPeerListResponse => FLOODED_PEER_LIST_RESPONSE_SEVERITY,
             PeerSanctionReason::InvalidMessage => INVALID_MESSAGE_SEVERITY,
             PeerSanctionReason::TooShortBlockBatch => INVALID_MESSAGE_SEVERITY,
+            PeerSanctionReason::TooLongBlockBatch => INVALID_MESSAGE_SEVERITY,
             PeerSanctionReason::ReceivedBatchBlocksOutsideOfSync => INVALID_MESSAGE_SEVERITY,
             PeerSanctionReason::BatchBlocksInvalidStartHeight => INVALID_MESSAGE_SEVERITY,
             PeerSanctionReason::BatchBlocksUnknownRequest => BAD_BLOCK_BATCH_REQUEST_SEVERITY,
+            PeerSanctionReason::BatchBlocksNotConnected => INVALID_BLOCK_SEVERITY,
             PeerSanctionReason::BlockRequestUnknownHeight => UNKNOWN_BLOCK_HEIGHT,
             PeerSanctionReason::InvalidTransaction => INVALID_TRANSACTION,
             PeerSanctionReason::UnconfirmableTransaction => UNCONFIRMABLE_TRANSACTION,
+            PeerSanctionReason::InvalidBlockHeader(_) => INVALID_BLOCK_SEVERITY,
+            PeerSanctionReason::BatchBlocksNotInHeaderChain => INVALID_BLOCK_SEVERITY,
+            PeerSanctionReason::FalseProofOfWorkClaim(_) => FALSE_PROOF_OF_WORK_CLAIM_SEVERITY,
+            PeerSanctionReason::UnrequestedMessage => UNREQUESTED_MESSAGE_SEVERITY,
+            PeerSanctionReason::HandshakeTimeout => HANDSHAKE_TIMEOUT_SEVERITY,
+            PeerSanctionReason::InvalidBlockTimestamp(_) => INVALID_BLOCK_TIMESTAMP_SEVERITY,
+            PeerSanctionReason::InvalidCompactBlock(_) => INVALID_COMPACT_BLOCK_SEVERITY,
+            PeerSanctionReason::TooLongBlockTransactionsRequest => INVALID_MESSAGE_SEVERITY,
+            PeerSanctionReason::FloodMempoolInventoryRequest => {
+                FLOODED_MEMPOOL_INVENTORY_REQUEST_SEVERITY
+            }
+            PeerSanctionReason::FloodPing => FLOODED_PING_SEVERITY,
+            PeerSanctionReason::InvalidBlockRequestThrottle => {
+                INVALID_BLOCK_REQUEST_THROTTLE_SEVERITY
+            }
+            PeerSanctionReason::BlockDigestMismatch(_) => BLOCK_DIGEST_MISMATCH_SEVERITY,
+            PeerSanctionReason::NegativeFeeTransaction => INVALID_TRANSACTION,
             PeerSanctionReason::NonMinedTransactionHasCoinbase => INVALID_TRANSACTION,
             PeerSanctionReason::NoStandingFoundMaybeCrash => NO_STANDING_FOUND_MAYBE_CRASH,
         }
@@ -180,6 +384,25 @@ impl PeerStanding {
         self.standing.is_negative()
     }
 
+    /// Whether the latest sanction happened at least `ban_duration` before
+    /// `now`, after which the standing is forgotten.
+    pub fn has_expired(&self, ban_duration: Duration, now: SystemTime) -> bool {
+        self.timestamp_of_latest_sanction
+            .is_some_and(|sanctioned_at| {
+                now.duration_since(sanctioned_at)
+                    .is_ok_and(|elapsed| elapsed >= ban_duration)
+            })
+    }
+
+    /// The standing as of `now`, which is the default standing if it has
+    /// expired. Standings never expire without a `ban_duration`.
+    pub fn as_of(self, ban_duration: Option<Duration>, now: SystemTime) -> Self {
+        match ban_duration {
+            Some(ban_duration) if self.has_expired(ban_duration, now) => Self::default(),
+            _ => self,
+        }
+    }
+
     pub fn new_on_no_standing_found_in_map() -> Self {
         Self {
             standing: -(NO_STANDING_FOUND_MAYBE_CRASH as i32),
@@ -189,16 +412,129 @@ impl PeerStanding {
     }
 }
 
+/// A peer that this node completed a handshake with. It is stored in the
+/// database, keyed by the address the peer listens on, such that the node can
+/// reconnect to it after a restart.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KnownPeer {
+    pub instance_id: InstanceId,
+    pub last_seen: SystemTime,
+}
+
+/// Version of the peer-to-peer protocol spoken by this node. Must be bumped
+/// whenever peer messages change in a way that older nodes cannot handle.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version spoken by peers that this node can still talk to.
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = 1;
+
+/// The services a node offers to its peers, announced in the handshake.
+/// Unknown bits are ignored, such that features can be added without bumping
+/// the protocol version.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct PeerFeatures(u64);
+
+impl PeerFeatures {
+    /// Stores all blocks and the archival mutator set
+    pub const ARCHIVAL: Self = Self(1 << 0);
+
+    /// Relays transactions
+    pub const TRANSACTION_RELAY: Self = Self(1 << 1);
+
+    /// Answers `Ping` messages
+    pub const PING: Self = Self(1 << 2);
+
+    /// Understands `BlockRequestThrottled` messages
+    pub const BLOCK_SERVING_QUOTA: Self = Self(1 << 3);
+
+    /// Answers `BlockRequestBatchByHeight` messages
+    pub const BLOCK_BATCH_BY_HEIGHT: Self = Self(1 << 4);
+
+    /// Opted into encoding the messages following the handshake as JSON, see
+    /// [`PeerCodec::negotiate`](crate::peer_codec::PeerCodec::negotiate)
+    pub const JSON_CODEC: Self = Self(1 << 5);
+
+    /// Understands `TransactionNotificationWithFee` messages
+    pub const TRANSACTION_FEE_NOTIFICATION: Self = Self(1 << 6);
+
+    pub fn contains(&self, features: Self) -> bool {
+        self.0 & features.0 == features.0
+    }
+
+    pub fn with(self, features: Self) -> Self {
+        Self(self.0 | features.0)
+    }
+
+    pub fn is_archival_node(&self) -> bool {
+        self.contains(Self::ARCHIVAL)
+    }
+}
+
+/// The handshake sent by both sides of a connection.
+///
+/// Nodes that predate protocol versions send a [`LegacyHandshakeData`] in
+/// place of this. It is decoded as protocol version 0, such that these nodes
+/// are refused for their incompatible version.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct HandshakeData {
     pub tip_header: BlockHeader,
     pub listen_port: Option<u16>,
     pub network: Network,
     pub instance_id: u128,
+
+    /// Release of the software, for display only
+    pub version: String,
+
+    /// Newest protocol version the sender speaks
+    pub protocol_version: u32,
+
+    /// Oldest protocol version the sender can talk to
+    pub min_protocol_version: u32,
+    pub features: PeerFeatures,
+}
+
+impl HandshakeData {
+    /// Whether the two senders have a protocol version in common, i.e.
+    /// whether the ranges between their minimum supported versions and their
+    /// own versions overlap. Both sides thus come to the same conclusion.
+    pub fn is_compatible_with(&self, other: &HandshakeData) -> bool {
+        self.min_protocol_version <= other.protocol_version
+            && other.min_protocol_version <= self.protocol_version
+    }
+}
+
+/// The handshake of nodes that predate protocol versions. Only decoded, to
+/// refuse these nodes with a proper reason.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LegacyHandshakeData {
+    pub tip_header: BlockHeader,
+    pub listen_port: Option<u16>,
+    pub network: Network,
+    pub instance_id: u128,
     pub version: String,
     pub is_archival_node: bool,
 }
 
+impl From<LegacyHandshakeData> for HandshakeData {
+    fn from(legacy: LegacyHandshakeData) -> Self {
+        let features = if legacy.is_archival_node {
+            PeerFeatures::ARCHIVAL
+        } else {
+            PeerFeatures::default()
+        };
+        Self {
+            tip_header: legacy.tip_header,
+            listen_port: legacy.listen_port,
+            network: legacy.network,
+            instance_id: legacy.instance_id,
+            version: legacy.version,
+            protocol_version: 0,
+            min_protocol_version: 0,
+            features,
+        }
+    }
+}
+
 /// Used to tell peers that a new block has been found without having toPeerMessage
 /// send the entire block
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -245,6 +581,8 @@ pub enum ConnectionRefusedReason {
     IncompatibleVersion,
     MaxPeerNumberExceeded,
     SelfConnect,
+    BadNetwork,
+    TooManyConnectionsFromHost,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -272,6 +610,36 @@ impl From<Transaction> for TransactionNotification {
     }
 }
 
+/// A `TransactionNotification` that also carries the fee of the transaction,
+/// such that receivers can decide whether the transaction is worth
+/// requesting. Only sent to peers announcing
+/// `PeerFeatures::TRANSACTION_FEE_NOTIFICATION`, since it is a separate
+/// message on the wire.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TransactionNotificationWithFee {
+    pub transaction_digest: Digest,
+    pub fee: NeptuneCoins,
+}
+
+impl From<Transaction> for TransactionNotificationWithFee {
+    fn from(transaction: Transaction) -> Self {
+        let transaction_digest = Hash::hash(&transaction);
+
+        Self {
+            transaction_digest,
+            fee: transaction.kernel.fee,
+        }
+    }
+}
+
+impl From<TransactionNotificationWithFee> for TransactionNotification {
+    fn from(notification: TransactionNotificationWithFee) -> Self {
+        Self {
+            transaction_digest: notification.transaction_digest,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum PeerMessage {
     Handshake(Box<(Vec<u8>, HandshakeData)>),
@@ -297,6 +665,59 @@ pub enum PeerMessage {
     /// Inform peer that we are disconnecting them.
     Bye,
     ConnectionStatus(ConnectionStatus),
+    /// Request up to `count` consecutive blocks from the canonical chain,
+    /// starting at `start_height`. Answered with a `BlockResponseBatch`.
+    BlockRequestBatchByHeight {
+        start_height: BlockHeight,
+        count: u16,
+    },
+    /// Announce a new block by its digest, its header, the coinbase
+    /// transaction, and the ids of the mempool transactions that were merged
+    /// into its transaction. The receiver rebuilds the block from its own
+    /// mempool, and requests the transactions it does not have with
+    /// `GetBlockTransactions`. The rebuilt block must have the given digest.
+    CompactBlock {
+        digest: Digest,
+        header: BlockHeader,
+        txids: Vec<Digest>,
+        coinbase: Box<Transaction>,
+    },
+    /// Request the transactions with the given ids, which are missing for the
+    /// reconstruction of a compact block. Answered with `BlockTransactions`.
+    GetBlockTransactions(Vec<Digest>),
+    BlockTransactions(Vec<Transaction>),
+    /// Request a summary of the transactions in the peer's mempool. Answered
+    /// with a `MempoolInventoryResponse`.
+    MempoolInventoryRequest,
+    /// (transaction ID, fee, size in bytes) of the transactions in the mempool,
+    /// in descending order of fee density. The receiver can fetch the
+    /// transactions it is missing with `TransactionRequest`.
+    MempoolInventoryResponse(Vec<(Digest, NeptuneCoins, usize)>),
+    /// Request the headers of the blocks in the given range of heights on the
+    /// canonical chain. Answered with a `BlockHeadersResponse`.
+    BlockHeaderRequest(Range<BlockHeight>),
+    /// Consecutive block headers, each with the MAST hash of its block's body,
+    /// since the block digest commits to the body. Lets a syncing node
+    /// validate the header chain before downloading any bodies.
+    BlockHeadersResponse(Vec<(BlockHeader, Digest)>),
+    /// Measure the round-trip time to the peer, and check that it is still
+    /// responsive. Only sent to peers announcing `PeerFeatures::PING`, which
+    /// answer with a `Pong` carrying the same nonce.
+    Ping(u64),
+    Pong(u64),
+    /// Answer to a block request that exceeds the requester's quota under the
+    /// sender's serving policy. The requester should not request blocks from
+    /// the sender for the given number of seconds, and pace its requests
+    /// according to the policy. Only sent to peers announcing
+    /// `PeerFeatures::BLOCK_SERVING_QUOTA`.
+    BlockRequestThrottled {
+        retry_after_in_secs: u64,
+        policy: BlockServingPolicy,
+    },
+    /// A `TransactionNotification` that carries the fee of the transaction.
+    /// Only sent to peers announcing
+    /// `PeerFeatures::TRANSACTION_FEE_NOTIFICATION`.
+    TransactionNotificationWithFee(TransactionNotificationWithFee),
 }
 
 impl PeerMessage {
@@ -317,6 +738,57 @@ impl PeerMessage {
             PeerMessage::PeerListResponse(_) => "peer list resp".to_string(),
             PeerMessage::Bye => "bye".to_string(),
             PeerMessage::ConnectionStatus(_) => "connection status".to_string(),
+            PeerMessage::BlockRequestBatchByHeight { .. } => {
+                "block req batch by height".to_string()
+            }
+            PeerMessage::CompactBlock { .. } => "compact block".to_string(),
+            PeerMessage::GetBlockTransactions(_) => "get block transactions".to_string(),
+            PeerMessage::BlockTransactions(_) => "block transactions".to_string(),
+            PeerMessage::MempoolInventoryRequest => "mempool inventory req".to_string(),
+            PeerMessage::MempoolInventoryResponse(_) => "mempool inventory resp".to_string(),
+            PeerMessage::BlockHeaderRequest(_) => "block header req".to_string(),
+            PeerMessage::BlockHeadersResponse(_) => "block headers resp".to_string(),
+            PeerMessage::Ping(_) => "ping".to_string(),
+            PeerMessage::Pong(_) => "pong".to_string(),
+            PeerMessage::BlockRequestThrottled { .. } => "block req throttled".to_string(),
+            PeerMessage::TransactionNotificationWithFee(_) => {
+                "transaction notification with fee".to_string()
+            }
+        }
+    }
+
+    /// Whether the message carries a payload that must not be written to
+    /// debugging output, such as peer traffic transcripts. Transactions may
+    /// carry secret witness data, and handshakes identify the node.
+    pub fn is_sensitive(&self) -> bool {
+        match self {
+            PeerMessage::Handshake(_) => true,
+            PeerMessage::Block(_) => false,
+            PeerMessage::BlockNotificationRequest => false,
+            PeerMessage::BlockNotification(_) => false,
+            PeerMessage::BlockRequestByHeight(_) => false,
+            PeerMessage::BlockRequestByHash(_) => false,
+            PeerMessage::BlockRequestBatch(_, _) => false,
+            PeerMessage::BlockResponseBatch(_) => false,
+            PeerMessage::Transaction(_) => true,
+            PeerMessage::TransactionNotification(_) => false,
+            PeerMessage::TransactionRequest(_) => false,
+            PeerMessage::PeerListRequest => false,
+            PeerMessage::PeerListResponse(_) => false,
+            PeerMessage::Bye => false,
+            PeerMessage::ConnectionStatus(_) => false,
+            PeerMessage::BlockRequestBatchByHeight { .. } => false,
+            PeerMessage::CompactBlock { .. } => true,
+            PeerMessage::GetBlockTransactions(_) => false,
+            PeerMessage::BlockTransactions(_) => true,
+            PeerMessage::MempoolInventoryRequest => false,
+            PeerMessage::MempoolInventoryResponse(_) => false,
+            PeerMessage::BlockHeaderRequest(_) => false,
+            PeerMessage::BlockHeadersResponse(_) => false,
+            PeerMessage::Ping(_) => false,
+            PeerMessage::Pong(_) => false,
+            PeerMessage::BlockRequestThrottled { .. } => false,
+            PeerMessage::TransactionNotificationWithFee(_) => false,
         }
     }
 
@@ -337,6 +809,18 @@ impl PeerMessage {
             PeerMessage::PeerListResponse(_) => false,
             PeerMessage::Bye => false,
             PeerMessage::ConnectionStatus(_) => false,
+            PeerMessage::BlockRequestBatchByHeight { .. } => false,
+            PeerMessage::CompactBlock { .. } => false,
+            PeerMessage::GetBlockTransactions(_) => false,
+            PeerMessage::BlockTransactions(_) => false,
+            PeerMessage::MempoolInventoryRequest => false,
+            PeerMessage::MempoolInventoryResponse(_) => false,
+            PeerMessage::BlockHeaderRequest(_) => false,
+            PeerMessage::BlockHeadersResponse(_) => true,
+            PeerMessage::Ping(_) => false,
+            PeerMessage::Pong(_) => false,
+            PeerMessage::BlockRequestThrottled { .. } => false,
+            PeerMessage::TransactionNotificationWithFee(_) => false,
         }
     }
 
@@ -358,15 +842,155 @@ impl PeerMessage {
             PeerMessage::PeerListResponse(_) => false,
             PeerMessage::Bye => false,
             PeerMessage::ConnectionStatus(_) => false,
+            PeerMessage::BlockRequestBatchByHeight { .. } => false,
+            PeerMessage::CompactBlock { .. } => true,
+            PeerMessage::GetBlockTransactions(_) => false,
+            PeerMessage::BlockTransactions(_) => true,
+            PeerMessage::MempoolInventoryRequest => false,
+            PeerMessage::MempoolInventoryResponse(_) => true,
+            PeerMessage::BlockHeaderRequest(_) => false,
+            PeerMessage::BlockHeadersResponse(_) => false,
+            PeerMessage::Ping(_) => false,
+            PeerMessage::Pong(_) => false,
+            PeerMessage::BlockRequestThrottled { .. } => false,
+            PeerMessage::TransactionNotificationWithFee(_) => false,
+        }
+    }
+
+    /// Function to filter out messages that should not be handled while the
+    /// client is quiesced, because handling them would change the state
+    pub fn ignore_when_quiesced(&self) -> bool {
+        match self {
+            PeerMessage::Handshake(_) => false,
+            PeerMessage::Block(_) => true,
+            PeerMessage::BlockNotificationRequest => false,
+            PeerMessage::BlockNotification(_) => true,
+            PeerMessage::BlockRequestByHeight(_) => false,
+            PeerMessage::BlockRequestByHash(_) => false,
+            PeerMessage::BlockRequestBatch(_, _) => false,
+            PeerMessage::BlockResponseBatch(_) => true,
+            PeerMessage::Transaction(_) => true,
+            PeerMessage::TransactionNotification(_) => true,
+            PeerMessage::TransactionRequest(_) => false,
+            PeerMessage::PeerListRequest => false,
+            PeerMessage::PeerListResponse(_) => false,
+            PeerMessage::Bye => false,
+            PeerMessage::ConnectionStatus(_) => false,
+            PeerMessage::BlockRequestBatchByHeight { .. } => false,
+            PeerMessage::CompactBlock { .. } => true,
+            PeerMessage::GetBlockTransactions(_) => false,
+            PeerMessage::BlockTransactions(_) => true,
+            PeerMessage::MempoolInventoryRequest => false,
+            PeerMessage::MempoolInventoryResponse(_) => true,
+            PeerMessage::BlockHeaderRequest(_) => false,
+            PeerMessage::BlockHeadersResponse(_) => true,
+            PeerMessage::Ping(_) => false,
+            PeerMessage::Pong(_) => false,
+            PeerMessage::BlockRequestThrottled { .. } => false,
+            PeerMessage::TransactionNotificationWithFee(_) => true,
         }
     }
 }
 
+/// A compact block whose reconstruction awaits transactions requested from
+/// the peer that announced it.
+#[derive(Clone, Debug)]
+pub struct PendingCompactBlock {
+    pub digest: Digest,
+    pub header: BlockHeader,
+    pub txids: Vec<Digest>,
+    pub coinbase: Transaction,
+}
+
+/// The purpose of a `BlockHeaderRequest` sent to the peer that has not been
+/// answered yet. Peers answer header requests in order, such that each
+/// response belongs to the oldest pending request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PendingHeaderRequest {
+    /// Headers to extend the header chain with while syncing
+    HeaderChain,
+
+    /// Headers from a block this node knows up to the notified block, to
+    /// spot check the proof-of-work claimed in the notification
+    SpotCheck(PeerBlockNotification),
+}
+
+/// A batch of blocks requested from the peer that has not been answered yet
+#[derive(Clone, Debug)]
+pub struct PendingBlockBatch {
+    /// Height of the first requested block, for requests made by height
+    pub start_height: Option<BlockHeight>,
+    pub count: usize,
+
+    /// Set when an earlier batch came back shorter than requested, or did
+    /// not connect, such that the response to this request is discarded
+    pub superseded: bool,
+}
+
+/// The batches of blocks requested by height from a peer while syncing, each
+/// continuing the one before it. A returned batch must descend from `tip`. If
+/// it does not, the peer's canonical chain forked off below it, and the blocks
+/// are requested again by the digests of our most canonical blocks.
+#[derive(Clone, Debug)]
+pub struct BlockBatchWindow {
+    /// Digest and height of the last block received in the window, or of the
+    /// block that the window was opened from
+    pub tip: (Digest, BlockHeight),
+    pub most_canonical_digests: Vec<Digest>,
+}
+
 /// `MutablePeerState` contains the part of the peer-loop's state that is mutable
 #[derive(Clone, Debug)]
 pub struct MutablePeerState {
     pub highest_shared_block_height: BlockHeight,
     pub fork_reconciliation_blocks: Vec<Block>,
+    pub received_bye: bool,
+    pub pending_compact_block: Option<PendingCompactBlock>,
+
+    /// Whether a `MempoolInventoryRequest` was sent to the peer that has not
+    /// been answered yet
+    pub mempool_inventory_requested: bool,
+
+    /// When a `MempoolInventoryRequest` was last sent to, respectively
+    /// answered for, the peer. Used to limit the rate of these requests.
+    pub last_mempool_inventory_request_sent: Option<Instant>,
+    pub last_mempool_inventory_request_answered: Option<Instant>,
+
+    /// Nonce and send time of the `Ping` sent to the peer that has not been
+    /// answered yet
+    pub pending_ping: Option<(u64, Instant)>,
+
+    /// When a `Ping` from the peer was last answered. Used to limit the rate
+    /// of pings.
+    pub last_ping_answered: Option<Instant>,
+
+    /// Header requests sent to the peer that have not been answered yet,
+    /// oldest first
+    pub pending_header_requests: VecDeque<PendingHeaderRequest>,
+
+    /// Whether a single block was requested from the peer, by hash or by
+    /// height, and has not been received yet. Together with the pending
+    /// batch requests, this tells whether the peer may throttle requests.
+    pub block_requested: bool,
+
+    /// The digest of the single block requested by hash, which the block
+    /// received in response must have
+    pub requested_block_digest: Option<Digest>,
+
+    /// Blocks received from the peer in batch responses, limited by the
+    /// peer's serving policy, if it announced one
+    pub received_blocks: BlockServingQuota,
+    pub peer_block_serving_policy: Option<BlockServingPolicy>,
+
+    /// Outstanding batch requests, oldest first. Up to a window of them are
+    /// kept in flight, such that the download is not paced by the round trip
+    /// to the peer.
+    pub pending_block_batches: VecDeque<PendingBlockBatch>,
+    pub block_batch_window: Option<BlockBatchWindow>,
+
+    /// Batches of the window that were received before the main loop stored
+    /// their parent, and are handled once it did
+    pub block_batches_awaiting_parent: VecDeque<Vec<Block>>,
 }
 
 impl MutablePeerState {
@@ -374,6 +998,282 @@ impl MutablePeerState {
         Self {
             highest_shared_block_height: block_height,
             fork_reconciliation_blocks: vec![],
+            received_bye: false,
+            pending_compact_block: None,
+            mempool_inventory_requested: false,
+            last_mempool_inventory_request_sent: None,
+            last_mempool_inventory_request_answered: None,
+            pending_ping: None,
+            last_ping_answered: None,
+            pending_header_requests: VecDeque::new(),
+            block_requested: false,
+            requested_block_digest: None,
+            received_blocks: BlockServingQuota::default(),
+            peer_block_serving_policy: None,
+            pending_block_batches: VecDeque::new(),
+            block_batch_window: None,
+            block_batches_awaiting_parent: VecDeque::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod peer_tests {
+    use super::*;
+
+    use std::pin::Pin;
+
+    use bytes::BytesMut;
+    use rand::random;
+    use tokio_serde::formats::SymmetricalBincode;
+    use tokio_serde::{Deserializer, Serializer};
+
+    use crate::models::consensus::mast_hash::MastHash;
+    use crate::models::state::wallet::WalletSecret;
+    use crate::peer_codec::{PeerCodec, PeerMessageCodec};
+    use crate::tests::shared::{
+        get_dummy_handshake_data_for_genesis, get_dummy_socket_address, make_mock_block,
+        make_mock_transaction,
+    };
+
+    #[tokio::test]
+    async fn all_peer_messages_survive_wire_codecs_round_trip_test() {
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (block_1, _, _) = make_mock_block(network, &genesis_block, None, address, random());
+        let transfer_block = TransferBlock::from(block_1.clone());
+        let transaction = make_mock_transaction(vec![], vec![]);
+
+        let messages = vec![
+            PeerMessage::Handshake(Box::new((
+                crate::MAGIC_STRING_REQUEST.to_vec(),
+                get_dummy_handshake_data_for_genesis(network).await,
+            ))),
+            PeerMessage::Block(Box::new(transfer_block.clone())),
+            PeerMessage::BlockNotificationRequest,
+            PeerMessage::BlockNotification((&block_1).into()),
+            PeerMessage::BlockRequestByHeight(block_1.kernel.header.height),
+            PeerMessage::BlockRequestByHash(block_1.hash()),
+            PeerMessage::BlockRequestBatch(vec![genesis_block.hash(), block_1.hash()], 10),
+            PeerMessage::BlockResponseBatch(vec![transfer_block]),
+            PeerMessage::Transaction(Box::new(transaction.clone())),
+            PeerMessage::TransactionNotification(transaction.clone().into()),
+            PeerMessage::TransactionNotificationWithFee(transaction.clone().into()),
+            PeerMessage::TransactionRequest(Hash::hash(&transaction)),
+            PeerMessage::MempoolInventoryRequest,
+            PeerMessage::MempoolInventoryResponse(vec![(
+                Hash::hash(&transaction),
+                transaction.kernel.fee,
+                transaction.serialized_size(),
+            )]),
+            PeerMessage::PeerListRequest,
+            PeerMessage::PeerListResponse(vec![(get_dummy_socket_address(1), random())]),
+            PeerMessage::Bye,
+            PeerMessage::ConnectionStatus(ConnectionStatus::Accepted),
+            PeerMessage::ConnectionStatus(ConnectionStatus::Refused(
+                ConnectionRefusedReason::BadStanding,
+            )),
+            PeerMessage::BlockRequestBatchByHeight {
+                start_height: BlockHeight::genesis(),
+                count: 50,
+            },
+            PeerMessage::CompactBlock {
+                digest: block_1.hash(),
+                header: block_1.kernel.header.clone(),
+                txids: vec![Hash::hash(&transaction)],
+                coinbase: Box::new(transaction.clone()),
+            },
+            PeerMessage::GetBlockTransactions(vec![Hash::hash(&transaction)]),
+            PeerMessage::BlockTransactions(vec![transaction.clone()]),
+            PeerMessage::BlockHeaderRequest(BlockHeight::genesis()..block_1.kernel.header.height),
+            PeerMessage::BlockHeadersResponse(vec![(
+                block_1.kernel.header.clone(),
+                block_1.kernel.body.mast_hash(),
+            )]),
+            PeerMessage::Ping(random()),
+            PeerMessage::Pong(random()),
+            PeerMessage::BlockRequestThrottled {
+                retry_after_in_secs: 600,
+                policy: BlockServingPolicy {
+                    max_blocks: 1000,
+                    max_bytes: 1 << 30,
+                    window: Duration::from_secs(600),
+                },
+            },
+        ];
+
+        for peer_codec in [PeerCodec::Bincode, PeerCodec::Json] {
+            let (mut codec, codec_switch) = PeerMessageCodec::new();
+            codec_switch.switch_to(peer_codec);
+            for message in messages.iter() {
+                let bytes = Pin::new(&mut codec).serialize(message).unwrap();
+                let decoded = Pin::new(&mut codec)
+                    .deserialize(&BytesMut::from(&bytes[..]))
+                    .unwrap();
+                assert_eq!(
+                    *message,
+                    decoded,
+                    "{} must survive round trip through {peer_codec} codec",
+                    message.get_type()
+                );
+            }
+        }
+    }
+
+    async fn handshake_with_protocol_versions(
+        min_protocol_version: u32,
+        protocol_version: u32,
+    ) -> HandshakeData {
+        let mut handshake = get_dummy_handshake_data_for_genesis(Network::Alpha).await;
+        handshake.min_protocol_version = min_protocol_version;
+        handshake.protocol_version = protocol_version;
+        handshake
+    }
+
+    #[tokio::test]
+    async fn supported_protocol_versions_are_compatible() {
+        let own_handshake = handshake_with_protocol_versions(3, 5).await;
+        for other_version in [3, 4, 5] {
+            let other_handshake = handshake_with_protocol_versions(1, other_version).await;
+            assert!(own_handshake.is_compatible_with(&other_handshake));
+            assert!(other_handshake.is_compatible_with(&own_handshake));
+        }
+
+        // A newer node that still supports this node's version
+        let newer_handshake = handshake_with_protocol_versions(5, 6).await;
+        assert!(own_handshake.is_compatible_with(&newer_handshake));
+        assert!(newer_handshake.is_compatible_with(&own_handshake));
+
+        // Release versions are irrelevant
+        let mut other_handshake = handshake_with_protocol_versions(5, 5).await;
+        "not a version".clone_into(&mut other_handshake.version);
+        assert!(own_handshake.is_compatible_with(&other_handshake));
+    }
+
+    #[tokio::test]
+    async fn unsupported_protocol_versions_are_incompatible() {
+        let own_handshake = handshake_with_protocol_versions(3, 5).await;
+        for (other_min_version, other_version) in [(0, 0), (0, 2), (6, 6), (6, 8)] {
+            let other_handshake =
+                handshake_with_protocol_versions(other_min_version, other_version).await;
+            assert!(!own_handshake.is_compatible_with(&other_handshake));
+            assert!(!other_handshake.is_compatible_with(&own_handshake));
+        }
+    }
+
+    #[test]
+    fn peer_features_test() {
+        let archival_relay = PeerFeatures::ARCHIVAL.with(PeerFeatures::TRANSACTION_RELAY);
+        assert!(archival_relay.is_archival_node());
+        assert!(archival_relay.contains(PeerFeatures::TRANSACTION_RELAY));
+        assert!(!PeerFeatures::TRANSACTION_RELAY.is_archival_node());
+        assert!(!PeerFeatures::default().contains(PeerFeatures::TRANSACTION_RELAY));
+
+        // Unknown features are carried along and ignored
+        let with_unknown_feature: PeerFeatures =
+            bincode::deserialize(&bincode::serialize(&(1u64 << 63 | 1)).unwrap()).unwrap();
+        assert!(with_unknown_feature.is_archival_node());
+        assert!(!with_unknown_feature.contains(PeerFeatures::TRANSACTION_RELAY));
+    }
+
+    #[test]
+    fn peer_traffic_is_counted_by_message_type_test() {
+        let mut traffic = PeerTraffic::default();
+        let transaction = make_mock_transaction(vec![], vec![]);
+        traffic.record_received(&PeerMessage::BlockTransactions(vec![
+            transaction.clone(),
+            transaction.clone(),
+        ]));
+        traffic.record_received(&PeerMessage::Transaction(Box::new(transaction)));
+        traffic.record_received(&PeerMessage::Ping(1));
+        traffic.record_sent(&PeerMessage::Pong(1));
+        traffic.record_sent(&PeerMessage::Pong(2));
+
+        assert_eq!(3, traffic.transactions_received);
+        assert_eq!(0, traffic.blocks_received);
+        assert_eq!(Some(&1), traffic.messages_received.get("ping"));
+        assert_eq!(Some(&2), traffic.messages_sent.get("pong"));
+    }
+
+    #[tokio::test]
+    async fn legacy_handshakes_are_not_understood_test() {
+        let handshake = get_dummy_handshake_data_for_genesis(Network::Alpha).await;
+        let legacy_handshake = LegacyHandshakeData {
+            tip_header: handshake.tip_header.clone(),
+            listen_port: handshake.listen_port,
+            network: handshake.network,
+            instance_id: handshake.instance_id,
+            version: handshake.version.clone(),
+            is_archival_node: true,
+        };
+
+        // Handshakes round trip
+        let decoded_handshake: HandshakeData =
+            bincode::deserialize(&bincode::serialize(&handshake).unwrap()).unwrap();
+        assert_eq!(handshake, decoded_handshake);
+
+        // Legacy handshakes, alone and as the payload of the handshake
+        // message, do not decode as current ones. Only the fallback of
+        // `PeerMessageCodec` understands them.
+        assert!(bincode::deserialize::<HandshakeData>(
+            &bincode::serialize(&legacy_handshake).unwrap()
+        )
+        .is_err());
+        let legacy_message = (
+            0u32, // variant index of `PeerMessage::Handshake`
+            crate::MAGIC_STRING_REQUEST.to_vec(),
+            legacy_handshake,
+        );
+        let legacy_bytes =
+            Pin::new(&mut SymmetricalBincode::<(u32, Vec<u8>, LegacyHandshakeData)>::default())
+                .serialize(&legacy_message)
+                .unwrap();
+        assert!(Pin::new(&mut SymmetricalBincode::<PeerMessage>::default())
+            .deserialize(&BytesMut::from(&legacy_bytes[..]))
+            .is_err());
+    }
+
+    #[test]
+    fn block_serving_quota_rolls_over_test() {
+        let policy = BlockServingPolicy {
+            max_blocks: 3,
+            max_bytes: 1000,
+            window: Duration::from_secs(600),
+        };
+        let start = tokio::time::Instant::now();
+        let mut quota = BlockServingQuota::default();
+        assert!(quota.retry_after(&policy, start).is_none());
+
+        // Block count limit
+        for i in 0..3 {
+            quota.record(start + Duration::from_secs(10 * i), 100);
         }
+        let later = start + Duration::from_secs(100);
+        assert_eq!(
+            Some(Duration::from_secs(500)),
+            quota.retry_after(&policy, later)
+        );
+
+        // Once the oldest block leaves the window, one more can be exchanged
+        let after_window = start + Duration::from_secs(600);
+        assert!(quota.retry_after(&policy, after_window).is_none());
+
+        // Either limit being reached pauses until the oldest block expires
+        quota.record(after_window, 800);
+        assert_eq!(
+            Some(Duration::from_secs(10)),
+            quota.retry_after(&policy, after_window)
+        );
+
+        // A single block exceeding the byte limit blocks the whole window
+        let mut big_block_quota = BlockServingQuota::default();
+        big_block_quota.record(start, 2000);
+        assert_eq!(
+            Some(Duration::from_secs(600)),
+            big_block_quota.retry_after(&policy, start)
+        );
     }
 }
@@ -0,0 +1,236 @@
+//! The address book holds the addresses of potential peers, learned from the
+//! peer lists of other peers and from completed handshakes. It is persisted,
+//! such that a restarted node knows more peers than those given with
+//! `--peers`, and it tracks how well each address worked out, such that the
+//! dialer prefers addresses that lead to working peers.
+
+use itertools::Itertools;
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::SystemTime;
+
+/// Maximum number of addresses in the address book. Once it is full, new
+/// addresses replace the entries with the worst track record.
+pub const ADDRESS_BOOK_CAPACITY: usize = 10_000;
+
+/// Addresses that never led to a handshake are forgotten after this many
+/// failed connection attempts.
+pub const ADDRESS_BOOK_MAX_FAILURES: u32 = 8;
+
+/// Maximum number of outbound peers in the same network bucket, such that a
+/// single network cannot take over the outbound slots.
+pub const MAX_OUTBOUND_PEERS_PER_BUCKET: usize = 2;
+
+/// Track record of an address in the address book.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AddressRecord {
+    /// When the address was last learned from a peer list or handshake
+    pub last_seen: SystemTime,
+
+    /// When a handshake with the address was last completed
+    pub last_success: Option<SystemTime>,
+
+    /// Failed connection attempts since the last success
+    pub failure_count: u32,
+}
+
+impl AddressRecord {
+    pub fn new(last_seen: SystemTime) -> Self {
+        Self {
+            last_seen,
+            last_success: None,
+            failure_count: 0,
+        }
+    }
+
+    /// Whether the address has failed too often to be worth keeping.
+    pub fn is_hopeless(&self) -> bool {
+        self.last_success.is_none() && self.failure_count >= ADDRESS_BOOK_MAX_FAILURES
+    }
+
+    /// Order in which entries are evicted from a full address book, highest
+    /// first: addresses that never led to a handshake, then those that failed
+    /// most often, then those that were seen longest ago.
+    pub fn eviction_priority(&self) -> (bool, u32, std::cmp::Reverse<SystemTime>) {
+        (
+            self.last_success.is_none(),
+            self.failure_count,
+            std::cmp::Reverse(self.last_seen),
+        )
+    }
+}
+
+/// The network an address belongs to: the /16 of IPv4 addresses, and the /32
+/// of IPv6 addresses. Addresses in the same bucket are likely operated by the
+/// same party.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AddressBucket {
+    V4([u8; 2]),
+    V6([u8; 4]),
+}
+
+impl From<IpAddr> for AddressBucket {
+    fn from(ip: IpAddr) -> Self {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => {
+                let octets = ip.octets();
+                AddressBucket::V4([octets[0], octets[1]])
+            }
+            IpAddr::V6(ip) => {
+                let octets = ip.octets();
+                AddressBucket::V6([octets[0], octets[1], octets[2], octets[3]])
+            }
+        }
+    }
+}
+
+/// Pick an address to dial among the candidates, given the number of outbound
+/// peers in each bucket. A random bucket that is not full is picked first,
+/// such that buckets with many addresses are not favored, and then the
+/// address in it with the best track record.
+pub fn select_address_to_dial<R: Rng>(
+    candidates: Vec<(SocketAddr, AddressRecord)>,
+    outbound_peers_per_bucket: &HashMap<AddressBucket, usize>,
+    rng: &mut R,
+) -> Option<SocketAddr> {
+    let candidates_per_bucket = candidates
+        .into_iter()
+        .into_group_map_by(|(address, _record)| AddressBucket::from(address.ip()));
+    let (_bucket, bucket_candidates) = candidates_per_bucket
+        .into_iter()
+        .filter(|(bucket, _)| {
+            outbound_peers_per_bucket.get(bucket).copied().unwrap_or(0)
+                < MAX_OUTBOUND_PEERS_PER_BUCKET
+        })
+        .choose(rng)?;
+
+    bucket_candidates
+        .into_iter()
+        .max_by_key(|(_address, record)| {
+            (
+                record.last_success.is_some(),
+                std::cmp::Reverse(record.failure_count),
+                record.last_seen,
+            )
+        })
+        .map(|(address, _record)| address)
+}
+
+#[cfg(test)]
+mod address_book_tests {
+    use super::*;
+
+    use rand::thread_rng;
+    use std::time::Duration;
+
+    #[test]
+    fn buckets_group_by_network_test() {
+        let bucket = |address: &str| AddressBucket::from(address.parse::<IpAddr>().unwrap());
+        assert_eq!(bucket("10.1.2.3"), bucket("10.1.200.100"));
+        assert_ne!(bucket("10.1.2.3"), bucket("10.2.2.3"));
+        assert_eq!(bucket("10.1.2.3"), bucket("::ffff:10.1.9.9"));
+        assert_eq!(bucket("2001:db8::1"), bucket("2001:db8:ffff::1"));
+        assert_ne!(bucket("2001:db8::1"), bucket("2001:db9::1"));
+    }
+
+    #[test]
+    fn full_buckets_are_not_dialed_test() {
+        let now = SystemTime::now();
+        let candidates: Vec<(SocketAddr, AddressRecord)> = (0..50)
+            .map(|i| {
+                (
+                    SocketAddr::from(([10, 1, i, 1], 9798)),
+                    AddressRecord::new(now),
+                )
+            })
+            .chain([(
+                SocketAddr::from(([10, 2, 0, 1], 9798)),
+                AddressRecord::new(now),
+            )])
+            .collect();
+
+        // Many addresses in one /16 do not make that /16 more likely to be
+        // dialed, and a full /16 is not dialed at all.
+        let full_bucket = AddressBucket::from(candidates[0].0.ip());
+        let outbound_peers_per_bucket =
+            HashMap::from([(full_bucket, MAX_OUTBOUND_PEERS_PER_BUCKET)]);
+        let mut rng = thread_rng();
+        for _ in 0..20 {
+            assert_eq!(
+                Some(candidates[50].0),
+                select_address_to_dial(candidates.clone(), &outbound_peers_per_bucket, &mut rng)
+            );
+        }
+
+        let only_full_bucket = candidates[..50].to_vec();
+        assert!(
+            select_address_to_dial(only_full_bucket, &outbound_peers_per_bucket, &mut rng)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn best_address_in_bucket_is_dialed_test() {
+        let now = SystemTime::now();
+        let failing = AddressRecord {
+            last_seen: now,
+            last_success: None,
+            failure_count: 3,
+        };
+        let fresh = AddressRecord::new(now - Duration::from_secs(60));
+        let working = AddressRecord {
+            last_seen: now - Duration::from_secs(3600),
+            last_success: Some(now - Duration::from_secs(3600)),
+            failure_count: 1,
+        };
+        let address = |i| SocketAddr::from(([10, 1, 0, i], 9798));
+        let candidates = vec![(address(1), failing), (address(2), fresh)];
+        let mut rng = thread_rng();
+        assert_eq!(
+            Some(address(2)),
+            select_address_to_dial(candidates.clone(), &HashMap::new(), &mut rng)
+        );
+
+        let mut candidates_with_working = candidates;
+        candidates_with_working.push((address(3), working));
+        assert_eq!(
+            Some(address(3)),
+            select_address_to_dial(candidates_with_working, &HashMap::new(), &mut rng)
+        );
+
+        assert!(!working.is_hopeless());
+        assert!(AddressRecord {
+            failure_count: ADDRESS_BOOK_MAX_FAILURES,
+            ..fresh
+        }
+        .is_hopeless());
+    }
+
+    #[test]
+    fn worst_address_is_evicted_first_test() {
+        let now = SystemTime::now();
+        let old = AddressRecord::new(now - Duration::from_secs(3600));
+        let failing = AddressRecord {
+            failure_count: 2,
+            ..AddressRecord::new(now)
+        };
+        let working = AddressRecord {
+            last_seen: now - Duration::from_secs(7200),
+            last_success: Some(now - Duration::from_secs(7200)),
+            failure_count: 5,
+        };
+        let worst = |records: &[AddressRecord]| {
+            records
+                .iter()
+                .copied()
+                .max_by_key(AddressRecord::eviction_priority)
+        };
+
+        assert_eq!(Some(old), worst(&[AddressRecord::new(now), old]));
+        assert_eq!(Some(failing), worst(&[old, failing]));
+        assert_eq!(Some(old), worst(&[old, working]));
+    }
+}
@@ -2,32 +2,63 @@ use crate::config_models::network::Network;
 use crate::prelude::twenty_first;
 
 use crate::database::storage::storage_schema::traits::*;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use memmap2::MmapOptions;
 use num_traits::Zero;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ops::DerefMut;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tokio::io::AsyncReadExt;
 use tokio::io::AsyncSeekExt;
 use tokio::io::AsyncWriteExt;
 use tokio::io::SeekFrom;
 use tracing::{debug, warn};
+use twenty_first::amount::u32s::U32s;
 use twenty_first::math::digest::Digest;
 
+use super::block_cache::BlockCache;
+use super::digest_lookup::HexPrefix;
 use super::shared::new_block_file_is_needed;
+use super::storage_scrubber::{stratified_sample, ScrubRound, ScrubbedKey, StorageScrubReport};
 use crate::config_models::data_directory::DataDirectory;
 use crate::database::{create_db_if_missing, NeptuneLevelDb, WriteBatchAsync};
-use crate::models::blockchain::block::block_header::BlockHeader;
-use crate::models::blockchain::block::{block_height::BlockHeight, Block};
+use crate::models::blockchain::block::block_header::{
+    BlockHeader, MEDIAN_TIMESTAMP_BLOCK_COUNT, PROOF_OF_WORK_COUNT_U32_SIZE,
+};
+use crate::models::blockchain::block::{
+    block_body::UncleBlock,
+    block_height::BlockHeight,
+    checkpoint::{Checkpoint, CheckpointSnapshot},
+    Block, RecentAncestor, MAX_UNCLES_PER_BLOCK, MAX_UNCLE_GENERATIONS,
+};
+use crate::models::consensus::mast_hash::MastHash;
+use crate::models::consensus::timestamp::Timestamp;
 use crate::models::database::{
     BlockFileLocation, BlockIndexKey, BlockIndexValue, BlockRecord, FileRecord, LastFileRecord,
 };
 use crate::util_types::mutator_set::addition_record::AdditionRecord;
+use crate::util_types::mutator_set::archival_mutator_set::{
+    BatchIndexTotals, MutatorSetStatistics,
+};
 use crate::util_types::mutator_set::removal_record::RemovalRecord;
 use crate::util_types::mutator_set::rusty_archival_mutator_set::RustyArchivalMutatorSet;
+use crate::util_types::mutator_set::shared::BATCH_SIZE;
 
 pub const BLOCK_INDEX_DB_NAME: &str = "block_index";
 pub const MUTATOR_SET_DIRECTORY_NAME: &str = "mutator_set";
 
+/// Number of blocks after which cached mutator set statistics are recomputed.
+pub const MUTATOR_SET_STATISTICS_REFRESH_INTERVAL: u64 = 100;
+
+/// Estimated false-positive rate of the sliding-window Bloom filter above
+/// which a warning is logged. Far below the rate at which an invalid removal
+/// becomes a practical concern, such that operators are warned early.
+pub const SWBF_FALSE_POSITIVE_RATE_ALARM_THRESHOLD: f64 = 1e-12;
+
 /// Provides interface to historic blockchain data which consists of
 ///  * block-data stored in individual files (append-only)
 ///  * block-index database stored in levelDB
@@ -57,6 +88,18 @@ pub struct ArchivalState {
     // The archival mutator set is persisted to one database that also records a sync label,
     // which corresponds to the hash of the block to which the mutator set is synced.
     pub archival_mutator_set: RustyArchivalMutatorSet,
+
+    // Summarized mutator set statistics and the block height at which they were
+    // computed. Computing them requires reading all chunks, so they are cached.
+    mutator_set_statistics_cache: Option<(BlockHeight, MutatorSetStatistics)>,
+
+    // Recently read blocks, to avoid repeatedly reading and deserializing the
+    // same blocks from disk. Behind a mutex since blocks are read through a
+    // shared reference.
+    block_cache: Mutex<BlockCache>,
+
+    // Outcome of the background verification of stored blocks and chunks.
+    storage_scrub_report: StorageScrubReport,
 }
 
 // The only reason we have this `Debug` implementation is that it's required
@@ -187,6 +230,7 @@ impl ArchivalState {
         block_index_db: NeptuneLevelDb<BlockIndexKey, BlockIndexValue>,
         mut archival_mutator_set: RustyArchivalMutatorSet,
         network: Network,
+        block_cache_capacity: usize,
     ) -> Self {
         let genesis_block = Box::new(Block::genesis_block(network));
 
@@ -195,7 +239,7 @@ impl ArchivalState {
         // We could have populated the archival mutator set with the genesis block UTXOs earlier in
         // the setup, but we don't have the genesis block in scope before this function, so it makes
         // sense to do it here.
-        if archival_mutator_set.ams().aocl.is_empty().await {
+        if archival_mutator_set.ams().aocl_leaf_count().await == 0 {
             for addition_record in genesis_block.kernel.body.transaction.kernel.outputs.iter() {
                 archival_mutator_set.ams_mut().add(addition_record).await;
             }
@@ -209,6 +253,9 @@ impl ArchivalState {
             block_index_db,
             genesis_block,
             archival_mutator_set,
+            mutator_set_statistics_cache: None,
+            block_cache: Mutex::new(BlockCache::new(block_cache_capacity)),
+            storage_scrub_report: StorageScrubReport::default(),
         }
     }
 
@@ -216,6 +263,10 @@ impl ArchivalState {
         &self.genesis_block
     }
 
+    pub fn data_dir(&self) -> &DataDirectory {
+        &self.data_dir
+    }
+
     /// Write a newly found block to database and to disk, and set it as tip.
     pub async fn write_block_as_tip(&mut self, new_block: &Block) -> Result<()> {
         // Fetch last file record to find disk location to store block.
@@ -291,6 +342,13 @@ impl ArchivalState {
                 Some(rec) => rec.as_height_record(),
                 None => vec![],
             };
+        let hex_prefix_key = BlockIndexKey::HexPrefix(HexPrefix::index_key(&new_block.hash()));
+        let mut blocks_with_same_hex_prefix: Vec<Digest> = self
+            .block_index_db
+            .get(hex_prefix_key.clone())
+            .await
+            .map(|x| x.as_hex_prefix_record())
+            .unwrap_or_default();
 
         // Write to file with mmap, only map relevant part of file into memory
         // we use spawn_blocking to make the blocking mmap async-friendly.
@@ -321,6 +379,22 @@ impl ArchivalState {
 
         block_index_entries.push((file_record_key, BlockIndexValue::File(file_record_value)));
         block_index_entries.push((block_record_key, block_record_value));
+        block_index_entries.push((
+            BlockIndexKey::BodyDigest(new_block.hash()),
+            BlockIndexValue::BodyDigest(new_block.kernel.body.mast_hash()),
+        ));
+        block_index_entries.push((
+            BlockIndexKey::Uncles(new_block.hash()),
+            BlockIndexValue::Uncles(
+                new_block
+                    .kernel
+                    .body
+                    .uncle_blocks
+                    .iter()
+                    .map(|uncle| uncle.digest())
+                    .collect(),
+            ),
+        ));
 
         block_index_entries.push((BlockIndexKey::LastFile, BlockIndexValue::LastFile(last_rec)));
         blocks_at_same_height.push(new_block.hash());
@@ -328,6 +402,13 @@ impl ArchivalState {
             height_record_key,
             BlockIndexValue::Height(blocks_at_same_height),
         ));
+        if !blocks_with_same_hex_prefix.contains(&new_block.hash()) {
+            blocks_with_same_hex_prefix.push(new_block.hash());
+        }
+        block_index_entries.push((
+            hex_prefix_key,
+            BlockIndexValue::HexPrefix(blocks_with_same_hex_prefix),
+        ));
 
         // Mark block as tip
         block_index_entries.push((
@@ -342,9 +423,309 @@ impl ArchivalState {
 
         self.block_index_db.batch_write(batch).await;
 
+        // Make sure no stale copy of this block is served from the cache
+        self.block_cache.lock().unwrap().remove(new_block.hash());
+
+        Ok(())
+    }
+
+    /// Drop the transaction witnesses of all stored blocks at heights up to and
+    /// including `prune_height`, retaining the transaction kernels. Returns the
+    /// number of blocks whose witness was dropped.
+    ///
+    /// The block files that store these blocks are rewritten, such that the
+    /// space is reclaimed. This moves the other blocks in those files too, so
+    /// this method is meant for an occasional maintenance job. The height up
+    /// to which witnesses were pruned is recorded, so blocks are only visited
+    /// once.
+    pub async fn prune_transaction_witnesses(
+        &mut self,
+        prune_height: BlockHeight,
+    ) -> Result<usize> {
+        let mut pruned_count = 0;
+        for file_index in self.block_files_to_prune(prune_height).await {
+            pruned_count += self
+                .prune_transaction_witnesses_in_file(file_index, prune_height)
+                .await?;
+        }
+        self.set_witness_prune_height(prune_height).await;
+
+        Ok(pruned_count)
+    }
+
+    /// The height up to and including which the transaction witnesses of the
+    /// stored blocks were dropped.
+    pub async fn witness_prune_height(&self) -> BlockHeight {
+        self.block_index_db
+            .get(BlockIndexKey::WitnessPruneHeight)
+            .await
+            .map(|x| x.as_witness_prune_height())
+            .unwrap_or_else(BlockHeight::genesis)
+    }
+
+    /// Record that the transaction witnesses of the stored blocks were dropped
+    /// up to and including `prune_height`.
+    pub async fn set_witness_prune_height(&mut self, prune_height: BlockHeight) {
+        if prune_height <= self.witness_prune_height().await {
+            return;
+        }
+
+        let mut batch = WriteBatchAsync::new();
+        batch.op_write(
+            BlockIndexKey::WitnessPruneHeight,
+            BlockIndexValue::WitnessPruneHeight(prune_height),
+        );
+        self.block_index_db.batch_write(batch).await;
+    }
+
+    /// The indices of the block files storing blocks above the recorded
+    /// witness prune height, up to and including `prune_height`. Blocks on
+    /// abandoned forks are included.
+    pub async fn block_files_to_prune(&self, prune_height: BlockHeight) -> BTreeSet<u32> {
+        let mut file_indices = BTreeSet::new();
+        let mut height = self.witness_prune_height().await.next();
+        while height <= prune_height {
+            for digest in self.block_height_to_block_digests(height).await {
+                if let Some(block_record) = self
+                    .block_index_db
+                    .get(BlockIndexKey::Block(digest))
+                    .await
+                    .map(|x| x.as_block_record())
+                {
+                    file_indices.insert(block_record.file_location.file_index);
+                }
+            }
+            height = height.next();
+        }
+
+        file_indices
+    }
+
+    /// Drop the transaction witnesses of the blocks in the given block file at
+    /// heights up to and including `prune_height`. Returns the number of
+    /// blocks whose witness was dropped.
+    ///
+    /// The compacted file is written next to the original. Then the block
+    /// index is updated in one batch, which also records that the file is to
+    /// be replaced. Only then is the original replaced. If the node stops
+    /// before the index is updated, the original file is still in place; if
+    /// it stops after, [`Self::finish_interrupted_block_file_rewrite`] replaces
+    /// the file on startup.
+    pub async fn prune_transaction_witnesses_in_file(
+        &mut self,
+        file_index: u32,
+        prune_height: BlockHeight,
+    ) -> Result<usize> {
+        let Some(file_record) = self
+            .block_index_db
+            .get(BlockIndexKey::File(file_index))
+            .await
+            .map(|x| x.as_file_record())
+        else {
+            bail!("Block file {file_index} is not in the block index");
+        };
+
+        // Only the heights stored in the file need to be visited
+        let mut blocks: Vec<(Digest, BlockRecord)> = vec![];
+        let mut height = file_record.min_block_height;
+        while height <= file_record.max_block_height {
+            for digest in self.block_height_to_block_digests(height).await {
+                if let Some(block_record) = self
+                    .block_index_db
+                    .get(BlockIndexKey::Block(digest))
+                    .await
+                    .map(|x| x.as_block_record())
+                {
+                    if block_record.file_location.file_index == file_index {
+                        blocks.push((digest, block_record));
+                    }
+                }
+            }
+            height = height.next();
+        }
+        blocks.sort_by_key(|(_, block_record)| block_record.file_location.offset);
+        blocks.dedup_by_key(|(digest, _)| *digest);
+
+        let block_file_path = self.data_dir.block_file_path(file_index);
+        let compacted_file_path = block_file_path.with_extension("tmp");
+        let mut compacted_file = tokio::fs::File::create(&compacted_file_path).await?;
+        let mut batch = WriteBatchAsync::new();
+        let mut file_size = 0u64;
+        let mut pruned_count = 0;
+        let mut moved_digests = vec![];
+        for (digest, mut block_record) in blocks {
+            let mut block = self
+                .get_block_from_block_record(block_record.clone())
+                .await?;
+            if block.kernel.header.height <= prune_height
+                && !block.has_stripped_transaction_witness()
+            {
+                block.strip_transaction_witness();
+                pruned_count += 1;
+            }
+
+            let serialized_block: Vec<u8> = bincode::serialize(&block)?;
+            compacted_file.write_all(&serialized_block).await?;
+            block_record.file_location.offset = file_size;
+            block_record.file_location.block_length = serialized_block.len();
+            file_size += serialized_block.len() as u64;
+            batch.op_write(
+                BlockIndexKey::Block(digest),
+                BlockIndexValue::Block(Box::new(block_record)),
+            );
+            moved_digests.push(digest);
+        }
+        compacted_file.sync_all().await?;
+        drop(compacted_file);
+
+        let mut file_record = file_record;
+        file_record.file_size = file_size;
+        batch.op_write(
+            BlockIndexKey::File(file_index),
+            BlockIndexValue::File(file_record),
+        );
+        batch.op_write(
+            BlockIndexKey::PendingBlockFileRewrite,
+            BlockIndexValue::PendingBlockFileRewrite(file_index),
+        );
+        self.block_index_db.batch_write(batch).await;
+
+        // Make sure no stale copy of a moved block is served from the cache
+        {
+            let mut block_cache = self.block_cache.lock().unwrap();
+            for digest in moved_digests {
+                block_cache.remove(digest);
+            }
+        }
+
+        self.finish_interrupted_block_file_rewrite().await?;
+        debug!("Rewrote block file {file_index} with pruned witnesses");
+
+        Ok(pruned_count)
+    }
+
+    /// Replace the block file whose rewrite was committed to the block index
+    /// with its rewritten version, if the node stopped before replacing it.
+    /// Must be called on startup, before any block is read.
+    pub async fn finish_interrupted_block_file_rewrite(&mut self) -> Result<()> {
+        let Some(file_index) = self
+            .block_index_db
+            .get(BlockIndexKey::PendingBlockFileRewrite)
+            .await
+            .map(|x| x.as_pending_block_file_rewrite())
+        else {
+            return Ok(());
+        };
+
+        // The rewritten file is missing if it already replaced the original
+        let block_file_path = self.data_dir.block_file_path(file_index);
+        let compacted_file_path = block_file_path.with_extension("tmp");
+        if tokio::fs::try_exists(&compacted_file_path).await? {
+            tokio::fs::rename(&compacted_file_path, &block_file_path).await?;
+        }
+
+        let mut batch = WriteBatchAsync::new();
+        batch.op_delete(BlockIndexKey::PendingBlockFileRewrite);
+        self.block_index_db.batch_write(batch).await;
+
+        Ok(())
+    }
+
+    /// Fail if the canonical chain disagrees with the checkpoint, i.e. if its
+    /// block at the checkpoint's height is another block, or has another
+    /// mutator set accumulator. A chain that has not reached the height of the
+    /// checkpoint yet agrees with it.
+    pub async fn check_checkpoint(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let tip = self.get_tip().await;
+        if tip.kernel.header.height < checkpoint.height {
+            return Ok(());
+        }
+
+        let canonical_digest = self
+            .block_height_to_canonical_block_digest(checkpoint.height, tip.hash())
+            .await;
+        if canonical_digest != Some(checkpoint.block_digest) {
+            bail!(
+                "Canonical block at height {} is not the checkpointed block {}",
+                checkpoint.height,
+                checkpoint.block_digest
+            );
+        }
+        let Some(block) = self.get_block(checkpoint.block_digest).await? else {
+            bail!(
+                "Checkpointed block {} is missing from the database",
+                checkpoint.block_digest
+            );
+        };
+        if Checkpoint::from_block(&block) != *checkpoint {
+            bail!(
+                "Mutator set accumulator of block {} does not match the checkpoint",
+                checkpoint.block_digest
+            );
+        }
+
         Ok(())
     }
 
+    /// A snapshot of the mutator set after the tip, from which other nodes
+    /// can start, see [Self::bootstrap_from_checkpoint]. The contents of the
+    /// mutator set are read into memory.
+    pub async fn checkpoint_snapshot(&self) -> CheckpointSnapshot {
+        let tip = self.get_tip().await;
+        let (aocl_leafs, chunks) = self.archival_mutator_set.ams().contents().await;
+
+        CheckpointSnapshot {
+            checkpoint: Checkpoint::from_block(&tip),
+            block: tip.into(),
+            aocl_leafs,
+            chunks,
+        }
+    }
+
+    /// Start from a checkpoint rather than from genesis: fill the mutator set
+    /// with the contents of the snapshot, and store the checkpointed block as
+    /// tip, such that only the blocks after it need to be applied. The
+    /// snapshot's checkpoint must have been verified against a trusted hash,
+    /// see [CheckpointSnapshot::load]; its block and contents are verified
+    /// here. Returns the new tip. On failure, nothing is persisted, but the
+    /// mutator set must not be used any further.
+    ///
+    /// Only a node without any blocks besides genesis can start from a
+    /// checkpoint. The blocks before the checkpoint are never stored, so the
+    /// node can neither serve them to peers nor follow a fork off them.
+    pub async fn bootstrap_from_checkpoint(
+        &mut self,
+        snapshot: CheckpointSnapshot,
+    ) -> Result<Block> {
+        if self.get_tip().await.hash() != self.genesis_block.hash() {
+            bail!("Cannot start from a checkpoint once blocks have been stored");
+        }
+
+        let block = snapshot.block()?;
+        let expected_accumulator = snapshot.checkpoint.mutator_set_accumulator;
+        self.archival_mutator_set
+            .ams_mut()
+            .replace_contents(
+                &snapshot.aocl_leafs,
+                snapshot.chunks,
+                expected_accumulator.swbf_active.clone(),
+            )
+            .await;
+
+        if self.archival_mutator_set.ams().accumulator().await != expected_accumulator {
+            bail!(
+                "Mutator set contents do not match the checkpoint of block {}",
+                block.hash()
+            );
+        }
+
+        self.write_block_as_tip(&block).await?;
+        self.archival_mutator_set.set_sync_label(block.hash()).await;
+        self.archival_mutator_set.persist().await;
+
+        Ok(block)
+    }
+
     async fn get_block_from_block_record(&self, block_record: BlockRecord) -> Result<Block> {
         // Get path of file for block
         let block_file_path: PathBuf = self
@@ -374,6 +755,137 @@ impl ArchivalState {
         .await?
     }
 
+    /// Read a block from disk with plain reads rather than a memory map, such
+    /// that truncated or corrupted records result in an error rather than a
+    /// crash.
+    async fn read_block_from_block_record(&self, block_record: &BlockRecord) -> Result<Block> {
+        let block_file_path: PathBuf = self
+            .data_dir
+            .block_file_path(block_record.file_location.file_index);
+        let mut block_file = tokio::fs::File::open(block_file_path).await?;
+        block_file
+            .seek(SeekFrom::Start(block_record.file_location.offset))
+            .await?;
+        let mut bytes = vec![0u8; block_record.file_location.block_length];
+        block_file.read_exact(&mut bytes).await?;
+
+        Ok(bincode::deserialize(&bytes)?)
+    }
+
+    /// Re-read the block with the given digest from disk, bypassing the block
+    /// cache, and verify that it is intact: it must decode, hash to its
+    /// digest, which commits to both its header and its body, match its header
+    /// in the block index, and link to a known parent one height below it.
+    /// Records that cannot be decoded result in an error rather than a panic.
+    pub async fn verify_stored_block(&self, block_digest: Digest) -> Result<()> {
+        let Some(block_record) = self.try_get_block_record(block_digest).await? else {
+            bail!("Block {block_digest} not found in block index");
+        };
+
+        let block = self.read_block_from_block_record(&block_record).await?;
+        if block.hash() != block_digest {
+            bail!("Stored block {block_digest} hashes to {}", block.hash());
+        }
+        if block.kernel.header != block_record.block_header {
+            bail!("Stored block {block_digest} does not match its header in the block index");
+        }
+        let parent_digest = block.kernel.header.prev_block_digest;
+        let parent_height = match self.try_get_block_record(parent_digest).await? {
+            Some(parent_record) => Some(parent_record.block_header.height),
+            None if parent_digest == self.genesis_block.hash() => {
+                Some(self.genesis_block.kernel.header.height)
+            }
+            None => None,
+        };
+        if parent_height.map(|height| height.next()) != Some(block.kernel.header.height) {
+            bail!("Stored block {block_digest} does not link to a parent at the height below it");
+        }
+
+        Ok(())
+    }
+
+    /// Read the record of the block with the given digest from the block
+    /// index, or return an error if it cannot be decoded.
+    async fn try_get_block_record(&self, block_digest: Digest) -> Result<Option<BlockRecord>> {
+        match self
+            .block_index_db
+            .try_get(BlockIndexKey::Block(block_digest))
+            .await?
+        {
+            Some(BlockIndexValue::Block(block_record)) => Ok(Some(*block_record)),
+            Some(_) => bail!("Block index entry of block {block_digest} is not a block record"),
+            None => Ok(None),
+        }
+    }
+
+    /// Verify a sample of the blocks stored at heights up to `tip_height`, and
+    /// of the chunks of the archival mutator set. The samples are spread
+    /// evenly over heights and chunk indices, such that old records are
+    /// verified as often as recent ones. Records that cannot be decoded are
+    /// reported like any other corrupt record.
+    pub async fn scrub_sample(
+        &self,
+        tip_height: BlockHeight,
+        block_sample_size: usize,
+        chunk_sample_size: usize,
+    ) -> ScrubRound {
+        let mut round = ScrubRound::default();
+
+        // The genesis block is not stored on disk, so heights 1 through
+        // `tip_height` are sampled.
+        let height_offsets =
+            stratified_sample(tip_height.into(), block_sample_size, &mut thread_rng());
+        for height_offset in height_offsets {
+            let height = BlockHeight::from(height_offset + 1);
+            let digests = match self
+                .block_index_db
+                .try_get(BlockIndexKey::Height(height))
+                .await
+            {
+                Ok(Some(BlockIndexValue::Height(digests))) => digests,
+                Ok(None) => vec![],
+                Ok(Some(_)) | Err(_) => {
+                    round.blocks_checked += 1;
+                    warn!("Scrubbed list of blocks at height {height} cannot be decoded");
+                    round.corrupt_keys.push(ScrubbedKey::Height(height));
+                    continue;
+                }
+            };
+            let Some(block_digest) = digests.choose(&mut thread_rng()).copied() else {
+                continue;
+            };
+
+            round.blocks_checked += 1;
+            if let Err(err) = self.verify_stored_block(block_digest).await {
+                warn!("Scrubbed block {block_digest} failed verification: {err}");
+                round.corrupt_keys.push(ScrubbedKey::Block(block_digest));
+            }
+        }
+
+        let ams = self.archival_mutator_set.ams();
+        let chunk_count = ams.chunk_count().await;
+        let chunk_indices = stratified_sample(chunk_count, chunk_sample_size, &mut thread_rng());
+        for chunk_index in chunk_indices {
+            round.chunks_checked += 1;
+            if let Err(err) = ams.verify_chunk(chunk_index).await {
+                warn!("Scrubbed chunk {chunk_index} failed verification: {err}");
+                round.corrupt_keys.push(ScrubbedKey::Chunk(chunk_index));
+            }
+        }
+
+        round
+    }
+
+    pub fn storage_scrub_report(&self) -> &StorageScrubReport {
+        &self.storage_scrub_report
+    }
+
+    /// Add a scrub round to the storage scrub report, and return the records
+    /// that were found to be corrupt for the first time.
+    pub fn record_scrub_round(&mut self, round: ScrubRound, now: SystemTime) -> Vec<ScrubbedKey> {
+        self.storage_scrub_report.record(round, now)
+    }
+
     /// Return the latest block that was stored to disk. If no block has been stored to disk, i.e.
     /// if tip is genesis, then `None` is returned
     async fn get_tip_from_disk(&self) -> Result<Option<Block>> {
@@ -446,8 +958,43 @@ impl ArchivalState {
         ret
     }
 
+    /// Return the header of the block with the given digest along with the
+    /// Merkle root of its body, without reading the block from disk, iff the
+    /// block is stored. Only blocks stored before the Merkle roots of bodies
+    /// were indexed are read from disk.
+    pub async fn get_block_header_with_body_digest(
+        &self,
+        block_digest: Digest,
+    ) -> Result<Option<(BlockHeader, Digest)>> {
+        let Some(header) = self.get_block_header(block_digest).await else {
+            return Ok(None);
+        };
+        if block_digest == self.genesis_block.hash() {
+            return Ok(Some((header, self.genesis_block.kernel.body.mast_hash())));
+        }
+
+        let body_digest = match self
+            .block_index_db
+            .get(BlockIndexKey::BodyDigest(block_digest))
+            .await
+        {
+            Some(body_digest) => body_digest.as_body_digest(),
+            None => match self.get_block(block_digest).await? {
+                Some(block) => block.kernel.body.mast_hash(),
+                None => bail!("Block {block_digest} is indexed but cannot be read"),
+            },
+        };
+
+        Ok(Some((header, body_digest)))
+    }
+
     // Return the block with a given block digest, iff it's available in state somewhere.
+    // Recently read blocks are served from an in-memory cache.
     pub async fn get_block(&self, block_digest: Digest) -> Result<Option<Block>> {
+        if let Some(block) = self.block_cache.lock().unwrap().get(block_digest) {
+            return Ok(Some(block));
+        }
+
         let maybe_record: Option<BlockRecord> = self
             .block_index_db
             .get(BlockIndexKey::Block(block_digest))
@@ -466,6 +1013,10 @@ impl ArchivalState {
 
         // Fetch block from disk
         let block = self.get_block_from_block_record(record).await?;
+        self.block_cache
+            .lock()
+            .unwrap()
+            .insert(block_digest, block.clone());
 
         Ok(Some(block))
     }
@@ -541,6 +1092,39 @@ impl ArchivalState {
         None
     }
 
+    /// Return the height of the last block in the canonical chain ending in
+    /// the given tip whose timestamp is not later than `timestamp`, or None if
+    /// `timestamp` predates the genesis block. Block timestamps are assumed to
+    /// increase with the height, which allows for a binary search.
+    pub async fn canonical_block_height_at_timestamp(
+        &self,
+        timestamp: Timestamp,
+        tip_digest: Digest,
+    ) -> Option<BlockHeight> {
+        if self.genesis_block.kernel.header.timestamp > timestamp {
+            return None;
+        }
+
+        // Invariant: the block at height `low` is not later than `timestamp`,
+        // and all blocks above height `high` are.
+        let mut low = 0u64;
+        let mut high: u64 = self.get_block_header(tip_digest).await?.height.into();
+        while low < high {
+            let middle = low + (high - low + 1) / 2;
+            let middle_digest = self
+                .block_height_to_canonical_block_digest(middle.into(), tip_digest)
+                .await?;
+            let middle_header = self.get_block_header(middle_digest).await?;
+            if middle_header.timestamp <= timestamp {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+
+        Some(low.into())
+    }
+
     pub async fn get_children_block_headers(
         &self,
         parent_block_digest: Digest,
@@ -675,73 +1259,481 @@ impl ArchivalState {
         ret
     }
 
-    /// Update the mutator set with a block after this block has been stored to the database.
-    /// Handles rollback of the mutator set if needed but requires that all blocks that are
-    /// rolled back are present in the DB. The input block is considered chain tip. All blocks
-    /// stored in the database are assumed to be valid.
-    pub async fn update_mutator_set(&mut self, new_block: &Block) -> Result<()> {
-        let (forwards, backwards) = {
-            // Get the block digest that the mutator set was most recently synced to
-            let ms_block_sync_digest = self.archival_mutator_set.get_sync_label().await;
-
-            // Find path from mutator set sync digest to new block. Optimize for the common case,
-            // where the new block is the child block of block that the mutator set is synced to.
-            let (backwards, _luca, forwards) =
-                if ms_block_sync_digest == new_block.kernel.header.prev_block_digest {
-                    // Trivial path
-                    (vec![], ms_block_sync_digest, vec![])
-                } else {
-                    // Non-trivial path from current mutator set sync digest to new block
-                    self.find_path(
-                        ms_block_sync_digest,
-                        new_block.kernel.header.prev_block_digest,
-                    )
-                    .await
-                };
-            let forwards = [forwards, vec![new_block.hash()]].concat();
-
-            (forwards, backwards)
-        };
-
-        for digest in backwards {
-            // Roll back mutator set
-            let roll_back_block = self
-                .get_block(digest)
-                .await
-                .expect("Fetching block must succeed")
-                .unwrap();
-
-            debug!(
-                "Updating mutator set: rolling back block with height {}",
-                roll_back_block.kernel.header.height
-            );
+    /// Return the digests of the uncles that the stored block with the given
+    /// digest references, without reading the block from disk. Only blocks
+    /// stored before uncles were indexed are read from disk.
+    async fn get_uncle_digests(&self, block_digest: Digest) -> Result<Option<Vec<Digest>>> {
+        if let Some(uncles) = self
+            .block_index_db
+            .get(BlockIndexKey::Uncles(block_digest))
+            .await
+        {
+            return Ok(Some(uncles.as_uncles()));
+        }
 
-            // Roll back all addition records contained in block
-            for addition_record in roll_back_block
+        let uncles = self.get_block(block_digest).await?.map(|block| {
+            block
                 .kernel
                 .body
-                .transaction
-                .kernel
-                .outputs
+                .uncle_blocks
                 .iter()
-                .rev()
-            {
-                assert!(
-                    self.archival_mutator_set
-                        .ams_mut()
-                        .add_is_reversible(addition_record)
-                        .await,
-                    "Addition record must be in sync with block being rolled back."
-                );
-                self.archival_mutator_set
-                    .ams_mut()
-                    .revert_add(addition_record)
-                    .await;
-            }
+                .map(|uncle| uncle.digest())
+                .collect()
+        });
+        Ok(uncles)
+    }
 
-            // Roll back all removal records contained in block
-            for removal_record in roll_back_block.kernel.body.transaction.kernel.inputs.iter() {
-                self.archival_mutator_set
+    /// Return `block` and its stored ancestors, newest first, along with the
+    /// uncles each references, for as far back as uncles of a child of
+    /// `block` may go. Ancestors are read from the block index. See
+    /// [Block::has_valid_uncles].
+    pub async fn recent_ancestors_with_uncles(&self, block: &Block) -> Vec<RecentAncestor> {
+        let mut ancestors = vec![RecentAncestor::from(block)];
+        let mut parent_digest = block.kernel.header.prev_block_digest;
+        while ancestors.len() <= MAX_UNCLE_GENERATIONS {
+            let Some(parent_header) = self.get_block_header(parent_digest).await else {
+                break;
+            };
+            let Ok(Some(uncles)) = self.get_uncle_digests(parent_digest).await else {
+                break;
+            };
+            let grandparent_digest = parent_header.prev_block_digest;
+            ancestors.push(RecentAncestor {
+                digest: parent_digest,
+                header: parent_header,
+                uncles,
+            });
+            parent_digest = grandparent_digest;
+        }
+
+        ancestors
+    }
+
+    /// Return the timestamps of `block` and of its stored ancestors, newest
+    /// first, as far back as they count towards the median timestamp that a
+    /// child of `block` must exceed. See [Block::has_valid_timestamp].
+    pub async fn recent_timestamps(&self, block: &Block) -> Vec<Timestamp> {
+        let mut timestamps = vec![block.kernel.header.timestamp];
+        let mut parent_digest = block.kernel.header.prev_block_digest;
+        while timestamps.len() < MEDIAN_TIMESTAMP_BLOCK_COUNT {
+            let Some(parent_header) = self.get_block_header(parent_digest).await else {
+                break;
+            };
+            timestamps.push(parent_header.timestamp);
+            parent_digest = parent_header.prev_block_digest;
+        }
+
+        timestamps
+    }
+
+    /// Return the stored blocks that a child of `tip` may reference as
+    /// uncles, at most [MAX_UNCLES_PER_BLOCK] of them, most recent first.
+    pub async fn uncle_candidates(&self, tip: &Block) -> Vec<UncleBlock> {
+        let ancestors = self.recent_ancestors_with_uncles(tip).await;
+        let mut candidates = vec![];
+        for ancestor in ancestors.iter().skip(1) {
+            for child_digest in self.get_children_block_digests(ancestor.digest).await {
+                let is_ancestor = ancestors.iter().any(|a| a.digest == child_digest);
+                let is_referenced = ancestors.iter().any(|a| a.uncles.contains(&child_digest));
+                if is_ancestor || is_referenced || candidates.len() >= MAX_UNCLES_PER_BLOCK {
+                    continue;
+                }
+                if let Ok(Some((header, body_digest))) =
+                    self.get_block_header_with_body_digest(child_digest).await
+                {
+                    candidates.push(UncleBlock {
+                        header,
+                        body_digest,
+                    });
+                }
+            }
+        }
+
+        candidates
+    }
+
+    /// Return the digests of all stored blocks, on any branch, whose hex
+    /// encoding starts with the given prefix. Reads at most one entry of the
+    /// hex prefix index per index key that the prefix spans.
+    pub async fn block_digests_with_hex_prefix(&self, prefix: &HexPrefix) -> Vec<Digest> {
+        let mut matching_digests = vec![];
+        let genesis_digest = self.genesis_block.hash();
+        if prefix.matches(&genesis_digest) {
+            matching_digests.push(genesis_digest);
+        }
+        for index_key in prefix.index_keys() {
+            let Some(digests) = self
+                .block_index_db
+                .get(BlockIndexKey::HexPrefix(index_key))
+                .await
+                .map(|x| x.as_hex_prefix_record())
+            else {
+                continue;
+            };
+            matching_digests.extend(digests.into_iter().filter(|digest| prefix.matches(digest)));
+        }
+
+        matching_digests
+    }
+
+    /// Add the blocks that were stored before the hex prefix index existed
+    /// to it. Reads the block index at every height once, after which it is
+    /// a no-op. Must be called on startup, before any block is written.
+    pub async fn complete_hex_prefix_index(&mut self) {
+        if self
+            .block_index_db
+            .get(BlockIndexKey::HexPrefixIndexComplete)
+            .await
+            .is_some()
+        {
+            return;
+        }
+
+        let mut digests_by_index_key: HashMap<u16, Vec<Digest>> = HashMap::new();
+        let mut height = BlockHeight::genesis().next();
+        loop {
+            let digests = self.block_height_to_block_digests(height).await;
+            if digests.is_empty() {
+                break;
+            }
+
+            for digest in digests {
+                digests_by_index_key
+                    .entry(HexPrefix::index_key(&digest))
+                    .or_default()
+                    .push(digest);
+            }
+            height = height.next();
+        }
+
+        let mut batch = WriteBatchAsync::new();
+        for (index_key, digests) in digests_by_index_key {
+            let mut indexed_digests = self
+                .block_index_db
+                .get(BlockIndexKey::HexPrefix(index_key))
+                .await
+                .map(|x| x.as_hex_prefix_record())
+                .unwrap_or_default();
+            for digest in digests {
+                if !indexed_digests.contains(&digest) {
+                    indexed_digests.push(digest);
+                }
+            }
+            batch.op_write(
+                BlockIndexKey::HexPrefix(index_key),
+                BlockIndexValue::HexPrefix(indexed_digests),
+            );
+        }
+        batch.op_write(
+            BlockIndexKey::HexPrefixIndexComplete,
+            BlockIndexValue::HexPrefixIndexComplete,
+        );
+        self.block_index_db.batch_write(batch).await;
+    }
+
+    /// Return the competing branches of the block tree above the given height,
+    /// or the empty list if all known blocks above this height form a single
+    /// chain. Each branch is the list of digests of the blocks from height
+    /// `height + 1` up to and including a block without known children,
+    /// ordered by ascending height, such that branches that diverge after
+    /// `height + 1` share a prefix. Longer branches come first.
+    pub async fn forks_above(&self, height: BlockHeight) -> Vec<Vec<Digest>> {
+        // Collect the headers of all blocks above the given height, and which
+        // of them have children
+        let mut headers: HashMap<Digest, BlockHeader> = HashMap::new();
+        let mut parents: HashSet<Digest> = HashSet::new();
+        let mut current_height = height.next();
+        loop {
+            let digests = self.block_height_to_block_digests(current_height).await;
+            if digests.is_empty() {
+                break;
+            }
+
+            for digest in digests {
+                let header = self.get_block_header(digest).await.unwrap_or_else(|| {
+                    panic!("Cannot get block header from digest, even though digest was fetched from height. Digest: {digest}")
+                });
+                parents.insert(header.prev_block_digest);
+                headers.insert(digest, header);
+            }
+            current_height = current_height.next();
+        }
+
+        let branch_tips: Vec<Digest> = headers
+            .keys()
+            .filter(|digest| !parents.contains(digest))
+            .copied()
+            .collect();
+        if branch_tips.len() < 2 {
+            return vec![];
+        }
+
+        // Walk back from each branch tip to the first block above `height`
+        let mut branches: Vec<Vec<Digest>> = branch_tips
+            .into_iter()
+            .map(|tip_digest| {
+                let mut branch = vec![tip_digest];
+                let mut parent_digest = headers[&tip_digest].prev_block_digest;
+                while let Some(parent_header) = headers.get(&parent_digest) {
+                    branch.push(parent_digest);
+                    parent_digest = parent_header.prev_block_digest;
+                }
+                branch.reverse();
+                branch
+            })
+            .collect();
+        branches.sort_by_key(|branch| std::cmp::Reverse(branch.len()));
+
+        branches
+    }
+
+    /// Return the proof-of-work accumulated by the blocks on the path from
+    /// `from`, exclusive, up to `to`, inclusive. The work of a block is the
+    /// expected number of hashes needed to mine it, *i.e.*, the difficulty
+    /// set by its parent. `from` must be an ancestor of `to`, or `to` itself.
+    pub async fn cumulative_pow(
+        &self,
+        from: Digest,
+        to: Digest,
+    ) -> Result<U32s<PROOF_OF_WORK_COUNT_U32_SIZE>> {
+        let Some(from_header) = self.get_block_header(from).await else {
+            bail!("Unknown block {from}");
+        };
+
+        let mut cumulative_pow = U32s::zero();
+        let mut digest = to;
+        while digest != from {
+            let Some(header) = self.get_block_header(digest).await else {
+                bail!("Unknown block {digest}");
+            };
+            if header.height <= from_header.height {
+                bail!("Block {from} is not an ancestor of block {to}");
+            }
+            let Some(parent_header) = self.get_block_header(header.prev_block_digest).await else {
+                bail!("Unknown parent of block {digest}");
+            };
+
+            cumulative_pow = cumulative_pow + parent_header.difficulty;
+            digest = header.prev_block_digest;
+        }
+
+        Ok(cumulative_pow)
+    }
+
+    /// Return the number of additions and removals applied to the mutator set
+    /// per batch index, obtained by replaying the blocks from genesis up to the
+    /// block to which the mutator set is synced. Additions are attributed to
+    /// the batch that their AOCL leaf belongs to, and removals to the batch
+    /// index the mutator set had after the additions of the same block.
+    pub async fn mutator_set_batch_index_totals(&self) -> Result<Vec<BatchIndexTotals>> {
+        // Collect the digests of the canonical chain, from the sync label down to genesis
+        let mut digests = vec![];
+        let mut digest = self.archival_mutator_set.get_sync_label().await;
+        loop {
+            let header = match self.get_block_header(digest).await {
+                Some(header) => header,
+                None => bail!("Block {} not found in block index", digest),
+            };
+            digests.push(digest);
+            if header.height.is_genesis() {
+                break;
+            }
+            digest = header.prev_block_digest;
+        }
+
+        // Replay the blocks one at a time, from genesis and up
+        let mut totals: BTreeMap<u64, BatchIndexTotals> = BTreeMap::new();
+        let mut aocl_leaf_count = 0u64;
+        for digest in digests.into_iter().rev() {
+            let block = match self.get_block(digest).await? {
+                Some(block) => block,
+                None => bail!("Block {} not found on disk", digest),
+            };
+            let transaction_kernel = &block.kernel.body.transaction.kernel;
+
+            for _ in transaction_kernel.outputs.iter() {
+                let batch_index = aocl_leaf_count / BATCH_SIZE as u64;
+                let entry = totals.entry(batch_index).or_insert(BatchIndexTotals {
+                    batch_index,
+                    ..Default::default()
+                });
+                entry.additions += 1;
+                aocl_leaf_count += 1;
+            }
+
+            if !transaction_kernel.inputs.is_empty() {
+                let batch_index = aocl_leaf_count.saturating_sub(1) / BATCH_SIZE as u64;
+                let entry = totals.entry(batch_index).or_insert(BatchIndexTotals {
+                    batch_index,
+                    ..Default::default()
+                });
+                entry.removals += transaction_kernel.inputs.len() as u64;
+            }
+        }
+
+        Ok(totals.into_values().collect())
+    }
+
+    /// Export statistics about the archival mutator set to the file at `path`,
+    /// including per-batch-index totals joined from the block index. See
+    /// [`ArchivalMutatorSet::export_statistics`](crate::util_types::mutator_set::archival_mutator_set::ArchivalMutatorSet::export_statistics).
+    pub async fn export_mutator_set_statistics(&self, path: &Path) -> Result<MutatorSetStatistics> {
+        let batch_totals = self.mutator_set_batch_index_totals().await?;
+        let statistics = self
+            .archival_mutator_set
+            .ams()
+            .export_statistics(path, &batch_totals)
+            .await?;
+
+        Ok(statistics)
+    }
+
+    /// Return summarized statistics about the archival mutator set. The result
+    /// is cached and only recomputed once the tip has moved at least
+    /// [`MUTATOR_SET_STATISTICS_REFRESH_INTERVAL`] blocks away from the height
+    /// at which it was computed.
+    pub async fn mutator_set_statistics(
+        &mut self,
+        tip_height: BlockHeight,
+    ) -> (BlockHeight, MutatorSetStatistics) {
+        if let Some(cached) = self.cached_mutator_set_statistics(tip_height) {
+            return cached;
+        }
+
+        let statistics = self.archival_mutator_set.ams().statistics().await;
+        self.cache_mutator_set_statistics(tip_height, statistics.clone());
+
+        (tip_height, statistics)
+    }
+
+    /// Return the cached mutator set statistics and the height at which they
+    /// were computed, unless they are missing or too old relative to
+    /// `tip_height`.
+    pub fn cached_mutator_set_statistics(
+        &self,
+        tip_height: BlockHeight,
+    ) -> Option<(BlockHeight, MutatorSetStatistics)> {
+        let (cached_height, statistics) = self.mutator_set_statistics_cache.as_ref()?;
+        if (tip_height - *cached_height).unsigned_abs()
+            >= MUTATOR_SET_STATISTICS_REFRESH_INTERVAL as u128
+        {
+            return None;
+        }
+
+        Some((*cached_height, statistics.clone()))
+    }
+
+    /// Store mutator set statistics computed at `tip_height` in the cache.
+    pub fn cache_mutator_set_statistics(
+        &mut self,
+        tip_height: BlockHeight,
+        statistics: MutatorSetStatistics,
+    ) {
+        self.mutator_set_statistics_cache = Some((tip_height, statistics));
+    }
+
+    /// Warn if the estimated false-positive rate of the sliding-window Bloom
+    /// filter exceeds `threshold`, and return the rate if so. Uses the cached
+    /// statistics, see [`Self::mutator_set_statistics`].
+    pub async fn check_swbf_saturation(
+        &mut self,
+        tip_height: BlockHeight,
+        threshold: f64,
+    ) -> Option<f64> {
+        let (computed_at, statistics) = self.mutator_set_statistics(tip_height).await;
+        Self::report_swbf_saturation(computed_at, &statistics, threshold)
+    }
+
+    /// Warn if the estimated false-positive rate of the sliding-window Bloom
+    /// filter, according to the statistics computed at height `computed_at`,
+    /// exceeds `threshold`, and return the rate if so.
+    pub fn report_swbf_saturation(
+        computed_at: BlockHeight,
+        statistics: &MutatorSetStatistics,
+        threshold: f64,
+    ) -> Option<f64> {
+        let false_positive_rate = statistics.estimated_false_positive_rate();
+        if false_positive_rate <= threshold {
+            return None;
+        }
+
+        warn!(
+            "Sliding-window Bloom filter is saturating: estimated false-positive rate \
+            {false_positive_rate:e} exceeds {threshold:e} at height {computed_at}. \
+            Fullest chunk has {} set bits, active window has {} set bits.",
+            statistics.max_chunk_set_bits, statistics.active_window_set_bits
+        );
+
+        Some(false_positive_rate)
+    }
+
+    /// Update the mutator set with a block after this block has been stored to the database.
+    /// Handles rollback of the mutator set if needed but requires that all blocks that are
+    /// rolled back are present in the DB. The input block is considered chain tip. All blocks
+    /// stored in the database are assumed to be valid.
+    pub async fn update_mutator_set(&mut self, new_block: &Block) -> Result<()> {
+        let (forwards, backwards) = {
+            // Get the block digest that the mutator set was most recently synced to
+            let ms_block_sync_digest = self.archival_mutator_set.get_sync_label().await;
+
+            // Find path from mutator set sync digest to new block. Optimize for the common case,
+            // where the new block is the child block of block that the mutator set is synced to.
+            let (backwards, _luca, forwards) =
+                if ms_block_sync_digest == new_block.kernel.header.prev_block_digest {
+                    // Trivial path
+                    (vec![], ms_block_sync_digest, vec![])
+                } else {
+                    // Non-trivial path from current mutator set sync digest to new block
+                    self.find_path(
+                        ms_block_sync_digest,
+                        new_block.kernel.header.prev_block_digest,
+                    )
+                    .await
+                };
+            let forwards = [forwards, vec![new_block.hash()]].concat();
+
+            (forwards, backwards)
+        };
+
+        for digest in backwards {
+            // Roll back mutator set
+            let roll_back_block = self
+                .get_block(digest)
+                .await
+                .expect("Fetching block must succeed")
+                .unwrap();
+
+            debug!(
+                "Updating mutator set: rolling back block with height {}",
+                roll_back_block.kernel.header.height
+            );
+
+            // Roll back all addition records contained in block
+            for addition_record in roll_back_block
+                .kernel
+                .body
+                .transaction
+                .kernel
+                .outputs
+                .iter()
+                .rev()
+            {
+                assert!(
+                    self.archival_mutator_set
+                        .ams_mut()
+                        .add_is_reversible(addition_record)
+                        .await,
+                    "Addition record must be in sync with block being rolled back."
+                );
+                self.archival_mutator_set
+                    .ams_mut()
+                    .revert_add(addition_record)
+                    .await;
+            }
+
+            // Roll back all removal records contained in block
+            for removal_record in roll_back_block.kernel.body.transaction.kernel.inputs.iter() {
+                self.archival_mutator_set
                     .ams_mut()
                     .revert_remove(removal_record)
                     .await;
@@ -843,20 +1835,30 @@ mod archival_state_tests {
 
     use crate::config_models::network::Network;
     use crate::database::storage::storage_vec::traits::*;
+    use crate::models::blockchain::shared::Hash;
     use crate::models::blockchain::transaction::utxo::LockScript;
     use crate::models::blockchain::transaction::utxo::Utxo;
     use crate::models::blockchain::transaction::PublicAnnouncement;
     use crate::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
     use crate::models::consensus::timestamp::Timestamp;
     use crate::models::state::archival_state::ArchivalState;
+    use crate::models::state::block_cache::DEFAULT_BLOCK_CACHE_CAPACITY;
     use crate::models::state::global_state_tests::create_transaction_with_timestamp;
+    use crate::models::state::storage_scrubber::{SCRUB_BLOCKS_PER_ROUND, SCRUB_CHUNKS_PER_ROUND};
     use crate::models::state::wallet::utxo_notification_pool::UtxoNotifier;
     use crate::models::state::wallet::WalletSecret;
     use crate::models::state::UtxoReceiverData;
     use crate::tests::shared::{
         add_block_to_archival_state, make_mock_block_with_valid_pow, mock_genesis_archival_state,
-        mock_genesis_global_state, mock_genesis_wallet_state, unit_test_databases,
+        mock_genesis_global_state, mock_genesis_wallet_state, random_addition_record,
+        unit_test_databases,
     };
+    use crate::util_types::mutator_set::chunk::Chunk;
+    use crate::util_types::mutator_set::commit;
+    use crate::util_types::mutator_set::ms_membership_proof::MsMembershipProof;
+    use crate::util_types::mutator_set::shared::NUM_TRIALS;
+    use crate::util_types::test_shared::mutator_set::make_item_and_randomnesses;
+    use itertools::Itertools;
     use rand::rngs::StdRng;
     use rand::Rng;
     use rand::SeedableRng;
@@ -870,7 +1872,14 @@ mod archival_state_tests {
             .await
             .unwrap();
 
-        ArchivalState::new(data_dir, block_index_db, ams, network).await
+        ArchivalState::new(
+            data_dir,
+            block_index_db,
+            ams,
+            network,
+            DEFAULT_BLOCK_CACHE_CAPACITY,
+        )
+        .await
     }
 
     #[traced_test]
@@ -889,7 +1898,7 @@ mod archival_state_tests {
         let some_receiving_address = some_spending_key.to_address();
 
         let (block_1, _, _) =
-            make_mock_block_with_valid_pow(&b, None, some_receiving_address, rng.gen());
+            make_mock_block_with_valid_pow(network, &b, None, some_receiving_address, rng.gen());
         add_block_to_archival_state(&mut archival_state0, block_1.clone())
             .await
             .unwrap();
@@ -920,8 +1929,7 @@ mod archival_state_tests {
             archival_state
                 .archival_mutator_set
                 .ams()
-                .aocl
-                .count_leaves()
+                .aocl_leaf_count()
                 .await,
             "Archival mutator set must be populated with premine outputs"
         );
@@ -945,6 +1953,7 @@ mod archival_state_tests {
         let genesis_wallet_state =
             mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
         let (mock_block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &archival_state.genesis_block,
             None,
             genesis_wallet_state
@@ -990,6 +1999,7 @@ mod archival_state_tests {
             genesis_receiver_global_state_lock.lock_guard_mut().await;
 
         let (mock_block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis_receiver_global_state
                 .chain
                 .archival_state_mut()
@@ -1008,7 +2018,7 @@ mod archival_state_tests {
                 .chain
                 .archival_state()
                 .archival_mutator_set;
-            assert_ne!(0, ams_ref.ams().aocl.count_leaves().await);
+            assert_ne!(0, ams_ref.ams().aocl_leaf_count().await);
         }
 
         let now = mock_block_1.kernel.header.timestamp;
@@ -1018,6 +2028,7 @@ mod archival_state_tests {
         // to the block, and this removal record will insert indices in the Bloom filter.
         {
             let (mut mock_block_2, _, _) = make_mock_block_with_valid_pow(
+                network,
                 &mock_block_1,
                 None,
                 own_receiving_address,
@@ -1055,7 +2066,7 @@ mod archival_state_tests {
                 .chain
                 .archival_state()
                 .archival_mutator_set;
-            assert_ne!(0, ams_ref.ams().swbf_active.sbf.len());
+            assert_ne!(0, ams_ref.ams().active_window().sbf.len());
         }
 
         Ok(())
@@ -1073,6 +2084,7 @@ mod archival_state_tests {
 
         // 1. Create new block 1 and store it to the DB
         let (mock_block_1a, _, _) = make_mock_block_with_valid_pow(
+            network,
             &archival_state.genesis_block,
             None,
             own_receiving_address,
@@ -1088,6 +2100,7 @@ mod archival_state_tests {
 
         // 3. Create competing block 1 and store it to DB
         let (mock_block_1b, _, _) = make_mock_block_with_valid_pow(
+            network,
             &archival_state.genesis_block,
             None,
             own_receiving_address,
@@ -1127,6 +2140,7 @@ mod archival_state_tests {
 
         // 1. Create new block 1 with one input and four outputs and store it to disk
         let (mut block_1a, _, _) = make_mock_block_with_valid_pow(
+            network,
             &archival_state.genesis_block,
             None,
             own_receiving_address,
@@ -1175,7 +2189,7 @@ mod archival_state_tests {
             )
             .await;
 
-        assert!(block_1a.is_valid(&genesis_block, now + seven_months));
+        assert!(block_1a.is_valid(&genesis_block, network));
 
         {
             archival_state.write_block_as_tip(&block_1a).await.unwrap();
@@ -1185,6 +2199,7 @@ mod archival_state_tests {
 
             // 3. Create competing block 1 and store it to DB
             let (mock_block_1b, _, _) = make_mock_block_with_valid_pow(
+                network,
                 &archival_state.genesis_block,
                 None,
                 own_receiving_address,
@@ -1211,7 +2226,7 @@ mod archival_state_tests {
             archival_state
                 .archival_mutator_set
                 .ams()
-                .swbf_active
+                .active_window()
                 .sbf
                 .is_empty(),
             "Active window must be empty when no UTXOs have been spent"
@@ -1222,8 +2237,7 @@ mod archival_state_tests {
             archival_state
                 .archival_mutator_set
                 .ams()
-                .aocl
-                .count_leaves()
+                .aocl_leaf_count()
                 .await as usize,
             "AOCL leaf count must agree with blockchain after rollback"
         );
@@ -1258,6 +2272,7 @@ mod archival_state_tests {
         for i in 0..10 {
             // Create next block with inputs and outputs
             let (mut next_block, _, _) = make_mock_block_with_valid_pow(
+                network,
                 &previous_block,
                 None,
                 own_receiving_address,
@@ -1298,7 +2313,7 @@ mod archival_state_tests {
                 .await;
 
             assert!(
-                next_block.is_valid(&previous_block, now + seven_months),
+                next_block.is_valid(&previous_block, network),
                 "next block ({i}) not valid for devnet"
             );
 
@@ -1348,6 +2363,7 @@ mod archival_state_tests {
         {
             // 3. Create competing block 1 and treat it as new tip
             let (mock_block_1b, _, _) = make_mock_block_with_valid_pow(
+                network,
                 &genesis_block,
                 None,
                 own_receiving_address,
@@ -1379,7 +2395,7 @@ mod archival_state_tests {
                 .archival_state()
                 .archival_mutator_set
                 .ams()
-                .swbf_active
+                .active_window()
                 .sbf
                 .is_empty(),
             "Active window must be empty when no UTXOs have been spent"
@@ -1392,8 +2408,7 @@ mod archival_state_tests {
                 .archival_state()
                 .archival_mutator_set
                 .ams()
-                .aocl
-                .count_leaves().await as usize,
+                .aocl_leaf_count().await as usize,
             "AOCL leaf count must agree with #premine allocations + #transaction outputs in all blocks, even after rollback"
         );
 
@@ -1412,13 +2427,18 @@ mod archival_state_tests {
         let genesis_block = Block::genesis_block(network);
         let now = genesis_block.kernel.header.timestamp;
         let seven_months = Timestamp::months(7);
-        let (mut block_1_a, _, _) =
-            make_mock_block_with_valid_pow(&genesis_block, None, own_receiving_address, rng.gen());
+        let (mut block_1_a, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
         let global_state_lock = mock_genesis_global_state(network, 42, genesis_wallet).await;
 
         // Verify that block_1 that only contains the coinbase output is valid
         assert!(block_1_a.has_proof_of_work(&genesis_block));
-        assert!(block_1_a.is_valid(&genesis_block, now));
+        assert!(block_1_a.is_valid(&genesis_block, network));
 
         // Add a valid input to the block transaction
         let one_money: NeptuneCoins = NeptuneCoins::new(1);
@@ -1446,7 +2466,7 @@ mod archival_state_tests {
             .await;
 
         // Block with signed transaction must validate
-        assert!(block_1_a.is_valid(&genesis_block, now + seven_months));
+        assert!(block_1_a.is_valid(&genesis_block, network));
 
         Ok(())
     }
@@ -1479,6 +2499,7 @@ mod archival_state_tests {
         let seven_months = Timestamp::months(7);
 
         let (mut block_1, cb_utxo, cb_output_randomness) = make_mock_block_with_valid_pow(
+            network,
             &genesis_block,
             None,
             genesis_spending_key.to_address(),
@@ -1550,7 +2571,7 @@ mod archival_state_tests {
                     &genesis_block.kernel.body.mutator_set_accumulator,
                 )
                 .await;
-            assert!(block_1.is_valid(&genesis_block, launch + seven_months));
+            assert!(block_1.is_valid(&genesis_block, network));
         }
 
         println!("Accumulated transaction into block_1.");
@@ -1720,6 +2741,7 @@ mod archival_state_tests {
         // - 6 outputs: 2 from Alice to Genesis, 3 from Bob to Genesis, and 1 coinbase to Genesis
         let (mut block_2, cb_utxo_block_2, cb_sender_randomness_block_2) =
             make_mock_block_with_valid_pow(
+                network,
                 &block_1,
                 None,
                 genesis_spending_key.to_address(),
@@ -1739,7 +2761,7 @@ mod archival_state_tests {
         assert_eq!(4, block_2.kernel.body.transaction.kernel.inputs.len());
         assert_eq!(6, block_2.kernel.body.transaction.kernel.outputs.len());
         let now = block_1.kernel.header.timestamp;
-        assert!(block_2.is_valid(&block_1, now));
+        assert!(block_2.is_valid(&block_1, network));
 
         // Expect incoming UTXOs
         for rec_data in receiver_data_from_alice {
@@ -1871,8 +2893,13 @@ mod archival_state_tests {
             let own_wallet = WalletSecret::new_random();
             let own_receiving_address = own_wallet.nth_generation_spending_key(0).to_address();
             let genesis = *archival_state.genesis_block.clone();
-            let (mock_block_1, _, _) =
-                make_mock_block_with_valid_pow(&genesis, None, own_receiving_address, rng.gen());
+            let (mock_block_1, _, _) = make_mock_block_with_valid_pow(
+                network,
+                &genesis,
+                None,
+                own_receiving_address,
+                rng.gen(),
+            );
             add_block_to_archival_state(&mut archival_state, mock_block_1.clone())
                 .await
                 .unwrap();
@@ -1890,6 +2917,7 @@ mod archival_state_tests {
 
             // Add a 2nd block and verify that this new block is now returned
             let (mock_block_2, _, _) = make_mock_block_with_valid_pow(
+                network,
                 &mock_block_1,
                 None,
                 own_receiving_address,
@@ -1926,6 +2954,7 @@ mod archival_state_tests {
         let own_wallet = WalletSecret::new_random();
         let own_receiving_address = own_wallet.nth_generation_spending_key(0).to_address();
         let (mock_block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis.clone(),
             None,
             own_receiving_address,
@@ -1953,6 +2982,7 @@ mod archival_state_tests {
 
         // Inserted a new block and verify that both blocks can be found
         let (mock_block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_1.clone(),
             None,
             own_receiving_address,
@@ -1980,8 +3010,13 @@ mod archival_state_tests {
         let mut last_block = mock_block_2.clone();
         let mut blocks = vec![genesis, mock_block_1, mock_block_2];
         for _ in 0..(thread_rng().next_u32() % 20) {
-            let (new_block, _, _) =
-                make_mock_block_with_valid_pow(&last_block, None, own_receiving_address, rng.gen());
+            let (new_block, _, _) = make_mock_block_with_valid_pow(
+                network,
+                &last_block,
+                None,
+                own_receiving_address,
+                rng.gen(),
+            );
             add_block_to_archival_state(&mut archival_state, new_block.clone()).await?;
             blocks.push(new_block.clone());
             last_block = new_block;
@@ -2027,6 +3062,7 @@ mod archival_state_tests {
         let own_wallet = WalletSecret::new_random();
         let own_receiving_address = own_wallet.nth_generation_spending_key(0).to_address();
         let (mock_block_1_a, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis.clone(),
             None,
             own_receiving_address,
@@ -2035,6 +3071,7 @@ mod archival_state_tests {
         add_block_to_archival_state(&mut archival_state, mock_block_1_a.clone()).await?;
 
         let (mock_block_1_b, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis.clone(),
             None,
             own_receiving_address,
@@ -2093,35 +3130,217 @@ mod archival_state_tests {
 
     #[traced_test]
     #[tokio::test]
-    async fn fork_path_finding_test() -> Result<()> {
+    async fn cumulative_pow_test() -> Result<()> {
         let mut rng = thread_rng();
-        // Test behavior of fork-resolution functions such as `find_path` and checking if block
-        // belongs to canonical chain.
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
+        let genesis = *archival_state.genesis_block.clone();
+        let own_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        // genesis <- 1 <- 2a <- 3a
+        //              <- 2b
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        add_block_to_archival_state(&mut archival_state, block_1.clone()).await?;
+        let (block_2a, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        add_block_to_archival_state(&mut archival_state, block_2a.clone()).await?;
+        let (block_3a, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2a,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        add_block_to_archival_state(&mut archival_state, block_3a.clone()).await?;
+        let (block_2b, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        add_block_to_archival_state(&mut archival_state, block_2b.clone()).await?;
 
-        /// Assert that the `find_path` result agrees with the result from `get_ancestor_block_digests`
-        async fn dag_walker_leash_prop(
-            start: Digest,
-            stop: Digest,
-            archival_state: &ArchivalState,
-        ) {
-            let (mut backwards, luca, mut forwards) = archival_state.find_path(start, stop).await;
+        // The work of each block is the difficulty set by its parent
+        let expected = block_1.kernel.header.difficulty
+            + block_2a.kernel.header.difficulty
+            + genesis.kernel.header.difficulty;
+        assert_eq!(
+            expected,
+            archival_state
+                .cumulative_pow(genesis.hash(), block_3a.hash())
+                .await?
+        );
+        assert_eq!(
+            block_1.kernel.header.difficulty + block_2a.kernel.header.difficulty,
+            archival_state
+                .cumulative_pow(block_1.hash(), block_3a.hash())
+                .await?
+        );
+        assert_eq!(
+            block_1.kernel.header.difficulty,
+            archival_state
+                .cumulative_pow(block_1.hash(), block_2b.hash())
+                .await?
+        );
 
-            if let Some(last_forward) = forwards.pop() {
-                assert_eq!(
-                    stop, last_forward,
-                    "Last forward digest must be `stop` digest"
-                );
+        // An empty path carries no work
+        assert!(archival_state
+            .cumulative_pow(block_3a.hash(), block_3a.hash())
+            .await?
+            .is_zero());
 
-                // Verify that 1st element has luca as parent
-                let first_forward = if let Some(first) = forwards.first() {
-                    *first
-                } else {
-                    last_forward
-                };
+        // The segment's work is the growth of the accumulated proof-of-work
+        assert_eq!(
+            block_3a.kernel.header.proof_of_work_family,
+            block_1.kernel.header.proof_of_work_family
+                + archival_state
+                    .cumulative_pow(block_1.hash(), block_3a.hash())
+                    .await?
+        );
 
-                let first_forwards_block_header = archival_state
-                    .get_block_header(first_forward)
-                    .await
+        // Blocks on different branches are not connected by an upward path
+        assert!(archival_state
+            .cumulative_pow(block_2b.hash(), block_3a.hash())
+            .await
+            .is_err());
+        assert!(archival_state
+            .cumulative_pow(block_3a.hash(), block_1.hash())
+            .await
+            .is_err());
+        assert!(archival_state
+            .cumulative_pow(rng.gen(), block_3a.hash())
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn forks_above_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
+        let genesis = *archival_state.genesis_block.clone();
+        let own_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        // genesis <- 1 <- 2a <- 3a
+        //              <- 2b
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        add_block_to_archival_state(&mut archival_state, block_1.clone()).await?;
+        let (block_2a, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        add_block_to_archival_state(&mut archival_state, block_2a.clone()).await?;
+        let (block_3a, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2a,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        add_block_to_archival_state(&mut archival_state, block_3a.clone()).await?;
+
+        // A single chain is not a fork
+        assert!(archival_state
+            .forks_above(BlockHeight::from(0u64))
+            .await
+            .is_empty());
+
+        let (block_2b, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        add_block_to_archival_state(&mut archival_state, block_2b.clone()).await?;
+
+        assert_eq!(
+            vec![
+                vec![block_1.hash(), block_2a.hash(), block_3a.hash()],
+                vec![block_1.hash(), block_2b.hash()],
+            ],
+            archival_state.forks_above(BlockHeight::from(0u64)).await
+        );
+        assert_eq!(
+            vec![
+                vec![block_2a.hash(), block_3a.hash()],
+                vec![block_2b.hash()]
+            ],
+            archival_state.forks_above(BlockHeight::from(1u64)).await
+        );
+
+        // Above the fork, only a single chain remains
+        assert!(archival_state
+            .forks_above(BlockHeight::from(2u64))
+            .await
+            .is_empty());
+        assert!(archival_state
+            .forks_above(BlockHeight::from(3u64))
+            .await
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn fork_path_finding_test() -> Result<()> {
+        let mut rng = thread_rng();
+        // Test behavior of fork-resolution functions such as `find_path` and checking if block
+        // belongs to canonical chain.
+
+        /// Assert that the `find_path` result agrees with the result from `get_ancestor_block_digests`
+        async fn dag_walker_leash_prop(
+            start: Digest,
+            stop: Digest,
+            archival_state: &ArchivalState,
+        ) {
+            let (mut backwards, luca, mut forwards) = archival_state.find_path(start, stop).await;
+
+            if let Some(last_forward) = forwards.pop() {
+                assert_eq!(
+                    stop, last_forward,
+                    "Last forward digest must be `stop` digest"
+                );
+
+                // Verify that 1st element has luca as parent
+                let first_forward = if let Some(first) = forwards.first() {
+                    *first
+                } else {
+                    last_forward
+                };
+
+                let first_forwards_block_header = archival_state
+                    .get_block_header(first_forward)
+                    .await
                     .unwrap();
                 assert_eq!(
                     first_forwards_block_header.prev_block_digest, luca,
@@ -2176,6 +3395,7 @@ mod archival_state_tests {
         let own_wallet = WalletSecret::new_random();
         let own_receiving_address = own_wallet.nth_generation_spending_key(0).to_address();
         let (mock_block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis.clone(),
             None,
             own_receiving_address,
@@ -2197,6 +3417,7 @@ mod archival_state_tests {
 
         // Insert three more blocks and verify that all are part of the canonical chain
         let (mock_block_2_a, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_1.clone(),
             None,
             own_receiving_address,
@@ -2204,6 +3425,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_2_a.clone()).await?;
         let (mock_block_3_a, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_2_a.clone(),
             None,
             own_receiving_address,
@@ -2211,6 +3433,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_3_a.clone()).await?;
         let (mock_block_4_a, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_3_a.clone(),
             None,
             own_receiving_address,
@@ -2248,6 +3471,7 @@ mod archival_state_tests {
         // Make a tree and verify that the correct parts of the tree are identified as
         // belonging to the canonical chain
         let (mock_block_2_b, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_1.clone(),
             None,
             own_receiving_address,
@@ -2255,6 +3479,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_2_b.clone()).await?;
         let (mock_block_3_b, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_2_b.clone(),
             None,
             own_receiving_address,
@@ -2262,6 +3487,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_3_b.clone()).await?;
         let (mock_block_4_b, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_3_b.clone(),
             None,
             own_receiving_address,
@@ -2269,6 +3495,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_4_b.clone()).await?;
         let (mock_block_5_b, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_4_b.clone(),
             None,
             own_receiving_address,
@@ -2335,6 +3562,7 @@ mod archival_state_tests {
 
         // Prior to this line, block 4a is tip.
         let (mock_block_3_c, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_2_a.clone(),
             None,
             own_receiving_address,
@@ -2342,6 +3570,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_3_c.clone()).await?;
         let (mock_block_4_c, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_3_c.clone(),
             None,
             own_receiving_address,
@@ -2349,6 +3578,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_4_c.clone()).await?;
         let (mock_block_5_c, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_4_c.clone(),
             None,
             own_receiving_address,
@@ -2356,6 +3586,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_5_c.clone()).await?;
         let (mock_block_6_c, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_5_c.clone(),
             None,
             own_receiving_address,
@@ -2363,6 +3594,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_6_c.clone()).await?;
         let (mock_block_7_c, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_6_c.clone(),
             None,
             own_receiving_address,
@@ -2370,6 +3602,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_7_c.clone()).await?;
         let (mock_block_8_c, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_7_c.clone(),
             None,
             own_receiving_address,
@@ -2377,6 +3610,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_8_c.clone()).await?;
         let (mock_block_5_a, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_4_a.clone(),
             None,
             own_receiving_address,
@@ -2384,6 +3618,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_5_a.clone()).await?;
         let (mock_block_3_d, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_2_a.clone(),
             None,
             own_receiving_address,
@@ -2391,6 +3626,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_3_d.clone()).await?;
         let (mock_block_4_d, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_3_d.clone(),
             None,
             own_receiving_address,
@@ -2398,6 +3634,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_4_d.clone()).await?;
         let (mock_block_5_d, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_4_d.clone(),
             None,
             own_receiving_address,
@@ -2407,6 +3644,7 @@ mod archival_state_tests {
 
         // This is the most canonical block in the known set
         let (mock_block_6_d, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_5_d.clone(),
             None,
             own_receiving_address,
@@ -2415,6 +3653,7 @@ mod archival_state_tests {
         add_block_to_archival_state(&mut archival_state, mock_block_6_d.clone()).await?;
 
         let (mock_block_4_e, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_3_d.clone(),
             None,
             own_receiving_address,
@@ -2422,6 +3661,7 @@ mod archival_state_tests {
         );
         add_block_to_archival_state(&mut archival_state, mock_block_4_e.clone()).await?;
         let (mock_block_5_e, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_4_e.clone(),
             None,
             own_receiving_address,
@@ -2485,6 +3725,7 @@ mod archival_state_tests {
 
         // Make a new block, 6b, canonical and verify that all checks work
         let (mock_block_6_b, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_5_b.clone(),
             None,
             own_receiving_address,
@@ -2604,7 +3845,8 @@ mod archival_state_tests {
     #[tokio::test]
     async fn digest_of_ancestors_test() {
         let mut rng = thread_rng();
-        let mut archival_state = make_test_archival_state(Network::Alpha).await;
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
         let genesis = *archival_state.genesis_block.clone();
         let own_wallet = WalletSecret::new_random();
         let own_receiving_address = own_wallet.nth_generation_spending_key(0).to_address();
@@ -2624,6 +3866,7 @@ mod archival_state_tests {
 
         // Insert blocks and verify that the same result is returned
         let (mock_block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis.clone(),
             None,
             own_receiving_address,
@@ -2633,6 +3876,7 @@ mod archival_state_tests {
             .await
             .unwrap();
         let (mock_block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_1.clone(),
             None,
             own_receiving_address,
@@ -2642,6 +3886,7 @@ mod archival_state_tests {
             .await
             .unwrap();
         let (mock_block_3, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_2.clone(),
             None,
             own_receiving_address,
@@ -2651,6 +3896,7 @@ mod archival_state_tests {
             .await
             .unwrap();
         let (mock_block_4, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_3.clone(),
             None,
             own_receiving_address,
@@ -2716,16 +3962,649 @@ mod archival_state_tests {
             .is_empty());
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn get_block_is_served_from_cache_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
+        let genesis = *archival_state.genesis_block.clone();
+        let own_wallet = WalletSecret::new_random();
+        let own_receiving_address = own_wallet.nth_generation_spending_key(0).to_address();
+
+        let (mock_block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        let (mock_block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &mock_block_1,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        archival_state.write_block_as_tip(&mock_block_1).await?;
+        archival_state.write_block_as_tip(&mock_block_2).await?;
+        assert!(archival_state.block_cache.lock().unwrap().is_empty());
+
+        // Reading a block from disk caches it, and later reads return the same block
+        for _ in 0..3 {
+            assert_eq!(
+                mock_block_1,
+                archival_state
+                    .get_block(mock_block_1.hash())
+                    .await?
+                    .unwrap()
+            );
+        }
+        assert_eq!(1, archival_state.block_cache.lock().unwrap().len());
+
+        assert_eq!(
+            mock_block_2,
+            archival_state
+                .get_block(mock_block_2.hash())
+                .await?
+                .unwrap()
+        );
+        assert_eq!(2, archival_state.block_cache.lock().unwrap().len());
+
+        // Writing a block invalidates its cache entry
+        archival_state.write_block_as_tip(&mock_block_1).await?;
+        assert_eq!(1, archival_state.block_cache.lock().unwrap().len());
+        assert_eq!(
+            mock_block_1,
+            archival_state
+                .get_block(mock_block_1.hash())
+                .await?
+                .unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn stored_chain_is_checked_against_checkpoint_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
+        let genesis = *archival_state.genesis_block.clone();
+        let own_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        let (block_1_b, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        let (block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        archival_state.write_block_as_tip(&block_1).await?;
+
+        // The chain agrees with checkpoints on it, and with those it has not
+        // reached yet
+        archival_state
+            .check_checkpoint(&Checkpoint::from_block(&genesis))
+            .await?;
+        archival_state
+            .check_checkpoint(&Checkpoint::from_block(&block_1))
+            .await?;
+        archival_state
+            .check_checkpoint(&Checkpoint::from_block(&block_2))
+            .await?;
+
+        // Other blocks at a reached height, or another mutator set, disagree
+        assert!(archival_state
+            .check_checkpoint(&Checkpoint::from_block(&block_1_b))
+            .await
+            .is_err());
+        let mut tampered_checkpoint = Checkpoint::from_block(&block_1);
+        tampered_checkpoint.mutator_set_accumulator = genesis.kernel.body.mutator_set_accumulator;
+        assert!(archival_state
+            .check_checkpoint(&tampered_checkpoint)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn bootstrapping_from_checkpoint_matches_full_replay_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let own_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        // Replay a chain from genesis
+        let mut replayed_state = make_test_archival_state(network).await;
+        let mut blocks = vec![*replayed_state.genesis_block.clone()];
+        for _ in 0..3 {
+            let (block, _, _) = make_mock_block_with_valid_pow(
+                network,
+                blocks.last().unwrap(),
+                None,
+                own_receiving_address,
+                rng.gen(),
+            );
+            add_block_to_archival_state(&mut replayed_state, block.clone()).await?;
+            blocks.push(block);
+        }
+
+        // The trusted hash is that of the stored block, not of the snapshot
+        let trusted_hash =
+            Checkpoint::from_block(&replayed_state.get_block(blocks[3].hash()).await?.unwrap())
+                .hash();
+        let snapshot_path = replayed_state
+            .data_dir
+            .root_dir_path()
+            .join("checkpoint.bin");
+        replayed_state
+            .checkpoint_snapshot()
+            .await
+            .write_to_file(&snapshot_path)
+            .await?;
+        let snapshot = CheckpointSnapshot::load(&snapshot_path, trusted_hash).await?;
+
+        // Snapshots whose contents do not match the checkpoint are rejected
+        let mut tampered_snapshot = snapshot.clone();
+        tampered_snapshot.aocl_leafs.push(rng.gen());
+        assert!(make_test_archival_state(network)
+            .await
+            .bootstrap_from_checkpoint(tampered_snapshot)
+            .await
+            .is_err());
+        let mut tampered_snapshot = snapshot.clone();
+        tampered_snapshot.chunks.push(Chunk::empty_chunk());
+        assert!(make_test_archival_state(network)
+            .await
+            .bootstrap_from_checkpoint(tampered_snapshot)
+            .await
+            .is_err());
+
+        // Start from the checkpoint, without the blocks before it
+        let mut bootstrapped_state = make_test_archival_state(network).await;
+        let tip = bootstrapped_state
+            .bootstrap_from_checkpoint(snapshot.clone())
+            .await?;
+        assert_eq!(blocks[3].hash(), tip.hash());
+        assert_eq!(blocks[3].hash(), bootstrapped_state.get_tip().await.hash());
+        assert!(bootstrapped_state
+            .get_block(blocks[2].hash())
+            .await?
+            .is_none());
+        assert_eq!(
+            replayed_state.archival_mutator_set.ams().hash().await,
+            bootstrapped_state.archival_mutator_set.ams().hash().await
+        );
+
+        // Applying the next block to either state yields the same mutator set
+        let (block_4, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &blocks[3],
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        add_block_to_archival_state(&mut replayed_state, block_4.clone()).await?;
+        add_block_to_archival_state(&mut bootstrapped_state, block_4.clone()).await?;
+        assert_eq!(
+            block_4.kernel.body.mutator_set_accumulator.hash(),
+            bootstrapped_state.archival_mutator_set.ams().hash().await
+        );
+        assert_eq!(
+            replayed_state
+                .archival_mutator_set
+                .ams()
+                .accumulator()
+                .await,
+            bootstrapped_state
+                .archival_mutator_set
+                .ams()
+                .accumulator()
+                .await
+        );
+
+        // A node that stores blocks cannot start from a checkpoint
+        assert!(bootstrapped_state
+            .bootstrap_from_checkpoint(snapshot)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn blocks_stored_before_hex_prefix_index_are_indexed_on_startup_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
+        let genesis = *archival_state.genesis_block.clone();
+        let own_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        let (block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        archival_state.write_block_as_tip(&block_1).await?;
+        archival_state.write_block_as_tip(&block_2).await?;
+
+        // Stored blocks are found by their index key
+        let prefix_of = |block: &Block| HexPrefix::parse(&block.hash().to_hex()[..6]).unwrap();
+        assert!(archival_state
+            .block_digests_with_hex_prefix(&prefix_of(&block_1))
+            .await
+            .contains(&block_1.hash()));
+
+        // Drop the index, as in a database from before it existed
+        for block in [&block_1, &block_2] {
+            archival_state
+                .block_index_db
+                .delete(BlockIndexKey::HexPrefix(HexPrefix::index_key(
+                    &block.hash(),
+                )))
+                .await;
+        }
+        assert!(archival_state
+            .block_digests_with_hex_prefix(&prefix_of(&block_2))
+            .await
+            .is_empty());
+
+        archival_state.complete_hex_prefix_index().await;
+        for block in [&block_1, &block_2] {
+            assert!(archival_state
+                .block_digests_with_hex_prefix(&prefix_of(block))
+                .await
+                .contains(&block.hash()));
+        }
+        assert!(archival_state
+            .block_digests_with_hex_prefix(&HexPrefix::parse(&genesis.hash().to_hex()).unwrap())
+            .await
+            .contains(&genesis.hash()));
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn prune_transaction_witnesses_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
+        let genesis = *archival_state.genesis_block.clone();
+        let own_wallet = WalletSecret::new_random();
+        let own_receiving_address = own_wallet.nth_generation_spending_key(0).to_address();
+
+        let mut blocks = vec![];
+        let mut previous_block = genesis;
+        for _ in 0..5 {
+            let (block, _, _) = make_mock_block_with_valid_pow(
+                network,
+                &previous_block,
+                None,
+                own_receiving_address,
+                rng.gen(),
+            );
+            archival_state.write_block_as_tip(&block).await?;
+            assert!(!block.has_stripped_transaction_witness());
+            blocks.push(block.clone());
+            previous_block = block;
+        }
+        let block_file_path = archival_state.data_dir.block_file_path(0);
+        let original_file_size = tokio::fs::metadata(&block_file_path).await?.len();
+
+        // Prune the witnesses of blocks 1 through 3
+        assert_eq!(
+            3,
+            archival_state
+                .prune_transaction_witnesses(BlockHeight::from(3u64))
+                .await?
+        );
+        assert!(tokio::fs::metadata(&block_file_path).await?.len() < original_file_size);
+
+        for (i, block) in blocks.iter().enumerate() {
+            let stored_block = archival_state.get_block(block.hash()).await?.unwrap();
+            assert_eq!(block.hash(), stored_block.hash());
+            assert_eq!(
+                block.kernel.body.transaction.kernel,
+                stored_block.kernel.body.transaction.kernel
+            );
+            if i < 3 {
+                assert!(stored_block.has_stripped_transaction_witness());
+            } else {
+                assert_eq!(
+                    block.kernel.body.transaction.witness,
+                    stored_block.kernel.body.transaction.witness
+                );
+            }
+        }
+
+        // Blocks are pruned only once
+        assert_eq!(
+            0,
+            archival_state
+                .prune_transaction_witnesses(BlockHeight::from(3u64))
+                .await?
+        );
+        assert_eq!(
+            2,
+            archival_state
+                .prune_transaction_witnesses(BlockHeight::from(5u64))
+                .await?
+        );
+
+        // Blocks can still be appended to the rewritten file
+        let (block_6, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &blocks[4],
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        archival_state.write_block_as_tip(&block_6).await?;
+        assert_eq!(
+            block_6,
+            archival_state.get_block(block_6.hash()).await?.unwrap()
+        );
+        assert_eq!(
+            blocks[4].hash(),
+            archival_state
+                .get_block(blocks[4].hash())
+                .await?
+                .unwrap()
+                .hash()
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn interrupted_block_file_rewrite_is_finished_on_startup_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
+        let genesis = *archival_state.genesis_block.clone();
+        let own_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        let mut blocks = vec![];
+        let mut previous_block = genesis;
+        for _ in 0..3 {
+            let (block, _, _) = make_mock_block_with_valid_pow(
+                network,
+                &previous_block,
+                None,
+                own_receiving_address,
+                rng.gen(),
+            );
+            archival_state.write_block_as_tip(&block).await?;
+            blocks.push(block.clone());
+            previous_block = block;
+        }
+        archival_state
+            .prune_transaction_witnesses(BlockHeight::from(2u64))
+            .await?;
+
+        // Simulate a crash after the block index was updated, but before the
+        // rewritten file replaced the original
+        let block_file_path = archival_state.data_dir.block_file_path(0);
+        let compacted_file_path = block_file_path.with_extension("tmp");
+        let rewritten_file = tokio::fs::read(&block_file_path).await?;
+        tokio::fs::write(&compacted_file_path, &rewritten_file).await?;
+        tokio::fs::write(&block_file_path, vec![0u8; rewritten_file.len()]).await?;
+        let mut batch = WriteBatchAsync::new();
+        batch.op_write(
+            BlockIndexKey::PendingBlockFileRewrite,
+            BlockIndexValue::PendingBlockFileRewrite(0),
+        );
+        archival_state.block_index_db.batch_write(batch).await;
+        archival_state.block_cache = Mutex::new(BlockCache::new(0));
+
+        archival_state
+            .finish_interrupted_block_file_rewrite()
+            .await?;
+        assert!(!tokio::fs::try_exists(&compacted_file_path).await?);
+        assert!(archival_state
+            .block_index_db
+            .get(BlockIndexKey::PendingBlockFileRewrite)
+            .await
+            .is_none());
+        for block in blocks.iter() {
+            let stored_block = archival_state.get_block(block.hash()).await?.unwrap();
+            assert_eq!(block.hash(), stored_block.hash());
+        }
+
+        // If the original was replaced before the crash, only the record of
+        // the pending rewrite is removed
+        let mut batch = WriteBatchAsync::new();
+        batch.op_write(
+            BlockIndexKey::PendingBlockFileRewrite,
+            BlockIndexValue::PendingBlockFileRewrite(0),
+        );
+        archival_state.block_index_db.batch_write(batch).await;
+        archival_state
+            .finish_interrupted_block_file_rewrite()
+            .await?;
+        assert!(archival_state
+            .block_index_db
+            .get(BlockIndexKey::PendingBlockFileRewrite)
+            .await
+            .is_none());
+        assert_eq!(rewritten_file, tokio::fs::read(&block_file_path).await?);
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn scrubber_flags_corrupt_block_and_chunk_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
+        let own_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        let mut blocks = vec![archival_state.genesis_block().clone()];
+        for _ in 0..8 {
+            let (next_block, _, _) = make_mock_block_with_valid_pow(
+                network,
+                blocks.last().unwrap(),
+                None,
+                own_receiving_address,
+                rng.gen(),
+            );
+            add_block_to_archival_state(&mut archival_state, next_block.clone()).await?;
+            blocks.push(next_block);
+        }
+        for _ in 0..4 * BATCH_SIZE {
+            archival_state
+                .archival_mutator_set
+                .ams_mut()
+                .add(&random_addition_record())
+                .await;
+        }
+        let tip_height = blocks.last().unwrap().kernel.header.height;
+        let chunk_count = archival_state
+            .archival_mutator_set
+            .ams()
+            .chunk_count()
+            .await;
+        assert!(chunk_count >= 4);
+
+        // Intact records pass verification
+        let full_round = archival_state.scrub_sample(tip_height, 100, 100).await;
+        assert_eq!(8, full_round.blocks_checked);
+        assert_eq!(chunk_count as usize, full_round.chunks_checked);
+        assert!(full_round.corrupt_keys.is_empty());
+
+        // Corrupt the header of the oldest stored block, to which its digest
+        // commits, on disk
+        let old_block_digest = blocks[1].hash();
+        let block_record = archival_state
+            .block_index_db
+            .get(BlockIndexKey::Block(old_block_digest))
+            .await
+            .unwrap()
+            .as_block_record();
+        let block_file_path = archival_state
+            .data_dir
+            .block_file_path(block_record.file_location.file_index);
+        let mut file_bytes = tokio::fs::read(&block_file_path).await?;
+        let header_start = block_record.file_location.offset as usize;
+        for byte in file_bytes[header_start + 8..header_start + 40].iter_mut() {
+            *byte ^= 0xff;
+        }
+        tokio::fs::write(&block_file_path, file_bytes).await?;
+        assert!(archival_state
+            .verify_stored_block(old_block_digest)
+            .await
+            .is_err());
+
+        // Corrupt the oldest chunk without updating its commitment
+        let mut corrupt_chunk = archival_state.archival_mutator_set.ams().get_chunk(0).await;
+        corrupt_chunk.insert(0);
+        archival_state
+            .archival_mutator_set
+            .ams_mut()
+            .set_chunk(0, corrupt_chunk)
+            .await;
+
+        // Both are flagged within a bounded number of rounds of the default size
+        let expected_corrupt_keys =
+            HashSet::from([ScrubbedKey::Block(old_block_digest), ScrubbedKey::Chunk(0)]);
+        let mut rounds = 0;
+        while archival_state.storage_scrub_report().corrupt_keys.len() < 2 && rounds < 100 {
+            let round = archival_state
+                .scrub_sample(tip_height, SCRUB_BLOCKS_PER_ROUND, SCRUB_CHUNKS_PER_ROUND)
+                .await;
+            archival_state.record_scrub_round(round, SystemTime::now());
+            rounds += 1;
+        }
+        let report = archival_state.storage_scrub_report();
+        assert_eq!(
+            expected_corrupt_keys,
+            report.corrupt_keys.iter().copied().collect::<HashSet<_>>()
+        );
+        assert_eq!(rounds, report.rounds);
+        assert!(!report.is_healthy());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn scrubber_reports_undecodable_records_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
+        let own_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        let mut blocks = vec![archival_state.genesis_block().clone()];
+        for _ in 0..4 {
+            let (next_block, _, _) = make_mock_block_with_valid_pow(
+                network,
+                blocks.last().unwrap(),
+                None,
+                own_receiving_address,
+                rng.gen(),
+            );
+            add_block_to_archival_state(&mut archival_state, next_block.clone()).await?;
+            blocks.push(next_block);
+        }
+        let tip_height = blocks.last().unwrap().kernel.header.height;
+
+        // Overwrite the block index entries of block 1 and of height 3 with
+        // bytes that do not decode
+        let garbage = vec![0xff; 16];
+        for key in [
+            BlockIndexKey::Block(blocks[1].hash()),
+            BlockIndexKey::Height(3u64.into()),
+        ] {
+            archival_state
+                .block_index_db
+                .put_u8(bincode::serialize(&key)?, garbage.clone())
+                .await;
+        }
+
+        // Overwrite block 2 on disk with the same bytes
+        let block_record = archival_state
+            .block_index_db
+            .get(BlockIndexKey::Block(blocks[2].hash()))
+            .await
+            .unwrap()
+            .as_block_record();
+        let block_file_path = archival_state
+            .data_dir
+            .block_file_path(block_record.file_location.file_index);
+        let mut file_bytes = tokio::fs::read(&block_file_path).await?;
+        let block_start = block_record.file_location.offset as usize;
+        let block_end = block_start + block_record.file_location.block_length;
+        file_bytes[block_start..block_end].fill(0xff);
+        tokio::fs::write(&block_file_path, file_bytes).await?;
+
+        // The records are reported rather than crashing the scrubber
+        let round = archival_state.scrub_sample(tip_height, 100, 0).await;
+        let expected_corrupt_keys = HashSet::from([
+            ScrubbedKey::Block(blocks[1].hash()),
+            ScrubbedKey::Block(blocks[2].hash()),
+            ScrubbedKey::Height(3u64.into()),
+        ]);
+        assert_eq!(
+            expected_corrupt_keys,
+            round.corrupt_keys.iter().copied().collect::<HashSet<_>>()
+        );
+        assert_eq!(4, round.blocks_checked);
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn write_block_db_test() -> Result<()> {
         let mut rng = thread_rng();
-        let mut archival_state = make_test_archival_state(Network::Alpha).await;
+        let network = Network::Alpha;
+        let mut archival_state = make_test_archival_state(network).await;
         let genesis = *archival_state.genesis_block.clone();
         let own_wallet = WalletSecret::new_random();
         let own_receiving_address = own_wallet.nth_generation_spending_key(0).to_address();
 
         let (mock_block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis.clone(),
             None,
             own_receiving_address,
@@ -2813,6 +4692,7 @@ mod archival_state_tests {
 
         // Store another block and verify that this block is appended to disk
         let (mock_block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
             &mock_block_1.clone(),
             None,
             own_receiving_address,
@@ -2966,6 +4846,212 @@ mod archival_state_tests {
         Ok(())
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn mutator_set_statistics_match_replayed_blocks_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let mut archival_state = make_test_archival_state(network).await;
+        let genesis_state_lock =
+            mock_genesis_global_state(network, 3, WalletSecret::devnet_wallet()).await;
+        let own_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        // Spend the premine in block 1, such that the replay sees removals
+        let genesis_block = archival_state.genesis_block().clone();
+        let (mut block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        let receiver_data = vec![UtxoReceiverData {
+            public_announcement: PublicAnnouncement::default(),
+            receiver_privacy_digest: own_receiving_address.privacy_digest,
+            sender_randomness: random(),
+            utxo: Utxo {
+                lock_script_hash: own_receiving_address.lock_script().hash(),
+                coins: NeptuneCoins::new(20).to_native_coins(),
+            },
+        }];
+        let tx = create_transaction_with_timestamp(
+            &genesis_state_lock,
+            &receiver_data,
+            NeptuneCoins::one(),
+            genesis_block.kernel.header.timestamp + Timestamp::months(7),
+        )
+        .await?;
+        block_1
+            .accumulate_transaction(tx, &genesis_block.kernel.body.mutator_set_accumulator)
+            .await;
+        assert!(block_1.is_valid(&genesis_block, network));
+        add_block_to_archival_state(&mut archival_state, block_1.clone()).await?;
+
+        let mut blocks = vec![genesis_block, block_1];
+        for _ in 0..4 {
+            let (next_block, _, _) = make_mock_block_with_valid_pow(
+                network,
+                blocks.last().unwrap(),
+                None,
+                own_receiving_address,
+                rng.gen(),
+            );
+            add_block_to_archival_state(&mut archival_state, next_block.clone()).await?;
+            blocks.push(next_block);
+        }
+
+        // Independently count additions and removals by replaying the blocks
+        let expected_additions: u64 = blocks
+            .iter()
+            .map(|block| block.kernel.body.transaction.kernel.outputs.len() as u64)
+            .sum();
+        let expected_removals: u64 = blocks
+            .iter()
+            .map(|block| block.kernel.body.transaction.kernel.inputs.len() as u64)
+            .sum();
+        assert!(!blocks[1].kernel.body.transaction.kernel.inputs.is_empty());
+
+        let batch_totals = archival_state.mutator_set_batch_index_totals().await?;
+        assert_eq!(
+            expected_additions,
+            batch_totals.iter().map(|t| t.additions).sum::<u64>()
+        );
+        assert_eq!(
+            expected_removals,
+            batch_totals.iter().map(|t| t.removals).sum::<u64>()
+        );
+        assert!(batch_totals
+            .iter()
+            .all(|t| t.additions <= BATCH_SIZE as u64));
+
+        let export_path = archival_state
+            .data_dir
+            .root_dir_path()
+            .join("mutator_set_statistics.csv");
+        let statistics = archival_state
+            .export_mutator_set_statistics(&export_path)
+            .await?;
+        assert_eq!(expected_additions, statistics.aocl_leaf_count);
+        assert_eq!(
+            expected_removals * NUM_TRIALS as u64,
+            statistics.active_window_set_bits + statistics.inactive_set_bits
+        );
+        assert_eq!(
+            archival_state
+                .archival_mutator_set
+                .ams()
+                .chunk_count()
+                .await,
+            statistics.chunk_count
+        );
+        let csv = tokio::fs::read_to_string(&export_path).await?;
+        assert_eq!(
+            batch_totals.len(),
+            csv.lines().filter(|row| row.starts_with("batch,")).count()
+        );
+
+        // Cached statistics are only recomputed after enough blocks
+        let tip_height = blocks.last().unwrap().kernel.header.height;
+        let (computed_at, cached_statistics) =
+            archival_state.mutator_set_statistics(tip_height).await;
+        assert_eq!(tip_height, computed_at);
+        assert_eq!(statistics, cached_statistics);
+        let (computed_at, _) = archival_state
+            .mutator_set_statistics(tip_height.next())
+            .await;
+        assert_eq!(tip_height, computed_at);
+        let far_height = tip_height + MUTATOR_SET_STATISTICS_REFRESH_INTERVAL as usize;
+        let (computed_at, _) = archival_state.mutator_set_statistics(far_height).await;
+        assert_eq!(far_height, computed_at);
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn swbf_saturation_alarm_fires_past_threshold_test() {
+        let network = Network::RegTest;
+        let mut archival_state = make_test_archival_state(network).await;
+
+        // Without removals, no bits are set and the alarm stays silent
+        let mut tip_height = BlockHeight::genesis();
+        assert!(archival_state
+            .check_swbf_saturation(tip_height, 0.0)
+            .await
+            .is_none());
+
+        let num_items = 40;
+        let ams = archival_state.archival_mutator_set.ams_mut();
+        let mut membership_proofs: Vec<MsMembershipProof> = vec![];
+        let mut items: Vec<Digest> = vec![];
+        for _ in 0..num_items {
+            let (item, sender_randomness, receiver_preimage) = make_item_and_randomnesses();
+            let addition_record = commit(item, sender_randomness, receiver_preimage.hash::<Hash>());
+            let membership_proof = ams.prove(item, sender_randomness, receiver_preimage).await;
+            MsMembershipProof::batch_update_from_addition(
+                &mut membership_proofs.iter_mut().collect_vec(),
+                &items,
+                &ams.accumulator().await,
+                &addition_record,
+            )
+            .expect("MS membership update must work");
+            ams.add(&addition_record).await;
+            membership_proofs.push(membership_proof);
+            items.push(item);
+        }
+
+        // Remove half of the items, and use the resulting false-positive rate
+        // as the threshold
+        let (first_half_proofs, second_half_proofs) = membership_proofs.split_at_mut(num_items / 2);
+        let (first_half_items, second_half_items) = items.split_at(num_items / 2);
+        let mut first_half_removal_records = vec![];
+        for (item, membership_proof) in first_half_items.iter().zip_eq(first_half_proofs.iter()) {
+            first_half_removal_records.push(ams.drop(*item, membership_proof).await);
+        }
+        ams.batch_remove(
+            first_half_removal_records,
+            &mut second_half_proofs.iter_mut().collect_vec(),
+        )
+        .await;
+
+        tip_height = tip_height + MUTATOR_SET_STATISTICS_REFRESH_INTERVAL as usize;
+        let threshold = archival_state
+            .check_swbf_saturation(tip_height, 0.0)
+            .await
+            .expect("Removals must raise the false-positive rate above zero");
+        assert!(archival_state
+            .check_swbf_saturation(tip_height, threshold)
+            .await
+            .is_none());
+
+        // Removing the other half pushes the rate past the threshold
+        let mut removal_records = vec![];
+        for (item, membership_proof) in second_half_items.iter().zip_eq(second_half_proofs.iter()) {
+            removal_records.push(
+                archival_state
+                    .archival_mutator_set
+                    .ams()
+                    .drop(*item, membership_proof)
+                    .await,
+            );
+        }
+        archival_state
+            .archival_mutator_set
+            .ams_mut()
+            .batch_remove(removal_records, &mut [])
+            .await;
+
+        tip_height = tip_height + MUTATOR_SET_STATISTICS_REFRESH_INTERVAL as usize;
+        let false_positive_rate = archival_state
+            .check_swbf_saturation(tip_height, threshold)
+            .await
+            .expect("Alarm must fire once the threshold is crossed");
+        assert!(false_positive_rate > threshold);
+        assert!(logs_contain("Sliding-window Bloom filter is saturating"));
+    }
+
     use crate::config_models::{cli_args, data_directory::DataDirectory};
 
     #[traced_test]
@@ -0,0 +1,126 @@
+use crate::prelude::twenty_first;
+
+use std::collections::{HashMap, VecDeque};
+use twenty_first::math::digest::Digest;
+
+use crate::models::blockchain::block::Block;
+
+/// Default number of blocks kept in the [`BlockCache`] of the archival state.
+pub const DEFAULT_BLOCK_CACHE_CAPACITY: usize = 64;
+
+/// A least-recently-used cache of blocks, keyed by block digest.
+///
+/// Reading a block from disk requires deserializing it in full. Some
+/// operations, e.g. resynchronizing the membership proofs of many UTXOs
+/// across a fork, read the same handful of blocks over and over again, so
+/// the archival state keeps the most recently read ones in memory.
+///
+/// A capacity of zero disables the cache.
+#[derive(Debug, Clone)]
+pub struct BlockCache {
+    capacity: usize,
+    blocks: HashMap<Digest, Block>,
+
+    // Digests of cached blocks, from least to most recently used
+    recency: VecDeque<Digest>,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            blocks: HashMap::with_capacity(capacity),
+            recency: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    fn touch(&mut self, block_digest: Digest) {
+        if let Some(position) = self.recency.iter().position(|d| *d == block_digest) {
+            self.recency.remove(position);
+        }
+        self.recency.push_back(block_digest);
+    }
+
+    /// Return a copy of the cached block, and mark it as most recently used.
+    pub fn get(&mut self, block_digest: Digest) -> Option<Block> {
+        let block = self.blocks.get(&block_digest)?.clone();
+        self.touch(block_digest);
+        Some(block)
+    }
+
+    /// Cache the block, evicting the least recently used one if the cache is
+    /// full.
+    pub fn insert(&mut self, block_digest: Digest, block: Block) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.blocks.insert(block_digest, block);
+        self.touch(block_digest);
+
+        while self.recency.len() > self.capacity {
+            if let Some(evicted) = self.recency.pop_front() {
+                self.blocks.remove(&evicted);
+            }
+        }
+    }
+
+    /// Drop the block from the cache, if present.
+    pub fn remove(&mut self, block_digest: Digest) {
+        if self.blocks.remove(&block_digest).is_some() {
+            self.recency.retain(|d| *d != block_digest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod block_cache_tests {
+    use rand::random;
+
+    use super::*;
+    use crate::config_models::network::Network;
+
+    #[test]
+    fn least_recently_used_block_is_evicted_test() {
+        let block = Block::genesis_block(Network::RegTest);
+        let mut cache = BlockCache::new(2);
+        let (digest_a, digest_b, digest_c): (Digest, Digest, Digest) =
+            (random(), random(), random());
+
+        cache.insert(digest_a, block.clone());
+        cache.insert(digest_b, block.clone());
+        assert_eq!(Some(block.clone()), cache.get(digest_a));
+
+        // `digest_b` is now the least recently used
+        cache.insert(digest_c, block.clone());
+        assert_eq!(2, cache.len());
+        assert!(cache.get(digest_b).is_none());
+        assert!(cache.get(digest_a).is_some());
+        assert!(cache.get(digest_c).is_some());
+
+        cache.remove(digest_a);
+        assert!(cache.get(digest_a).is_none());
+        assert_eq!(1, cache.len());
+    }
+
+    #[test]
+    fn zero_capacity_disables_cache_test() {
+        let mut cache = BlockCache::new(0);
+        let digest: Digest = random();
+        cache.insert(digest, Block::genesis_block(Network::RegTest));
+        assert!(cache.is_empty());
+        assert!(cache.get(digest).is_none());
+    }
+}
@@ -1,3 +1,7 @@
+use crate::prelude::twenty_first;
+
+use twenty_first::math::digest::Digest;
+
 use super::{archival_state::ArchivalState, light_state::LightState};
 
 /// `BlockChainState` provides an `Archival` variant
@@ -91,3 +95,17 @@ pub struct BlockchainArchivalState {
     /// The present tip.
     pub light_state: LightState,
 }
+
+/// A change to the canonical chain that state derived from it, like the
+/// wallet's membership proofs, must react to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// The tip moved to a block on another branch.
+    Reorg {
+        /// Blocks that are no longer canonical, from the old tip down to, but
+        /// excluding, the last common ancestor with the new branch.
+        disconnected: Vec<Digest>,
+
+        new_tip: Digest,
+    },
+}
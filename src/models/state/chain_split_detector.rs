@@ -0,0 +1,559 @@
+//! Detection of chain splits, i.e. connected peers persistently announcing
+//! tips that are not part of this node's canonical chain. This happens in case
+//! of consensus bugs and attacks, or when this node is stale.
+
+use crate::models::blockchain::block::block_height::BlockHeight;
+use crate::prelude::twenty_first;
+
+use serde::{Deserialize, Serialize};
+use std::cmp::max;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use twenty_first::math::digest::Digest;
+
+/// Peers whose chain differs from this node's by fewer blocks are attributed
+/// to propagation delays and competing miners.
+pub const CHAIN_SPLIT_MIN_DEPTH: u64 = 3;
+
+/// Minimum share, in percent, of the peers with a claimed tip that must
+/// diverge before a chain split is suspected.
+pub const CHAIN_SPLIT_MIN_PEER_PERCENTAGE: usize = 50;
+
+/// Duration for which peers must diverge before a chain split is suspected.
+pub const CHAIN_SPLIT_MIN_DURATION: Duration = Duration::from_secs(10 * 60);
+
+/// Number of most recent tips remembered per peer.
+const TIP_CLAIM_HISTORY_LENGTH: usize = 16;
+
+/// Number of events buffered for subscribers that lag behind.
+const NETWORK_EVENT_CHANNEL_CAPACITY: usize = 16;
+
+/// A tip announced by a peer.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TipClaim {
+    pub digest: Digest,
+    pub height: BlockHeight,
+    pub claimed_at: SystemTime,
+}
+
+/// A peer whose latest claimed tip is not part of this node's canonical chain.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DivergingPeer {
+    pub peer_address: SocketAddr,
+    pub latest_claim: TipClaim,
+
+    /// Lower bound on the number of blocks by which the peer's chain differs
+    /// from this node's
+    pub depth: u64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ChainSplitKind {
+    /// All diverging peers claim tips above this node's tip, so this node is
+    /// likely stale, e.g. because it fails to download blocks.
+    OwnNodeBehind,
+
+    /// Some diverging peers claim tips at or below the height of this node's
+    /// tip, so they follow a competing chain.
+    CompetingChain,
+}
+
+/// Diverging peers that follow the same chain, as far as their claims tell.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TipCluster {
+    /// Sorted
+    pub peer_addresses: Vec<SocketAddr>,
+
+    /// The highest latest tip claimed by any of the peers
+    pub tip: TipClaim,
+}
+
+/// Details of a suspected chain split.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChainSplitAlert {
+    pub kind: ChainSplitKind,
+
+    /// When the peers started diverging
+    pub diverging_since: SystemTime,
+    pub own_tip_digest: Digest,
+    pub own_tip_height: BlockHeight,
+
+    /// Number of peers that claimed a tip
+    pub peer_count: usize,
+
+    /// Sorted by peer address
+    pub diverging_peers: Vec<DivergingPeer>,
+
+    /// The chains followed by the diverging peers, largest first. Peers
+    /// that claimed a common tip outside the canonical chain follow the same
+    /// chain.
+    pub clusters: Vec<TipCluster>,
+}
+
+/// Notifications about the network as seen through the connected peers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetworkEvent {
+    ChainSplitSuspected(Box<ChainSplitAlert>),
+    ChainSplitResolved,
+}
+
+/// Tracks the tips announced by connected peers, and raises an alert when a
+/// significant share of them persistently diverges from the canonical chain.
+#[derive(Debug, Clone)]
+pub struct ChainSplitDetector {
+    // Most recent tips of every connected peer, oldest first
+    claims: HashMap<SocketAddr, VecDeque<TipClaim>>,
+
+    // Since when the peers are diverging, if they are
+    diverging_since: Option<SystemTime>,
+
+    // Present until the peers agree again
+    alert: Option<ChainSplitAlert>,
+
+    event_tx: broadcast::Sender<NetworkEvent>,
+}
+
+impl Default for ChainSplitDetector {
+    fn default() -> Self {
+        Self {
+            claims: HashMap::new(),
+            diverging_since: None,
+            alert: None,
+            event_tx: broadcast::channel(NETWORK_EVENT_CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl ChainSplitDetector {
+    /// Record a tip announced by a peer.
+    pub fn record_claim(
+        &mut self,
+        peer_address: SocketAddr,
+        digest: Digest,
+        height: BlockHeight,
+        claimed_at: SystemTime,
+    ) {
+        let claims = self.claims.entry(peer_address).or_default();
+        if claims.back().is_some_and(|claim| claim.digest == digest) {
+            return;
+        }
+        if claims.len() == TIP_CLAIM_HISTORY_LENGTH {
+            claims.pop_front();
+        }
+        claims.push_back(TipClaim {
+            digest,
+            height,
+            claimed_at,
+        });
+    }
+
+    /// Forget the tips of a disconnected peer.
+    pub fn forget_peer(&mut self, peer_address: SocketAddr) {
+        self.claims.remove(&peer_address);
+    }
+
+    /// The digests of all remembered tips, such that the caller can determine
+    /// which of them are part of the canonical chain.
+    pub fn claimed_digests(&self) -> HashSet<Digest> {
+        self.claims
+            .values()
+            .flatten()
+            .map(|claim| claim.digest)
+            .collect()
+    }
+
+    /// The current alert, if a chain split is suspected.
+    pub fn alert(&self) -> Option<&ChainSplitAlert> {
+        self.alert.as_ref()
+    }
+
+    /// Subscribe to notifications about suspected and resolved chain splits.
+    pub fn subscribe_to_events(&self) -> broadcast::Receiver<NetworkEvent> {
+        self.event_tx.subscribe()
+    }
+
+    /// Compare the claimed tips to this node's canonical chain, given by its
+    /// tip and the set of claimed digests that belong to it. Raises an alert
+    /// once at least [CHAIN_SPLIT_MIN_PEER_PERCENTAGE] of the peers have been
+    /// diverging by at least [CHAIN_SPLIT_MIN_DEPTH] blocks for
+    /// [CHAIN_SPLIT_MIN_DURATION], and clears it once they no longer are.
+    pub fn evaluate(
+        &mut self,
+        own_tip_digest: Digest,
+        own_tip_height: BlockHeight,
+        canonical_digests: &HashSet<Digest>,
+        now: SystemTime,
+    ) -> Option<&ChainSplitAlert> {
+        let mut diverging_peers = self
+            .claims
+            .iter()
+            .filter_map(|(peer_address, claims)| {
+                let depth = Self::divergence_depth(claims, own_tip_height, canonical_digests)?;
+                Some(DivergingPeer {
+                    peer_address: *peer_address,
+                    latest_claim: *claims.back()?,
+                    depth,
+                })
+            })
+            .filter(|peer| peer.depth >= CHAIN_SPLIT_MIN_DEPTH)
+            .collect::<Vec<_>>();
+        let peer_count = self.claims.len();
+        let is_diverging = !diverging_peers.is_empty()
+            && diverging_peers.len() * 100 >= CHAIN_SPLIT_MIN_PEER_PERCENTAGE * peer_count;
+
+        if !is_diverging {
+            self.diverging_since = None;
+            if self.alert.take().is_some() {
+                info!("Connected peers agree with the canonical chain again");
+                let _ = self.event_tx.send(NetworkEvent::ChainSplitResolved);
+            }
+            return None;
+        }
+
+        let diverging_since = *self.diverging_since.get_or_insert(now);
+        if now.duration_since(diverging_since).unwrap_or_default() < CHAIN_SPLIT_MIN_DURATION {
+            return None;
+        }
+
+        diverging_peers.sort_by_key(|peer| peer.peer_address);
+        let kind = if diverging_peers
+            .iter()
+            .all(|peer| peer.latest_claim.height > own_tip_height)
+        {
+            ChainSplitKind::OwnNodeBehind
+        } else {
+            ChainSplitKind::CompetingChain
+        };
+        let clusters = self.cluster(&diverging_peers, canonical_digests);
+        let alert = ChainSplitAlert {
+            kind,
+            diverging_since,
+            own_tip_digest,
+            own_tip_height,
+            peer_count,
+            diverging_peers,
+            clusters,
+        };
+
+        if self.alert.is_none() {
+            warn!(
+                "Chain split suspected: {} of {peer_count} peers on {} chains disagree with tip {own_tip_digest} at height {own_tip_height} ({kind:?})",
+                alert.diverging_peers.len(),
+                alert.clusters.len(),
+            );
+            let _ = self
+                .event_tx
+                .send(NetworkEvent::ChainSplitSuspected(Box::new(alert.clone())));
+        }
+        self.alert = Some(alert);
+
+        self.alert.as_ref()
+    }
+
+    /// Group the diverging peers by the chain they follow. Two peers are
+    /// assumed to follow the same chain if they claimed a common tip outside
+    /// the canonical chain, directly or through other peers.
+    fn cluster(
+        &self,
+        diverging_peers: &[DivergingPeer],
+        canonical_digests: &HashSet<Digest>,
+    ) -> Vec<TipCluster> {
+        let mut clusters: Vec<(HashSet<Digest>, TipCluster)> = vec![];
+        for peer in diverging_peers {
+            let mut digests = self.claims[&peer.peer_address]
+                .iter()
+                .map(|claim| claim.digest)
+                .filter(|digest| !canonical_digests.contains(digest))
+                .collect::<HashSet<_>>();
+            let mut cluster = TipCluster {
+                peer_addresses: vec![peer.peer_address],
+                tip: peer.latest_claim,
+            };
+
+            // Merge all clusters that share a tip with this peer
+            let (overlapping, mut disjoint): (Vec<_>, Vec<_>) = clusters
+                .into_iter()
+                .partition(|(other_digests, _)| !other_digests.is_disjoint(&digests));
+            for (other_digests, other_cluster) in overlapping {
+                digests.extend(other_digests);
+                cluster.peer_addresses.extend(other_cluster.peer_addresses);
+                if other_cluster.tip.height > cluster.tip.height {
+                    cluster.tip = other_cluster.tip;
+                }
+            }
+            cluster.peer_addresses.sort();
+            disjoint.push((digests, cluster));
+            clusters = disjoint;
+        }
+
+        let mut clusters = clusters
+            .into_iter()
+            .map(|(_, cluster)| cluster)
+            .collect::<Vec<_>>();
+        clusters.sort_by(|a, b| {
+            b.peer_addresses
+                .len()
+                .cmp(&a.peer_addresses.len())
+                .then_with(|| a.peer_addresses.cmp(&b.peer_addresses))
+        });
+        clusters
+    }
+
+    /// Return `None` if the latest tip claimed by the peer is part of the
+    /// canonical chain. Otherwise, return the number of heights spanned by its
+    /// most recent claims outside the canonical chain, or the number of
+    /// blocks by which its tip is ahead of this node's, whichever is larger.
+    fn divergence_depth(
+        claims: &VecDeque<TipClaim>,
+        own_tip_height: BlockHeight,
+        canonical_digests: &HashSet<Digest>,
+    ) -> Option<u64> {
+        let latest_claim = claims.back()?;
+        if canonical_digests.contains(&latest_claim.digest) {
+            return None;
+        }
+
+        let earliest_diverging_claim = claims
+            .iter()
+            .rev()
+            .take_while(|claim| !canonical_digests.contains(&claim.digest))
+            .last()?;
+        let latest_height = u64::from(latest_claim.height);
+        let span = latest_height
+            .saturating_sub(u64::from(earliest_diverging_claim.height))
+            .saturating_add(1);
+        let lead = latest_height.saturating_sub(u64::from(own_tip_height));
+
+        Some(max(span, lead))
+    }
+}
+
+#[cfg(test)]
+mod chain_split_detector_tests {
+    use super::*;
+    use crate::tests::shared::get_dummy_socket_address;
+    use rand::random;
+    use tracing_test::traced_test;
+
+    #[traced_test]
+    #[test]
+    fn propagation_races_do_not_raise_alert_test() {
+        let mut detector = ChainSplitDetector::default();
+        let mut events = detector.subscribe_to_events();
+        let start = SystemTime::now();
+        let own_tip_digest: Digest = random();
+        let own_tip_height = BlockHeight::from(10u64);
+        let canonical_digests = HashSet::from([own_tip_digest]);
+
+        // Two peers know a competing block at the same height, one peer is a
+        // block ahead, and one agrees.
+        let peers = (0..4).map(get_dummy_socket_address).collect::<Vec<_>>();
+        detector.record_claim(peers[0], random(), own_tip_height, start);
+        detector.record_claim(peers[1], random(), own_tip_height, start);
+        detector.record_claim(peers[2], random(), own_tip_height.next(), start);
+        detector.record_claim(peers[3], own_tip_digest, own_tip_height, start);
+
+        for elapsed in [Duration::ZERO, 2 * CHAIN_SPLIT_MIN_DURATION] {
+            assert!(detector
+                .evaluate(
+                    own_tip_digest,
+                    own_tip_height,
+                    &canonical_digests,
+                    start + elapsed
+                )
+                .is_none());
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    #[traced_test]
+    #[test]
+    fn competing_chain_raises_and_clears_alert_test() {
+        let mut detector = ChainSplitDetector::default();
+        let mut events = detector.subscribe_to_events();
+        let start = SystemTime::now();
+        let own_tip_digest: Digest = random();
+        let own_tip_height = BlockHeight::from(10u64);
+        let mut canonical_digests = HashSet::from([own_tip_digest]);
+
+        // Three of four peers announce three blocks of a competing chain
+        let peers = (0..4).map(get_dummy_socket_address).collect::<Vec<_>>();
+        let competing_tips = (8u64..=10)
+            .map(|height| (random::<Digest>(), BlockHeight::from(height)))
+            .collect::<Vec<_>>();
+        for peer in &peers[..3] {
+            for (digest, height) in competing_tips.iter() {
+                detector.record_claim(*peer, *digest, *height, start);
+            }
+        }
+        detector.record_claim(peers[3], own_tip_digest, own_tip_height, start);
+
+        // The peers must diverge for long enough
+        assert!(detector
+            .evaluate(own_tip_digest, own_tip_height, &canonical_digests, start)
+            .is_none());
+        let later = start + CHAIN_SPLIT_MIN_DURATION;
+        let alert = detector
+            .evaluate(own_tip_digest, own_tip_height, &canonical_digests, later)
+            .cloned()
+            .unwrap();
+        assert_eq!(ChainSplitKind::CompetingChain, alert.kind);
+        assert_eq!(start, alert.diverging_since);
+        assert_eq!(4, alert.peer_count);
+        let mut expected_peers = peers[..3].to_vec();
+        expected_peers.sort();
+        assert_eq!(
+            expected_peers,
+            alert
+                .diverging_peers
+                .iter()
+                .map(|peer| peer.peer_address)
+                .collect::<Vec<_>>()
+        );
+        assert!(alert
+            .diverging_peers
+            .iter()
+            .all(|peer| peer.latest_claim.digest == competing_tips[2].0 && peer.depth == 3));
+        assert_eq!(1, alert.clusters.len());
+        assert_eq!(expected_peers, alert.clusters[0].peer_addresses);
+        assert_eq!(competing_tips[2].0, alert.clusters[0].tip.digest);
+        assert_eq!(Some(&alert), detector.alert());
+        assert_eq!(
+            NetworkEvent::ChainSplitSuspected(Box::new(alert)),
+            events.try_recv().unwrap()
+        );
+
+        // The alert persists without further events
+        assert!(detector
+            .evaluate(own_tip_digest, own_tip_height, &canonical_digests, later)
+            .is_some());
+        assert!(events.try_recv().is_err());
+
+        // Once this node switches to the competing chain, the peers agree
+        canonical_digests.extend(competing_tips.iter().map(|(digest, _)| *digest));
+        assert!(detector
+            .evaluate(
+                competing_tips[2].0,
+                competing_tips[2].1,
+                &canonical_digests,
+                later
+            )
+            .is_none());
+        assert!(detector.alert().is_none());
+        assert_eq!(NetworkEvent::ChainSplitResolved, events.try_recv().unwrap());
+    }
+
+    #[traced_test]
+    #[test]
+    fn stale_node_raises_alert_test() {
+        let mut detector = ChainSplitDetector::default();
+        let start = SystemTime::now();
+        let own_tip_digest: Digest = random();
+        let own_tip_height = BlockHeight::from(10u64);
+        let canonical_digests = HashSet::from([own_tip_digest]);
+
+        // Two of three peers are far ahead
+        let peers = (0..3).map(get_dummy_socket_address).collect::<Vec<_>>();
+        let far_ahead_tip: Digest = random();
+        for peer in &peers[..2] {
+            detector.record_claim(*peer, far_ahead_tip, BlockHeight::from(20u64), start);
+        }
+        detector.record_claim(peers[2], own_tip_digest, own_tip_height, start);
+
+        assert!(detector
+            .evaluate(own_tip_digest, own_tip_height, &canonical_digests, start)
+            .is_none());
+        let alert = detector
+            .evaluate(
+                own_tip_digest,
+                own_tip_height,
+                &canonical_digests,
+                start + CHAIN_SPLIT_MIN_DURATION,
+            )
+            .unwrap();
+        assert_eq!(ChainSplitKind::OwnNodeBehind, alert.kind);
+        assert_eq!(2, alert.diverging_peers.len());
+        assert!(alert.diverging_peers.iter().all(|peer| peer.depth == 10));
+
+        // Peers that disconnect no longer count
+        detector.forget_peer(peers[0]);
+        detector.forget_peer(peers[1]);
+        assert!(detector
+            .evaluate(
+                own_tip_digest,
+                own_tip_height,
+                &canonical_digests,
+                start + CHAIN_SPLIT_MIN_DURATION,
+            )
+            .is_none());
+    }
+
+    #[traced_test]
+    #[test]
+    fn peers_on_different_chains_are_clustered_separately_test() {
+        let mut detector = ChainSplitDetector::default();
+        let start = SystemTime::now();
+        let own_tip_digest: Digest = random();
+        let own_tip_height = BlockHeight::from(10u64);
+        let canonical_digests = HashSet::from([own_tip_digest]);
+
+        // Two peers follow one competing chain, of which one of them has seen
+        // a further block, and two peers follow another one
+        let peers = (0..5).map(get_dummy_socket_address).collect::<Vec<_>>();
+        let chain_a = (5u64..=10)
+            .map(|height| (random::<Digest>(), BlockHeight::from(height)))
+            .collect::<Vec<_>>();
+        let chain_b = (5u64..=10)
+            .map(|height| (random::<Digest>(), BlockHeight::from(height)))
+            .collect::<Vec<_>>();
+        for (digest, height) in chain_a.iter().take(5) {
+            detector.record_claim(peers[0], *digest, *height, start);
+        }
+        for (digest, height) in chain_a.iter() {
+            detector.record_claim(peers[1], *digest, *height, start);
+        }
+        for peer in &peers[2..4] {
+            for (digest, height) in chain_b.iter() {
+                detector.record_claim(*peer, *digest, *height, start);
+            }
+        }
+        detector.record_claim(peers[4], own_tip_digest, own_tip_height, start);
+
+        detector.evaluate(own_tip_digest, own_tip_height, &canonical_digests, start);
+        let alert = detector
+            .evaluate(
+                own_tip_digest,
+                own_tip_height,
+                &canonical_digests,
+                start + CHAIN_SPLIT_MIN_DURATION,
+            )
+            .cloned()
+            .unwrap();
+        assert_eq!(4, alert.diverging_peers.len());
+        assert_eq!(2, alert.clusters.len());
+
+        let mut cluster_a = peers[..2].to_vec();
+        cluster_a.sort();
+        let mut cluster_b = peers[2..4].to_vec();
+        cluster_b.sort();
+        let (first, second) = if cluster_a < cluster_b {
+            (cluster_a, cluster_b)
+        } else {
+            (cluster_b, cluster_a)
+        };
+        assert_eq!(first, alert.clusters[0].peer_addresses);
+        assert_eq!(second, alert.clusters[1].peer_addresses);
+
+        let tip_of_chain_a = alert
+            .clusters
+            .iter()
+            .find(|cluster| cluster.peer_addresses.contains(&peers[0]))
+            .unwrap()
+            .tip;
+        assert_eq!(chain_a[5].0, tip_of_chain_a.digest);
+        assert_eq!(chain_a[5].1, tip_of_chain_a.height);
+    }
+}
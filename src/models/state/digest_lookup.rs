@@ -0,0 +1,105 @@
+//! Lookup of the objects known to this node by a prefix of the hex encoding
+//! of their digest, such that digests shortened in logs can be resolved.
+
+use crate::prelude::twenty_first;
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use twenty_first::math::digest::Digest;
+
+/// Number of leading hex digits by which stored blocks are indexed
+pub const INDEXED_HEX_DIGITS: usize = 3;
+
+/// The kind of object a digest belongs to.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DigestKind {
+    Block,
+    MempoolTransaction,
+    WalletTransaction,
+    WalletUtxo,
+}
+
+/// A known object whose digest matches a looked up prefix.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct ResolvedDigest {
+    pub digest: Digest,
+    pub kind: DigestKind,
+}
+
+/// A non-empty prefix of the hex encoding of digests, in lower case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HexPrefix(String);
+
+impl HexPrefix {
+    /// Parse a hex prefix, ignoring case. Returns `None` if the prefix is
+    /// empty, which would match all digests, or not hex.
+    pub fn parse(prefix: &str) -> Option<Self> {
+        let prefix = prefix.trim().to_lowercase();
+        if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        Some(Self(prefix))
+    }
+
+    pub fn matches(&self, digest: &Digest) -> bool {
+        digest.to_hex().starts_with(&self.0)
+    }
+
+    /// The index keys of all digests that may match this prefix. Prefixes
+    /// shorter than [INDEXED_HEX_DIGITS] span several keys.
+    pub fn index_keys(&self) -> Range<u16> {
+        let indexed_digits = &self.0[..self.0.len().min(INDEXED_HEX_DIGITS)];
+        let value = u16::from_str_radix(indexed_digits, 16).expect("Prefix must be hex");
+        let span = 16u16.pow((INDEXED_HEX_DIGITS - indexed_digits.len()) as u32);
+
+        value * span..(value + 1) * span
+    }
+
+    /// The index key of a digest, the value of its leading
+    /// [INDEXED_HEX_DIGITS] hex digits
+    pub fn index_key(digest: &Digest) -> u16 {
+        u16::from_str_radix(&digest.to_hex()[..INDEXED_HEX_DIGITS], 16)
+            .expect("Hex encoding of digest must be hex")
+    }
+}
+
+#[cfg(test)]
+mod digest_lookup_tests {
+    use super::*;
+
+    use rand::random;
+
+    #[test]
+    fn hex_prefix_test() {
+        assert!(HexPrefix::parse("").is_none());
+        assert!(HexPrefix::parse("  ").is_none());
+        assert!(HexPrefix::parse("0xab").is_none());
+        assert!(HexPrefix::parse("ghij").is_none());
+
+        let digest: Digest = random();
+        let hex = digest.to_hex();
+        for length in 1..=hex.len() {
+            let prefix = HexPrefix::parse(&hex[..length].to_uppercase()).unwrap();
+            assert!(prefix.matches(&digest));
+        }
+
+        let other_digest: Digest = random();
+        if other_digest != digest {
+            assert!(!HexPrefix::parse(&hex).unwrap().matches(&other_digest));
+        }
+    }
+
+    #[test]
+    fn index_keys_of_prefix_cover_matching_digests_test() {
+        let digest: Digest = random();
+        let hex = digest.to_hex();
+        let index_key = HexPrefix::index_key(&digest);
+        for length in 1..=hex.len() {
+            let index_keys = HexPrefix::parse(&hex[..length]).unwrap().index_keys();
+            assert!(index_keys.contains(&index_key));
+            let expected_key_count = 16usize.pow(INDEXED_HEX_DIGITS.saturating_sub(length) as u32);
+            assert_eq!(expected_key_count, index_keys.len());
+        }
+    }
+}
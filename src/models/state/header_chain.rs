@@ -0,0 +1,356 @@
+use crate::prelude::twenty_first;
+
+use std::collections::HashMap;
+use thiserror::Error;
+use twenty_first::math::digest::Digest;
+
+use crate::config_models::network::Network;
+use crate::models::blockchain::block::block_header::BlockHeader;
+use crate::models::blockchain::block::block_height::BlockHeight;
+use crate::models::blockchain::block::block_kernel::BlockKernel;
+use crate::models::blockchain::block::Block;
+use crate::models::consensus::mast_hash::MastHash;
+use crate::models::consensus::timestamp::Timestamp;
+
+/// Returned when a header does not validly extend the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum HeaderChainError {
+    #[error("Header at height {0} does not build on the preceding header")]
+    NotConnected(BlockHeight),
+
+    #[error("Header at height {0} is not one above its parent")]
+    InvalidHeight(BlockHeight),
+
+    #[error("Header at height {0} is timestamped before its parent or too far in the future")]
+    InvalidTimestamp(BlockHeight),
+
+    #[error("Header at height {0} has an incorrect difficulty")]
+    InvalidDifficulty(BlockHeight),
+
+    #[error("Header at height {0} has an incorrect proof-of-work family")]
+    InvalidProofOfWorkFamily(BlockHeight),
+
+    #[error("Header at height {0} has insufficient proof-of-work")]
+    InsufficientProofOfWork(BlockHeight),
+}
+
+impl HeaderChainError {
+    /// The height of the offending header
+    pub fn height(&self) -> BlockHeight {
+        match *self {
+            HeaderChainError::NotConnected(height)
+            | HeaderChainError::InvalidHeight(height)
+            | HeaderChainError::InvalidTimestamp(height)
+            | HeaderChainError::InvalidDifficulty(height)
+            | HeaderChainError::InvalidProofOfWorkFamily(height)
+            | HeaderChainError::InsufficientProofOfWork(height) => height,
+        }
+    }
+}
+
+/// Compute the digest of a block from its header and the MAST hash of its
+/// body, which is all that is needed to link and validate headers.
+pub fn block_digest(header: &BlockHeader, body_digest: Digest) -> Digest {
+    BlockKernel::mast_hash_from_digests(header.mast_hash(), body_digest)
+}
+
+/// Check that the header of the block with digest `digest` validly extends
+/// the header of the block with digest `parent_digest`. These are the checks
+/// of [Block::is_valid] that do not need the block bodies, along with the
+/// proof-of-work.
+pub fn validate_child_header(
+    parent_digest: Digest,
+    parent_header: &BlockHeader,
+    digest: Digest,
+    header: &BlockHeader,
+    now: Timestamp,
+    network: Network,
+) -> Result<(), HeaderChainError> {
+    let height = header.height;
+    if header.prev_block_digest != parent_digest {
+        return Err(HeaderChainError::NotConnected(height));
+    }
+    if parent_header.height.next() != height {
+        return Err(HeaderChainError::InvalidHeight(height));
+    }
+    if parent_header.timestamp > header.timestamp || header.timestamp >= now + Timestamp::hours(2) {
+        return Err(HeaderChainError::InvalidTimestamp(height));
+    }
+    let difficulty = Block::difficulty_control_from_header(
+        parent_header,
+        header.timestamp,
+        network.target_block_interval(),
+        network.max_difficulty_adjustment_factor(),
+    );
+    if header.difficulty != difficulty {
+        return Err(HeaderChainError::InvalidDifficulty(height));
+    }
+    if header.proof_of_work_family != parent_header.proof_of_work_family + parent_header.difficulty
+    {
+        return Err(HeaderChainError::InvalidProofOfWorkFamily(height));
+    }
+    if digest > Block::difficulty_to_digest_threshold(parent_header.difficulty) {
+        return Err(HeaderChainError::InsufficientProofOfWork(height));
+    }
+
+    Ok(())
+}
+
+/// The validated headers of blocks that this node is about to download while
+/// syncing, possibly spanning several branches.
+///
+/// Headers are validated against their parents as they arrive. Once the
+/// headers have caught up with the peers, the branch with the most
+/// proof-of-work is the only one whose bodies are downloaded. Headers are
+/// removed once their block is stored.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderChain {
+    // Validated headers, by the digest of their block
+    headers: HashMap<Digest, BlockHeader>,
+}
+
+impl HeaderChain {
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    pub fn contains(&self, digest: Digest) -> bool {
+        self.headers.contains_key(&digest)
+    }
+
+    pub fn get(&self, digest: Digest) -> Option<&BlockHeader> {
+        self.headers.get(&digest)
+    }
+
+    /// Forget the header of a block, e.g. once the block is stored.
+    pub fn remove(&mut self, digest: Digest) {
+        self.headers.remove(&digest);
+    }
+
+    pub fn clear(&mut self) {
+        self.headers.clear();
+    }
+
+    /// Validate and add consecutive headers, given with the MAST hashes of
+    /// their bodies. The first header must be a child of the block with
+    /// `parent_digest` and `parent_header`. Headers that are already known are
+    /// validated again, but not counted. Returns the number of added headers,
+    /// or the first error, in which case the headers preceding the invalid
+    /// one are kept.
+    pub fn extend(
+        &mut self,
+        parent_digest: Digest,
+        parent_header: &BlockHeader,
+        headers: Vec<(BlockHeader, Digest)>,
+        now: Timestamp,
+        network: Network,
+    ) -> Result<usize, HeaderChainError> {
+        let mut added_count = 0;
+        let mut parent = (parent_digest, parent_header.clone());
+        for (header, body_digest) in headers {
+            let digest = block_digest(&header, body_digest);
+            validate_child_header(parent.0, &parent.1, digest, &header, now, network)?;
+            if self.headers.insert(digest, header.clone()).is_none() {
+                added_count += 1;
+            }
+            parent = (digest, header);
+        }
+
+        Ok(added_count)
+    }
+
+    /// Return the digest and header of the known block with the most
+    /// proof-of-work.
+    pub fn best_tip(&self) -> Option<(Digest, &BlockHeader)> {
+        self.headers
+            .iter()
+            .max_by_key(|(_digest, header)| header.proof_of_work_family)
+            .map(|(digest, header)| (*digest, header))
+    }
+
+    /// Return the digests of the blocks on the branch leading to the best tip,
+    /// oldest first. The parent of the first block is not in the header
+    /// chain, which means it is stored already.
+    pub fn best_chain(&self) -> Vec<Digest> {
+        let Some((mut digest, _)) = self.best_tip() else {
+            return vec![];
+        };
+
+        let mut chain = vec![];
+        while let Some(header) = self.headers.get(&digest) {
+            chain.push(digest);
+            digest = header.prev_block_digest;
+        }
+        chain.reverse();
+
+        chain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::random;
+
+    use crate::models::state::wallet::WalletSecret;
+    use crate::tests::shared::make_mock_block_with_valid_pow;
+
+    fn header_and_body_digest(block: &Block) -> (BlockHeader, Digest) {
+        (block.kernel.header.clone(), block.kernel.body.mast_hash())
+    }
+
+    fn make_branch(network: Network, parent: &Block, length: usize) -> Vec<Block> {
+        let address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let mut branch: Vec<Block> = vec![];
+        for _ in 0..length {
+            let previous_block = branch.last().unwrap_or(parent);
+            let (block, _, _) =
+                make_mock_block_with_valid_pow(network, previous_block, None, address, random());
+            branch.push(block);
+        }
+
+        branch
+    }
+
+    #[test]
+    fn block_digest_agrees_with_block_hash_test() {
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let block = make_branch(network, &genesis_block, 1).pop().unwrap();
+        let (header, body_digest) = header_and_body_digest(&block);
+        assert_eq!(block.hash(), block_digest(&header, body_digest));
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected_test() {
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let branch = make_branch(network, &genesis_block, 2);
+        let now = branch[1].kernel.header.timestamp;
+        let mut header_chain = HeaderChain::default();
+
+        // Headers must form a chain
+        assert_eq!(
+            Err(HeaderChainError::NotConnected(
+                branch[1].kernel.header.height
+            )),
+            header_chain.extend(
+                genesis_block.hash(),
+                &genesis_block.kernel.header,
+                vec![header_and_body_digest(&branch[1])],
+                now,
+                network
+            )
+        );
+
+        let mut bad_family = header_and_body_digest(&branch[0]);
+        bad_family.0.proof_of_work_family =
+            bad_family.0.proof_of_work_family + bad_family.0.difficulty;
+        assert!(header_chain
+            .extend(
+                genesis_block.hash(),
+                &genesis_block.kernel.header,
+                vec![bad_family],
+                now,
+                network
+            )
+            .is_err());
+
+        let mut bad_difficulty = header_and_body_digest(&branch[0]);
+        bad_difficulty.0.difficulty = bad_difficulty.0.difficulty + bad_difficulty.0.difficulty;
+        assert!(header_chain
+            .extend(
+                genesis_block.hash(),
+                &genesis_block.kernel.header,
+                vec![bad_difficulty],
+                now,
+                network
+            )
+            .is_err());
+
+        // Headers of the future are rejected
+        assert!(header_chain
+            .extend(
+                genesis_block.hash(),
+                &genesis_block.kernel.header,
+                vec![header_and_body_digest(&branch[0])],
+                genesis_block.kernel.header.timestamp - Timestamp::hours(2),
+                network
+            )
+            .is_err());
+        assert!(header_chain.is_empty());
+
+        // Invalid headers do not invalidate the valid headers before them
+        let mut bad_tail = header_and_body_digest(&branch[1]);
+        bad_tail.0.height = bad_tail.0.height.next();
+        assert!(header_chain
+            .extend(
+                genesis_block.hash(),
+                &genesis_block.kernel.header,
+                vec![header_and_body_digest(&branch[0]), bad_tail],
+                now,
+                network
+            )
+            .is_err());
+        assert_eq!(1, header_chain.len());
+        assert!(header_chain.contains(branch[0].hash()));
+    }
+
+    #[test]
+    fn only_the_winning_branch_is_selected_for_download_test() {
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let trunk = make_branch(network, &genesis_block, 2);
+        let losing_branch = make_branch(network, &trunk[1], 2);
+        let winning_branch = make_branch(network, &trunk[1], 3);
+        let now = winning_branch[2].kernel.header.timestamp;
+
+        // The trunk is stored already, so the headers of both branches build
+        // on the trunk's last block.
+        let mut header_chain = HeaderChain::default();
+        for branch in [&losing_branch, &winning_branch] {
+            assert_eq!(
+                Ok(branch.len()),
+                header_chain.extend(
+                    trunk[1].hash(),
+                    &trunk[1].kernel.header,
+                    branch.iter().map(header_and_body_digest).collect(),
+                    now,
+                    network
+                )
+            );
+        }
+        assert_eq!(5, header_chain.len());
+
+        // Known headers are not added twice
+        assert_eq!(
+            Ok(0),
+            header_chain.extend(
+                trunk[1].hash(),
+                &trunk[1].kernel.header,
+                losing_branch.iter().map(header_and_body_digest).collect(),
+                now,
+                network
+            )
+        );
+
+        let (best_digest, _) = header_chain.best_tip().unwrap();
+        assert_eq!(winning_branch[2].hash(), best_digest);
+        let winning_digests = winning_branch.iter().map(Block::hash).collect::<Vec<_>>();
+        assert_eq!(winning_digests, header_chain.best_chain());
+
+        // Storing blocks of the winning branch leaves the rest to download
+        header_chain.remove(winning_branch[0].hash());
+        assert_eq!(winning_digests[1..].to_vec(), header_chain.best_chain());
+        assert!(losing_branch
+            .iter()
+            .all(|block| !header_chain.best_chain().contains(&block.hash())));
+    }
+}
@@ -14,17 +14,26 @@ use crate::{
         consensus::{timestamp::Timestamp, WitnessType},
     },
     prelude::twenty_first,
-    util_types::mutator_set::mutator_set_accumulator::MutatorSetAccumulator,
+    util_types::mutator_set::{
+        mutator_set_accumulator::MutatorSetAccumulator, removal_record::RemovalRecord,
+    },
 };
 
+use anyhow::{Context, Result};
 use bytesize::ByteSize;
 use get_size::GetSize;
-use num_traits::Zero;
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
 use priority_queue::{double_priority_queue::iterators::IntoSortedIter, DoublePriorityQueue};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{hash_map::RandomState, HashMap, HashSet},
     iter::Rev,
+    path::Path,
 };
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
 use twenty_first::math::digest::Digest;
 use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 
@@ -32,6 +41,8 @@ use crate::models::blockchain::block::Block;
 use crate::models::blockchain::shared::Hash;
 use crate::models::blockchain::transaction::Transaction;
 
+use super::orphan_pool::OrphanPool;
+
 /// `FeeDensity` is a measure of 'Fee/Bytes' or 'reward per storage unit' for a
 /// transactions.  Different strategies are possible for selecting transactions
 /// to mine, but a simple one is to pick transactions in descending order of
@@ -51,26 +62,142 @@ use crate::models::blockchain::transaction::Transaction;
 /// TransactionC }.
 use num_rational::BigRational as FeeDensity;
 
-// 72 hours in secs
+// 72 hours in secs, the default time to live of mempool transactions
 pub const MEMPOOL_TX_THRESHOLD_AGE_IN_SECS: u64 = 72 * 60 * 60;
 // 5 minutes in secs
 pub const MEMPOOL_IGNORE_TRANSACTIONS_THIS_MANY_SECS_AHEAD: u64 = 5 * 60;
 
 pub const TRANSACTION_NOTIFICATION_AGE_LIMIT_IN_SECS: u64 = 60 * 60 * 24;
 
+/// Number of mempool events buffered for subscribers that lag behind.
+const MEMPOOL_EVENT_CHANNEL_CAPACITY: usize = 100;
+
+/// Name of the file in the data directory holding the mempool's transactions
+/// across restarts.
+pub const MEMPOOL_FILE_NAME: &str = "mempool.dat";
+
 type LookupItem<'a> = (Digest, &'a Transaction);
 
-#[derive(Debug, Clone, PartialEq, Eq, GetSize)]
+/// Returned when a transaction is not inserted into the mempool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum MempoolRejection {
+    #[error("Transaction has a negative fee")]
+    NegativeFee,
+
+    #[error("Transaction conflicts with mempool transaction {0}")]
+    Conflict(Digest),
+}
+
+/// Notifications about changes to the mempool that are not caused by the
+/// subscriber itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolEvent {
+    /// The transaction was removed to keep the mempool within its maximum
+    /// size, because it had the lowest fee density.
+    Evicted {
+        transaction_id: Digest,
+        transaction: Box<Transaction>,
+    },
+
+    /// The transaction was removed because its timestamp is older than the
+    /// mempool's time to live. Its inputs are no longer claimed by any
+    /// transaction in the mempool.
+    Expired {
+        transaction_id: Digest,
+        transaction: Box<Transaction>,
+    },
+
+    /// The orphan transaction entered the mempool, because the block it was
+    /// built against arrived. It has not been announced to peers yet.
+    Promoted { transaction_id: Digest },
+}
+
+/// The difference between the transactions in two mempools, identified by
+/// their transaction IDs. See [Mempool::diff].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MempoolDiff {
+    /// Transactions in the other mempool that are missing in this one
+    pub missing_locally: HashSet<Digest>,
+
+    /// Transactions in this mempool that are missing in the other one
+    pub missing_remotely: HashSet<Digest>,
+}
+
+impl MempoolDiff {
+    /// Return true if both mempools hold the same transactions
+    pub fn is_empty(&self) -> bool {
+        self.missing_locally.is_empty() && self.missing_remotely.is_empty()
+    }
+}
+
+/// Summary of a transaction in the mempool. See [Mempool::list].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolTransactionInfo {
+    pub transaction_id: Digest,
+    pub fee: NeptuneCoins,
+
+    /// The [serialized size](Transaction::serialized_size) in bytes
+    pub size: usize,
+    pub timestamp: Timestamp,
+}
+
+/// Aggregate statistics about the transactions in the mempool. See
+/// [Mempool::stats].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MempoolStats {
+    pub transaction_count: usize,
+
+    /// Sum of the serialized sizes of the transactions, in bytes
+    pub total_size: usize,
+    pub total_fees: NeptuneCoins,
+
+    /// Fee densities in nau per byte, or `None` if the mempool is empty
+    pub min_fee_density: Option<f64>,
+    pub median_fee_density: Option<f64>,
+    pub max_fee_density: Option<f64>,
+}
+
+#[derive(Debug, Clone, GetSize)]
 pub struct Mempool {
     max_total_size: usize,
 
+    // Age, measured from the kernel timestamp, after which transactions expire
+    transaction_ttl: Timestamp,
+
+    // Amount by which the fee of a transaction must exceed the combined fee
+    // of the transactions it conflicts with in order to replace them, on top
+    // of having a higher fee density. Zero disables this requirement.
+    replace_by_fee_margin: NeptuneCoins,
+
     // Maintain for constant lookup
     tx_dictionary: HashMap<Digest, Transaction>,
 
     // Maintain for fast min and max
     #[get_size(ignore)] // This is relatively small compared to `LookupTable`
     queue: DoublePriorityQueue<Digest, FeeDensity>,
+
+    // Notifies subscribers of evictions
+    #[get_size(ignore)]
+    event_tx: broadcast::Sender<MempoolEvent>,
+
+    // Transactions waiting for the block they were built against. Bounded
+    // separately from the mempool's maximum size.
+    #[get_size(ignore)]
+    orphans: OrphanPool,
+}
+
+impl PartialEq for Mempool {
+    fn eq(&self, other: &Self) -> bool {
+        // The subscribers of the event channel are not part of the state
+        self.max_total_size == other.max_total_size
+            && self.transaction_ttl == other.transaction_ttl
+            && self.replace_by_fee_margin == other.replace_by_fee_margin
+            && self.tx_dictionary == other.tx_dictionary
+            && self.queue == other.queue
+            && self.orphans == other.orphans
+    }
 }
+impl Eq for Mempool {}
 
 impl Mempool {
     /// instantiate a new `Mempool`
@@ -80,11 +207,155 @@ impl Mempool {
         let max_total_size = max_total_size.0.try_into().unwrap();
         Self {
             max_total_size,
+            transaction_ttl: Timestamp::seconds(MEMPOOL_TX_THRESHOLD_AGE_IN_SECS),
+            replace_by_fee_margin: NeptuneCoins::zero(),
             tx_dictionary: table,
             queue,
+            event_tx: broadcast::channel(MEMPOOL_EVENT_CHANNEL_CAPACITY).0,
+            orphans: OrphanPool::default(),
         }
     }
 
+    /// Set the age, measured from the kernel timestamp, after which
+    /// transactions are removed by [Self::prune_expired]. Defaults to
+    /// [MEMPOOL_TX_THRESHOLD_AGE_IN_SECS].
+    pub fn with_transaction_ttl(mut self, transaction_ttl: Timestamp) -> Self {
+        self.transaction_ttl = transaction_ttl;
+        self
+    }
+
+    /// The age, measured from the kernel timestamp, after which transactions
+    /// expire.
+    pub fn transaction_ttl(&self) -> Timestamp {
+        self.transaction_ttl
+    }
+
+    /// Set the amount by which the fee of a transaction must exceed the
+    /// combined fee of the mempool transactions it conflicts with in order to
+    /// replace them, see [Self::insert]. Defaults to zero, such that a higher
+    /// fee density suffices.
+    pub fn with_replace_by_fee_margin(mut self, replace_by_fee_margin: NeptuneCoins) -> Self {
+        self.replace_by_fee_margin = replace_by_fee_margin;
+        self
+    }
+
+    /// Instantiate a `Mempool` with the transactions stored in the given file
+    /// by [Self::write_to_file]. Transactions are re-validated against the tip
+    /// whose mutator set accumulator is given; those that fail or that have
+    /// expired are logged and dropped. Returns an empty mempool if the file
+    /// does not exist.
+    pub async fn read_from_file(
+        max_total_size: ByteSize,
+        transaction_ttl: Timestamp,
+        path: &Path,
+        tip_mutator_set_accumulator: &MutatorSetAccumulator,
+    ) -> Result<Self> {
+        let mut mempool = Self::new(max_total_size).with_transaction_ttl(transaction_ttl);
+        if !tokio::fs::try_exists(path).await? {
+            return Ok(mempool);
+        }
+
+        let bytes = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read mempool file {}", path.display()))?;
+        let transactions: Vec<Transaction> = bincode::deserialize(&bytes)
+            .with_context(|| format!("Failed to decode mempool file {}", path.display()))?;
+
+        let stored_count = transactions.len();
+        for transaction in transactions {
+            let transaction_id = Hash::hash(&transaction);
+            if !Self::is_insertable_at_tip(&transaction, tip_mutator_set_accumulator) {
+                warn!("Dropping stored mempool transaction {transaction_id} that is invalid against the tip");
+                continue;
+            }
+
+            if let Err(rejection) = mempool.insert(&transaction) {
+                warn!("Dropping stored mempool transaction {transaction_id}: {rejection}");
+            }
+        }
+        mempool.prune_expired(Timestamp::now());
+        info!(
+            "Restored {} of {stored_count} stored mempool transactions",
+            mempool.len()
+        );
+
+        Ok(mempool)
+    }
+
+    /// Store the transactions in the mempool in the given file, such that they
+    /// can be restored with [Self::read_from_file] after a restart. The file is
+    /// replaced atomically.
+    pub async fn write_to_file(&self, path: &Path) -> Result<()> {
+        Self::write_encoded_to_file(self.encode_for_file()?, path).await
+    }
+
+    /// Encode the transactions in the mempool, most valuable first, for
+    /// storing them with [Self::write_encoded_to_file]. Allows writing the
+    /// file without holding on to the mempool.
+    pub fn encode_for_file(&self) -> Result<Vec<u8>> {
+        let transactions = self
+            .get_sorted_iter()
+            .filter_map(|(transaction_id, _fee_density)| self.get(transaction_id))
+            .collect::<Vec<_>>();
+
+        Ok(bincode::serialize(&transactions)?)
+    }
+
+    /// Store transactions encoded by [Self::encode_for_file] in the given
+    /// file. The file is replaced atomically.
+    pub async fn write_encoded_to_file(encoded_transactions: Vec<u8>, path: &Path) -> Result<()> {
+        let temporary_path = path.with_extension("tmp");
+        tokio::fs::write(&temporary_path, encoded_transactions)
+            .await
+            .with_context(|| {
+                format!("Failed to write mempool file {}", temporary_path.display())
+            })?;
+        tokio::fs::rename(&temporary_path, path)
+            .await
+            .with_context(|| format!("Failed to replace mempool file {}", path.display()))?;
+
+        Ok(())
+    }
+
+    /// Determine whether a transaction that was not validated by this node
+    /// before can be inserted into the mempool at the tip whose mutator set
+    /// accumulator is given.
+    fn is_insertable_at_tip(
+        transaction: &Transaction,
+        tip_mutator_set_accumulator: &MutatorSetAccumulator,
+    ) -> bool {
+        let has_insertable_witness = matches!(
+            transaction.witness.vast.witness_type,
+            WitnessType::Faith | WitnessType::Proof(_)
+        );
+        has_insertable_witness
+            && !transaction.kernel.fee.is_negative()
+            && transaction.is_valid()
+            && transaction.kernel.mutator_set_hash == tip_mutator_set_accumulator.hash()
+            && transaction
+                .kernel
+                .inputs
+                .iter()
+                .all(|input| tip_mutator_set_accumulator.can_remove(input))
+    }
+
+    /// Subscribe to notifications about transactions that are removed from
+    /// the mempool for other reasons than being mined or replaced, such that
+    /// e.g. the wallet can react to its transactions being dropped, and about
+    /// orphans entering the mempool.
+    pub fn subscribe_to_events(&self) -> broadcast::Receiver<MempoolEvent> {
+        self.event_tx.subscribe()
+    }
+
+    /// Return the memory used by the transactions in the mempool, which is
+    /// kept below the `max_total_size` given at construction by evicting the
+    /// transactions with the lowest fee density.
+    ///
+    /// Computes in O(N)
+    pub fn size_in_bytes(&self) -> usize {
+        self.get_size()
+    }
+
     /// check if transaction exists in mempool
     ///
     /// Computes in O(1) from HashMap
@@ -99,12 +370,91 @@ impl Mempool {
         self.tx_dictionary.get(&transaction_id)
     }
 
-    /// Returns `Some(txid, transaction)` iff a transcation conflicts with a block that's already in
-    /// the mempool. Returns `None` otherwise.
-    fn transaction_conflicts_with(
-        &self,
-        transaction: &Transaction,
-    ) -> Option<(Digest, Transaction)> {
+    /// Return the IDs of all transactions in the mempool
+    ///
+    /// Computes in O(N)
+    pub fn id_set(&self) -> HashSet<Digest> {
+        self.tx_dictionary.keys().copied().collect()
+    }
+
+    /// Compare the transactions in the mempool to those in another node's
+    /// mempool, given by their IDs, e.g. to find gaps in the propagation of
+    /// transactions.
+    ///
+    /// Computes in O(N + M)
+    pub fn diff(&self, other_ids: &HashSet<Digest>) -> MempoolDiff {
+        let own_ids = self.id_set();
+        MempoolDiff {
+            missing_locally: other_ids.difference(&own_ids).copied().collect(),
+            missing_remotely: own_ids.difference(other_ids).copied().collect(),
+        }
+    }
+
+    /// Return summaries of the transactions with positions in the range
+    /// `[offset, offset + limit)`, in descending order of fee density. Only
+    /// the priority queue is copied, not the transactions.
+    ///
+    /// Computes in O(N lg N)
+    pub fn list(&self, offset: usize, limit: usize) -> Vec<MempoolTransactionInfo> {
+        self.get_sorted_iter()
+            .skip(offset)
+            .take(limit)
+            .filter_map(|(transaction_id, _fee_density)| {
+                self.get(transaction_id)
+                    .map(|transaction| MempoolTransactionInfo {
+                        transaction_id,
+                        fee: transaction.kernel.fee,
+                        size: transaction.serialized_size(),
+                        timestamp: transaction.kernel.timestamp,
+                    })
+            })
+            .collect()
+    }
+
+    /// Return the number of transactions, their total size and fees, and the
+    /// spread of their fee densities.
+    ///
+    /// Computes in O(N lg N)
+    pub fn stats(&self) -> MempoolStats {
+        let (total_size, total_fees) = self.tx_dictionary.values().fold(
+            (0, NeptuneCoins::zero()),
+            |(size, fees), transaction| {
+                (
+                    size + transaction.serialized_size(),
+                    fees + transaction.kernel.fee,
+                )
+            },
+        );
+
+        // In descending order
+        let fee_densities = self
+            .get_sorted_iter()
+            .map(|(_transaction_id, fee_density)| fee_density)
+            .collect::<Vec<_>>();
+        let count = fee_densities.len();
+        let median_fee_density = match count {
+            0 => None,
+            _ if count % 2 == 1 => Some(fee_densities[count / 2].clone()),
+            _ => Some(
+                (fee_densities[count / 2 - 1].clone() + fee_densities[count / 2].clone())
+                    / FeeDensity::from_integer(BigInt::from(2)),
+            ),
+        };
+
+        MempoolStats {
+            transaction_count: self.len(),
+            total_size,
+            total_fees,
+            min_fee_density: fee_densities.last().and_then(|fd| fd.to_f64()),
+            median_fee_density: median_fee_density.and_then(|fd| fd.to_f64()),
+            max_fee_density: fee_densities.first().and_then(|fd| fd.to_f64()),
+        }
+    }
+
+    /// Return the IDs of the transactions in the mempool that spend any of the
+    /// UTXOs spent by the given transaction, i.e., that have a removal record
+    /// with the same absolute indices. At most one of them can be mined.
+    pub fn find_conflicts(&self, transaction: &Transaction) -> Vec<Digest> {
         // This check could be made a lot more efficient, for example with an invertible Bloom filter
         let tx_sbf_indices: HashSet<_> = transaction
             .kernel
@@ -113,21 +463,28 @@ impl Mempool {
             .map(|x| x.absolute_indices.to_array())
             .collect();
 
-        for (txid, tx) in self.tx_dictionary.iter() {
-            for mempool_tx_input in tx.kernel.inputs.iter() {
-                if tx_sbf_indices.contains(&mempool_tx_input.absolute_indices.to_array()) {
-                    return Some((*txid, tx.to_owned()));
-                }
-            }
-        }
-
-        None
+        self.tx_dictionary
+            .iter()
+            .filter(|(_txid, tx)| {
+                tx.kernel.inputs.iter().any(|mempool_tx_input| {
+                    tx_sbf_indices.contains(&mempool_tx_input.absolute_indices.to_array())
+                })
+            })
+            .map(|(txid, _tx)| *txid)
+            .collect()
     }
 
     /// Insert a transaction into the mempool. It is the caller's responsibility to validate
     /// the transaction. Also, the caller must ensure that the witness type is correct --
     /// this method accepts only fully proven transactions (or, for the time being, faith witnesses).
-    pub fn insert(&mut self, transaction: &Transaction) -> Option<Digest> {
+    ///
+    /// A transaction that conflicts with transactions in the mempool replaces them if its fee
+    /// density is higher than theirs, and, if a replace-by-fee margin is set, its fee exceeds
+    /// their combined fee by more than the margin. Otherwise it is not inserted, and the ID of
+    /// a conflicting transaction is returned in the rejection.
+    ///
+    /// Transactions with a negative fee are rejected, since no block can include them.
+    pub fn insert(&mut self, transaction: &Transaction) -> Result<(), MempoolRejection> {
         match transaction.witness.vast.witness_type {
             WitnessType::RawWitness(_) => panic!("Can only insert fully proven transactions into mempool; not accepting raw witnesses."),
             WitnessType::Decomposition => panic!("Can only insert fully proven transactions into mempool; not accepting decompositions."),
@@ -135,19 +492,32 @@ impl Mempool {
             WitnessType::Faith => {},
             WitnessType::Proof(_) => {},
         }
-        // If transaction to be inserted conflicts with a transaction that's already
-        // in the mempool we preserve only the one with the highest fee density.
-        if let Some((txid, tx)) = self.transaction_conflicts_with(transaction) {
-            if tx.fee_density() < transaction.fee_density() {
-                // If new transaction has a higher fee density than the one previously seen
-                // remove the old one.
+        if transaction.kernel.fee.is_negative() {
+            return Err(MempoolRejection::NegativeFee);
+        }
+        let conflicts = self.find_conflicts(transaction);
+        if let Some(&conflicting_txid) = conflicts.first() {
+            let fee_density = transaction.fee_density();
+            let has_higher_fee_density = conflicts.iter().all(|txid| {
+                self.queue
+                    .get_priority(txid)
+                    .is_some_and(|conflicting_fee_density| *conflicting_fee_density < fee_density)
+            });
+            let conflicting_fee: NeptuneCoins = conflicts
+                .iter()
+                .map(|txid| self.tx_dictionary[txid].kernel.fee)
+                .sum();
+            let pays_margin = self.replace_by_fee_margin.is_zero()
+                || transaction.kernel.fee > conflicting_fee + self.replace_by_fee_margin;
+            if !has_higher_fee_density || !pays_margin {
+                // The replacement does not pay enough. Stop execution here.
+                return Err(MempoolRejection::Conflict(conflicting_txid));
+            }
+
+            for txid in conflicts {
                 self.remove(txid);
-            } else {
-                // If new transaction has a lower fee density than the one previous seen,
-                // ignore it. Stop execution here.
-                return Some(txid);
             }
-        };
+        }
 
         let transaction_id: Digest = Hash::hash(transaction);
 
@@ -165,7 +535,20 @@ impl Mempool {
             self.queue.len(),
             "mempool's table and queue length must agree after shrink"
         );
-        None
+        Ok(())
+    }
+
+    /// Buffer a transaction that was built against a mutator set this node
+    /// does not know yet, such that it can be promoted into the mempool once
+    /// the corresponding block arrives, see [Self::update_with_block].
+    /// Returns false if the transaction was not buffered.
+    pub fn insert_orphan(&mut self, transaction: Transaction, now: Timestamp) -> bool {
+        self.orphans.insert(transaction, now)
+    }
+
+    /// The transactions waiting for the block they were built against.
+    pub fn orphans(&self) -> &OrphanPool {
+        &self.orphans
     }
 
     /// remove a transaction from the `Mempool`
@@ -194,29 +577,25 @@ impl Mempool {
 
     /// Return a vector with copies of the transactions, in descending order by fee
     /// density and using at most `remaining_storage` bytes.
+    ///
+    /// Transactions are picked greedily by fee density, measuring each by its
+    /// [serialized size](Transaction::serialized_size). Selection stops at the
+    /// first transaction that would exceed the remaining capacity.
     pub fn get_transactions_for_block(&self, mut remaining_storage: usize) -> Vec<Transaction> {
         let mut transactions = vec![];
-        let mut _fee_acc = NeptuneCoins::zero();
 
         for (transaction_digest, _fee_density) in self.get_sorted_iter() {
-            // No more transactions can possibly be packed
-            if remaining_storage == 0 {
-                break;
-            }
-
             if let Some(transaction_ptr) = self.get(transaction_digest) {
-                let transaction_copy = transaction_ptr.to_owned();
-                let transaction_size = transaction_copy.get_size();
+                let transaction_size = transaction_ptr.serialized_size();
 
-                // Current transaction is too big
+                // Block is full
                 if transaction_size > remaining_storage {
-                    continue;
+                    break;
                 }
 
                 // Include transaction
                 remaining_storage -= transaction_size;
-                _fee_acc = _fee_acc + transaction_copy.kernel.fee;
-                transactions.push(transaction_copy)
+                transactions.push(transaction_ptr.to_owned())
             }
         }
 
@@ -270,16 +649,41 @@ impl Mempool {
         self.shrink_to_fit()
     }
 
-    /// Prune based on `Transaction.timestamp`
+    /// Remove the transactions whose kernel timestamp is older than the
+    /// mempool's time to live at time `now`, and announce each of them as
+    /// [MempoolEvent::Expired]. A transaction that replaced a conflicting one
+    /// expires according to its own timestamp, not that of the transaction it
+    /// replaced. Returns the IDs of the removed transactions.
+    ///
     /// Computes in O(n)
-    pub fn prune_stale_transactions(&mut self) {
-        let cutoff = Timestamp::now() - Timestamp::seconds(MEMPOOL_TX_THRESHOLD_AGE_IN_SECS);
+    pub fn prune_expired(&mut self, now: Timestamp) -> Vec<Digest> {
+        let cutoff = now - self.transaction_ttl;
+        let expired = self
+            .tx_dictionary
+            .iter()
+            .filter(|(_transaction_id, transaction)| transaction.kernel.timestamp <= cutoff)
+            .map(|(transaction_id, _transaction)| *transaction_id)
+            .collect::<Vec<_>>();
 
-        let keep = |(_transaction_id, transaction): LookupItem| -> bool {
-            cutoff < transaction.kernel.timestamp
-        };
+        for &transaction_id in expired.iter() {
+            let Some(transaction) = self.remove(transaction_id) else {
+                continue;
+            };
+            info!(
+                "Removing expired transaction {transaction_id} from mempool; timestamp: {}",
+                transaction.kernel.timestamp.standard_format()
+            );
+
+            // Sending fails only if there are no subscribers, which is fine
+            let _ = self.event_tx.send(MempoolEvent::Expired {
+                transaction_id,
+                transaction: Box::new(transaction),
+            });
+        }
+        self.shrink_to_fit();
+        self.orphans.prune_expired(now);
 
-        self.retain(keep);
+        expired
     }
 
     /// Remove from the mempool all transactions that become invalid because
@@ -290,50 +694,38 @@ impl Mempool {
         previous_mutator_set_accumulator: MutatorSetAccumulator,
         block: &Block,
     ) {
-        // The general strategy is to check whether the SWBF index set of a given
-        // transaction in the mempool is disjoint (*i.e.*, not contained by) the
-        // SWBF indices coming from the block transaction. If they are not disjoint,
-        // then remove the transaction from the mempool.
-
-        // Compute the union of all index sets generated by the block transaction.
-        let swbf_index_set_union: HashSet<_> = block
-            .kernel
-            .body
-            .transaction
-            .kernel
-            .inputs
-            .iter()
-            .flat_map(|rr| rr.absolute_indices.to_array())
-            .collect();
-
-        // The indices that the block transaction inserts are used to determine
-        // which mempool transactions contain UTXOs that were spent in this block. Any
-        // transaction that contains just *one* input-UTXO that was spent in
-        // this block is invalid
-        let keep = |(_transaction_id, tx): LookupItem| -> bool {
-            let transaction_index_sets: HashSet<_> = tx
-                .kernel
-                .inputs
-                .iter()
-                .map(|rr| rr.absolute_indices.to_array())
-                .collect();
-
-            transaction_index_sets.iter().all(|index_set| {
-                index_set
-                    .iter()
-                    .any(|index| !swbf_index_set_union.contains(index))
-            })
-        };
+        let applied_removal_records = &block.kernel.body.transaction.kernel.inputs;
 
         // Remove the transactions that become invalid with this block
-        self.retain(keep);
+        let removed_indices = Self::removed_indices(applied_removal_records);
+        self.retain(|(_transaction_id, tx)| !Self::spends_any_of(tx, &removed_indices));
 
-        // Update the remaining transactions so their mutator set data is still valid
-        for tx in self.tx_dictionary.values_mut() {
-            *tx = tx
-                .new_with_updated_mutator_set_records(&previous_mutator_set_accumulator, block)
-                .expect("Updating mempool transaction must succeed");
+        // Update the remaining transactions so their mutator set data is still
+        // valid. Transactions that cannot be updated, e.g. because they were
+        // built against another fork, are dropped.
+        let mut victims = vec![];
+        for (transaction_id, tx) in self.tx_dictionary.iter_mut() {
+            match tx.new_with_updated_mutator_set_records(&previous_mutator_set_accumulator, block)
+            {
+                Ok(updated_tx) => *tx = updated_tx,
+                Err(err) => {
+                    warn!("Dropping mempool transaction {transaction_id} that could not be updated: {err}");
+                    victims.push(*transaction_id);
+                }
+            }
         }
+        for transaction_id in victims {
+            self.remove(transaction_id);
+        }
+
+        self.retain_valid_against_tip(
+            &block.kernel.body.mutator_set_accumulator,
+            applied_removal_records,
+        );
+
+        // Now that the mutator set of this block is known, the orphans that
+        // were built against it can be validated.
+        self.promote_orphans(&block.kernel.body.mutator_set_accumulator);
 
         // Maintaining the mutator set data could have increased the size of the
         // transactions in the mempool. So we should shrink it to max size after
@@ -341,12 +733,99 @@ impl Mempool {
         self.shrink_to_max_size();
     }
 
+    /// Move the orphans that were built against the tip whose mutator set
+    /// accumulator is given into the mempool, dropping those that are
+    /// invalid. Returns the IDs of the promoted transactions.
+    pub fn promote_orphans(
+        &mut self,
+        tip_mutator_set_accumulator: &MutatorSetAccumulator,
+    ) -> Vec<Digest> {
+        let mut promoted = vec![];
+        for transaction in self.orphans.take(tip_mutator_set_accumulator.hash()) {
+            let transaction_id = Hash::hash(&transaction);
+            if !Self::is_insertable_at_tip(&transaction, tip_mutator_set_accumulator) {
+                warn!(
+                    "Dropping orphan transaction {transaction_id} that is invalid against the tip"
+                );
+                continue;
+            }
+
+            if let Err(rejection) = self.insert(&transaction) {
+                warn!("Dropping orphan transaction {transaction_id}: {rejection}");
+                continue;
+            }
+
+            info!("Promoted orphan transaction {transaction_id} into mempool");
+            promoted.push(transaction_id);
+
+            // Sending fails only if there are no subscribers, which is fine
+            let _ = self
+                .event_tx
+                .send(MempoolEvent::Promoted { transaction_id });
+        }
+
+        promoted
+    }
+
+    /// Remove from the mempool all transactions that are invalid against the
+    /// tip whose mutator set accumulator is given, i.e., transactions that
+    /// spend an input that was removed by one of the `applied_removal_records`
+    /// and transactions whose mutator set hash does not match the tip. The
+    /// latter must be updated, see [Self::update_with_block], before calling
+    /// this method, or they are lost.
+    ///
+    /// Computes in O(n)
+    pub fn retain_valid_against_tip(
+        &mut self,
+        tip_mutator_set_accumulator: &MutatorSetAccumulator,
+        applied_removal_records: &[RemovalRecord],
+    ) {
+        let tip_mutator_set_hash = tip_mutator_set_accumulator.hash();
+        let removed_indices = Self::removed_indices(applied_removal_records);
+        self.retain(|(_transaction_id, tx)| {
+            tx.kernel.mutator_set_hash == tip_mutator_set_hash
+                && !Self::spends_any_of(tx, &removed_indices)
+        });
+    }
+
+    /// Compute the union of all sliding window Bloom filter index sets of the
+    /// given removal records.
+    fn removed_indices(removal_records: &[RemovalRecord]) -> HashSet<u128> {
+        removal_records
+            .iter()
+            .flat_map(|rr| rr.absolute_indices.to_array())
+            .collect()
+    }
+
+    /// Determine whether one of the transaction's inputs was spent, i.e.,
+    /// whether all of its indices into the sliding window Bloom filter are
+    /// among the removed indices.
+    fn spends_any_of(transaction: &Transaction, removed_indices: &HashSet<u128>) -> bool {
+        transaction.kernel.inputs.iter().any(|input| {
+            input
+                .absolute_indices
+                .to_array()
+                .iter()
+                .all(|index| removed_indices.contains(index))
+        })
+    }
+
     /// Shrink the memory pool to the value of its `max_size` field.
     /// Likely computes in O(n)
     fn shrink_to_max_size(&mut self) {
-        // Repeately remove the least valuable transaction
-        while self.get_size() > self.max_total_size && self.pop_min().is_some() {
-            continue;
+        // Repeately remove the least valuable transaction. Transactions in the
+        // mempool only spend confirmed UTXOs, so no remaining transaction can
+        // depend on an evicted one.
+        while self.get_size() > self.max_total_size {
+            let Some((transaction, _fee_density)) = self.pop_min() else {
+                break;
+            };
+
+            // Sending fails only if there are no subscribers, which is fine
+            let _ = self.event_tx.send(MempoolEvent::Evicted {
+                transaction_id: Hash::hash(&transaction),
+                transaction: Box::new(transaction),
+            });
         }
 
         self.shrink_to_fit()
@@ -388,6 +867,8 @@ impl Mempool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config_models::data_directory::DataDirectory;
+    use crate::models::consensus::mast_hash::MastHash;
     use crate::{
         config_models::network::Network,
         models::{
@@ -403,9 +884,11 @@ mod tests {
             },
         },
         tests::shared::{
-            make_mock_block, make_mock_transaction_with_wallet, mock_genesis_global_state,
-            mock_genesis_wallet_state,
+            make_mock_block, make_mock_transaction, make_mock_transaction_with_wallet,
+            mock_genesis_global_state, mock_genesis_wallet_state, random_addition_record,
+            unit_test_data_directory,
         },
+        util_types::test_shared::mutator_set::random_removal_record,
     };
     use anyhow::Result;
     use itertools::Itertools;
@@ -429,7 +912,7 @@ mod tests {
         );
         let transaction_digest = Hash::hash(&transaction);
         assert!(!mempool.contains(transaction_digest));
-        mempool.insert(&transaction);
+        mempool.insert(&transaction).unwrap();
         assert!(mempool.contains(transaction_digest));
 
         let transaction_get_option = mempool.get(transaction_digest);
@@ -457,7 +940,7 @@ mod tests {
                 &wallet_state,
                 None,
             );
-            mempool.insert(&t);
+            mempool.insert(&t).unwrap();
         }
         mempool
     }
@@ -515,7 +998,7 @@ mod tests {
                 &wallet_state,
                 timestamp,
             );
-            mempool.insert(&t);
+            mempool.insert(&t).unwrap();
         }
 
         for i in 0u32..5 {
@@ -526,13 +1009,275 @@ mod tests {
                 &wallet_state,
                 None,
             );
-            mempool.insert(&t);
+            mempool.insert(&t).unwrap();
         }
         assert_eq!(mempool.len(), 10);
-        mempool.prune_stale_transactions();
+        assert_eq!(5, mempool.prune_expired(Timestamp::now()).len());
         assert_eq!(mempool.len(), 5)
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn diff_reports_transactions_missing_on_each_side_test() {
+        let wallet_state =
+            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), Network::Alpha).await;
+        let transactions = (1u32..=5)
+            .map(|fee| {
+                make_mock_transaction_with_wallet(
+                    vec![],
+                    vec![],
+                    NeptuneCoins::new(fee),
+                    &wallet_state,
+                    None,
+                )
+            })
+            .collect_vec();
+        let ids = transactions.iter().map(Hash::hash).collect_vec();
+
+        // The mempools share transactions 2 and 3
+        let mut own_mempool = Mempool::new(ByteSize::gb(1));
+        for transaction in &transactions[..4] {
+            own_mempool.insert(transaction);
+        }
+        let mut peer_mempool = Mempool::new(ByteSize::gb(1));
+        for transaction in &transactions[2..] {
+            peer_mempool.insert(transaction);
+        }
+        assert_eq!(
+            ids[..4].iter().copied().collect::<HashSet<_>>(),
+            own_mempool.id_set()
+        );
+
+        let diff = own_mempool.diff(&peer_mempool.id_set());
+        assert!(!diff.is_empty());
+        assert_eq!(HashSet::from([ids[4]]), diff.missing_locally);
+        assert_eq!(HashSet::from([ids[0], ids[1]]), diff.missing_remotely);
+
+        // The diff is symmetric
+        let reverse_diff = peer_mempool.diff(&own_mempool.id_set());
+        assert_eq!(diff.missing_locally, reverse_diff.missing_remotely);
+        assert_eq!(diff.missing_remotely, reverse_diff.missing_locally);
+
+        assert!(own_mempool.diff(&own_mempool.id_set()).is_empty());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn get_transactions_for_block_prefers_fee_density_test() {
+        let wallet_state =
+            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), Network::Alpha).await;
+        let large_low_fee_transactions = (0..3)
+            .map(|_| {
+                make_mock_transaction_with_wallet(
+                    vec![],
+                    (0..20).map(|_| random_addition_record()).collect_vec(),
+                    NeptuneCoins::new(2),
+                    &wallet_state,
+                    None,
+                )
+            })
+            .collect_vec();
+        let small_high_fee_transactions = (0..3)
+            .map(|_| {
+                make_mock_transaction_with_wallet(
+                    vec![],
+                    vec![random_addition_record()],
+                    NeptuneCoins::new(10),
+                    &wallet_state,
+                    None,
+                )
+            })
+            .collect_vec();
+
+        // Large transactions pay more in total, but less per byte
+        let large_size = large_low_fee_transactions[0].serialized_size();
+        let small_size = small_high_fee_transactions[0].serialized_size();
+        assert!(small_size < large_size);
+        assert!(
+            small_high_fee_transactions[0].fee_density()
+                > large_low_fee_transactions[0].fee_density()
+        );
+
+        let mut mempool = Mempool::new(ByteSize::gb(1));
+        for transaction in large_low_fee_transactions
+            .iter()
+            .chain(small_high_fee_transactions.iter())
+        {
+            mempool.insert(transaction);
+        }
+
+        // Room for all small transactions, but not for any large one on top
+        let capacity: usize = small_high_fee_transactions
+            .iter()
+            .map(|tx| tx.serialized_size())
+            .sum::<usize>()
+            + large_size
+            - 1;
+        let selected = mempool.get_transactions_for_block(capacity);
+        let selected_ids = selected.iter().map(Hash::hash).collect::<HashSet<_>>();
+        let expected_ids = small_high_fee_transactions
+            .iter()
+            .map(Hash::hash)
+            .collect::<HashSet<_>>();
+        assert_eq!(expected_ids, selected_ids);
+        assert!(
+            selected
+                .iter()
+                .map(|tx| tx.serialized_size())
+                .sum::<usize>()
+                <= capacity
+        );
+
+        // With unlimited room, everything is selected in order of fee density
+        let all = mempool.get_transactions_for_block(usize::MAX);
+        assert_eq!(6, all.len());
+        assert!(all
+            .iter()
+            .tuple_windows()
+            .all(|(a, b)| a.fee_density() >= b.fee_density()));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn list_and_stats_test() {
+        let mut mempool = Mempool::new(ByteSize::gb(1));
+        assert!(mempool.list(0, 10).is_empty());
+        let empty_stats = mempool.stats();
+        assert_eq!(0, empty_stats.transaction_count);
+        assert!(empty_stats.median_fee_density.is_none());
+
+        let wallet_state =
+            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), Network::Alpha).await;
+        let transactions = (1u32..=4)
+            .map(|fee| {
+                make_mock_transaction_with_wallet(
+                    vec![],
+                    vec![],
+                    NeptuneCoins::new(fee),
+                    &wallet_state,
+                    None,
+                )
+            })
+            .collect_vec();
+        for transaction in &transactions {
+            mempool.insert(transaction);
+        }
+
+        // Pages are ordered by fee density, highest first
+        let first_page = mempool.list(0, 3);
+        assert_eq!(
+            vec![
+                Hash::hash(&transactions[3]),
+                Hash::hash(&transactions[2]),
+                Hash::hash(&transactions[1])
+            ],
+            first_page
+                .iter()
+                .map(|info| info.transaction_id)
+                .collect_vec()
+        );
+        assert_eq!(transactions[3].kernel.fee, first_page[0].fee);
+        assert_eq!(transactions[3].serialized_size(), first_page[0].size);
+        assert_eq!(transactions[3].kernel.timestamp, first_page[0].timestamp);
+        let second_page = mempool.list(3, 3);
+        assert_eq!(1, second_page.len());
+        assert_eq!(Hash::hash(&transactions[0]), second_page[0].transaction_id);
+        assert!(mempool.list(4, 3).is_empty());
+
+        let stats = mempool.stats();
+        assert_eq!(4, stats.transaction_count);
+        assert_eq!(
+            transactions
+                .iter()
+                .map(|tx| tx.serialized_size())
+                .sum::<usize>(),
+            stats.total_size
+        );
+        assert_eq!(NeptuneCoins::new(10), stats.total_fees);
+        assert_eq!(
+            transactions[0].fee_density().to_f64(),
+            stats.min_fee_density
+        );
+        assert_eq!(
+            transactions[3].fee_density().to_f64(),
+            stats.max_fee_density
+        );
+
+        // The median of an even number of transactions is the average of the
+        // middle two
+        let median = stats.median_fee_density.unwrap();
+        assert!(transactions[1].fee_density().to_f64().unwrap() < median);
+        assert!(median < transactions[2].fee_density().to_f64().unwrap());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn replacement_resets_expiration_clock_test() -> Result<()> {
+        let network = Network::RegTest;
+        let preminer_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let in_seven_months =
+            Block::genesis_block(network).kernel.header.timestamp + Timestamp::months(7);
+        let mut preminer_state = preminer_state_lock.lock_guard_mut().await;
+        let premine_address = preminer_state
+            .wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0)
+            .to_address();
+        let receiver_data = || UtxoReceiverData {
+            utxo: Utxo {
+                coins: NeptuneCoins::new(1).to_native_coins(),
+                lock_script_hash: premine_address.lock_script().hash(),
+            },
+            receiver_privacy_digest: premine_address.privacy_digest,
+            sender_randomness: random(),
+            public_announcement: PublicAnnouncement::default(),
+        };
+
+        let mut mempool = Mempool::new(ByteSize::gb(1)).with_transaction_ttl(Timestamp::days(1));
+        let mut events = mempool.subscribe_to_events();
+        let original = preminer_state
+            .create_transaction(vec![receiver_data()], NeptuneCoins::new(1), in_seven_months)
+            .await?;
+        mempool.insert(&original).unwrap();
+
+        // Replace the transaction half a day later with one that spends the
+        // same UTXO and pays a higher fee
+        let replacement = preminer_state
+            .create_transaction(
+                vec![receiver_data()],
+                NeptuneCoins::new(10),
+                in_seven_months + Timestamp::hours(12),
+            )
+            .await?;
+        mempool.insert(&replacement).unwrap();
+        assert_eq!(1, mempool.len());
+        let replacement_id = Hash::hash(&replacement);
+        assert!(mempool.contains(replacement_id));
+
+        // The original would have expired by now, but the replacement has not
+        let after_original_expiry = in_seven_months + Timestamp::days(1) + Timestamp::hours(1);
+        assert!(mempool.prune_expired(after_original_expiry).is_empty());
+        assert!(mempool.contains(replacement_id));
+        assert!(events.try_recv().is_err());
+
+        let after_replacement_expiry = after_original_expiry + Timestamp::hours(12);
+        assert_eq!(
+            vec![replacement_id],
+            mempool.prune_expired(after_replacement_expiry)
+        );
+        assert!(mempool.is_empty());
+        assert_eq!(
+            MempoolEvent::Expired {
+                transaction_id: replacement_id,
+                transaction: Box::new(replacement),
+            },
+            events.try_recv()?
+        );
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn remove_transactions_with_block_test() -> Result<()> {
@@ -575,8 +1320,13 @@ mod tests {
 
         // Ensure that both wallets have a non-zero balance
         let genesis_block = Block::genesis_block(network);
-        let (block_1, coinbase_utxo_1, cb_sender_randomness_1) =
-            make_mock_block(&genesis_block, None, other_receiver_address, rng.gen());
+        let (block_1, coinbase_utxo_1, cb_sender_randomness_1) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            other_receiver_address,
+            rng.gen(),
+        );
 
         // Update both states with block 1
         premine_receiver_global_state
@@ -628,7 +1378,7 @@ mod tests {
                 utxo: new_utxo,
             });
         }
-        let mut now = genesis_block.kernel.header.timestamp;
+        let now = genesis_block.kernel.header.timestamp;
         let seven_months = Timestamp::months(7);
         let tx_by_preminer = premine_receiver_global_state
             .create_transaction(
@@ -640,7 +1390,7 @@ mod tests {
 
         // Add this transaction to the mempool
         let mut mempool = Mempool::new(ByteSize::gb(1));
-        mempool.insert(&tx_by_preminer);
+        mempool.insert(&tx_by_preminer).unwrap();
 
         // Create another transaction that's valid to be included in block 2, but isn't actually
         // included by the miner. This transaction is inserted into the mempool, but since it's
@@ -664,11 +1414,11 @@ mod tests {
             )
             .await
             .unwrap();
-        mempool.insert(&tx_by_other_original);
+        mempool.insert(&tx_by_other_original).unwrap();
 
         // Create next block which includes preminer's transaction
         let (mut block_2, _, _) =
-            make_mock_block(&block_1, None, premine_receiver_address, rng.gen());
+            make_mock_block(network, &block_1, None, premine_receiver_address, rng.gen());
         block_2
             .accumulate_transaction(tx_by_preminer, &block_1.kernel.body.mutator_set_accumulator)
             .await;
@@ -694,7 +1444,7 @@ mod tests {
         );
 
         let (block_3_with_no_input, _, _) =
-            make_mock_block(&block_2, None, premine_receiver_address, rng.gen());
+            make_mock_block(network, &block_2, None, premine_receiver_address, rng.gen());
         let mut block_3_with_updated_tx = block_3_with_no_input.clone();
 
         debug!(
@@ -720,9 +1470,8 @@ mod tests {
                 &block_2.kernel.body.mutator_set_accumulator,
             )
             .await;
-        now = block_2.kernel.header.timestamp;
         assert!(
-            block_3_with_updated_tx.is_valid(&block_2, now + seven_months),
+            block_3_with_updated_tx.is_valid(&block_2, network),
             "Block with tx with updated mutator set data must be valid"
         );
 
@@ -731,8 +1480,13 @@ mod tests {
         // valid.
         let mut previous_block = block_3_with_no_input;
         for _ in 0..10 {
-            let (next_block, _, _) =
-                make_mock_block(&previous_block, None, other_receiver_address, rng.gen());
+            let (next_block, _, _) = make_mock_block(
+                network,
+                &previous_block,
+                None,
+                other_receiver_address,
+                rng.gen(),
+            );
             mempool
                 .update_with_block(
                     previous_block.kernel.body.mutator_set_accumulator.clone(),
@@ -742,8 +1496,13 @@ mod tests {
             previous_block = next_block;
         }
 
-        let (mut block_14, _, _) =
-            make_mock_block(&previous_block, None, other_receiver_address, rng.gen());
+        let (mut block_14, _, _) = make_mock_block(
+            network,
+            &previous_block,
+            None,
+            other_receiver_address,
+            rng.gen(),
+        );
         assert_eq!(Into::<BlockHeight>::into(14), block_14.kernel.header.height);
         tx_by_other_updated = mempool.get_transactions_for_block(usize::MAX)[0].clone();
         block_14
@@ -752,9 +1511,8 @@ mod tests {
                 &previous_block.kernel.body.mutator_set_accumulator,
             )
             .await;
-        now = previous_block.kernel.header.timestamp;
         assert!(
-            block_14.is_valid(&previous_block, now+seven_months),
+            block_14.is_valid(&previous_block, network),
             "Block with tx with updated mutator set data must be valid after 10 blocks have been mined"
         );
 
@@ -773,6 +1531,244 @@ mod tests {
         Ok(())
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn pooled_transaction_remains_minable_after_three_blocks_test() -> Result<()> {
+        let network = Network::RegTest;
+        let preminer_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let genesis_block = Block::genesis_block(network);
+        let seven_months = Timestamp::months(7);
+        let mut preminer_state = preminer_state_lock.lock_guard_mut().await;
+        let premine_address = preminer_state
+            .wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        // Create a transaction relative to the genesis block
+        let receiver_data = UtxoReceiverData {
+            utxo: Utxo {
+                coins: NeptuneCoins::new(1).to_native_coins(),
+                lock_script_hash: premine_address.lock_script().hash(),
+            },
+            receiver_privacy_digest: premine_address.privacy_digest,
+            sender_randomness: random(),
+            public_announcement: PublicAnnouncement::default(),
+        };
+        let tx = preminer_state
+            .create_transaction(
+                vec![receiver_data],
+                NeptuneCoins::new(1),
+                genesis_block.kernel.header.timestamp + seven_months,
+            )
+            .await?;
+        let mut mempool = Mempool::new(ByteSize::gb(1));
+        mempool.insert(&tx).unwrap();
+
+        // Mine two blocks without the transaction, keeping the mempool updated
+        let mut previous_block = genesis_block;
+        for _ in 0..2 {
+            let (next_block, _, _) =
+                make_mock_block(network, &previous_block, None, premine_address, random());
+            mempool
+                .update_with_block(
+                    previous_block.kernel.body.mutator_set_accumulator.clone(),
+                    &next_block,
+                )
+                .await;
+            previous_block = next_block;
+        }
+        assert_eq!(1, mempool.len());
+
+        // The updated transaction must be includable in block 3
+        let updated_tx = mempool.get_transactions_for_block(usize::MAX)[0].clone();
+        assert_eq!(
+            previous_block.kernel.body.mutator_set_accumulator.hash(),
+            updated_tx.kernel.mutator_set_hash
+        );
+        let (mut block_3, _, _) =
+            make_mock_block(network, &previous_block, None, premine_address, random());
+        block_3
+            .accumulate_transaction(
+                updated_tx,
+                &previous_block.kernel.body.mutator_set_accumulator,
+            )
+            .await;
+        assert_eq!(BlockHeight::from(3u64), block_3.kernel.header.height);
+        assert!(block_3.is_valid(&previous_block, network));
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn orphan_is_promoted_when_its_block_arrives_test() -> Result<()> {
+        // A peer that received block 1 before us relays a transaction that
+        // was built against it.
+        let network = Network::RegTest;
+        let preminer_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let genesis_block = Block::genesis_block(network);
+        let seven_months = Timestamp::months(7);
+        let mut preminer_state = preminer_state_lock.lock_guard_mut().await;
+        let premine_address = preminer_state
+            .wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (block_1, _, _) =
+            make_mock_block(network, &genesis_block, None, premine_address, random());
+        preminer_state.set_new_tip(block_1.clone()).await?;
+
+        let receiver_data = UtxoReceiverData {
+            utxo: Utxo {
+                coins: NeptuneCoins::new(1).to_native_coins(),
+                lock_script_hash: premine_address.lock_script().hash(),
+            },
+            receiver_privacy_digest: premine_address.privacy_digest,
+            sender_randomness: random(),
+            public_announcement: PublicAnnouncement::default(),
+        };
+        let tx = preminer_state
+            .create_transaction(
+                vec![receiver_data],
+                NeptuneCoins::new(1),
+                genesis_block.kernel.header.timestamp + seven_months,
+            )
+            .await?;
+        assert_eq!(
+            block_1.kernel.body.mutator_set_accumulator.hash(),
+            tx.kernel.mutator_set_hash
+        );
+
+        // Our mempool is still at genesis, so the transaction is an orphan
+        let mut mempool = Mempool::new(ByteSize::gb(1));
+        let mut events = mempool.subscribe_to_events();
+        assert!(mempool.insert_orphan(tx.clone(), Timestamp::now()));
+        assert!(mempool.is_empty());
+        assert_eq!(1, mempool.orphans().len());
+
+        // A competing block does not promote the orphan
+        let (other_block_1, _, _) =
+            make_mock_block(network, &genesis_block, None, premine_address, random());
+        assert!(mempool
+            .promote_orphans(&other_block_1.kernel.body.mutator_set_accumulator)
+            .is_empty());
+        assert_eq!(1, mempool.orphans().len());
+
+        // Block 1 arrives and the orphan is promoted
+        mempool
+            .update_with_block(
+                genesis_block.kernel.body.mutator_set_accumulator.clone(),
+                &block_1,
+            )
+            .await;
+        assert!(mempool.orphans().is_empty());
+        assert_eq!(1, mempool.len());
+        assert!(mempool.contains(Hash::hash(&tx)));
+        assert_eq!(
+            MempoolEvent::Promoted {
+                transaction_id: Hash::hash(&tx)
+            },
+            events.try_recv().unwrap()
+        );
+        assert!(events.try_recv().is_err());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn transactions_invalidated_by_new_tip_are_purged_test() -> Result<()> {
+        let network = Network::RegTest;
+        let preminer_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let genesis_block = Block::genesis_block(network);
+        let now = genesis_block.kernel.header.timestamp;
+        let seven_months = Timestamp::months(7);
+        let mut preminer_state = preminer_state_lock.lock_guard_mut().await;
+        let premine_address = preminer_state
+            .wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        let receiver_data = UtxoReceiverData {
+            utxo: Utxo {
+                coins: NeptuneCoins::new(1).to_native_coins(),
+                lock_script_hash: premine_address.lock_script().hash(),
+            },
+            receiver_privacy_digest: premine_address.privacy_digest,
+            sender_randomness: random(),
+            public_announcement: PublicAnnouncement::default(),
+        };
+        let tx_by_preminer = preminer_state
+            .create_transaction(
+                vec![receiver_data],
+                NeptuneCoins::new(1),
+                now + seven_months,
+            )
+            .await?;
+        preminer_state.mempool.insert(&tx_by_preminer).unwrap();
+        let tx_by_preminer_id = Hash::hash(&tx_by_preminer);
+
+        // The transaction is valid against the current tip, as is a transaction
+        // without inputs that was built against it
+        let genesis_mutator_set_accumulator = &genesis_block.kernel.body.mutator_set_accumulator;
+        let mut tx_without_inputs = make_mock_transaction(vec![], vec![random_addition_record()]);
+        tx_without_inputs.kernel.mutator_set_hash = genesis_mutator_set_accumulator.hash();
+        preminer_state.mempool.insert(&tx_without_inputs).unwrap();
+        let tx_without_inputs_id = Hash::hash(&tx_without_inputs);
+        preminer_state
+            .mempool
+            .retain_valid_against_tip(genesis_mutator_set_accumulator, &[]);
+        assert_eq!(2, preminer_state.mempool.len());
+
+        // A transaction built against another mutator set is dropped
+        let tx_against_other_mutator_set =
+            make_mock_transaction(vec![], vec![random_addition_record()]);
+        preminer_state
+            .mempool
+            .insert(&tx_against_other_mutator_set)
+            .unwrap();
+        assert_eq!(3, preminer_state.mempool.len());
+        preminer_state
+            .mempool
+            .retain_valid_against_tip(genesis_mutator_set_accumulator, &[]);
+        assert!(!preminer_state
+            .mempool
+            .contains(Hash::hash(&tx_against_other_mutator_set)));
+        assert_eq!(2, preminer_state.mempool.len());
+
+        // A transaction spending a removed input is dropped, even though its
+        // mutator set hash matches the tip
+        preminer_state.mempool.retain_valid_against_tip(
+            genesis_mutator_set_accumulator,
+            &tx_by_preminer.kernel.inputs,
+        );
+        assert!(!preminer_state.mempool.contains(tx_by_preminer_id));
+        assert!(preminer_state.mempool.contains(tx_without_inputs_id));
+
+        // Setting the block that mines the transaction as the new tip, as the
+        // main loop does, purges the mined transaction. The transaction
+        // without inputs has no witness to update its mutator set data with,
+        // so it is dropped as well.
+        preminer_state.mempool.insert(&tx_by_preminer).unwrap();
+        assert_eq!(2, preminer_state.mempool.len());
+        let (mut block_1, _, _) =
+            make_mock_block(network, &genesis_block, None, premine_address, random());
+        block_1
+            .accumulate_transaction(tx_by_preminer, genesis_mutator_set_accumulator)
+            .await;
+        preminer_state.set_new_tip(block_1).await?;
+        assert!(!preminer_state.mempool.contains(tx_by_preminer_id));
+        assert!(!preminer_state.mempool.contains(tx_without_inputs_id));
+        assert!(preminer_state.mempool.is_empty());
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn conflicting_txs_preserve_highest_fee() -> Result<()> {
@@ -792,22 +1788,27 @@ mod tests {
             coins: NeptuneCoins::new(1).to_native_coins(),
             lock_script_hash: premine_address.lock_script().hash(),
         };
-        let receiver_data = UtxoReceiverData {
-            utxo,
+        // Every transaction needs fresh sender randomness, since the wallet
+        // refuses to reuse it
+        let receiver_data = || UtxoReceiverData {
+            utxo: utxo.clone(),
             receiver_privacy_digest: premine_address.privacy_digest,
             sender_randomness: random(),
             public_announcement: PublicAnnouncement::default(),
         };
         let tx_by_preminer_low_fee = preminer_state
             .create_transaction(
-                vec![receiver_data.clone()],
+                vec![receiver_data()],
                 NeptuneCoins::new(1),
                 now + seven_months,
             )
             .await?;
 
         assert_eq!(0, preminer_state.mempool.len());
-        preminer_state.mempool.insert(&tx_by_preminer_low_fee);
+        preminer_state
+            .mempool
+            .insert(&tx_by_preminer_low_fee)
+            .unwrap();
 
         assert_eq!(1, preminer_state.mempool.len());
         assert_eq!(
@@ -822,12 +1823,15 @@ mod tests {
         // Verify that this replaces the previous transaction.
         let tx_by_preminer_high_fee = preminer_state
             .create_transaction(
-                vec![receiver_data.clone()],
+                vec![receiver_data()],
                 NeptuneCoins::new(10),
                 now + seven_months,
             )
             .await?;
-        preminer_state.mempool.insert(&tx_by_preminer_high_fee);
+        preminer_state
+            .mempool
+            .insert(&tx_by_preminer_high_fee)
+            .unwrap();
         assert_eq!(1, preminer_state.mempool.len());
         assert_eq!(
             &tx_by_preminer_high_fee,
@@ -841,12 +1845,15 @@ mod tests {
         // does *not* replace the existing transaction.
         let tx_by_preminer_medium_fee = preminer_state
             .create_transaction(
-                vec![receiver_data],
+                vec![receiver_data()],
                 NeptuneCoins::new(4),
                 now + seven_months,
             )
             .await?;
-        preminer_state.mempool.insert(&tx_by_preminer_medium_fee);
+        preminer_state
+            .mempool
+            .insert(&tx_by_preminer_medium_fee)
+            .unwrap();
         assert_eq!(1, preminer_state.mempool.len());
         assert_eq!(
             &tx_by_preminer_high_fee,
@@ -859,6 +1866,241 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn replace_by_fee_defaults_to_fee_density_test() {
+        let spent = random_removal_record();
+        let transaction_with_fee = |output_count: usize, fee: u32| {
+            let outputs = (0..output_count)
+                .map(|_| random_addition_record())
+                .collect();
+            let mut transaction = make_mock_transaction(vec![spent.clone()], outputs);
+            transaction.kernel.fee = NeptuneCoins::new(fee);
+            transaction
+        };
+        let tx_large = transaction_with_fee(20, 3);
+        let tx_small_lower_fee = transaction_with_fee(0, 2);
+        let tx_large_lower_fee_density = transaction_with_fee(40, 4);
+        assert!(tx_small_lower_fee.fee_density() > tx_large.fee_density());
+        assert!(tx_large_lower_fee_density.fee_density() < tx_small_lower_fee.fee_density());
+
+        // Without a margin, a higher fee density suffices, even with a lower
+        // absolute fee
+        let mut mempool = Mempool::new(ByteSize::gb(1));
+        assert!(mempool.insert(&tx_large).is_ok());
+        assert!(mempool.insert(&tx_small_lower_fee).is_ok());
+        assert_eq!(1, mempool.len());
+        assert!(mempool.contains(Hash::hash(&tx_small_lower_fee)));
+
+        // A higher absolute fee does not make up for a lower fee density
+        assert_eq!(
+            Err(MempoolRejection::Conflict(Hash::hash(&tx_small_lower_fee))),
+            mempool.insert(&tx_large_lower_fee_density)
+        )
+        .unwrap();
+        assert!(mempool.contains(Hash::hash(&tx_small_lower_fee)));
+    }
+
+    #[test]
+    fn replace_by_fee_requires_margin_over_all_conflicts_test() {
+        let spent_by_a = random_removal_record();
+        let spent_by_b = random_removal_record();
+        let transaction_with_fee = |inputs: Vec<RemovalRecord>, fee: u32| {
+            let mut transaction = make_mock_transaction(inputs, vec![random_addition_record()]);
+            transaction.kernel.fee = NeptuneCoins::new(fee);
+            transaction
+        };
+        let tx_a = transaction_with_fee(vec![spent_by_a.clone()], 1);
+        let tx_b = transaction_with_fee(vec![spent_by_b.clone()], 2);
+        let tx_unrelated = transaction_with_fee(vec![random_removal_record()], 1);
+
+        let mut mempool =
+            Mempool::new(ByteSize::gb(1)).with_replace_by_fee_margin(NeptuneCoins::new(2));
+        for transaction in [&tx_a, &tx_b, &tx_unrelated] {
+            assert!(mempool.find_conflicts(transaction).is_empty());
+            assert!(mempool.insert(transaction).is_ok());
+        }
+        assert_eq!(3, mempool.len());
+
+        // A transaction spending the inputs of both A and B conflicts with both
+        let conflicting_inputs = vec![spent_by_a, spent_by_b];
+        let tx_ab_cheap = transaction_with_fee(conflicting_inputs.clone(), 5);
+        assert_eq!(
+            HashSet::from([Hash::hash(&tx_a), Hash::hash(&tx_b)]),
+            mempool
+                .find_conflicts(&tx_ab_cheap)
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+
+        // Its fee must exceed their combined fee of 3 by more than the margin
+        // of 2 in order to replace them
+        assert!(matches!(
+            mempool.insert(&tx_ab_cheap),
+            Err(MempoolRejection::Conflict(_))
+        ))
+        .unwrap();
+        assert_eq!(3, mempool.len());
+        assert!(!mempool.contains(Hash::hash(&tx_ab_cheap)));
+
+        let tx_ab = transaction_with_fee(conflicting_inputs, 6);
+        assert!(mempool.insert(&tx_ab).is_ok());
+        assert_eq!(2, mempool.len());
+        assert!(mempool.contains(Hash::hash(&tx_ab)));
+        assert!(mempool.contains(Hash::hash(&tx_unrelated)));
+        assert!(!mempool.contains(Hash::hash(&tx_a)));
+        assert!(!mempool.contains(Hash::hash(&tx_b)));
+    }
+
+    #[test]
+    fn transactions_with_negative_fee_are_not_inserted_test() {
+        let spent = random_removal_record();
+        let mut transaction = make_mock_transaction(vec![spent.clone()], vec![]);
+        transaction.kernel.fee = -NeptuneCoins::new(1);
+        let mut mempool = Mempool::new(ByteSize::gb(1));
+        assert_eq!(
+            Err(MempoolRejection::NegativeFee),
+            mempool.insert(&transaction)
+        )
+        .unwrap();
+        assert!(mempool.is_empty());
+
+        // Nor does it replace a conflicting transaction
+        let conflicting_transaction = make_mock_transaction(vec![spent], vec![]);
+        assert!(mempool.insert(&conflicting_transaction).is_ok());
+        assert_eq!(
+            Err(MempoolRejection::NegativeFee),
+            mempool.insert(&transaction)
+        )
+        .unwrap();
+        assert_eq!(1, mempool.len());
+        assert!(mempool.contains(Hash::hash(&conflicting_transaction)));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn lowest_fee_transactions_are_evicted_and_announced_test() {
+        let network = Network::Alpha;
+        let wallet_state = mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
+        let transactions = (1..=10)
+            .map(|fee| {
+                make_mock_transaction_with_wallet(
+                    vec![],
+                    vec![],
+                    NeptuneCoins::new(fee),
+                    &wallet_state,
+                    None,
+                )
+            })
+            .collect_vec();
+
+        // Allow only about half of the transactions
+        let mut unbounded_mempool = Mempool::new(ByteSize::gb(1));
+        for transaction in transactions.iter() {
+            unbounded_mempool.insert(transaction);
+        }
+        let max_size = unbounded_mempool.size_in_bytes() / 2;
+
+        let mut mempool = Mempool::new(ByteSize::b(max_size as u64));
+        let mut events = mempool.subscribe_to_events();
+        for transaction in transactions.iter() {
+            mempool.insert(transaction);
+        }
+        assert!(mempool.size_in_bytes() <= max_size);
+
+        let mut evicted = vec![];
+        while let Ok(MempoolEvent::Evicted {
+            transaction_id,
+            transaction,
+        }) = events.try_recv()
+        {
+            assert_eq!(Hash::hash(transaction.as_ref()), transaction_id);
+            assert!(!mempool.contains(transaction_id));
+            evicted.push(*transaction);
+        }
+        assert!(!evicted.is_empty());
+        assert_eq!(transactions.len(), evicted.len() + mempool.len());
+
+        // Every remaining transaction pays more than every evicted one
+        let max_evicted_fee = evicted.iter().map(|tx| tx.kernel.fee).max().unwrap();
+        for (transaction_id, _fee_density) in mempool.get_sorted_iter() {
+            assert!(mempool.get(transaction_id).unwrap().kernel.fee > max_evicted_fee);
+        }
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn mempool_survives_restart_test() -> Result<()> {
+        let network = Network::RegTest;
+        let data_dir = unit_test_data_directory(network)?;
+        DataDirectory::create_dir_if_not_exists(&data_dir.root_dir_path()).await?;
+        let mempool_file_path = data_dir.mempool_file_path();
+        let wallet_state = mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
+        let tip_mutator_set_accumulator = Block::genesis_block(network)
+            .kernel
+            .body
+            .mutator_set_accumulator
+            .clone();
+
+        // Write a pool of five transactions that are valid against the tip, and
+        // one that is not
+        let mut mempool = Mempool::new(ByteSize::gb(1));
+        let mut kernel_hashes = vec![];
+        for i in 0..5 {
+            let mut transaction = make_mock_transaction_with_wallet(
+                vec![],
+                vec![],
+                NeptuneCoins::new(i),
+                &wallet_state,
+                None,
+            );
+            transaction.kernel.mutator_set_hash = tip_mutator_set_accumulator.hash();
+            kernel_hashes.push(transaction.kernel.mast_hash());
+            mempool.insert(&transaction).unwrap();
+        }
+        let outdated_transaction = make_mock_transaction_with_wallet(
+            vec![],
+            vec![],
+            NeptuneCoins::new(5),
+            &wallet_state,
+            None,
+        );
+        mempool.insert(&outdated_transaction).unwrap();
+        assert_eq!(6, mempool.len());
+        mempool.write_to_file(&mempool_file_path).await?;
+
+        // Restart from disk
+        let restored_mempool = Mempool::read_from_file(
+            ByteSize::gb(1),
+            Timestamp::seconds(MEMPOOL_TX_THRESHOLD_AGE_IN_SECS),
+            &mempool_file_path,
+            &tip_mutator_set_accumulator,
+        )
+        .await?;
+        let restored_kernel_hashes = restored_mempool
+            .get_transactions_for_block(usize::MAX)
+            .iter()
+            .map(|transaction| transaction.kernel.mast_hash())
+            .collect::<HashSet<_>>();
+        assert_eq!(5, restored_mempool.len());
+        assert_eq!(
+            kernel_hashes.into_iter().collect::<HashSet<_>>(),
+            restored_kernel_hashes
+        );
+        assert!(!restored_mempool.contains(Hash::hash(&outdated_transaction)));
+
+        // A missing file yields an empty mempool
+        let empty_mempool = Mempool::read_from_file(
+            ByteSize::gb(1),
+            Timestamp::seconds(MEMPOOL_TX_THRESHOLD_AGE_IN_SECS),
+            &data_dir.root_dir_path().join("missing.dat"),
+            &tip_mutator_set_accumulator,
+        )
+        .await?;
+        assert!(empty_mempool.is_empty());
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn get_mempool_size() {
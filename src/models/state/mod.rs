@@ -8,19 +8,28 @@ use crate::util_types::mutator_set::commit;
 use anyhow::{bail, Result};
 use itertools::Itertools;
 use num_traits::CheckedSub;
-use std::cmp::max;
+use rand::Rng;
+use std::cmp::{max, min, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
 use std::ops::{Deref, DerefMut};
+use std::time::SystemTime;
 use tracing::{debug, info, warn};
 use twenty_first::math::bfield_codec::BFieldCodec;
 use twenty_first::math::digest::Digest;
 use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 
-use self::blockchain_state::BlockchainState;
+use self::archival_state::{ArchivalState, SWBF_FALSE_POSITIVE_RATE_ALARM_THRESHOLD};
+use self::blockchain_state::{BlockchainState, ChainEvent};
+use self::chain_split_detector::ChainSplitAlert;
+use self::digest_lookup::{DigestKind, HexPrefix, ResolvedDigest};
 use self::mempool::Mempool;
 use self::networking_state::NetworkingState;
+use self::storage_scrubber::{ScrubbedKey, SCRUB_BLOCKS_PER_ROUND, SCRUB_CHUNKS_PER_ROUND};
 use self::wallet::address::generation_address::SpendingKey;
+use self::wallet::sender_randomness_filter::SenderRandomnessFilter;
 use self::wallet::utxo_notification_pool::UtxoNotifier;
-use self::wallet::wallet_state::WalletState;
+use self::wallet::wallet_state::{WalletState, PROOF_AUDIT_BATCH_SIZE};
 use self::wallet::wallet_status::WalletStatus;
 use super::blockchain::block::block_height::BlockHeight;
 use super::blockchain::block::Block;
@@ -34,26 +43,43 @@ use super::blockchain::type_scripts::native_currency::NativeCurrency;
 use super::blockchain::type_scripts::neptune_coins::NeptuneCoins;
 use super::blockchain::type_scripts::time_lock::TimeLock;
 use super::blockchain::type_scripts::TypeScript;
+use super::consensus::mast_hash::MastHash;
 use super::consensus::tasm::program::ConsensusProgram;
 use super::consensus::timestamp::Timestamp;
 use crate::config_models::cli_args;
 use crate::locks::tokio as sync_tokio;
-use crate::models::peer::HandshakeData;
-use crate::models::state::wallet::monitored_utxo::MonitoredUtxo;
+use crate::models::channel::{MinerRecoveries, PeerThreadToMainTransaction};
+use crate::models::database::PeerDatabaseGcReport;
+use crate::models::peer::{
+    HandshakeData, PeerFeatures, PeerStanding, MIN_SUPPORTED_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use crate::models::state::wallet::monitored_utxo::{
+    MembershipProofSyncStatus, MonitoredUtxo, MonitoredUtxoInfo, ProofAuditReport, UnspentUtxoInfo,
+};
+use crate::models::state::wallet::transaction_label::{validate_label, LabelTarget};
 use crate::models::state::wallet::utxo_notification_pool::ExpectedUtxo;
+use crate::peer_codec::PeerCodec;
 use crate::time_fn_call_async;
 use crate::util_types::mutator_set::addition_record::AdditionRecord;
+use crate::util_types::mutator_set::archival_mutator_set::MutatorSetStatistics;
 use crate::util_types::mutator_set::ms_membership_proof::MsMembershipProof;
 use crate::util_types::mutator_set::removal_record::RemovalRecord;
 
 use crate::{Hash, VERSION};
 
+pub mod address_book;
 pub mod archival_state;
+pub mod block_cache;
 pub mod blockchain_state;
+pub mod chain_split_detector;
+pub mod digest_lookup;
+pub mod header_chain;
 pub mod light_state;
 pub mod mempool;
 pub mod networking_state;
+pub mod orphan_pool;
 pub mod shared;
+pub mod storage_scrubber;
 pub mod wallet;
 
 /// `GlobalStateLock` holds a [`tokio::AtomicRw`](crate::locks::tokio::AtomicRw)
@@ -155,6 +181,31 @@ impl GlobalStateLock {
         self.lock_guard_mut().await.flush_databases().await
     }
 
+    /// store the mempool's transactions on disk. The transactions are encoded
+    /// under the read lock, and the file is written after releasing it.
+    pub async fn persist_mempool(&self) -> Result<()> {
+        let (encoded_transactions, transaction_count, mempool_file_path) = {
+            let global_state = self.lock_guard().await;
+            (
+                global_state.mempool.encode_for_file()?,
+                global_state.mempool.len(),
+                global_state
+                    .chain
+                    .archival_state()
+                    .data_dir()
+                    .mempool_file_path(),
+            )
+        };
+
+        Mempool::write_encoded_to_file(encoded_transactions, &mempool_file_path).await?;
+        debug!(
+            "Stored {transaction_count} mempool transactions in {}",
+            mempool_file_path.display()
+        );
+
+        Ok(())
+    }
+
     /// store a coinbase (self-mined) block
     pub async fn store_coinbase_block(
         &self,
@@ -172,11 +223,216 @@ impl GlobalStateLock {
         self.lock_guard_mut().await.set_new_tip(new_block).await
     }
 
+    /// drop transaction witnesses of deeply buried blocks. The write lock is
+    /// only held while a single block file is rewritten.
+    pub async fn prune_transaction_witnesses(&self) -> Result<usize> {
+        let (prune_height, file_indices) = {
+            let global_state = self.lock_guard().await;
+            let Some(prune_height) = global_state.witness_prune_target() else {
+                return Ok(0);
+            };
+            let file_indices = global_state
+                .chain
+                .archival_state()
+                .block_files_to_prune(prune_height)
+                .await;
+            (prune_height, file_indices)
+        };
+
+        let mut pruned_count = 0;
+        for file_index in file_indices {
+            pruned_count += self
+                .lock_guard_mut()
+                .await
+                .chain
+                .archival_state_mut()
+                .prune_transaction_witnesses_in_file(file_index, prune_height)
+                .await?;
+        }
+        self.lock_guard_mut()
+            .await
+            .chain
+            .archival_state_mut()
+            .set_witness_prune_height(prune_height)
+            .await;
+        if pruned_count > 0 {
+            info!("Dropped transaction witnesses of {pruned_count} blocks up to height {prune_height}");
+        }
+
+        Ok(pruned_count)
+    }
+
+    /// compare the tips announced by peers to the canonical chain. The chain is
+    /// walked under the read lock, the write lock is only held to update the
+    /// alert.
+    pub async fn check_for_chain_split(&self) -> Option<ChainSplitAlert> {
+        let (tip_digest, tip_height, canonical_digests) =
+            self.lock_guard().await.canonical_claimed_digests().await?;
+        let mut global_state_mut = self.lock_guard_mut().await;
+
+        // A new tip while the lock was released invalidates the walk, which
+        // is then repeated in the next check
+        if global_state_mut.chain.light_state().hash() != tip_digest {
+            return global_state_mut.net.chain_split_detector.alert().cloned();
+        }
+        global_state_mut
+            .net
+            .chain_split_detector
+            .evaluate(
+                tip_digest,
+                tip_height,
+                &canonical_digests,
+                SystemTime::now(),
+            )
+            .cloned()
+    }
+
+    /// record whether this node has peers, and warn if it has been without
+    /// peers for too long
+    pub async fn check_peer_count(&self) -> Option<SystemTime> {
+        self.lock_guard_mut()
+            .await
+            .check_peer_count(SystemTime::now())
+    }
+
+    /// verify a random sample of the stored blocks and mutator set chunks, and
+    /// return the records found to be corrupt for the first time. The sample
+    /// is verified under the read lock, such that the write lock is only held
+    /// to record the outcome.
+    pub async fn scrub_storage(&self) -> Vec<ScrubbedKey> {
+        let round = {
+            let global_state = self.lock_guard().await;
+            if !global_state.chain.is_archival_node() {
+                return vec![];
+            }
+
+            let tip_height = global_state.chain.light_state().header().height;
+            global_state
+                .chain
+                .archival_state()
+                .scrub_sample(tip_height, SCRUB_BLOCKS_PER_ROUND, SCRUB_CHUNKS_PER_ROUND)
+                .await
+        };
+
+        self.lock_guard_mut()
+            .await
+            .chain
+            .archival_state_mut()
+            .record_scrub_round(round, SystemTime::now())
+    }
+
+    /// return summarized statistics about the archival mutator set, and the
+    /// height at which they were computed. Stale statistics are recomputed
+    /// under the read lock, such that the write lock is only held to update
+    /// the cache.
+    pub async fn mutator_set_statistics(&self) -> (BlockHeight, MutatorSetStatistics) {
+        let (tip_height, statistics) = {
+            let global_state = self.lock_guard().await;
+            let tip_height = global_state.chain.light_state().header().height;
+            let archival_state = global_state.chain.archival_state();
+            if let Some(cached) = archival_state.cached_mutator_set_statistics(tip_height) {
+                return cached;
+            }
+
+            let statistics = archival_state.archival_mutator_set.ams().statistics().await;
+            (tip_height, statistics)
+        };
+
+        self.lock_guard_mut()
+            .await
+            .chain
+            .archival_state_mut()
+            .cache_mutator_set_statistics(tip_height, statistics.clone());
+
+        (tip_height, statistics)
+    }
+
+    /// warn if the sliding-window Bloom filter of the archival mutator set is
+    /// saturating, and return its estimated false-positive rate if so. Uses
+    /// [Self::mutator_set_statistics], so the write lock is only taken when
+    /// the cached statistics are refreshed.
+    pub async fn check_swbf_saturation(&self) -> Option<f64> {
+        if !self.lock(|s| s.chain.is_archival_node()).await {
+            return None;
+        }
+
+        let (computed_at, statistics) = self.mutator_set_statistics().await;
+        ArchivalState::report_swbf_saturation(
+            computed_at,
+            &statistics,
+            SWBF_FALSE_POSITIVE_RATE_ALARM_THRESHOLD,
+        )
+    }
+
+    /// Audit the membership proofs of the wallet against the current tip, see
+    /// [WalletState::audit_proofs]. The global state is read-locked for one
+    /// batch of monitored UTXOs at a time, such that auditing a large wallet
+    /// does not hold up writers. The audit starts over if the tip changes in
+    /// between batches.
+    pub async fn audit_wallet_proofs(&self) -> ProofAuditReport {
+        'audit: loop {
+            let (tip_digest, count) = {
+                let global_state = self.lock_guard().await;
+                let count = global_state
+                    .wallet_state
+                    .wallet_db
+                    .monitored_utxos()
+                    .len()
+                    .await;
+                (global_state.chain.light_state().hash(), count)
+            };
+            let mut report = ProofAuditReport {
+                tip_digest,
+                audited_count: 0,
+                entries: vec![],
+            };
+            let mut abandoned_blocks = HashMap::new();
+            for batch_start in (0..count).step_by(PROOF_AUDIT_BATCH_SIZE) {
+                let global_state = self.lock_guard().await;
+                let tip = global_state.chain.light_state();
+                if tip.hash() != tip_digest {
+                    continue 'audit;
+                }
+
+                let archival_state = global_state
+                    .chain
+                    .is_archival_node()
+                    .then(|| global_state.chain.archival_state());
+                let batch_end = min(batch_start + PROOF_AUDIT_BATCH_SIZE as u64, count);
+                global_state
+                    .wallet_state
+                    .audit_proof_batch(
+                        tip,
+                        archival_state,
+                        batch_start..batch_end,
+                        &mut abandoned_blocks,
+                        &mut report,
+                    )
+                    .await;
+            }
+
+            return report;
+        }
+    }
+
     /// resync membership proofs
     pub async fn resync_membership_proofs(&self) -> Result<()> {
         self.lock_guard_mut().await.resync_membership_proofs().await
     }
 
+    /// rescan the canonical blocks that the wallet has not seen
+    pub async fn rescan_wallet_if_behind(&self) -> Result<usize> {
+        self.lock_guard_mut().await.rescan_wallet_if_behind().await
+    }
+
+    /// rebuild the wallet state from the canonical blocks
+    pub async fn rescan_wallet_from_height(&self, start_height: BlockHeight) -> Result<usize> {
+        self.lock_guard_mut()
+            .await
+            .rescan_wallet_from_height(start_height)
+            .await
+    }
+
     pub async fn prune_abandoned_monitored_utxos(
         &self,
         block_depth_threshhold: usize,
@@ -236,6 +492,9 @@ pub struct GlobalState {
 
     // Only the mining thread should write to this, anyone can read.
     pub mining: bool,
+
+    // Only the mining thread should write to this, anyone can read.
+    pub miner_recoveries: MinerRecoveries,
 }
 
 #[derive(Debug, Clone)]
@@ -262,6 +521,7 @@ impl GlobalState {
             cli,
             mempool,
             mining,
+            miner_recoveries: MinerRecoveries::default(),
         }
     }
 
@@ -350,21 +610,31 @@ impl GlobalState {
         max(max_confirmed_in_block, max_spent_in_block)
     }
 
-    /// Retrieve wallet balance history
-    pub async fn get_balance_history(&self) -> Vec<(Digest, Timestamp, BlockHeight, NeptuneCoins)> {
+    /// Retrieve wallet balance history, along with the labels of the
+    /// transactions that created or spent the UTXOs, if any.
+    pub async fn get_balance_history(
+        &self,
+    ) -> Vec<(Digest, Timestamp, BlockHeight, NeptuneCoins, Option<String>)> {
         let current_tip_digest = self.chain.light_state().hash();
 
         let monitored_utxos = self.wallet_state.wallet_db.monitored_utxos();
+        let transaction_labels = self.wallet_state.wallet_db.get_transaction_labels().await;
+        let archival_restore_utxos = self
+            .wallet_state
+            .wallet_db
+            .get_archival_restore_utxos()
+            .await;
 
         // let num_monitored_utxos = monitored_utxos.len();
         let mut history = vec![];
 
-        let stream = monitored_utxos.stream_values().await;
+        let stream = monitored_utxos.stream().await;
         pin_mut!(stream); // needed for iteration
-        while let Some(monitored_utxo) = stream.next().await {
+        while let Some((i, monitored_utxo)) = stream.next().await {
             if monitored_utxo
                 .get_membership_proof_for_block(current_tip_digest)
                 .is_none()
+                && !archival_restore_utxos.contains_key(&i)
             {
                 continue;
             }
@@ -373,22 +643,234 @@ impl GlobalState {
                 monitored_utxo.confirmed_in_block
             {
                 let amount = monitored_utxo.utxo.get_native_currency_amount();
+                let creation_label = transaction_labels
+                    .values()
+                    .find(|transaction_label| transaction_label.created(&monitored_utxo))
+                    .map(|transaction_label| transaction_label.label.clone());
                 history.push((
                     confirming_block,
                     confirmation_timestamp,
                     confirmation_height,
                     amount,
+                    creation_label,
                 ));
                 if let Some((spending_block, spending_timestamp, spending_height)) =
                     monitored_utxo.spent_in_block
                 {
-                    history.push((spending_block, spending_timestamp, spending_height, -amount));
+                    let spending_label = transaction_labels
+                        .values()
+                        .find(|transaction_label| transaction_label.spent(&monitored_utxo))
+                        .map(|transaction_label| transaction_label.label.clone());
+                    history.push((
+                        spending_block,
+                        spending_timestamp,
+                        spending_height,
+                        -amount,
+                        spending_label,
+                    ));
                 }
             }
         }
         history
     }
 
+    /// Retrieve the entries of the wallet balance history whose label contains
+    /// the given substring.
+    pub async fn find_history(
+        &self,
+        label_substring: &str,
+    ) -> Vec<(Digest, Timestamp, BlockHeight, NeptuneCoins, Option<String>)> {
+        self.get_balance_history()
+            .await
+            .into_iter()
+            .filter(|(.., label)| {
+                label
+                    .as_deref()
+                    .is_some_and(|text| text.contains(label_substring))
+            })
+            .collect()
+    }
+
+    /// Find the blocks, mempool transactions, and wallet transactions and
+    /// UTXOs whose digest starts with the given hex prefix. Several candidates
+    /// are returned if the prefix is ambiguous. Light nodes only know the
+    /// digest of their tip.
+    pub async fn resolve_digest(&self, prefix: &HexPrefix) -> Vec<ResolvedDigest> {
+        let block_digests = if self.chain.is_archival_node() {
+            self.chain
+                .archival_state()
+                .block_digests_with_hex_prefix(prefix)
+                .await
+        } else {
+            vec![self.chain.light_state().hash()]
+        };
+        let mempool_transaction_ids = self.mempool.id_set();
+        let wallet_transaction_ids = self
+            .wallet_state
+            .wallet_db
+            .get_transaction_labels()
+            .await
+            .into_keys();
+        let utxo_digests = self
+            .wallet_state
+            .wallet_db
+            .monitored_utxos()
+            .get_all()
+            .await
+            .into_iter()
+            .map(|monitored_utxo| Hash::hash(&monitored_utxo.utxo));
+
+        let with_kind = |kind: DigestKind| move |digest: Digest| ResolvedDigest { digest, kind };
+        block_digests
+            .into_iter()
+            .map(with_kind(DigestKind::Block))
+            .chain(
+                mempool_transaction_ids
+                    .into_iter()
+                    .map(with_kind(DigestKind::MempoolTransaction)),
+            )
+            .chain(wallet_transaction_ids.map(with_kind(DigestKind::WalletTransaction)))
+            .chain(utxo_digests.map(with_kind(DigestKind::WalletUtxo)))
+            .filter(|resolved| prefix.matches(&resolved.digest))
+            .unique()
+            .collect()
+    }
+
+    /// Compute the wallet's confirmed balance as of the block at the given
+    /// height in the current canonical chain, see
+    /// [WalletState::balance_at_height]. Returns None if the height exceeds
+    /// that of the tip.
+    pub async fn balance_at_height(&self, height: BlockHeight) -> Option<NeptuneCoins> {
+        let tip = self.chain.light_state();
+        if height > tip.kernel.header.height {
+            return None;
+        }
+
+        Some(
+            self.wallet_state
+                .balance_at_height(height, tip.hash(), self.chain.archival_state())
+                .await,
+        )
+    }
+
+    /// Compute the wallet's confirmed balance as of the last block in the
+    /// current canonical chain whose timestamp is not later than the given
+    /// timestamp. Returns the height of that block along with the balance, or
+    /// None if the timestamp predates the genesis block.
+    pub async fn balance_at_timestamp(
+        &self,
+        timestamp: Timestamp,
+    ) -> Option<(BlockHeight, NeptuneCoins)> {
+        let height = self
+            .chain
+            .archival_state()
+            .canonical_block_height_at_timestamp(timestamp, self.chain.light_state().hash())
+            .await?;
+        let balance = self.balance_at_height(height).await?;
+
+        Some((height, balance))
+    }
+
+    /// Attach a label to a transaction or a monitored UTXO, replacing any
+    /// previous label. A transaction that was not labeled when it was created
+    /// can be labeled for as long as it lives in the mempool. Returns false
+    /// if the target is unknown.
+    pub async fn set_label(&mut self, target: LabelTarget, label: String) -> Result<bool> {
+        match target {
+            LabelTarget::Transaction(kernel_hash) => {
+                if self
+                    .wallet_state
+                    .relabel_transaction(kernel_hash, label.clone())
+                    .await?
+                {
+                    return Ok(true);
+                }
+
+                let Some(kernel) = self
+                    .mempool
+                    .get_sorted_iter()
+                    .filter_map(|(transaction_id, _)| self.mempool.get(transaction_id))
+                    .map(|transaction| transaction.kernel.clone())
+                    .find(|kernel| kernel.mast_hash() == kernel_hash)
+                else {
+                    return Ok(false);
+                };
+                self.wallet_state
+                    .set_transaction_label(&kernel, label)
+                    .await?;
+
+                Ok(true)
+            }
+            LabelTarget::MonitoredUtxo(index) => Ok(self
+                .wallet_state
+                .set_monitored_utxo_label(index, label)
+                .await?),
+        }
+    }
+
+    /// Report the state of the monitored UTXOs with database indices in the
+    /// range `[offset, offset + limit)`, relative to the current tip.
+    ///
+    /// Confirmations require a lookup in the archival state for each confirmed
+    /// UTXO, so callers with large wallets should use modest page sizes.
+    pub async fn list_monitored_utxos(&self, offset: u64, limit: u64) -> Vec<MonitoredUtxoInfo> {
+        let tip_digest = self.chain.light_state().hash();
+        let tip_height = self.chain.light_state().header().height;
+        let monitored_utxos = self.wallet_state.wallet_db.monitored_utxos();
+        let mut labels = self.wallet_state.wallet_db.get_utxo_labels().await;
+
+        let end = std::cmp::min(offset.saturating_add(limit), monitored_utxos.len().await);
+        if offset >= end {
+            return vec![];
+        }
+        let indices = (offset..end).collect_vec();
+
+        let mut infos = vec![];
+        for (index, monitored_utxo) in indices
+            .iter()
+            .zip_eq(monitored_utxos.get_many(&indices).await)
+        {
+            let confirmations = match monitored_utxo.confirmed_in_block {
+                Some((block_digest, _, block_height)) => {
+                    if self
+                        .chain
+                        .archival_state()
+                        .block_belongs_to_canonical_chain(block_digest, tip_digest)
+                        .await
+                    {
+                        Some((tip_height - block_height) as u64 + 1)
+                    } else {
+                        None
+                    }
+                }
+                None => None,
+            };
+
+            infos.push(MonitoredUtxoInfo {
+                index: *index,
+                utxo_digest: Hash::hash(&monitored_utxo.utxo),
+                amount: monitored_utxo.utxo.get_native_currency_amount(),
+                confirmed_in_block: monitored_utxo
+                    .confirmed_in_block
+                    .map(|(digest, _, height)| (digest, height)),
+                confirmations,
+                synced_to_tip: monitored_utxo.is_synced_to(tip_digest),
+                spent: monitored_utxo.spent_in_block.is_some(),
+                abandoned: monitored_utxo.abandoned_at.is_some(),
+                label: labels.remove(index),
+            });
+        }
+
+        infos
+    }
+
+    /// Report the wallet's unspent UTXOs relative to the current tip, see
+    /// [WalletState::list_unspent].
+    pub async fn list_unspent(&self) -> Vec<UnspentUtxoInfo> {
+        let tip_digest = self.chain.light_state().hash();
+        self.wallet_state.list_unspent(tip_digest).await
+    }
+
     /// Given the desired outputs, assemble UTXOs that are both spendable
     /// (*i.e.*, synced and never or no longer timelocked) and that sum to
     /// enough funds.
@@ -403,7 +885,7 @@ impl GlobalState {
         // collect spendable inputs
         let spendable_utxos_and_mps: Vec<(Utxo, LockScript, MsMembershipProof)> = self
             .wallet_state
-            .allocate_sufficient_input_funds_from_lock(total_spend, block_tip.hash(), timestamp)
+            .allocate_sufficient_input_funds_from_lock(total_spend, block_tip, timestamp)
             .await?;
 
         Ok(spendable_utxos_and_mps)
@@ -440,8 +922,14 @@ impl GlobalState {
 
     /// Generate a change UTXO and transaction output to ensure that the difference
     /// in input amount and output amount goes back to us. Also, make sure to expect
-    /// the UTXO so that we can synchronize it after it is confirmed.
-    pub async fn add_change(&mut self, change_amount: NeptuneCoins) -> (AdditionRecord, Utxo) {
+    /// the UTXO so that we can synchronize it after it is confirmed. The sender
+    /// randomness of the change is chosen to be absent from, and is then added to,
+    /// the given filter.
+    pub async fn add_change(
+        &mut self,
+        change_amount: NeptuneCoins,
+        sender_randomness_filter: &mut SenderRandomnessFilter,
+    ) -> (AdditionRecord, Utxo) {
         // generate utxo
         let own_spending_key_for_change = self
             .wallet_state
@@ -456,11 +944,24 @@ impl GlobalState {
         };
 
         // generate addition record
+        // Bump the derivation nonce until we find sender randomness that was not
+        // used for a recent output, e.g. the change of an earlier transaction
+        // created while this block was the tip, or another output of this
+        // transaction.
         let receiver_digest = own_receiving_address.privacy_digest;
-        let change_sender_randomness = self.wallet_state.wallet_secret.generate_sender_randomness(
-            self.chain.light_state().kernel.header.height,
-            receiver_digest,
-        );
+        let block_height = self.chain.light_state().kernel.header.height;
+        let mut nonce = 0;
+        let change_sender_randomness = loop {
+            let sender_randomness = self
+                .wallet_state
+                .wallet_secret
+                .generate_sender_randomness_with_nonce(block_height, receiver_digest, nonce);
+            if !sender_randomness_filter.contains(sender_randomness) {
+                break sender_randomness;
+            }
+            debug!("Change sender randomness with nonce {nonce} was used before; deriving anew");
+            nonce += 1;
+        };
         let change_addition_record = commit(
             Hash::hash(&change_utxo),
             change_sender_randomness,
@@ -479,6 +980,7 @@ impl GlobalState {
                 UtxoNotifier::Myself,
             )
             .expect("Adding change UTXO to UTXO notification pool must succeed");
+        sender_randomness_filter.insert(change_sender_randomness);
 
         (change_addition_record, change_utxo)
     }
@@ -531,15 +1033,42 @@ impl GlobalState {
     ///
     /// Returns the transaction and a vector containing the sender
     /// randomness for each output UTXO.
+    ///
+    /// Fails with a [`SenderRandomnessReuseError`] if the sender randomness
+    /// of any of the receivers was used for a recent output of this wallet,
+    /// or for another receiver of this transaction.
+    /// The caller should then derive fresh sender randomness, e.g. with
+    /// [`WalletSecret::generate_sender_randomness_with_nonce`], and retry.
+    ///
+    /// [`SenderRandomnessReuseError`]: wallet::sender_randomness_filter::SenderRandomnessReuseError
+    /// [`WalletSecret::generate_sender_randomness_with_nonce`]: wallet::WalletSecret::generate_sender_randomness_with_nonce
     pub async fn create_transaction(
         &mut self,
         receiver_data: Vec<UtxoReceiverData>,
         fee: NeptuneCoins,
         timestamp: Timestamp,
     ) -> Result<Transaction> {
+        // Refuse to reuse sender randomness before any change is derived. The
+        // filter is persisted once the transaction is created.
+        let mut sender_randomness_filter = self
+            .wallet_state
+            .reserve_sender_randomness(receiver_data.iter().map(|rd| rd.sender_randomness))
+            .await?;
+
+        // Membership proofs that were dropped must be restored before they can
+        // be spent. UTXOs whose proofs cannot be restored are not spent.
+        if let Err(err) = self.restore_membership_proofs_from_archival_state().await {
+            warn!("Could not restore membership proofs from the archival state: {err}");
+        }
+
         // UTXO data: inputs, outputs, and supporting witness data
         let (inputs, spendable_utxos_and_mps, outputs, output_utxos) = self
-            .generate_utxo_data_for_transaction(&receiver_data, fee, timestamp)
+            .generate_utxo_data_for_transaction(
+                &receiver_data,
+                fee,
+                timestamp,
+                &mut sender_randomness_filter,
+            )
             .await?;
 
         // other data
@@ -563,7 +1092,7 @@ impl GlobalState {
             .nth_generation_spending_key(0);
 
         // assemble transaction object (lengthy operation)
-        Self::create_transaction_from_data(
+        let transaction = Self::create_transaction_from_data(
             spending_key,
             inputs,
             spendable_utxos_and_mps,
@@ -575,7 +1104,48 @@ impl GlobalState {
             mutator_set_accumulator,
             privacy,
         )
-        .await
+        .await?;
+
+        self.wallet_state
+            .store_sender_randomness_filter(sender_randomness_filter)
+            .await;
+        self.wallet_state
+            .register_outputs_to_watched_addresses(
+                &Self::generate_addition_records(&receiver_data)
+                    .into_iter()
+                    .zip(receiver_data.iter().map(|rd| rd.utxo.clone()))
+                    .collect_vec(),
+            )
+            .await;
+
+        Ok(transaction)
+    }
+
+    /// Like [`Self::create_transaction`], but additionally attaches the label,
+    /// if any, to the created transaction. The label is stored in the wallet
+    /// and never broadcast.
+    pub async fn create_transaction_with_label(
+        &mut self,
+        receiver_data: Vec<UtxoReceiverData>,
+        fee: NeptuneCoins,
+        timestamp: Timestamp,
+        label: Option<String>,
+    ) -> Result<Transaction> {
+        // reject the label before the wallet commits to the transaction
+        if let Some(label) = &label {
+            validate_label(label)?;
+        }
+        let transaction = self
+            .create_transaction(receiver_data, fee, timestamp)
+            .await?;
+
+        if let Some(label) = label {
+            self.wallet_state
+                .set_transaction_label(&transaction.kernel, label)
+                .await?;
+        }
+
+        Ok(transaction)
     }
 
     /// Given a list of UTXOs with receiver data, assemble owned and synced and spendable
@@ -587,6 +1157,7 @@ impl GlobalState {
         receiver_data: &[UtxoReceiverData],
         fee: NeptuneCoins,
         timestamp: Timestamp,
+        sender_randomness_filter: &mut SenderRandomnessFilter,
     ) -> Result<(
         Vec<RemovalRecord>,
         Vec<(Utxo, LockScript, MsMembershipProof)>,
@@ -627,7 +1198,9 @@ impl GlobalState {
         // keep track of change (if any)
         if total_spend < input_amount {
             let change_amount = input_amount.checked_sub(&total_spend).unwrap();
-            let (change_addition_record, change_utxo) = self.add_change(change_amount).await;
+            let (change_addition_record, change_utxo) = self
+                .add_change(change_amount, sender_randomness_filter)
+                .await;
             outputs.push(change_addition_record);
             output_utxos.push(change_utxo.clone());
         }
@@ -723,20 +1296,280 @@ impl GlobalState {
         }
     }
 
+    /// Return the stored standing of the IP, or the default standing if its
+    /// latest sanction is older than the ban duration.
+    pub async fn peer_standing(&self, ip: IpAddr) -> PeerStanding {
+        self.net
+            .get_peer_standing_from_database(ip)
+            .await
+            .as_of(self.cli.peer_ban_duration(), SystemTime::now())
+    }
+
+    /// Decide whether to accept the inbound connection from the given address
+    /// before any handshake state is allocated for it, and if so, count it
+    /// towards the limits per host and subnet until it is released. It is
+    /// refused if the host or subnet of the peer already holds too many of the
+    /// inbound connections, including those that are still handshaking.
+    /// Connections from the loopback interface are exempt.
+    pub fn accept_inbound_connection(&mut self, peer_address: SocketAddr) -> bool {
+        if !peer_address.ip().to_canonical().is_loopback() {
+            let (from_ip, from_subnet) = self.net.inbound_peer_counts_from(peer_address.ip());
+            if from_ip >= self.cli.max_connections_per_ip as usize
+                || from_subnet >= self.cli.max_connections_per_subnet as usize
+            {
+                return false;
+            }
+        }
+
+        self.net.register_inbound_connection(peer_address);
+        true
+    }
+
+    /// Store the standing of the IP if it is worse than the stored one, see
+    /// [NetworkingState::write_peer_standing_on_decrease].
+    pub async fn write_peer_standing_on_decrease(&mut self, ip: IpAddr, standing: PeerStanding) {
+        let ban_duration = self.cli.peer_ban_duration();
+        self.net
+            .write_peer_standing_on_decrease(ip, standing, ban_duration)
+            .await
+    }
+
+    /// Garbage collect the peer databases, see
+    /// [NetworkingState::garbage_collect_peer_databases]. Active bans and the
+    /// peers given with `--peers` are kept. Returns None, and keeps all
+    /// entries, if no retention window is configured.
+    pub async fn garbage_collect_peer_databases(
+        &mut self,
+        now: SystemTime,
+    ) -> Option<PeerDatabaseGcReport> {
+        let retention = self.cli.peer_database_retention()?;
+        let ban_duration = self.cli.peer_ban_duration();
+        let peer_tolerance = self.cli.peer_tolerance;
+        let report = self
+            .net
+            .garbage_collect_peer_databases(
+                now,
+                retention,
+                &self.cli.peer_addresses(),
+                |standing| standing.as_of(ban_duration, now).standing < -(peer_tolerance as i32),
+            )
+            .await;
+        info!(
+            "Removed {} peer standings, {} known peers, and {} address book entries not updated in {} days",
+            report.peer_standings_removed,
+            report.known_peers_removed,
+            report.addresses_removed,
+            self.cli.peer_database_retention
+        );
+        self.net.latest_peer_database_gc = Some(report);
+
+        Some(report)
+    }
+
+    /// Record whether this node has connected peers, and warn once it has
+    /// been without peers for longer than the configured threshold. Returns
+    /// the time since which it has been peerless, if for too long.
+    pub fn check_peer_count(&mut self, now: SystemTime) -> Option<SystemTime> {
+        let threshold = self.cli.peerless_warning_threshold()?;
+        if self.net.record_peer_count(now, threshold) {
+            warn!(
+                "This node has had no peers for more than {} minutes. Check its network connection and the addresses given with --peers.",
+                self.cli.peerless_warning_threshold
+            );
+        }
+
+        self.peerless_alert(now)
+    }
+
+    /// The time since which this node has been without peers, if it has been
+    /// for longer than the configured threshold.
+    pub fn peerless_alert(&self, now: SystemTime) -> Option<SystemTime> {
+        self.net
+            .peerless_alert(now, self.cli.peerless_warning_threshold()?)
+    }
+
+    /// Return the listen addresses of up to `limit` peers that this node
+    /// completed a handshake with, most recently seen first, for the node to
+    /// reconnect to on startup. Peers that are banned, either via the CLI or
+    /// because of their standing, are excluded.
+    pub async fn get_bootstrap_peers(&self, limit: usize) -> Vec<SocketAddr> {
+        let mut known_peers = self.net.all_known_peers_in_database().await;
+        known_peers.sort_by_key(|(_listen_address, known_peer)| Reverse(known_peer.last_seen));
+
+        let mut bootstrap_peers = vec![];
+        for (listen_address, _known_peer) in known_peers {
+            if bootstrap_peers.len() >= limit {
+                break;
+            }
+
+            let ip = listen_address.ip();
+            if self.cli.ban.contains(&ip) {
+                continue;
+            }
+            let standing = self.peer_standing(ip).await;
+            if standing.standing < -(self.cli.peer_tolerance as i32) {
+                continue;
+            }
+
+            bootstrap_peers.push(listen_address);
+        }
+
+        bootstrap_peers
+    }
+
+    /// Pick an address from the address book to dial when below the target
+    /// number of outbound peers, see [address_book::select_address_to_dial].
+    /// Addresses of connected peers, addresses that lead back to this node or
+    /// are backing off, and banned addresses are skipped. The standing is
+    /// only looked up for the picked address, and another one is picked if
+    /// it is banned.
+    pub async fn select_address_to_dial<R: Rng>(&self, rng: &mut R) -> Option<SocketAddr> {
+        let now = SystemTime::now();
+        let connected_addresses: HashSet<SocketAddr> = self
+            .net
+            .peer_map
+            .values()
+            .flat_map(|peer| [Some(peer.connected_address), peer.listen_address()])
+            .flatten()
+            .collect();
+
+        let mut candidates = self
+            .net
+            .all_addresses_in_address_book()
+            .await
+            .into_iter()
+            .filter(|(address, _record)| {
+                !connected_addresses.contains(address)
+                    && !self.net.do_not_dial.contains(address)
+                    && self.net.may_dial(*address, now)
+                    && !self.cli.ban.contains(&address.ip())
+            })
+            .collect_vec();
+        let outbound_peers_per_bucket = self.net.outbound_peers_per_bucket();
+        loop {
+            let address = address_book::select_address_to_dial(
+                candidates.clone(),
+                &outbound_peers_per_bucket,
+                rng,
+            )?;
+            let standing = self.peer_standing(address.ip()).await;
+            if standing.standing >= -(self.cli.peer_tolerance as i32) {
+                return Some(address);
+            }
+
+            candidates.retain(|(candidate, _record)| candidate.ip() != address.ip());
+        }
+    }
+
     pub async fn get_own_handshakedata(&self) -> HandshakeData {
         HandshakeData {
             tip_header: self.chain.light_state().header().clone(),
             // TODO: Should be `None` if incoming connections are not accepted
-            listen_port: Some(self.cli().peer_port),
+            listen_port: self.cli().advertised_listen_port(),
             network: self.cli().network,
             instance_id: self.net.instance_id,
             version: VERSION.to_string(),
-            // For now, all nodes are archival nodes
-            is_archival_node: self.chain.is_archival_node(),
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_SUPPORTED_PROTOCOL_VERSION,
+            features: self.own_peer_features(),
         }
     }
 
-    /// In case the wallet database is corrupted or deleted, this method will restore
+    /// The features announced to peers in the handshake
+    pub fn own_peer_features(&self) -> PeerFeatures {
+        let mut features = PeerFeatures::TRANSACTION_RELAY
+            .with(PeerFeatures::PING)
+            .with(PeerFeatures::BLOCK_SERVING_QUOTA)
+            .with(PeerFeatures::TRANSACTION_FEE_NOTIFICATION);
+        if self.cli.peer_codec == PeerCodec::Json {
+            features = features.with(PeerFeatures::JSON_CODEC);
+        }
+        if self.chain.is_archival_node() {
+            features
+                .with(PeerFeatures::ARCHIVAL)
+                .with(PeerFeatures::BLOCK_BATCH_BY_HEIGHT)
+        } else {
+            features
+        }
+    }
+
+    /// Restore the membership proofs of unspent monitored UTXOs that were moved
+    /// to archival-restore mode, from the archival mutator set. Returns the
+    /// number of restored membership proofs. UTXOs whose proof cannot be
+    /// restored are skipped and stay in archival-restore mode.
+    ///
+    /// Restored membership proofs are maintained with every block again, until
+    /// they are dropped anew after the next block, if they remain too large.
+    pub async fn restore_membership_proofs_from_archival_state(&mut self) -> Result<usize> {
+        let mut archival_restore_utxos = self
+            .wallet_state
+            .wallet_db
+            .get_archival_restore_utxos()
+            .await;
+        if archival_restore_utxos.is_empty() {
+            return Ok(0);
+        }
+
+        let tip_hash = self.chain.light_state().hash();
+        let ams_ref = &self.chain.archival_state().archival_mutator_set;
+        if ams_ref.get_sync_label().await != tip_hash {
+            bail!("Archival mutator set must be synced to tip to restore membership proofs");
+        }
+
+        let monitored_utxos = self.wallet_state.wallet_db.monitored_utxos();
+        let mut restored_mutxos = vec![];
+        for (&i, recovery_data) in archival_restore_utxos.iter() {
+            let mut monitored_utxo = monitored_utxos.get(i).await;
+            if monitored_utxo.spent_in_block.is_some() || monitored_utxo.abandoned_at.is_some() {
+                continue;
+            }
+
+            let item = Hash::hash(&recovery_data.utxo);
+            let membership_proof = match ams_ref
+                .ams()
+                .restore_membership_proof(
+                    item,
+                    recovery_data.sender_randomness,
+                    recovery_data.receiver_preimage,
+                    recovery_data.aocl_index,
+                )
+                .await
+            {
+                Ok(membership_proof) => membership_proof,
+                Err(err) => {
+                    warn!("Could not restore membership proof of monitored UTXO {i}. Got: {err}");
+                    continue;
+                }
+            };
+            if !ams_ref.ams().verify(item, &membership_proof).await {
+                warn!("Restored membership proof of monitored UTXO {i} is invalid. Maybe this UTXO is on an abandoned chain?");
+                continue;
+            }
+
+            monitored_utxo.add_membership_proof_for_tip(tip_hash, membership_proof);
+            restored_mutxos.push((i, monitored_utxo));
+        }
+
+        let restored_count = restored_mutxos.len();
+        for (i, _) in restored_mutxos.iter() {
+            archival_restore_utxos.remove(i);
+        }
+        self.wallet_state
+            .wallet_db
+            .monitored_utxos_mut()
+            .set_many(restored_mutxos)
+            .await;
+        self.wallet_state
+            .wallet_db
+            .set_archival_restore_utxos(archival_restore_utxos)
+            .await;
+        self.wallet_state.wallet_db.persist().await;
+        info!("Restored {restored_count} membership proofs from the archival state");
+
+        Ok(restored_count)
+    }
+
+    /// In case the wallet database is corrupted or deleted, this method will restore
     /// monitored UTXO data structures from recovery data. This method should only be
     /// called on startup, not while the program is running, since it will only restore
     /// a wallet state, if the monitored UTXOs have been deleted. Not merely if they
@@ -761,6 +1594,11 @@ impl GlobalState {
         // monitored UTXO in the database. All monitored UTXOs are fetched outside
         // of the loop to avoid DB access/IO inside the loop.
         let mut recovery_data_for_missing_mutxos = vec![];
+        let archival_restore_utxos = self
+            .wallet_state
+            .wallet_db
+            .get_archival_restore_utxos()
+            .await;
 
         {
             let stream = self
@@ -772,6 +1610,14 @@ impl GlobalState {
             pin_mut!(stream); // needed for iteration
 
             '_outer: for incoming_utxo in incoming_utxos.into_iter() {
+                // UTXOs whose membership proof was dropped are in the database too
+                if archival_restore_utxos.values().any(|recovery_data| {
+                    recovery_data.utxo == incoming_utxo.utxo
+                        && recovery_data.aocl_index == incoming_utxo.aocl_index
+                }) {
+                    continue '_outer;
+                }
+
                 'inner: while let Some(monitored_utxo) = stream.next().await {
                     if monitored_utxo.utxo == incoming_utxo.utxo {
                         let msmp_res = monitored_utxo.get_latest_membership_proof_entry();
@@ -807,7 +1653,7 @@ impl GlobalState {
             "Attempting to restore {} missing monitored UTXOs to wallet database",
             recovery_data_for_missing_mutxos.len()
         );
-        let current_aocl_leaf_count = ams_ref.ams().aocl.count_leaves().await;
+        let current_aocl_leaf_count = ams_ref.ams().aocl_leaf_count().await;
         let mut restored_mutxos = 0;
         for incoming_utxo in recovery_data_for_missing_mutxos {
             // If the referenced UTXO is in the future from our tip, do not attempt to recover it. Instead: warn the user of this.
@@ -863,21 +1709,36 @@ impl GlobalState {
         tip_hash: Digest,
     ) -> Result<()> {
         // loop over all monitored utxos
+        let archival_restore_utxos = self
+            .wallet_state
+            .wallet_db
+            .get_archival_restore_utxos()
+            .await;
         let monitored_utxos = self.wallet_state.wallet_db.monitored_utxos_mut();
 
         'outer: for i in 0..monitored_utxos.len().await {
             let i = i as Index;
             let monitored_utxo = monitored_utxos.get(i).await;
 
-            // Ignore those MUTXOs that were marked as abandoned
-            if monitored_utxo.abandoned_at.is_some() {
+            // Ignore those MUTXOs whose membership proofs are restored from
+            // the archival state when needed
+            if archival_restore_utxos.contains_key(&i) {
                 continue;
             }
 
-            // ignore synced ones
-            if monitored_utxo.is_synced_to(tip_hash) {
-                continue;
-            }
+            // Ignore those MUTXOs that were marked as abandoned, synced ones,
+            // and unconfirmed ones, for which there is no point in
+            // synchronizing the membership proof.
+            let (confirming_block_digest, confirming_block_height) =
+                match monitored_utxo.sync_status(tip_hash) {
+                    MembershipProofSyncStatus::Stale {
+                        confirming_block_digest,
+                        confirming_block_height,
+                    } => (confirming_block_digest, confirming_block_height),
+                    MembershipProofSyncStatus::Abandoned
+                    | MembershipProofSyncStatus::Synced
+                    | MembershipProofSyncStatus::Unconfirmed => continue,
+                };
 
             debug!(
                 "Resyncing monitored UTXO number {}, with hash {}",
@@ -885,18 +1746,6 @@ impl GlobalState {
                 Hash::hash(&monitored_utxo.utxo)
             );
 
-            // If the UTXO was not confirmed yet, there is no
-            // point in synchronizing its membership proof.
-            let (confirming_block_digest, confirming_block_height) =
-                match monitored_utxo.confirmed_in_block {
-                    Some((confirmed_block_hash, _timestamp, block_height)) => {
-                        (confirmed_block_hash, block_height)
-                    }
-                    None => {
-                        continue;
-                    }
-                };
-
             // try latest (block hash, membership proof) entry
             let (block_hash, mut membership_proof) = monitored_utxo
                 .get_latest_membership_proof_entry()
@@ -1103,6 +1952,120 @@ impl GlobalState {
         Ok(removed_count)
     }
 
+    /// Insert a transaction relayed by a peer thread into the mempool, unless
+    /// the tip changed since the peer thread found the transaction to be
+    /// confirmable. Returns true if the transaction is new to the mempool, in
+    /// which case it should be announced to peers.
+    pub fn insert_relayed_transaction(&mut self, relayed: &PeerThreadToMainTransaction) -> bool {
+        if relayed.confirmable_for_block != self.chain.light_state().hash() {
+            warn!("main loop got unmined transaction with bad mutator set data, discarding transaction");
+            return false;
+        }
+
+        // Several peers may relay the same transaction
+        let transaction_id = Hash::hash(&relayed.transaction);
+        if self.mempool.contains(transaction_id) {
+            return false;
+        }
+
+        if let Err(rejection) = self.mempool.insert(&relayed.transaction) {
+            warn!("Not inserting relayed transaction {transaction_id} into mempool: {rejection}");
+            return false;
+        }
+
+        // The transaction may have been evicted right away if the mempool is full
+        self.mempool.contains(transaction_id)
+    }
+
+    /// Store the transactions in the mempool in the data directory, such that
+    /// they survive a restart.
+    pub async fn persist_mempool(&self) -> Result<()> {
+        let mempool_file_path = self.chain.archival_state().data_dir().mempool_file_path();
+        self.mempool.write_to_file(&mempool_file_path).await?;
+        debug!(
+            "Stored {} mempool transactions in {}",
+            self.mempool.len(),
+            mempool_file_path.display()
+        );
+
+        Ok(())
+    }
+
+    /// The height up to and including which the transaction witnesses of
+    /// stored blocks are to be dropped, such that the blocks buried under at
+    /// least `--witness-prune-depth` blocks are pruned. None if witnesses are
+    /// not pruned.
+    fn witness_prune_target(&self) -> Option<BlockHeight> {
+        let witness_prune_depth = self.cli.witness_prune_depth?;
+        if !self.chain.is_archival_node() {
+            return None;
+        }
+
+        let tip_height: u64 = self.chain.light_state().kernel.header.height.into();
+        tip_height
+            .checked_sub(witness_prune_depth as u64)
+            .map(BlockHeight::from)
+    }
+
+    /// Compare the tips announced by the connected peers to the canonical
+    /// chain, and raise or clear the chain split alert accordingly. Returns
+    /// the alert if a chain split is suspected. Only archival nodes can tell
+    /// whether a tip belongs to the canonical chain.
+    pub async fn check_for_chain_split(&mut self, now: SystemTime) -> Option<ChainSplitAlert> {
+        let (tip_digest, tip_height, canonical_digests) = self.canonical_claimed_digests().await?;
+
+        self.net
+            .chain_split_detector
+            .evaluate(tip_digest, tip_height, &canonical_digests, now)
+            .cloned()
+    }
+
+    /// The tip, and the digests claimed by peers that are part of the
+    /// canonical chain. The chain is walked once from the tip down to the
+    /// lowest claimed block this node knows of, rather than once per claim.
+    /// Returns `None` for non-archival nodes.
+    async fn canonical_claimed_digests(&self) -> Option<(Digest, BlockHeight, HashSet<Digest>)> {
+        if !self.chain.is_archival_node() {
+            return None;
+        }
+
+        let tip_digest = self.chain.light_state().hash();
+        let tip_height = self.chain.light_state().header().height;
+        let archival_state = self.chain.archival_state();
+        let claimed_digests = self.net.chain_split_detector.claimed_digests();
+        let mut lowest_claimed_height = None;
+        for digest in claimed_digests.iter() {
+            if let Some(header) = archival_state.get_block_header(*digest).await {
+                lowest_claimed_height = Some(
+                    lowest_claimed_height
+                        .map_or(header.height, |height| min(height, header.height)),
+                );
+            }
+        }
+
+        let mut canonical_digests = HashSet::new();
+        let Some(lowest_claimed_height) = lowest_claimed_height else {
+            return Some((tip_digest, tip_height, canonical_digests));
+        };
+        let mut digest = tip_digest;
+        let mut header = self.chain.light_state().header().clone();
+        loop {
+            if claimed_digests.contains(&digest) {
+                canonical_digests.insert(digest);
+            }
+            if header.height <= lowest_claimed_height {
+                break;
+            }
+            digest = header.prev_block_digest;
+            match archival_state.get_block_header(digest).await {
+                Some(parent_header) => header = parent_header,
+                None => break,
+            }
+        }
+
+        Some((tip_digest, tip_height, canonical_digests))
+    }
+
     pub async fn flush_databases(&mut self) -> Result<()> {
         // flush wallet databases
         self.wallet_state.wallet_db.persist().await;
@@ -1124,14 +2087,212 @@ impl GlobalState {
             .persist()
             .await;
 
-        // flush peer_standings
+        // flush peer_standings and known_peers
         self.net.peer_databases.peer_standings.flush().await;
+        self.net.peer_databases.known_peers.flush().await;
 
         debug!("Flushed all databases");
 
         Ok(())
     }
 
+    /// Quiesce the node for maintenance, e.g. a disk snapshot. Sets the flag
+    /// that defers mining, the periodic jobs, and state-changing peer
+    /// messages, and writes the mempool and all databases to disk.
+    ///
+    /// Blocks are applied under the write lock, so holding `&mut self`
+    /// guarantees that no block application is in flight.
+    pub async fn quiesce(&mut self) -> Result<()> {
+        self.net.quiesced = true;
+        self.persist_mempool().await?;
+        self.flush_databases().await
+    }
+
+    /// Undo [GlobalState::quiesce]. Returns false if the node was not
+    /// quiesced.
+    pub fn resume(&mut self) -> bool {
+        std::mem::replace(&mut self.net.quiesced, false)
+    }
+
+    /// Prepare the wallet for a new tip that belongs to another branch than the
+    /// block the wallet is synced to. Reverts every block on the abandoned
+    /// branch, then applies the stored blocks of the new branch up to the new
+    /// tip's parent. A no-op if the new tip is a child of the wallet's block.
+    ///
+    /// Must be called before `new_block` is set as tip.
+    pub async fn switch_wallet_to_branch_of(&mut self, new_block: &Block) -> Result<()> {
+        let wallet_sync_label = self.wallet_state.wallet_db.get_sync_label().await;
+        let new_parent_digest = new_block.kernel.header.prev_block_digest;
+        if wallet_sync_label == new_parent_digest {
+            return Ok(());
+        }
+
+        let (backwards, _luca, forwards) = self
+            .chain
+            .archival_state()
+            .find_path(wallet_sync_label, new_parent_digest)
+            .await;
+
+        for digest in backwards {
+            let Some(abandoned_block) = self.chain.archival_state().get_block(digest).await? else {
+                bail!("Abandoned block {digest} must be stored in order to revert it");
+            };
+            info!(
+                "Reverting block {digest} at height {} from wallet",
+                abandoned_block.kernel.header.height
+            );
+            self.wallet_state.revert_block(&abandoned_block).await?;
+        }
+
+        for digest in forwards {
+            let archival_state = self.chain.archival_state();
+            let Some(block) = archival_state.get_block(digest).await? else {
+                bail!("Block {digest} on new branch must be stored in order to apply it");
+            };
+            let Some(parent) = archival_state
+                .get_block(block.kernel.header.prev_block_digest)
+                .await?
+            else {
+                bail!("Parent of block {digest} on new branch must be stored");
+            };
+            self.wallet_state
+                .update_wallet_state_with_new_block(
+                    &parent.kernel.body.mutator_set_accumulator,
+                    &block,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Rescan the canonical blocks that the wallet has not seen, e.g. because
+    /// it was imported into a data directory that already holds the
+    /// blockchain. Wallets on an abandoned fork are left to the regular fork
+    /// handling. Returns the number of replayed blocks.
+    pub async fn rescan_wallet_if_behind(&mut self) -> Result<usize> {
+        if !self.chain.is_archival_node() {
+            return Ok(0);
+        }
+
+        let tip_digest = self.chain.light_state().hash();
+        let sync_label = self.wallet_state.wallet_db.get_sync_label().await;
+        if sync_label == tip_digest {
+            return Ok(0);
+        }
+
+        let archival_state = self.chain.archival_state();
+        let Some(sync_header) = archival_state.get_block_header(sync_label).await else {
+            warn!("Wallet is synced to unknown block {sync_label}");
+            return Ok(0);
+        };
+        if !archival_state
+            .block_belongs_to_canonical_chain(sync_label, tip_digest)
+            .await
+        {
+            return Ok(0);
+        }
+
+        info!(
+            "Wallet is synced to height {}, rescanning the following blocks",
+            sync_header.height
+        );
+        self.rescan_wallet_from_height(sync_header.height.next())
+            .await
+    }
+
+    /// Rebuild the wallet state by replaying the canonical blocks from
+    /// `start_height` up to the tip. The wallet's sync label is first reset to
+    /// the parent of the block at `start_height`, and when starting from
+    /// genesis, the premine UTXOs of this wallet are expected anew. Membership
+    /// proofs of UTXOs received before `start_height` are resynced afterwards.
+    ///
+    /// Known UTXOs are not deduplicated, so monitored UTXOs confirmed at or
+    /// after `start_height` must be removed before rescanning. Refuses to run
+    /// while mining. Returns the number of replayed blocks.
+    pub async fn rescan_wallet_from_height(&mut self, start_height: BlockHeight) -> Result<usize> {
+        if self.mining {
+            bail!("Cannot rescan the wallet while mining");
+        }
+
+        let tip_digest = self.chain.light_state().hash();
+        let tip_height = self.chain.light_state().kernel.header.height;
+        if start_height > tip_height {
+            bail!(
+                "Cannot rescan the wallet from height {start_height} above tip height {tip_height}"
+            );
+        }
+
+        {
+            let monitored_utxos = self.wallet_state.wallet_db.monitored_utxos();
+            let stream = monitored_utxos.stream_values().await;
+            pin_mut!(stream); // needed for iteration
+
+            while let Some(monitored_utxo) = stream.next().await {
+                if matches!(monitored_utxo.confirmed_in_block, Some((_, _, height)) if height >= start_height)
+                {
+                    bail!("Wallet already monitors a UTXO confirmed at or after height {start_height}");
+                }
+            }
+        }
+
+        let (parent_digest, mut previous_mutator_set_accumulator) = if start_height.is_genesis() {
+            self.wallet_state.expect_premine_utxos(self.cli.network);
+            (Digest::default(), MutatorSetAccumulator::default())
+        } else {
+            let archival_state = self.chain.archival_state();
+            let Some(parent_digest) = archival_state
+                .block_height_to_canonical_block_digest(start_height.previous(), tip_digest)
+                .await
+            else {
+                bail!("No canonical block at height {}", start_height.previous());
+            };
+            let Some(parent) = archival_state.get_block(parent_digest).await? else {
+                bail!("Canonical block {parent_digest} must be stored in order to rescan from it");
+            };
+            (parent_digest, parent.kernel.body.mutator_set_accumulator)
+        };
+        self.wallet_state
+            .wallet_db
+            .set_sync_label(parent_digest)
+            .await;
+
+        let mut block_count = 0;
+        let mut height = start_height;
+        while height <= tip_height {
+            let archival_state = self.chain.archival_state();
+            let Some(digest) = archival_state
+                .block_height_to_canonical_block_digest(height, tip_digest)
+                .await
+            else {
+                bail!("No canonical block at height {height}");
+            };
+            let Some(block) = archival_state.get_block(digest).await? else {
+                bail!("Canonical block {digest} must be stored in order to rescan it");
+            };
+            self.wallet_state
+                .update_wallet_state_with_new_block(&previous_mutator_set_accumulator, &block)
+                .await?;
+            previous_mutator_set_accumulator = block.kernel.body.mutator_set_accumulator;
+            block_count += 1;
+            height = height.next();
+        }
+
+        // Blocks that do not affect an empty wallet leave its sync label
+        // untouched, and UTXOs received before `start_height` may lack a
+        // membership proof for the block the rescan started from.
+        self.wallet_state.wallet_db.set_sync_label(tip_digest).await;
+        if !self.wallet_state.is_synced_to(tip_digest).await {
+            self.resync_membership_proofs_from_stored_blocks(tip_digest)
+                .await?;
+        }
+        self.wallet_state.wallet_db.persist().await;
+
+        info!("Rescanned {block_count} blocks into the wallet from height {start_height}");
+
+        Ok(block_count)
+    }
+
     /// Update client's state with a new block. Block is assumed to be valid, also wrt. to PoW.
     /// The received block will be set as the new tip, regardless of its accumulated PoW.
     pub async fn set_new_tip(&mut self, new_block: Block) -> Result<()> {
@@ -1146,8 +2307,12 @@ impl GlobalState {
         new_block: Block,
         coinbase_utxo_info: ExpectedUtxo,
     ) -> Result<()> {
+        let new_block_hash = new_block.hash();
         self.set_new_tip_internal(new_block, Some(coinbase_utxo_info))
-            .await
+            .await?;
+        self.net.record_mined_block(new_block_hash);
+
+        Ok(())
     }
 
     /// Update client's state with a new block. Block is assumed to be valid, also wrt. to PoW.
@@ -1165,6 +2330,23 @@ impl GlobalState {
             new_block: Block,
             coinbase_utxo_info: Option<ExpectedUtxo>,
         ) -> Result<()> {
+            // Determine whether the new block belongs to another branch than the current tip
+            let old_tip_digest = myself.chain.light_state().hash();
+            let new_parent_digest = new_block.kernel.header.prev_block_digest;
+            let reorg = if old_tip_digest != new_parent_digest {
+                let (disconnected, _luca, _forwards) = myself
+                    .chain
+                    .archival_state()
+                    .find_path(old_tip_digest, new_parent_digest)
+                    .await;
+                (!disconnected.is_empty()).then(|| ChainEvent::Reorg {
+                    disconnected,
+                    new_tip: new_block.hash(),
+                })
+            } else {
+                None
+            };
+
             // Apply the updates
             myself
                 .chain
@@ -1219,6 +2401,39 @@ impl GlobalState {
                 .update_wallet_state_with_new_block(&previous_ms_accumulator, &new_block)
                 .await?;
 
+            // Notify wallet of reorganization, such that it can discard invalidated membership
+            // proofs, and resync if configured to do so.
+            if let Some(chain_event) = reorg {
+                let needs_resync = myself.wallet_state.handle_chain_event(&chain_event).await;
+                if needs_resync && myself.cli.resync_on_reorg {
+                    myself
+                        .resync_membership_proofs_from_stored_blocks(new_block.hash())
+                        .await?;
+                }
+            }
+
+            // Stop maintaining membership proofs whose chunk dictionaries grew too large, if so
+            // configured. Archival nodes can restore them when they are needed.
+            if let Some(max_entries) = myself.cli.max_chunk_dictionary_entries {
+                if myself.chain.is_archival_node() {
+                    let moved_count = myself
+                        .wallet_state
+                        .move_to_archival_restore_mode(max_entries)
+                        .await;
+                    if moved_count > 0 {
+                        info!("Dropped {moved_count} membership proofs with more than {max_entries} chunk dictionary entries");
+                    }
+                }
+            }
+            let statistics = myself.wallet_state.chunk_dictionary_statistics().await;
+            debug!(
+                "Chunk dictionary entries per membership proof: average {:.2}, max {}, over {} proofs; {} proofs restored on demand",
+                statistics.average_entries(),
+                statistics.max_entries,
+                statistics.proof_count,
+                statistics.archival_restore_count
+            );
+
             // Update mempool with UTXOs from this block. This is done by removing all transaction
             // that became invalid/was mined by this block.
             myself
@@ -1282,15 +2497,35 @@ mod global_state_tests {
         config_models::network::Network,
         models::{blockchain::block::Block, state::wallet::utxo_notification_pool::UtxoNotifier},
         tests::shared::{
-            add_block_to_light_state, make_mock_block, make_mock_block_with_valid_pow,
-            mock_genesis_global_state, mock_genesis_wallet_state,
+            add_block_to_light_state, assert_no_sender_randomness_reuse, make_mock_block,
+            make_mock_block_with_valid_pow, make_mock_transaction, mock_genesis_global_state,
+            mock_genesis_wallet_state,
         },
     };
     use num_traits::{One, Zero};
     use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
     use tracing_test::traced_test;
 
-    use super::{wallet::WalletSecret, *};
+    use super::{
+        wallet::{sender_randomness_filter::SenderRandomnessReuseError, WalletSecret},
+        *,
+    };
+    use crate::models::consensus::mast_hash::MastHash;
+    use crate::models::peer::{KnownPeer, PeerSanctionReason, PeerStanding};
+    use crate::models::state::address_book::{
+        AddressRecord, ADDRESS_BOOK_CAPACITY, ADDRESS_BOOK_MAX_FAILURES,
+    };
+    use crate::models::state::networking_state::{
+        DIAL_BACKOFF_CAP, DIAL_BACKOFF_RETENTION, KNOWN_PEERS_CAPACITY, TRANSACTION_REQUEST_TIMEOUT,
+    };
+    use crate::models::state::wallet::monitored_utxo::{
+        ProofAuditEntry, ProofAuditFinding, ProofRepair,
+    };
+    use crate::models::state::wallet::watched_address::{WalletEvent, WatchedAddressSighting};
+    use crate::socks5::Socks5Target;
+    use crate::tests::shared::{get_dummy_socket_address, get_peer_map, unit_test_data_directory};
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, SystemTime};
 
     async fn wallet_state_has_all_valid_mps_for(
         wallet_state: &WalletState,
@@ -1327,11 +2562,28 @@ mod global_state_tests {
         timestamp: Timestamp,
     ) -> Result<Transaction> {
         // UTXO data: inputs, outputs, and supporting witness data
-        let (inputs, spendable_utxos_and_mps, outputs, output_utxos) = global_state_lock
-            .lock_guard_mut()
-            .await
-            .generate_utxo_data_for_transaction(receiver_data, fee, timestamp)
-            .await?;
+        let (inputs, spendable_utxos_and_mps, outputs, output_utxos) = {
+            let mut global_state = global_state_lock.lock_guard_mut().await;
+            let mut sender_randomness_filter = global_state
+                .wallet_state
+                .wallet_db
+                .get_sender_randomness_filter()
+                .await;
+            let utxo_data = global_state
+                .generate_utxo_data_for_transaction(
+                    receiver_data,
+                    fee,
+                    timestamp,
+                    &mut sender_randomness_filter,
+                )
+                .await?;
+            global_state
+                .wallet_state
+                .store_sender_randomness_filter(sender_randomness_filter)
+                .await;
+
+            utxo_data
+        };
 
         // other data
         let public_announcements = receiver_data
@@ -1442,6 +2694,22 @@ mod global_state_tests {
         // assert!(!tx.is_valid());
         tx.kernel.timestamp = tx.kernel.timestamp + Timestamp::months(2);
 
+        // the transaction must also pass the full block validation performed
+        // when a block containing it is received
+        let mut rng = thread_rng();
+        let (mut block_1, _, _) =
+            make_mock_block(network, &genesis_block, None, recipient_address, rng.gen());
+        block_1
+            .accumulate_transaction(
+                tx.clone(),
+                &genesis_block.kernel.body.mutator_set_accumulator,
+            )
+            .await;
+        assert!(
+            block_1.is_valid(&genesis_block, network),
+            "block spending premine after release date must be valid"
+        );
+
         assert_eq!(
             2,
             tx.kernel.outputs.len(),
@@ -1512,8 +2780,13 @@ mod global_state_tests {
             .nth_generation_spending_key(0)
             .to_address();
         let genesis_block = Block::genesis_block(network);
-        let (mock_block_1, _, _) =
-            make_mock_block(&genesis_block, None, other_receiver_address, rng.gen());
+        let (mock_block_1, _, _) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            other_receiver_address,
+            rng.gen(),
+        );
         crate::tests::shared::add_block_to_archival_state(
             global_state.chain.archival_state_mut(),
             mock_block_1.clone(),
@@ -1595,8 +2868,13 @@ mod global_state_tests {
         let genesis_block = Block::genesis_block(network);
         let launch = genesis_block.kernel.header.timestamp;
         let seven_months = Timestamp::months(7);
-        let (mock_block_1a, _, _) =
-            make_mock_block(&genesis_block, None, other_receiver_address, rng.gen());
+        let (mock_block_1a, _, _) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            other_receiver_address,
+            rng.gen(),
+        );
         {
             global_state
                 .chain
@@ -1663,8 +2941,13 @@ mod global_state_tests {
 
         // 1. Create new block 1a where we receive a coinbase UTXO, store it
         let genesis_block = global_state.chain.archival_state().get_tip().await;
-        let (mock_block_1a, coinbase_utxo, coinbase_output_randomness) =
-            make_mock_block(&genesis_block, None, own_receiving_address, rng.gen());
+        let (mock_block_1a, coinbase_utxo, coinbase_output_randomness) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
         global_state
             .set_new_self_mined_tip(
                 mock_block_1a.clone(),
@@ -1692,8 +2975,13 @@ mod global_state_tests {
             .to_address();
         let mut parent_block = genesis_block;
         for _ in 0..5 {
-            let (next_block, _, _) =
-                make_mock_block(&parent_block, None, other_receiving_address, rng.gen());
+            let (next_block, _, _) = make_mock_block(
+                network,
+                &parent_block,
+                None,
+                other_receiving_address,
+                rng.gen(),
+            );
             global_state.set_new_tip(next_block.clone()).await.unwrap();
             parent_block = next_block;
         }
@@ -1735,488 +3023,2750 @@ mod global_state_tests {
 
     #[traced_test]
     #[tokio::test]
-    async fn resync_ms_membership_proofs_across_stale_fork() -> Result<()> {
+    async fn wallet_proof_audit_classifies_failures_test() -> Result<()> {
         let mut rng = thread_rng();
         let network = Network::RegTest;
         let global_state_lock =
             mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
         let mut global_state = global_state_lock.lock_guard_mut().await;
-        let wallet_secret = global_state.wallet_state.wallet_secret.clone();
-        let own_spending_key = wallet_secret.nth_generation_spending_key(0);
-        let own_receiving_address = own_spending_key.to_address();
-        let other_wallet_secret = WalletSecret::new_random();
-        let other_receiving_address = other_wallet_secret
+        let own_spending_key = global_state
+            .wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0);
+        let other_address = WalletSecret::new_random()
             .nth_generation_spending_key(0)
             .to_address();
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
 
-        // 1. Create new block 1a where we receive a coinbase UTXO, store it
-        let genesis_block = global_state.chain.archival_state().get_tip().await;
-        assert!(genesis_block.kernel.header.height.is_genesis());
-        let (mock_block_1a, coinbase_utxo_1a, cb_utxo_output_randomness_1a) =
-            make_mock_block(&genesis_block, None, own_receiving_address, rng.gen());
-        {
-            global_state
-                .set_new_self_mined_tip(
-                    mock_block_1a.clone(),
-                    ExpectedUtxo::new(
-                        coinbase_utxo_1a,
-                        cb_utxo_output_randomness_1a,
-                        own_spending_key.privacy_preimage,
-                        UtxoNotifier::OwnMiner,
-                    ),
-                )
-                .await
-                .unwrap();
-
-            // Verify that UTXO was recorded
-            let wallet_status_after_1a = global_state
-                .wallet_state
-                .get_wallet_status_from_lock(mock_block_1a.hash())
-                .await;
-            assert_eq!(2, wallet_status_after_1a.synced_unspent.len());
-        }
-
-        // Add 100 blocks on top of 1a, *not* mined by us
-        let mut fork_a_block = mock_block_1a.clone();
-        for _ in 0..100 {
-            let (next_a_block, _, _) =
-                make_mock_block(&fork_a_block, None, other_receiving_address, rng.gen());
-            global_state
-                .set_new_tip(next_a_block.clone())
-                .await
-                .unwrap();
-            fork_a_block = next_a_block;
-        }
-
-        // Verify that all both MUTXOs have synced MPs
-        let wallet_status_on_a_fork = global_state
+        // Block 1 pays a coinbase to this wallet, next to the premine
+        let (block_1, coinbase_utxo, coinbase_sender_randomness) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            own_spending_key.to_address(),
+            rng.gen(),
+        );
+        global_state
+            .set_new_self_mined_tip(
+                block_1.clone(),
+                ExpectedUtxo::new(
+                    coinbase_utxo,
+                    coinbase_sender_randomness,
+                    own_spending_key.privacy_preimage,
+                    UtxoNotifier::OwnMiner,
+                ),
+            )
+            .await?;
+        drop(global_state);
+        let synced_report = global_state_lock.audit_wallet_proofs().await;
+        global_state = global_state_lock.lock_guard_mut().await;
+        assert_eq!(block_1.hash(), synced_report.tip_digest);
+        assert_eq!(2, synced_report.audited_count);
+        assert!(synced_report.is_healthy());
+
+        // A tampered proof is corrupt, and only restorable by archival nodes
+        let monitored_utxos = global_state.wallet_state.wallet_db.monitored_utxos_mut();
+        let intact_monitored_utxo = monitored_utxos.get(0).await;
+        let mut tampered_monitored_utxo = intact_monitored_utxo.clone();
+        tampered_monitored_utxo.blockhash_to_membership_proof[0]
+            .1
+            .sender_randomness = rng.gen();
+        monitored_utxos.set(0, tampered_monitored_utxo).await;
+        let corrupt_report = global_state
             .wallet_state
-            .get_wallet_status_from_lock(fork_a_block.hash())
+            .audit_proofs(&block_1, Some(global_state.chain.archival_state()))
+            .await;
+        assert_eq!(
+            vec![ProofAuditEntry {
+                index: 0,
+                utxo_digest: Hash::hash(&intact_monitored_utxo.utxo),
+                finding: ProofAuditFinding::Corrupt,
+                suggested_repair: Some(ProofRepair::RestoreFromArchival),
+            }],
+            corrupt_report.entries
+        );
+        let light_corrupt_report = global_state.wallet_state.audit_proofs(&block_1, None).await;
+        assert_eq!(None, light_corrupt_report.entries[0].suggested_repair);
+        global_state
+            .wallet_state
+            .wallet_db
+            .monitored_utxos_mut()
+            .set(0, intact_monitored_utxo)
             .await;
 
-        assert_eq!(2, wallet_status_on_a_fork.synced_unspent.len());
-
-        // Fork away from the "a" chain to the "b" chain, with block 1a as LUCA
-        let mut fork_b_block = mock_block_1a.clone();
-        for _ in 0..100 {
-            let (next_b_block, _, _) =
-                make_mock_block(&fork_b_block, None, other_receiving_address, rng.gen());
-            global_state
-                .set_new_tip(next_b_block.clone())
-                .await
-                .unwrap();
-            fork_b_block = next_b_block;
-        }
-
-        // Verify that there are zero MUTXOs with synced MPs
-        let wallet_status_on_b_fork_before_resync = global_state
+        // Block 2 spends a UTXO of this wallet without the wallet learning of
+        // it, while its membership proofs are resynced to block 2
+        let tx_2 = global_state
+            .create_transaction(
+                vec![UtxoReceiverData {
+                    utxo: Utxo::new_native_coin(other_address.lock_script(), NeptuneCoins::new(20)),
+                    sender_randomness: rng.gen(),
+                    receiver_privacy_digest: other_address.privacy_digest,
+                    public_announcement: PublicAnnouncement::default(),
+                }],
+                NeptuneCoins::one(),
+                in_seven_months,
+            )
+            .await?;
+        let (mut block_2, _, _) =
+            make_mock_block(network, &block_1, None, other_address, rng.gen());
+        block_2
+            .accumulate_transaction(tx_2, &block_1.kernel.body.mutator_set_accumulator)
+            .await;
+        global_state
+            .chain
+            .archival_state_mut()
+            .write_block_as_tip(&block_2)
+            .await?;
+        global_state
+            .resync_membership_proofs_from_stored_blocks(block_2.hash())
+            .await?;
+        let spent_report = global_state
             .wallet_state
-            .get_wallet_status_from_lock(fork_b_block.hash())
+            .audit_proofs(&block_2, Some(global_state.chain.archival_state()))
             .await;
+        assert_eq!(2, spent_report.audited_count);
+        assert_eq!(1, spent_report.entries.len());
         assert_eq!(
-            0,
-            wallet_status_on_b_fork_before_resync.synced_unspent.len()
-        );
-        assert_eq!(
-            2,
-            wallet_status_on_b_fork_before_resync.unsynced_unspent.len()
+            ProofAuditFinding::SpentUnnoticed,
+            spent_report.entries[0].finding
         );
+        assert_eq!(None, spent_report.entries[0].suggested_repair);
 
-        // Run the resync and verify that MPs are synced
+        // A fork from genesis leaves the premine's proofs stale, and abandons
+        // the coinbase of block 1. Only the archival state tells them apart.
+        let (block_1b, _, _) =
+            make_mock_block(network, &genesis_block, None, other_address, rng.gen());
         global_state
-            .resync_membership_proofs_from_stored_blocks(fork_b_block.hash())
-            .await
-            .unwrap();
-        let wallet_status_on_b_fork_after_resync = global_state
+            .chain
+            .archival_state_mut()
+            .write_block_as_tip(&block_1b)
+            .await?;
+        let fork_report = global_state
             .wallet_state
-            .get_wallet_status_from_lock(fork_b_block.hash())
+            .audit_proofs(&block_1b, Some(global_state.chain.archival_state()))
             .await;
-        assert_eq!(2, wallet_status_on_b_fork_after_resync.synced_unspent.len());
+        let findings = fork_report
+            .entries
+            .iter()
+            .map(|entry| (entry.index, entry.finding, entry.suggested_repair))
+            .collect_vec();
         assert_eq!(
-            0,
-            wallet_status_on_b_fork_after_resync.unsynced_unspent.len()
+            vec![
+                (0, ProofAuditFinding::Stale, Some(ProofRepair::Resync)),
+                (
+                    1,
+                    ProofAuditFinding::Abandoned,
+                    Some(ProofRepair::ArchiveAsAbandoned)
+                ),
+            ],
+            findings
         );
+        let light_fork_report = global_state
+            .wallet_state
+            .audit_proofs(&block_1b, None)
+            .await;
+        assert!(light_fork_report
+            .entries
+            .iter()
+            .all(|entry| entry.finding == ProofAuditFinding::Stale));
 
-        // `wallet_state_has_all_valid_mps_for`
-        // Make a new chain c with genesis block as LUCA. Verify that the genesis UTXO can be synced
-        // to this new chain
-        let mut fork_c_block = genesis_block.clone();
-        for _ in 0..100 {
-            let (next_c_block, _, _) =
-                make_mock_block(&fork_c_block, None, other_receiving_address, rng.gen());
-            global_state
-                .set_new_tip(next_c_block.clone())
-                .await
-                .unwrap();
-            fork_c_block = next_c_block;
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn wallet_behind_the_tip_is_rescanned_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::new_random()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
+
+        // A wallet that is synced to the tip is left alone
+        assert_eq!(0, global_state.rescan_wallet_if_behind().await?);
+
+        let other_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (block_1, _, _) =
+            make_mock_block(network, &genesis_block, None, other_address, rng.gen());
+        let (block_2, _, _) = make_mock_block(network, &block_1, None, other_address, rng.gen());
+        for block in [&block_1, &block_2] {
+            global_state.set_new_tip(block.clone()).await?;
         }
 
-        // Verify that there are zero MUTXOs with synced MPs
-        let wallet_status_on_c_fork_before_resync = global_state
+        // An imported wallet has only seen the genesis block
+        global_state.wallet_state =
+            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
+        assert!(!global_state.wallet_state.is_synced_to(block_2.hash()).await);
+
+        assert_eq!(2, global_state.rescan_wallet_if_behind().await?);
+        assert!(global_state.wallet_state.is_synced_to(block_2.hash()).await);
+        assert!(wallet_state_has_all_valid_mps_for(&global_state.wallet_state, &block_2).await);
+        assert!(!global_state
+            .get_wallet_status_for_tip()
+            .await
+            .synced_unspent_available_amount(in_seven_months)
+            .is_zero());
+        assert_eq!(0, global_state.rescan_wallet_if_behind().await?);
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn rescan_wallet_from_genesis_recovers_premine_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
+
+        let other_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (block_1, _, _) =
+            make_mock_block(network, &genesis_block, None, other_address, rng.gen());
+        global_state.set_new_tip(block_1.clone()).await?;
+        let premine_balance = global_state
+            .get_wallet_status_for_tip()
+            .await
+            .synced_unspent_available_amount(in_seven_months);
+        assert!(!premine_balance.is_zero());
+
+        // Zero the wallet state
+        {
+            let monitored_utxos = global_state.wallet_state.wallet_db.monitored_utxos_mut();
+            while !monitored_utxos.is_empty().await {
+                monitored_utxos.pop().await;
+            }
+        }
+        global_state
             .wallet_state
-            .get_wallet_status_from_lock(fork_c_block.hash())
+            .wallet_db
+            .set_sync_label(Digest::default())
             .await;
+        assert!(global_state
+            .get_wallet_status_for_tip()
+            .await
+            .synced_unspent_available_amount(in_seven_months)
+            .is_zero());
+
+        // Rescanning is refused while mining
+        global_state.mining = true;
+        assert!(global_state
+            .rescan_wallet_from_height(BlockHeight::genesis())
+            .await
+            .is_err());
+        global_state.mining = false;
+
         assert_eq!(
-            0,
-            wallet_status_on_c_fork_before_resync.synced_unspent.len()
+            2,
+            global_state
+                .rescan_wallet_from_height(BlockHeight::genesis())
+                .await?
         );
+        assert!(global_state.wallet_state.is_synced_to(block_1.hash()).await);
         assert_eq!(
-            2,
-            wallet_status_on_c_fork_before_resync.unsynced_unspent.len()
+            premine_balance,
+            global_state
+                .get_wallet_status_for_tip()
+                .await
+                .synced_unspent_available_amount(in_seven_months)
         );
+        assert!(wallet_state_has_all_valid_mps_for(&global_state.wallet_state, &block_1).await);
 
-        // Run the resync and verify that UTXO from genesis is synced, but that
-        // UTXO from 1a is not synced.
-        global_state
-            .resync_membership_proofs_from_stored_blocks(fork_c_block.hash())
+        // Rescanning over known UTXOs would duplicate them
+        assert!(global_state
+            .rescan_wallet_from_height(BlockHeight::genesis())
             .await
-            .unwrap();
-        let wallet_status_on_c_fork_after_resync = global_state
-            .wallet_state
-            .get_wallet_status_from_lock(fork_c_block.hash())
-            .await;
-        assert_eq!(1, wallet_status_on_c_fork_after_resync.synced_unspent.len());
+            .is_err());
         assert_eq!(
             1,
-            wallet_status_on_c_fork_after_resync.unsynced_unspent.len()
-        );
-
-        // Also check that UTXO from 1a is considered abandoned
-        let monitored_utxos = global_state.wallet_state.wallet_db.monitored_utxos();
-        assert!(
-            !monitored_utxos
-                .get(0)
-                .await
-                .was_abandoned(fork_c_block.hash(), global_state.chain.archival_state())
-                .await
+            global_state.rescan_wallet_from_height(1u64.into()).await?
         );
-        assert!(
-            monitored_utxos
-                .get(1)
-                .await
-                .was_abandoned(fork_c_block.hash(), global_state.chain.archival_state())
+        assert_eq!(
+            1,
+            global_state
+                .wallet_state
+                .wallet_db
+                .monitored_utxos()
+                .len()
                 .await
         );
 
         Ok(())
     }
 
+    #[traced_test]
     #[tokio::test]
-    async fn flaky_mutator_set_test() {
-        // Test various parts of the state update when a block contains multiple inputs and outputs
-        // Scenario: Three parties: Alice, Bob, and Premine Receiver, mine blocks and pass coins
-        // around.
-
-        let seed = {
-            let mut rng: StdRng =
-                SeedableRng::from_rng(thread_rng()).expect("failure lifting thread_rng to StdRng");
-            let seed: [u8; 32] = rng.gen();
-            // let seed = [
-            //     0xf4, 0xc2, 0x1c, 0xd0, 0x5a, 0xac, 0x99, 0xe7, 0x3a, 0x1e, 0x29, 0x7f, 0x16, 0xc1,
-            //     0x50, 0x5e, 0x1e, 0xd, 0x4b, 0x49, 0x51, 0x9c, 0x1b, 0xa0, 0x38, 0x3c, 0xd, 0x83, 0x29,
-            //     0xdb, 0xab, 0xe2,
-            // ];
-            println!(
-                "seed: [{}]",
-                seed.iter().map(|h| format!("{:#x}", h)).join(", ")
-            );
-            seed
-        };
-        let mut rng: StdRng = SeedableRng::from_seed(seed);
+    async fn switch_wallet_to_branch_reverts_abandoned_blocks_test() -> Result<()> {
+        let mut rng = thread_rng();
         let network = Network::RegTest;
-
-        let genesis_wallet_state =
-            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
-        let genesis_spending_key = genesis_wallet_state
-            .wallet_secret
-            .nth_generation_spending_key(0);
-        let genesis_state_lock =
-            mock_genesis_global_state(network, 3, genesis_wallet_state.wallet_secret).await;
-
-        let wallet_secret_alice = WalletSecret::new_pseudorandom(rng.gen());
-        let alice_spending_key = wallet_secret_alice.nth_generation_spending_key(0);
-        let alice_state_lock = mock_genesis_global_state(network, 3, wallet_secret_alice).await;
-
-        let wallet_secret_bob = WalletSecret::new_pseudorandom(rng.gen());
-        let bob_spending_key = wallet_secret_bob.nth_generation_spending_key(0);
-        let bob_state_lock = mock_genesis_global_state(network, 3, wallet_secret_bob).await;
-
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
         let genesis_block = Block::genesis_block(network);
-        let launch = genesis_block.kernel.header.timestamp;
-        let seven_months = Timestamp::months(7);
+        let seven_months_after_launch =
+            genesis_block.kernel.header.timestamp + Timestamp::months(7);
+        let other_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
 
-        let (mut block_1, cb_utxo, cb_output_randomness) = make_mock_block_with_valid_pow(
+        let premine_status = global_state_lock
+            .lock_guard()
+            .await
+            .get_wallet_status_for_tip()
+            .await;
+        let premine_balance =
+            premine_status.synced_unspent_available_amount(seven_months_after_launch);
+        assert_eq!(1, premine_status.synced_unspent.len());
+
+        // Block 1a spends the premine UTXO, sending some coins to someone else
+        let (mut block_1a, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis_block,
             None,
-            genesis_spending_key.to_address(),
+            other_receiving_address,
             rng.gen(),
         );
-
-        // Send two outputs each to Alice and Bob, from genesis receiver
+        let receiver_data = vec![UtxoReceiverData {
+            public_announcement: PublicAnnouncement::default(),
+            receiver_privacy_digest: other_receiving_address.privacy_digest,
+            sender_randomness: rng.gen(),
+            utxo: Utxo {
+                lock_script_hash: other_receiving_address.lock_script().hash(),
+                coins: NeptuneCoins::new(20).to_native_coins(),
+            },
+        }];
+        let tx = create_transaction_with_timestamp(
+            &global_state_lock,
+            &receiver_data,
+            NeptuneCoins::one(),
+            seven_months_after_launch,
+        )
+        .await?;
+        block_1a
+            .accumulate_transaction(tx, &genesis_block.kernel.body.mutator_set_accumulator)
+            .await;
+
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        global_state.switch_wallet_to_branch_of(&block_1a).await?;
+        global_state.set_new_tip(block_1a.clone()).await?;
+
+        let status_1a = global_state.get_wallet_status_for_tip().await;
+        assert_eq!(1, status_1a.synced_spent.len(), "premine must be spent");
+        assert_eq!(1, status_1a.synced_unspent.len(), "change must be received");
+        assert_eq!(
+            premine_balance + -NeptuneCoins::new(21),
+            status_1a.synced_unspent_available_amount(seven_months_after_launch)
+        );
+
+        // A competing fork from genesis abandons block 1a
+        let (block_1b, _, _) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            other_receiving_address,
+            rng.gen(),
+        );
+        let (block_2b, _, _) =
+            make_mock_block(network, &block_1b, None, other_receiving_address, rng.gen());
+        for block in [&block_1b, &block_2b] {
+            global_state.switch_wallet_to_branch_of(block).await?;
+            global_state.set_new_tip(block.clone()).await?;
+        }
+
+        let status_2b = global_state.get_wallet_status_for_tip().await;
+        assert!(status_2b.synced_spent.is_empty());
+        assert!(status_2b.unsynced_spent.is_empty());
+        assert_eq!(
+            1,
+            status_2b.unsynced_unspent.len(),
+            "change UTXO confirmed in abandoned block must be kept, unsynced"
+        );
+        assert_eq!(1, status_2b.synced_unspent.len());
+        assert_eq!(
+            premine_balance,
+            status_2b.synced_unspent_available_amount(seven_months_after_launch)
+        );
+        assert!(global_state
+            .wallet_state
+            .expected_utxos
+            .get_all_expected_utxos()
+            .iter()
+            .all(|expected_utxo| expected_utxo.mined_in_block.is_none()));
+
+        // Switching back to the first branch applies block 1a again
+        let (block_2a, _, _) =
+            make_mock_block(network, &block_1a, None, other_receiving_address, rng.gen());
+        let (block_3a, _, _) =
+            make_mock_block(network, &block_2a, None, other_receiving_address, rng.gen());
+        for block in [&block_2a, &block_3a] {
+            global_state.switch_wallet_to_branch_of(block).await?;
+            global_state.set_new_tip(block.clone()).await?;
+        }
+
+        let status_3a = global_state.get_wallet_status_for_tip().await;
+        assert_eq!(1, status_3a.synced_spent.len());
+        assert_eq!(1, status_3a.synced_unspent.len());
+        assert!(status_3a.unsynced_unspent.is_empty());
+        assert_eq!(
+            2,
+            global_state
+                .wallet_state
+                .wallet_db
+                .monitored_utxos()
+                .len()
+                .await,
+            "change UTXO must not be registered twice when block 1a is applied again"
+        );
+        assert_eq!(
+            premine_balance + -NeptuneCoins::new(21),
+            status_3a.synced_unspent_available_amount(seven_months_after_launch)
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn reverting_and_reorg_notification_agree_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let other_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let own_spending_key = WalletSecret::devnet_wallet().nth_generation_spending_key(0);
+        let genesis_block = Block::genesis_block(network);
+        let (block_1a, coinbase_utxo, coinbase_output_randomness) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            own_spending_key.to_address(),
+            rng.gen(),
+        );
+        let (block_1b, _, _) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            other_receiving_address,
+            rng.gen(),
+        );
+        let (block_2b, _, _) =
+            make_mock_block(network, &block_1b, None, other_receiving_address, rng.gen());
+
+        // One wallet is reverted block by block before the new branch is
+        // applied, as the main loop does. The other one only learns about the
+        // reorganization when the new tip is set.
+        let mut statuses = vec![];
+        for revert_first in [true, false] {
+            let global_state_lock =
+                mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+            let mut global_state = global_state_lock.lock_guard_mut().await;
+            global_state
+                .set_new_self_mined_tip(
+                    block_1a.clone(),
+                    ExpectedUtxo::new(
+                        coinbase_utxo.clone(),
+                        coinbase_output_randomness,
+                        own_spending_key.privacy_preimage,
+                        UtxoNotifier::OwnMiner,
+                    ),
+                )
+                .await?;
+
+            for block in [&block_1b, &block_2b] {
+                if revert_first {
+                    global_state.switch_wallet_to_branch_of(block).await?;
+                }
+                global_state.set_new_tip(block.clone()).await?;
+            }
+
+            let status = global_state.get_wallet_status_for_tip().await;
+            let monitored_utxo_count = global_state
+                .wallet_state
+                .wallet_db
+                .monitored_utxos()
+                .len()
+                .await;
+            statuses.push((
+                status.synced_unspent.len(),
+                status.unsynced_unspent.len(),
+                status.synced_spent.len(),
+                status.unsynced_spent.len(),
+                monitored_utxo_count,
+            ));
+        }
+
+        assert_eq!((1, 1, 0, 0, 2), statuses[0]);
+        assert_eq!(statuses[0], statuses[1]);
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn reorg_notification_unsyncs_and_resyncs_wallet_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let mut global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        global_state_lock
+            .set_cli(cli_args::Args {
+                network,
+                resync_on_reorg: true,
+                ..Default::default()
+            })
+            .await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let own_spending_key = global_state
+            .wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0);
+        let other_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        // Receive a coinbase UTXO in block 1a
+        let genesis_block = Block::genesis_block(network);
+        let (block_1a, coinbase_utxo, coinbase_output_randomness) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            own_spending_key.to_address(),
+            rng.gen(),
+        );
+        global_state
+            .set_new_self_mined_tip(
+                block_1a.clone(),
+                ExpectedUtxo::new(
+                    coinbase_utxo,
+                    coinbase_output_randomness,
+                    own_spending_key.privacy_preimage,
+                    UtxoNotifier::OwnMiner,
+                ),
+            )
+            .await?;
+        assert_eq!(
+            2,
+            global_state
+                .get_wallet_status_for_tip()
+                .await
+                .synced_unspent
+                .len()
+        );
+
+        // Reorganize to a fork that disconnects block 1a. The coinbase UTXO must
+        // be marked as unsynced, while the premine UTXO stays synced.
+        let (block_1b, _, _) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            other_receiving_address,
+            rng.gen(),
+        );
+        let (block_2b, _, _) =
+            make_mock_block(network, &block_1b, None, other_receiving_address, rng.gen());
+        global_state.set_new_tip(block_1b).await?;
+        global_state.set_new_tip(block_2b.clone()).await?;
+
+        let status_on_b = global_state.get_wallet_status_for_tip().await;
+        assert_eq!(1, status_on_b.synced_unspent.len());
+        assert_eq!(1, status_on_b.unsynced_unspent.len());
+        let coinbase_mutxo = global_state
+            .wallet_state
+            .wallet_db
+            .monitored_utxos()
+            .get(1)
+            .await;
+        assert!(!coinbase_mutxo.is_synced_to(block_2b.hash()));
+        assert!(
+            coinbase_mutxo.is_synced_to(block_1a.hash()),
+            "membership proof for confirming block must be kept for resync"
+        );
+
+        // Reorganize back. Block 2a's parent is 1a, for which the premine UTXO
+        // has no membership proof, so it can only be synced through a resync.
+        let (block_2a, _, _) =
+            make_mock_block(network, &block_1a, None, other_receiving_address, rng.gen());
+        global_state.set_new_tip(block_2a).await?;
+
+        let status_on_a = global_state.get_wallet_status_for_tip().await;
+        assert_eq!(2, status_on_a.synced_unspent.len());
+        assert!(status_on_a.unsynced_unspent.is_empty());
+        assert!(
+            wallet_state_has_all_valid_mps_for(
+                &global_state.wallet_state,
+                global_state.chain.light_state()
+            )
+            .await
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn resync_ms_membership_proofs_across_stale_fork() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let wallet_secret = global_state.wallet_state.wallet_secret.clone();
+        let own_spending_key = wallet_secret.nth_generation_spending_key(0);
+        let own_receiving_address = own_spending_key.to_address();
+        let other_wallet_secret = WalletSecret::new_random();
+        let other_receiving_address = other_wallet_secret
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        // 1. Create new block 1a where we receive a coinbase UTXO, store it
+        let genesis_block = global_state.chain.archival_state().get_tip().await;
+        assert!(genesis_block.kernel.header.height.is_genesis());
+        let (mock_block_1a, coinbase_utxo_1a, cb_utxo_output_randomness_1a) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            own_receiving_address,
+            rng.gen(),
+        );
+        {
+            global_state
+                .set_new_self_mined_tip(
+                    mock_block_1a.clone(),
+                    ExpectedUtxo::new(
+                        coinbase_utxo_1a,
+                        cb_utxo_output_randomness_1a,
+                        own_spending_key.privacy_preimage,
+                        UtxoNotifier::OwnMiner,
+                    ),
+                )
+                .await
+                .unwrap();
+
+            // Verify that UTXO was recorded
+            let wallet_status_after_1a = global_state
+                .wallet_state
+                .get_wallet_status_from_lock(mock_block_1a.hash())
+                .await;
+            assert_eq!(2, wallet_status_after_1a.synced_unspent.len());
+        }
+
+        // Add 100 blocks on top of 1a, *not* mined by us
+        let mut fork_a_block = mock_block_1a.clone();
+        for _ in 0..100 {
+            let (next_a_block, _, _) = make_mock_block(
+                network,
+                &fork_a_block,
+                None,
+                other_receiving_address,
+                rng.gen(),
+            );
+            global_state
+                .set_new_tip(next_a_block.clone())
+                .await
+                .unwrap();
+            fork_a_block = next_a_block;
+        }
+
+        // Verify that all both MUTXOs have synced MPs
+        let wallet_status_on_a_fork = global_state
+            .wallet_state
+            .get_wallet_status_from_lock(fork_a_block.hash())
+            .await;
+
+        assert_eq!(2, wallet_status_on_a_fork.synced_unspent.len());
+
+        // Fork away from the "a" chain to the "b" chain, with block 1a as LUCA
+        let mut fork_b_block = mock_block_1a.clone();
+        for _ in 0..100 {
+            let (next_b_block, _, _) = make_mock_block(
+                network,
+                &fork_b_block,
+                None,
+                other_receiving_address,
+                rng.gen(),
+            );
+            global_state
+                .set_new_tip(next_b_block.clone())
+                .await
+                .unwrap();
+            fork_b_block = next_b_block;
+        }
+
+        // Verify that there are zero MUTXOs with synced MPs
+        let wallet_status_on_b_fork_before_resync = global_state
+            .wallet_state
+            .get_wallet_status_from_lock(fork_b_block.hash())
+            .await;
+        assert_eq!(
+            0,
+            wallet_status_on_b_fork_before_resync.synced_unspent.len()
+        );
+        assert_eq!(
+            2,
+            wallet_status_on_b_fork_before_resync.unsynced_unspent.len()
+        );
+
+        // Run the resync and verify that MPs are synced
+        global_state
+            .resync_membership_proofs_from_stored_blocks(fork_b_block.hash())
+            .await
+            .unwrap();
+        let wallet_status_on_b_fork_after_resync = global_state
+            .wallet_state
+            .get_wallet_status_from_lock(fork_b_block.hash())
+            .await;
+        assert_eq!(2, wallet_status_on_b_fork_after_resync.synced_unspent.len());
+        assert_eq!(
+            0,
+            wallet_status_on_b_fork_after_resync.unsynced_unspent.len()
+        );
+
+        // `wallet_state_has_all_valid_mps_for`
+        // Make a new chain c with genesis block as LUCA. Verify that the genesis UTXO can be synced
+        // to this new chain
+        let mut fork_c_block = genesis_block.clone();
+        for _ in 0..100 {
+            let (next_c_block, _, _) = make_mock_block(
+                network,
+                &fork_c_block,
+                None,
+                other_receiving_address,
+                rng.gen(),
+            );
+            global_state
+                .set_new_tip(next_c_block.clone())
+                .await
+                .unwrap();
+            fork_c_block = next_c_block;
+        }
+
+        // Verify that there are zero MUTXOs with synced MPs
+        let wallet_status_on_c_fork_before_resync = global_state
+            .wallet_state
+            .get_wallet_status_from_lock(fork_c_block.hash())
+            .await;
+        assert_eq!(
+            0,
+            wallet_status_on_c_fork_before_resync.synced_unspent.len()
+        );
+        assert_eq!(
+            2,
+            wallet_status_on_c_fork_before_resync.unsynced_unspent.len()
+        );
+
+        // Run the resync and verify that UTXO from genesis is synced, but that
+        // UTXO from 1a is not synced.
+        global_state
+            .resync_membership_proofs_from_stored_blocks(fork_c_block.hash())
+            .await
+            .unwrap();
+        let wallet_status_on_c_fork_after_resync = global_state
+            .wallet_state
+            .get_wallet_status_from_lock(fork_c_block.hash())
+            .await;
+        assert_eq!(1, wallet_status_on_c_fork_after_resync.synced_unspent.len());
+        assert_eq!(
+            1,
+            wallet_status_on_c_fork_after_resync.unsynced_unspent.len()
+        );
+
+        // Also check that UTXO from 1a is considered abandoned
+        let monitored_utxos = global_state.wallet_state.wallet_db.monitored_utxos();
+        assert!(
+            !monitored_utxos
+                .get(0)
+                .await
+                .was_abandoned(fork_c_block.hash(), global_state.chain.archival_state())
+                .await
+        );
+        assert!(
+            monitored_utxos
+                .get(1)
+                .await
+                .was_abandoned(fork_c_block.hash(), global_state.chain.archival_state())
+                .await
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn flaky_mutator_set_test() {
+        // Test various parts of the state update when a block contains multiple inputs and outputs
+        // Scenario: Three parties: Alice, Bob, and Premine Receiver, mine blocks and pass coins
+        // around.
+
+        let seed = {
+            let mut rng: StdRng =
+                SeedableRng::from_rng(thread_rng()).expect("failure lifting thread_rng to StdRng");
+            let seed: [u8; 32] = rng.gen();
+            // let seed = [
+            //     0xf4, 0xc2, 0x1c, 0xd0, 0x5a, 0xac, 0x99, 0xe7, 0x3a, 0x1e, 0x29, 0x7f, 0x16, 0xc1,
+            //     0x50, 0x5e, 0x1e, 0xd, 0x4b, 0x49, 0x51, 0x9c, 0x1b, 0xa0, 0x38, 0x3c, 0xd, 0x83, 0x29,
+            //     0xdb, 0xab, 0xe2,
+            // ];
+            println!(
+                "seed: [{}]",
+                seed.iter().map(|h| format!("{:#x}", h)).join(", ")
+            );
+            seed
+        };
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let network = Network::RegTest;
+
+        let genesis_wallet_state =
+            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
+        let genesis_spending_key = genesis_wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0);
+        let genesis_state_lock =
+            mock_genesis_global_state(network, 3, genesis_wallet_state.wallet_secret).await;
+
+        let wallet_secret_alice = WalletSecret::new_pseudorandom(rng.gen());
+        let alice_spending_key = wallet_secret_alice.nth_generation_spending_key(0);
+        let alice_state_lock = mock_genesis_global_state(network, 3, wallet_secret_alice).await;
+
+        let wallet_secret_bob = WalletSecret::new_pseudorandom(rng.gen());
+        let bob_spending_key = wallet_secret_bob.nth_generation_spending_key(0);
+        let bob_state_lock = mock_genesis_global_state(network, 3, wallet_secret_bob).await;
+
+        let genesis_block = Block::genesis_block(network);
+        let launch = genesis_block.kernel.header.timestamp;
+        let seven_months = Timestamp::months(7);
+
+        let (mut block_1, cb_utxo, cb_output_randomness) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            genesis_spending_key.to_address(),
+            rng.gen(),
+        );
+
+        // Send two outputs each to Alice and Bob, from genesis receiver
         let fee = NeptuneCoins::one();
         let sender_randomness: Digest = rng.gen();
         let receiver_data_for_alice = vec![
             UtxoReceiverData {
-                public_announcement: PublicAnnouncement::default(),
-                receiver_privacy_digest: alice_spending_key.to_address().privacy_digest,
+                public_announcement: PublicAnnouncement::default(),
+                receiver_privacy_digest: alice_spending_key.to_address().privacy_digest,
+                sender_randomness,
+                utxo: Utxo {
+                    lock_script_hash: alice_spending_key.to_address().lock_script().hash(),
+                    coins: NeptuneCoins::new(41).to_native_coins(),
+                },
+            },
+            UtxoReceiverData {
+                public_announcement: PublicAnnouncement::default(),
+                receiver_privacy_digest: alice_spending_key.to_address().privacy_digest,
+                sender_randomness,
+                utxo: Utxo {
+                    lock_script_hash: alice_spending_key.to_address().lock_script().hash(),
+                    coins: NeptuneCoins::new(59).to_native_coins(),
+                },
+            },
+        ];
+
+        // Two outputs for Bob
+        let receiver_data_for_bob = vec![
+            UtxoReceiverData {
+                public_announcement: PublicAnnouncement::default(),
+                receiver_privacy_digest: bob_spending_key.to_address().privacy_digest,
+                sender_randomness,
+                utxo: Utxo {
+                    lock_script_hash: bob_spending_key.to_address().lock_script().hash(),
+                    coins: NeptuneCoins::new(141).to_native_coins(),
+                },
+            },
+            UtxoReceiverData {
+                public_announcement: PublicAnnouncement::default(),
+                receiver_privacy_digest: bob_spending_key.to_address().privacy_digest,
+                sender_randomness,
+                utxo: Utxo {
+                    lock_script_hash: bob_spending_key.to_address().lock_script().hash(),
+                    coins: NeptuneCoins::new(59).to_native_coins(),
+                },
+            },
+        ];
+        {
+            let tx_to_alice_and_bob = create_transaction_with_timestamp(
+                &genesis_state_lock,
+                &[
+                    receiver_data_for_alice.clone(),
+                    receiver_data_for_bob.clone(),
+                ]
+                .concat(),
+                fee,
+                launch + seven_months,
+            )
+            .await
+            .unwrap();
+
+            // Absorb and verify validity
+            block_1
+                .accumulate_transaction(
+                    tx_to_alice_and_bob,
+                    &genesis_block.kernel.body.mutator_set_accumulator,
+                )
+                .await;
+            let now = genesis_block.kernel.header.timestamp;
+            assert!(block_1.is_valid(&genesis_block, network));
+        }
+
+        println!("Accumulated transaction into block_1.");
+        println!(
+            "Transaction has {} inputs (removal records) and {} outputs (addition records)",
+            block_1.kernel.body.transaction.kernel.inputs.len(),
+            block_1.kernel.body.transaction.kernel.outputs.len()
+        );
+
+        // Update states with `block_1`
+        for rec_data in receiver_data_for_alice {
+            alice_state_lock
+                .lock_guard_mut()
+                .await
+                .wallet_state
+                .expected_utxos
+                .add_expected_utxo(
+                    rec_data.utxo.clone(),
+                    rec_data.sender_randomness,
+                    alice_spending_key.privacy_preimage,
+                    UtxoNotifier::Cli,
+                )
+                .unwrap();
+        }
+
+        for rec_data in receiver_data_for_bob {
+            bob_state_lock
+                .lock_guard_mut()
+                .await
+                .wallet_state
+                .expected_utxos
+                .add_expected_utxo(
+                    rec_data.utxo.clone(),
+                    rec_data.sender_randomness,
+                    bob_spending_key.privacy_preimage,
+                    UtxoNotifier::Cli,
+                )
+                .unwrap();
+        }
+
+        genesis_state_lock
+            .lock_guard_mut()
+            .await
+            .set_new_self_mined_tip(
+                block_1.clone(),
+                ExpectedUtxo::new(
+                    cb_utxo,
+                    cb_output_randomness,
+                    genesis_spending_key.privacy_preimage,
+                    UtxoNotifier::OwnMiner,
+                ),
+            )
+            .await
+            .unwrap();
+
+        for state_lock in [&alice_state_lock, &bob_state_lock] {
+            let mut state = state_lock.lock_guard_mut().await;
+            state.set_new_tip(block_1.clone()).await.unwrap();
+        }
+
+        assert_eq!(
+                3,
+                genesis_state_lock
+                    .lock_guard_mut()
+                    .await
+                    .wallet_state
+                    .wallet_db
+                    .monitored_utxos()
+                    .len().await, "Genesis receiver must have 3 UTXOs after block 1: change from transaction, coinbase from block 1, and the spent premine UTXO"
+            );
+
+        // Now Alice should have a balance of 100 and Bob a balance of 200
+        assert_eq!(
+            NeptuneCoins::new(100),
+            alice_state_lock
+                .lock_guard()
+                .await
+                .get_wallet_status_for_tip()
+                .await
+                .synced_unspent_available_amount(launch + seven_months)
+        );
+        assert_eq!(
+            NeptuneCoins::new(200),
+            bob_state_lock
+                .lock_guard()
+                .await
+                .get_wallet_status_for_tip()
+                .await
+                .synced_unspent_available_amount(launch + seven_months)
+        );
+
+        // Make two transactions: Alice sends two UTXOs to Genesis and Bob sends three UTXOs to genesis
+        let receiver_data_from_alice = vec![
+            UtxoReceiverData {
+                utxo: Utxo {
+                    lock_script_hash: genesis_spending_key.to_address().lock_script().hash(),
+                    coins: NeptuneCoins::new(50).to_native_coins(),
+                },
+                sender_randomness: rng.gen(),
+                receiver_privacy_digest: genesis_spending_key.to_address().privacy_digest,
+                public_announcement: PublicAnnouncement::default(),
+            },
+            UtxoReceiverData {
+                utxo: Utxo {
+                    lock_script_hash: genesis_spending_key.to_address().lock_script().hash(),
+                    coins: NeptuneCoins::new(49).to_native_coins(),
+                },
+                sender_randomness: rng.gen(),
+                receiver_privacy_digest: genesis_spending_key.to_address().privacy_digest,
+                public_announcement: PublicAnnouncement::default(),
+            },
+        ];
+        let now = genesis_block.kernel.header.timestamp;
+        let tx_from_alice = alice_state_lock
+            .lock_guard_mut()
+            .await
+            .create_transaction(receiver_data_from_alice.clone(), NeptuneCoins::new(1), now)
+            .await
+            .unwrap();
+        let receiver_data_from_bob = vec![
+            UtxoReceiverData {
+                utxo: Utxo {
+                    lock_script_hash: genesis_spending_key.to_address().lock_script().hash(),
+                    coins: NeptuneCoins::new(50).to_native_coins(),
+                },
+                sender_randomness: rng.gen(),
+                receiver_privacy_digest: genesis_spending_key.to_address().privacy_digest,
+                public_announcement: PublicAnnouncement::default(),
+            },
+            UtxoReceiverData {
+                utxo: Utxo {
+                    lock_script_hash: genesis_spending_key.to_address().lock_script().hash(),
+                    coins: NeptuneCoins::new(50).to_native_coins(),
+                },
+                sender_randomness: rng.gen(),
+                receiver_privacy_digest: genesis_spending_key.to_address().privacy_digest,
+                public_announcement: PublicAnnouncement::default(),
+            },
+            UtxoReceiverData {
+                utxo: Utxo {
+                    lock_script_hash: genesis_spending_key.to_address().lock_script().hash(),
+                    coins: NeptuneCoins::new(98).to_native_coins(),
+                },
+                sender_randomness: rng.gen(),
+                receiver_privacy_digest: genesis_spending_key.to_address().privacy_digest,
+                public_announcement: PublicAnnouncement::default(),
+            },
+        ];
+        let tx_from_bob = bob_state_lock
+            .lock_guard_mut()
+            .await
+            .create_transaction(receiver_data_from_bob.clone(), NeptuneCoins::new(2), now)
+            .await
+            .unwrap();
+
+        // Make block_2 with tx that contains:
+        // - 4 inputs: 2 from Alice and 2 from Bob
+        // - 6 outputs: 2 from Alice to Genesis, 3 from Bob to Genesis, and 1 coinbase to Genesis
+        let (mut block_2, _cb_utxo_block_2, _cb_sender_randomness_block_2) =
+            make_mock_block_with_valid_pow(
+                network,
+                &block_1,
+                None,
+                genesis_spending_key.to_address(),
+                rng.gen(),
+            );
+        block_2
+            .accumulate_transaction(tx_from_alice, &block_1.kernel.body.mutator_set_accumulator)
+            .await;
+        assert_eq!(2, block_2.kernel.body.transaction.kernel.inputs.len());
+        assert_eq!(3, block_2.kernel.body.transaction.kernel.outputs.len());
+
+        block_2
+            .accumulate_transaction(tx_from_bob, &block_1.kernel.body.mutator_set_accumulator)
+            .await;
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn list_monitored_utxos_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+
+        // The premine UTXO is confirmed in the genesis block
+        let infos = global_state.list_monitored_utxos(0, 10).await;
+        assert_eq!(1, infos.len());
+        let premine_info = &infos[0];
+        assert_eq!(0, premine_info.index);
+        assert_eq!(
+            Hash::hash(&Block::premine_utxos(network)[0]),
+            premine_info.utxo_digest
+        );
+        assert_eq!(
+            Some((genesis_block.hash(), BlockHeight::genesis())),
+            premine_info.confirmed_in_block
+        );
+        assert_eq!(Some(1), premine_info.confirmations);
+        assert!(premine_info.synced_to_tip);
+        assert!(!premine_info.spent);
+        assert!(!premine_info.abandoned);
+        assert!(premine_info.label.is_none());
+
+        // Labels show up in the report
+        assert!(
+            global_state
+                .set_label(LabelTarget::MonitoredUtxo(0), "premine".to_string())
+                .await?
+        );
+        assert_eq!(
+            Some("premine".to_string()),
+            global_state.list_monitored_utxos(0, 10).await[0].label
+        );
+
+        // Mine two blocks to someone else and verify the confirmation count
+        let other_receiver_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            other_receiver_address,
+            rng.gen(),
+        );
+        let (block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1,
+            None,
+            other_receiver_address,
+            rng.gen(),
+        );
+        global_state.set_new_tip(block_1).await?;
+        global_state.set_new_tip(block_2).await?;
+
+        let infos = global_state.list_monitored_utxos(0, 10).await;
+        assert_eq!(Some(3), infos[0].confirmations);
+        assert!(infos[0].synced_to_tip);
+
+        // Pages beyond the end are empty
+        assert!(global_state.list_monitored_utxos(1, 10).await.is_empty());
+        assert!(global_state.list_monitored_utxos(0, 0).await.is_empty());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn list_unspent_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
+
+        // The premine UTXO is confirmed in the genesis block
+        let unspent = global_state.list_unspent().await;
+        assert_eq!(1, unspent.len());
+        assert_eq!(Block::premine_utxos(network)[0], unspent[0].utxo);
+        assert_eq!(
+            Some((genesis_block.hash(), genesis_block.kernel.header.timestamp)),
+            unspent[0].confirmed_in_block
+        );
+        assert!(unspent[0].synced_to_tip);
+        assert!(!unspent[0].abandoned);
+
+        // Spending the premine replaces it with change
+        let other_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let receiver_data = vec![UtxoReceiverData {
+            utxo: Utxo::new_native_coin(other_address.lock_script(), NeptuneCoins::new(5)),
+            sender_randomness: rng.gen(),
+            receiver_privacy_digest: other_address.privacy_digest,
+            public_announcement: PublicAnnouncement::default(),
+        }];
+        let tx = global_state
+            .create_transaction(receiver_data, NeptuneCoins::new(1), in_seven_months)
+            .await?;
+        let (mut block_1, _, _) =
+            make_mock_block(network, &genesis_block, None, other_address, rng.gen());
+        block_1
+            .accumulate_transaction(tx, &genesis_block.kernel.body.mutator_set_accumulator)
+            .await;
+        global_state.set_new_tip(block_1.clone()).await?;
+
+        let unspent = global_state.list_unspent().await;
+        assert_eq!(1, unspent.len());
+        assert_ne!(Block::premine_utxos(network)[0], unspent[0].utxo);
+        assert_eq!(
+            Some((block_1.hash(), block_1.kernel.header.timestamp)),
+            unspent[0].confirmed_in_block
+        );
+        assert!(unspent[0].synced_to_tip);
+        assert!(!unspent[0].abandoned);
+
+        // A newer proof from a fork, with another AOCL leaf index, does not
+        // hide the proof for the tip
+        let monitored_utxos = global_state.wallet_state.wallet_db.monitored_utxos_mut();
+        let change_index = monitored_utxos.len().await - 1;
+        let mut change_mutxo = monitored_utxos.get(change_index).await;
+        let mut fork_membership_proof = change_mutxo
+            .get_membership_proof_for_block(block_1.hash())
+            .unwrap();
+        fork_membership_proof.auth_path_aocl.leaf_index += 1;
+        change_mutxo.add_membership_proof_for_tip(rng.gen(), fork_membership_proof);
+        monitored_utxos.set(change_index, change_mutxo).await;
+
+        let unspent_with_fork_proof = global_state.list_unspent().await;
+        assert_eq!(unspent, unspent_with_fork_proof);
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn mock_global_state_is_valid() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+
+        let wallet_secret = WalletSecret::new_random();
+        let receiving_address = wallet_secret.nth_generation_spending_key(0).to_address();
+        let (block_1, _cb_utxo, _cb_output_randomness) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            receiving_address,
+            rng.gen(),
+        );
+
+        global_state.set_new_tip(block_1).await.unwrap();
+
+        assert!(global_state
+            .chain
+            .light_state()
+            .is_valid(&genesis_block, network));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn sender_randomness_reuse_is_detected_and_recovered_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
+
+        let wallet_secret = global_state.wallet_state.wallet_secret.clone();
+        let recipient_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let receiver_data_with_nonce = |nonce: u64| UtxoReceiverData {
+            utxo: Utxo::new_native_coin(recipient_address.lock_script(), NeptuneCoins::new(1)),
+            sender_randomness: wallet_secret.generate_sender_randomness_with_nonce(
+                genesis_block.kernel.header.height,
+                recipient_address.privacy_digest,
+                nonce,
+            ),
+            receiver_privacy_digest: recipient_address.privacy_digest,
+            public_announcement: PublicAnnouncement::default(),
+        };
+
+        // nonce 0 is the plain derivation
+        assert_eq!(
+            wallet_secret.generate_sender_randomness(
+                genesis_block.kernel.header.height,
+                recipient_address.privacy_digest
+            ),
+            receiver_data_with_nonce(0).sender_randomness
+        );
+
+        let first_payment = vec![receiver_data_with_nonce(0)];
+        global_state
+            .create_transaction(first_payment.clone(), NeptuneCoins::new(1), in_seven_months)
+            .await
+            .unwrap();
+        assert!(
+            global_state
+                .wallet_state
+                .sender_randomness_was_used(first_payment[0].sender_randomness)
+                .await
+        );
+
+        // paying the same recipient again within the same block with the same
+        // nonce reuses the sender randomness, which must be refused
+        let colliding_payment = vec![receiver_data_with_nonce(0)];
+        let err = global_state
+            .create_transaction(
+                colliding_payment.clone(),
+                NeptuneCoins::new(1),
+                in_seven_months,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            Some(&SenderRandomnessReuseError {
+                sender_randomness: colliding_payment[0].sender_randomness
+            }),
+            err.downcast_ref::<SenderRandomnessReuseError>()
+        );
+
+        // incrementing the nonce recovers
+        let second_payment = vec![receiver_data_with_nonce(1)];
+        assert_no_sender_randomness_reuse(first_payment.iter().chain(second_payment.iter()));
+        global_state
+            .create_transaction(second_payment, NeptuneCoins::new(1), in_seven_months)
+            .await
+            .unwrap();
+
+        // two outputs of one transaction sharing sender randomness are refused
+        // as well, and the refused batch reserves nothing
+        let duplicate_payment = vec![receiver_data_with_nonce(2), receiver_data_with_nonce(2)];
+        let err = global_state
+            .create_transaction(
+                duplicate_payment.clone(),
+                NeptuneCoins::new(1),
+                in_seven_months,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            Some(&SenderRandomnessReuseError {
+                sender_randomness: duplicate_payment[0].sender_randomness
+            }),
+            err.downcast_ref::<SenderRandomnessReuseError>()
+        );
+        assert!(
+            !global_state
+                .wallet_state
+                .sender_randomness_was_used(duplicate_payment[0].sender_randomness)
+                .await
+        );
+
+        // both transactions produced change, which must not share randomness
+        // either
+        let change_randomness = global_state
+            .wallet_state
+            .expected_utxos
+            .get_all_expected_utxos()
+            .into_iter()
+            .map(|expected_utxo| expected_utxo.sender_randomness)
+            .collect_vec();
+        assert_eq!(2, change_randomness.len());
+        assert_ne!(change_randomness[0], change_randomness[1]);
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn watched_address_payments_are_sighted_test() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
+        let mut wallet_events = global_state.wallet_state.subscribe_to_wallet_events();
+
+        let watched_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let miner_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let payment_to_watched_address = |amount: u32, rng: &mut rand::rngs::ThreadRng| {
+            let utxo =
+                Utxo::new_native_coin(watched_address.lock_script(), NeptuneCoins::new(amount));
+            let sender_randomness = rng.gen();
+            UtxoReceiverData {
+                public_announcement: watched_address
+                    .generate_public_announcement(&utxo, sender_randomness)
+                    .unwrap(),
+                utxo,
                 sender_randomness,
-                utxo: Utxo {
-                    lock_script_hash: alice_spending_key.to_address().lock_script().hash(),
-                    coins: NeptuneCoins::new(41).to_native_coins(),
-                },
+                receiver_privacy_digest: watched_address.privacy_digest,
+            }
+        };
+
+        // The first payment is made before the address is watched, so the
+        // wallet only learns about it from the public announcement.
+        let tx_before_watching = global_state
+            .create_transaction(
+                vec![payment_to_watched_address(3, &mut rng)],
+                NeptuneCoins::new(1),
+                in_seven_months,
+            )
+            .await
+            .unwrap();
+        assert!(
+            global_state
+                .wallet_state
+                .add_watch_address(watched_address, "counterparty".to_string())
+                .await
+        );
+
+        let (mut block_1, _, _) =
+            make_mock_block(network, &genesis_block, None, miner_address, rng.gen());
+        block_1
+            .accumulate_transaction(
+                tx_before_watching,
+                &genesis_block.kernel.body.mutator_set_accumulator,
+            )
+            .await;
+        global_state.set_new_tip(block_1.clone()).await.unwrap();
+
+        let unknown_amount_sighting = WatchedAddressSighting {
+            block_digest: block_1.hash(),
+            block_height: 1u64.into(),
+            amount: None,
+        };
+        assert_eq!(
+            WalletEvent::WatchedAddressActivity {
+                lock_script_hash: watched_address.lock_script().hash(),
+                label: "counterparty".to_string(),
+                sighting: unknown_amount_sighting.clone(),
             },
-            UtxoReceiverData {
-                public_announcement: PublicAnnouncement::default(),
-                receiver_privacy_digest: alice_spending_key.to_address().privacy_digest,
-                sender_randomness,
-                utxo: Utxo {
-                    lock_script_hash: alice_spending_key.to_address().lock_script().hash(),
-                    coins: NeptuneCoins::new(59).to_native_coins(),
-                },
+            wallet_events.try_recv().unwrap()
+        );
+
+        // The second payment is made by this wallet while watching the address,
+        // so its amount is known.
+        let tx_while_watching = global_state
+            .create_transaction(
+                vec![payment_to_watched_address(5, &mut rng)],
+                NeptuneCoins::new(1),
+                in_seven_months,
+            )
+            .await
+            .unwrap();
+        let (mut block_2, _, _) =
+            make_mock_block(network, &block_1, None, miner_address, rng.gen());
+        block_2
+            .accumulate_transaction(
+                tx_while_watching,
+                &block_1.kernel.body.mutator_set_accumulator,
+            )
+            .await;
+        global_state.set_new_tip(block_2.clone()).await.unwrap();
+
+        let known_amount_sighting = WatchedAddressSighting {
+            block_digest: block_2.hash(),
+            block_height: 2u64.into(),
+            amount: Some(NeptuneCoins::new(5)),
+        };
+        assert_eq!(
+            WalletEvent::WatchedAddressActivity {
+                lock_script_hash: watched_address.lock_script().hash(),
+                label: "counterparty".to_string(),
+                sighting: known_amount_sighting.clone(),
+            },
+            wallet_events.try_recv().unwrap()
+        );
+        assert!(
+            wallet_events.try_recv().is_err(),
+            "Payment must be sighted only once"
+        );
+
+        let watched_addresses = global_state.wallet_state.get_watched_addresses().await;
+        assert_eq!(1, watched_addresses.len());
+        assert_eq!(
+            vec![unknown_amount_sighting, known_amount_sighting],
+            watched_addresses[0].sightings
+        );
+        assert!(watched_addresses[0].pending_outputs.is_empty());
+
+        assert!(
+            global_state
+                .wallet_state
+                .remove_watch_address(&watched_address)
+                .await
+        );
+        assert!(global_state
+            .wallet_state
+            .get_watched_addresses()
+            .await
+            .is_empty());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn removal_of_own_transaction_from_mempool_is_announced_test() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
+        let mut wallet_events = global_state.wallet_state.subscribe_to_wallet_events();
+
+        let other_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let utxo = Utxo::new_native_coin(other_address.lock_script(), NeptuneCoins::new(3));
+        let sender_randomness = rng.gen();
+        let receiver_data = vec![UtxoReceiverData {
+            public_announcement: other_address
+                .generate_public_announcement(&utxo, sender_randomness)
+                .unwrap(),
+            utxo,
+            sender_randomness,
+            receiver_privacy_digest: other_address.privacy_digest,
+        }];
+        let own_transaction = global_state
+            .create_transaction(receiver_data, NeptuneCoins::new(1), in_seven_months)
+            .await
+            .unwrap();
+        let foreign_transaction = make_mock_transaction(vec![], vec![]);
+
+        // Only the removal of a transaction spending the wallet's UTXOs is
+        // announced.
+        assert!(
+            !global_state
+                .wallet_state
+                .handle_mempool_event(&mempool::MempoolEvent::Evicted {
+                    transaction_id: Hash::hash(&foreign_transaction),
+                    transaction: Box::new(foreign_transaction),
+                })
+                .await
+        );
+        assert!(wallet_events.try_recv().is_err());
+
+        let own_transaction_id = Hash::hash(&own_transaction);
+        assert!(
+            global_state
+                .wallet_state
+                .handle_mempool_event(&mempool::MempoolEvent::Evicted {
+                    transaction_id: own_transaction_id,
+                    transaction: Box::new(own_transaction.clone()),
+                })
+                .await
+        );
+        assert_eq!(
+            WalletEvent::OwnTransactionEvicted {
+                transaction_id: own_transaction_id
+            },
+            wallet_events.try_recv().unwrap()
+        );
+        assert!(
+            global_state
+                .wallet_state
+                .handle_mempool_event(&mempool::MempoolEvent::Expired {
+                    transaction_id: own_transaction_id,
+                    transaction: Box::new(own_transaction),
+                })
+                .await
+        );
+        assert_eq!(
+            WalletEvent::OwnTransactionExpired {
+                transaction_id: own_transaction_id
             },
+            wallet_events.try_recv().unwrap()
+        );
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn balance_history_includes_transaction_labels_test() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
+
+        let other_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let receiver_data = vec![UtxoReceiverData {
+            utxo: Utxo::new_native_coin(other_address.lock_script(), NeptuneCoins::new(5)),
+            sender_randomness: rng.gen(),
+            receiver_privacy_digest: other_address.privacy_digest,
+            public_announcement: PublicAnnouncement::default(),
+        }];
+        let labeled_tx = global_state
+            .create_transaction_with_label(
+                receiver_data,
+                NeptuneCoins::new(1),
+                in_seven_months,
+                Some("coffee".to_string()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("coffee".to_string()),
+            global_state
+                .wallet_state
+                .get_transaction_label(labeled_tx.kernel.mast_hash())
+                .await
+        );
+
+        let (mut block_1, _cb_utxo, _cb_output_randomness) =
+            make_mock_block(network, &genesis_block, None, other_address, rng.gen());
+        block_1
+            .accumulate_transaction(
+                labeled_tx,
+                &genesis_block.kernel.body.mutator_set_accumulator,
+            )
+            .await;
+        global_state.set_new_tip(block_1.clone()).await.unwrap();
+
+        // The premine was received without label, and both spent and replaced
+        // by change in the labeled transaction.
+        let history = global_state.get_balance_history().await;
+        assert_eq!(3, history.len());
+        let labels = history
+            .iter()
+            .map(|(block_digest, _, _, _, label)| (*block_digest, label.clone()))
+            .collect_vec();
+        assert!(labels.contains(&(genesis_block.hash(), None)));
+        assert_eq!(
+            2,
+            labels
+                .iter()
+                .filter(|entry| **entry == (block_1.hash(), Some("coffee".to_string())))
+                .count()
+        );
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn balance_at_historical_heights_follows_canonical_chain_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
+        let own_spending_key = global_state
+            .wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0);
+        let other_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let pay_other = |sender_randomness: Digest| {
+            vec![UtxoReceiverData {
+                utxo: Utxo::new_native_coin(other_address.lock_script(), NeptuneCoins::new(20)),
+                sender_randomness,
+                receiver_privacy_digest: other_address.privacy_digest,
+                public_announcement: PublicAnnouncement::default(),
+            }]
+        };
+
+        let premine = global_state
+            .get_wallet_status_for_tip()
+            .await
+            .synced_unspent
+            .iter()
+            .map(|(wse, _msmp)| wse.utxo.get_native_currency_amount())
+            .sum::<NeptuneCoins>();
+
+        // Block 1 pays a coinbase to this wallet
+        let (block_1, coinbase_utxo, coinbase_sender_randomness) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            own_spending_key.to_address(),
+            rng.gen(),
+        );
+        let coinbase = coinbase_utxo.get_native_currency_amount();
+        global_state.wallet_state.expected_utxos.add_expected_utxo(
+            coinbase_utxo,
+            coinbase_sender_randomness,
+            own_spending_key.privacy_preimage,
+            UtxoNotifier::OwnMiner,
+        )?;
+        global_state.set_new_tip(block_1.clone()).await?;
+
+        // Block 2a spends 21 coins, including the fee
+        let tx_2a = global_state
+            .create_transaction(pay_other(rng.gen()), NeptuneCoins::one(), in_seven_months)
+            .await?;
+        let (mut block_2a, _, _) =
+            make_mock_block(network, &block_1, None, other_address, rng.gen());
+        block_2a
+            .accumulate_transaction(tx_2a, &block_1.kernel.body.mutator_set_accumulator)
+            .await;
+        global_state.set_new_tip(block_2a.clone()).await?;
+
+        let spent = premine + coinbase + -NeptuneCoins::new(21);
+        assert_eq!(
+            Some(premine),
+            global_state.balance_at_height(0u64.into()).await
+        );
+        assert_eq!(
+            Some(premine + coinbase),
+            global_state.balance_at_height(1u64.into()).await
+        );
+        assert_eq!(
+            Some(spent),
+            global_state.balance_at_height(2u64.into()).await
+        );
+
+        // A competing fork abandons block 2a and its spend, and spends 21 coins
+        // in block 4b instead
+        let (block_2b, _, _) = make_mock_block(network, &block_1, None, other_address, rng.gen());
+        let (block_3b, _, _) = make_mock_block(network, &block_2b, None, other_address, rng.gen());
+        for block in [&block_2b, &block_3b] {
+            global_state.switch_wallet_to_branch_of(block).await?;
+            global_state.set_new_tip(block.clone()).await?;
+        }
+        let tx_4b = global_state
+            .create_transaction(pay_other(rng.gen()), NeptuneCoins::one(), in_seven_months)
+            .await?;
+        let (mut block_4b, _, _) =
+            make_mock_block(network, &block_3b, None, other_address, rng.gen());
+        block_4b
+            .accumulate_transaction(tx_4b, &block_3b.kernel.body.mutator_set_accumulator)
+            .await;
+        global_state.set_new_tip(block_4b.clone()).await?;
+
+        let expected_balances = [
+            premine,
+            premine + coinbase,
+            premine + coinbase,
+            premine + coinbase,
+            spent,
         ];
+        for (height, expected_balance) in expected_balances.into_iter().enumerate() {
+            assert_eq!(
+                Some(expected_balance),
+                global_state.balance_at_height((height as u64).into()).await,
+                "balance at height {height}"
+            );
+        }
+        assert!(global_state.balance_at_height(5u64.into()).await.is_none());
+
+        // Timestamps resolve to the last block that is not later
+        let genesis_timestamp = genesis_block.kernel.header.timestamp;
+        assert!(global_state
+            .balance_at_timestamp(genesis_timestamp - Timestamp::millis(1))
+            .await
+            .is_none());
+        assert_eq!(
+            Some((BlockHeight::genesis(), premine)),
+            global_state.balance_at_timestamp(genesis_timestamp).await
+        );
+        assert_eq!(
+            Some((BlockHeight::from(3u64), premine + coinbase)),
+            global_state
+                .balance_at_timestamp(block_3b.kernel.header.timestamp + Timestamp::millis(1))
+                .await
+        );
+        assert_eq!(
+            Some((BlockHeight::from(4u64), spent)),
+            global_state
+                .balance_at_timestamp(block_4b.kernel.header.timestamp)
+                .await
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn balance_grows_monotonically_with_coinbase_rewards_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let own_spending_key = global_state
+            .wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0);
+
+        // Every block pays a coinbase to this wallet
+        let mut previous_block = Block::genesis_block(network);
+        for _ in 0..4 {
+            let (block, coinbase_utxo, coinbase_sender_randomness) = make_mock_block(
+                network,
+                &previous_block,
+                None,
+                own_spending_key.to_address(),
+                rng.gen(),
+            );
+            global_state.wallet_state.expected_utxos.add_expected_utxo(
+                coinbase_utxo,
+                coinbase_sender_randomness,
+                own_spending_key.privacy_preimage,
+                UtxoNotifier::OwnMiner,
+            )?;
+            global_state.set_new_tip(block.clone()).await?;
+            previous_block = block;
+        }
+
+        let mut previous_balance = global_state
+            .balance_at_height(BlockHeight::genesis())
+            .await
+            .unwrap();
+        for height in 1..=4u64 {
+            let balance = global_state.balance_at_height(height.into()).await.unwrap();
+            assert!(
+                balance > previous_balance,
+                "balance must grow with the coinbase of block {height}"
+            );
+            previous_balance = balance;
+        }
+        let tip_balance = global_state
+            .get_wallet_status_for_tip()
+            .await
+            .synced_unspent
+            .iter()
+            .map(|(wse, _msmp)| wse.utxo.get_native_currency_amount())
+            .sum::<NeptuneCoins>();
+        assert_eq!(tip_balance, previous_balance);
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn transaction_labels_are_searchable_and_survive_rescan_test() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
 
-        // Two outputs for Bob
-        let receiver_data_for_bob = vec![
-            UtxoReceiverData {
-                public_announcement: PublicAnnouncement::default(),
-                receiver_privacy_digest: bob_spending_key.to_address().privacy_digest,
-                sender_randomness,
-                utxo: Utxo {
-                    lock_script_hash: bob_spending_key.to_address().lock_script().hash(),
-                    coins: NeptuneCoins::new(141).to_native_coins(),
-                },
-            },
-            UtxoReceiverData {
-                public_announcement: PublicAnnouncement::default(),
-                receiver_privacy_digest: bob_spending_key.to_address().privacy_digest,
-                sender_randomness,
-                utxo: Utxo {
-                    lock_script_hash: bob_spending_key.to_address().lock_script().hash(),
-                    coins: NeptuneCoins::new(59).to_native_coins(),
-                },
-            },
-        ];
-        {
-            let tx_to_alice_and_bob = create_transaction_with_timestamp(
-                &genesis_state_lock,
-                &[
-                    receiver_data_for_alice.clone(),
-                    receiver_data_for_bob.clone(),
-                ]
-                .concat(),
-                fee,
-                launch + seven_months,
-            )
+        let other_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let receiver_data = vec![UtxoReceiverData {
+            utxo: Utxo::new_native_coin(other_address.lock_script(), NeptuneCoins::new(5)),
+            sender_randomness: rng.gen(),
+            receiver_privacy_digest: other_address.privacy_digest,
+            public_announcement: PublicAnnouncement::default(),
+        }];
+        let tx = global_state
+            .create_transaction(receiver_data, NeptuneCoins::new(1), in_seven_months)
             .await
             .unwrap();
+        let kernel_hash = tx.kernel.mast_hash();
 
-            // Absorb and verify validity
-            block_1
+        // An unlabeled transaction can be labeled while it is in the mempool,
+        // and relabeled afterwards
+        assert!(!global_state
+            .set_label(LabelTarget::Transaction(kernel_hash), "invoice".to_string())
+            .await
+            .unwrap());
+        global_state.mempool.insert(&tx).unwrap();
+        assert!(global_state
+            .set_label(LabelTarget::Transaction(kernel_hash), "invoice".to_string())
+            .await
+            .unwrap());
+        assert!(global_state
+            .set_label(
+                LabelTarget::Transaction(kernel_hash),
+                "invoice #1234".to_string()
+            )
+            .await
+            .unwrap());
+        assert!(global_state
+            .set_label(LabelTarget::Transaction(kernel_hash), "\u{7}".to_string())
+            .await
+            .is_err());
+
+        let (mut block_1, _cb_utxo, _cb_output_randomness) =
+            make_mock_block(network, &genesis_block, None, other_address, rng.gen());
+        block_1
+            .accumulate_transaction(tx, &genesis_block.kernel.body.mutator_set_accumulator)
+            .await;
+        global_state.set_new_tip(block_1.clone()).await.unwrap();
+
+        // Spending the premine and receiving change both match
+        let found = global_state.find_history("#1234").await;
+        assert_eq!(2, found.len());
+        assert!(found
+            .iter()
+            .all(|(block_digest, .., label)| *block_digest == block_1.hash()
+                && *label == Some("invoice #1234".to_string())));
+        assert!(global_state.find_history("payroll").await.is_empty());
+
+        // Rebuild the history from the chain in a fresh wallet that keeps the
+        // labels only. The labels must re-attach to the rebuilt entries.
+        let rescanned_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut rescanned_state = rescanned_state_lock.lock_guard_mut().await;
+        let labels = global_state
+            .wallet_state
+            .wallet_db
+            .get_transaction_labels()
+            .await;
+        rescanned_state
+            .wallet_state
+            .wallet_db
+            .set_transaction_labels(labels)
+            .await;
+        rescanned_state.set_new_tip(block_1.clone()).await.unwrap();
+
+        let refound = rescanned_state.find_history("invoice").await;
+        assert!(!refound.is_empty());
+        assert!(refound
+            .iter()
+            .all(|(block_digest, .., label)| *block_digest == block_1.hash()
+                && *label == Some("invoice #1234".to_string())));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn oversized_membership_proofs_are_restored_from_archival_state_test() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let in_seven_months = genesis_block.kernel.header.timestamp + Timestamp::months(7);
+        let other_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        // Blocks with many outputs slide the window over the premine's indices,
+        // such that the chunk dictionary of its membership proof grows.
+        async fn make_block_with_many_outputs(
+            network: Network,
+            previous_block: &Block,
+            seed: [u8; 32],
+        ) -> Block {
+            let mut rng: StdRng = SeedableRng::from_seed(seed);
+            let other_address = WalletSecret::new_random()
+                .nth_generation_spending_key(0)
+                .to_address();
+            let (mut block, _cb_utxo, _cb_output_randomness) =
+                make_mock_block(network, previous_block, None, other_address, rng.gen());
+            let outputs = (0..80)
+                .map(|_| AdditionRecord::new(rng.gen()))
+                .collect_vec();
+            let mut transaction = make_mock_transaction(vec![], outputs);
+            transaction.kernel.mutator_set_hash =
+                block.kernel.body.transaction.kernel.mutator_set_hash;
+            block
                 .accumulate_transaction(
-                    tx_to_alice_and_bob,
-                    &genesis_block.kernel.body.mutator_set_accumulator,
+                    transaction,
+                    &previous_block.kernel.body.mutator_set_accumulator,
                 )
                 .await;
-            let now = genesis_block.kernel.header.timestamp;
-            assert!(block_1.is_valid(&genesis_block, now + seven_months));
+            block
+        }
+
+        // Fifty blocks of churn in total, of which the first few grow the
+        // proof uncapped
+        const CHURN_BLOCK_COUNT: usize = 50;
+        const UNCAPPED_BLOCK_COUNT: usize = 5;
+        let mut previous_block = genesis_block.clone();
+        for _ in 0..UNCAPPED_BLOCK_COUNT {
+            let block = make_block_with_many_outputs(network, &previous_block, rng.gen()).await;
+            global_state.set_new_tip(block.clone()).await.unwrap();
+            previous_block = block;
+        }
+        let statistics = global_state
+            .wallet_state
+            .chunk_dictionary_statistics()
+            .await;
+        assert_eq!(1, statistics.proof_count);
+        assert!(statistics.max_entries > 0);
+        assert_eq!(0, statistics.archival_restore_count);
+
+        // With a cap below the current size, the premine's proof is dropped
+        // and no longer grows
+        global_state.cli.max_chunk_dictionary_entries = Some(statistics.max_entries - 1);
+        for _ in UNCAPPED_BLOCK_COUNT..CHURN_BLOCK_COUNT {
+            let block = make_block_with_many_outputs(network, &previous_block, rng.gen()).await;
+            global_state.set_new_tip(block.clone()).await.unwrap();
+            previous_block = block;
+
+            let capped_statistics = global_state
+                .wallet_state
+                .chunk_dictionary_statistics()
+                .await;
+            assert_eq!(0, capped_statistics.proof_count);
+            assert_eq!(0, capped_statistics.max_entries);
+            assert_eq!(1, capped_statistics.archival_restore_count);
+            assert!(global_state
+                .wallet_state
+                .wallet_db
+                .monitored_utxos()
+                .get(0)
+                .await
+                .blockhash_to_membership_proof
+                .is_empty());
+        }
+
+        // The premine remains spendable
+        let receiver_data = vec![UtxoReceiverData {
+            utxo: Utxo::new_native_coin(other_address.lock_script(), NeptuneCoins::new(5)),
+            sender_randomness: rng.gen(),
+            receiver_privacy_digest: other_address.privacy_digest,
+            public_announcement: PublicAnnouncement::default(),
+        }];
+        let tx = global_state
+            .create_transaction(receiver_data, NeptuneCoins::new(1), in_seven_months)
+            .await
+            .unwrap();
+        assert!(tx.is_valid());
+        assert_eq!(
+            0,
+            global_state
+                .wallet_state
+                .chunk_dictionary_statistics()
+                .await
+                .archival_restore_count
+        );
+
+        let (mut spending_block, _cb_utxo, _cb_output_randomness) =
+            make_mock_block(network, &previous_block, None, other_address, rng.gen());
+        spending_block
+            .accumulate_transaction(tx, &previous_block.kernel.body.mutator_set_accumulator)
+            .await;
+        global_state
+            .set_new_tip(spending_block.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(spending_block.hash()),
+            global_state
+                .wallet_state
+                .wallet_db
+                .monitored_utxos()
+                .get(0)
+                .await
+                .spent_in_block
+                .map(|(block_hash, _, _)| block_hash)
+        );
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn bootstrap_peers_are_sorted_by_last_seen_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 0, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+
+        // Peer `i` was last seen `i` minutes ago
+        let now = SystemTime::now();
+        let addresses = (1..=4).map(get_dummy_socket_address).collect_vec();
+        for i in [3, 1, 4, 2] {
+            let known_peer = KnownPeer {
+                instance_id: i as u128,
+                last_seen: now - Duration::from_secs(60 * i as u64),
+            };
+            global_state
+                .net
+                .write_known_peer(addresses[i - 1], known_peer)
+                .await;
+        }
+
+        // Peer 2 is in bad standing
+        let bad_standing = PeerStanding {
+            standing: -(global_state.cli().peer_tolerance as i32) - 1,
+            ..Default::default()
+        };
+        global_state
+            .write_peer_standing_on_decrease(addresses[1].ip(), bad_standing)
+            .await;
+
+        assert_eq!(
+            vec![addresses[0], addresses[2], addresses[3]],
+            global_state.get_bootstrap_peers(10).await
+        );
+        assert_eq!(
+            vec![addresses[0], addresses[2]],
+            global_state.get_bootstrap_peers(2).await
+        );
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn known_peers_are_kept_within_capacity_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 0, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+
+        // Fill the database, peer `i` having been seen `i` seconds ago
+        let now = SystemTime::now();
+        let address_of = |i: usize| SocketAddr::new(Ipv4Addr::from(i as u32 + 1).into(), 9798);
+        let known_peer_seen_secs_ago = |i: usize| KnownPeer {
+            instance_id: i as u128,
+            last_seen: now - Duration::from_secs(i as u64),
+        };
+        for i in 0..KNOWN_PEERS_CAPACITY {
+            global_state
+                .net
+                .write_known_peer(address_of(i), known_peer_seen_secs_ago(i))
+                .await;
+        }
+        assert_eq!(
+            KNOWN_PEERS_CAPACITY,
+            global_state.net.db_stats().known_peers_count
+        );
+
+        // Seeing a known peer again does not make room
+        global_state
+            .net
+            .write_known_peer(address_of(0), known_peer_seen_secs_ago(0))
+            .await;
+        assert_eq!(
+            KNOWN_PEERS_CAPACITY,
+            global_state.net.db_stats().known_peers_count
+        );
+
+        // A new peer replaces the least recently seen one
+        let new_address = address_of(KNOWN_PEERS_CAPACITY);
+        global_state
+            .net
+            .write_known_peer(new_address, known_peer_seen_secs_ago(0))
+            .await;
+        let known_addresses = global_state
+            .net
+            .all_known_peers_in_database()
+            .await
+            .into_iter()
+            .map(|(listen_address, _known_peer)| listen_address)
+            .collect::<HashSet<_>>();
+        assert_eq!(KNOWN_PEERS_CAPACITY, known_addresses.len());
+        assert_eq!(
+            KNOWN_PEERS_CAPACITY,
+            global_state.net.db_stats().known_peers_count
+        );
+        assert!(known_addresses.contains(&new_address));
+        assert!(!known_addresses.contains(&address_of(KNOWN_PEERS_CAPACITY - 1)));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn address_book_tracks_and_selects_addresses_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 0, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let mut rng = thread_rng();
+        assert!(global_state
+            .select_address_to_dial(&mut rng)
+            .await
+            .is_none());
+
+        // Addresses are learned from peer lists, and are dialed from the
+        // address book
+        let now = SystemTime::now();
+        let address = get_dummy_socket_address(1);
+        global_state.net.add_to_address_book([address], now).await;
+        assert_eq!(
+            Some(address),
+            global_state.select_address_to_dial(&mut rng).await
+        );
+
+        // A success resets the failure count, while too many failures without
+        // a success make the address be forgotten
+        global_state.net.record_address_failure(address).await;
+        global_state.net.record_address_success(address, now).await;
+        let expected_record = AddressRecord {
+            last_seen: now,
+            last_success: Some(now),
+            failure_count: 0,
+        };
+        assert_eq!(
+            vec![(address, expected_record)],
+            global_state.net.all_addresses_in_address_book().await
+        );
+
+        let other_address = get_dummy_socket_address(2);
+        global_state
+            .net
+            .add_to_address_book([other_address], now)
+            .await;
+        for _ in 0..ADDRESS_BOOK_MAX_FAILURES {
+            global_state.net.record_address_failure(other_address).await;
         }
+        assert_eq!(
+            vec![address],
+            global_state
+                .net
+                .all_addresses_in_address_book()
+                .await
+                .into_iter()
+                .map(|(book_address, _)| book_address)
+                .collect_vec()
+        );
+
+        // Banned and backing off addresses are not dialed
+        global_state.net.record_dial_failure(address);
+        assert!(global_state
+            .select_address_to_dial(&mut rng)
+            .await
+            .is_none());
+        global_state.net.reset_dial_backoff(address);
+        let bad_standing = PeerStanding {
+            standing: -(global_state.cli().peer_tolerance as i32) - 1,
+            ..Default::default()
+        };
+        global_state
+            .write_peer_standing_on_decrease(address.ip(), bad_standing)
+            .await;
+        assert!(global_state
+            .select_address_to_dial(&mut rng)
+            .await
+            .is_none());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn full_address_book_evicts_worst_address_test() {
+        let data_dir = unit_test_data_directory(Network::RegTest).unwrap();
+        let peer_databases = NetworkingState::initialize_peer_databases(&data_dir)
+            .await
+            .unwrap();
+        let mut net = NetworkingState::new(get_peer_map(), peer_databases, false);
+
+        let now = SystemTime::now();
+        let address = |i: usize| SocketAddr::from(([10, (i >> 8) as u8, i as u8, 1], 9798));
+        net.add_to_address_book((0..ADDRESS_BOOK_CAPACITY).map(address), now)
+            .await;
+        net.record_address_success(address(0), now).await;
+        net.record_address_failure(address(1)).await;
+
+        // A new address replaces the entry with the worst track record,
+        // rather than being ignored
+        let new_address = address(ADDRESS_BOOK_CAPACITY);
+        net.add_to_address_book([new_address], now).await;
+        let addresses: HashSet<SocketAddr> = net
+            .all_addresses_in_address_book()
+            .await
+            .into_iter()
+            .map(|(book_address, _record)| book_address)
+            .collect();
+        assert_eq!(ADDRESS_BOOK_CAPACITY, addresses.len());
+        assert!(addresses.contains(&new_address));
+        assert!(addresses.contains(&address(0)));
+        assert!(!addresses.contains(&address(1)));
+        assert_eq!(ADDRESS_BOOK_CAPACITY, net.db_stats().address_book_count);
+        assert_eq!(None, net.peer_databases.address_book.get(address(1)).await);
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn address_book_survives_restart_test() {
+        let data_dir = unit_test_data_directory(Network::RegTest).unwrap();
+        let now = SystemTime::now();
+        let working_address = get_dummy_socket_address(1);
+        let failing_address = get_dummy_socket_address(2);
+        let expected_addresses = {
+            let peer_databases = NetworkingState::initialize_peer_databases(&data_dir)
+                .await
+                .unwrap();
+            let mut net = NetworkingState::new(get_peer_map(), peer_databases, false);
+            net.add_to_address_book([working_address, failing_address], now)
+                .await;
+            net.record_address_success(working_address, now).await;
+            net.record_address_failure(failing_address).await;
+            net.all_addresses_in_address_book()
+                .await
+                .into_iter()
+                .sorted_by_key(|(address, _record)| *address)
+                .collect_vec()
+        };
+
+        // The databases were closed when the state was dropped
+        let peer_databases = NetworkingState::initialize_peer_databases(&data_dir)
+            .await
+            .unwrap();
+        let net = NetworkingState::new(get_peer_map(), peer_databases, false);
+        let addresses_after_restart = net
+            .all_addresses_in_address_book()
+            .await
+            .into_iter()
+            .sorted_by_key(|(address, _record)| *address)
+            .collect_vec();
+        assert_eq!(2, addresses_after_restart.len());
+        assert_eq!(expected_addresses, addresses_after_restart);
+        assert_eq!(2, net.db_stats().address_book_count);
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn expired_dial_backoffs_are_pruned_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 0, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+
+        let address = get_dummy_socket_address(0);
+        global_state.net.record_dial_failure(address);
+        let backoff = global_state.net.next_dial_delay(address);
+        assert!(!backoff.is_zero());
+
+        // The backoff is kept until its retention period has passed
+        let now = SystemTime::now();
+        global_state.net.prune_dial_backoffs(now + DIAL_BACKOFF_CAP);
+        assert_eq!(backoff, global_state.net.next_dial_delay(address));
+
+        global_state
+            .net
+            .prune_dial_backoffs(now + DIAL_BACKOFF_RETENTION + Duration::from_secs(1));
+        assert!(global_state.net.next_dial_delay(address).is_zero());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn unanswered_transaction_request_falls_back_to_other_announcer_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 0, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+
+        let transaction_id: Digest = thread_rng().gen();
+        let first_announcer = get_dummy_socket_address(0);
+        let second_announcer = get_dummy_socket_address(1);
+        let now = SystemTime::now();
+
+        // Only the first announcer is asked, the second one is remembered
+        assert!(global_state.net.request_announced_transaction(
+            transaction_id,
+            first_announcer,
+            now
+        ));
+        assert!(!global_state.net.request_announced_transaction(
+            transaction_id,
+            second_announcer,
+            now
+        ));
+        assert!(global_state
+            .net
+            .retry_timed_out_transaction_requests(now)
+            .is_empty());
+
+        // The first announcer did not deliver in time, so the second one is
+        // asked
+        let timed_out = now + TRANSACTION_REQUEST_TIMEOUT;
+        assert_eq!(
+            vec![(transaction_id, second_announcer)],
+            global_state
+                .net
+                .retry_timed_out_transaction_requests(timed_out)
+        );
+
+        // Neither did the second announcer. The request is abandoned, and the
+        // transaction is requested from the next peer announcing it.
+        let timed_out_again = timed_out + TRANSACTION_REQUEST_TIMEOUT;
+        assert!(global_state
+            .net
+            .retry_timed_out_transaction_requests(timed_out_again)
+            .is_empty());
+        assert!(global_state.net.request_announced_transaction(
+            transaction_id,
+            first_announcer,
+            timed_out_again
+        ));
+
+        // A received transaction is not requested again
+        global_state.net.record_received_transaction(transaction_id);
+        assert!(global_state
+            .net
+            .retry_timed_out_transaction_requests(timed_out_again + TRANSACTION_REQUEST_TIMEOUT)
+            .is_empty());
+        assert!(!global_state.net.request_announced_transaction(
+            transaction_id,
+            second_announcer,
+            timed_out_again
+        ));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn peerless_alert_is_raised_after_threshold_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 1, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let threshold = global_state.cli().peerless_warning_threshold().unwrap();
+        let start = SystemTime::now();
+
+        // Connected to a peer
+        assert!(global_state.check_peer_count(start).is_none());
+        let peer_map = global_state.net.peer_map.clone();
+        global_state.net.peer_map.clear();
+
+        // Lost the peer. The alert is raised once the threshold has passed,
+        // and the warning is only issued once.
+        assert!(global_state.check_peer_count(start).is_none());
+        assert!(!global_state
+            .net
+            .record_peer_count(start + threshold, threshold));
+        let later = start + threshold + Duration::from_secs(1);
+        assert!(global_state.net.record_peer_count(later, threshold));
+        assert!(!global_state.net.record_peer_count(later, threshold));
+        assert_eq!(Some(start), global_state.check_peer_count(later));
+        assert_eq!(Some(start), global_state.peerless_alert(later));
+
+        // Reconnecting clears the alert
+        global_state.net.peer_map = peer_map;
+        assert!(global_state.check_peer_count(later).is_none());
+        assert!(global_state.peerless_alert(later).is_none());
+    }
 
-        println!("Accumulated transaction into block_1.");
-        println!(
-            "Transaction has {} inputs (removal records) and {} outputs (addition records)",
-            block_1.kernel.body.transaction.kernel.inputs.len(),
-            block_1.kernel.body.transaction.kernel.outputs.len()
-        );
+    #[traced_test]
+    #[tokio::test]
+    async fn only_claims_on_the_canonical_chain_are_canonical_test() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 3, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let genesis_block = Block::genesis_block(network);
+        let other_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
 
-        // Update states with `block_1`
-        for rec_data in receiver_data_for_alice {
-            alice_state_lock
-                .lock_guard_mut()
+        // Block 2b is abandoned in favor of blocks 2a and 3a
+        let (block_1, _, _) =
+            make_mock_block(network, &genesis_block, None, other_address, rng.gen());
+        let (block_2a, _, _) = make_mock_block(network, &block_1, None, other_address, rng.gen());
+        let (block_2b, _, _) = make_mock_block(network, &block_1, None, other_address, rng.gen());
+        let (block_3a, _, _) = make_mock_block(network, &block_2a, None, other_address, rng.gen());
+        for block in [&block_1, &block_2b, &block_2a, &block_3a] {
+            global_state
+                .switch_wallet_to_branch_of(block)
                 .await
-                .wallet_state
-                .expected_utxos
-                .add_expected_utxo(
-                    rec_data.utxo.clone(),
-                    rec_data.sender_randomness,
-                    alice_spending_key.privacy_preimage,
-                    UtxoNotifier::Cli,
-                )
                 .unwrap();
+            global_state.set_new_tip(block.clone()).await.unwrap();
         }
 
-        for rec_data in receiver_data_for_bob {
-            bob_state_lock
-                .lock_guard_mut()
-                .await
-                .wallet_state
-                .expected_utxos
-                .add_expected_utxo(
-                    rec_data.utxo.clone(),
-                    rec_data.sender_randomness,
-                    bob_spending_key.privacy_preimage,
-                    UtxoNotifier::Cli,
-                )
-                .unwrap();
+        // The peers claim a canonical block, an abandoned block, and an
+        // unknown block
+        let peers = global_state.net.peer_map.keys().copied().collect_vec();
+        let now = SystemTime::now();
+        for (peer, block) in peers.iter().zip([&block_1, &block_2b]) {
+            global_state.net.chain_split_detector.record_claim(
+                *peer,
+                block.hash(),
+                block.kernel.header.height,
+                now,
+            );
         }
+        global_state.net.chain_split_detector.record_claim(
+            peers[2],
+            rng.gen(),
+            block_3a.kernel.header.height,
+            now,
+        );
 
-        genesis_state_lock
-            .lock_guard_mut()
-            .await
-            .set_new_self_mined_tip(
-                block_1.clone(),
-                ExpectedUtxo::new(
-                    cb_utxo,
-                    cb_output_randomness,
-                    genesis_spending_key.privacy_preimage,
-                    UtxoNotifier::OwnMiner,
-                ),
-            )
+        let (tip_digest, tip_height, canonical_digests) =
+            global_state.canonical_claimed_digests().await.unwrap();
+        assert_eq!(block_3a.hash(), tip_digest);
+        assert_eq!(block_3a.kernel.header.height, tip_height);
+        assert_eq!(HashSet::from([block_1.hash()]), canonical_digests);
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn peer_database_gc_removes_stale_entries_only_test() {
+        let network = Network::RegTest;
+        let mut global_state_lock =
+            mock_genesis_global_state(network, 0, WalletSecret::devnet_wallet()).await;
+
+        // Bans are permanent, and address 4 is pinned
+        let addresses = (1..=4).map(get_dummy_socket_address).collect_vec();
+        let pinned_address = addresses[3];
+        global_state_lock
+            .set_cli(cli_args::Args {
+                network,
+                peer_ban_duration: 0,
+                peers: vec![Socks5Target::Address(pinned_address)],
+                ..Default::default()
+            })
+            .await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let now = SystemTime::now();
+        let long_ago = now - Duration::from_secs(100 * 24 * 60 * 60);
+        let standing_since = |standing: i32, sanctioned_at: SystemTime| PeerStanding {
+            standing,
+            latest_sanction: None,
+            timestamp_of_latest_sanction: Some(sanctioned_at),
+        };
+
+        // Address 1 is stale, address 2 is fresh, address 3 is stale but
+        // banned, and address 4 is stale but pinned
+        let banned = -(global_state.cli().peer_tolerance as i32) - 1;
+        for (address, standing) in addresses.iter().zip([
+            standing_since(-5, long_ago),
+            standing_since(-5, now),
+            standing_since(banned, long_ago),
+            standing_since(-5, long_ago),
+        ]) {
+            global_state
+                .write_peer_standing_on_decrease(address.ip(), standing)
+                .await;
+        }
+        for (i, last_seen) in [long_ago, now, long_ago, long_ago].into_iter().enumerate() {
+            let known_peer = KnownPeer {
+                instance_id: i as u128,
+                last_seen,
+            };
+            global_state
+                .net
+                .write_known_peer(addresses[i], known_peer)
+                .await;
+            global_state
+                .net
+                .add_to_address_book([addresses[i]], last_seen)
+                .await;
+        }
+
+        let report = global_state
+            .garbage_collect_peer_databases(now)
             .await
             .unwrap();
+        assert_eq!(1, report.peer_standings_removed);
+        assert_eq!(2, report.known_peers_removed);
+        assert_eq!(2, report.addresses_removed);
+
+        let remaining_ips = global_state
+            .net
+            .peer_databases
+            .peer_standings
+            .iter()
+            .map(|(ip, _standing)| ip)
+            .sorted()
+            .collect_vec();
+        assert_eq!(
+            vec![addresses[1].ip(), addresses[2].ip(), pinned_address.ip()],
+            remaining_ips
+        );
+        let remaining_known_peers = global_state
+            .net
+            .all_known_peers_in_database()
+            .await
+            .into_iter()
+            .map(|(address, _known_peer)| address)
+            .sorted()
+            .collect_vec();
+        assert_eq!(vec![addresses[1], pinned_address], remaining_known_peers);
+        let remaining_addresses = global_state
+            .net
+            .all_addresses_in_address_book()
+            .await
+            .into_iter()
+            .map(|(address, _record)| address)
+            .sorted()
+            .collect_vec();
+        assert_eq!(vec![addresses[1], pinned_address], remaining_addresses);
 
-        for state_lock in [&alice_state_lock, &bob_state_lock] {
-            let mut state = state_lock.lock_guard_mut().await;
-            state.set_new_tip(block_1.clone()).await.unwrap();
-        }
+        let db_stats = global_state.net.db_stats();
+        assert_eq!(3, db_stats.peer_standings_count);
+        assert_eq!(2, db_stats.known_peers_count);
+        assert_eq!(2, db_stats.address_book_count);
+        assert_eq!(Some(report), db_stats.latest_peer_database_gc);
 
+        // Nothing is left to collect
+        let second_report = global_state
+            .garbage_collect_peer_databases(now)
+            .await
+            .unwrap();
         assert_eq!(
-                3,
-                genesis_state_lock
-                    .lock_guard_mut()
-                    .await
-                    .wallet_state
-                    .wallet_db
-                    .monitored_utxos()
-                    .len().await, "Genesis receiver must have 3 UTXOs after block 1: change from transaction, coinbase from block 1, and the spent premine UTXO"
-            );
+            (0, 0, 0),
+            (
+                second_report.peer_standings_removed,
+                second_report.known_peers_removed,
+                second_report.addresses_removed
+            )
+        );
+    }
 
-        // Now Alice should have a balance of 100 and Bob a balance of 200
+    #[traced_test]
+    #[tokio::test]
+    async fn cleared_peer_standing_is_forgotten_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 0, WalletSecret::devnet_wallet()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let stored_standings_count =
+            |state: &GlobalState| state.net.peer_databases.peer_standings.iter().count();
+
+        let ips = (1..=3)
+            .map(|i| get_dummy_socket_address(i).ip())
+            .collect_vec();
+        let never_seen_ip = get_dummy_socket_address(4).ip();
+        for ip in ips.iter() {
+            let mut standing = PeerStanding::default();
+            standing.sanction(PeerSanctionReason::InvalidBlock((
+                BlockHeight::from(7u64),
+                Digest::default(),
+            )));
+            global_state
+                .write_peer_standing_on_decrease(*ip, standing)
+                .await;
+        }
+        assert_eq!(3, stored_standings_count(&global_state));
+
+        // A cleared peer is indistinguishable from one that was never seen
+        global_state.net.clear_ip_standing_in_database(ips[0]).await;
+        assert_eq!(2, stored_standings_count(&global_state));
         assert_eq!(
-            NeptuneCoins::new(100),
-            alice_state_lock
-                .lock_guard()
-                .await
-                .get_wallet_status_for_tip()
+            PeerStanding::default(),
+            global_state
+                .net
+                .get_peer_standing_from_database(ips[0])
                 .await
-                .synced_unspent_available_amount(launch + seven_months)
         );
         assert_eq!(
-            NeptuneCoins::new(200),
-            bob_state_lock
-                .lock_guard()
+            global_state
+                .net
+                .get_peer_standing_from_database(never_seen_ip)
+                .await,
+            global_state
+                .net
+                .get_peer_standing_from_database(ips[0])
                 .await
-                .get_wallet_status_for_tip()
+        );
+        assert!(!global_state
+            .net
+            .all_peer_sanctions_in_database()
+            .await
+            .contains_key(&ips[0]));
+
+        // Sanctions after clearing start from zero
+        let mut new_standing = PeerStanding::default();
+        new_standing.sanction(PeerSanctionReason::FloodPeerListResponse);
+        global_state
+            .write_peer_standing_on_decrease(ips[0], new_standing)
+            .await;
+        assert_eq!(
+            new_standing,
+            global_state
+                .net
+                .get_peer_standing_from_database(ips[0])
                 .await
-                .synced_unspent_available_amount(launch + seven_months)
         );
+        assert_eq!(3, stored_standings_count(&global_state));
 
-        // Make two transactions: Alice sends two UTXOs to Genesis and Bob sends three UTXOs to genesis
-        let receiver_data_from_alice = vec![
-            UtxoReceiverData {
-                utxo: Utxo {
-                    lock_script_hash: genesis_spending_key.to_address().lock_script().hash(),
-                    coins: NeptuneCoins::new(50).to_native_coins(),
-                },
-                sender_randomness: rng.gen(),
-                receiver_privacy_digest: genesis_spending_key.to_address().privacy_digest,
-                public_announcement: PublicAnnouncement::default(),
-            },
-            UtxoReceiverData {
-                utxo: Utxo {
-                    lock_script_hash: genesis_spending_key.to_address().lock_script().hash(),
-                    coins: NeptuneCoins::new(49).to_native_coins(),
-                },
-                sender_randomness: rng.gen(),
-                receiver_privacy_digest: genesis_spending_key.to_address().privacy_digest,
-                public_announcement: PublicAnnouncement::default(),
-            },
-        ];
-        let now = genesis_block.kernel.header.timestamp;
-        let tx_from_alice = alice_state_lock
-            .lock_guard_mut()
-            .await
-            .create_transaction(receiver_data_from_alice.clone(), NeptuneCoins::new(1), now)
-            .await
-            .unwrap();
-        let receiver_data_from_bob = vec![
-            UtxoReceiverData {
-                utxo: Utxo {
-                    lock_script_hash: genesis_spending_key.to_address().lock_script().hash(),
-                    coins: NeptuneCoins::new(50).to_native_coins(),
-                },
-                sender_randomness: rng.gen(),
-                receiver_privacy_digest: genesis_spending_key.to_address().privacy_digest,
-                public_announcement: PublicAnnouncement::default(),
-            },
-            UtxoReceiverData {
-                utxo: Utxo {
-                    lock_script_hash: genesis_spending_key.to_address().lock_script().hash(),
-                    coins: NeptuneCoins::new(50).to_native_coins(),
-                },
-                sender_randomness: rng.gen(),
-                receiver_privacy_digest: genesis_spending_key.to_address().privacy_digest,
-                public_announcement: PublicAnnouncement::default(),
-            },
-            UtxoReceiverData {
-                utxo: Utxo {
-                    lock_script_hash: genesis_spending_key.to_address().lock_script().hash(),
-                    coins: NeptuneCoins::new(98).to_native_coins(),
-                },
-                sender_randomness: rng.gen(),
-                receiver_privacy_digest: genesis_spending_key.to_address().privacy_digest,
-                public_announcement: PublicAnnouncement::default(),
-            },
-        ];
-        let tx_from_bob = bob_state_lock
-            .lock_guard_mut()
-            .await
-            .create_transaction(receiver_data_from_bob.clone(), NeptuneCoins::new(2), now)
-            .await
-            .unwrap();
+        // A standing that is not worse than the default is not stored
+        global_state
+            .write_peer_standing_on_decrease(never_seen_ip, PeerStanding::default())
+            .await;
+        assert_eq!(3, stored_standings_count(&global_state));
 
-        // Make block_2 with tx that contains:
-        // - 4 inputs: 2 from Alice and 2 from Bob
-        // - 6 outputs: 2 from Alice to Genesis, 3 from Bob to Genesis, and 1 coinbase to Genesis
-        let (mut block_2, _cb_utxo_block_2, _cb_sender_randomness_block_2) =
-            make_mock_block_with_valid_pow(
-                &block_1,
+        global_state.net.clear_all_standings_in_database().await;
+        assert_eq!(0, stored_standings_count(&global_state));
+        for ip in ips.iter() {
+            assert_eq!(
+                PeerStanding::default(),
+                global_state.net.get_peer_standing_from_database(*ip).await
+            );
+        }
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn resolve_digest_returns_all_candidates_of_ambiguous_prefix_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 0, WalletSecret::new_random()).await;
+        let mut global_state = global_state_lock.lock_guard_mut().await;
+        let mut rng = thread_rng();
+        let other_receiving_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        // With more blocks than hex digits, some blocks share the first digit
+        let mut blocks = vec![Block::genesis_block(network)];
+        for _ in 0..16 {
+            let (next_block, _, _) = make_mock_block(
+                network,
+                blocks.last().unwrap(),
                 None,
-                genesis_spending_key.to_address(),
+                other_receiving_address,
                 rng.gen(),
             );
-        block_2
-            .accumulate_transaction(tx_from_alice, &block_1.kernel.body.mutator_set_accumulator)
-            .await;
-        assert_eq!(2, block_2.kernel.body.transaction.kernel.inputs.len());
-        assert_eq!(3, block_2.kernel.body.transaction.kernel.outputs.len());
+            global_state.set_new_tip(next_block.clone()).await.unwrap();
+            blocks.push(next_block);
+        }
+        let block_digests_by_first_hex_digit = blocks
+            .iter()
+            .map(|block| block.hash())
+            .into_group_map_by(|digest| digest.to_hex()[..1].to_string());
+        let (ambiguous_prefix, ambiguous_digests) = block_digests_by_first_hex_digit
+            .iter()
+            .find(|(_, digests)| digests.len() > 1)
+            .unwrap();
 
-        block_2
-            .accumulate_transaction(tx_from_bob, &block_1.kernel.body.mutator_set_accumulator)
+        let candidates = global_state
+            .resolve_digest(&HexPrefix::parse(ambiguous_prefix).unwrap())
             .await;
+        let resolved_block_digests = candidates
+            .iter()
+            .filter(|candidate| candidate.kind == DigestKind::Block)
+            .map(|candidate| candidate.digest)
+            .sorted()
+            .collect_vec();
+        assert_eq!(
+            ambiguous_digests.iter().copied().sorted().collect_vec(),
+            resolved_block_digests
+        );
+
+        // The full digest is not ambiguous
+        let digest = blocks[7].hash();
+        assert_eq!(
+            vec![ResolvedDigest {
+                digest,
+                kind: DigestKind::Block
+            }],
+            global_state
+                .resolve_digest(&HexPrefix::parse(&digest.to_hex()).unwrap())
+                .await
+        );
     }
 
     #[traced_test]
     #[tokio::test]
-    async fn mock_global_state_is_valid() {
-        let mut rng = thread_rng();
+    async fn relayed_transaction_with_negative_fee_is_not_inserted_test() {
         let network = Network::RegTest;
         let global_state_lock =
             mock_genesis_global_state(network, 2, WalletSecret::devnet_wallet()).await;
         let mut global_state = global_state_lock.lock_guard_mut().await;
-        let genesis_block = Block::genesis_block(network);
-        let now = genesis_block.kernel.header.timestamp;
-
-        let wallet_secret = WalletSecret::new_random();
-        let receiving_address = wallet_secret.nth_generation_spending_key(0).to_address();
-        let (block_1, _cb_utxo, _cb_output_randomness) =
-            make_mock_block_with_valid_pow(&genesis_block, None, receiving_address, rng.gen());
-
-        global_state.set_new_tip(block_1).await.unwrap();
+        let tip_digest = global_state.chain.light_state().hash();
 
-        assert!(global_state
-            .chain
-            .light_state()
-            .is_valid(&genesis_block, now));
+        let mut transaction = make_mock_transaction(vec![], vec![]);
+        transaction.kernel.fee = -NeptuneCoins::new(1);
+        let relayed_with_negative_fee = PeerThreadToMainTransaction {
+            transaction: transaction.clone(),
+            confirmable_for_block: tip_digest,
+        };
+        assert!(!global_state.insert_relayed_transaction(&relayed_with_negative_fee));
+        assert!(global_state.mempool.is_empty());
+
+        // The same transaction without the negative fee is inserted
+        transaction.kernel.fee = NeptuneCoins::zero();
+        let relayed_without_fee = PeerThreadToMainTransaction {
+            transaction,
+            confirmable_for_block: tip_digest,
+        };
+        assert!(global_state.insert_relayed_transaction(&relayed_without_fee));
+        assert_eq!(1, global_state.mempool.len());
     }
 }
@@ -1,12 +1,86 @@
 use crate::config_models::data_directory::DataDirectory;
 use crate::database::{create_db_if_missing, NeptuneLevelDb, WriteBatchAsync};
-use crate::models::database::PeerDatabases;
-use crate::models::peer::{self, PeerStanding};
+use crate::models::blockchain::shared::Hash;
+use crate::models::blockchain::transaction::Transaction;
+use crate::models::channel::MinedBlockTransactions;
+use crate::models::database::{DbStats, PeerDatabaseGcReport, PeerDatabases};
+use crate::models::peer::{self, BlockServingPolicy, BlockServingQuota, KnownPeer, PeerStanding};
+use crate::models::state::address_book::{
+    AddressBucket, AddressRecord, ADDRESS_BOOK_CAPACITY, MAX_OUTBOUND_PEERS_PER_BUCKET,
+};
+use crate::models::state::chain_split_detector::ChainSplitDetector;
+use crate::models::state::header_chain::HeaderChain;
+use crate::prelude::twenty_first;
 use anyhow::Result;
+use itertools::Itertools;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::net::IpAddr;
-use std::{collections::HashMap, net::SocketAddr};
+use std::time::{Duration, SystemTime};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+};
+use twenty_first::math::digest::Digest;
+use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 
 pub const BANNED_IPS_DB_NAME: &str = "banned_ips";
+pub const KNOWN_PEERS_DB_NAME: &str = "known_peers";
+pub const ADDRESS_BOOK_DB_NAME: &str = "address_book";
+
+/// Maximum number of peers that this node completed a handshake with that are
+/// remembered. Once full, the least recently seen peer is forgotten in favor
+/// of a new one.
+pub const KNOWN_PEERS_CAPACITY: usize = 1_000;
+
+/// Number of blocks mined by this node whose hashes are remembered, such that
+/// peers echoing them back can be recognized.
+pub const RECENTLY_MINED_BLOCKS_CAPACITY: usize = 16;
+
+/// Number of transactions announced by peers whose IDs are remembered, such
+/// that the same transaction is not requested again from every peer relaying
+/// it.
+pub const RECENTLY_SEEN_TRANSACTIONS_CAPACITY: usize = 10_000;
+
+/// Duration after which a transaction that was requested from one peer is
+/// requested from another peer that announced it.
+pub const TRANSACTION_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of further announcers remembered per requested transaction,
+/// to fall back to if the requested peer does not deliver.
+pub const MAX_FALLBACK_TRANSACTION_ANNOUNCERS: usize = 8;
+
+/// Delay before redialing an address after its first `Bye` or failed
+/// connection. Each further one doubles the delay.
+pub const DIAL_BACKOFF_BASE: Duration = Duration::from_secs(5);
+
+/// Maximum delay before redialing an address.
+pub const DIAL_BACKOFF_CAP: Duration = Duration::from_secs(30 * 60);
+
+/// Connections that last at least this long reset the backoff of the address.
+pub const SUSTAINED_CONNECTION_DURATION: Duration = Duration::from_secs(10 * 60);
+
+/// Backoffs of addresses that have not failed for this long are forgotten, such
+/// that the backoffs of addresses that are never redialed don't accumulate.
+pub const DIAL_BACKOFF_RETENTION: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// Track record of an address that recently said `Bye` or could not be
+/// connected to.
+#[derive(Debug, Clone, Copy)]
+struct DialBackoff {
+    failure_count: u32,
+    last_failure: SystemTime,
+}
+
+/// A transaction that was requested from a peer and has not arrived yet.
+#[derive(Debug, Clone)]
+struct TransactionRequest {
+    peer_address: SocketAddr,
+    requested_at: SystemTime,
+
+    // Further peers that announced the transaction, oldest first
+    fallback_announcers: VecDeque<SocketAddr>,
+}
 
 type PeerMap = HashMap<SocketAddr, peer::PeerInfo>;
 
@@ -18,7 +92,8 @@ pub struct NetworkingState {
     // Peer threads may update their own entries into this map.
     pub peer_map: PeerMap,
 
-    // `peer_databases` are used to persist IPs with their standing.
+    // `peer_databases` are used to persist IPs with their standing, and the
+    // peers that this node completed a handshake with.
     // The peer threads may update their own entries into this map.
     pub peer_databases: PeerDatabases,
 
@@ -27,21 +102,471 @@ pub struct NetworkingState {
     // Only the main thread may update this flag
     pub syncing: bool,
 
+    // True while the node is quiesced for maintenance, e.g. a disk snapshot.
+    // Mining, the periodic jobs, and peer messages that would change the
+    // state are then deferred. Only the RPC server may update this flag.
+    pub quiesced: bool,
+
     // Read-only value set during startup
     pub instance_id: u128,
+
+    // Addresses that turned out to lead back to this node, e.g. through a
+    // relay or its own advertised address. They are never dialed again.
+    // Only the connection handlers may add entries to this set.
+    pub do_not_dial: HashSet<SocketAddr>,
+
+    // Addresses of the inbound connections accepted by the main thread, from
+    // the moment they are accepted until they are closed, such that
+    // connections that are still handshaking count towards the limits per
+    // host and subnet. Only the main thread may add entries.
+    inbound_connections: HashSet<SocketAddr>,
+
+    // Hashes of the blocks most recently mined by this node, oldest first.
+    // Peers commonly send these blocks back after they were broadcast.
+    // Only the main thread may add entries.
+    recently_mined_blocks: VecDeque<Digest>,
+
+    // Hash of the block most recently mined by this node, and the transactions
+    // merged into it. The mempool forgets these transactions once the block is
+    // the tip, but peers rebuilding the block from a compact block may still
+    // request them. Only the main thread may set this value.
+    latest_mined_block_transactions: Option<(Digest, MinedBlockTransactions)>,
+
+    // Backoff of addresses that recently said `Bye` or could not be connected
+    // to. The dialer waits for the backoff to expire before redialing them.
+    dial_backoffs: HashMap<SocketAddr, DialBackoff>,
+
+    // Tips announced by the connected peers, compared periodically to the
+    // canonical chain to detect chain splits.
+    pub chain_split_detector: ChainSplitDetector,
+
+    // IDs of the transactions most recently announced or sent by peers, oldest
+    // first, and for fast lookup. Peer threads add entries before requesting a
+    // transaction, such that only one of the peers relaying it is asked.
+    recently_seen_transactions: VecDeque<Digest>,
+    recently_seen_transaction_ids: HashSet<Digest>,
+
+    // Transactions requested from a peer that have not arrived yet. Requests
+    // that time out are retried with another announcer by the main thread.
+    transaction_requests: HashMap<Digest, TransactionRequest>,
+
+    // Validated headers of the blocks to download while syncing. Filled by
+    // the peer threads, and emptied by the main thread as blocks are stored
+    // and when syncing ends.
+    pub header_chain: HeaderChain,
+
+    // Copy of the address book database, such that it can be kept within its
+    // capacity, and addresses can be picked to dial, without reading the
+    // database. Writes go to both.
+    address_records: HashMap<SocketAddr, AddressRecord>,
+
+    // Blocks served to each IP address, limited by this node's serving
+    // policy. Kept across connections, such that reconnecting does not reset
+    // the quota. Peer threads update the entry of their peer.
+    served_block_quotas: HashMap<IpAddr, BlockServingQuota>,
+
+    // Number of peers in the known peers database, such that it can be kept
+    // within its capacity without counting the entries.
+    known_peers_size: usize,
+
+    // Outcome of the garbage collection of the peer databases on startup.
+    // Only set during startup.
+    pub latest_peer_database_gc: Option<PeerDatabaseGcReport>,
+
+    // Since when this node has been without connected peers, or `None` while
+    // it has peers, and whether the user was warned about it. Only the main
+    // thread may update these values.
+    peerless_since: Option<SystemTime>,
+    peerless_warning_issued: bool,
 }
 
 impl NetworkingState {
     pub fn new(peer_map: PeerMap, peer_databases: PeerDatabases, syncing: bool) -> Self {
+        let address_records = peer_databases.address_book.iter().collect();
+        let known_peers_size = peer_databases.known_peers.iter().count();
         Self {
             peer_map,
             peer_databases,
             syncing,
+            quiesced: false,
             instance_id: rand::random(),
+            do_not_dial: HashSet::new(),
+            inbound_connections: HashSet::new(),
+            recently_mined_blocks: VecDeque::new(),
+            latest_mined_block_transactions: None,
+            dial_backoffs: HashMap::new(),
+            chain_split_detector: ChainSplitDetector::default(),
+            recently_seen_transactions: VecDeque::new(),
+            recently_seen_transaction_ids: HashSet::new(),
+            transaction_requests: HashMap::new(),
+            header_chain: HeaderChain::default(),
+            address_records,
+            served_block_quotas: HashMap::new(),
+            known_peers_size,
+            latest_peer_database_gc: None,
+            peerless_since: None,
+            peerless_warning_issued: false,
+        }
+    }
+
+    /// Record whether this node has connected peers at time `now`. Returns
+    /// true iff it has been without peers for longer than `threshold`, and
+    /// this was not reported before, such that it is reported only once per
+    /// peerless period.
+    pub fn record_peer_count(&mut self, now: SystemTime, threshold: Duration) -> bool {
+        if !self.peer_map.is_empty() {
+            self.peerless_since = None;
+            self.peerless_warning_issued = false;
+            return false;
+        }
+
+        self.peerless_since.get_or_insert(now);
+        if self.peerless_warning_issued || self.peerless_alert(now, threshold).is_none() {
+            return false;
         }
+
+        self.peerless_warning_issued = true;
+        true
+    }
+
+    /// The time since which this node has been without connected peers, if it
+    /// has been for longer than `threshold` at time `now`.
+    pub fn peerless_alert(&self, now: SystemTime, threshold: Duration) -> Option<SystemTime> {
+        self.peerless_since.filter(|since| {
+            now.duration_since(*since)
+                .is_ok_and(|peerless_duration| peerless_duration > threshold)
+        })
+    }
+
+    /// Record that the peer at the given address said `Bye`, or that a
+    /// connection to it failed, which doubles its backoff.
+    pub fn record_dial_failure(&mut self, address: SocketAddr) {
+        self.prune_dial_backoffs(SystemTime::now());
+
+        let backoff = self.dial_backoffs.entry(address).or_insert(DialBackoff {
+            failure_count: 0,
+            last_failure: SystemTime::now(),
+        });
+        backoff.failure_count = backoff.failure_count.saturating_add(1);
+        backoff.last_failure = SystemTime::now();
+    }
+
+    /// Forget the backoff of the given address, e.g. after a sustained
+    /// connection.
+    pub fn reset_dial_backoff(&mut self, address: SocketAddr) {
+        self.dial_backoffs.remove(&address);
     }
 
-    /// Create databases for peer standings
+    /// Forget the backoffs of the addresses whose last failure is more than
+    /// [`DIAL_BACKOFF_RETENTION`] before `now`.
+    pub fn prune_dial_backoffs(&mut self, now: SystemTime) {
+        self.dial_backoffs.retain(|_address, backoff| {
+            now.duration_since(backoff.last_failure)
+                .map(|elapsed| elapsed < DIAL_BACKOFF_RETENTION)
+                .unwrap_or(true)
+        });
+    }
+
+    /// The time to wait after the last failure before redialing the given
+    /// address. Zero for addresses without failures.
+    pub fn next_dial_delay(&self, address: SocketAddr) -> Duration {
+        let Some(backoff) = self.dial_backoffs.get(&address) else {
+            return Duration::ZERO;
+        };
+
+        let factor = 1u32
+            .checked_shl(backoff.failure_count - 1)
+            .unwrap_or(u32::MAX);
+        DIAL_BACKOFF_BASE
+            .saturating_mul(factor)
+            .min(DIAL_BACKOFF_CAP)
+    }
+
+    /// Determine whether the backoff of the given address has expired.
+    pub fn may_dial(&self, address: SocketAddr, now: SystemTime) -> bool {
+        let Some(backoff) = self.dial_backoffs.get(&address) else {
+            return true;
+        };
+
+        // If the clock went backwards, don't block the address indefinitely
+        now.duration_since(backoff.last_failure)
+            .map(|elapsed| elapsed >= self.next_dial_delay(address))
+            .unwrap_or(true)
+    }
+
+    /// Remember that this node mined the block with the given hash. Only the
+    /// last `RECENTLY_MINED_BLOCKS_CAPACITY` blocks are remembered.
+    pub fn record_mined_block(&mut self, block_hash: Digest) {
+        if self.recently_mined_blocks.contains(&block_hash) {
+            return;
+        }
+
+        if self.recently_mined_blocks.len() >= RECENTLY_MINED_BLOCKS_CAPACITY {
+            self.recently_mined_blocks.pop_front();
+        }
+        self.recently_mined_blocks.push_back(block_hash);
+    }
+
+    /// Determine whether the block with the given hash was recently mined by
+    /// this node.
+    pub fn is_recently_mined_block(&self, block_hash: Digest) -> bool {
+        self.recently_mined_blocks.contains(&block_hash)
+    }
+
+    /// Remember that a peer announced or sent the transaction with the given
+    /// ID. Only the last `RECENTLY_SEEN_TRANSACTIONS_CAPACITY` transactions
+    /// are remembered. Returns false if the transaction was seen already.
+    pub fn record_seen_transaction(&mut self, transaction_id: Digest) -> bool {
+        if !self.recently_seen_transaction_ids.insert(transaction_id) {
+            return false;
+        }
+
+        if self.recently_seen_transactions.len() >= RECENTLY_SEEN_TRANSACTIONS_CAPACITY {
+            if let Some(forgotten) = self.recently_seen_transactions.pop_front() {
+                self.recently_seen_transaction_ids.remove(&forgotten);
+            }
+        }
+        self.recently_seen_transactions.push_back(transaction_id);
+
+        true
+    }
+
+    /// Register that a peer announced the transaction with the given ID at
+    /// time `now`. Returns true if the transaction must be requested from the
+    /// peer, because it was not seen before. If the transaction is being
+    /// requested from another peer already, this peer is remembered as a
+    /// fallback in case the other peer does not deliver.
+    pub fn request_announced_transaction(
+        &mut self,
+        transaction_id: Digest,
+        peer_address: SocketAddr,
+        now: SystemTime,
+    ) -> bool {
+        if let Some(request) = self.transaction_requests.get_mut(&transaction_id) {
+            if request.peer_address != peer_address
+                && !request.fallback_announcers.contains(&peer_address)
+                && request.fallback_announcers.len() < MAX_FALLBACK_TRANSACTION_ANNOUNCERS
+            {
+                request.fallback_announcers.push_back(peer_address);
+            }
+            return false;
+        }
+
+        if !self.record_seen_transaction(transaction_id) {
+            return false;
+        }
+        self.transaction_requests.insert(
+            transaction_id,
+            TransactionRequest {
+                peer_address,
+                requested_at: now,
+                fallback_announcers: VecDeque::new(),
+            },
+        );
+
+        true
+    }
+
+    /// Remember that a peer sent the transaction with the given ID, such that
+    /// it is not requested again.
+    pub fn record_received_transaction(&mut self, transaction_id: Digest) {
+        self.record_seen_transaction(transaction_id);
+        self.transaction_requests.remove(&transaction_id);
+    }
+
+    /// Move the requests that timed out at time `now` on to the next peer that
+    /// announced the transaction, and return the transaction IDs with the
+    /// peers to request them from. Requests without further announcers are
+    /// abandoned, and the transaction is forgotten, such that it is requested
+    /// from the next peer announcing it.
+    pub fn retry_timed_out_transaction_requests(
+        &mut self,
+        now: SystemTime,
+    ) -> Vec<(Digest, SocketAddr)> {
+        let mut retries = vec![];
+        let mut abandoned = vec![];
+        for (transaction_id, request) in self.transaction_requests.iter_mut() {
+            if now.duration_since(request.requested_at).unwrap_or_default()
+                < TRANSACTION_REQUEST_TIMEOUT
+            {
+                continue;
+            }
+
+            match request.fallback_announcers.pop_front() {
+                Some(peer_address) => {
+                    request.peer_address = peer_address;
+                    request.requested_at = now;
+                    retries.push((*transaction_id, peer_address));
+                }
+                None => abandoned.push(*transaction_id),
+            }
+        }
+
+        for transaction_id in abandoned {
+            self.transaction_requests.remove(&transaction_id);
+            self.recently_seen_transaction_ids.remove(&transaction_id);
+            self.recently_seen_transactions
+                .retain(|seen_transaction_id| *seen_transaction_id != transaction_id);
+        }
+
+        retries
+    }
+
+    /// Determine whether a peer recently announced or sent the transaction
+    /// with the given ID.
+    pub fn is_recently_seen_transaction(&self, transaction_id: Digest) -> bool {
+        self.recently_seen_transaction_ids.contains(&transaction_id)
+    }
+
+    /// Remember the transactions merged into the block with the given hash,
+    /// which was mined by this node. Replaces those of the previous block.
+    pub fn record_mined_block_transactions(
+        &mut self,
+        block_hash: Digest,
+        block_transactions: MinedBlockTransactions,
+    ) {
+        self.latest_mined_block_transactions = Some((block_hash, block_transactions));
+    }
+
+    /// The transactions merged into the block with the given hash, if it is
+    /// the block most recently mined by this node.
+    pub fn mined_block_transactions(&self, block_hash: Digest) -> Option<&MinedBlockTransactions> {
+        self.latest_mined_block_transactions
+            .as_ref()
+            .filter(|(mined_block_hash, _)| *mined_block_hash == block_hash)
+            .map(|(_, block_transactions)| block_transactions)
+    }
+
+    /// Find the transaction with the given id among the mempool transactions
+    /// merged into the block most recently mined by this node.
+    pub fn mined_block_transaction(&self, transaction_id: Digest) -> Option<&Transaction> {
+        self.latest_mined_block_transactions
+            .as_ref()
+            .and_then(|(_, block_transactions)| {
+                block_transactions
+                    .mempool_transactions
+                    .iter()
+                    .find(|transaction| Hash::hash(*transaction) == transaction_id)
+            })
+    }
+
+    /// Number of outbound peers in each network bucket.
+    pub fn outbound_peers_per_bucket(&self) -> HashMap<AddressBucket, usize> {
+        let mut outbound_peers_per_bucket = HashMap::new();
+        for peer in self.peer_map.values().filter(|peer| !peer.inbound) {
+            *outbound_peers_per_bucket
+                .entry(AddressBucket::from(peer.connected_address.ip()))
+                .or_insert(0) += 1;
+        }
+
+        outbound_peers_per_bucket
+    }
+
+    /// Determine whether the network bucket of the given address already
+    /// holds the maximum number of outbound peers.
+    pub fn outbound_bucket_is_full(&self, address: SocketAddr) -> bool {
+        self.outbound_peers_per_bucket()
+            .get(&AddressBucket::from(address.ip()))
+            .is_some_and(|count| *count >= MAX_OUTBOUND_PEERS_PER_BUCKET)
+    }
+
+    /// Count the inbound connections from the given IP address, and from its
+    /// network bucket, respectively. Connections that are still handshaking
+    /// are counted as well as connected peers.
+    pub fn inbound_peer_counts_from(&self, ip: IpAddr) -> (usize, usize) {
+        let ip = ip.to_canonical();
+        let bucket = AddressBucket::from(ip);
+        let inbound_addresses: HashSet<SocketAddr> = self
+            .peer_map
+            .values()
+            .filter(|peer| peer.inbound)
+            .map(|peer| peer.connected_address)
+            .chain(self.inbound_connections.iter().copied())
+            .collect();
+        let mut from_ip = 0;
+        let mut from_bucket = 0;
+        for address in inbound_addresses {
+            let peer_ip = address.ip().to_canonical();
+            if peer_ip == ip {
+                from_ip += 1;
+            }
+            if AddressBucket::from(peer_ip) == bucket {
+                from_bucket += 1;
+            }
+        }
+
+        (from_ip, from_bucket)
+    }
+
+    /// Count the inbound connection from the given address towards the limits
+    /// per host and subnet until it is released.
+    pub fn register_inbound_connection(&mut self, peer_address: SocketAddr) {
+        self.inbound_connections.insert(peer_address);
+    }
+
+    /// Stop counting the closed inbound connection from the given address.
+    pub fn release_inbound_connection(&mut self, peer_address: SocketAddr) {
+        self.inbound_connections.remove(&peer_address);
+    }
+
+    /// Number of connected peers that connected to this node if `inbound`,
+    /// or that this node connected to otherwise.
+    pub fn peer_count(&self, inbound: bool) -> usize {
+        self.peer_map
+            .values()
+            .filter(|peer| peer.inbound == inbound)
+            .count()
+    }
+
+    /// How long the given IP address has to wait at time `now` before it is
+    /// served more blocks under the given policy, or None if its quota is not
+    /// used up.
+    pub fn block_serving_retry_after(
+        &mut self,
+        ip: IpAddr,
+        policy: &BlockServingPolicy,
+        now: tokio::time::Instant,
+    ) -> Option<Duration> {
+        self.served_block_quotas
+            .get_mut(&ip.to_canonical())
+            .and_then(|quota| quota.retry_after(policy, now))
+    }
+
+    /// Record blocks of the given sizes as served to the given IP address at
+    /// time `now`, and forget the quotas whose blocks all left the window.
+    pub fn record_served_blocks(
+        &mut self,
+        ip: IpAddr,
+        block_sizes: &[usize],
+        policy: &BlockServingPolicy,
+        now: tokio::time::Instant,
+    ) {
+        self.served_block_quotas
+            .retain(|_ip, quota| !quota.expire(policy, now));
+        let quota = self
+            .served_block_quotas
+            .entry(ip.to_canonical())
+            .or_default();
+        for size in block_sizes {
+            quota.record(now, *size as u64);
+        }
+    }
+
+    /// The connected peers that asked this node not to request blocks from
+    /// them until after `now`.
+    pub fn peers_pausing_block_requests(&self, now: SystemTime) -> HashSet<SocketAddr> {
+        self.peer_map
+            .iter()
+            .filter(|(_, peer)| {
+                peer.statistics
+                    .block_requests_paused_until
+                    .is_some_and(|paused_until| paused_until > now)
+            })
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    /// Create databases for peer standings and known peers
     pub async fn initialize_peer_databases(data_dir: &DataDirectory) -> Result<PeerDatabases> {
         let database_dir_path = data_dir.database_dir_path();
         DataDirectory::create_dir_if_not_exists(&database_dir_path).await?;
@@ -52,7 +577,227 @@ impl NetworkingState {
         )
         .await?;
 
-        Ok(PeerDatabases { peer_standings })
+        let known_peers = NeptuneLevelDb::<SocketAddr, KnownPeer>::new(
+            &data_dir.known_peers_database_dir_path(),
+            &create_db_if_missing(),
+        )
+        .await?;
+
+        let address_book = NeptuneLevelDb::<SocketAddr, AddressRecord>::new(
+            &data_dir.address_book_database_dir_path(),
+            &create_db_if_missing(),
+        )
+        .await?;
+
+        Ok(PeerDatabases {
+            peer_standings,
+            known_peers,
+            address_book,
+        })
+    }
+
+    /// Return all peers that this node completed a handshake with, keyed by
+    /// the address they listen on.
+    pub async fn all_known_peers_in_database(&self) -> Vec<(SocketAddr, KnownPeer)> {
+        self.peer_databases.known_peers.iter().collect()
+    }
+
+    /// Record a peer that this node completed a handshake with, replacing any
+    /// previous record for the same listen address. Once the database is
+    /// full, the least recently seen peer is forgotten to make room.
+    pub async fn write_known_peer(&mut self, listen_address: SocketAddr, known_peer: KnownPeer) {
+        let is_new = self
+            .peer_databases
+            .known_peers
+            .get(listen_address)
+            .await
+            .is_none();
+        if is_new && self.known_peers_size >= KNOWN_PEERS_CAPACITY {
+            let least_recently_seen = self
+                .peer_databases
+                .known_peers
+                .iter()
+                .min_by_key(|(_listen_address, known_peer)| known_peer.last_seen)
+                .map(|(listen_address, _known_peer)| listen_address);
+            if let Some(evicted_address) = least_recently_seen {
+                self.peer_databases
+                    .known_peers
+                    .delete(evicted_address)
+                    .await;
+                self.known_peers_size -= 1;
+            }
+        }
+        if is_new {
+            self.known_peers_size += 1;
+        }
+
+        self.peer_databases
+            .known_peers
+            .put(listen_address, known_peer)
+            .await
+    }
+
+    /// Return all addresses in the address book.
+    pub async fn all_addresses_in_address_book(&self) -> Vec<(SocketAddr, AddressRecord)> {
+        self.address_records
+            .iter()
+            .map(|(address, record)| (*address, *record))
+            .collect()
+    }
+
+    /// Add addresses learned from a peer list to the address book, or
+    /// refresh when known addresses were last seen. Once the address book is
+    /// full, a new address replaces the entry with the worst track record,
+    /// see [AddressRecord::eviction_priority].
+    pub async fn add_to_address_book(
+        &mut self,
+        addresses: impl IntoIterator<Item = SocketAddr>,
+        now: SystemTime,
+    ) {
+        for address in addresses {
+            let record = match self.address_records.get(&address).copied() {
+                Some(record) => AddressRecord {
+                    last_seen: now,
+                    ..record
+                },
+                None => {
+                    if self.address_records.len() >= ADDRESS_BOOK_CAPACITY {
+                        self.evict_from_address_book().await;
+                    }
+                    AddressRecord::new(now)
+                }
+            };
+            self.put_address_record(address, record).await;
+        }
+    }
+
+    /// Remove the address book entry with the worst track record.
+    async fn evict_from_address_book(&mut self) {
+        let Some(evicted) = self
+            .address_records
+            .iter()
+            .max_by_key(|(_address, record)| record.eviction_priority())
+            .map(|(address, _record)| *address)
+        else {
+            return;
+        };
+
+        self.address_records.remove(&evicted);
+        self.peer_databases.address_book.delete(evicted).await;
+    }
+
+    async fn put_address_record(&mut self, address: SocketAddr, record: AddressRecord) {
+        self.address_records.insert(address, record);
+        self.peer_databases.address_book.put(address, record).await;
+    }
+
+    /// Record that a handshake with the peer listening on the given address
+    /// was completed, adding the address to the address book if needed.
+    pub async fn record_address_success(&mut self, address: SocketAddr, now: SystemTime) {
+        if !self.address_records.contains_key(&address)
+            && self.address_records.len() >= ADDRESS_BOOK_CAPACITY
+        {
+            self.evict_from_address_book().await;
+        }
+        let record = AddressRecord {
+            last_seen: now,
+            last_success: Some(now),
+            failure_count: 0,
+        };
+        self.put_address_record(address, record).await;
+    }
+
+    /// Record a failed attempt to connect to the given address. Addresses
+    /// that never led to a handshake are forgotten after too many failures.
+    pub async fn record_address_failure(&mut self, address: SocketAddr) {
+        let Some(mut record) = self.address_records.get(&address).copied() else {
+            return;
+        };
+
+        record.failure_count = record.failure_count.saturating_add(1);
+        if record.is_hopeless() {
+            self.address_records.remove(&address);
+            self.peer_databases.address_book.delete(address).await;
+        } else {
+            self.put_address_record(address, record).await;
+        }
+    }
+
+    /// Remove the entries of the peer databases that were last updated more
+    /// than `retention` before `now`, and compact the key ranges they
+    /// occupied. The entries of the pinned addresses, and the standings for
+    /// which `is_banned` holds, are kept.
+    pub async fn garbage_collect_peer_databases(
+        &mut self,
+        now: SystemTime,
+        retention: Duration,
+        pinned_addresses: &[SocketAddr],
+        is_banned: impl Fn(&PeerStanding) -> bool,
+    ) -> PeerDatabaseGcReport {
+        let cutoff = now.checked_sub(retention).unwrap_or(SystemTime::UNIX_EPOCH);
+        let is_pinned_ip = |ip: IpAddr| pinned_addresses.iter().any(|address| address.ip() == ip);
+
+        let stale_ips = self
+            .peer_databases
+            .peer_standings
+            .iter()
+            .filter(|(ip, standing)| {
+                !standing
+                    .timestamp_of_latest_sanction
+                    .is_some_and(|sanctioned_at| sanctioned_at >= cutoff)
+                    && !is_banned(standing)
+                    && !is_pinned_ip(*ip)
+            })
+            .map(|(ip, _standing)| ip)
+            .collect_vec();
+        let stale_known_peers = self
+            .peer_databases
+            .known_peers
+            .iter()
+            .filter(|(listen_address, known_peer)| {
+                known_peer.last_seen < cutoff && !pinned_addresses.contains(listen_address)
+            })
+            .map(|(listen_address, _known_peer)| listen_address)
+            .collect_vec();
+        let stale_addresses = self
+            .address_records
+            .iter()
+            .filter(|(address, record)| {
+                record.last_seen < cutoff && !pinned_addresses.contains(address)
+            })
+            .map(|(address, _record)| *address)
+            .collect_vec();
+
+        let report = PeerDatabaseGcReport {
+            collected_at: now,
+            retention,
+            peer_standings_removed: stale_ips.len(),
+            known_peers_removed: stale_known_peers.len(),
+            addresses_removed: stale_addresses.len(),
+        };
+
+        delete_and_compact(&mut self.peer_databases.peer_standings, stale_ips).await;
+        delete_and_compact(&mut self.peer_databases.known_peers, stale_known_peers).await;
+        for address in stale_addresses.iter() {
+            self.address_records.remove(address);
+        }
+        delete_and_compact(&mut self.peer_databases.address_book, stale_addresses).await;
+        self.known_peers_size = self
+            .known_peers_size
+            .saturating_sub(report.known_peers_removed);
+
+        report
+    }
+
+    /// Number of entries in each peer database, and the outcome of their
+    /// latest garbage collection.
+    pub fn db_stats(&self) -> DbStats {
+        DbStats {
+            peer_standings_count: self.peer_databases.peer_standings.iter().count(),
+            known_peers_count: self.known_peers_size,
+            address_book_count: self.address_records.len(),
+            latest_peer_database_gc: self.latest_peer_database_gc,
+        }
     }
 
     /// Return a list of peer sanctions stored in the database.
@@ -69,37 +814,44 @@ impl NetworkingState {
         sanctions
     }
 
-    pub async fn get_peer_standing_from_database(&self, ip: IpAddr) -> Option<PeerStanding> {
-        self.peer_databases.peer_standings.get(ip).await
+    /// Return the stored standing of the IP. Peers without a stored standing,
+    /// whether never seen or cleared, have the default standing.
+    pub async fn get_peer_standing_from_database(&self, ip: IpAddr) -> PeerStanding {
+        self.peer_databases
+            .peer_standings
+            .get(ip)
+            .await
+            .unwrap_or_default()
     }
 
+    /// Forget the stored standing of the IP, such that it is indistinguishable
+    /// from a peer that was never seen.
     pub async fn clear_ip_standing_in_database(&mut self, ip: IpAddr) {
-        let old_standing = self.peer_databases.peer_standings.get(ip).await;
-
-        if old_standing.is_some() {
-            self.peer_databases
-                .peer_standings
-                .put(ip, PeerStanding::default())
-                .await
-        }
+        self.peer_databases.peer_standings.delete(ip).await;
     }
 
+    /// Forget all stored standings.
     pub async fn clear_all_standings_in_database(&mut self) {
-        let new_entries: Vec<_> = self
+        let ips: Vec<_> = self
             .peer_databases
             .peer_standings
             .iter()
-            .map(|(ip, _old_standing)| (ip, PeerStanding::default()))
+            .map(|(ip, _old_standing)| ip)
             .collect();
 
         let mut batch = WriteBatchAsync::new();
-        for (ip, standing) in new_entries.into_iter() {
-            batch.op_write(ip, standing);
+        for ip in ips.into_iter() {
+            batch.op_delete(ip);
         }
 
         self.peer_databases.peer_standings.batch_write(batch).await
     }
 
+    /// Store the standing of the IP if it is worse than the stored one, or if
+    /// the stored one has expired according to `ban_duration` and the IP was
+    /// sanctioned since. The latter lets a new sanction ban a peer again after
+    /// its earlier ban expired, while an active ban is never replaced by a
+    /// milder standing.
     // Storing IP addresses is, according to this answer, not a violation of GDPR:
     // https://law.stackexchange.com/a/28609/45846
     // Wayback machine: https://web.archive.org/web/20220708143841/https://law.stackexchange.com/questions/28603/how-to-satisfy-gdprs-consent-requirement-for-ip-logging/28609
@@ -107,10 +859,17 @@ impl NetworkingState {
         &mut self,
         ip: IpAddr,
         current_standing: PeerStanding,
+        ban_duration: Option<Duration>,
     ) {
-        let old_standing = self.peer_databases.peer_standings.get(ip).await;
+        let old_standing = self.get_peer_standing_from_database(ip).await;
+        let old_standing_has_expired = ban_duration
+            .is_some_and(|ban_duration| old_standing.has_expired(ban_duration, SystemTime::now()));
 
-        if old_standing.is_none() || old_standing.unwrap().standing > current_standing.standing {
+        if old_standing.standing > current_standing.standing
+            || (old_standing_has_expired
+                && old_standing.timestamp_of_latest_sanction
+                    < current_standing.timestamp_of_latest_sanction)
+        {
             self.peer_databases
                 .peer_standings
                 .put(ip, current_standing)
@@ -118,3 +877,22 @@ impl NetworkingState {
         }
     }
 }
+
+/// Delete the given keys from the database, and compact the range they
+/// occupied to reclaim their space.
+async fn delete_and_compact<Key, Value>(database: &mut NeptuneLevelDb<Key, Value>, keys: Vec<Key>)
+where
+    Key: Serialize + DeserializeOwned + Copy + Send + Sync + 'static,
+    Value: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    if keys.is_empty() {
+        return;
+    }
+
+    let mut batch = WriteBatchAsync::new();
+    for key in keys.iter() {
+        batch.op_delete(*key);
+    }
+    database.batch_write(batch).await;
+    database.compact_range_of(keys).await;
+}
@@ -0,0 +1,254 @@
+use crate::prelude::twenty_first;
+
+use get_size::GetSize;
+use std::collections::HashMap;
+use tracing::{debug, info};
+use twenty_first::math::digest::Digest;
+use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
+
+use crate::models::blockchain::shared::Hash;
+use crate::models::blockchain::transaction::Transaction;
+use crate::models::consensus::timestamp::Timestamp;
+
+/// Default maximum memory used by the transactions in the [`OrphanPool`].
+pub const ORPHAN_POOL_MAX_SIZE_IN_BYTES: usize = 10_000_000;
+
+/// Default age, measured from when it was received, after which an orphan
+/// transaction is dropped if the block it was built against did not arrive.
+pub const ORPHAN_TRANSACTION_TTL_IN_SECS: u64 = 30 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Orphan {
+    transaction: Transaction,
+    size: usize,
+    received_at: Timestamp,
+}
+
+/// A bounded buffer of transactions that were built against a mutator set
+/// this node does not know yet, typically because a peer relays a
+/// transaction before this node has received the latest block.
+///
+/// Orphans are keyed by the hash of the mutator set they require. Once a
+/// block with that mutator set becomes the tip, its orphans can be taken out
+/// of the pool and, if still valid, be promoted into the mempool. Orphans
+/// whose block never arrives are dropped by age, and the oldest ones are
+/// dropped when the pool exceeds its maximum size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanPool {
+    max_total_size: usize,
+    ttl: Timestamp,
+    total_size: usize,
+
+    // Orphans by the mutator set hash they require, then by transaction ID
+    orphans: HashMap<Digest, HashMap<Digest, Orphan>>,
+}
+
+impl Default for OrphanPool {
+    fn default() -> Self {
+        Self::new(
+            ORPHAN_POOL_MAX_SIZE_IN_BYTES,
+            Timestamp::seconds(ORPHAN_TRANSACTION_TTL_IN_SECS),
+        )
+    }
+}
+
+impl OrphanPool {
+    pub fn new(max_total_size: usize, ttl: Timestamp) -> Self {
+        Self {
+            max_total_size,
+            ttl,
+            total_size: 0,
+            orphans: HashMap::new(),
+        }
+    }
+
+    /// Return the number of orphan transactions in the pool.
+    pub fn len(&self) -> usize {
+        self.orphans.values().map(|orphans| orphans.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+
+    /// Return the memory used by the orphan transactions in the pool.
+    pub fn size_in_bytes(&self) -> usize {
+        self.total_size
+    }
+
+    pub fn contains(&self, transaction_id: Digest) -> bool {
+        self.orphans
+            .values()
+            .any(|orphans| orphans.contains_key(&transaction_id))
+    }
+
+    /// Buffer a transaction until a block with the mutator set it was built
+    /// against arrives. Evicts the oldest orphans if the pool grows too
+    /// large. Returns false if the transaction was not buffered, because it
+    /// is already known or because it alone exceeds the pool's maximum size.
+    pub fn insert(&mut self, transaction: Transaction, now: Timestamp) -> bool {
+        let transaction_id = Hash::hash(&transaction);
+        let size = transaction.get_size();
+        if size > self.max_total_size || self.contains(transaction_id) {
+            return false;
+        }
+
+        debug!(
+            "Buffering orphan transaction {transaction_id} requiring mutator set {}",
+            transaction.kernel.mutator_set_hash
+        );
+        self.total_size += size;
+        self.orphans
+            .entry(transaction.kernel.mutator_set_hash)
+            .or_default()
+            .insert(
+                transaction_id,
+                Orphan {
+                    transaction,
+                    size,
+                    received_at: now,
+                },
+            );
+
+        while self.total_size > self.max_total_size {
+            self.evict_oldest();
+        }
+
+        true
+    }
+
+    /// Remove and return all orphans that were built against the mutator set
+    /// with the given hash. It is the caller's responsibility to revalidate
+    /// them.
+    pub fn take(&mut self, mutator_set_hash: Digest) -> Vec<Transaction> {
+        let Some(orphans) = self.orphans.remove(&mutator_set_hash) else {
+            return vec![];
+        };
+
+        orphans
+            .into_values()
+            .map(|orphan| {
+                self.total_size -= orphan.size;
+                orphan.transaction
+            })
+            .collect()
+    }
+
+    /// Remove the orphans that were received longer than the pool's time to
+    /// live before `now`. Returns the number of removed orphans.
+    pub fn prune_expired(&mut self, now: Timestamp) -> usize {
+        let cutoff = now - self.ttl;
+        let mut removed_count = 0;
+        for orphans in self.orphans.values_mut() {
+            orphans.retain(|_transaction_id, orphan| {
+                let is_expired = orphan.received_at <= cutoff;
+                if is_expired {
+                    self.total_size -= orphan.size;
+                    removed_count += 1;
+                }
+                !is_expired
+            });
+        }
+        self.orphans
+            .retain(|_mutator_set_hash, orphans| !orphans.is_empty());
+
+        if removed_count > 0 {
+            info!("Dropped {removed_count} expired orphan transactions");
+        }
+
+        removed_count
+    }
+
+    /// Remove the orphan that was received first.
+    fn evict_oldest(&mut self) {
+        let Some((mutator_set_hash, transaction_id)) = self
+            .orphans
+            .iter()
+            .flat_map(|(mutator_set_hash, orphans)| {
+                orphans
+                    .iter()
+                    .map(move |(transaction_id, orphan)| (mutator_set_hash, transaction_id, orphan))
+            })
+            .min_by_key(|(_mutator_set_hash, _transaction_id, orphan)| orphan.received_at)
+            .map(|(mutator_set_hash, transaction_id, _orphan)| {
+                (*mutator_set_hash, *transaction_id)
+            })
+        else {
+            return;
+        };
+
+        let orphans = self.orphans.get_mut(&mutator_set_hash).unwrap();
+        let orphan = orphans.remove(&transaction_id).unwrap();
+        if orphans.is_empty() {
+            self.orphans.remove(&mutator_set_hash);
+        }
+        self.total_size -= orphan.size;
+        debug!("Evicted orphan transaction {transaction_id} to keep orphan pool within its maximum size");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::shared::make_mock_transaction;
+
+    #[test]
+    fn take_returns_orphans_for_mutator_set_test() {
+        let mut orphan_pool = OrphanPool::default();
+        let now = Timestamp::now();
+        let transaction_a = make_mock_transaction(vec![], vec![]);
+        let transaction_b = make_mock_transaction(vec![], vec![]);
+        assert!(orphan_pool.insert(transaction_a.clone(), now));
+        assert!(orphan_pool.insert(transaction_b.clone(), now));
+        assert!(
+            !orphan_pool.insert(transaction_a.clone(), now),
+            "Known orphan must not be buffered twice"
+        );
+        assert_eq!(2, orphan_pool.len());
+
+        assert_eq!(
+            vec![transaction_a.clone()],
+            orphan_pool.take(transaction_a.kernel.mutator_set_hash)
+        );
+        assert!(orphan_pool
+            .take(transaction_a.kernel.mutator_set_hash)
+            .is_empty());
+        assert_eq!(1, orphan_pool.len());
+        assert_eq!(transaction_b.get_size(), orphan_pool.size_in_bytes());
+    }
+
+    #[test]
+    fn orphans_are_evicted_by_age_and_size_test() {
+        let transactions = (0..3)
+            .map(|_| make_mock_transaction(vec![], vec![]))
+            .collect::<Vec<_>>();
+        let ttl = Timestamp::minutes(30);
+        let mut orphan_pool =
+            OrphanPool::new(transactions[1].get_size() + transactions[2].get_size(), ttl);
+
+        // Exceeding the maximum size evicts the oldest orphan
+        let now = Timestamp::now();
+        for (i, transaction) in transactions.iter().enumerate() {
+            assert!(orphan_pool.insert(transaction.clone(), now + Timestamp::seconds(i as u64)));
+        }
+        assert_eq!(2, orphan_pool.len());
+        assert!(!orphan_pool.contains(Hash::hash(&transactions[0])));
+        assert!(orphan_pool.contains(Hash::hash(&transactions[1])));
+        assert!(orphan_pool.contains(Hash::hash(&transactions[2])));
+
+        // Orphans expire once their time to live has passed since they were received
+        assert_eq!(0, orphan_pool.prune_expired(now + ttl));
+        assert_eq!(
+            1,
+            orphan_pool.prune_expired(now + ttl + Timestamp::seconds(1))
+        );
+        assert!(orphan_pool.contains(Hash::hash(&transactions[2])));
+        assert_eq!(
+            1,
+            orphan_pool.prune_expired(now + ttl + Timestamp::seconds(2))
+        );
+        assert!(orphan_pool.is_empty());
+        assert_eq!(0, orphan_pool.size_in_bytes());
+    }
+}
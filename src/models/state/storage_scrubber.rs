@@ -0,0 +1,163 @@
+//! Background verification of a random sample of the stored blocks and of the
+//! chunks of the archival mutator set, such that records corrupted on disk are
+//! noticed before they are needed, rather than when they are finally read.
+
+use crate::models::blockchain::block::block_height::BlockHeight;
+use crate::prelude::twenty_first;
+
+use itertools::Itertools;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::time::SystemTime;
+use tracing::error;
+use twenty_first::math::digest::Digest;
+
+/// Number of stored blocks verified per scrub round. Blocks are read in full,
+/// so this is kept small to keep the I/O negligible.
+pub const SCRUB_BLOCKS_PER_ROUND: usize = 2;
+
+/// Number of archival mutator set chunks verified per scrub round.
+pub const SCRUB_CHUNKS_PER_ROUND: usize = 8;
+
+/// A stored record that can be verified by the scrubber.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ScrubbedKey {
+    Block(Digest),
+    Chunk(u64),
+
+    /// The list of blocks at a height in the block index
+    Height(BlockHeight),
+}
+
+impl Display for ScrubbedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScrubbedKey::Block(digest) => write!(f, "block {digest}"),
+            ScrubbedKey::Chunk(chunk_index) => write!(f, "chunk {chunk_index}"),
+            ScrubbedKey::Height(height) => write!(f, "blocks at height {height}"),
+        }
+    }
+}
+
+/// The outcome of verifying one sample of the stored records.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScrubRound {
+    pub blocks_checked: usize,
+    pub chunks_checked: usize,
+    pub corrupt_keys: Vec<ScrubbedKey>,
+}
+
+/// The outcome of all scrub rounds since startup.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StorageScrubReport {
+    pub rounds: u64,
+    pub blocks_checked: u64,
+    pub chunks_checked: u64,
+    pub latest_round_at: Option<SystemTime>,
+
+    /// The records that failed verification, in the order they were found.
+    /// These must be restored, e.g. by resyncing, before they are needed.
+    pub corrupt_keys: Vec<ScrubbedKey>,
+}
+
+impl StorageScrubReport {
+    pub fn is_healthy(&self) -> bool {
+        self.corrupt_keys.is_empty()
+    }
+
+    /// Add a round to the report, and return the records that were found to
+    /// be corrupt for the first time.
+    pub fn record(&mut self, round: ScrubRound, now: SystemTime) -> Vec<ScrubbedKey> {
+        self.rounds += 1;
+        self.blocks_checked += round.blocks_checked as u64;
+        self.chunks_checked += round.chunks_checked as u64;
+        self.latest_round_at = Some(now);
+
+        let mut new_corrupt_keys = vec![];
+        for key in round.corrupt_keys {
+            if !self.corrupt_keys.contains(&key) && !new_corrupt_keys.contains(&key) {
+                new_corrupt_keys.push(key);
+            }
+        }
+        if !new_corrupt_keys.is_empty() {
+            error!(
+                "Storage scrubber found corrupt records: {}. Restore them, e.g. by resyncing, before they are needed.",
+                new_corrupt_keys.iter().join(", ")
+            );
+        }
+        self.corrupt_keys.extend(new_corrupt_keys.iter().copied());
+
+        new_corrupt_keys
+    }
+}
+
+/// Sample `sample_size` distinct indices below `count`, by dividing the
+/// indices into `sample_size` strata of (almost) equal size and picking one
+/// index uniformly at random from each. All indices are thus equally likely
+/// to be sampled, irrespective of age, and every round covers the entire key
+/// space. Returns all indices if there are no more than `sample_size`.
+pub fn stratified_sample<R: Rng>(count: u64, sample_size: usize, rng: &mut R) -> Vec<u64> {
+    if count <= sample_size as u64 {
+        return (0..count).collect();
+    }
+
+    let stratum_boundary =
+        |stratum: usize| (stratum as u128 * count as u128 / sample_size as u128) as u64;
+    (0..sample_size)
+        .map(|stratum| rng.gen_range(stratum_boundary(stratum)..stratum_boundary(stratum + 1)))
+        .collect()
+}
+
+#[cfg(test)]
+mod storage_scrubber_tests {
+    use super::*;
+
+    use rand::random;
+    use rand::thread_rng;
+
+    #[test]
+    fn stratified_sample_covers_all_indices_test() {
+        let mut rng = thread_rng();
+        assert_eq!(vec![0, 1, 2], stratified_sample(3, 8, &mut rng));
+        assert!(stratified_sample(0, 8, &mut rng).is_empty());
+
+        // Every index is sampled eventually, old ones as well as recent ones
+        let count = 100;
+        let mut hit_counts = vec![0usize; count as usize];
+        for _ in 0..2_000 {
+            let sample = stratified_sample(count, 4, &mut rng);
+            assert_eq!(4, sample.len());
+            assert!(sample.iter().all_unique());
+            for index in sample {
+                hit_counts[index as usize] += 1;
+            }
+        }
+        assert!(hit_counts.iter().all(|hits| *hits > 0));
+    }
+
+    #[test]
+    fn corrupt_keys_are_reported_once_test() {
+        let mut report = StorageScrubReport::default();
+        let corrupt_block = ScrubbedKey::Block(random());
+        let round = ScrubRound {
+            blocks_checked: 2,
+            chunks_checked: 8,
+            corrupt_keys: vec![corrupt_block, ScrubbedKey::Chunk(3)],
+        };
+        assert_eq!(
+            vec![corrupt_block, ScrubbedKey::Chunk(3)],
+            report.record(round.clone(), SystemTime::now())
+        );
+        assert!(report.record(round, SystemTime::now()).is_empty());
+        assert!(report
+            .record(ScrubRound::default(), SystemTime::now())
+            .is_empty());
+
+        assert_eq!(3, report.rounds);
+        assert_eq!(4, report.blocks_checked);
+        assert_eq!(16, report.chunks_checked);
+        assert_eq!(2, report.corrupt_keys.len());
+        assert!(!report.is_healthy());
+    }
+}
@@ -360,6 +360,18 @@ impl ReceivingAddress {
         Ok(PublicAnnouncement::new(ciphertext))
     }
 
+    /// Determine whether the public announcement is addressed to this address,
+    /// i.e., is marked as a generation address ciphertext and carries this
+    /// address' receiver identifier. Decrypting the announcement requires the
+    /// spending key.
+    pub fn matches_public_announcement(&self, announcement: &PublicAnnouncement) -> bool {
+        public_announcement_is_marked(announcement)
+            && matches!(
+                receiver_identifier_from_public_announcement(announcement),
+                Ok(receiver_identifier) if receiver_identifier == self.receiver_identifier
+            )
+    }
+
     /// Generate a lock script from the spending lock. Satisfaction
     /// of this lock script establishes the UTXO owner's assent to
     /// the transaction. The logic contained in here should be
@@ -0,0 +1,126 @@
+use aead::Aead;
+use aead::KeyInit;
+use aead::Payload;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::Nonce;
+use anyhow::{anyhow, bail, Result};
+use argon2::Argon2;
+use rand::thread_rng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::WalletSecret;
+
+const ENCRYPTED_WALLET_SECRET_VERSION: u8 = 0;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const KEY_LENGTH: usize = 32;
+
+/// A [WalletSecret] encrypted under a key that is derived from a passphrase,
+/// for moving the secret between wallets without exposing it in plaintext.
+///
+/// The key is derived with Argon2id from the passphrase and a random salt,
+/// and the secret is encrypted with AES-256-GCM, which authenticates it
+/// along with the version.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EncryptedWalletSecret {
+    version: u8,
+    salt: [u8; SALT_LENGTH],
+    nonce: [u8; NONCE_LENGTH],
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedWalletSecret {
+    /// Encrypt the wallet secret under a key derived from the passphrase.
+    pub fn encrypt(wallet_secret: &WalletSecret, passphrase: &str) -> Result<Self> {
+        if passphrase.is_empty() {
+            bail!("Passphrase must not be empty");
+        }
+
+        let mut rng = thread_rng();
+        let salt: [u8; SALT_LENGTH] = rng.gen();
+        let nonce: [u8; NONCE_LENGTH] = rng.gen();
+        let key = Self::derive_key(passphrase, &salt)?;
+
+        let plaintext = Zeroizing::new(bincode::serialize(wallet_secret)?);
+        let cipher = Aes256Gcm::new(&(*key).into());
+        let payload = Payload {
+            msg: plaintext.as_ref(),
+            aad: &[ENCRYPTED_WALLET_SECRET_VERSION],
+        };
+        let Ok(ciphertext) = cipher.encrypt(Nonce::from_slice(&nonce), payload) else {
+            bail!("Could not encrypt wallet secret.");
+        };
+
+        Ok(Self {
+            version: ENCRYPTED_WALLET_SECRET_VERSION,
+            salt,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Decrypt the wallet secret with the passphrase it was encrypted under.
+    /// Fails if the passphrase is wrong or the ciphertext was tampered with.
+    pub fn decrypt(&self, passphrase: &str) -> Result<WalletSecret> {
+        if self.version != ENCRYPTED_WALLET_SECRET_VERSION {
+            bail!(
+                "Unsupported encrypted wallet secret version {}",
+                self.version
+            );
+        }
+
+        let key = Self::derive_key(passphrase, &self.salt)?;
+        let cipher = Aes256Gcm::new(&(*key).into());
+        let payload = Payload {
+            msg: self.ciphertext.as_ref(),
+            aad: &[self.version],
+        };
+        let Ok(plaintext) = cipher.decrypt(Nonce::from_slice(&self.nonce), payload) else {
+            bail!("Could not decrypt wallet secret; wrong passphrase?");
+        };
+        let plaintext = Zeroizing::new(plaintext);
+
+        Ok(bincode::deserialize(&plaintext)?)
+    }
+
+    fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; KEY_LENGTH]>> {
+        let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+            .map_err(|err| anyhow!("Could not derive key from passphrase: {err}"))?;
+
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_wallet_secret_round_trip_test() {
+        let wallet_secret = WalletSecret::new_random();
+        let encrypted = EncryptedWalletSecret::encrypt(&wallet_secret, "hunter2").unwrap();
+        assert_eq!(wallet_secret, encrypted.decrypt("hunter2").unwrap());
+
+        // The encryption is randomized
+        let encrypted_again = EncryptedWalletSecret::encrypt(&wallet_secret, "hunter2").unwrap();
+        assert_ne!(encrypted, encrypted_again);
+    }
+
+    #[test]
+    fn encrypted_wallet_secret_rejects_wrong_passphrase_test() {
+        let wallet_secret = WalletSecret::new_random();
+        let encrypted = EncryptedWalletSecret::encrypt(&wallet_secret, "hunter2").unwrap();
+        assert!(encrypted.decrypt("hunter3").is_err());
+        assert!(encrypted.decrypt("").is_err());
+
+        let mut tampered = encrypted.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(tampered.decrypt("hunter2").is_err());
+
+        assert!(EncryptedWalletSecret::encrypt(&wallet_secret, "").is_err());
+    }
+}
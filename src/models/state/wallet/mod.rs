@@ -2,11 +2,15 @@ use crate::prelude::twenty_first;
 
 pub mod address;
 pub mod coin_with_possible_timelock;
+pub mod encrypted_wallet_secret;
 pub mod monitored_utxo;
 pub mod rusty_wallet_database;
+pub mod sender_randomness_filter;
+pub mod transaction_label;
 pub mod utxo_notification_pool;
 pub mod wallet_state;
 pub mod wallet_status;
+pub mod watched_address;
 
 use anyhow::{bail, Context, Result};
 use bip39::Mnemonic;
@@ -213,15 +217,32 @@ impl WalletSecret {
         &self,
         block_height: BlockHeight,
         receiver_digest: Digest,
+    ) -> Digest {
+        self.generate_sender_randomness_with_nonce(block_height, receiver_digest, 0)
+    }
+
+    /// Like [`Self::generate_sender_randomness`], but with a nonce that can be
+    /// incremented to derive fresh sender randomness when the previous value
+    /// was already used for the same receiver at the same block height. Nonce
+    /// 0 yields the same randomness as `generate_sender_randomness`.
+    pub fn generate_sender_randomness_with_nonce(
+        &self,
+        block_height: BlockHeight,
+        receiver_digest: Digest,
+        nonce: u64,
     ) -> Digest {
         const SENDER_RANDOMNESS_FLAG: u64 = 0x5e116e1270u64;
+        let mut domain = vec![
+            BFieldElement::new(SENDER_RANDOMNESS_FLAG),
+            block_height.into(),
+        ];
+        if nonce != 0 {
+            domain.push(BFieldElement::new(nonce));
+        }
         Hash::hash_varlen(
             &[
                 self.secret_seed.0.encode(),
-                vec![
-                    BFieldElement::new(SENDER_RANDOMNESS_FLAG),
-                    block_height.into(),
-                ],
+                domain,
                 receiver_digest.encode(),
             ]
             .concat(),
@@ -245,6 +266,36 @@ impl WalletSecret {
         })
     }
 
+    /// Initialize a fresh wallet directory with this wallet secret, e.g. when
+    /// importing it from another wallet. A node started on the directory
+    /// finds the wallet's UTXOs as it synchronizes the blockchain. Fails if
+    /// the directory already holds a wallet secret, which is never
+    /// overwritten.
+    pub fn initialize_wallet_directory(
+        &self,
+        wallet_directory_path: &Path,
+    ) -> Result<WalletSecretFileLocations> {
+        let wallet_secret_path = Self::wallet_secret_path(wallet_directory_path);
+        if wallet_secret_path.exists() {
+            bail!(
+                "Wallet directory {} already holds a wallet secret",
+                wallet_directory_path.to_string_lossy()
+            );
+        }
+
+        fs::create_dir_all(wallet_directory_path).with_context(|| {
+            format!(
+                "Failed to create wallet directory {}",
+                wallet_directory_path.to_string_lossy()
+            )
+        })?;
+        self.save_to_disk(&wallet_secret_path)?;
+        let (_, wallet_secret_file_locations) =
+            Self::read_from_file_or_create(wallet_directory_path)?;
+
+        Ok(wallet_secret_file_locations)
+    }
+
     /// Used to generate both the file for incoming and outgoing randomness
     fn create_empty_wallet_randomness_file(file_path: &Path) -> Result<()> {
         let init_value: String = String::default();
@@ -369,7 +420,8 @@ mod wallet_tests {
     use crate::models::state::wallet::utxo_notification_pool::UtxoNotifier;
     use crate::models::state::UtxoReceiverData;
     use crate::tests::shared::{
-        make_mock_block, make_mock_transaction_with_generation_key, mock_genesis_global_state,
+        make_mock_block, make_mock_block_with_coinbase_randomness,
+        make_mock_transaction_with_generation_key, mock_genesis_global_state,
         mock_genesis_wallet_state,
     };
 
@@ -417,8 +469,13 @@ mod wallet_tests {
             .to_address();
         for _ in 0..12 {
             let previous_block = next_block;
-            let (nb, _coinbase_utxo, _sender_randomness) =
-                make_mock_block(&previous_block, None, other_receiver_address, rng.gen());
+            let (nb, _coinbase_utxo, _sender_randomness) = make_mock_block(
+                network,
+                &previous_block,
+                None,
+                other_receiver_address,
+                rng.gen(),
+            );
             next_block = nb;
             let current_mutator_set_accumulator =
                 previous_block.kernel.body.mutator_set_accumulator.clone();
@@ -471,8 +528,13 @@ mod wallet_tests {
         let genesis_block = Block::genesis_block(network);
         let own_spending_key = own_wallet_secret.nth_generation_spending_key(0);
         let own_recipient_address = own_spending_key.to_address();
-        let (block_1, block_1_coinbase_utxo, block_1_coinbase_sender_randomness) =
-            make_mock_block(&genesis_block, None, own_recipient_address, rng.gen());
+        let (block_1, block_1_coinbase_utxo, block_1_coinbase_sender_randomness) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            own_recipient_address,
+            rng.gen(),
+        );
 
         own_wallet_state
             .expected_utxos
@@ -528,8 +590,10 @@ mod wallet_tests {
 
         // Create new blocks, verify that the membership proofs are *not* valid
         // under this block as tip
-        let (block_2, _, _) = make_mock_block(&block_1, None, other_recipient_address, rng.gen());
-        let (block_3, _, _) = make_mock_block(&block_2, None, other_recipient_address, rng.gen());
+        let (block_2, _, _) =
+            make_mock_block(network, &block_1, None, other_recipient_address, rng.gen());
+        let (block_3, _, _) =
+            make_mock_block(network, &block_2, None, other_recipient_address, rng.gen());
         monitored_utxos = get_monitored_utxos(&own_wallet_state).await;
         {
             let block_1_tx_output_digest = Hash::hash(&block_1_coinbase_utxo);
@@ -596,6 +660,7 @@ mod wallet_tests {
             .nth_generation_spending_key(0);
         let genesis_block = Block::genesis_block(network);
         let (block_1, cb_utxo, cb_output_randomness) = make_mock_block(
+            network,
             &genesis_block,
             None,
             own_spending_key.to_address(),
@@ -624,7 +689,7 @@ mod wallet_tests {
         assert_eq!(
             1,
             own_wallet_state
-                .allocate_sufficient_input_funds(NeptuneCoins::one(), block_1.hash())
+                .allocate_sufficient_input_funds(NeptuneCoins::one(), &block_1)
                 .await
                 .unwrap()
                 .len()
@@ -634,7 +699,7 @@ mod wallet_tests {
             own_wallet_state
                 .allocate_sufficient_input_funds(
                     mining_reward.checked_sub(&NeptuneCoins::one()).unwrap(),
-                    block_1.hash()
+                    &block_1
                 )
                 .await
                 .unwrap()
@@ -643,7 +708,7 @@ mod wallet_tests {
         assert_eq!(
             1,
             own_wallet_state
-                .allocate_sufficient_input_funds(mining_reward, block_1.hash())
+                .allocate_sufficient_input_funds(mining_reward, &block_1)
                 .await
                 .unwrap()
                 .len()
@@ -651,7 +716,7 @@ mod wallet_tests {
 
         // Cannot allocate more than we have: `mining_reward`
         assert!(own_wallet_state
-            .allocate_sufficient_input_funds(mining_reward + NeptuneCoins::one(), block_1.hash())
+            .allocate_sufficient_input_funds(mining_reward + NeptuneCoins::one(), &block_1)
             .await
             .is_err());
 
@@ -660,6 +725,7 @@ mod wallet_tests {
         for _ in 0..21 {
             let previous_block = next_block;
             let (next_block_prime, cb_utxo_prime, cb_output_randomness_prime) = make_mock_block(
+                network,
                 &previous_block,
                 None,
                 own_spending_key.to_address(),
@@ -686,7 +752,7 @@ mod wallet_tests {
         assert_eq!(
             5,
             own_wallet_state
-                .allocate_sufficient_input_funds(mining_reward.scalar_mul(5), next_block.hash())
+                .allocate_sufficient_input_funds(mining_reward.scalar_mul(5), &next_block)
                 .await
                 .unwrap()
                 .len()
@@ -696,7 +762,7 @@ mod wallet_tests {
             own_wallet_state
                 .allocate_sufficient_input_funds(
                     mining_reward.scalar_mul(5) + NeptuneCoins::one(),
-                    next_block.hash()
+                    &next_block
                 )
                 .await
                 .unwrap()
@@ -707,7 +773,7 @@ mod wallet_tests {
         assert_eq!(
             22,
             own_wallet_state
-                .allocate_sufficient_input_funds(expected_balance, next_block.hash())
+                .allocate_sufficient_input_funds(expected_balance, &next_block)
                 .await
                 .unwrap()
                 .len()
@@ -715,17 +781,14 @@ mod wallet_tests {
 
         // Cannot allocate more than we have: 22 * mining reward
         assert!(own_wallet_state
-            .allocate_sufficient_input_funds(
-                expected_balance + NeptuneCoins::one(),
-                next_block.hash()
-            )
+            .allocate_sufficient_input_funds(expected_balance + NeptuneCoins::one(), &next_block)
             .await
             .is_err());
 
         // Make a block that spends an input, then verify that this is reflected by
         // the allocator.
         let two_utxos = own_wallet_state
-            .allocate_sufficient_input_funds(mining_reward.scalar_mul(2), next_block.hash())
+            .allocate_sufficient_input_funds(mining_reward.scalar_mul(2), &next_block)
             .await
             .unwrap();
         assert_eq!(
@@ -745,6 +808,7 @@ mod wallet_tests {
         );
         let msa_tip_previous = next_block.kernel.body.mutator_set_accumulator.clone();
         (next_block, _, _) = make_mock_block(
+            network,
             &next_block.clone(),
             None,
             own_spending_key.to_address(),
@@ -786,7 +850,7 @@ mod wallet_tests {
         assert_eq!(
             20,
             own_wallet_state
-                .allocate_sufficient_input_funds(NeptuneCoins::new(2000), next_block.hash())
+                .allocate_sufficient_input_funds(NeptuneCoins::new(2000), &next_block)
                 .await
                 .unwrap()
                 .len()
@@ -794,13 +858,122 @@ mod wallet_tests {
 
         // Cannot allocate more than we have: 2000
         assert!(own_wallet_state
-            .allocate_sufficient_input_funds(NeptuneCoins::new(2001), next_block.hash())
+            .allocate_sufficient_input_funds(NeptuneCoins::new(2001), &next_block)
             .await
             .is_err());
 
         Ok(())
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn allocate_sufficient_input_funds_respects_confirmation_depth_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let mut own_wallet_state =
+            mock_genesis_wallet_state(WalletSecret::new_random(), network).await;
+        own_wallet_state.spend_confirmation_depth = 1;
+        own_wallet_state.coinbase_maturity_depth = 3;
+        let own_spending_key = own_wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0);
+        let own_address = own_spending_key.to_address();
+        let other_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let genesis_block = Block::genesis_block(network);
+
+        // Block 1 pays an ordinary UTXO to us
+        let (block_1, incoming_utxo, incoming_randomness) =
+            make_mock_block(network, &genesis_block, None, own_address, rng.gen());
+        let incoming_amount = incoming_utxo.get_native_currency_amount();
+        own_wallet_state
+            .expected_utxos
+            .add_expected_utxo(
+                incoming_utxo,
+                incoming_randomness,
+                own_spending_key.privacy_preimage,
+                UtxoNotifier::Cli,
+            )
+            .unwrap();
+        own_wallet_state
+            .update_wallet_state_with_new_block(
+                &genesis_block.kernel.body.mutator_set_accumulator,
+                &block_1,
+            )
+            .await?;
+
+        // Block 2 is mined by us
+        let coinbase_randomness = own_wallet_state.wallet_secret.generate_sender_randomness(
+            block_1.kernel.header.height.next(),
+            own_address.privacy_digest,
+        );
+        let (block_2, coinbase_utxo, _) = make_mock_block_with_coinbase_randomness(
+            network,
+            &block_1,
+            None,
+            own_address,
+            coinbase_randomness,
+        );
+        let coinbase_amount = coinbase_utxo.get_native_currency_amount();
+        own_wallet_state
+            .expected_utxos
+            .add_expected_utxo(
+                coinbase_utxo,
+                coinbase_randomness,
+                own_spending_key.privacy_preimage,
+                UtxoNotifier::OwnMiner,
+            )
+            .unwrap();
+        own_wallet_state
+            .update_wallet_state_with_new_block(
+                &block_1.kernel.body.mutator_set_accumulator,
+                &block_2,
+            )
+            .await?;
+
+        // With block 2 as tip, only the UTXO from block 1 is buried deep enough
+        assert_eq!(
+            1,
+            own_wallet_state
+                .allocate_sufficient_input_funds(incoming_amount, &block_2)
+                .await?
+                .len()
+        );
+        let err = own_wallet_state
+            .allocate_sufficient_input_funds(
+                incoming_amount + coinbase_amount + NeptuneCoins::one(),
+                &block_2,
+            )
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&format!(
+            "Synced unspent amount locked until sufficiently confirmed: {coinbase_amount}."
+        )));
+        assert!(err.contains(&format!("Missing amount: {}.", NeptuneCoins::one())));
+
+        // The coinbase UTXO needs three blocks on top of it
+        let mut tip = block_2;
+        for depth in 1..=3 {
+            let (next_block, _, _) = make_mock_block(network, &tip, None, other_address, rng.gen());
+            own_wallet_state
+                .update_wallet_state_with_new_block(
+                    &tip.kernel.body.mutator_set_accumulator,
+                    &next_block,
+                )
+                .await?;
+            tip = next_block;
+
+            let allocation = own_wallet_state
+                .allocate_sufficient_input_funds(incoming_amount + coinbase_amount, &tip)
+                .await;
+            assert_eq!(depth == 3, allocation.is_ok(), "depth {depth}");
+        }
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn wallet_state_maintanence_multiple_inputs_outputs_test() -> Result<()> {
@@ -832,7 +1005,8 @@ mod wallet_tests {
         );
 
         let previous_msa = genesis_block.kernel.body.mutator_set_accumulator.clone();
-        let (mut block_1, _, _) = make_mock_block(&genesis_block, None, own_address, rng.gen());
+        let (mut block_1, _, _) =
+            make_mock_block(network, &genesis_block, None, own_address, rng.gen());
 
         let receiver_data_12_to_other = UtxoReceiverData {
             public_announcement: PublicAnnouncement::default(),
@@ -855,9 +1029,10 @@ mod wallet_tests {
             sender_randomness: premine_receiver_global_state
                 .wallet_state
                 .wallet_secret
-                .generate_sender_randomness(
+                .generate_sender_randomness_with_nonce(
                     genesis_block.kernel.header.height,
                     own_address.privacy_digest,
+                    1,
                 ),
             utxo: Utxo {
                 coins: NeptuneCoins::new(1).to_native_coins(),
@@ -880,7 +1055,7 @@ mod wallet_tests {
             .await;
 
         // Verify the validity of the merged transaction and block
-        assert!(block_1.is_valid(&genesis_block, now + seven_months));
+        assert!(block_1.is_valid(&genesis_block, network));
 
         // Update wallet state with block_1
         let mut monitored_utxos = get_monitored_utxos(&own_wallet_state).await;
@@ -949,7 +1124,7 @@ mod wallet_tests {
         let mut next_block = block_1.clone();
         for _ in 0..17 {
             let previous_block = next_block;
-            let ret = make_mock_block(&previous_block, None, own_address, rng.gen());
+            let ret = make_mock_block(network, &previous_block, None, own_address, rng.gen());
             next_block = ret.0;
             own_wallet_state
                 .expected_utxos
@@ -1026,6 +1201,7 @@ mod wallet_tests {
             .wallet_secret
             .nth_generation_spending_key(0);
         let (block_2_b, _, _) = make_mock_block(
+            network,
             &block_1,
             None,
             premine_wallet_spending_key.to_address(),
@@ -1076,6 +1252,7 @@ mod wallet_tests {
         // Fork back again to the long chain and verify that the membership proofs
         // all work again
         let (block_19, _, _) = make_mock_block(
+            network,
             &block_18,
             None,
             premine_wallet_spending_key.to_address(),
@@ -1114,10 +1291,10 @@ mod wallet_tests {
         // Fork back to the B-chain with `block_3b` which contains two outputs for `own_wallet`,
         // one coinbase UTXO and one other UTXO
         let (mut block_3_b, cb_utxo, cb_sender_randomness) =
-            make_mock_block(&block_2_b, None, own_address, rng.gen());
+            make_mock_block(network, &block_2_b, None, own_address, rng.gen());
         now = block_3_b.kernel.header.timestamp;
         assert!(
-            block_3_b.is_valid(&block_2_b, now),
+            block_3_b.is_valid(&block_2_b, network),
             "Block must be valid before merging txs"
         );
 
@@ -1141,7 +1318,7 @@ mod wallet_tests {
             )
             .await;
         assert!(
-            block_3_b.is_valid(&block_2_b, now),
+            block_3_b.is_valid(&block_2_b, network),
             "Block must be valid after accumulating txs"
         );
         own_wallet_state
@@ -1204,6 +1381,7 @@ mod wallet_tests {
 
         // Then fork back to A-chain
         let (block_20, _, _) = make_mock_block(
+            network,
             &block_19,
             None,
             premine_wallet_spending_key.to_address(),
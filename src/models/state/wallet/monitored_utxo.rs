@@ -10,6 +10,8 @@ use serde::{Deserialize, Serialize};
 use twenty_first::math::tip5::Digest;
 
 use crate::models::blockchain::transaction::utxo::Utxo;
+use crate::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
+use crate::models::state::wallet::wallet_state::IncomingUtxoRecoveryData;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MonitoredUtxo {
@@ -77,6 +79,38 @@ impl MonitoredUtxo {
         self.blockhash_to_membership_proof.iter().next().cloned()
     }
 
+    /// Check that the membership proofs are structurally consistent and that
+    /// there are no more of them than the UTXO keeps. See
+    /// [MsMembershipProof::is_well_formed].
+    pub fn is_well_formed(&self) -> bool {
+        self.blockhash_to_membership_proof.len() <= self.number_of_mps_per_utxo
+            && self
+                .blockhash_to_membership_proof
+                .iter()
+                .all(|(_block_digest, membership_proof)| membership_proof.is_well_formed())
+    }
+
+    /// Determine how the membership proofs relate to the given tip, and
+    /// thereby whether they must be resynced.
+    pub fn sync_status(&self, tip_digest: Digest) -> MembershipProofSyncStatus {
+        if self.abandoned_at.is_some() {
+            return MembershipProofSyncStatus::Abandoned;
+        }
+        if self.is_synced_to(tip_digest) {
+            return MembershipProofSyncStatus::Synced;
+        }
+
+        match self.confirmed_in_block {
+            Some((confirming_block_digest, _timestamp, confirming_block_height)) => {
+                MembershipProofSyncStatus::Stale {
+                    confirming_block_digest,
+                    confirming_block_height,
+                }
+            }
+            None => MembershipProofSyncStatus::Unconfirmed,
+        }
+    }
+
     /// Returns true if the MUTXO was abandoned
     pub async fn was_abandoned(&self, tip_digest: Digest, archival_state: &ArchivalState) -> bool {
         match self.confirmed_in_block {
@@ -89,3 +123,200 @@ impl MonitoredUtxo {
         }
     }
 }
+
+/// How the membership proofs of a [`MonitoredUtxo`] relate to a tip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MembershipProofSyncStatus {
+    /// The UTXO was marked as belonging to an abandoned fork
+    Abandoned,
+
+    /// A membership proof synced to the tip exists
+    Synced,
+
+    /// The UTXO was not confirmed yet, so there is no membership proof to sync
+    Unconfirmed,
+
+    /// The membership proofs lag behind the tip. The UTXO was confirmed in the
+    /// given block, which may or may not belong to the canonical chain.
+    Stale {
+        confirming_block_digest: Digest,
+        confirming_block_height: BlockHeight,
+    },
+}
+
+/// A monitored UTXO as exported to JSON, along with the label that the user
+/// attached to it, if any.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedMonitoredUtxo {
+    pub monitored_utxo: MonitoredUtxo,
+    pub label: Option<String>,
+
+    /// The data to restore the membership proof from the archival state with,
+    /// for UTXOs whose membership proofs are not kept in the wallet
+    #[serde(default)]
+    pub(crate) archival_restore: Option<IncomingUtxoRecoveryData>,
+}
+
+/// The monitored UTXOs of a wallet, for backup and migration without a full
+/// rescan of the chain. Does not contain the wallet secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitoredUtxoExport {
+    /// Digest of the block to which the wallet was synced when exporting
+    pub sync_label: Digest,
+    pub monitored_utxos: Vec<ExportedMonitoredUtxo>,
+}
+
+/// A report of the state of a single [`MonitoredUtxo`], relative to the current
+/// tip. For debugging and reporting purposes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonitoredUtxoInfo {
+    /// Index of the monitored UTXO in the wallet database
+    pub index: u64,
+
+    pub utxo_digest: Digest,
+    pub amount: NeptuneCoins,
+
+    /// Digest and height of the block in which the UTXO was confirmed, if any
+    pub confirmed_in_block: Option<(Digest, BlockHeight)>,
+
+    /// Number of blocks on top of the confirming block, counting the confirming
+    /// block itself. `None` if the UTXO is unconfirmed or if the confirming
+    /// block does not belong to the canonical chain.
+    pub confirmations: Option<u64>,
+
+    /// Whether a membership proof synced to the current tip exists
+    pub synced_to_tip: bool,
+
+    pub spent: bool,
+    pub abandoned: bool,
+
+    /// The label that the user attached to the UTXO, if any
+    pub label: Option<String>,
+}
+
+/// A report of a single unspent UTXO of the wallet, relative to the current
+/// tip. Intended for wallet UIs that enumerate coins.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnspentUtxoInfo {
+    pub utxo: Utxo,
+
+    /// Digest and timestamp of the block in which the UTXO was confirmed, if
+    /// any
+    pub confirmed_in_block: Option<(Digest, Timestamp)>,
+
+    /// Whether a membership proof synced to the current tip exists
+    pub synced_to_tip: bool,
+
+    pub abandoned: bool,
+}
+
+/// Sizes of the chunk dictionaries in the latest membership proofs of the
+/// wallet's unspent monitored UTXOs. Each slide of the sliding window of the
+/// mutator set can add an entry to the chunk dictionary of a membership proof,
+/// which grows the wallet database and the cost of updating the proof.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkDictionaryStatistics {
+    /// Number of membership proofs that are maintained
+    pub proof_count: usize,
+
+    /// Total number of chunk dictionary entries across these proofs
+    pub total_entries: usize,
+
+    /// Largest number of entries in a single chunk dictionary
+    pub max_entries: usize,
+
+    /// Number of unspent UTXOs whose membership proof was dropped, to be
+    /// restored from the archival mutator set when spent
+    pub archival_restore_count: usize,
+}
+
+impl ChunkDictionaryStatistics {
+    /// Average number of chunk dictionary entries per maintained proof
+    pub fn average_entries(&self) -> f64 {
+        if self.proof_count == 0 {
+            return 0.0;
+        }
+
+        self.total_entries as f64 / self.proof_count as f64
+    }
+}
+
+/// A problem with the membership proof of a monitored UTXO, as found by
+/// auditing the wallet's proofs against a tip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProofAuditFinding {
+    /// The membership proofs lag behind the tip
+    Stale,
+
+    /// The UTXO was confirmed in a block that does not belong to the canonical
+    /// chain
+    Abandoned,
+
+    /// The membership proof synced to the tip is malformed or does not verify
+    Corrupt,
+
+    /// The UTXO is spent according to the sliding window Bloom filter of the
+    /// tip, but the wallet does not know the block that spent it
+    SpentUnnoticed,
+}
+
+/// A repair that resolves a [`ProofAuditFinding`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProofRepair {
+    /// Resync the membership proofs from the stored blocks
+    Resync,
+
+    /// Restore the membership proof from the archival mutator set
+    RestoreFromArchival,
+
+    /// Mark the UTXO as abandoned, such that it is no longer synced
+    ArchiveAsAbandoned,
+}
+
+impl ProofAuditFinding {
+    /// The repair to suggest for the finding, if any. Corrupt proofs can only
+    /// be restored by archival nodes, and a UTXO that was spent cannot be
+    /// repaired at all.
+    pub fn suggested_repair(self, is_archival_node: bool) -> Option<ProofRepair> {
+        match self {
+            ProofAuditFinding::Stale => Some(ProofRepair::Resync),
+            ProofAuditFinding::Abandoned => Some(ProofRepair::ArchiveAsAbandoned),
+            ProofAuditFinding::Corrupt if is_archival_node => {
+                Some(ProofRepair::RestoreFromArchival)
+            }
+            ProofAuditFinding::Corrupt | ProofAuditFinding::SpentUnnoticed => None,
+        }
+    }
+}
+
+/// A monitored UTXO whose membership proof failed the audit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofAuditEntry {
+    /// Index of the monitored UTXO in the wallet database
+    pub index: u64,
+
+    pub utxo_digest: Digest,
+    pub finding: ProofAuditFinding,
+    pub suggested_repair: Option<ProofRepair>,
+}
+
+/// The outcome of auditing the membership proofs of all unspent monitored
+/// UTXOs against a tip.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofAuditReport {
+    pub tip_digest: Digest,
+
+    /// Number of monitored UTXOs whose membership proofs were audited. Spent,
+    /// unconfirmed and abandoned UTXOs are skipped, as are those whose
+    /// membership proofs are restored from the archival state when needed.
+    pub audited_count: u64,
+
+    /// The monitored UTXOs that failed the audit, by database index
+    pub entries: Vec<ProofAuditEntry>,
+}
+
+impl ProofAuditReport {
+    pub fn is_healthy(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
@@ -9,6 +9,11 @@ use crate::database::{
 use twenty_first::math::tip5::Digest;
 
 use super::monitored_utxo::MonitoredUtxo;
+use super::sender_randomness_filter::SenderRandomnessFilter;
+use super::transaction_label::TransactionLabel;
+use super::wallet_state::IncomingUtxoRecoveryData;
+use super::watched_address::WatchedAddress;
+use std::collections::HashMap;
 
 pub struct RustyWalletDatabase {
     storage: SimpleRustyStorage,
@@ -20,6 +25,22 @@ pub struct RustyWalletDatabase {
 
     // counts the number of output UTXOs generated by this wallet
     counter: DbtSingleton<u64>,
+
+    // remembers the sender randomness of recent outgoing UTXOs
+    sender_randomness_filter: DbtSingleton<SenderRandomnessFilter>,
+
+    // user-supplied labels of transactions, keyed by transaction kernel hash
+    transaction_labels: DbtSingleton<HashMap<Digest, TransactionLabel>>,
+
+    // user-supplied labels of monitored UTXOs, keyed by monitored UTXO index
+    utxo_labels: DbtSingleton<HashMap<u64, String>>,
+
+    // data to restore the membership proofs of monitored UTXOs whose proofs were
+    // dropped, keyed by monitored UTXO index
+    archival_restore_utxos: DbtSingleton<HashMap<u64, IncomingUtxoRecoveryData>>,
+
+    // third-party addresses tracked by this wallet, keyed by lock script hash
+    watched_addresses: DbtSingleton<HashMap<Digest, WatchedAddress>>,
 }
 
 impl RustyWalletDatabase {
@@ -36,12 +57,40 @@ impl RustyWalletDatabase {
             .await;
         let sync_label_storage = storage.schema.new_singleton::<Digest>("sync_label").await;
         let counter_storage = storage.schema.new_singleton::<u64>("counter").await;
+        let sender_randomness_filter_storage = storage
+            .schema
+            .new_singleton::<SenderRandomnessFilter>("sender_randomness_filter")
+            .await;
+        let transaction_labels_storage = storage
+            .schema
+            .new_singleton::<HashMap<Digest, TransactionLabel>>("transaction_labels")
+            .await;
+        let watched_addresses_storage = storage
+            .schema
+            .new_singleton::<HashMap<Digest, WatchedAddress>>("watched_addresses")
+            .await;
+
+        // Tables are identified by the order of their creation, so new tables
+        // must be created last to keep existing databases readable.
+        let utxo_labels_storage = storage
+            .schema
+            .new_singleton::<HashMap<u64, String>>("utxo_labels")
+            .await;
+        let archival_restore_utxos_storage = storage
+            .schema
+            .new_singleton::<HashMap<u64, IncomingUtxoRecoveryData>>("archival_restore_utxos")
+            .await;
 
         Self {
             storage,
             monitored_utxos: monitored_utxos_storage,
             sync_label: sync_label_storage,
             counter: counter_storage,
+            sender_randomness_filter: sender_randomness_filter_storage,
+            transaction_labels: transaction_labels_storage,
+            utxo_labels: utxo_labels_storage,
+            archival_restore_utxos: archival_restore_utxos_storage,
+            watched_addresses: watched_addresses_storage,
         }
     }
 
@@ -71,6 +120,56 @@ impl RustyWalletDatabase {
     pub async fn set_counter(&mut self, counter: u64) {
         self.counter.set(counter).await;
     }
+
+    pub async fn get_sender_randomness_filter(&self) -> SenderRandomnessFilter {
+        self.sender_randomness_filter.get().await
+    }
+
+    pub async fn set_sender_randomness_filter(&mut self, filter: SenderRandomnessFilter) {
+        self.sender_randomness_filter.set(filter).await;
+    }
+
+    pub async fn get_transaction_labels(&self) -> HashMap<Digest, TransactionLabel> {
+        self.transaction_labels.get().await
+    }
+
+    pub async fn set_transaction_labels(&mut self, labels: HashMap<Digest, TransactionLabel>) {
+        self.transaction_labels.set(labels).await;
+    }
+
+    pub async fn get_utxo_labels(&self) -> HashMap<u64, String> {
+        self.utxo_labels.get().await
+    }
+
+    pub async fn set_utxo_labels(&mut self, labels: HashMap<u64, String>) {
+        self.utxo_labels.set(labels).await;
+    }
+
+    pub(crate) async fn get_archival_restore_utxos(
+        &self,
+    ) -> HashMap<u64, IncomingUtxoRecoveryData> {
+        self.archival_restore_utxos.get().await
+    }
+
+    pub(crate) async fn set_archival_restore_utxos(
+        &mut self,
+        archival_restore_utxos: HashMap<u64, IncomingUtxoRecoveryData>,
+    ) {
+        self.archival_restore_utxos
+            .set(archival_restore_utxos)
+            .await;
+    }
+
+    pub async fn get_watched_addresses(&self) -> HashMap<Digest, WatchedAddress> {
+        self.watched_addresses.get().await
+    }
+
+    pub async fn set_watched_addresses(
+        &mut self,
+        watched_addresses: HashMap<Digest, WatchedAddress>,
+    ) {
+        self.watched_addresses.set(watched_addresses).await;
+    }
 }
 
 impl StorageWriter for RustyWalletDatabase {
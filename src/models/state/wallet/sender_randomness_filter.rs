@@ -0,0 +1,133 @@
+use crate::prelude::twenty_first;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use twenty_first::math::digest::Digest;
+
+/// Number of outgoing outputs whose sender randomness is remembered.
+pub const SENDER_RANDOMNESS_FILTER_CAPACITY: usize = 10_000;
+
+/// Number of bits in each of the two generations of the filter.
+const BITS_PER_GENERATION: usize = 1 << 17;
+
+/// Number of bits set per inserted element. Sender randomness is a hash
+/// digest, so its elements can serve as independent hash functions.
+const HASH_COUNT: usize = 4;
+
+/// A rolling bloom filter over the sender randomness of the wallet's most
+/// recent outgoing outputs.
+///
+/// Reusing sender randomness for the same receiver produces identical
+/// addition records, which links the outputs and, once one of them is
+/// spent, makes the other one unspendable. The filter lets the wallet
+/// refuse such outputs before they end up in a transaction.
+///
+/// The filter consists of two generations of half the capacity each. When
+/// the current generation is full it replaces the previous one, such that
+/// at least the last `SENDER_RANDOMNESS_FILTER_CAPACITY / 2` and at most
+/// the last `SENDER_RANDOMNESS_FILTER_CAPACITY` insertions are remembered.
+/// False positives are possible but rare, and merely cause a re-derivation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderRandomnessFilter {
+    current: Vec<u64>,
+    previous: Vec<u64>,
+    current_count: usize,
+}
+
+impl Default for SenderRandomnessFilter {
+    fn default() -> Self {
+        Self {
+            current: vec![0u64; BITS_PER_GENERATION / 64],
+            previous: vec![0u64; BITS_PER_GENERATION / 64],
+            current_count: 0,
+        }
+    }
+}
+
+impl SenderRandomnessFilter {
+    fn bit_indices(sender_randomness: Digest) -> impl Iterator<Item = usize> {
+        sender_randomness
+            .values()
+            .into_iter()
+            .take(HASH_COUNT)
+            .map(|element| (element.value() % BITS_PER_GENERATION as u64) as usize)
+    }
+
+    fn generation_contains(generation: &[u64], sender_randomness: Digest) -> bool {
+        // an empty generation (e.g. from a default value) contains nothing
+        !generation.is_empty()
+            && Self::bit_indices(sender_randomness)
+                .all(|index| generation[index / 64] & (1u64 << (index % 64)) != 0)
+    }
+
+    /// Determine whether the sender randomness was (probably) used recently.
+    pub fn contains(&self, sender_randomness: Digest) -> bool {
+        Self::generation_contains(&self.current, sender_randomness)
+            || Self::generation_contains(&self.previous, sender_randomness)
+    }
+
+    /// Record the use of the given sender randomness.
+    pub fn insert(&mut self, sender_randomness: Digest) {
+        if self.current_count >= SENDER_RANDOMNESS_FILTER_CAPACITY / 2 {
+            self.previous =
+                std::mem::replace(&mut self.current, vec![0u64; BITS_PER_GENERATION / 64]);
+            self.current_count = 0;
+        }
+
+        for index in Self::bit_indices(sender_randomness) {
+            self.current[index / 64] |= 1u64 << (index % 64);
+        }
+        self.current_count += 1;
+    }
+}
+
+/// Returned when a transaction would contain an output whose sender
+/// randomness was recently used by this wallet. The caller should derive
+/// fresh sender randomness, e.g. with an incremented nonce, and retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("Sender randomness {sender_randomness} was already used in a recent output")]
+pub struct SenderRandomnessReuseError {
+    pub sender_randomness: Digest,
+}
+
+#[cfg(test)]
+mod sender_randomness_filter_tests {
+    use rand::rngs::StdRng;
+    use rand::{thread_rng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn inserted_randomness_is_detected_test() {
+        let mut rng = thread_rng();
+        let mut filter = SenderRandomnessFilter::default();
+        let used: Digest = rng.gen();
+        let fresh: Digest = rng.gen();
+
+        assert!(!filter.contains(used));
+        filter.insert(used);
+        assert!(filter.contains(used));
+        assert!(!filter.contains(fresh));
+    }
+
+    #[test]
+    fn filter_forgets_after_two_generations_test() {
+        // fixed seed, so false positives cannot make this test flaky
+        let mut rng = StdRng::seed_from_u64(0x5e11d);
+        let mut filter = SenderRandomnessFilter::default();
+        let oldest: Digest = rng.gen();
+        filter.insert(oldest);
+
+        // fill up the first generation; `oldest` moves to the previous one
+        for _ in 0..SENDER_RANDOMNESS_FILTER_CAPACITY / 2 {
+            filter.insert(rng.gen());
+        }
+        assert!(filter.contains(oldest));
+
+        // fill up the second generation; `oldest` is forgotten
+        for _ in 0..SENDER_RANDOMNESS_FILTER_CAPACITY / 2 {
+            filter.insert(rng.gen());
+        }
+        assert!(!filter.contains(oldest));
+    }
+}
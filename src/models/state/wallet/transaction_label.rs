@@ -0,0 +1,132 @@
+use crate::prelude::twenty_first;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use twenty_first::math::digest::Digest;
+use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
+
+use crate::models::blockchain::transaction::transaction_kernel::TransactionKernel;
+use crate::models::state::wallet::monitored_utxo::MonitoredUtxo;
+use crate::util_types::mutator_set::addition_record::AdditionRecord;
+use crate::util_types::mutator_set::commit;
+use crate::util_types::mutator_set::removal_record::AbsoluteIndexSet;
+use crate::Hash;
+
+/// Maximum length of a label, in bytes of its UTF-8 encoding.
+pub const MAX_LABEL_LENGTH: usize = 256;
+
+/// Returned when a label is rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LabelError {
+    #[error("Label is {0} bytes long; at most {MAX_LABEL_LENGTH} bytes are allowed")]
+    TooLong(usize),
+
+    #[error("Label must not contain control characters")]
+    ControlCharacter,
+}
+
+/// Check that the label is short enough and printable. Labels arrive as
+/// `String`s and are therefore valid UTF-8, but control characters would
+/// garble reports and exports.
+pub fn validate_label(label: &str) -> Result<(), LabelError> {
+    if label.len() > MAX_LABEL_LENGTH {
+        return Err(LabelError::TooLong(label.len()));
+    }
+    if label.chars().any(char::is_control) {
+        return Err(LabelError::ControlCharacter);
+    }
+
+    Ok(())
+}
+
+/// The wallet entry that a label is attached to.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LabelTarget {
+    /// A transaction created by this wallet, identified by its kernel hash
+    Transaction(Digest),
+
+    /// A monitored UTXO, identified by its index in the wallet database
+    MonitoredUtxo(u64),
+}
+
+/// A label, or memo, that the user attached to a transaction created by this
+/// wallet. Labels are local metadata and are never broadcast.
+///
+/// Blocks merge transactions, so a labeled transaction cannot be recognized
+/// by its kernel hash once confirmed. The inputs and outputs are stored along
+/// with the label such that the monitored UTXOs that the transaction spends
+/// or creates can be matched to it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TransactionLabel {
+    pub label: String,
+    pub inputs: Vec<AbsoluteIndexSet>,
+    pub outputs: Vec<AdditionRecord>,
+}
+
+impl TransactionLabel {
+    pub fn new(label: String, kernel: &TransactionKernel) -> Self {
+        Self {
+            label,
+            inputs: kernel
+                .inputs
+                .iter()
+                .map(|rr| rr.absolute_indices.clone())
+                .collect(),
+            outputs: kernel.outputs.clone(),
+        }
+    }
+
+    /// Determine whether the labeled transaction created the monitored UTXO.
+    pub fn created(&self, monitored_utxo: &MonitoredUtxo) -> bool {
+        let Some((_, membership_proof)) = monitored_utxo.get_latest_membership_proof_entry() else {
+            return false;
+        };
+        let addition_record = commit(
+            Hash::hash(&monitored_utxo.utxo),
+            membership_proof.sender_randomness,
+            membership_proof.receiver_preimage.hash::<Hash>(),
+        );
+
+        self.outputs.contains(&addition_record)
+    }
+
+    /// Determine whether the labeled transaction spent the monitored UTXO.
+    pub fn spent(&self, monitored_utxo: &MonitoredUtxo) -> bool {
+        let Some((_, membership_proof)) = monitored_utxo.get_latest_membership_proof_entry() else {
+            return false;
+        };
+        let absolute_indices = membership_proof.compute_indices(Hash::hash(&monitored_utxo.utxo));
+
+        self.inputs.contains(&absolute_indices)
+    }
+}
+
+#[cfg(test)]
+mod transaction_label_tests {
+    use super::*;
+
+    #[test]
+    fn validate_label_test() {
+        assert!(validate_label("invoice #1234").is_ok());
+        assert!(validate_label("").is_ok());
+        assert!(validate_label("løn for juni ✓").is_ok());
+        assert!(validate_label(&"a".repeat(MAX_LABEL_LENGTH)).is_ok());
+
+        assert_eq!(
+            Err(LabelError::TooLong(MAX_LABEL_LENGTH + 1)),
+            validate_label(&"a".repeat(MAX_LABEL_LENGTH + 1))
+        );
+
+        // the limit applies to the encoding, not the number of characters
+        let multi_byte = "ø".repeat(MAX_LABEL_LENGTH / 2 + 1);
+        assert_eq!(
+            Err(LabelError::TooLong(multi_byte.len())),
+            validate_label(&multi_byte)
+        );
+
+        assert_eq!(
+            Err(LabelError::ControlCharacter),
+            validate_label("payroll\nJune")
+        );
+    }
+}
@@ -298,6 +298,16 @@ impl UtxoNotificationPool {
         }
     }
 
+    /// Mark an expected incoming UTXO as not received, if it was received in
+    /// the given block. Used when that block is reverted.
+    pub fn mark_as_not_received(&mut self, addition_record: AdditionRecord, block_digest: Digest) {
+        if let Some(entry) = self.notifications.get_mut(&addition_record) {
+            if matches!(entry.mined_in_block, Some((digest, _)) if digest == block_digest) {
+                entry.mined_in_block = None;
+            }
+        }
+    }
+
     /// Delete UTXO notifications that exceed a certain age
     pub fn prune_stale_utxo_notifications(&mut self) {
         let cutoff_for_unreceived = SystemTime::now()
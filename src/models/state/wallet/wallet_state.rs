@@ -1,3 +1,4 @@
+use crate::config_models::network::Network;
 use crate::models::blockchain::type_scripts::native_currency::NativeCurrency;
 use crate::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
 use crate::models::consensus::tasm::program::ConsensusProgram;
@@ -11,44 +12,82 @@ use anyhow::{bail, Result};
 use itertools::Itertools;
 use num_traits::Zero;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt::Debug;
+use std::ops::Range;
 use std::path::PathBuf;
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
 use twenty_first::math::bfield_codec::BFieldCodec;
 use twenty_first::math::digest::Digest;
 use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 
+use super::address::generation_address::ReceivingAddress;
 use super::coin_with_possible_timelock::CoinWithPossibleTimeLock;
+use super::monitored_utxo::{
+    ChunkDictionaryStatistics, ExportedMonitoredUtxo, MembershipProofSyncStatus,
+    MonitoredUtxoExport, ProofAuditEntry, ProofAuditFinding, ProofAuditReport, UnspentUtxoInfo,
+};
 use super::rusty_wallet_database::RustyWalletDatabase;
+use super::sender_randomness_filter::{SenderRandomnessFilter, SenderRandomnessReuseError};
+use super::transaction_label::{validate_label, LabelError, TransactionLabel};
 use super::utxo_notification_pool::{UtxoNotificationPool, UtxoNotifier};
 use super::wallet_status::{WalletStatus, WalletStatusElement};
+use super::watched_address::{WalletEvent, WatchedAddress};
 use super::{WalletSecret, WALLET_INCOMING_SECRETS_FILE_NAME};
 use crate::config_models::cli_args::Args;
 use crate::config_models::data_directory::DataDirectory;
+use crate::models::blockchain::block::block_height::BlockHeight;
 use crate::models::blockchain::block::Block;
+use crate::models::blockchain::transaction::transaction_kernel::TransactionKernel;
 use crate::models::blockchain::transaction::utxo::{LockScript, Utxo};
-use crate::models::blockchain::transaction::Transaction;
+use crate::models::blockchain::transaction::{Transaction, TransactionSummary};
+use crate::models::consensus::mast_hash::MastHash;
+use crate::models::state::archival_state::ArchivalState;
+use crate::models::state::blockchain_state::ChainEvent;
+use crate::models::state::mempool::MempoolEvent;
 use crate::models::state::wallet::monitored_utxo::MonitoredUtxo;
 use crate::util_types::mutator_set::addition_record::AdditionRecord;
+use crate::util_types::mutator_set::get_swbf_indices;
 use crate::util_types::mutator_set::ms_membership_proof::MsMembershipProof;
 use crate::util_types::mutator_set::mutator_set_accumulator::MutatorSetAccumulator;
 use crate::util_types::mutator_set::removal_record::{AbsoluteIndexSet, RemovalRecord};
 use crate::Hash;
 
+/// Number of wallet events buffered for each subscriber
+const WALLET_EVENT_CHANNEL_CAPACITY: usize = 100;
+
+/// Number of monitored UTXOs read from the database, and audited without
+/// releasing the global state, at a time when auditing their membership proofs
+pub(crate) const PROOF_AUDIT_BATCH_SIZE: usize = 100;
+
 pub struct WalletState {
     pub wallet_db: RustyWalletDatabase,
     pub wallet_secret: WalletSecret,
     pub number_of_mps_per_utxo: usize,
 
+    /// Number of blocks on top of the confirming block before a UTXO is spent
+    pub spend_confirmation_depth: usize,
+
+    /// Number of blocks on top of the confirming block before an own coinbase
+    /// UTXO is spent
+    pub coinbase_maturity_depth: usize,
+
     // Any thread may read from expected_utxos, only main thread may write
     pub expected_utxos: UtxoNotificationPool,
 
     /// Path to directory containing wallet files
     wallet_directory_path: PathBuf,
+
+    // Notifies subscribers of wallet events
+    wallet_event_tx: broadcast::Sender<WalletEvent>,
+
+    // Number of times the wallet secret was exported since the node started
+    wallet_secret_export_count: u64,
 }
 
 /// Contains the cryptographic (non-public) data that is needed to recover the mutator set
@@ -61,6 +100,19 @@ pub(crate) struct IncomingUtxoRecoveryData {
     pub aocl_index: u64,
 }
 
+impl IncomingUtxoRecoveryData {
+    /// The indices that are set in the Bloom filter of the mutator set when
+    /// the UTXO is spent.
+    fn absolute_indices(&self) -> AbsoluteIndexSet {
+        AbsoluteIndexSet::new(&get_swbf_indices(
+            Hash::hash(&self.utxo),
+            self.sender_randomness,
+            self.receiver_preimage,
+            self.aocl_index,
+        ))
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 struct StrongUtxoKey {
     utxo_digest: Digest,
@@ -81,6 +133,8 @@ impl Debug for WalletState {
         f.debug_struct("WalletState")
             .field("wallet_secret", &self.wallet_secret)
             .field("number_of_mps_per_utxo", &self.number_of_mps_per_utxo)
+            .field("spend_confirmation_depth", &self.spend_confirmation_depth)
+            .field("coinbase_maturity_depth", &self.coinbase_maturity_depth)
             .field("expected_utxos", &self.expected_utxos)
             .field("wallet_directory_path", &self.wallet_directory_path)
             .finish()
@@ -184,11 +238,15 @@ impl WalletState {
             wallet_db: rusty_wallet_database,
             wallet_secret,
             number_of_mps_per_utxo: cli_args.number_of_mps_per_utxo,
+            spend_confirmation_depth: cli_args.spend_confirmation_depth,
+            coinbase_maturity_depth: cli_args.coinbase_maturity_depth,
             expected_utxos: UtxoNotificationPool::new(
                 cli_args.max_utxo_notification_size,
                 cli_args.max_unconfirmed_utxo_notification_count_per_peer,
             ),
             wallet_directory_path: data_dir.wallet_directory_path(),
+            wallet_event_tx: broadcast::channel(WALLET_EVENT_CHANNEL_CAPACITY).0,
+            wallet_secret_export_count: 0,
         };
 
         // Wallet state has to be initialized with the genesis block, otherwise the outputs
@@ -197,23 +255,7 @@ impl WalletState {
         // incoming randomness such that a wallet-DB recovery will include genesis block
         // outputs.
         if sync_label == Digest::default() {
-            // Check if we are premine recipients
-            let own_spending_key = wallet_state.wallet_secret.nth_generation_spending_key(0);
-            let own_receiving_address = own_spending_key.to_address();
-            for utxo in Block::premine_utxos(cli_args.network) {
-                if utxo.lock_script_hash == own_receiving_address.lock_script().hash() {
-                    wallet_state
-                        .expected_utxos
-                        .add_expected_utxo(
-                            utxo,
-                            Digest::default(),
-                            own_spending_key.privacy_preimage,
-                            UtxoNotifier::Premine,
-                        )
-                        .unwrap();
-                }
-            }
-
+            wallet_state.expect_premine_utxos(cli_args.network);
             wallet_state
                 .update_wallet_state_with_new_block(
                     &MutatorSetAccumulator::default(),
@@ -226,6 +268,25 @@ impl WalletState {
         wallet_state
     }
 
+    /// Register the premine UTXOs that belong to this wallet as expected, such
+    /// that they are picked up when the genesis block is applied.
+    pub(crate) fn expect_premine_utxos(&mut self, network: Network) {
+        let own_spending_key = self.wallet_secret.nth_generation_spending_key(0);
+        let own_receiving_address = own_spending_key.to_address();
+        for utxo in Block::premine_utxos(network) {
+            if utxo.lock_script_hash == own_receiving_address.lock_script().hash() {
+                self.expected_utxos
+                    .add_expected_utxo(
+                        utxo,
+                        Digest::default(),
+                        own_spending_key.privacy_preimage,
+                        UtxoNotifier::Premine,
+                    )
+                    .unwrap();
+            }
+        }
+    }
+
     /// Return a list of UTXOs spent by this wallet in the transaction
     async fn scan_for_spent_utxos(
         &self,
@@ -239,15 +300,20 @@ impl WalletState {
             .collect_vec();
 
         let monitored_utxos = self.wallet_db.monitored_utxos();
+        let archival_restore_utxos = self.wallet_db.get_archival_restore_utxos().await;
         let mut spent_own_utxos = vec![];
 
         let stream = monitored_utxos.stream().await;
         pin_mut!(stream); // needed for iteration
 
         while let Some((i, monitored_utxo)) = stream.next().await {
-            let abs_i = match monitored_utxo.get_latest_membership_proof_entry() {
-                Some(msmp) => msmp.1.compute_indices(Hash::hash(&monitored_utxo.utxo)),
-                None => continue,
+            let abs_i = match (
+                monitored_utxo.get_latest_membership_proof_entry(),
+                archival_restore_utxos.get(&i),
+            ) {
+                (Some(msmp), _) => msmp.1.compute_indices(Hash::hash(&monitored_utxo.utxo)),
+                (None, Some(recovery_data)) => recovery_data.absolute_indices(),
+                (None, None) => continue,
             };
 
             if confirmed_absolute_index_sets.contains(&abs_i) {
@@ -286,6 +352,33 @@ impl WalletState {
             .collect_vec()
     }
 
+    /// Summarize the transaction from the point of view of this wallet.
+    /// Inputs are identified as owned by comparing their absolute index sets
+    /// against those derived from the monitored UTXOs. Outputs are identified
+    /// as going to self if they are either announced to one of the wallet's
+    /// keys, or expected by the wallet (e.g. change outputs).
+    pub async fn summarize_transaction(&self, transaction: &Transaction) -> TransactionSummary {
+        let inputs_owned = self
+            .scan_for_spent_utxos(transaction)
+            .await
+            .into_iter()
+            .map(|(utxo, _absolute_indices, _index)| utxo)
+            .collect_vec();
+
+        let own_outputs: HashMap<AdditionRecord, Utxo> = self
+            .scan_for_announced_utxos(transaction)
+            .into_iter()
+            .chain(self.expected_utxos.scan_for_expected_utxos(transaction))
+            .map(
+                |(addition_record, utxo, _sender_randomness, _receiver_preimage)| {
+                    (addition_record, utxo)
+                },
+            )
+            .collect();
+
+        transaction.summary(inputs_owned, &own_outputs)
+    }
+
     /// Update wallet state with new block. Assume the given block
     /// is valid and that the wallet state is not up to date yet.
     pub async fn update_wallet_state_with_new_block(
@@ -293,6 +386,8 @@ impl WalletState {
         current_mutator_set_accumulator: &MutatorSetAccumulator,
         new_block: &Block,
     ) -> Result<()> {
+        self.scan_for_watched_address_activity(new_block).await;
+
         let transaction: Transaction = new_block.kernel.body.transaction.clone();
 
         let spent_inputs: Vec<(Utxo, AbsoluteIndexSet, u64)> =
@@ -320,6 +415,7 @@ impl WalletState {
         // the process update existing membership proofs with
         // updates from this block
 
+        let archival_restore_utxos = self.wallet_db.get_archival_restore_utxos().await;
         let monitored_utxos = self.wallet_db.monitored_utxos_mut();
         let mut incoming_utxo_recovery_data_list = vec![];
 
@@ -366,6 +462,8 @@ impl WalletState {
                         // TODO: If MUTXO was spent, maybe we also don't want to maintain it?
                         if monitored_utxo.abandoned_at.is_some() {
                             debug!("Monitored UTXO with digest {utxo_digest} was marked as abandoned. Skipping.");
+                        } else if archival_restore_utxos.contains_key(&i) {
+                            debug!("Membership proof of monitored UTXO with digest {utxo_digest} is restored from the archival state when needed. Skipping.");
                         } else {
                             let confirmed_in_block_info = match monitored_utxo.confirmed_in_block {
                                 Some(mutxo_received_in_block) => format!(
@@ -383,6 +481,24 @@ impl WalletState {
             }
         }
 
+        // Monitored UTXOs that were confirmed in this block before, when the block
+        // was canonical previously, must not be registered twice.
+        let reconfirmed_utxo_digests: HashSet<Digest> = {
+            let stream = monitored_utxos.stream_values().await;
+            pin_mut!(stream); // needed for iteration
+
+            stream
+                .filter(|monitored_utxo| {
+                    futures::future::ready(matches!(
+                        monitored_utxo.confirmed_in_block,
+                        Some((digest, _, _)) if digest == new_block.hash()
+                    ))
+                })
+                .map(|monitored_utxo| Hash::hash(&monitored_utxo.utxo))
+                .collect()
+                .await
+        };
+
         // Loop over all input UTXOs, applying all addition records. In each iteration,
         // a) Update all existing MS membership proofs
         // b) Register incoming transactions and derive their membership proofs
@@ -426,7 +542,12 @@ impl WalletState {
 
             // If output UTXO belongs to us, add it to the list of monitored UTXOs and
             // add its membership proof to the list of managed membership proofs.
-            if addition_record_to_utxo_info.contains_key(addition_record) {
+            let is_reconfirmed = addition_record_to_utxo_info
+                .get(addition_record)
+                .is_some_and(|(utxo, _, _)| reconfirmed_utxo_digests.contains(&Hash::hash(utxo)));
+            if is_reconfirmed {
+                debug!("UTXO with addition record {addition_record:?} is already monitored");
+            } else if addition_record_to_utxo_info.contains_key(addition_record) {
                 let utxo = addition_record_to_utxo_info[addition_record].0.clone();
                 let sender_randomness = addition_record_to_utxo_info[addition_record].1;
                 let receiver_preimage = addition_record_to_utxo_info[addition_record].2;
@@ -481,14 +602,15 @@ impl WalletState {
 
         // sanity check
         {
-            let stream = monitored_utxos.stream_values().await;
+            let stream = monitored_utxos.stream().await;
             pin_mut!(stream); // needed for iteration
 
             let mutxo_with_valid_mps = stream
-                .filter(|mutxo| {
+                .filter(|(i, mutxo)| {
                     futures::future::ready(
                         mutxo.is_synced_to(new_block.kernel.header.prev_block_digest)
-                            || mutxo.blockhash_to_membership_proof.is_empty(),
+                            || (mutxo.blockhash_to_membership_proof.is_empty()
+                                && !archival_restore_utxos.contains_key(i)),
                     )
                 })
                 .count()
@@ -619,26 +741,816 @@ impl WalletState {
         Ok(())
     }
 
+    /// Determine whether the given sender randomness was used in one of this
+    /// wallet's recent outgoing UTXOs.
+    pub async fn sender_randomness_was_used(&self, sender_randomness: Digest) -> bool {
+        self.wallet_db
+            .get_sender_randomness_filter()
+            .await
+            .contains(sender_randomness)
+    }
+
+    /// Load the filter over the sender randomness of this wallet's recent
+    /// outgoing UTXOs, and add the given sender randomness values to it.
+    /// Fails if any of the values was used in a recent outgoing UTXO, or
+    /// occurs more than once among the given values.
+    ///
+    /// The returned filter is only persisted by
+    /// [`Self::store_sender_randomness_filter`], such that a batch of outputs
+    /// loads and persists the filter once.
+    pub async fn reserve_sender_randomness(
+        &self,
+        sender_randomness: impl IntoIterator<Item = Digest>,
+    ) -> Result<SenderRandomnessFilter, SenderRandomnessReuseError> {
+        let mut filter = self.wallet_db.get_sender_randomness_filter().await;
+        for randomness in sender_randomness {
+            if filter.contains(randomness) {
+                error!(
+                    "Refusing to reuse sender randomness {randomness}! \
+                    Reusing it links outputs and can render funds unspendable."
+                );
+                return Err(SenderRandomnessReuseError {
+                    sender_randomness: randomness,
+                });
+            }
+            filter.insert(randomness);
+        }
+
+        Ok(filter)
+    }
+
+    /// Persist the filter over the sender randomness of recent outgoing UTXOs,
+    /// see [`Self::reserve_sender_randomness`].
+    pub async fn store_sender_randomness_filter(&mut self, filter: SenderRandomnessFilter) {
+        self.wallet_db.set_sender_randomness_filter(filter).await;
+        self.wallet_db.persist().await;
+    }
+
+    /// Attach a label to a transaction created by this wallet, replacing any
+    /// previous label. The label is stored locally and never broadcast.
+    pub async fn set_transaction_label(
+        &mut self,
+        kernel: &TransactionKernel,
+        label: String,
+    ) -> Result<(), LabelError> {
+        validate_label(&label)?;
+
+        let mut labels = self.wallet_db.get_transaction_labels().await;
+        labels.insert(kernel.mast_hash(), TransactionLabel::new(label, kernel));
+        self.wallet_db.set_transaction_labels(labels).await;
+        self.wallet_db.persist().await;
+
+        Ok(())
+    }
+
+    /// Replace the label of a previously labeled transaction. Returns false
+    /// if no transaction with the given kernel hash was labeled.
+    pub async fn relabel_transaction(
+        &mut self,
+        kernel_hash: Digest,
+        label: String,
+    ) -> Result<bool, LabelError> {
+        validate_label(&label)?;
+
+        let mut labels = self.wallet_db.get_transaction_labels().await;
+        let Some(transaction_label) = labels.get_mut(&kernel_hash) else {
+            return Ok(false);
+        };
+        transaction_label.label = label;
+        self.wallet_db.set_transaction_labels(labels).await;
+        self.wallet_db.persist().await;
+
+        Ok(true)
+    }
+
+    /// Get the label of the transaction with the given kernel hash, if any.
+    pub async fn get_transaction_label(&self, kernel_hash: Digest) -> Option<String> {
+        self.wallet_db
+            .get_transaction_labels()
+            .await
+            .get(&kernel_hash)
+            .map(|transaction_label| transaction_label.label.clone())
+    }
+
+    /// Attach a label to the monitored UTXO with the given index, replacing
+    /// any previous label. Returns false if there is no such monitored UTXO.
+    pub async fn set_monitored_utxo_label(
+        &mut self,
+        index: u64,
+        label: String,
+    ) -> Result<bool, LabelError> {
+        validate_label(&label)?;
+
+        if index >= self.wallet_db.monitored_utxos().len().await {
+            return Ok(false);
+        }
+        let mut labels = self.wallet_db.get_utxo_labels().await;
+        labels.insert(index, label);
+        self.wallet_db.set_utxo_labels(labels).await;
+        self.wallet_db.persist().await;
+
+        Ok(true)
+    }
+
+    /// Get the label of the monitored UTXO with the given index, if any.
+    pub async fn get_monitored_utxo_label(&self, index: u64) -> Option<String> {
+        self.wallet_db.get_utxo_labels().await.remove(&index)
+    }
+
+    /// Serialize the monitored UTXOs to JSON, for backup and migration. The
+    /// export includes the membership proofs, the digests of the blocks they
+    /// are synced to, the data to restore proofs from the archival state with,
+    /// and the labels of the UTXOs, but not the wallet secret. See
+    /// [Self::import_monitored_utxos].
+    pub async fn export_monitored_utxos(&self) -> String {
+        let labels = self.wallet_db.get_utxo_labels().await;
+        let archival_restore_utxos = self.wallet_db.get_archival_restore_utxos().await;
+        let mut monitored_utxos = vec![];
+
+        let stream = self.wallet_db.monitored_utxos().stream().await;
+        pin_mut!(stream); // needed for iteration
+
+        while let Some((i, monitored_utxo)) = stream.next().await {
+            monitored_utxos.push(ExportedMonitoredUtxo {
+                monitored_utxo,
+                label: labels.get(&i).cloned(),
+                archival_restore: archival_restore_utxos.get(&i).cloned(),
+            });
+        }
+
+        let export = MonitoredUtxoExport {
+            sync_label: self.wallet_db.get_sync_label().await,
+            monitored_utxos,
+        };
+        serde_json::to_string_pretty(&export).expect("monitored UTXOs must serialize to JSON")
+    }
+
+    /// Import monitored UTXOs from JSON produced by
+    /// [Self::export_monitored_utxos]. UTXOs with the same hash as a monitored
+    /// UTXO already in the wallet are skipped. Nothing is imported if the
+    /// export was synced to another block than the wallet, or if any UTXO is
+    /// not spendable by this wallet, lacks a membership proof for that block
+    /// and the data to restore one, or has a malformed membership proof or
+    /// label. Returns the number of imported UTXOs.
+    pub async fn import_monitored_utxos(&mut self, json: &str) -> Result<usize> {
+        let export: MonitoredUtxoExport = serde_json::from_str(json)?;
+        let sync_label = self.wallet_db.get_sync_label().await;
+        if export.sync_label != sync_label {
+            bail!(
+                "Export is synced to block {}, but the wallet is synced to block {sync_label}",
+                export.sync_label
+            );
+        }
+
+        let own_spending_key = self.wallet_secret.nth_generation_spending_key(0);
+        let own_lock_script_hash = own_spending_key.to_address().lock_script().hash();
+        for (i, exported) in export.monitored_utxos.iter().enumerate() {
+            let monitored_utxo = &exported.monitored_utxo;
+            if !monitored_utxo.is_well_formed() {
+                bail!("Monitored UTXO {i} has a malformed membership proof");
+            }
+            if let Some(label) = &exported.label {
+                validate_label(label)?;
+            }
+
+            let receiver_preimages_are_own = monitored_utxo
+                .blockhash_to_membership_proof
+                .iter()
+                .map(|(_, membership_proof)| membership_proof.receiver_preimage)
+                .chain(
+                    exported
+                        .archival_restore
+                        .iter()
+                        .map(|restore_data| restore_data.receiver_preimage),
+                )
+                .all(|receiver_preimage| receiver_preimage == own_spending_key.privacy_preimage);
+            if monitored_utxo.utxo.lock_script_hash != own_lock_script_hash
+                || !receiver_preimages_are_own
+            {
+                bail!("Monitored UTXO {i} is not spendable by this wallet");
+            }
+
+            match &exported.archival_restore {
+                Some(restore_data) if restore_data.utxo != monitored_utxo.utxo => {
+                    bail!("Monitored UTXO {i} has restore data for another UTXO")
+                }
+                Some(_) => (),
+                None => {
+                    let is_settled = monitored_utxo.spent_in_block.is_some()
+                        || monitored_utxo.abandoned_at.is_some();
+                    let has_usable_proof = if is_settled {
+                        !monitored_utxo.blockhash_to_membership_proof.is_empty()
+                    } else {
+                        monitored_utxo
+                            .get_membership_proof_for_block(sync_label)
+                            .is_some()
+                    };
+                    if !has_usable_proof {
+                        bail!(
+                            "Monitored UTXO {i} has neither a membership proof for block {sync_label} nor restore data"
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut known_utxo_digests = HashSet::new();
+        {
+            let stream = self.wallet_db.monitored_utxos().stream_values().await;
+            pin_mut!(stream); // needed for iteration
+
+            while let Some(monitored_utxo) = stream.next().await {
+                known_utxo_digests.insert(Hash::hash(&monitored_utxo.utxo));
+            }
+        }
+
+        let mut labels = self.wallet_db.get_utxo_labels().await;
+        let mut archival_restore_utxos = self.wallet_db.get_archival_restore_utxos().await;
+        let mut imported_count = 0;
+        for exported in export.monitored_utxos {
+            let mut monitored_utxo = exported.monitored_utxo;
+            if !known_utxo_digests.insert(Hash::hash(&monitored_utxo.utxo)) {
+                continue;
+            }
+
+            // Keep the most recent membership proofs, as many as this wallet does
+            monitored_utxo.number_of_mps_per_utxo = self.number_of_mps_per_utxo;
+            monitored_utxo
+                .blockhash_to_membership_proof
+                .truncate(self.number_of_mps_per_utxo);
+
+            let index = self.wallet_db.monitored_utxos().len().await;
+            self.wallet_db
+                .monitored_utxos_mut()
+                .push(monitored_utxo)
+                .await;
+            if let Some(label) = exported.label {
+                labels.insert(index, label);
+            }
+            if let Some(restore_data) = exported.archival_restore {
+                archival_restore_utxos.insert(index, restore_data);
+            }
+            imported_count += 1;
+        }
+        self.wallet_db.set_utxo_labels(labels).await;
+        self.wallet_db
+            .set_archival_restore_utxos(archival_restore_utxos)
+            .await;
+        self.wallet_db.persist().await;
+
+        info!("Imported {imported_count} monitored UTXOs");
+
+        Ok(imported_count)
+    }
+
+    /// Record that the wallet secret was exported, such that every export
+    /// leaves a trace in the log and is announced to wallet event subscribers.
+    pub fn record_wallet_secret_export(&mut self, now: Timestamp) {
+        self.wallet_secret_export_count += 1;
+        warn!(
+            "Wallet secret was exported; {} exports since startup",
+            self.wallet_secret_export_count
+        );
+
+        // Sending fails only if there are no subscribers, which is fine
+        let _ = self
+            .wallet_event_tx
+            .send(WalletEvent::WalletSecretExported {
+                exported_at: now,
+                export_count: self.wallet_secret_export_count,
+            });
+    }
+
+    /// The number of times the wallet secret was exported since the node
+    /// started.
+    pub fn wallet_secret_export_count(&self) -> u64 {
+        self.wallet_secret_export_count
+    }
+
+    /// Receive the wallet events emitted from now on.
+    pub fn subscribe_to_wallet_events(&self) -> broadcast::Receiver<WalletEvent> {
+        self.wallet_event_tx.subscribe()
+    }
+
+    /// Start tracking payments to a third-party address, or update its label
+    /// if it is tracked already. Returns true if the address was not tracked
+    /// before.
+    pub async fn add_watch_address(&mut self, address: ReceivingAddress, label: String) -> bool {
+        let mut watched_addresses = self.wallet_db.get_watched_addresses().await;
+        let lock_script_hash = address.lock_script().hash();
+        let is_new = match watched_addresses.get_mut(&lock_script_hash) {
+            Some(watched_address) => {
+                watched_address.label = label;
+                false
+            }
+            None => {
+                watched_addresses.insert(lock_script_hash, WatchedAddress::new(address, label));
+                true
+            }
+        };
+        self.wallet_db
+            .set_watched_addresses(watched_addresses)
+            .await;
+        self.wallet_db.persist().await;
+
+        is_new
+    }
+
+    /// Stop tracking payments to the address, and forget its sightings.
+    /// Returns true if the address was tracked.
+    pub async fn remove_watch_address(&mut self, address: &ReceivingAddress) -> bool {
+        let mut watched_addresses = self.wallet_db.get_watched_addresses().await;
+        let was_watched = watched_addresses
+            .remove(&address.lock_script().hash())
+            .is_some();
+        if was_watched {
+            self.wallet_db
+                .set_watched_addresses(watched_addresses)
+                .await;
+            self.wallet_db.persist().await;
+        }
+
+        was_watched
+    }
+
+    /// Return the tracked third-party addresses along with their sightings.
+    pub async fn get_watched_addresses(&self) -> Vec<WatchedAddress> {
+        self.wallet_db
+            .get_watched_addresses()
+            .await
+            .into_values()
+            .collect_vec()
+    }
+
+    /// Remember the amounts of outputs to watched addresses that this wallet
+    /// created, such that their sightings include the amount.
+    pub async fn register_outputs_to_watched_addresses(
+        &mut self,
+        outputs: &[(AdditionRecord, Utxo)],
+    ) {
+        let mut watched_addresses = self.wallet_db.get_watched_addresses().await;
+        let mut is_updated = false;
+        for (addition_record, utxo) in outputs {
+            if let Some(watched_address) = watched_addresses.get_mut(&utxo.lock_script_hash) {
+                watched_address
+                    .pending_outputs
+                    .push((*addition_record, utxo.get_native_currency_amount()));
+                is_updated = true;
+            }
+        }
+
+        if is_updated {
+            self.wallet_db
+                .set_watched_addresses(watched_addresses)
+                .await;
+            self.wallet_db.persist().await;
+        }
+    }
+
+    /// Record the payments to watched addresses in the block, and notify
+    /// subscribers of them.
+    async fn scan_for_watched_address_activity(&mut self, block: &Block) {
+        let mut watched_addresses = self.wallet_db.get_watched_addresses().await;
+        let mut is_updated = false;
+        for (lock_script_hash, watched_address) in watched_addresses.iter_mut() {
+            for sighting in watched_address.scan_block(block) {
+                info!(
+                    "Watched address \"{}\" received {} in block {}",
+                    watched_address.label,
+                    sighting
+                        .amount
+                        .map(|amount| amount.to_string())
+                        .unwrap_or_else(|| "unknown amount".to_string()),
+                    sighting.block_height
+                );
+
+                // Sending fails if there are no subscribers, which is fine
+                let _ = self
+                    .wallet_event_tx
+                    .send(WalletEvent::WatchedAddressActivity {
+                        lock_script_hash: *lock_script_hash,
+                        label: watched_address.label.clone(),
+                        sighting,
+                    });
+                is_updated = true;
+            }
+        }
+
+        if is_updated {
+            self.wallet_db
+                .set_watched_addresses(watched_addresses)
+                .await;
+            self.wallet_db.persist().await;
+        }
+    }
+
+    /// Undo the effects of the block that the wallet is synced to, such that
+    /// the wallet is synced to the block's parent again. Used when the block is
+    /// abandoned in favor of a competing fork. See
+    /// [`Self::disconnect_blocks`] for how monitored UTXOs are affected.
+    ///
+    /// Expected UTXOs that were confirmed in the block are marked as not
+    /// received.
+    pub async fn revert_block(&mut self, block: &Block) -> Result<()> {
+        let block_digest = block.hash();
+        let sync_label = self.wallet_db.get_sync_label().await;
+        if sync_label != block_digest {
+            bail!("Cannot revert block {block_digest}, as wallet is synced to block {sync_label}");
+        }
+
+        self.disconnect_blocks(&[block_digest]).await;
+
+        for addition_record in block.kernel.body.transaction.kernel.outputs.iter() {
+            self.expected_utxos
+                .mark_as_not_received(*addition_record, block_digest);
+        }
+
+        self.wallet_db
+            .set_sync_label(block.kernel.header.prev_block_digest)
+            .await;
+        self.wallet_db.persist().await;
+
+        Ok(())
+    }
+
+    /// Undo the effects of blocks that are no longer canonical on the monitored
+    /// UTXOs. UTXOs spent in a disconnected block are marked as unspent, and
+    /// membership proofs for disconnected blocks are discarded.
+    ///
+    /// Monitored UTXOs confirmed in a disconnected block are kept, but are left
+    /// unsynced. They are synced again if their block becomes canonical again,
+    /// and are otherwise found to be abandoned, see
+    /// [`GlobalState::prune_abandoned_monitored_utxos`](crate::models::state::GlobalState::prune_abandoned_monitored_utxos).
+    /// The oldest membership proof of a UTXO is always kept, since
+    /// resynchronization starts from it.
+    async fn disconnect_blocks(&mut self, disconnected: &[Digest]) {
+        let monitored_utxos = self.wallet_db.monitored_utxos_mut();
+        let mut updated_mutxos = vec![];
+        {
+            let stream = monitored_utxos.stream().await;
+            pin_mut!(stream); // needed for iteration
+
+            while let Some((i, mut monitored_utxo)) = stream.next().await {
+                if monitored_utxo.abandoned_at.is_some() {
+                    continue;
+                }
+
+                let mut updated = false;
+                if let Some((spending_block, _, _)) = monitored_utxo.spent_in_block {
+                    if disconnected.contains(&spending_block) {
+                        debug!("Marking monitored UTXO {i} spent in disconnected block {spending_block} as unspent");
+                        monitored_utxo.spent_in_block = None;
+                        updated = true;
+                    }
+                }
+
+                let mp_count = monitored_utxo.blockhash_to_membership_proof.len();
+                let oldest_mp_entry = monitored_utxo.blockhash_to_membership_proof.back().cloned();
+                monitored_utxo
+                    .blockhash_to_membership_proof
+                    .retain(|(digest, _)| !disconnected.contains(digest));
+                if monitored_utxo.blockhash_to_membership_proof.is_empty() {
+                    monitored_utxo
+                        .blockhash_to_membership_proof
+                        .extend(oldest_mp_entry);
+                }
+                updated |= monitored_utxo.blockhash_to_membership_proof.len() != mp_count;
+
+                if updated {
+                    updated_mutxos.push((i, monitored_utxo));
+                }
+            }
+        }
+        monitored_utxos.set_many(updated_mutxos).await;
+    }
+
+    /// React to a change of the canonical chain. On a reorganization, the
+    /// effects of the disconnected blocks are undone as described in
+    /// [`Self::disconnect_blocks`]. This is the same rule that
+    /// [`Self::revert_block`] applies, so the outcome does not depend on
+    /// whether the wallet was reverted block by block first.
+    ///
+    /// Returns true if any monitored UTXO is not synced to the new tip, in which
+    /// case the membership proofs should be resynced.
+    pub async fn handle_chain_event(&mut self, chain_event: &ChainEvent) -> bool {
+        match chain_event {
+            ChainEvent::Reorg {
+                disconnected,
+                new_tip,
+            } => {
+                self.disconnect_blocks(disconnected).await;
+
+                let stream = self.wallet_db.monitored_utxos().stream_values().await;
+                pin_mut!(stream); // needed for iteration
+
+                stream
+                    .any(|monitored_utxo| {
+                        futures::future::ready(
+                            monitored_utxo.abandoned_at.is_none()
+                                && !monitored_utxo.blockhash_to_membership_proof.is_empty()
+                                && !monitored_utxo.is_synced_to(*new_tip),
+                        )
+                    })
+                    .await
+            }
+        }
+    }
+
+    /// React to a change of the mempool that the wallet did not cause. If one
+    /// of the wallet's own transactions was evicted or expired, a warning is
+    /// logged and the removal is announced to wallet event subscribers. Its
+    /// expected UTXOs are kept, since the transaction may still be mined by
+    /// others.
+    ///
+    /// Returns true if the event concerns one of the wallet's transactions.
+    pub async fn handle_mempool_event(&self, mempool_event: &MempoolEvent) -> bool {
+        let (transaction_id, transaction, reason, wallet_event) = match mempool_event {
+            MempoolEvent::Evicted {
+                transaction_id,
+                transaction,
+            } => (
+                transaction_id,
+                transaction,
+                "evicted",
+                WalletEvent::OwnTransactionEvicted {
+                    transaction_id: *transaction_id,
+                },
+            ),
+            MempoolEvent::Expired {
+                transaction_id,
+                transaction,
+            } => (
+                transaction_id,
+                transaction,
+                "expired",
+                WalletEvent::OwnTransactionExpired {
+                    transaction_id: *transaction_id,
+                },
+            ),
+            MempoolEvent::Promoted { .. } => return false,
+        };
+        if self.scan_for_spent_utxos(transaction).await.is_empty() {
+            return false;
+        }
+
+        warn!("Own transaction {transaction_id} {reason} from the mempool");
+
+        // Sending fails only if there are no subscribers, which is fine
+        let _ = self.wallet_event_tx.send(wallet_event);
+        true
+    }
+
     pub async fn is_synced_to(&self, tip_hash: Digest) -> bool {
         let db_sync_digest = self.wallet_db.get_sync_label().await;
         if db_sync_digest != tip_hash {
             return false;
         }
         let monitored_utxos = self.wallet_db.monitored_utxos();
+        let archival_restore_utxos = self.wallet_db.get_archival_restore_utxos().await;
 
         // We assume that the membership proof can only be stored
         // if it is valid for the given block hash, so there is
-        // no need to test validity here.
-        let stream = monitored_utxos.stream_values().await;
+        // no need to test validity here. Membership proofs that are
+        // restored from the archival state when needed are never synced.
+        let stream = monitored_utxos.stream().await;
         pin_mut!(stream); // needed for iteration
 
         stream
-            .all(|m| futures::future::ready(m.get_membership_proof_for_block(tip_hash).is_some()))
+            .all(|(i, m)| {
+                futures::future::ready(
+                    m.get_membership_proof_for_block(tip_hash).is_some()
+                        || archival_restore_utxos.contains_key(&i),
+                )
+            })
             .await
     }
 
+    /// Summarize the sizes of the chunk dictionaries in the latest membership
+    /// proofs of unspent monitored UTXOs.
+    pub async fn chunk_dictionary_statistics(&self) -> ChunkDictionaryStatistics {
+        let archival_restore_utxos = self.wallet_db.get_archival_restore_utxos().await;
+        let mut statistics = ChunkDictionaryStatistics::default();
+
+        let stream = self.wallet_db.monitored_utxos().stream().await;
+        pin_mut!(stream); // needed for iteration
+
+        while let Some((i, monitored_utxo)) = stream.next().await {
+            if monitored_utxo.spent_in_block.is_some() || monitored_utxo.abandoned_at.is_some() {
+                continue;
+            }
+
+            match monitored_utxo.get_latest_membership_proof_entry() {
+                Some((_, membership_proof)) => {
+                    let entries = membership_proof.target_chunks.dictionary.len();
+                    statistics.proof_count += 1;
+                    statistics.total_entries += entries;
+                    statistics.max_entries = max(statistics.max_entries, entries);
+                }
+                None if archival_restore_utxos.contains_key(&i) => {
+                    statistics.archival_restore_count += 1;
+                }
+                None => (),
+            }
+        }
+
+        statistics
+    }
+
+    /// Verify the membership proof of every unspent monitored UTXO against
+    /// `tip`, and classify the failures, each with a suggested repair. The
+    /// classification follows [MonitoredUtxo::sync_status], like the resync
+    /// from stored blocks does. Stale proofs can only be told apart from those
+    /// of abandoned UTXOs with the archival state.
+    ///
+    /// To audit a large wallet without holding on to the global state, audit
+    /// it in batches with [Self::audit_proof_batch] instead.
+    pub async fn audit_proofs(
+        &self,
+        tip: &Block,
+        archival_state: Option<&ArchivalState>,
+    ) -> ProofAuditReport {
+        let mut report = ProofAuditReport {
+            tip_digest: tip.hash(),
+            audited_count: 0,
+            entries: vec![],
+        };
+        let mut abandoned_blocks = HashMap::new();
+        let count = self.wallet_db.monitored_utxos().len().await;
+        for batch_start in (0..count).step_by(PROOF_AUDIT_BATCH_SIZE) {
+            let batch_end = min(batch_start + PROOF_AUDIT_BATCH_SIZE as u64, count);
+            self.audit_proof_batch(
+                tip,
+                archival_state,
+                batch_start..batch_end,
+                &mut abandoned_blocks,
+                &mut report,
+            )
+            .await;
+        }
+
+        report
+    }
+
+    /// Audit the monitored UTXOs with the given database indices against
+    /// `tip`, adding them to `report`, see [Self::audit_proofs].
+    /// `abandoned_blocks` caches whether the confirming blocks seen so far
+    /// were abandoned, such that each is looked up only once per audit.
+    pub async fn audit_proof_batch(
+        &self,
+        tip: &Block,
+        archival_state: Option<&ArchivalState>,
+        indices: Range<u64>,
+        abandoned_blocks: &mut HashMap<Digest, bool>,
+        report: &mut ProofAuditReport,
+    ) {
+        let tip_digest = tip.hash();
+        let archival_restore_utxos = self.wallet_db.get_archival_restore_utxos().await;
+        let indices = indices.collect_vec();
+        for (index, monitored_utxo) in indices
+            .iter()
+            .zip_eq(self.wallet_db.monitored_utxos().get_many(&indices).await)
+        {
+            // Spent UTXOs need no membership proof, and those restored from
+            // the archival state when needed have none
+            if monitored_utxo.spent_in_block.is_some() || archival_restore_utxos.contains_key(index)
+            {
+                continue;
+            }
+
+            let finding = match monitored_utxo.sync_status(tip_digest) {
+                MembershipProofSyncStatus::Abandoned | MembershipProofSyncStatus::Unconfirmed => {
+                    continue
+                }
+                MembershipProofSyncStatus::Synced => Self::audit_synced_proof(&monitored_utxo, tip),
+                MembershipProofSyncStatus::Stale {
+                    confirming_block_digest,
+                    ..
+                } => match archival_state {
+                    Some(archival_state) => {
+                        let was_abandoned = match abandoned_blocks.get(&confirming_block_digest) {
+                            Some(was_abandoned) => *was_abandoned,
+                            None => {
+                                let was_abandoned = monitored_utxo
+                                    .was_abandoned(tip_digest, archival_state)
+                                    .await;
+                                abandoned_blocks.insert(confirming_block_digest, was_abandoned);
+                                was_abandoned
+                            }
+                        };
+                        if was_abandoned {
+                            Some(ProofAuditFinding::Abandoned)
+                        } else {
+                            Some(ProofAuditFinding::Stale)
+                        }
+                    }
+                    None => Some(ProofAuditFinding::Stale),
+                },
+            };
+
+            report.audited_count += 1;
+            if let Some(finding) = finding {
+                report.entries.push(ProofAuditEntry {
+                    index: *index,
+                    utxo_digest: Hash::hash(&monitored_utxo.utxo),
+                    finding,
+                    suggested_repair: finding.suggested_repair(archival_state.is_some()),
+                });
+            }
+        }
+    }
+
+    /// Verify the membership proof of a monitored UTXO that is synced to `tip`
+    /// against the mutator set of `tip`. A proof that fails only because all
+    /// of the item's Bloom filter indices are set belongs to a spent UTXO.
+    fn audit_synced_proof(
+        monitored_utxo: &MonitoredUtxo,
+        tip: &Block,
+    ) -> Option<ProofAuditFinding> {
+        let membership_proof = monitored_utxo.get_membership_proof_for_block(tip.hash())?;
+        if !membership_proof.is_well_formed() {
+            return Some(ProofAuditFinding::Corrupt);
+        }
+
+        let item = Hash::hash(&monitored_utxo.utxo);
+        let mutator_set = &tip.kernel.body.mutator_set_accumulator;
+        if mutator_set.verify(item, &membership_proof) {
+            return None;
+        }
+
+        let removal_record = mutator_set.drop(item, &membership_proof);
+        if removal_record.validate(mutator_set) && !mutator_set.can_remove(&removal_record) {
+            Some(ProofAuditFinding::SpentUnnoticed)
+        } else {
+            Some(ProofAuditFinding::Corrupt)
+        }
+    }
+
+    /// Drop the membership proofs of unspent monitored UTXOs whose chunk
+    /// dictionary has more than `max_entries` entries, and keep only the data
+    /// needed to restore them from the archival mutator set. The wallet stops
+    /// updating these proofs with every block, such that the cost of a block
+    /// update does not grow with the chunk dictionaries.
+    ///
+    /// Only valid for archival nodes. Returns the number of UTXOs whose
+    /// membership proofs were dropped.
+    pub async fn move_to_archival_restore_mode(&mut self, max_entries: usize) -> usize {
+        let sync_label = self.wallet_db.get_sync_label().await;
+        let mut archival_restore_utxos = self.wallet_db.get_archival_restore_utxos().await;
+        let mut moved_mutxos = vec![];
+        {
+            let stream = self.wallet_db.monitored_utxos().stream().await;
+            pin_mut!(stream); // needed for iteration
+
+            while let Some((i, mut monitored_utxo)) = stream.next().await {
+                if monitored_utxo.spent_in_block.is_some() || monitored_utxo.abandoned_at.is_some()
+                {
+                    continue;
+                }
+                let Some(membership_proof) =
+                    monitored_utxo.get_membership_proof_for_block(sync_label)
+                else {
+                    continue;
+                };
+                if membership_proof.target_chunks.dictionary.len() <= max_entries {
+                    continue;
+                }
+
+                debug!(
+                    "Dropping membership proof of monitored UTXO {i} with {} chunk dictionary entries",
+                    membership_proof.target_chunks.dictionary.len()
+                );
+                archival_restore_utxos.insert(
+                    i,
+                    IncomingUtxoRecoveryData {
+                        utxo: monitored_utxo.utxo.clone(),
+                        sender_randomness: membership_proof.sender_randomness,
+                        receiver_preimage: membership_proof.receiver_preimage,
+                        aocl_index: membership_proof.auth_path_aocl.leaf_index,
+                    },
+                );
+                monitored_utxo.blockhash_to_membership_proof.clear();
+                moved_mutxos.push((i, monitored_utxo));
+            }
+        }
+
+        let moved_count = moved_mutxos.len();
+        if moved_count > 0 {
+            self.wallet_db
+                .set_archival_restore_utxos(archival_restore_utxos)
+                .await;
+            self.wallet_db
+                .monitored_utxos_mut()
+                .set_many(moved_mutxos)
+                .await;
+            self.wallet_db.persist().await;
+        }
+
+        moved_count
+    }
+
     pub async fn get_wallet_status_from_lock(&self, tip_digest: Digest) -> WalletStatus {
         let monitored_utxos = self.wallet_db.monitored_utxos();
+        let archival_restore_utxos = self.wallet_db.get_archival_restore_utxos().await;
         let mut synced_unspent = vec![];
         let mut unsynced_unspent = vec![];
         let mut synced_spent = vec![];
@@ -647,7 +1559,7 @@ impl WalletState {
         let stream = monitored_utxos.stream().await;
         pin_mut!(stream); // needed for iteration
 
-        while let Some((_i, mutxo)) = stream.next().await {
+        while let Some((i, mutxo)) = stream.next().await {
             // for (_i, mutxo) in monitored_utxos.iter() {
             let utxo = mutxo.utxo.clone();
             let spent = mutxo.spent_in_block.is_some();
@@ -661,17 +1573,16 @@ impl WalletState {
                     ));
                 }
             } else {
-                let any_mp = &mutxo.blockhash_to_membership_proof.iter().next().unwrap().1;
+                // UTXOs without membership proof count as unsynced until their
+                // proof is restored from the archival state
+                let aocl_leaf_index = match mutxo.blockhash_to_membership_proof.iter().next() {
+                    Some((_, any_mp)) => any_mp.auth_path_aocl.leaf_index,
+                    None => archival_restore_utxos[&i].aocl_index,
+                };
                 if spent {
-                    unsynced_spent.push(WalletStatusElement::new(
-                        any_mp.auth_path_aocl.leaf_index,
-                        utxo,
-                    ));
+                    unsynced_spent.push(WalletStatusElement::new(aocl_leaf_index, utxo));
                 } else {
-                    unsynced_unspent.push(WalletStatusElement::new(
-                        any_mp.auth_path_aocl.leaf_index,
-                        utxo,
-                    ));
+                    unsynced_unspent.push(WalletStatusElement::new(aocl_leaf_index, utxo));
                 }
             }
         }
@@ -683,26 +1594,154 @@ impl WalletState {
         }
     }
 
+    /// Report the unspent monitored UTXOs, classified like in
+    /// [Self::get_wallet_status_from_lock] by whether they have a membership
+    /// proof for the given tip, along with the block that confirmed them.
+    ///
+    /// The monitored UTXOs are reported directly rather than looked up from
+    /// the wallet status, whose AOCL leaf indices need not identify a UTXO
+    /// that holds membership proofs from several forks.
+    pub async fn list_unspent(&self, tip_digest: Digest) -> Vec<UnspentUtxoInfo> {
+        let mut synced_unspent = vec![];
+        let mut unsynced_unspent = vec![];
+        let stream = self.wallet_db.monitored_utxos().stream_values().await;
+        pin_mut!(stream); // needed for iteration
+        while let Some(mutxo) = stream.next().await {
+            if mutxo.spent_in_block.is_some() {
+                continue;
+            }
+
+            let synced_to_tip = mutxo.is_synced_to(tip_digest);
+            let info = UnspentUtxoInfo {
+                confirmed_in_block: mutxo
+                    .confirmed_in_block
+                    .map(|(block_digest, timestamp, _height)| (block_digest, timestamp)),
+                synced_to_tip,
+                abandoned: mutxo.abandoned_at.is_some(),
+                utxo: mutxo.utxo,
+            };
+            if synced_to_tip {
+                synced_unspent.push(info);
+            } else {
+                unsynced_unspent.push(info);
+            }
+        }
+
+        synced_unspent.extend(unsynced_unspent);
+        synced_unspent
+    }
+
+    /// Determine whether the monitored UTXO is the coinbase of a block mined
+    /// by this wallet.
+    ///
+    /// The sender randomness of an own coinbase UTXO is derived from the height
+    /// of the block that confirms it. All other outputs of this wallet derive
+    /// it from the height of the tip at the time the transaction was made,
+    /// which precedes the confirming block.
+    fn is_own_coinbase(&self, monitored_utxo: &MonitoredUtxo) -> bool {
+        let (Some((_, _, confirmation_height)), Some((_, membership_proof))) = (
+            monitored_utxo.confirmed_in_block,
+            monitored_utxo.get_latest_membership_proof_entry(),
+        ) else {
+            return false;
+        };
+        let receiver_digest = self
+            .wallet_secret
+            .nth_generation_spending_key(0)
+            .to_address()
+            .privacy_digest;
+
+        membership_proof.sender_randomness
+            == self
+                .wallet_secret
+                .generate_sender_randomness(confirmation_height, receiver_digest)
+    }
+
+    /// Return the AOCL leaf indices of the unspent monitored UTXOs that are not
+    /// yet buried deep enough below the tip to be spent, as configured by
+    /// `spend_confirmation_depth` and `coinbase_maturity_depth`.
+    async fn immature_aocl_leaf_indices(&self, tip_height: BlockHeight) -> HashSet<u64> {
+        let mut immature = HashSet::new();
+        if self.spend_confirmation_depth == 0 && self.coinbase_maturity_depth == 0 {
+            return immature;
+        }
+
+        let monitored_utxos = self.wallet_db.monitored_utxos();
+        let stream = monitored_utxos.stream_values().await;
+        pin_mut!(stream); // needed for iteration
+
+        while let Some(mutxo) = stream.next().await {
+            if mutxo.spent_in_block.is_some() {
+                continue;
+            }
+            let (Some((_, _, confirmation_height)), Some((_, membership_proof))) = (
+                mutxo.confirmed_in_block,
+                mutxo.get_latest_membership_proof_entry(),
+            ) else {
+                continue;
+            };
+
+            let required_depth = if self.is_own_coinbase(&mutxo) {
+                max(self.spend_confirmation_depth, self.coinbase_maturity_depth)
+            } else {
+                self.spend_confirmation_depth
+            };
+            if tip_height - confirmation_height < required_depth as i128 {
+                immature.insert(membership_proof.auth_path_aocl.leaf_index);
+            }
+        }
+
+        immature
+    }
+
     pub async fn allocate_sufficient_input_funds_from_lock(
         &self,
         requested_amount: NeptuneCoins,
-        tip_digest: Digest,
+        tip: &Block,
         timestamp: Timestamp,
     ) -> Result<Vec<(Utxo, LockScript, MsMembershipProof)>> {
         // TODO: Should return the correct spending keys associated with the UTXOs
         // We only attempt to generate a transaction using those UTXOs that have up-to-date
         // membership proofs.
+        let tip_digest = tip.hash();
         let wallet_status = self.get_wallet_status_from_lock(tip_digest).await;
 
+        // Set aside the UTXOs that do not have enough confirmations yet
+        let immature_aocl_leaf_indices = self
+            .immature_aocl_leaf_indices(tip.kernel.header.height)
+            .await;
+        let (immature_unspent, mature_unspent): (Vec<_>, Vec<_>) = wallet_status
+            .synced_unspent
+            .iter()
+            .cloned()
+            .partition(|(wse, _msmp)| immature_aocl_leaf_indices.contains(&wse.aocl_leaf_index));
+        let available_amount = |utxos: &Vec<(WalletStatusElement, MsMembershipProof)>| {
+            utxos
+                .iter()
+                .map(|(wse, _msmp)| &wse.utxo)
+                .filter(|utxo| utxo.can_spend_at(timestamp))
+                .map(|utxo| utxo.get_native_currency_amount())
+                .sum::<NeptuneCoins>()
+        };
+
         // First check that we have enough. Otherwise return an error.
-        if wallet_status.synced_unspent_available_amount(timestamp) < requested_amount {
+        if available_amount(&mature_unspent) < requested_amount {
+            let synced_unspent_available_amount =
+                wallet_status.synced_unspent_available_amount(timestamp);
+            let missing_amount = if synced_unspent_available_amount < requested_amount {
+                requested_amount + -synced_unspent_available_amount
+            } else {
+                NeptuneCoins::zero()
+            };
             bail!(
-                "Insufficient synced amount to create transaction. Requested: {}, Total synced UTXOs: {}. Total synced amount: {}. Synced unspent available amount: {}. Synced unspent timelocked amount: {}. Total unsynced UTXOs: {}. Unsynced unspent amount: {}. Block is: {}",
+                "Insufficient synced amount to create transaction. Requested: {}, Total synced UTXOs: {}. Total synced amount: {}. Synced unspent available amount: {}. Synced unspent timelocked amount: {}. Synced unspent amount locked until sufficiently confirmed: {}. Missing amount: {}. Total unsynced UTXOs: {}. Unsynced unspent amount: {}. Block is: {}",
                 requested_amount,
                 wallet_status.synced_unspent.len(),
                 wallet_status.synced_unspent.iter().map(|(wse, _msmp)| wse.utxo.get_native_currency_amount()).sum::<NeptuneCoins>(),
-                wallet_status.synced_unspent_available_amount(timestamp),
+                synced_unspent_available_amount,
                 wallet_status.synced_unspent_timelocked_amount(timestamp),
+                available_amount(&immature_unspent),
+                missing_amount,
                 wallet_status.unsynced_unspent.len(),
                 wallet_status.unsynced_unspent_amount(),
                 tip_digest);
@@ -716,8 +1755,7 @@ impl WalletState {
             .to_address()
             .lock_script();
         while allocated_amount < requested_amount {
-            let (wallet_status_element, membership_proof) =
-                wallet_status.synced_unspent[ret.len()].clone();
+            let (wallet_status_element, membership_proof) = mature_unspent[ret.len()].clone();
             allocated_amount =
                 allocated_amount + wallet_status_element.utxo.get_native_currency_amount();
             ret.push((
@@ -735,10 +1773,10 @@ impl WalletState {
     pub async fn allocate_sufficient_input_funds(
         &self,
         requested_amount: NeptuneCoins,
-        tip_digest: Digest,
+        tip: &Block,
     ) -> Result<Vec<(Utxo, LockScript, MsMembershipProof)>> {
         let now = Timestamp::now();
-        self.allocate_sufficient_input_funds_from_lock(requested_amount, tip_digest, now)
+        self.allocate_sufficient_input_funds_from_lock(requested_amount, tip, now)
             .await
     }
 
@@ -766,6 +1804,70 @@ impl WalletState {
         }
         own_coins
     }
+
+    /// Compute the confirmed balance as of the block at the given height in
+    /// the canonical chain ending in the given tip, i.e., the total amount of
+    /// the UTXOs confirmed at or below that height and not spent at or below
+    /// it. Confirmations and spends recorded in blocks that do not belong to
+    /// this chain, e.g. because they were abandoned in a reorganization, are
+    /// ignored, such that the result reflects the chain at query time.
+    pub async fn balance_at_height(
+        &self,
+        height: BlockHeight,
+        tip_digest: Digest,
+        archival_state: &ArchivalState,
+    ) -> NeptuneCoins {
+        let monitored_utxos = self.wallet_db.monitored_utxos();
+        let mut balance = NeptuneCoins::zero();
+
+        let stream = monitored_utxos.stream_values().await;
+        pin_mut!(stream); // needed for iteration
+        while let Some(mutxo) = stream.next().await {
+            let confirmed = Self::canonical_at_or_below(
+                mutxo.confirmed_in_block,
+                height,
+                tip_digest,
+                archival_state,
+            )
+            .await;
+            if !confirmed {
+                continue;
+            }
+
+            let spent = Self::canonical_at_or_below(
+                mutxo.spent_in_block,
+                height,
+                tip_digest,
+                archival_state,
+            )
+            .await;
+            if !spent {
+                balance = balance + mutxo.utxo.get_native_currency_amount();
+            }
+        }
+
+        balance
+    }
+
+    /// Determine whether the block in which a monitored UTXO was confirmed or
+    /// spent, if any, lies at or below the given height in the canonical chain
+    /// ending in the given tip.
+    async fn canonical_at_or_below(
+        block: Option<(Digest, Timestamp, BlockHeight)>,
+        height: BlockHeight,
+        tip_digest: Digest,
+        archival_state: &ArchivalState,
+    ) -> bool {
+        match block {
+            Some((block_digest, _timestamp, block_height)) => {
+                block_height <= height
+                    && archival_state
+                        .block_belongs_to_canonical_chain(block_digest, tip_digest)
+                        .await
+            }
+            None => false,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -776,8 +1878,12 @@ mod tests {
 
     use crate::{
         config_models::network::Network,
+        models::blockchain::transaction::transaction_kernel::pseudorandom_transaction_kernel,
         models::state::wallet::utxo_notification_pool::ExpectedUtxo,
-        tests::shared::{make_mock_block, mock_genesis_global_state, mock_genesis_wallet_state},
+        tests::shared::{
+            make_mock_block, mock_genesis_global_state, mock_genesis_wallet_state,
+            unit_test_data_directory,
+        },
     };
 
     use super::*;
@@ -829,7 +1935,13 @@ mod tests {
         let mut latest_block = genesis_block;
         for _ in 1..=2 {
             let (new_block, _new_block_coinbase_utxo, _new_block_coinbase_sender_randomness) =
-                make_mock_block(&latest_block, None, other_recipient_address, rng.gen());
+                make_mock_block(
+                    network,
+                    &latest_block,
+                    None,
+                    other_recipient_address,
+                    rng.gen(),
+                );
             own_global_state
                 .wallet_state
                 .update_wallet_state_with_new_block(&mutator_set_accumulator, &new_block)
@@ -868,6 +1980,7 @@ mod tests {
         let own_recipient_address = own_spending_key.to_address();
         let (block_3a, block_3a_coinbase_utxo, block_3a_coinbase_sender_randomness) =
             make_mock_block(
+                network,
                 &latest_block.clone(),
                 None,
                 own_recipient_address,
@@ -915,7 +2028,13 @@ mod tests {
 
         // Fork the blockchain with 3b, with no coinbase for us
         let (block_3b, _block_3b_coinbase_utxo, _block_3b_coinbase_sender_randomness) =
-            make_mock_block(&latest_block, None, other_recipient_address, rng.gen());
+            make_mock_block(
+                network,
+                &latest_block,
+                None,
+                other_recipient_address,
+                rng.gen(),
+            );
         own_global_state
             .set_new_tip(block_3b.clone())
             .await
@@ -946,7 +2065,13 @@ mod tests {
         latest_block = block_3b;
         for _ in 4..=11 {
             let (new_block, _new_block_coinbase_utxo, _new_block_coinbase_sender_randomness) =
-                make_mock_block(&latest_block, None, other_recipient_address, rng.gen());
+                make_mock_block(
+                    network,
+                    &latest_block,
+                    None,
+                    other_recipient_address,
+                    rng.gen(),
+                );
             own_global_state
                 .set_new_tip(new_block.clone())
                 .await
@@ -977,8 +2102,13 @@ mod tests {
         );
 
         // Mine *one* more block. Verify that MUTXO is pruned
-        let (block_12, _, _) =
-            make_mock_block(&latest_block, None, other_recipient_address, rng.gen());
+        let (block_12, _, _) = make_mock_block(
+            network,
+            &latest_block,
+            None,
+            other_recipient_address,
+            rng.gen(),
+        );
         own_global_state
             .set_new_tip(block_12.clone())
             .await
@@ -1062,4 +2192,252 @@ mod tests {
                 .verify(Hash::hash(&utxo), &ms_membership_proof));
         }
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn transaction_labels_survive_wallet_db_reopen_test() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let wallet_secret = WalletSecret::new_random();
+        let cli_args = Args {
+            network,
+            ..Default::default()
+        };
+        let data_dir = unit_test_data_directory(network).unwrap();
+        let labeled_kernel = pseudorandom_transaction_kernel(rng.gen(), 2, 2, 0);
+        let unlabeled_kernel = pseudorandom_transaction_kernel(rng.gen(), 1, 1, 0);
+
+        let mut wallet_state =
+            WalletState::new_from_wallet_secret(&data_dir, wallet_secret.clone(), &cli_args).await;
+        wallet_state
+            .set_transaction_label(&labeled_kernel, "rent".to_string())
+            .await
+            .unwrap();
+        wallet_state
+            .set_transaction_label(&labeled_kernel, "rent, March".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("rent, March".to_string()),
+            wallet_state
+                .get_transaction_label(labeled_kernel.mast_hash())
+                .await,
+            "Setting a label must replace the previous one"
+        );
+
+        // close and reopen the wallet database
+        drop(wallet_state);
+        let reopened_wallet_state =
+            WalletState::new_from_wallet_secret(&data_dir, wallet_secret, &cli_args).await;
+
+        assert_eq!(
+            Some("rent, March".to_string()),
+            reopened_wallet_state
+                .get_transaction_label(labeled_kernel.mast_hash())
+                .await
+        );
+        assert!(reopened_wallet_state
+            .get_transaction_label(unlabeled_kernel.mast_hash())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn labels_can_be_edited_and_are_validated_test() {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let mut wallet_state =
+            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
+        let kernel = pseudorandom_transaction_kernel(rng.gen(), 1, 1, 0);
+
+        // Only labeled transactions can be relabeled by kernel hash
+        assert!(!wallet_state
+            .relabel_transaction(kernel.mast_hash(), "payroll June".to_string())
+            .await
+            .unwrap());
+        wallet_state
+            .set_transaction_label(&kernel, "payroll".to_string())
+            .await
+            .unwrap();
+        assert!(wallet_state
+            .relabel_transaction(kernel.mast_hash(), "payroll June".to_string())
+            .await
+            .unwrap());
+        assert_eq!(
+            Some("payroll June".to_string()),
+            wallet_state.get_transaction_label(kernel.mast_hash()).await
+        );
+
+        // The premine is the only monitored UTXO
+        assert!(wallet_state
+            .set_monitored_utxo_label(0, "premine".to_string())
+            .await
+            .unwrap());
+        assert!(!wallet_state
+            .set_monitored_utxo_label(1, "nonexistent".to_string())
+            .await
+            .unwrap());
+        assert_eq!(
+            Some("premine".to_string()),
+            wallet_state.get_monitored_utxo_label(0).await
+        );
+        assert!(wallet_state.get_monitored_utxo_label(1).await.is_none());
+
+        // Invalid labels are rejected and leave the previous label in place
+        assert_eq!(
+            Err(LabelError::ControlCharacter),
+            wallet_state
+                .set_monitored_utxo_label(0, "pre\tmine".to_string())
+                .await
+        );
+        assert!(wallet_state
+            .relabel_transaction(kernel.mast_hash(), "x".repeat(1000))
+            .await
+            .is_err());
+        assert_eq!(
+            Some("premine".to_string()),
+            wallet_state.get_monitored_utxo_label(0).await
+        );
+        assert_eq!(
+            Some("payroll June".to_string()),
+            wallet_state.get_transaction_label(kernel.mast_hash()).await
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn monitored_utxos_survive_json_round_trip_test() {
+        let network = Network::RegTest;
+        let mut premine_wallet_state =
+            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
+        premine_wallet_state
+            .set_monitored_utxo_label(0, "premine".to_string())
+            .await
+            .unwrap();
+        let exported = premine_wallet_state.export_monitored_utxos().await;
+
+        // Restoring into a fresh wallet with the same secret yields the same
+        // monitored UTXOs
+        let mut restored_wallet_state =
+            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
+        restored_wallet_state
+            .wallet_db
+            .monitored_utxos_mut()
+            .pop()
+            .await
+            .unwrap();
+        assert_eq!(
+            1,
+            restored_wallet_state
+                .import_monitored_utxos(&exported)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            exported,
+            restored_wallet_state.export_monitored_utxos().await
+        );
+        assert_eq!(
+            Some("premine".to_string()),
+            restored_wallet_state.get_monitored_utxo_label(0).await
+        );
+
+        // Known UTXOs are not imported twice
+        assert_eq!(
+            0,
+            restored_wallet_state
+                .import_monitored_utxos(&exported)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            0,
+            premine_wallet_state
+                .import_monitored_utxos(&exported)
+                .await
+                .unwrap()
+        );
+
+        // Malformed membership proofs are rejected
+        let mut malformed: MonitoredUtxoExport = serde_json::from_str(&exported).unwrap();
+        malformed.monitored_utxos[0]
+            .monitored_utxo
+            .blockhash_to_membership_proof[0]
+            .1
+            .auth_path_aocl
+            .authentication_path = vec![Digest::default(); 64];
+        let mut empty_wallet_state =
+            mock_genesis_wallet_state(WalletSecret::new_random(), network).await;
+        assert!(empty_wallet_state
+            .import_monitored_utxos(&serde_json::to_string(&malformed).unwrap())
+            .await
+            .is_err());
+        assert!(empty_wallet_state
+            .import_monitored_utxos("not json")
+            .await
+            .is_err());
+        assert!(
+            empty_wallet_state
+                .wallet_db
+                .monitored_utxos()
+                .is_empty()
+                .await
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn unusable_monitored_utxos_are_not_imported_test() {
+        let network = Network::RegTest;
+        let premine_wallet_state =
+            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
+        let exported: MonitoredUtxoExport =
+            serde_json::from_str(&premine_wallet_state.export_monitored_utxos().await).unwrap();
+        let mut fresh_wallet_state =
+            mock_genesis_wallet_state(WalletSecret::devnet_wallet(), network).await;
+        fresh_wallet_state
+            .wallet_db
+            .monitored_utxos_mut()
+            .pop()
+            .await
+            .unwrap();
+
+        // UTXOs of another wallet are rejected
+        let mut other_wallet_state =
+            mock_genesis_wallet_state(WalletSecret::new_random(), network).await;
+        assert!(other_wallet_state
+            .import_monitored_utxos(&serde_json::to_string(&exported).unwrap())
+            .await
+            .is_err());
+
+        // So are UTXOs without membership proof or restore data
+        let mut without_proof = exported.clone();
+        without_proof.monitored_utxos[0]
+            .monitored_utxo
+            .blockhash_to_membership_proof
+            .clear();
+        assert!(fresh_wallet_state
+            .import_monitored_utxos(&serde_json::to_string(&without_proof).unwrap())
+            .await
+            .is_err());
+
+        // And exports synced to another block than the wallet
+        let mut other_block = exported.clone();
+        other_block.sync_label = thread_rng().gen();
+        assert!(fresh_wallet_state
+            .import_monitored_utxos(&serde_json::to_string(&other_block).unwrap())
+            .await
+            .is_err());
+
+        for wallet_state in [&fresh_wallet_state, &other_wallet_state] {
+            assert!(wallet_state.wallet_db.monitored_utxos().is_empty().await);
+        }
+        let tip_digest = Block::genesis_block(network).hash();
+        assert!(fresh_wallet_state
+            .get_wallet_status_from_lock(tip_digest)
+            .await
+            .synced_unspent
+            .is_empty());
+    }
 }
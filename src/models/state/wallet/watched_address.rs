@@ -0,0 +1,115 @@
+use crate::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
+use crate::prelude::twenty_first;
+
+use serde::{Deserialize, Serialize};
+use twenty_first::math::digest::Digest;
+
+use super::address::generation_address::ReceivingAddress;
+use crate::models::blockchain::block::block_height::BlockHeight;
+use crate::models::blockchain::block::Block;
+use crate::models::consensus::timestamp::Timestamp;
+use crate::util_types::mutator_set::addition_record::AdditionRecord;
+
+/// A third-party address whose incoming payments the wallet tracks, without
+/// owning its keys. No membership proofs are maintained for such payments.
+///
+/// Outputs only reveal a commitment to their UTXO, so payments to a watched
+/// address are recognized by the public announcements addressed to it. The
+/// announcement's payload is encrypted to the owner of the address, so the
+/// amount remains unknown, unless the payment was made by this wallet.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WatchedAddress {
+    pub address: ReceivingAddress,
+    pub label: String,
+
+    /// Payments to this address that were made by this wallet and that have
+    /// not been seen in a block yet.
+    pub pending_outputs: Vec<(AdditionRecord, NeptuneCoins)>,
+
+    pub sightings: Vec<WatchedAddressSighting>,
+}
+
+/// A payment to a watched address seen in a block.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WatchedAddressSighting {
+    pub block_digest: Digest,
+    pub block_height: BlockHeight,
+
+    /// `None` if the amount could not be derived.
+    pub amount: Option<NeptuneCoins>,
+}
+
+impl WatchedAddress {
+    pub fn new(address: ReceivingAddress, label: String) -> Self {
+        Self {
+            address,
+            label,
+            pending_outputs: vec![],
+            sightings: vec![],
+        }
+    }
+
+    /// Find the payments to this address in the block, and record them as
+    /// sightings. Returns the new sightings.
+    pub fn scan_block(&mut self, block: &Block) -> Vec<WatchedAddressSighting> {
+        let kernel = &block.kernel.body.transaction.kernel;
+        let sighting = |amount| WatchedAddressSighting {
+            block_digest: block.hash(),
+            block_height: block.kernel.header.height,
+            amount,
+        };
+
+        // Payments made by this wallet have known amounts
+        let (confirmed_outputs, pending_outputs): (Vec<_>, Vec<_>) = self
+            .pending_outputs
+            .drain(..)
+            .partition(|(addition_record, _)| kernel.outputs.contains(addition_record));
+        self.pending_outputs = pending_outputs;
+        let mut new_sightings = confirmed_outputs
+            .into_iter()
+            .map(|(_, amount)| sighting(Some(amount)))
+            .collect::<Vec<_>>();
+
+        // Other payments can only be recognized by their announcements. Those of
+        // this wallet's own payments are accounted for above.
+        let announcement_count = kernel
+            .public_announcements
+            .iter()
+            .filter(|announcement| self.address.matches_public_announcement(announcement))
+            .count();
+        let unknown_payment_count = announcement_count.saturating_sub(new_sightings.len());
+        new_sightings.extend((0..unknown_payment_count).map(|_| sighting(None)));
+
+        self.sightings.extend(new_sightings.iter().cloned());
+        new_sightings
+    }
+}
+
+/// Notifications about the wallet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WalletEvent {
+    /// A payment to a watched address was seen in a block.
+    WatchedAddressActivity {
+        lock_script_hash: Digest,
+        label: String,
+        sighting: WatchedAddressSighting,
+    },
+
+    /// The wallet secret was exported in encrypted form. `export_count` is the
+    /// number of exports since the node started, including this one.
+    WalletSecretExported {
+        exported_at: Timestamp,
+        export_count: u64,
+    },
+
+    /// A transaction spending this wallet's UTXOs was evicted from the
+    /// mempool to make room for transactions with a higher fee density. Other
+    /// nodes may still mine it, but it is no longer relayed by this node, so
+    /// it may have to be resent with a higher fee.
+    OwnTransactionEvicted { transaction_id: Digest },
+
+    /// A transaction spending this wallet's UTXOs was removed from the mempool
+    /// because it was not mined within the mempool's time to live. Like an
+    /// evicted transaction, it may have to be resent.
+    OwnTransactionExpired { transaction_id: Digest },
+}
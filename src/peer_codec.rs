@@ -0,0 +1,281 @@
+//! Encoding of peer messages on the wire. The handshake and the connection
+//! status are always encoded with bincode, which every node speaks. The
+//! messages that follow are encoded with the codec that both sides agreed on
+//! in the handshake, see [`PeerCodec::negotiate`].
+
+use crate::models::peer::{HandshakeData, LegacyHandshakeData, PeerFeatures, PeerMessage};
+use crate::peer_traffic_counter::PeerByteCounts;
+
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio_serde::formats::{SymmetricalBincode, SymmetricalJson};
+use tokio_serde::{Deserializer, Serializer};
+
+/// The formats that peer messages can be encoded in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PeerCodec {
+    /// Compact binary encoding, spoken by all nodes
+    #[default]
+    Bincode,
+
+    /// Human-readable encoding, e.g. for inspecting the traffic between two
+    /// nodes. Only used if both nodes opted into it.
+    Json,
+}
+
+impl PeerCodec {
+    /// The codec for the messages following the handshake. JSON is only used
+    /// if both sides announce it, and bincode otherwise.
+    pub fn negotiate(own_handshake: &HandshakeData, other_handshake: &HandshakeData) -> Self {
+        if own_handshake.features.contains(PeerFeatures::JSON_CODEC)
+            && other_handshake.features.contains(PeerFeatures::JSON_CODEC)
+        {
+            PeerCodec::Json
+        } else {
+            PeerCodec::Bincode
+        }
+    }
+}
+
+impl fmt::Display for PeerCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let string = match self {
+            PeerCodec::Bincode => "bincode",
+            PeerCodec::Json => "json",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl FromStr for PeerCodec {
+    type Err = String;
+    fn from_str(input: &str) -> Result<PeerCodec, Self::Err> {
+        match input {
+            "bincode" => Ok(PeerCodec::Bincode),
+            "json" => Ok(PeerCodec::Json),
+            _ => Err(format!("Failed to parse {} as peer codec", input)),
+        }
+    }
+}
+
+/// Switches the codec of a connection once the handshake is complete. Held
+/// by the connection handler, while the [`PeerMessageCodec`] it belongs to
+/// is owned by the framed connection.
+#[derive(Clone, Debug, Default)]
+pub struct PeerCodecSwitch(Arc<AtomicBool>);
+
+impl PeerCodecSwitch {
+    /// Encode and decode all following messages with the given codec.
+    pub fn switch_to(&self, codec: PeerCodec) {
+        self.0.store(codec == PeerCodec::Json, Ordering::SeqCst);
+    }
+
+    fn codec(&self) -> PeerCodec {
+        if self.0.load(Ordering::SeqCst) {
+            PeerCodec::Json
+        } else {
+            PeerCodec::Bincode
+        }
+    }
+}
+
+/// The first message of nodes that predate protocol versions. Shares its
+/// variant index with [`PeerMessage::Handshake`].
+#[derive(Serialize, Deserialize)]
+enum LegacyPeerMessage {
+    Handshake(Box<(Vec<u8>, LegacyHandshakeData)>),
+}
+
+impl From<LegacyPeerMessage> for PeerMessage {
+    fn from(message: LegacyPeerMessage) -> Self {
+        let LegacyPeerMessage::Handshake(handshake) = message;
+        let (magic, handshake_data) = *handshake;
+        PeerMessage::Handshake(Box::new((magic, handshake_data.into())))
+    }
+}
+
+/// Encodes peer messages with bincode until its [`PeerCodecSwitch`] selects
+/// another codec, and counts the bytes of the encoded messages.
+#[derive(Debug, Default)]
+pub struct PeerMessageCodec {
+    switch: PeerCodecSwitch,
+    byte_counts: PeerByteCounts,
+    bincode: SymmetricalBincode<PeerMessage>,
+    json: SymmetricalJson<PeerMessage>,
+}
+
+impl PeerMessageCodec {
+    /// A codec starting out with bincode, and the switch that selects the
+    /// codec for the messages following the handshake.
+    pub fn new() -> (Self, PeerCodecSwitch) {
+        let codec = Self::default();
+        let switch = codec.switch.clone();
+        (codec, switch)
+    }
+
+    /// The bytes encoded and decoded by this codec, shared with the caller.
+    pub fn byte_counts(&self) -> PeerByteCounts {
+        self.byte_counts.clone()
+    }
+}
+
+impl Serializer<PeerMessage> for PeerMessageCodec {
+    type Error = io::Error;
+
+    fn serialize(self: Pin<&mut Self>, item: &PeerMessage) -> Result<Bytes, Self::Error> {
+        let codec = self.get_mut();
+        let bytes = match codec.switch.codec() {
+            PeerCodec::Bincode => Pin::new(&mut codec.bincode).serialize(item)?,
+            PeerCodec::Json => Pin::new(&mut codec.json).serialize(item)?,
+        };
+        codec.byte_counts.record_sent(bytes.len());
+        Ok(bytes)
+    }
+}
+
+impl Deserializer<PeerMessage> for PeerMessageCodec {
+    type Error = io::Error;
+
+    fn deserialize(self: Pin<&mut Self>, src: &BytesMut) -> Result<PeerMessage, Self::Error> {
+        let codec = self.get_mut();
+        let message = match codec.switch.codec() {
+            PeerCodec::Bincode => {
+                Pin::new(&mut codec.bincode)
+                    .deserialize(src)
+                    .or_else(|error| {
+                        // Let legacy handshakes through, to refuse them for their
+                        // protocol version
+                        bincode::deserialize::<LegacyPeerMessage>(src)
+                            .map(PeerMessage::from)
+                            .map_err(|_| error)
+                    })?
+            }
+            PeerCodec::Json => Pin::new(&mut codec.json).deserialize(src)?,
+        };
+        codec.byte_counts.record_received(src.len());
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod peer_codec_tests {
+    use super::*;
+
+    use crate::config_models::network::Network;
+    use crate::tests::shared::get_dummy_handshake_data_for_genesis;
+
+    #[tokio::test]
+    async fn json_is_used_only_if_both_sides_announce_it_test() {
+        let bincode_handshake = get_dummy_handshake_data_for_genesis(Network::Alpha).await;
+        let json_handshake = HandshakeData {
+            features: bincode_handshake.features.with(PeerFeatures::JSON_CODEC),
+            ..bincode_handshake.clone()
+        };
+
+        assert_eq!(
+            PeerCodec::Json,
+            PeerCodec::negotiate(&json_handshake, &json_handshake)
+        );
+        for (own_handshake, other_handshake) in [
+            (&bincode_handshake, &json_handshake),
+            (&json_handshake, &bincode_handshake),
+            (&bincode_handshake, &bincode_handshake),
+        ] {
+            assert_eq!(
+                PeerCodec::Bincode,
+                PeerCodec::negotiate(own_handshake, other_handshake)
+            );
+        }
+    }
+
+    #[test]
+    fn codec_is_switched_for_following_messages_test() {
+        let (mut codec, switch) = PeerMessageCodec::new();
+        let message = PeerMessage::BlockNotificationRequest;
+        let bincode_bytes = Pin::new(&mut codec).serialize(&message).unwrap();
+
+        switch.switch_to(PeerCodec::Json);
+        let json_bytes = Pin::new(&mut codec).serialize(&message).unwrap();
+        assert_ne!(bincode_bytes, json_bytes);
+        assert_eq!(
+            message,
+            Pin::new(&mut codec)
+                .deserialize(&BytesMut::from(&json_bytes[..]))
+                .unwrap()
+        );
+        assert!(Pin::new(&mut codec)
+            .deserialize(&BytesMut::from(&bincode_bytes[..]))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn legacy_handshake_is_decoded_as_protocol_version_zero_test() {
+        let handshake = get_dummy_handshake_data_for_genesis(Network::Alpha).await;
+        let legacy_handshake = LegacyHandshakeData {
+            tip_header: handshake.tip_header.clone(),
+            listen_port: handshake.listen_port,
+            network: handshake.network,
+            instance_id: handshake.instance_id,
+            version: handshake.version.clone(),
+            is_archival_node: true,
+        };
+        let magic = b"magic".to_vec();
+        let legacy_bytes = bincode::serialize(&LegacyPeerMessage::Handshake(Box::new((
+            magic.clone(),
+            legacy_handshake,
+        ))))
+        .unwrap();
+
+        let (mut codec, _switch) = PeerMessageCodec::new();
+        let PeerMessage::Handshake(decoded) = Pin::new(&mut codec)
+            .deserialize(&BytesMut::from(&legacy_bytes[..]))
+            .unwrap()
+        else {
+            panic!("legacy handshake must be decoded as a handshake");
+        };
+        let (decoded_magic, decoded_handshake) = *decoded;
+        assert_eq!(magic, decoded_magic);
+        assert_eq!(0, decoded_handshake.protocol_version);
+        assert!(decoded_handshake.features.is_archival_node());
+        assert!(!handshake.is_compatible_with(&decoded_handshake));
+    }
+
+    #[test]
+    fn encoded_bytes_are_counted_test() {
+        let (mut codec, switch) = PeerMessageCodec::new();
+        let byte_counts = codec.byte_counts();
+        let message = PeerMessage::Ping(1);
+        let bincode_bytes = Pin::new(&mut codec).serialize(&message).unwrap();
+        switch.switch_to(PeerCodec::Json);
+        let json_bytes = Pin::new(&mut codec).serialize(&message).unwrap();
+        assert_eq!(
+            (bincode_bytes.len() + json_bytes.len()) as u64,
+            byte_counts.sent()
+        );
+
+        Pin::new(&mut codec)
+            .deserialize(&BytesMut::from(&json_bytes[..]))
+            .unwrap();
+        assert_eq!(json_bytes.len() as u64, byte_counts.received());
+
+        // Undecodable messages are not counted
+        assert!(Pin::new(&mut codec)
+            .deserialize(&BytesMut::from(&bincode_bytes[..]))
+            .is_err());
+        assert_eq!(json_bytes.len() as u64, byte_counts.received());
+    }
+
+    #[test]
+    fn peer_codec_parses_from_its_name_test() {
+        for codec in [PeerCodec::Bincode, PeerCodec::Json] {
+            assert_eq!(Ok(codec), codec.to_string().parse());
+        }
+        assert!("protobuf".parse::<PeerCodec>().is_err());
+    }
+}
@@ -1,40 +1,185 @@
+use crate::models::consensus::mast_hash::MastHash;
 use crate::models::consensus::timestamp::Timestamp;
 use crate::prelude::twenty_first;
 
+use crate::config_models::network::Network;
 use crate::connect_to_peers::close_peer_connected_callback;
+use crate::mine_loop::make_block_body;
+use crate::models::blockchain::block::block_header::{BlockHeader, MEDIAN_TIMESTAMP_BLOCK_COUNT};
 use crate::models::blockchain::block::block_height::BlockHeight;
-use crate::models::blockchain::block::transfer_block::TransferBlock;
-use crate::models::blockchain::block::Block;
+use crate::models::blockchain::block::transfer_block::{ProofType, TransferBlock};
+use crate::models::blockchain::block::{Block, RecentAncestor, MAX_UNCLE_GENERATIONS};
+use crate::models::blockchain::shared::Hash;
+use crate::models::blockchain::transaction::Transaction;
+use crate::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
 use crate::models::channel::{MainToPeerThread, PeerThreadToMain, PeerThreadToMainTransaction};
 use crate::models::peer::{
-    HandshakeData, MutablePeerState, PeerInfo, PeerMessage, PeerSanctionReason, PeerStanding,
-};
-use crate::models::state::mempool::{
-    MEMPOOL_IGNORE_TRANSACTIONS_THIS_MANY_SECS_AHEAD, MEMPOOL_TX_THRESHOLD_AGE_IN_SECS,
+    BlockBatchWindow, HandshakeData, KnownPeer, MutablePeerState, PeerBlockNotification,
+    PeerFeatures, PeerInfo, PeerMessage, PeerSanctionReason, PeerStanding, PeerStatistics,
+    PeerTraffic, PendingBlockBatch, PendingCompactBlock, PendingHeaderRequest,
 };
+use crate::models::state::header_chain::{block_digest, validate_child_header};
+use crate::models::state::mempool::MEMPOOL_IGNORE_TRANSACTIONS_THIS_MANY_SECS_AHEAD;
+use crate::models::state::networking_state::SUSTAINED_CONNECTION_DURATION;
 use crate::models::state::GlobalStateLock;
+use crate::peer_traffic_counter::{CountingPeerStream, PeerByteCounts};
+use crate::socks5;
 use anyhow::{bail, Result};
 use futures::sink::{Sink, SinkExt};
 use futures::stream::{TryStream, TryStreamExt};
+use get_size::GetSize;
 use itertools::Itertools;
 use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::marker::Unpin;
-use std::net::SocketAddr;
-use std::time::SystemTime;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::select;
 use tokio::sync::{broadcast, mpsc};
+use tokio::time;
 use tracing::{debug, error, info, warn};
 use twenty_first::math::digest::Digest;
+use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 
 const STANDARD_BLOCK_BATCH_SIZE: usize = 50;
-const MAX_PEER_LIST_LENGTH: usize = 10;
+const MAX_MEMPOOL_INVENTORY_LENGTH: usize = 10_000;
+
+/// Minimum time between two `MempoolInventoryRequest`s on a connection. More
+/// frequent requests are not sent, and are not answered but sanctioned when
+/// received, since every answer lists up to `MAX_MEMPOOL_INVENTORY_LENGTH`
+/// transactions.
+const MIN_MEMPOOL_INVENTORY_REQUEST_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum number of transactions of a compact block that are requested or
+/// served with one `GetBlockTransactions` message. Compact blocks with more
+/// transactions are requested in full.
+const MAX_BLOCK_TRANSACTIONS_REQUEST_LENGTH: usize = 1_000;
+pub(crate) const MAX_BLOCK_HEADERS_RESPONSE_LENGTH: usize = 2_000;
 const MINIMUM_BLOCK_BATCH_SIZE: usize = 2;
 
+/// Number of batch requests kept in flight to a peer that serves blocks by
+/// height, such that syncing is not paced by the round trip to the peer
+const BLOCK_BATCH_WINDOW_SIZE: usize = 4;
+
+/// Interval at which peers announcing `PeerFeatures::PING` are pinged, and
+/// at which the traffic statistics are published in the peer map. A peer that
+/// has not answered a ping when the next one is due is disconnected.
+const PEER_PING_INTERVAL_IN_SECS: u64 = 60;
+
+/// Minimum time between two `Ping`s from a peer that are answered. More
+/// frequent pings are sanctioned. Well below `PEER_PING_INTERVAL_IN_SECS`,
+/// such that peers pinging at a slightly shorter interval are not affected.
+const MIN_PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Block notifications claiming more proof-of-work than this many blocks at
+/// the tip's difficulty on top of the tip are spot checked before they are
+/// trusted.
+const SPOT_CHECK_MARGIN_IN_BLOCKS: usize = 10;
+
+/// Longest window accepted from the block serving policy of a peer. A peer
+/// that throttles block requests never pauses them for longer than its
+/// window, as its quota is available again by then.
+const MAX_PEER_BLOCK_SERVING_WINDOW: Duration = Duration::from_secs(60 * 60);
+
 const KEEP_CONNECTION_ALIVE: bool = false;
 const _DISCONNECT_CONNECTION: bool = true;
 
 pub type PeerStandingNumber = i32;
 
+/// Whether the proof-of-work family claimed in a block notification exceeds
+/// that of the tip by more than [SPOT_CHECK_MARGIN_IN_BLOCKS] blocks at the
+/// tip's difficulty.
+fn requires_spot_check(
+    tip_header: &BlockHeader,
+    block_notification: &PeerBlockNotification,
+) -> bool {
+    let mut threshold = tip_header.proof_of_work_family;
+    for _ in 0..SPOT_CHECK_MARGIN_IN_BLOCKS {
+        threshold = threshold + tip_header.difficulty;
+    }
+
+    block_notification.proof_of_work_family > threshold
+}
+
+/// Remove duplicates and, on main net, addresses that are not globally
+/// routable from a list of peers reported by a peer. At most `max_length`
+/// peers are kept.
+fn sanitize_peer_list(
+    peers: Vec<(SocketAddr, u128)>,
+    max_length: usize,
+    network: Network,
+) -> Vec<(SocketAddr, u128)> {
+    let mut seen_addresses = HashSet::new();
+    peers
+        .into_iter()
+        .filter(|(address, _)| network != Network::Main || is_globally_routable(address.ip()))
+        .filter(|(address, _)| seen_addresses.insert(*address))
+        .take(max_length)
+        .collect()
+}
+
+/// Determine whether the IP address can be reached from the public internet,
+/// i.e. is not a loopback, private, link-local, or otherwise reserved address.
+fn is_globally_routable(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ipv4) => {
+            !(ipv4.is_loopback()
+                || ipv4.is_private()
+                || ipv4.is_link_local()
+                || ipv4.is_unspecified()
+                || ipv4.is_broadcast()
+                || ipv4.is_documentation())
+        }
+        IpAddr::V6(ipv6) => {
+            if let Some(ipv4) = ipv6.to_ipv4_mapped() {
+                return is_globally_routable(IpAddr::V4(ipv4));
+            }
+
+            let first_segment = ipv6.segments()[0];
+            let is_unique_local = first_segment & 0xfe00 == 0xfc00;
+            let is_unicast_link_local = first_segment & 0xffc0 == 0xfe80;
+            !(ipv6.is_loopback()
+                || ipv6.is_unspecified()
+                || is_unique_local
+                || is_unicast_link_local)
+        }
+    }
+}
+
+/// Rebuild a block from its header, its coinbase transaction, and the mempool
+/// transactions merged into it, in the order in which they were merged.
+/// Returns `None` if the transactions cannot be merged, or if the result is not
+/// a child of `parent_block`. Whether the rebuilt block is the announced one
+/// is left to [TransferBlock::into_block_with_digest].
+fn rebuild_compact_block(
+    parent_block: &Block,
+    header: BlockHeader,
+    coinbase: Transaction,
+    transactions: Vec<Transaction>,
+) -> Option<TransferBlock> {
+    if header.prev_block_digest != parent_block.hash()
+        || coinbase.kernel.mutator_set_hash
+            != parent_block.kernel.body.mutator_set_accumulator.hash()
+    {
+        return None;
+    }
+
+    let mut merged_transaction = coinbase;
+    for transaction in transactions {
+        if !merged_transaction.can_merge_with(&transaction) {
+            return None;
+        }
+        merged_transaction = merged_transaction.merge_with(transaction);
+    }
+
+    let body = make_block_body(parent_block, merged_transaction).ok()?;
+    Some(TransferBlock {
+        header,
+        body,
+        proof_type: ProofType::Unimplemented,
+    })
+}
+
 /// Contains the immutable data that this peer-loop needs. Does not contain the `peer` variable
 /// since this needs to be a mutable variable in most methods.
 pub struct PeerLoopHandler {
@@ -44,6 +189,7 @@ pub struct PeerLoopHandler {
     peer_handshake_data: HandshakeData,
     inbound_connection: bool,
     distance: u8,
+    byte_counts: PeerByteCounts,
 }
 
 impl PeerLoopHandler {
@@ -62,7 +208,27 @@ impl PeerLoopHandler {
             peer_handshake_data,
             inbound_connection,
             distance,
+            byte_counts: PeerByteCounts::default(),
+        }
+    }
+
+    /// Report the bytes counted by the connection's codec in the traffic
+    /// statistics.
+    pub fn with_byte_counts(mut self, byte_counts: PeerByteCounts) -> Self {
+        self.byte_counts = byte_counts;
+        self
+    }
+
+    /// The address on which this node would dial the peer: the connected address
+    /// for outbound connections, and the peer's listen address otherwise.
+    fn dial_address(&self) -> Option<SocketAddr> {
+        if !self.inbound_connection {
+            return Some(self.peer_address);
         }
+
+        self.peer_handshake_data
+            .listen_port
+            .map(|port| SocketAddr::new(self.peer_address.ip(), port))
     }
 
     // TODO: Add a reward function that mutates the peer status
@@ -80,7 +246,10 @@ impl PeerLoopHandler {
             .net
             .peer_map
             .get_mut(&self.peer_address)
-            .map(|p| p.standing.sanction(reason))
+            .map(|p| {
+                p.statistics.sanction_count += 1;
+                p.standing.sanction(reason)
+            })
             .unwrap_or(0);
 
         if new_standing < -(global_state_mut.cli().peer_tolerance as PeerStandingNumber) {
@@ -91,6 +260,88 @@ impl PeerLoopHandler {
         Ok(())
     }
 
+    /// Check whether the peer may be served more blocks under this node's
+    /// block serving policy. If not, the request is counted as throttled, and
+    /// the peer is told when to retry if it understands that, or ignored
+    /// otherwise.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for write
+    async fn may_serve_blocks<S>(&self, peer: &mut S) -> Result<bool>
+    where
+        S: Sink<PeerMessage> + Unpin,
+        <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
+    {
+        let policy = self.global_state_lock.cli().block_serving_policy();
+        let retry_after = self
+            .global_state_lock
+            .lock_mut(|s| {
+                let retry_after = s.net.block_serving_retry_after(
+                    self.peer_address.ip(),
+                    &policy,
+                    time::Instant::now(),
+                )?;
+                if let Some(peer_info) = s.net.peer_map.get_mut(&self.peer_address) {
+                    peer_info.statistics.block_requests_throttled += 1;
+                }
+                Some(retry_after)
+            })
+            .await;
+        let Some(retry_after) = retry_after else {
+            return Ok(true);
+        };
+
+        if self
+            .peer_handshake_data
+            .features
+            .contains(PeerFeatures::BLOCK_SERVING_QUOTA)
+        {
+            // Round up, such that the peer does not retry too early
+            let retry_after_in_secs =
+                retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            debug!(
+                "Block serving quota of {} used up, asking it to retry after {retry_after_in_secs} seconds",
+                self.peer_address
+            );
+            peer.send(PeerMessage::BlockRequestThrottled {
+                retry_after_in_secs,
+                policy,
+            })
+            .await?;
+        } else {
+            debug!(
+                "Block serving quota of {} used up, ignoring its block request",
+                self.peer_address
+            );
+        }
+
+        Ok(false)
+    }
+
+    /// Record blocks of the given sizes as served to the peer, against its
+    /// quota and in its statistics.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for write
+    async fn record_served_blocks(&self, block_sizes: &[usize]) {
+        let policy = self.global_state_lock.cli().block_serving_policy();
+        let bytes: u64 = block_sizes.iter().map(|size| *size as u64).sum();
+        self.global_state_lock
+            .lock_mut(|s| {
+                s.net.record_served_blocks(
+                    self.peer_address.ip(),
+                    block_sizes,
+                    &policy,
+                    time::Instant::now(),
+                );
+                if let Some(peer_info) = s.net.peer_map.get_mut(&self.peer_address) {
+                    peer_info.statistics.blocks_served += block_sizes.len() as u64;
+                    peer_info.statistics.block_bytes_served += bytes;
+                }
+            })
+            .await;
+    }
+
     /// Handle validation and send all blocks to the main thread if they're all
     /// valid. Use with a list of blocks or a single block. When the
     /// `received_blocks` is a list, the parent of the `i+1`th block in the
@@ -98,6 +349,7 @@ impl PeerLoopHandler {
     /// `parent_of_first_block`.
     ///
     /// Locking:
+    ///   * acquires `global_state_lock` for read
     ///   * acquires `global_state_lock` for write via Self::punish()
     async fn handle_blocks(
         &self,
@@ -114,6 +366,33 @@ impl PeerLoopHandler {
             }
         );
         let now = Timestamp::now();
+        let network = self.global_state_lock.cli().network;
+        let max_timestamp_drift = self.global_state_lock.cli().max_block_timestamp_drift();
+        let mut recent_timestamps = self
+            .global_state_lock
+            .lock_guard()
+            .await
+            .chain
+            .archival_state()
+            .recent_timestamps(&parent_of_first_block)
+            .await;
+
+        // The ancestors are only needed to check uncles, and reading them is
+        // not free
+        let references_uncles = received_blocks
+            .iter()
+            .any(|block| !block.kernel.body.uncle_blocks.is_empty());
+        let mut ancestors = if references_uncles {
+            self.global_state_lock
+                .lock_guard()
+                .await
+                .chain
+                .archival_state()
+                .recent_ancestors_with_uncles(&parent_of_first_block)
+                .await
+        } else {
+            vec![]
+        };
         let mut previous_block = &parent_of_first_block;
         for new_block in received_blocks.iter() {
             if !new_block.has_proof_of_work(previous_block) {
@@ -133,7 +412,7 @@ impl PeerLoopHandler {
                 )))
                 .await?;
                 bail!("Failed to validate block due to insufficient PoW");
-            } else if !new_block.is_valid(previous_block, now) {
+            } else if !new_block.is_valid(previous_block, network) {
                 warn!(
                     "Received invalid block of height {} from peer with IP {}",
                     new_block.kernel.header.height, self.peer_address
@@ -144,6 +423,27 @@ impl PeerLoopHandler {
                 )))
                 .await?;
                 bail!("Failed to validate block: invalid block");
+            } else if !new_block.has_valid_timestamp(&recent_timestamps, now, max_timestamp_drift) {
+                warn!(
+                    "Received block of height {} with invalid timestamp from peer with IP {}",
+                    new_block.kernel.header.height, self.peer_address
+                );
+                self.punish(PeerSanctionReason::InvalidBlockTimestamp(
+                    new_block.kernel.header.height,
+                ))
+                .await?;
+                bail!("Failed to validate block: invalid timestamp");
+            } else if !new_block.has_valid_uncles(&ancestors, now, network) {
+                warn!(
+                    "Received block of height {} with invalid uncles from peer with IP {}",
+                    new_block.kernel.header.height, self.peer_address
+                );
+                self.punish(PeerSanctionReason::InvalidBlock((
+                    new_block.kernel.header.height,
+                    new_block.hash(),
+                )))
+                .await?;
+                bail!("Failed to validate block: invalid uncles");
             } else {
                 info!(
                     "Block with height {} is valid. mined: {}",
@@ -152,6 +452,10 @@ impl PeerLoopHandler {
                 );
             }
 
+            ancestors.insert(0, RecentAncestor::from(new_block));
+            ancestors.truncate(MAX_UNCLE_GENERATIONS + 1);
+            recent_timestamps.insert(0, new_block.kernel.header.timestamp);
+            recent_timestamps.truncate(MEDIAN_TIMESTAMP_BLOCK_COUNT);
             previous_block = new_block;
         }
 
@@ -249,6 +553,8 @@ impl PeerLoopHandler {
 
             peer.send(PeerMessage::BlockRequestByHash(parent_digest))
                 .await?;
+            peer_state.block_requested = true;
+            peer_state.requested_block_digest = Some(parent_digest);
 
             return Ok(());
         }
@@ -299,11 +605,231 @@ impl PeerLoopHandler {
                 peer_state.highest_shared_block_height,
             ))
             .await?;
+            peer_state.block_requested = true;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a single block received from the peer, either sent in full or
+    /// rebuilt from a compact block.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    ///   * acquires `global_state_lock` for write via Self::receive_new_block()
+    async fn handle_received_block<S>(
+        &self,
+        block: Box<Block>,
+        peer: &mut S,
+        peer_state_info: &mut MutablePeerState,
+    ) -> Result<()>
+    where
+        S: Sink<PeerMessage> + TryStream<Ok = PeerMessage> + Unpin,
+        <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
+        <S as TryStream>::Error: std::error::Error,
+    {
+        let new_block_height = block.kernel.header.height;
+
+        // Update the value for the highest known height that peer possesses iff
+        // we are not in a fork reconciliation state.
+        if peer_state_info.fork_reconciliation_blocks.is_empty() {
+            peer_state_info.highest_shared_block_height = new_block_height;
+        }
+
+        let reconciliation_ongoing = match peer_state_info.fork_reconciliation_blocks.last() {
+            Some(last_block) => last_block.kernel.header.prev_block_digest == block.hash(),
+            None => false,
+        };
+
+        // Peers send blocks mined by this node back after they were broadcast. These
+        // are already known, unless the peer is reconciling a fork on top of them.
+        if !reconciliation_ongoing
+            && self
+                .global_state_lock
+                .lock_guard()
+                .await
+                .net
+                .is_recently_mined_block(block.hash())
+        {
+            debug!(
+                "Peer {} sent back block {} mined by this node; already known",
+                self.peer_address,
+                block.hash()
+            );
+            return Ok(());
         }
 
+        let incoming_block_is_heavier = self
+            .global_state_lock
+            .lock_guard()
+            .await
+            .chain
+            .light_state()
+            .kernel
+            .header
+            .proof_of_work_family
+            < block.kernel.header.proof_of_work_family;
+
+        // Determine whether
+        //  a) the incoming block's POW family is larger than what we have; or
+        //  b) we are populating a fork reconciliation blocks list.
+        if incoming_block_is_heavier || reconciliation_ongoing {
+            debug!("block is new");
+            self.receive_new_block(block, peer, peer_state_info).await?;
+        } else {
+            info!(
+                "Got non-canonical block from peer, height: {}, PoW family: {:?}",
+                new_block_height, block.kernel.header.proof_of_work_family,
+            );
+        }
         Ok(())
     }
 
+    /// Rebuild the block announced in a compact block from the transactions
+    /// received from the peer and those in the mempool, and handle it as if
+    /// the peer had sent the full block. Requests the full block instead if
+    /// the parent or a transaction is unknown, or if the rebuilt block does
+    /// not have a valid proof of work. Only blocks mined by the peer itself
+    /// are announced as compact blocks, so the peer is punished if its parent
+    /// is known but the block cannot be rebuilt.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    ///   * acquires `global_state_lock` for write via Self::punish()
+    ///   * acquires `global_state_lock` for write via Self::handle_received_block()
+    async fn reconstruct_compact_block<S>(
+        &self,
+        compact_block: PendingCompactBlock,
+        received_transactions: HashMap<Digest, Transaction>,
+        peer: &mut S,
+        peer_state_info: &mut MutablePeerState,
+    ) -> Result<()>
+    where
+        S: Sink<PeerMessage> + TryStream<Ok = PeerMessage> + Unpin,
+        <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
+        <S as TryStream>::Error: std::error::Error,
+    {
+        let block_height = compact_block.header.height;
+        let digest = compact_block.digest;
+        let (parent_is_known, reconstructed_block) = {
+            let global_state = self.global_state_lock.lock_guard().await;
+            let parent_block = global_state
+                .chain
+                .archival_state()
+                .get_block(compact_block.header.prev_block_digest)
+                .await?;
+            let transactions = compact_block
+                .txids
+                .iter()
+                .map(|txid| {
+                    received_transactions
+                        .get(txid)
+                        .or_else(|| global_state.mempool.get(*txid))
+                        .cloned()
+                })
+                .collect::<Option<Vec<_>>>();
+
+            let parent_is_known = parent_block.is_some();
+            let reconstructed_block = match (parent_block, transactions) {
+                (Some(parent_block), Some(transactions)) => rebuild_compact_block(
+                    &parent_block,
+                    compact_block.header,
+                    compact_block.coinbase,
+                    transactions,
+                ),
+                _ => None,
+            };
+            (parent_is_known, reconstructed_block)
+        };
+
+        match reconstructed_block.map(|block| block.into_block_with_digest(digest)) {
+            Some(Ok(block)) => {
+                debug!("Reconstructed compact block of height {block_height}");
+                self.handle_received_block(Box::new(block), peer, peer_state_info)
+                    .await
+            }
+            Some(Err(error)) => {
+                warn!(
+                    "Peer {} announced a compact block that does not match its digest: {error}",
+                    self.peer_address
+                );
+                self.punish(PeerSanctionReason::BlockDigestMismatch(block_height))
+                    .await
+            }
+            None => {
+                info!(
+                    "Could not reconstruct compact block of height {block_height}; requesting full block"
+                );
+                if parent_is_known {
+                    warn!(
+                        "Peer {} announced a compact block that it could not provide",
+                        self.peer_address
+                    );
+                    self.punish(PeerSanctionReason::InvalidCompactBlock(block_height))
+                        .await?;
+                }
+                peer.send(PeerMessage::BlockRequestByHash(digest)).await?;
+                peer_state_info.block_requested = true;
+                peer_state_info.requested_block_digest = Some(digest);
+                Ok(())
+            }
+        }
+    }
+
+    /// Handle a message received from the peer, unless it is to be ignored
+    /// in the current state of this node. Returns `Ok(true)` if the
+    /// connection should be closed, and an error if the peer must be
+    /// disconnected.
+    ///
+    /// Only communicates with the peer through `peer`, such that messages can
+    /// be fed to it without a connection, e.g. when fuzzing.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    ///   * acquires `global_state_lock` for write via Self::punish()
+    pub async fn handle_received_message<S>(
+        &self,
+        msg: PeerMessage,
+        peer: &mut S,
+        peer_state_info: &mut MutablePeerState,
+    ) -> Result<bool>
+    where
+        S: Sink<PeerMessage> + TryStream<Ok = PeerMessage> + Unpin,
+        <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
+        <S as TryStream>::Error: std::error::Error,
+    {
+        let (syncing, quiesced) = self
+            .global_state_lock
+            .lock(|s| (s.net.syncing, s.net.quiesced))
+            .await;
+        if msg.ignore_when_quiesced() && quiesced {
+            debug!(
+                "Ignoring {} message while quiesced, from {}",
+                msg.get_type(),
+                self.peer_address
+            );
+            return Ok(false);
+        }
+        if msg.ignore_during_sync() && syncing {
+            debug!(
+                "Ignoring {} message during syncing, from {}",
+                msg.get_type(),
+                self.peer_address
+            );
+            return Ok(false);
+        }
+        if msg.ignore_when_not_sync() && !syncing {
+            debug!(
+                "Ignoring {} message because we are not syncing, from {}",
+                msg.get_type(),
+                self.peer_address
+            );
+            return Ok(false);
+        }
+
+        self.handle_peer_message(msg, peer, peer_state_info).await
+    }
+
     /// Handle peer messages and returns Ok(true) if connection should be closed.
     /// Connection should also be closed if an error is returned.
     /// Otherwise returns OK(false).
@@ -332,12 +858,15 @@ impl PeerLoopHandler {
                 // Note that the current peer is not removed from the global_state.peer_map here
                 // but that this is done by the caller.
                 info!("Got bye. Closing connection to peer");
+                peer_state_info.received_bye = true;
                 Ok(true)
             }
             PeerMessage::PeerListRequest => {
                 // We are interested in the address on which peers accept ingoing connections,
                 // not in the address in which they are connected to us. We are only interested in
                 // peers that accept incoming connections.
+                let max_peer_list_length =
+                    self.global_state_lock.cli().max_peer_list_response_length;
                 let mut peer_info: Vec<(SocketAddr, u128)> = self
                     .global_state_lock
                     .lock_guard()
@@ -346,7 +875,8 @@ impl PeerLoopHandler {
                     .peer_map
                     .values()
                     .filter(|peer_info| peer_info.listen_address().is_some())
-                    .take(MAX_PEER_LIST_LENGTH) // limit length of response
+                    // Peers known by host name cannot be dialed by their stand-in address
+                    .filter(|peer_info| !socks5::is_stand_in_address(&peer_info.connected_address))
                     .map(|peer_info| {
                         (
                             // unwrap is safe bc of above `filter`
@@ -356,18 +886,24 @@ impl PeerLoopHandler {
                     })
                     .collect();
 
-                // We sort the returned list, so this function is easier to test
+                // We sort the returned list, so this function is easier to test.
+                // The length is limited by the same bound we apply to the peer
+                // lists we receive.
                 peer_info.sort_by_cached_key(|x| x.0);
+                peer_info.truncate(max_peer_list_length);
 
                 debug!("Responding with: {:?}", peer_info);
                 peer.send(PeerMessage::PeerListResponse(peer_info)).await?;
                 Ok(false)
             }
             PeerMessage::PeerListResponse(peers) => {
-                if peers.len() > MAX_PEER_LIST_LENGTH {
+                let cli = self.global_state_lock.cli();
+                if peers.len() > cli.max_peer_list_response_length {
                     self.punish(PeerSanctionReason::FloodPeerListResponse)
                         .await?;
                 }
+                let peers =
+                    sanitize_peer_list(peers, cli.max_peer_list_response_length, cli.network);
                 self.to_main_tx
                     .send(PeerThreadToMain::PeerDiscoveryAnswer((
                         peers,
@@ -385,17 +921,54 @@ impl PeerLoopHandler {
                     t_block.header.height,
                     t_block.header.timestamp.standard_format()
                 );
-                let new_block_height = t_block.header.height;
-
-                let block: Box<Block> = Box::new((*t_block).into());
+                peer_state_info.block_requested = false;
+                let block_height = t_block.header.height;
+                let block = match peer_state_info.requested_block_digest.take() {
+                    Some(requested_digest) => {
+                        match (*t_block).into_block_with_digest(requested_digest) {
+                            Ok(block) => block,
+                            Err(error) => {
+                                warn!(
+                                    "Peer {} sent a block other than requested: {error}",
+                                    self.peer_address
+                                );
+                                self.punish(PeerSanctionReason::BlockDigestMismatch(block_height))
+                                    .await?;
+                                return Ok(false);
+                            }
+                        }
+                    }
+                    None => (*t_block).into(),
+                };
+                self.handle_received_block(Box::new(block), peer, peer_state_info)
+                    .await?;
+                Ok(false)
+            }
+            PeerMessage::CompactBlock {
+                digest,
+                header,
+                txids,
+                coinbase,
+            } => {
+                info!(
+                    "Got compact block from peer {}, height {}, with {} mempool transactions",
+                    self.peer_address,
+                    header.height,
+                    txids.len()
+                );
 
-                // Update the value for the highest known height that peer possesses iff
-                // we are not in a fork reconciliation state.
-                if peer_state_info.fork_reconciliation_blocks.is_empty() {
-                    peer_state_info.highest_shared_block_height = new_block_height;
+                // Like a block notification, a compact block that arrives during
+                // fork reconciliation is only requested once reconciliation is done.
+                if !peer_state_info.fork_reconciliation_blocks.is_empty() {
+                    peer_state_info.highest_shared_block_height = header.height;
+                    debug!(
+                        "ignoring compact block of height {} while reconciling fork",
+                        header.height
+                    );
+                    return Ok(false);
                 }
 
-                let incoming_block_is_heavier = self
+                let block_is_new = self
                     .global_state_lock
                     .lock_guard()
                     .await
@@ -404,31 +977,122 @@ impl PeerLoopHandler {
                     .kernel
                     .header
                     .proof_of_work_family
-                    < block.kernel.header.proof_of_work_family;
-                let reconciliation_ongoing = match peer_state_info.fork_reconciliation_blocks.last()
-                {
-                    Some(last_block) => last_block.kernel.header.prev_block_digest == block.hash(),
-                    None => false,
+                    < header.proof_of_work_family;
+                if !block_is_new {
+                    info!(
+                        "Got non-canonical compact block from peer, height: {}, PoW family: {:?}",
+                        header.height, header.proof_of_work_family,
+                    );
+                    return Ok(false);
+                }
+
+                let compact_block = PendingCompactBlock {
+                    digest,
+                    header,
+                    txids,
+                    coinbase: *coinbase,
+                };
+                let missing_txids = {
+                    let global_state = self.global_state_lock.lock_guard().await;
+                    compact_block
+                        .txids
+                        .iter()
+                        .filter(|txid| !global_state.mempool.contains(**txid))
+                        .copied()
+                        .collect_vec()
                 };
 
-                // Determine whether
-                //  a) the incoming block's POW family is larger than what we have; or
-                //  b) we are populating a fork reconciliation blocks list.
-                if incoming_block_is_heavier || reconciliation_ongoing {
-                    debug!("block is new");
-                    self.receive_new_block(block, peer, peer_state_info).await?;
+                if missing_txids.len() > MAX_BLOCK_TRANSACTIONS_REQUEST_LENGTH {
+                    debug!(
+                        "Compact block is missing {} transactions; requesting full block",
+                        missing_txids.len()
+                    );
+                    peer.send(PeerMessage::BlockRequestByHash(compact_block.digest))
+                        .await?;
+                    peer_state_info.block_requested = true;
+                    peer_state_info.requested_block_digest = Some(compact_block.digest);
+                } else if missing_txids.is_empty() {
+                    self.reconstruct_compact_block(
+                        compact_block,
+                        HashMap::new(),
+                        peer,
+                        peer_state_info,
+                    )
+                    .await?;
                 } else {
-                    info!(
-                        "Got non-canonical block from peer, height: {}, PoW family: {:?}",
-                        new_block_height, block.kernel.header.proof_of_work_family,
+                    debug!(
+                        "Requesting {} transactions of compact block that are not in mempool",
+                        missing_txids.len()
+                    );
+                    peer_state_info.pending_compact_block = Some(compact_block);
+                    peer.send(PeerMessage::GetBlockTransactions(missing_txids))
+                        .await?;
+                }
+                Ok(false)
+            }
+            PeerMessage::GetBlockTransactions(txids) => {
+                if txids.len() > MAX_BLOCK_TRANSACTIONS_REQUEST_LENGTH {
+                    warn!(
+                        "Peer {} requested {} block transactions, more than the maximum of {}",
+                        self.peer_address,
+                        txids.len(),
+                        MAX_BLOCK_TRANSACTIONS_REQUEST_LENGTH
                     );
+                    self.punish(PeerSanctionReason::TooLongBlockTransactionsRequest)
+                        .await?;
+                    return Ok(KEEP_CONNECTION_ALIVE);
                 }
+
+                // The transactions of a block mined by this node are no longer in
+                // the mempool once the block is the tip.
+                let transactions = {
+                    let global_state = self.global_state_lock.lock_guard().await;
+                    txids
+                        .into_iter()
+                        .filter_map(|txid| {
+                            global_state
+                                .net
+                                .mined_block_transaction(txid)
+                                .or_else(|| global_state.mempool.get(txid))
+                                .cloned()
+                        })
+                        .collect_vec()
+                };
+                peer.send(PeerMessage::BlockTransactions(transactions))
+                    .await?;
+                Ok(false)
+            }
+            PeerMessage::BlockTransactions(transactions) => {
+                let Some(compact_block) = peer_state_info.pending_compact_block.take() else {
+                    warn!(
+                        "Peer {} sent block transactions that were not requested",
+                        self.peer_address
+                    );
+                    self.punish(PeerSanctionReason::UnrequestedMessage).await?;
+                    return Ok(false);
+                };
+
+                let received_transactions = transactions
+                    .into_iter()
+                    .map(|transaction| (Hash::hash(&transaction), transaction))
+                    .collect();
+                self.reconstruct_compact_block(
+                    compact_block,
+                    received_transactions,
+                    peer,
+                    peer_state_info,
+                )
+                .await?;
                 Ok(false)
             }
             PeerMessage::BlockRequestBatch(
                 peers_suggested_starting_points,
                 requested_batch_size,
             ) => {
+                if !self.may_serve_blocks(peer).await? {
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
+
                 // Find the block that the peer is requesting to start from
                 let mut peers_latest_canonical_block: Option<Block> = None;
 
@@ -496,6 +1160,12 @@ impl PeerLoopHandler {
                 let tip_digest = global_state.chain.light_state().hash();
 
                 let responded_batch_size = cmp::max(responded_batch_size, MINIMUM_BLOCK_BATCH_SIZE);
+                let max_response_size = self
+                    .global_state_lock
+                    .cli()
+                    .max_block_batch_response_size
+                    .as_u64() as usize;
+                let mut response_size = 0;
                 let mut returned_blocks: Vec<TransferBlock> =
                     Vec::with_capacity(responded_batch_size);
 
@@ -528,46 +1198,256 @@ impl PeerLoopHandler {
                         canonical
                     };
 
-                    // get block and append to list
-                    let canonical_child: Block = global_state
+                    // get block and append to list, unless it exceeds the size budget
+                    let Some(canonical_child) = global_state
                         .chain
                         .archival_state()
                         .get_block(canonical_child_digest)
                         .await?
-                        .unwrap();
-                    returned_blocks.push(canonical_child.into());
+                    else {
+                        error!(
+                            "Canonical block {canonical_child_digest} is missing from the database"
+                        );
+                        break;
+                    };
+                    if canonical_child.has_stripped_transaction_witness() {
+                        debug!("Ending batch response at block with pruned transaction witness");
+                        break;
+                    }
+                    let transfer_block: TransferBlock = canonical_child.into();
+                    response_size += bincode::serialized_size(&transfer_block)? as usize;
+                    if returned_blocks.len() >= MINIMUM_BLOCK_BATCH_SIZE
+                        && response_size > max_response_size
+                    {
+                        break;
+                    }
+                    returned_blocks.push(transfer_block);
 
                     // prepare for next iteration
                     current_digest = canonical_child_digest;
                 }
 
+                drop(global_state);
+
                 debug!(
                     "Returning {} blocks in batch response",
                     returned_blocks.len()
                 );
 
+                let block_sizes = returned_blocks.iter().map(|b| b.get_size()).collect_vec();
                 let response = PeerMessage::BlockResponseBatch(returned_blocks);
                 peer.send(response).await?;
+                self.record_served_blocks(&block_sizes).await;
 
                 Ok(false)
             }
-            PeerMessage::BlockResponseBatch(t_blocks) => {
+            PeerMessage::BlockRequestBatchByHeight {
+                start_height,
+                count,
+            } => {
                 debug!(
-                    "handling block response batch with {} blocks",
-                    t_blocks.len()
+                    "Got BlockRequestBatchByHeight of {} blocks from height {}",
+                    count, start_height
                 );
-                if t_blocks.len() < MINIMUM_BLOCK_BATCH_SIZE {
-                    warn!("Got smaller batch response than allowed");
-                    self.punish(PeerSanctionReason::TooShortBlockBatch).await?;
-                    return Ok(false);
+                if !self.may_serve_blocks(peer).await? {
+                    return Ok(KEEP_CONNECTION_ALIVE);
                 }
 
-                // Verify that we are in fact in syncing mode
-                // TODO: Seperate peer messages into those allowed under syncing
-                // and those that are not
-                if !self.global_state_lock.lock_guard().await.net.syncing {
-                    warn!("Received a batch of blocks without being in syncing mode");
-                    self.punish(PeerSanctionReason::ReceivedBatchBlocksOutsideOfSync)
+                // Get the relevant blocks, at most the configured maximum many,
+                // from the canonical chain.
+                let responded_batch_size = cmp::min(
+                    count,
+                    self.global_state_lock.cli().max_block_batch_response_length,
+                ) as usize;
+                let global_state = self.global_state_lock.lock_guard().await;
+                let tip_digest = global_state.chain.light_state().hash();
+                let tip_height = global_state.chain.light_state().kernel.header.height;
+
+                if start_height > tip_height {
+                    drop(global_state);
+                    warn!("Got block batch request by height for unknown block");
+                    self.punish(PeerSanctionReason::BlockRequestUnknownHeight)
+                        .await?;
+                    return Ok(false);
+                }
+
+                let max_response_size = self
+                    .global_state_lock
+                    .cli()
+                    .max_block_batch_response_size
+                    .as_u64() as usize;
+                let mut response_size = 0;
+                let mut returned_blocks: Vec<TransferBlock> =
+                    Vec::with_capacity(responded_batch_size);
+                let mut height = start_height;
+                while returned_blocks.len() < responded_batch_size && height <= tip_height {
+                    let Some(canonical_digest) = global_state
+                        .chain
+                        .archival_state()
+                        .block_height_to_canonical_block_digest(height, tip_digest)
+                        .await
+                    else {
+                        break;
+                    };
+                    let Some(canonical_block) = global_state
+                        .chain
+                        .archival_state()
+                        .get_block(canonical_digest)
+                        .await?
+                    else {
+                        error!("Canonical block of height {height} is missing from the database");
+                        break;
+                    };
+                    if canonical_block.has_stripped_transaction_witness() {
+                        debug!("Ending batch response at block with pruned transaction witness");
+                        break;
+                    }
+                    let transfer_block: TransferBlock = canonical_block.into();
+                    response_size += bincode::serialized_size(&transfer_block)? as usize;
+                    if returned_blocks.len() >= MINIMUM_BLOCK_BATCH_SIZE
+                        && response_size > max_response_size
+                    {
+                        break;
+                    }
+                    returned_blocks.push(transfer_block);
+                    height = height.next();
+                }
+                drop(global_state);
+
+                debug!(
+                    "Returning {} blocks in batch response",
+                    returned_blocks.len()
+                );
+
+                let block_sizes = returned_blocks.iter().map(|b| b.get_size()).collect_vec();
+                peer.send(PeerMessage::BlockResponseBatch(returned_blocks))
+                    .await?;
+                self.record_served_blocks(&block_sizes).await;
+
+                Ok(false)
+            }
+            PeerMessage::BlockResponseBatch(t_blocks) => {
+                debug!(
+                    "handling block response batch with {} blocks",
+                    t_blocks.len()
+                );
+                if t_blocks.len() < MINIMUM_BLOCK_BATCH_SIZE {
+                    warn!("Got smaller batch response than allowed");
+                    self.punish(PeerSanctionReason::TooShortBlockBatch).await?;
+                    return Ok(false);
+                }
+                if t_blocks.len() > STANDARD_BLOCK_BATCH_SIZE {
+                    warn!("Got larger batch response than requested");
+                    self.punish(PeerSanctionReason::TooLongBlockBatch).await?;
+                    return Ok(false);
+                }
+
+                // Responses arrive in the order of the requests
+                let pending_request = peer_state_info.pending_block_batches.pop_front();
+                if pending_request
+                    .as_ref()
+                    .is_some_and(|request| request.superseded)
+                {
+                    debug!("Discarding superseded block batch response");
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
+
+                // Verify that we are in fact in syncing mode
+                // TODO: Seperate peer messages into those allowed under syncing
+                // and those that are not
+                if !self.global_state_lock.lock_guard().await.net.syncing {
+                    // Batches requested while syncing may arrive after it ended
+                    if pending_request.is_some() {
+                        debug!("Discarding block batch response received after syncing ended");
+                        Self::close_block_batch_window(peer_state_info);
+                        return Ok(KEEP_CONNECTION_ALIVE);
+                    }
+                    warn!("Received a batch of blocks without being in syncing mode");
+                    self.punish(PeerSanctionReason::ReceivedBatchBlocksOutsideOfSync)
+                        .await?;
+                    return Ok(false);
+                }
+
+                // A response to a request by height must build on the tip of the
+                // download window. Otherwise, the peer's canonical chain forked
+                // off further down, and the fork point is found by requesting the
+                // blocks by digest.
+                let first_blocks_parent_digest: Digest = t_blocks[0].header.prev_block_digest;
+                let requested_by_height = pending_request
+                    .as_ref()
+                    .is_some_and(|request| request.start_height.is_some());
+                if let Some(window) = peer_state_info
+                    .block_batch_window
+                    .as_ref()
+                    .filter(|_| requested_by_height)
+                {
+                    let (tip_digest, _tip_height) = window.tip;
+                    if first_blocks_parent_digest != tip_digest {
+                        debug!(
+                            "Batch by height does not descend from {}; requesting by digest",
+                            tip_digest
+                        );
+                        let most_canonical_digests =
+                            if window.most_canonical_digests.first() == Some(&tip_digest) {
+                                window.most_canonical_digests.clone()
+                            } else {
+                                [vec![tip_digest], window.most_canonical_digests.clone()].concat()
+                            };
+                        let count = self.block_batch_request_size();
+                        Self::supersede_pending_block_batches(peer_state_info);
+                        peer.send(PeerMessage::BlockRequestBatch(
+                            most_canonical_digests,
+                            count,
+                        ))
+                        .await?;
+                        peer_state_info
+                            .pending_block_batches
+                            .push_back(PendingBlockBatch {
+                                start_height: None,
+                                count,
+                                superseded: false,
+                            });
+                        return Ok(false);
+                    }
+                }
+
+                // Convert all blocks to Block objects
+                let now = time::Instant::now();
+                for t_block in t_blocks.iter() {
+                    peer_state_info
+                        .received_blocks
+                        .record(now, t_block.get_size() as u64);
+                }
+                let received_blocks: Vec<Block> = t_blocks.into_iter().map(|x| x.into()).collect();
+
+                // Verify that each block builds on the one before it, such that
+                // the batch is a single chain
+                if !received_blocks
+                    .iter()
+                    .tuple_windows()
+                    .all(|(previous, next)| next.kernel.header.prev_block_digest == previous.hash())
+                {
+                    warn!("Got batch response with blocks that do not form a chain");
+                    self.punish(PeerSanctionReason::BatchBlocksNotConnected)
+                        .await?;
+                    return Ok(false);
+                }
+
+                // When syncing headers first, only the blocks on the best branch
+                // of the validated header chain are accepted
+                let best_header_chain: HashSet<Digest> = self
+                    .global_state_lock
+                    .lock(|s| s.net.header_chain.best_chain())
+                    .await
+                    .into_iter()
+                    .collect();
+                if !best_header_chain.is_empty()
+                    && received_blocks
+                        .iter()
+                        .any(|block| !best_header_chain.contains(&block.hash()))
+                {
+                    warn!("Got batch response with blocks outside of the best header chain");
+                    self.punish(PeerSanctionReason::BatchBlocksNotInHeaderChain)
                         .await?;
                     return Ok(false);
                 }
@@ -575,7 +1455,16 @@ impl PeerLoopHandler {
                 // Verify that the response matches the current state
                 // We get the latest block from the DB here since this message is
                 // only valid for archival nodes.
-                let first_blocks_parent_digest: Digest = t_blocks[0].header.prev_block_digest;
+                let received_blocks_count = received_blocks.len();
+                let last_received_block = &received_blocks[received_blocks_count - 1];
+                let received_tip = (
+                    last_received_block.hash(),
+                    last_received_block.kernel.header.height,
+                );
+                let builds_on_window = peer_state_info
+                    .block_batch_window
+                    .as_ref()
+                    .is_some_and(|window| window.tip.0 == first_blocks_parent_digest);
                 let most_canonical_own_block_match: Option<Block> = self
                     .global_state_lock
                     .lock_guard()
@@ -583,28 +1472,48 @@ impl PeerLoopHandler {
                     .chain
                     .archival_state()
                     .get_block(first_blocks_parent_digest)
-                    .await
-                    .expect("Block lookup must succeed");
-                let most_canonical_own_block_match: Block = match most_canonical_own_block_match {
-                    Some(block) => block,
+                    .await?;
+                match most_canonical_own_block_match {
+                    Some(parent_block) => {
+                        debug!(
+                            "Found own block of height {} to match received batch",
+                            parent_block.kernel.header.height
+                        );
+                        self.handle_blocks(received_blocks, parent_block).await?;
+                    }
+                    // The main loop may not have stored the batch before it yet
+                    None if builds_on_window
+                        && peer_state_info.block_batches_awaiting_parent.len()
+                            < BLOCK_BATCH_WINDOW_SIZE =>
+                    {
+                        debug!(
+                            "Holding back block batch until its parent {} is stored",
+                            first_blocks_parent_digest
+                        );
+                        peer_state_info
+                            .block_batches_awaiting_parent
+                            .push_back(received_blocks);
+                    }
                     None => {
                         warn!("Got batch reponse with invalid start height");
                         self.punish(PeerSanctionReason::BatchBlocksInvalidStartHeight)
                             .await?;
                         return Ok(false);
                     }
-                };
+                }
 
-                // Convert all blocks to Block objects
-                debug!(
-                    "Found own block of height {} to match received batch",
-                    most_canonical_own_block_match.kernel.header.height
-                );
-                let received_blocks: Vec<Block> = t_blocks.into_iter().map(|x| x.into()).collect();
+                // Slide the download window past the received blocks
+                if let Some(pending_request) = pending_request {
+                    if let Some(window) = peer_state_info.block_batch_window.as_mut() {
+                        window.tip = received_tip;
+                    }
 
-                // Get the latest block that we know of and handle all received blocks
-                self.handle_blocks(received_blocks, most_canonical_own_block_match)
-                    .await?;
+                    // A short batch leaves a gap before the requests in flight
+                    if received_blocks_count < pending_request.count {
+                        Self::supersede_pending_block_batches(peer_state_info);
+                    }
+                    self.fill_block_batch_window(peer, peer_state_info).await?;
+                }
 
                 Ok(false)
             }
@@ -632,81 +1541,123 @@ impl PeerLoopHandler {
                     block_notification.height
                 );
                 peer_state_info.highest_shared_block_height = block_notification.height;
-                {
-                    let block_is_new = self
-                        .global_state_lock
-                        .lock_guard()
-                        .await
-                        .chain
-                        .light_state()
-                        .kernel
-                        .header
-                        .proof_of_work_family
-                        < block_notification.proof_of_work_family;
-
-                    debug!("block_is_new: {}", block_is_new);
+                self.global_state_lock
+                    .lock_mut(|s| {
+                        s.net.chain_split_detector.record_claim(
+                            self.peer_address,
+                            block_notification.hash,
+                            block_notification.height,
+                            SystemTime::now(),
+                        )
+                    })
+                    .await;
 
-                    // Only request block if it is new, and if we are not currently reconciling
-                    // a fork. If we are reconciling, that is handled later, and the information
-                    // about that is stored in `highest_shared_block_height`. If we are syncing
-                    // we are also not requesting the block but instead updating the sync state.
-                    if self.global_state_lock.lock_guard().await.net.syncing {
-                        debug!(
-                            "ignoring peer block with height {} because we are presently syncing",
-                            block_notification.height
-                        );
+                // Claims of much more proof-of-work than the tip keep this node
+                // in sync mode, so they are spot checked before they are trusted
+                let tip_header = self
+                    .global_state_lock
+                    .lock_guard()
+                    .await
+                    .chain
+                    .light_state()
+                    .kernel
+                    .header
+                    .clone();
+                if requires_spot_check(&tip_header, &block_notification) {
+                    if block_notification.height.is_genesis() {
+                        warn!("Got block notification claiming proof-of-work for genesis");
+                        self.punish(PeerSanctionReason::FalseProofOfWorkClaim(
+                            block_notification.height,
+                        ))
+                        .await?;
+                        return Ok(KEEP_CONNECTION_ALIVE);
+                    }
 
-                        self.to_main_tx
-                            .send(PeerThreadToMain::AddPeerMaxBlockHeight((
-                                self.peer_address,
-                                block_notification.height,
-                                block_notification.proof_of_work_family,
-                            )))
-                            .await
-                            .expect("Sending to main thread must succeed");
-                    } else if block_is_new && peer_state_info.fork_reconciliation_blocks.is_empty()
+                    if peer_state_info
+                        .pending_header_requests
+                        .iter()
+                        .any(|request| matches!(request, PendingHeaderRequest::SpotCheck(_)))
                     {
                         debug!(
-                            "sending BlockRequestByHeight to peer for block with height {}",
-                            block_notification.height
-                        );
-                        peer.send(PeerMessage::BlockRequestByHeight(block_notification.height))
-                            .await?;
-                    } else {
-                        debug!(
-                            "ignoring peer block. height {}. new: {}, reconciling_fork: {}",
-                            block_notification.height,
-                            block_is_new,
-                            !peer_state_info.fork_reconciliation_blocks.is_empty()
+                            "Already spot checking a claim of {}, ignoring block notification",
+                            self.peer_address
                         );
+                        return Ok(KEEP_CONNECTION_ALIVE);
                     }
+
+                    // Request the headers from a block this node knows up to
+                    // the notified block, such that the claimed proof-of-work
+                    // must build on this node's chain. Start below the tip, in
+                    // case the peer's chain forked off recently.
+                    let anchor_height = cmp::min(
+                        BlockHeight::from(
+                            u64::from(tip_header.height)
+                                .saturating_sub(SPOT_CHECK_MARGIN_IN_BLOCKS as u64),
+                        ),
+                        block_notification.height.previous(),
+                    );
+                    let end_height = cmp::min(
+                        block_notification.height.next(),
+                        anchor_height + MAX_BLOCK_HEADERS_RESPONSE_LENGTH,
+                    );
+                    debug!(
+                        "Spot checking the proof-of-work claimed for block with height {}",
+                        block_notification.height
+                    );
+                    peer.send(PeerMessage::BlockHeaderRequest(anchor_height..end_height))
+                        .await?;
+                    peer_state_info
+                        .pending_header_requests
+                        .push_back(PendingHeaderRequest::SpotCheck(block_notification));
+                    return Ok(KEEP_CONNECTION_ALIVE);
                 }
 
+                self.handle_block_notification(block_notification, peer, peer_state_info)
+                    .await?;
+
                 Ok(false)
             }
             PeerMessage::BlockRequestByHash(block_digest) => {
-                match self
+                if !self.may_serve_blocks(peer).await? {
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
+
+                let block = self
                     .global_state_lock
                     .lock_guard()
                     .await
                     .chain
                     .archival_state()
                     .get_block(block_digest)
-                    .await?
-                {
+                    .await?;
+                match block {
                     None => {
                         // TODO: Consider punishing here
                         warn!("Peer requested unkown block with hash {}", block_digest);
                         Ok(false)
                     }
+                    Some(b) if b.has_stripped_transaction_witness() => {
+                        info!(
+                            "Not serving block {} whose transaction witness was pruned",
+                            block_digest
+                        );
+                        Ok(false)
+                    }
                     Some(b) => {
-                        peer.send(PeerMessage::Block(Box::new(b.into()))).await?;
+                        let transfer_block: TransferBlock = b.into();
+                        let block_size = transfer_block.get_size();
+                        peer.send(PeerMessage::Block(Box::new(transfer_block)))
+                            .await?;
+                        self.record_served_blocks(&[block_size]).await;
                         Ok(false)
                     }
                 }
             }
             PeerMessage::BlockRequestByHeight(block_height) => {
                 debug!("Got BlockRequestByHeight of height {}", block_height);
+                if !self.may_serve_blocks(peer).await? {
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
 
                 let block_digests = self
                     .global_state_lock
@@ -742,7 +1693,7 @@ impl PeerLoopHandler {
                     }
                 }
 
-                let canonical_chain_block: Block = self
+                let Some(canonical_chain_block) = self
                     .global_state_lock
                     .lock_guard()
                     .await
@@ -750,13 +1701,25 @@ impl PeerLoopHandler {
                     .archival_state()
                     .get_block(canonical_chain_block_digest)
                     .await?
-                    .unwrap();
-                let block_response: PeerMessage =
-                    PeerMessage::Block(Box::new(canonical_chain_block.into()));
+                else {
+                    error!("Block {canonical_chain_block_digest} is missing from the database");
+                    return Ok(false);
+                };
+                if canonical_chain_block.has_stripped_transaction_witness() {
+                    info!(
+                        "Not serving block of height {} whose transaction witness was pruned",
+                        block_height
+                    );
+                    return Ok(false);
+                }
+                let transfer_block: TransferBlock = canonical_chain_block.into();
+                let block_size = transfer_block.get_size();
+                let block_response: PeerMessage = PeerMessage::Block(Box::new(transfer_block));
 
                 debug!("Sending block");
                 peer.send(block_response).await?;
                 debug!("Sent block");
+                self.record_served_blocks(&[block_size]).await;
                 Ok(false)
             }
             PeerMessage::Handshake(_) => {
@@ -775,6 +1738,21 @@ impl PeerLoopHandler {
                     transaction.kernel.mutator_set_hash
                 );
 
+                // Ignore transactions that are in the mempool already, e.g. because
+                // another peer sent them first
+                let transaction_id = Hash::hash(transaction.as_ref());
+                let transaction_is_known = self
+                    .global_state_lock
+                    .lock_mut(|s| {
+                        s.net.record_received_transaction(transaction_id);
+                        s.mempool.contains(transaction_id)
+                    })
+                    .await;
+                if transaction_is_known {
+                    debug!("Received tx that is already in mempool");
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
+
                 // If transaction is invalid, punish
                 if !transaction.is_valid() {
                     warn!("Received invalid tx");
@@ -792,19 +1770,34 @@ impl PeerLoopHandler {
                     return Ok(KEEP_CONNECTION_ALIVE);
                 }
 
-                // if transaction is not confirmable, punish
-                let confirmable = transaction.is_confirmable_relative_to(
-                    &self
-                        .global_state_lock
-                        .lock_guard()
-                        .await
-                        .chain
-                        .light_state()
-                        .kernel
-                        .body
-                        .mutator_set_accumulator,
-                );
-                if !confirmable {
+                // If transaction has a negative fee, punish. No block can include it, so
+                // the mempool would reject it.
+                if transaction.kernel.fee.is_negative() {
+                    warn!("Received tx with negative fee");
+                    self.punish(PeerSanctionReason::NegativeFeeTransaction)
+                        .await?;
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
+
+                // if transaction is not confirmable, punish. Unless it was built against a
+                // mutator set that we do not know yet because the peer received a block
+                // before we did, in which case it is buffered as an orphan. Only a peer whose
+                // tip is ahead of ours can know such a block.
+                let (confirmable, is_orphan) = {
+                    let global_state = self.global_state_lock.lock_guard().await;
+                    let tip_kernel = &global_state.chain.light_state().kernel;
+                    let tip_mutator_set_accumulator = &tip_kernel.body.mutator_set_accumulator;
+                    let confirmable =
+                        transaction.is_confirmable_relative_to(tip_mutator_set_accumulator);
+                    let peer_is_ahead =
+                        peer_state_info.highest_shared_block_height > tip_kernel.header.height;
+                    let is_orphan = !confirmable
+                        && peer_is_ahead
+                        && transaction.kernel.mutator_set_hash
+                            != tip_mutator_set_accumulator.hash();
+                    (confirmable, is_orphan)
+                };
+                if !confirmable && !is_orphan {
                     warn!("Received unconfirmable tx");
                     self.punish(PeerSanctionReason::UnconfirmableTransaction)
                         .await?;
@@ -816,7 +1809,13 @@ impl PeerLoopHandler {
 
                 // 2. Ignore if transaction is too old
                 let now = Timestamp::now();
-                if tx_timestamp < now - Timestamp::seconds(MEMPOOL_TX_THRESHOLD_AGE_IN_SECS) {
+                let transaction_ttl = self
+                    .global_state_lock
+                    .lock_guard()
+                    .await
+                    .mempool
+                    .transaction_ttl();
+                if tx_timestamp <= now - transaction_ttl {
                     // TODO: Consider punishing here
                     warn!("Received too old tx");
                     return Ok(KEEP_CONNECTION_ALIVE);
@@ -831,6 +1830,14 @@ impl PeerLoopHandler {
                     return Ok(KEEP_CONNECTION_ALIVE);
                 }
 
+                if is_orphan {
+                    debug!("Received tx built against unknown mutator set; relaying it as orphan");
+                    self.to_main_tx
+                        .send(PeerThreadToMain::OrphanTransaction(transaction))
+                        .await?;
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
+
                 // Otherwise relay to main
                 let pt2m_transaction = PeerThreadToMainTransaction {
                     transaction: *transaction.to_owned(),
@@ -849,25 +1856,21 @@ impl PeerLoopHandler {
                 Ok(KEEP_CONNECTION_ALIVE)
             }
             PeerMessage::TransactionNotification(transaction_notification) => {
-                // 1. Ignore if we already know this transaction.
-                let transaction_is_known = self
-                    .global_state_lock
-                    .lock_guard()
-                    .await
-                    .mempool
-                    .contains(transaction_notification.transaction_digest);
-                if transaction_is_known {
-                    debug!("transaction was already known");
-                    return Ok(KEEP_CONNECTION_ALIVE);
-                }
-
-                // Should we check a timestamp here?
+                self.handle_transaction_notification(
+                    transaction_notification.transaction_digest,
+                    None,
+                    peer,
+                )
+                .await?;
 
-                // 2. Request the actual `Transaction` from peer
-                debug!("requesting transaction from peer");
-                peer.send(PeerMessage::TransactionRequest(
+                Ok(KEEP_CONNECTION_ALIVE)
+            }
+            PeerMessage::TransactionNotificationWithFee(transaction_notification) => {
+                self.handle_transaction_notification(
                     transaction_notification.transaction_digest,
-                ))
+                    Some(transaction_notification.fee),
+                    peer,
+                )
                 .await?;
 
                 Ok(KEEP_CONNECTION_ALIVE)
@@ -886,384 +1889,2906 @@ impl PeerLoopHandler {
 
                 Ok(KEEP_CONNECTION_ALIVE)
             }
-        }
-    }
-
-    /// Handle message from main thread. The boolean return value indicates if
-    /// the connection should be closed.
-    ///
-    /// Locking:
-    ///   * acquires `global_state_lock` for write via Self::punish()
-    async fn handle_main_thread_message<S>(
-        &self,
-        msg: MainToPeerThread,
-        peer: &mut S,
-        peer_state_info: &mut MutablePeerState,
-    ) -> Result<bool>
-    where
-        S: Sink<PeerMessage> + TryStream<Ok = PeerMessage> + Unpin,
-        <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
-        <S as TryStream>::Error: std::error::Error,
-    {
-        debug!("Handling {} message from main in peer loop", msg.get_type());
-        match msg {
-            MainToPeerThread::Block(block) => {
-                // We don't currently differentiate whether a new block came from a peer, or from our
-                // own miner. It's always shared through this logic.
-                let new_block_height = block.kernel.header.height;
-                if new_block_height > peer_state_info.highest_shared_block_height {
-                    debug!("Sending PeerMessage::BlockNotification");
-                    peer_state_info.highest_shared_block_height = new_block_height;
-                    peer.send(PeerMessage::BlockNotification((*block).into()))
+            PeerMessage::MempoolInventoryRequest => {
+                let now = Instant::now();
+                if peer_state_info
+                    .last_mempool_inventory_request_answered
+                    .is_some_and(|answered| {
+                        now.duration_since(answered) < MIN_MEMPOOL_INVENTORY_REQUEST_INTERVAL
+                    })
+                {
+                    warn!(
+                        "Peer {} requested the mempool inventory too often",
+                        self.peer_address
+                    );
+                    self.punish(PeerSanctionReason::FloodMempoolInventoryRequest)
                         .await?;
-                    debug!("Sent PeerMessage::BlockNotification");
-                }
-                Ok(false)
-            }
-            MainToPeerThread::RequestBlockBatch(most_canonical_block_digests, peer_addr_target) => {
-                // Only ask one of the peers about the batch of blocks
-                if peer_addr_target != self.peer_address {
-                    return Ok(false);
+                    return Ok(KEEP_CONNECTION_ALIVE);
                 }
+                peer_state_info.last_mempool_inventory_request_answered = Some(now);
 
-                let request_batch_size = std::cmp::min(
-                    STANDARD_BLOCK_BATCH_SIZE,
-                    self.global_state_lock
-                        .cli()
-                        .max_number_of_blocks_before_syncing,
-                );
+                let inventory = self
+                    .global_state_lock
+                    .lock_guard()
+                    .await
+                    .mempool
+                    .list(0, MAX_MEMPOOL_INVENTORY_LENGTH)
+                    .into_iter()
+                    .map(|info| (info.transaction_id, info.fee, info.size))
+                    .collect_vec();
+                peer.send(PeerMessage::MempoolInventoryResponse(inventory))
+                    .await?;
 
-                peer.send(PeerMessage::BlockRequestBatch(
-                    most_canonical_block_digests,
-                    request_batch_size,
-                ))
-                .await?;
+                Ok(KEEP_CONNECTION_ALIVE)
+            }
+            PeerMessage::MempoolInventoryResponse(inventory) => {
+                if !peer_state_info.mempool_inventory_requested {
+                    warn!(
+                        "Peer {} sent a mempool inventory that was not requested",
+                        self.peer_address
+                    );
+                    self.punish(PeerSanctionReason::UnrequestedMessage).await?;
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
+                peer_state_info.mempool_inventory_requested = false;
+
+                // Fetch the missing transactions with the highest fee densities,
+                // skipping those that are being fetched from other peers already
+                let max_transaction_count =
+                    self.global_state_lock.cli().max_mempool_sync_transactions;
+                let peer_address = self.peer_address;
+                let now = SystemTime::now();
+                let missing_transaction_ids = self
+                    .global_state_lock
+                    .lock_mut(|s| {
+                        inventory
+                            .into_iter()
+                            .map(|(transaction_id, _fee, _size)| transaction_id)
+                            .filter(|transaction_id| {
+                                !s.mempool.contains(*transaction_id)
+                                    && s.net.request_announced_transaction(
+                                        *transaction_id,
+                                        peer_address,
+                                        now,
+                                    )
+                            })
+                            .take(max_transaction_count)
+                            .collect_vec()
+                    })
+                    .await;
+                debug!(
+                    "Requesting {} transactions from mempool of peer {}",
+                    missing_transaction_ids.len(),
+                    self.peer_address
+                );
+                for transaction_id in missing_transaction_ids {
+                    peer.send(PeerMessage::TransactionRequest(transaction_id))
+                        .await?;
+                }
 
-                Ok(false)
+                Ok(KEEP_CONNECTION_ALIVE)
             }
-            MainToPeerThread::PeerSynchronizationTimeout(socket_addr) => {
-                if self.peer_address != socket_addr {
-                    return Ok(false);
+            PeerMessage::BlockHeaderRequest(heights) => {
+                debug!(
+                    "Got BlockHeaderRequest for heights {} to {}",
+                    heights.start, heights.end
+                );
+
+                // Get the headers of the canonical chain in the requested
+                // range, at most the maximum many.
+                let global_state = self.global_state_lock.lock_guard().await;
+                let tip_digest = global_state.chain.light_state().hash();
+                let tip_height = global_state.chain.light_state().kernel.header.height;
+                let mut headers = vec![];
+                let mut height = heights.start;
+                while height < heights.end
+                    && height <= tip_height
+                    && headers.len() < MAX_BLOCK_HEADERS_RESPONSE_LENGTH
+                {
+                    let Some(canonical_digest) = global_state
+                        .chain
+                        .archival_state()
+                        .block_height_to_canonical_block_digest(height, tip_digest)
+                        .await
+                    else {
+                        break;
+                    };
+                    let Some(header_and_body_digest) = global_state
+                        .chain
+                        .archival_state()
+                        .get_block_header_with_body_digest(canonical_digest)
+                        .await?
+                    else {
+                        bail!("Canonical block {canonical_digest} must be stored");
+                    };
+                    headers.push(header_and_body_digest);
+                    height = height.next();
                 }
+                drop(global_state);
 
-                self.punish(PeerSanctionReason::SynchronizationTimeout)
+                debug!("Returning {} block headers", headers.len());
+                peer.send(PeerMessage::BlockHeadersResponse(headers))
                     .await?;
 
-                // If this peer failed the last synchronization attempt, we only
-                // sanction, we don't disconnect.
-                Ok(false)
+                Ok(KEEP_CONNECTION_ALIVE)
             }
-            MainToPeerThread::MakePeerDiscoveryRequest => {
-                peer.send(PeerMessage::PeerListRequest).await?;
-                Ok(false)
+            PeerMessage::BlockHeadersResponse(headers) => {
+                debug!("Got {} block headers", headers.len());
+                match peer_state_info.pending_header_requests.pop_front() {
+                    Some(PendingHeaderRequest::HeaderChain) => (),
+                    Some(PendingHeaderRequest::SpotCheck(block_notification)) => {
+                        self.complete_spot_check(
+                            block_notification,
+                            &headers,
+                            peer,
+                            peer_state_info,
+                        )
+                        .await?;
+                        return Ok(KEEP_CONNECTION_ALIVE);
+                    }
+                    None => {
+                        warn!(
+                            "Peer {} sent block headers that were not requested",
+                            self.peer_address
+                        );
+                        self.punish(PeerSanctionReason::UnrequestedMessage).await?;
+                        return Ok(KEEP_CONNECTION_ALIVE);
+                    }
+                }
+
+                if headers.len() > MAX_BLOCK_HEADERS_RESPONSE_LENGTH {
+                    warn!("Got more block headers than allowed");
+                    self.punish(PeerSanctionReason::InvalidMessage).await?;
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
+
+                let extension = {
+                    let mut global_state_mut = self.global_state_lock.lock_guard_mut().await;
+
+                    // Skip the headers of blocks that are known already, since
+                    // the requested range overlaps with the known blocks.
+                    let mut new_headers = vec![];
+                    let mut known_parent = None;
+                    for (header, body_digest) in headers {
+                        let digest = block_digest(&header, body_digest);
+                        let is_known = new_headers.is_empty()
+                            && (global_state_mut.net.header_chain.contains(digest)
+                                || global_state_mut
+                                    .chain
+                                    .archival_state()
+                                    .get_block_header(digest)
+                                    .await
+                                    .is_some());
+                        if is_known {
+                            known_parent = Some((digest, header));
+                        } else {
+                            new_headers.push((header, body_digest));
+                        }
+                    }
+
+                    let Some((first_header, _)) = new_headers.first() else {
+                        debug!("Got no new block headers");
+                        return Ok(KEEP_CONNECTION_ALIVE);
+                    };
+                    let parent = match known_parent {
+                        Some(parent) => Some(parent),
+                        None => {
+                            let parent_digest = first_header.prev_block_digest;
+                            let parent_header =
+                                match global_state_mut.net.header_chain.get(parent_digest) {
+                                    Some(parent_header) => Some(parent_header.to_owned()),
+                                    None => {
+                                        global_state_mut
+                                            .chain
+                                            .archival_state()
+                                            .get_block_header(parent_digest)
+                                            .await
+                                    }
+                                };
+                            parent_header.map(|parent_header| (parent_digest, parent_header))
+                        }
+                    };
+                    let Some((parent_digest, parent_header)) = parent else {
+                        warn!(
+                            "Cannot connect block headers from height {} to known blocks",
+                            first_header.height
+                        );
+                        return Ok(KEEP_CONNECTION_ALIVE);
+                    };
+
+                    global_state_mut.net.header_chain.extend(
+                        parent_digest,
+                        &parent_header,
+                        new_headers,
+                        Timestamp::now(),
+                        self.global_state_lock.cli().network,
+                    )
+                };
+
+                match extension {
+                    Ok(added_count) => {
+                        debug!("Added {added_count} block headers to the header chain");
+                    }
+                    Err(err) => {
+                        warn!("Got invalid block headers: {err}");
+                        self.punish(PeerSanctionReason::InvalidBlockHeader(err.height()))
+                            .await?;
+                    }
+                }
+
+                Ok(KEEP_CONNECTION_ALIVE)
             }
-            MainToPeerThread::Disconnect(target_socket_addr) => {
-                // Disconnect from this peer if its address matches that which the main
-                // thread requested to disconnected from.
-                Ok(target_socket_addr == self.peer_address)
+            PeerMessage::Ping(nonce) => {
+                let now = Instant::now();
+                if peer_state_info
+                    .last_ping_answered
+                    .is_some_and(|answered| now.duration_since(answered) < MIN_PING_INTERVAL)
+                {
+                    warn!("Peer {} pinged too often", self.peer_address);
+                    self.punish(PeerSanctionReason::FloodPing).await?;
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
+                peer_state_info.last_ping_answered = Some(now);
+
+                peer.send(PeerMessage::Pong(nonce)).await?;
+                Ok(KEEP_CONNECTION_ALIVE)
             }
-            // Disconnect from this peer, no matter what.
-            MainToPeerThread::DisconnectAll() => Ok(true),
-            MainToPeerThread::MakeSpecificPeerDiscoveryRequest(target_socket_addr) => {
-                if target_socket_addr == self.peer_address {
-                    peer.send(PeerMessage::PeerListRequest).await?;
+            PeerMessage::Pong(nonce) => {
+                match peer_state_info.pending_ping {
+                    Some((pending_nonce, sent_at)) if pending_nonce == nonce => {
+                        peer_state_info.pending_ping = None;
+                        let round_trip = sent_at.elapsed();
+                        debug!("Round trip to {} took {round_trip:?}", self.peer_address);
+                        self.global_state_lock
+                            .lock_mut(|s| {
+                                if let Some(peer_info) = s.net.peer_map.get_mut(&self.peer_address)
+                                {
+                                    peer_info.statistics.latest_round_trip = Some(round_trip);
+                                }
+                            })
+                            .await;
+                    }
+                    _ => debug!("Ignoring unsolicited pong from {}", self.peer_address),
                 }
-                Ok(false)
+
+                Ok(KEEP_CONNECTION_ALIVE)
             }
-            MainToPeerThread::TransactionNotification(transaction_notification) => {
-                debug!("Sending PeerMessage::TransactionNotification");
-                peer.send(PeerMessage::TransactionNotification(
-                    transaction_notification,
-                ))
-                .await?;
-                debug!("Sent PeerMessage::TransactionNotification");
+            PeerMessage::BlockRequestThrottled {
+                retry_after_in_secs,
+                policy,
+            } => {
+                if !peer_state_info.block_requested
+                    && peer_state_info.pending_block_batches.is_empty()
+                {
+                    warn!(
+                        "Peer {} throttled block requests that were not made",
+                        self.peer_address
+                    );
+                    self.punish(PeerSanctionReason::InvalidBlockRequestThrottle)
+                        .await?;
+                    return Ok(KEEP_CONNECTION_ALIVE);
+                }
+                peer_state_info.block_requested = false;
+                peer_state_info.requested_block_digest = None;
+
+                let policy = policy.with_window_capped_at(MAX_PEER_BLOCK_SERVING_WINDOW);
+                let retry_after = Duration::from_secs(retry_after_in_secs);
+                if retry_after > policy.window {
+                    warn!(
+                        "Peer {} asked to pause block requests for {retry_after:?}, longer than its serving window of {:?}",
+                        self.peer_address, policy.window
+                    );
+                    self.punish(PeerSanctionReason::InvalidBlockRequestThrottle)
+                        .await?;
+                }
+                let pause = retry_after.min(policy.window);
+                info!(
+                    "Peer {} throttled our block requests for {pause:?}",
+                    self.peer_address
+                );
+                peer_state_info.peer_block_serving_policy = Some(policy);
+                self.pause_block_requests(pause).await;
+
                 Ok(KEEP_CONNECTION_ALIVE)
             }
         }
     }
 
-    /// Loop for the peer threads. Awaits either a message from the peer over TCP,
-    /// or a message from main over the main-to-peer-threads broadcast channel.
-    async fn run<S>(
+    /// Act on a block notification whose claimed proof-of-work is plausible.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn handle_block_notification<S>(
         &self,
-        mut peer: S,
-        mut from_main_rx: broadcast::Receiver<MainToPeerThread>,
+        block_notification: PeerBlockNotification,
+        peer: &mut S,
         peer_state_info: &mut MutablePeerState,
     ) -> Result<()>
     where
-        S: Sink<PeerMessage> + TryStream<Ok = PeerMessage> + Unpin,
+        S: Sink<PeerMessage> + Unpin,
         <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
-        <S as TryStream>::Error: std::error::Error,
     {
-        loop {
-            select! {
-                // Handle peer messages
-                peer_message = peer.try_next() => {
-                    match peer_message {
-                        Ok(peer_message) => {
-                            match peer_message {
-                                None => {
-                                    info!("Peer closed connection.");
-                                    break;
-                                }
-                                Some(peer_msg) => {
-                                    let syncing = self.global_state_lock.lock(|s| s.net.syncing).await;
-                                    if peer_msg.ignore_during_sync() && syncing {
-                                        debug!("Ignoring {} message during syncing, from {}", peer_msg.get_type(), self.peer_address);
-                                        continue;
-                                    }
-                                    if peer_msg.ignore_when_not_sync() && !syncing {
-                                        debug!("Ignoring {} message because we are not syncing, from {}", peer_msg.get_type(), self.peer_address);
-                                        continue;
-                                    }
-                                    let close_connection: bool = match self.handle_peer_message(peer_msg, &mut peer, peer_state_info).await {
-                                        Ok(close) => close,
-                                        Err(err) => {
-                                            warn!("{}. Closing connection.", err);
-                                            bail!("{}", err);
-                                        }
-                                    };
-
-                                    if close_connection {
-                                        info!("Closing connection to {}", self.peer_address);
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            error!("Error when receiving from peer: {}. Error: {err}", self.peer_address);
-                            bail!("Error when receiving from peer: {}. Closing connection:", err);
-                        }
-                    }
-                }
-
-                // Handle messages from main thread
-                main_msg_res = from_main_rx.recv() => {
-                    let close_connection = match main_msg_res {
-                        Ok(main_msg) => match self.handle_main_thread_message(main_msg, &mut peer, peer_state_info).await {
-                            Ok(close) => close,
-
-                            // If the handler of main-thread messages returns error, the connection is closed.
-                            // This might indicate that the peer got banned.
-                            Err(err) => {
-                                warn!("handle_main_thread_message returned an eror: {}", err);
-                                true
-                            },
-                        }
-                        Err(e) => panic!("Failed to read from main loop: {}", e),
-                    };
+        let block_is_new = self
+            .global_state_lock
+            .lock_guard()
+            .await
+            .chain
+            .light_state()
+            .kernel
+            .header
+            .proof_of_work_family
+            < block_notification.proof_of_work_family;
+
+        debug!("block_is_new: {}", block_is_new);
+
+        // Only request block if it is new, and if we are not currently reconciling
+        // a fork. If we are reconciling, that is handled later, and the information
+        // about that is stored in `highest_shared_block_height`. If we are syncing
+        // we are also not requesting the block but instead updating the sync state.
+        if self.global_state_lock.lock_guard().await.net.syncing {
+            debug!(
+                "ignoring peer block with height {} because we are presently syncing",
+                block_notification.height
+            );
 
-                    if close_connection {
-                        info!("handle_main_thread_message is closing the connection to {}", self.peer_address);
-                        break;
-                    }
-                }
-            }
+            self.to_main_tx
+                .send(PeerThreadToMain::AddPeerMaxBlockHeight((
+                    self.peer_address,
+                    block_notification.height,
+                    block_notification.proof_of_work_family,
+                )))
+                .await
+                .expect("Sending to main thread must succeed");
+        } else if block_is_new && peer_state_info.fork_reconciliation_blocks.is_empty() {
+            debug!(
+                "sending BlockRequestByHeight to peer for block with height {}",
+                block_notification.height
+            );
+            peer.send(PeerMessage::BlockRequestByHeight(block_notification.height))
+                .await?;
+            peer_state_info.block_requested = true;
+        } else {
+            debug!(
+                "ignoring peer block. height {}. new: {}, reconciling_fork: {}",
+                block_notification.height,
+                block_is_new,
+                !peer_state_info.fork_reconciliation_blocks.is_empty()
+            );
         }
+
         Ok(())
     }
 
-    /// Function called before entering the peer loop. Reads the potentially stored
-    /// peer standing from the database and does other book-keeping before entering
-    /// its final resting place: the `peer_loop`. Note that the peer has already been
-    /// accepted for a connection for this loop to be entered. So we don't need
-    /// to check the standing again.
+    /// Request an announced transaction from the peer, unless it is known
+    /// already or it is being requested from another peer. In the latter case
+    /// the main thread requests it from this peer if the other peer does not
+    /// deliver in time.
     ///
     /// Locking:
     ///   * acquires `global_state_lock` for write
-    pub async fn run_wrapper<S>(
+    async fn handle_transaction_notification<S>(
         &self,
-        mut peer: S,
-        from_main_rx: broadcast::Receiver<MainToPeerThread>,
+        transaction_id: Digest,
+        fee: Option<NeptuneCoins>,
+        peer: &mut S,
     ) -> Result<()>
     where
-        S: Sink<PeerMessage> + TryStream<Ok = PeerMessage> + Unpin,
+        S: Sink<PeerMessage> + Unpin,
         <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
-        <S as TryStream>::Error: std::error::Error,
     {
-        let global_state = self.global_state_lock.lock_guard().await;
-        // Check if peer standing exists in database, return default if it does not.
-        let standing: PeerStanding = global_state
-            .net
-            .peer_databases
-            .peer_standings
-            .get(self.peer_address.ip())
-            .await
-            .unwrap_or_default();
-
-        // Add peer to peer map
-        let new_peer = PeerInfo {
-            port_for_incoming_connections: self.peer_handshake_data.listen_port,
-            connected_address: self.peer_address,
-            inbound: self.inbound_connection,
-            instance_id: self.peer_handshake_data.instance_id,
-            last_seen: SystemTime::now(),
-            standing,
-            version: self.peer_handshake_data.version.clone(),
-            is_archival_node: self.peer_handshake_data.is_archival_node,
-        };
-
-        // There is potential for a race-condition in the peer_map here, as we've previously
-        // counted the number of entries and checked if instance ID was already connected. But
-        // this check could have been invalidated by other threads so we perform it again
-
-        if global_state
-            .net
-            .peer_map
-            .values()
-            .any(|pi| pi.instance_id == self.peer_handshake_data.instance_id)
-        {
-            bail!("Attempted to connect to already connected peer. Aborting connection.");
+        let peer_address = self.peer_address;
+        let must_request = self
+            .global_state_lock
+            .lock_mut(|s| {
+                !s.mempool.contains(transaction_id)
+                    && s.net.request_announced_transaction(
+                        transaction_id,
+                        peer_address,
+                        SystemTime::now(),
+                    )
+            })
+            .await;
+        if !must_request {
+            debug!("transaction was already known or requested");
+            return Ok(());
         }
 
-        if global_state.net.peer_map.len() >= global_state.cli().max_peers as usize {
-            bail!("Attempted to connect to more peers than allowed. Aborting connection.");
-        }
+        // Should we check a timestamp here?
 
-        if global_state.net.peer_map.contains_key(&self.peer_address) {
-            // This shouldn't be possible, unless the peer reports a different instance ID than
-            // for the other connection. Only a malignant client would do that.
-            bail!("Already connected to peer. Aborting connection");
+        match fee {
+            Some(fee) => debug!("requesting transaction with fee {fee} from peer"),
+            None => debug!("requesting transaction from peer"),
         }
-        drop(global_state);
-
-        self.global_state_lock
-            .lock_mut(|s| s.net.peer_map.insert(self.peer_address, new_peer))
-            .await;
-
-        // This message is used to determine if we are to enter synchronization mode.
-        self.to_main_tx
-            .send(PeerThreadToMain::AddPeerMaxBlockHeight((
-                self.peer_address,
-                self.peer_handshake_data.tip_header.height,
-                self.peer_handshake_data.tip_header.proof_of_work_family,
-            )))
+        peer.send(PeerMessage::TransactionRequest(transaction_id))
             .await?;
 
-        // `MutablePeerState` contains the part of the peer-loop's state that is mutable
-        let mut peer_state = MutablePeerState::new(self.peer_handshake_data.tip_header.height);
+        Ok(())
+    }
 
-        // If peer indicates more canonical block, request a block notification to catch up ASAP
-        if self.peer_handshake_data.tip_header.proof_of_work_family
-            > self
-                .global_state_lock
-                .lock_guard()
-                .await
-                .chain
-                .light_state()
-                .kernel
-                .header
-                .proof_of_work_family
-        {
-            peer.send(PeerMessage::BlockNotificationRequest).await?;
+    /// Check the proof-of-work claimed in a block notification against the
+    /// headers from a block this node knows up to the notified block. The
+    /// headers must form a valid chain on top of the known block, and end in
+    /// the notified block with the claimed proof-of-work. A response cut short
+    /// at the maximum length holds up if its headers are valid, as they carry
+    /// that many blocks of proof-of-work on top of this node's chain.
+    ///
+    /// A peer whose claim does not hold up is sanctioned heavily, and its
+    /// claimed tip is dropped from the sync state, such that it cannot keep
+    /// this node in sync mode.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    ///   * acquires `global_state_lock` for write via Self::punish()
+    async fn complete_spot_check<S>(
+        &self,
+        block_notification: PeerBlockNotification,
+        headers: &[(BlockHeader, Digest)],
+        peer: &mut S,
+        peer_state_info: &mut MutablePeerState,
+    ) -> Result<()>
+    where
+        S: Sink<PeerMessage> + Unpin,
+        <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
+    {
+        let digests = headers
+            .iter()
+            .map(|(header, body_digest)| block_digest(header, *body_digest))
+            .collect_vec();
+        let is_anchored = match digests.first() {
+            Some(anchor_digest) => {
+                let global_state = self.global_state_lock.lock_guard().await;
+                global_state.net.header_chain.contains(*anchor_digest)
+                    || global_state
+                        .chain
+                        .archival_state()
+                        .get_block_header(*anchor_digest)
+                        .await
+                        .is_some()
+            }
+            None => false,
+        };
+        let now = Timestamp::now();
+        let network = self.global_state_lock.cli().network;
+        let is_valid_chain = (1..headers.len()).all(|i| {
+            validate_child_header(
+                digests[i - 1],
+                &headers[i - 1].0,
+                digests[i],
+                &headers[i].0,
+                now,
+                network,
+            )
+            .is_ok()
+        });
+        let claim_holds = headers.len() >= 2
+            && is_anchored
+            && is_valid_chain
+            && match headers.last() {
+                Some((header, _)) if header.height == block_notification.height => {
+                    digests.last() == Some(&block_notification.hash)
+                        && header.proof_of_work_family == block_notification.proof_of_work_family
+                }
+                _ => headers.len() == MAX_BLOCK_HEADERS_RESPONSE_LENGTH,
+            };
+
+        if !claim_holds {
+            warn!(
+                "Proof-of-work claimed by {} for block with height {} does not hold up",
+                self.peer_address, block_notification.height
+            );
+            self.to_main_tx
+                .send(PeerThreadToMain::RemovePeerMaxBlockHeight(
+                    self.peer_address,
+                ))
+                .await?;
+            self.punish(PeerSanctionReason::FalseProofOfWorkClaim(
+                block_notification.height,
+            ))
+            .await?;
+            return Ok(());
         }
 
-        let res = self.run(peer, from_main_rx, &mut peer_state).await;
-        debug!("Exited peer loop for {}", self.peer_address);
+        debug!(
+            "Proof-of-work claimed for block with height {} holds up",
+            block_notification.height
+        );
+        self.handle_block_notification(block_notification, peer, peer_state_info)
+            .await
+    }
 
-        close_peer_connected_callback(
-            self.global_state_lock.clone(),
-            self.peer_address,
-            &self.to_main_tx,
+    /// The number of blocks requested in one batch
+    fn block_batch_request_size(&self) -> usize {
+        cmp::min(
+            STANDARD_BLOCK_BATCH_SIZE,
+            self.global_state_lock
+                .cli()
+                .max_number_of_blocks_before_syncing,
         )
-        .await?;
+    }
 
-        debug!("Ending peer loop for {}", self.peer_address);
+    /// Discard the responses to all batch requests in flight, such that new
+    /// requests can be made.
+    fn supersede_pending_block_batches(peer_state_info: &mut MutablePeerState) {
+        for request in peer_state_info.pending_block_batches.iter_mut() {
+            request.superseded = true;
+        }
+    }
 
-        // Return any error that `run` returned. Returning and not suppressing errors is a quite nice
-        // feature to have for testing purposes.
-        res
+    /// Stop downloading blocks in batches from the peer.
+    fn close_block_batch_window(peer_state_info: &mut MutablePeerState) {
+        Self::supersede_pending_block_batches(peer_state_info);
+        peer_state_info.block_batch_window = None;
+        peer_state_info.block_batches_awaiting_parent.clear();
     }
-}
 
-#[cfg(test)]
-mod peer_loop_tests {
-    use rand::{thread_rng, Rng};
-    use tokio::sync::mpsc::error::TryRecvError;
-    use tracing_test::traced_test;
+    /// Keep up to [BLOCK_BATCH_WINDOW_SIZE] batch requests by height in flight,
+    /// each continuing the one before it, as far as the peer has blocks and its
+    /// serving policy allows.
+    async fn fill_block_batch_window<S>(
+        &self,
+        peer: &mut S,
+        peer_state_info: &mut MutablePeerState,
+    ) -> Result<()>
+    where
+        S: Sink<PeerMessage> + Unpin,
+        <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
+    {
+        let Some((_tip_digest, tip_height)) = peer_state_info
+            .block_batch_window
+            .as_ref()
+            .map(|window| window.tip)
+        else {
+            return Ok(());
+        };
 
-    use crate::{
-        config_models::network::Network,
-        models::{peer::TransactionNotification, state::wallet::WalletSecret},
-        tests::shared::{
-            get_dummy_peer_connection_data_genesis, get_dummy_socket_address,
-            get_test_genesis_setup, make_mock_block_with_invalid_pow,
-            make_mock_block_with_valid_pow, make_mock_transaction, Action, Mock,
-        },
-    };
+        let request_batch_size = self.block_batch_request_size();
+        loop {
+            let requests_in_flight = peer_state_info
+                .pending_block_batches
+                .iter()
+                .filter(|request| !request.superseded)
+                .collect_vec();
+            if requests_in_flight.len() >= BLOCK_BATCH_WINDOW_SIZE {
+                return Ok(());
+            }
+
+            // The blocks following a request by digest are only known once it
+            // is answered
+            let start_height = match requests_in_flight.last() {
+                Some(request) => match request.start_height {
+                    Some(start_height) => start_height + request.count,
+                    None => return Ok(()),
+                },
+                None => tip_height.next(),
+            };
+
+            // Requests beyond the peer's tip are sanctioned by the peer
+            let count = cmp::min(
+                request_batch_size as i128,
+                peer_state_info.highest_shared_block_height - start_height + 1,
+            );
+            if count < MINIMUM_BLOCK_BATCH_SIZE as i128 {
+                return Ok(());
+            }
+            if let Some(policy) = peer_state_info.peer_block_serving_policy {
+                if peer_state_info
+                    .received_blocks
+                    .retry_after(&policy, time::Instant::now())
+                    .is_some()
+                {
+                    return Ok(());
+                }
+            }
+
+            peer.send(PeerMessage::BlockRequestBatchByHeight {
+                start_height,
+                count: count as u16,
+            })
+            .await?;
+            peer_state_info
+                .pending_block_batches
+                .push_back(PendingBlockBatch {
+                    start_height: Some(start_height),
+                    count: count as usize,
+                    superseded: false,
+                });
+        }
+    }
+
+    /// Record in the peer map that blocks should not be requested from the
+    /// peer for the given duration.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for write
+    async fn pause_block_requests(&self, pause: Duration) {
+        let paused_until = SystemTime::now() + pause;
+        self.global_state_lock
+            .lock_mut(|s| {
+                if let Some(peer_info) = s.net.peer_map.get_mut(&self.peer_address) {
+                    peer_info.statistics.block_requests_paused_until = Some(paused_until);
+                }
+            })
+            .await;
+    }
+
+    /// Handle message from main thread. The boolean return value indicates if
+    /// the connection should be closed.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for write via Self::punish()
+    async fn handle_main_thread_message<S>(
+        &self,
+        msg: MainToPeerThread,
+        peer: &mut S,
+        peer_state_info: &mut MutablePeerState,
+    ) -> Result<bool>
+    where
+        S: Sink<PeerMessage> + TryStream<Ok = PeerMessage> + Unpin,
+        <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
+        <S as TryStream>::Error: std::error::Error,
+    {
+        debug!("Handling {} message from main in peer loop", msg.get_type());
+        match msg {
+            MainToPeerThread::Block(block) => {
+                // A batch of the download window that arrived before the main
+                // loop stored its parent
+                if peer_state_info
+                    .block_batches_awaiting_parent
+                    .front()
+                    .is_some_and(|batch| batch[0].kernel.header.prev_block_digest == block.hash())
+                {
+                    if let Some(batch) = peer_state_info.block_batches_awaiting_parent.pop_front() {
+                        self.handle_blocks(batch, (*block).clone()).await?;
+                    }
+                }
+
+                // We don't currently differentiate whether a new block came from a peer, or from our
+                // own miner. It's always shared through this logic.
+                let new_block_height = block.kernel.header.height;
+                if new_block_height > peer_state_info.highest_shared_block_height {
+                    peer_state_info.highest_shared_block_height = new_block_height;
+
+                    // Blocks mined by this node are announced as compact blocks,
+                    // since peers likely hold most of their transactions already.
+                    // Compact blocks do not carry uncles, so blocks referencing
+                    // uncles are announced in full.
+                    let block_transactions = self
+                        .global_state_lock
+                        .lock_guard()
+                        .await
+                        .net
+                        .mined_block_transactions(block.hash())
+                        .filter(|_| block.kernel.body.uncle_blocks.is_empty())
+                        .cloned();
+                    match block_transactions {
+                        Some(block_transactions) => {
+                            debug!("Sending PeerMessage::CompactBlock");
+                            peer.send(PeerMessage::CompactBlock {
+                                digest: block.hash(),
+                                header: block.kernel.header.clone(),
+                                txids: block_transactions
+                                    .mempool_transactions
+                                    .iter()
+                                    .map(Hash::hash)
+                                    .collect(),
+                                coinbase: Box::new(block_transactions.coinbase),
+                            })
+                            .await?;
+                            debug!("Sent PeerMessage::CompactBlock");
+                        }
+                        None => {
+                            debug!("Sending PeerMessage::BlockNotification");
+                            peer.send(PeerMessage::BlockNotification((*block).into()))
+                                .await?;
+                            debug!("Sent PeerMessage::BlockNotification");
+                        }
+                    }
+                }
+                Ok(false)
+            }
+            MainToPeerThread::RequestBlockBatch(most_canonical_block_digests, peer_addr_target) => {
+                // Only ask one of the peers about the batch of blocks
+                if peer_addr_target != self.peer_address {
+                    return Ok(false);
+                }
+
+                // The main loop asks for more blocks whenever it stored some,
+                // which the requests in flight already cover
+                if peer_state_info
+                    .pending_block_batches
+                    .iter()
+                    .any(|request| !request.superseded)
+                {
+                    debug!(
+                        "Block batch requests to {} are still outstanding",
+                        self.peer_address
+                    );
+                    return Ok(false);
+                }
+
+                // Pace the requests according to the peer's serving policy,
+                // rather than running into its quota
+                if let Some(policy) = peer_state_info.peer_block_serving_policy {
+                    if let Some(retry_after) = peer_state_info
+                        .received_blocks
+                        .retry_after(&policy, time::Instant::now())
+                    {
+                        debug!(
+                            "Not requesting blocks from {} for {retry_after:?}, per its serving policy",
+                            self.peer_address
+                        );
+                        self.pause_block_requests(retry_after).await;
+                        return Ok(false);
+                    }
+                }
+
+                let request_batch_size = self.block_batch_request_size();
+
+                // Peers that serve blocks by height are asked for the blocks
+                // following our most canonical block, which saves them the search
+                // for the fork point.
+                let serves_batches_by_height = self
+                    .peer_handshake_data
+                    .features
+                    .contains(PeerFeatures::BLOCK_BATCH_BY_HEIGHT);
+                let parent_header = match most_canonical_block_digests.first() {
+                    Some(parent_digest) if serves_batches_by_height => self
+                        .global_state_lock
+                        .lock_guard()
+                        .await
+                        .chain
+                        .archival_state()
+                        .get_block_header(*parent_digest)
+                        .await
+                        .map(|header| (*parent_digest, header)),
+                    _ => None,
+                };
+                let Some((parent_digest, parent_header)) = parent_header else {
+                    peer.send(PeerMessage::BlockRequestBatch(
+                        most_canonical_block_digests,
+                        request_batch_size,
+                    ))
+                    .await?;
+                    peer_state_info
+                        .pending_block_batches
+                        .push_back(PendingBlockBatch {
+                            start_height: None,
+                            count: request_batch_size,
+                            superseded: false,
+                        });
+                    return Ok(false);
+                };
+
+                // Open a download window at our most canonical block. Batches
+                // held back from an earlier window are requested again.
+                let start_height = parent_header.height.next();
+                peer.send(PeerMessage::BlockRequestBatchByHeight {
+                    start_height,
+                    count: request_batch_size as u16,
+                })
+                .await?;
+                peer_state_info
+                    .pending_block_batches
+                    .push_back(PendingBlockBatch {
+                        start_height: Some(start_height),
+                        count: request_batch_size,
+                        superseded: false,
+                    });
+                peer_state_info.block_batches_awaiting_parent.clear();
+                peer_state_info.block_batch_window = Some(BlockBatchWindow {
+                    tip: (parent_digest, parent_header.height),
+                    most_canonical_digests: most_canonical_block_digests,
+                });
+                self.fill_block_batch_window(peer, peer_state_info).await?;
+
+                Ok(false)
+            }
+            MainToPeerThread::RequestBlockHeaders(heights, peer_addr_target) => {
+                // Only ask one of the peers about the headers
+                if peer_addr_target != self.peer_address {
+                    return Ok(false);
+                }
+
+                peer_state_info
+                    .pending_header_requests
+                    .push_back(PendingHeaderRequest::HeaderChain);
+                peer.send(PeerMessage::BlockHeaderRequest(heights)).await?;
+
+                Ok(false)
+            }
+            MainToPeerThread::PeerSynchronizationTimeout(socket_addr) => {
+                if self.peer_address != socket_addr {
+                    return Ok(false);
+                }
+
+                self.punish(PeerSanctionReason::SynchronizationTimeout)
+                    .await?;
+                Self::supersede_pending_block_batches(peer_state_info);
+
+                // If this peer failed the last synchronization attempt, we only
+                // sanction, we don't disconnect.
+                Ok(false)
+            }
+            MainToPeerThread::MakePeerDiscoveryRequest => {
+                peer.send(PeerMessage::PeerListRequest).await?;
+                Ok(false)
+            }
+            MainToPeerThread::Disconnect(target_socket_addr) => {
+                // Disconnect from this peer if its address matches that which the main
+                // thread requested to disconnected from.
+                Ok(target_socket_addr == self.peer_address)
+            }
+            // Disconnect from this peer, no matter what.
+            MainToPeerThread::DisconnectAll() => Ok(true),
+            MainToPeerThread::MakeSpecificPeerDiscoveryRequest(target_socket_addr) => {
+                if target_socket_addr == self.peer_address {
+                    peer.send(PeerMessage::PeerListRequest).await?;
+                }
+                Ok(false)
+            }
+            MainToPeerThread::RequestMempoolInventory(target_socket_addr) => {
+                let now = Instant::now();
+                let requested_recently = peer_state_info
+                    .last_mempool_inventory_request_sent
+                    .is_some_and(|sent| {
+                        now.duration_since(sent) < MIN_MEMPOOL_INVENTORY_REQUEST_INTERVAL
+                    });
+                if target_socket_addr == self.peer_address && !requested_recently {
+                    peer_state_info.mempool_inventory_requested = true;
+                    peer_state_info.last_mempool_inventory_request_sent = Some(now);
+                    peer.send(PeerMessage::MempoolInventoryRequest).await?;
+                }
+                Ok(KEEP_CONNECTION_ALIVE)
+            }
+            MainToPeerThread::RequestBlockNotification(target_socket_addr) => {
+                if target_socket_addr == self.peer_address {
+                    peer.send(PeerMessage::BlockNotificationRequest).await?;
+                }
+                Ok(KEEP_CONNECTION_ALIVE)
+            }
+            MainToPeerThread::TransactionNotification(transaction_notification) => {
+                debug!("Sending PeerMessage::TransactionNotification");
+                let message = if self
+                    .peer_handshake_data
+                    .features
+                    .contains(PeerFeatures::TRANSACTION_FEE_NOTIFICATION)
+                {
+                    PeerMessage::TransactionNotificationWithFee(transaction_notification)
+                } else {
+                    PeerMessage::TransactionNotification(transaction_notification.into())
+                };
+                peer.send(message).await?;
+                debug!("Sent PeerMessage::TransactionNotification");
+                Ok(KEEP_CONNECTION_ALIVE)
+            }
+            MainToPeerThread::RequestTransaction(transaction_id, target_socket_addr) => {
+                if target_socket_addr == self.peer_address {
+                    peer.send(PeerMessage::TransactionRequest(transaction_id))
+                        .await?;
+                }
+                Ok(KEEP_CONNECTION_ALIVE)
+            }
+        }
+    }
+
+    /// Publish the traffic statistics of this connection in the peer map.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for write
+    async fn publish_traffic_statistics<S>(&self, peer: &CountingPeerStream<S>) {
+        let traffic = PeerTraffic {
+            bytes_sent: self.byte_counts.sent(),
+            bytes_received: self.byte_counts.received(),
+            ..peer.traffic().clone()
+        };
+        self.global_state_lock
+            .lock_mut(|s| {
+                if let Some(peer_info) = s.net.peer_map.get_mut(&self.peer_address) {
+                    peer_info.statistics.traffic = traffic;
+                }
+            })
+            .await;
+    }
+
+    /// Loop for the peer threads. Awaits either a message from the peer over TCP,
+    /// a message from main over the main-to-peer-threads broadcast channel, or
+    /// the timer for pinging the peer.
+    async fn run<S>(
+        &self,
+        mut peer: CountingPeerStream<S>,
+        mut from_main_rx: broadcast::Receiver<MainToPeerThread>,
+        peer_state_info: &mut MutablePeerState,
+    ) -> Result<()>
+    where
+        S: Sink<PeerMessage> + TryStream<Ok = PeerMessage> + Unpin,
+        <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
+        <S as TryStream>::Error: std::error::Error,
+    {
+        let ping_timer = time::sleep(Duration::from_secs(PEER_PING_INTERVAL_IN_SECS));
+        tokio::pin!(ping_timer);
+
+        loop {
+            select! {
+                // Handle peer messages
+                peer_message = peer.try_next() => {
+                    match peer_message {
+                        Ok(peer_message) => {
+                            match peer_message {
+                                None => {
+                                    info!("Peer closed connection.");
+                                    break;
+                                }
+                                Some(peer_msg) => {
+                                    let close_connection: bool = match self.handle_received_message(peer_msg, &mut peer, peer_state_info).await {
+                                        Ok(close) => close,
+                                        Err(err) => {
+                                            warn!("{}. Closing connection.", err);
+                                            bail!("{}", err);
+                                        }
+                                    };
+
+                                    if close_connection {
+                                        info!("Closing connection to {}", self.peer_address);
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            error!("Error when receiving from peer: {}. Error: {err}", self.peer_address);
+                            bail!("Error when receiving from peer: {}. Closing connection:", err);
+                        }
+                    }
+                }
+
+                // Handle messages from main thread
+                main_msg_res = from_main_rx.recv() => {
+                    let close_connection = match main_msg_res {
+                        Ok(main_msg) => match self.handle_main_thread_message(main_msg, &mut peer, peer_state_info).await {
+                            Ok(close) => close,
+
+                            // If the handler of main-thread messages returns error, the connection is closed.
+                            // This might indicate that the peer got banned.
+                            Err(err) => {
+                                warn!("handle_main_thread_message returned an eror: {}", err);
+                                true
+                            },
+                        }
+                        Err(e) => panic!("Failed to read from main loop: {}", e),
+                    };
+
+                    if close_connection {
+                        info!("handle_main_thread_message is closing the connection to {}", self.peer_address);
+                        break;
+                    }
+                }
+
+                // Ping the peer, and disconnect it if the previous ping is unanswered
+                _ = &mut ping_timer => {
+                    if peer_state_info.pending_ping.is_some() {
+                        warn!("Peer {} did not answer ping in time. Closing connection.", self.peer_address);
+                        bail!("Peer did not answer ping in time");
+                    }
+
+                    self.publish_traffic_statistics(&peer).await;
+                    if self.peer_handshake_data.features.contains(PeerFeatures::PING) {
+                        let nonce = rand::random();
+                        peer.send(PeerMessage::Ping(nonce)).await?;
+                        peer_state_info.pending_ping = Some((nonce, Instant::now()));
+                    }
+
+                    ping_timer.as_mut().reset(time::Instant::now() + Duration::from_secs(PEER_PING_INTERVAL_IN_SECS));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Function called before entering the peer loop. Reads the potentially stored
+    /// peer standing from the database and does other book-keeping before entering
+    /// its final resting place: the `peer_loop`. Note that the peer has already been
+    /// accepted for a connection for this loop to be entered. So we don't need
+    /// to check the standing again.
+    ///
+    /// Locking:
+    ///   * acquires `global_state_lock` for write
+    pub async fn run_wrapper<S>(
+        &self,
+        peer: S,
+        from_main_rx: broadcast::Receiver<MainToPeerThread>,
+    ) -> Result<()>
+    where
+        S: Sink<PeerMessage> + TryStream<Ok = PeerMessage> + Unpin,
+        <S as Sink<PeerMessage>>::Error: std::error::Error + Sync + Send + 'static,
+        <S as TryStream>::Error: std::error::Error,
+    {
+        let mut peer = CountingPeerStream::new(peer);
+        let global_state = self.global_state_lock.lock_guard().await;
+        // Peers without a stored standing, or with an expired one, start from
+        // the default standing
+        let standing: PeerStanding = global_state.peer_standing(self.peer_address.ip()).await;
+
+        // Add peer to peer map
+        let new_peer = PeerInfo {
+            port_for_incoming_connections: self.peer_handshake_data.listen_port,
+            connected_address: self.peer_address,
+            inbound: self.inbound_connection,
+            instance_id: self.peer_handshake_data.instance_id,
+            last_seen: SystemTime::now(),
+            standing,
+            version: self.peer_handshake_data.version.clone(),
+            protocol_version: self.peer_handshake_data.protocol_version,
+            is_archival_node: self.peer_handshake_data.features.is_archival_node(),
+            statistics: PeerStatistics::default(),
+        };
+
+        // There is potential for a race-condition in the peer_map here, as we've previously
+        // counted the number of entries and checked if instance ID was already connected. But
+        // this check could have been invalidated by other threads so we perform it again
+
+        if global_state
+            .net
+            .peer_map
+            .values()
+            .any(|pi| pi.instance_id == self.peer_handshake_data.instance_id)
+        {
+            bail!("Attempted to connect to already connected peer. Aborting connection.");
+        }
+
+        if global_state.net.peer_count(self.inbound_connection)
+            >= global_state.cli().max_peers(self.inbound_connection)
+        {
+            bail!("Attempted to connect to more peers than allowed. Aborting connection.");
+        }
+
+        if global_state.net.peer_map.contains_key(&self.peer_address) {
+            // This shouldn't be possible, unless the peer reports a different instance ID than
+            // for the other connection. Only a malignant client would do that.
+            bail!("Already connected to peer. Aborting connection");
+        }
+        drop(global_state);
+
+        // Remember the peer, such that we can reconnect to it after a restart
+        let mut global_state_mut = self.global_state_lock.lock_guard_mut().await;
+        if let Some(listen_address) = new_peer.listen_address() {
+            let known_peer = KnownPeer {
+                instance_id: new_peer.instance_id,
+                last_seen: new_peer.last_seen,
+            };
+            global_state_mut
+                .net
+                .write_known_peer(listen_address, known_peer)
+                .await;
+            global_state_mut
+                .net
+                .record_address_success(listen_address, new_peer.last_seen)
+                .await;
+        }
+        global_state_mut
+            .net
+            .peer_map
+            .insert(self.peer_address, new_peer);
+
+        // The handshake only carries the header of the peer's tip, which does
+        // not determine the tip's digest. The digest of its parent is known
+        // though, so that is the first tip claimed by the peer.
+        let tip_header = &self.peer_handshake_data.tip_header;
+        if !tip_header.height.is_genesis() {
+            global_state_mut.net.chain_split_detector.record_claim(
+                self.peer_address,
+                tip_header.prev_block_digest,
+                tip_header.height.previous(),
+                SystemTime::now(),
+            );
+        }
+        drop(global_state_mut);
+
+        // This message is used to determine if we are to enter synchronization mode.
+        self.to_main_tx
+            .send(PeerThreadToMain::AddPeerMaxBlockHeight((
+                self.peer_address,
+                self.peer_handshake_data.tip_header.height,
+                self.peer_handshake_data.tip_header.proof_of_work_family,
+            )))
+            .await?;
+
+        // `MutablePeerState` contains the part of the peer-loop's state that is mutable
+        let mut peer_state = MutablePeerState::new(self.peer_handshake_data.tip_header.height);
+
+        // If peer indicates more canonical block, request a block notification to catch up ASAP
+        if self.peer_handshake_data.tip_header.proof_of_work_family
+            > self
+                .global_state_lock
+                .lock_guard()
+                .await
+                .chain
+                .light_state()
+                .kernel
+                .header
+                .proof_of_work_family
+        {
+            peer.send(PeerMessage::BlockNotificationRequest).await?;
+        }
+
+        let connection_start = SystemTime::now();
+        let res = self.run(peer, from_main_rx, &mut peer_state).await;
+        debug!("Exited peer loop for {}", self.peer_address);
+
+        // A sustained connection resets the backoff for redialing the peer, but a `Bye` makes
+        // the dialer wait before reconnecting, such that the peer is not immediately redialed.
+        if let Some(dial_address) = self.dial_address() {
+            let sustained = connection_start
+                .elapsed()
+                .is_ok_and(|duration| duration >= SUSTAINED_CONNECTION_DURATION);
+            if sustained || peer_state.received_bye {
+                let mut global_state_mut = self.global_state_lock.lock_guard_mut().await;
+                if sustained {
+                    global_state_mut.net.reset_dial_backoff(dial_address);
+                }
+                if peer_state.received_bye {
+                    global_state_mut.net.record_dial_failure(dial_address);
+                }
+            }
+        }
+
+        close_peer_connected_callback(
+            self.global_state_lock.clone(),
+            self.peer_address,
+            &self.to_main_tx,
+        )
+        .await?;
+
+        debug!("Ending peer loop for {}", self.peer_address);
+
+        // Return any error that `run` returned. Returning and not suppressing errors is a quite nice
+        // feature to have for testing purposes.
+        res
+    }
+}
+
+#[cfg(test)]
+mod peer_loop_tests {
+    use rand::{thread_rng, Rng};
+    use tokio::sync::mpsc::error::TryRecvError;
+    use tracing_test::traced_test;
+
+    use crate::{
+        config_models::network::Network,
+        models::{
+            peer::{BlockServingPolicy, TransactionNotification, TransactionNotificationWithFee},
+            state::wallet::{
+                utxo_notification_pool::{ExpectedUtxo, UtxoNotifier},
+                WalletSecret,
+            },
+        },
+        peer_traffic_capture::{
+            CaptureLimits, CapturingPeerStream, PeerTrafficCapture, TrafficDirection,
+        },
+        tests::shared::{
+            get_dummy_peer_connection_data_genesis, get_dummy_socket_address,
+            get_test_genesis_setup, make_mock_block, make_mock_block_with_invalid_pow,
+            make_mock_block_with_valid_pow, make_mock_transaction, replay_actions,
+            unit_test_data_directory, Action, Mock,
+        },
+        util_types::{mutator_set::commit, test_shared::mutator_set::random_removal_record},
+    };
+
+    use super::*;
+    use crate::models::state::networking_state::{DIAL_BACKOFF_BASE, DIAL_BACKOFF_CAP};
+    use std::time::Duration;
+    use twenty_first::amount::u32s::U32s;
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_peer_loop_bye() -> Result<()> {
+        let mock = Mock::new(vec![Action::Read(PeerMessage::Bye)]);
+
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(Network::Alpha, 2).await?;
+
+        let peer_address = get_dummy_socket_address(2);
+        let from_main_rx_clone = peer_broadcast_tx.subscribe();
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, true, 1);
+        peer_loop_handler
+            .run_wrapper(mock, from_main_rx_clone)
+            .await?;
+
+        assert_eq!(
+            2,
+            state_lock.lock_guard().await.net.peer_map.len(),
+            "peer map length must be back to 2 after goodbye"
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn ping_is_answered_and_pong_records_round_trip_test() -> Result<()> {
+        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(Network::Alpha, 1).await?;
+        let peer_address = *state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_map
+            .keys()
+            .next()
+            .unwrap();
+
+        // Pings are answered with the same nonce, and pongs that were not
+        // asked for are ignored.
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::Ping(7)),
+            Action::Write(PeerMessage::Pong(7)),
+            Action::Read(PeerMessage::Pong(8)),
+            Action::Read(PeerMessage::Pong(9)),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+        peer_state.pending_ping = Some((8, Instant::now()));
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock),
+                from_main_rx_clone,
+                &mut peer_state,
+            )
+            .await?;
+
+        assert!(peer_state.pending_ping.is_none());
+        assert!(state_lock.lock_guard().await.net.peer_map[&peer_address]
+            .statistics
+            .latest_round_trip
+            .is_some());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn frequent_pings_are_sanctioned_test() -> Result<()> {
+        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(Network::Alpha, 1).await?;
+        let peer_address = *state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_map
+            .keys()
+            .next()
+            .unwrap();
+
+        // The second ping follows the first one immediately, and is not
+        // answered
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::Ping(1)),
+            Action::Write(PeerMessage::Pong(1)),
+            Action::Read(PeerMessage::Ping(2)),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock),
+                from_main_rx_clone,
+                &mut peer_state,
+            )
+            .await?;
+
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(PeerSanctionReason::FloodPing),
+            standing.latest_sanction
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn blocks_with_pruned_witness_are_not_served_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+        let a_recipient_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let genesis_block = Block::genesis_block(network);
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_2, _, _) =
+            make_mock_block_with_valid_pow(network, &block_1, None, a_recipient_address, rng.gen());
+        {
+            let mut global_state_mut = state_lock.lock_guard_mut().await;
+            global_state_mut.set_new_tip(block_1.clone()).await?;
+            global_state_mut.set_new_tip(block_2.clone()).await?;
+            global_state_mut
+                .chain
+                .archival_state_mut()
+                .prune_transaction_witnesses(1u64.into())
+                .await?;
+        }
+
+        // Only the block whose witness was kept is served
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestByHash(block_1.hash())),
+            Action::Read(PeerMessage::BlockRequestByHeight(1u64.into())),
+            Action::Read(PeerMessage::BlockRequestByHash(block_2.hash())),
+            Action::Write(PeerMessage::Block(Box::new(block_2.into()))),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        peer_loop_handler
+            .run_wrapper(mock, peer_broadcast_tx.subscribe())
+            .await?;
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn greedy_peer_is_throttled_until_window_rolls_test() -> Result<()> {
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, mut state_lock, hsd) =
+            get_test_genesis_setup(Network::Alpha, 1).await?;
+        let peer_address = *state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_map
+            .keys()
+            .next()
+            .unwrap();
+        let genesis_block: Block = state_lock
+            .lock_guard()
+            .await
+            .chain
+            .archival_state()
+            .get_tip()
+            .await;
+
+        let mut cli = state_lock.cli().clone();
+        cli.max_served_blocks_per_peer = 2;
+        cli.block_serving_window = 3;
+        state_lock.set_cli(cli).await;
+        let policy = state_lock.cli().block_serving_policy();
+
+        // The third block request exceeds the quota, and is answered with the
+        // time until the first block served leaves the window.
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestByHash(genesis_block.hash())),
+            Action::Write(PeerMessage::Block(Box::new(genesis_block.clone().into()))),
+            Action::Read(PeerMessage::BlockRequestByHash(genesis_block.hash())),
+            Action::Write(PeerMessage::Block(Box::new(genesis_block.clone().into()))),
+            Action::Read(PeerMessage::BlockRequestByHash(genesis_block.hash())),
+            Action::Write(PeerMessage::BlockRequestThrottled {
+                retry_after_in_secs: 3,
+                policy,
+            }),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock),
+                peer_broadcast_tx.subscribe(),
+                &mut peer_state,
+            )
+            .await?;
+
+        let statistics = state_lock.lock_guard().await.net.peer_map[&peer_address]
+            .statistics
+            .clone();
+        assert_eq!(2, statistics.blocks_served);
+        assert_eq!(
+            2 * TransferBlock::from(genesis_block.clone()).get_size() as u64,
+            statistics.block_bytes_served
+        );
+        assert_eq!(1, statistics.block_requests_throttled);
+
+        // Reconnecting does not reset the quota
+        let mock_reconnected = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestByHash(genesis_block.hash())),
+            Action::Write(PeerMessage::BlockRequestThrottled {
+                retry_after_in_secs: 3,
+                policy,
+            }),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock_reconnected),
+                peer_broadcast_tx.subscribe(),
+                &mut MutablePeerState::new(BlockHeight::genesis()),
+            )
+            .await?;
+
+        // Once the window has rolled, the peer is served again
+        time::pause();
+        time::sleep(policy.window).await;
+        let mock_after_window = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestByHash(genesis_block.hash())),
+            Action::Write(PeerMessage::Block(Box::new(genesis_block.clone().into()))),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock_after_window),
+                peer_broadcast_tx.subscribe(),
+                &mut peer_state,
+            )
+            .await?;
+        assert_eq!(
+            3,
+            state_lock.lock_guard().await.net.peer_map[&peer_address]
+                .statistics
+                .blocks_served
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn block_requests_respect_peer_serving_policy_test() -> Result<()> {
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(Network::Alpha, 1).await?;
+        let peer_address = *state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_map
+            .keys()
+            .next()
+            .unwrap();
+        let policy = BlockServingPolicy {
+            max_blocks: 1,
+            max_bytes: 1 << 30,
+            window: Duration::from_secs(600),
+        };
+
+        // A throttled peer is not asked for blocks until it said so
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestThrottled {
+                retry_after_in_secs: 60,
+                policy,
+            }),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+        peer_state.block_requested = true;
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock),
+                peer_broadcast_tx.subscribe(),
+                &mut peer_state,
+            )
+            .await?;
+        assert_eq!(Some(policy), peer_state.peer_block_serving_policy);
+        let now = SystemTime::now();
+        assert!(state_lock
+            .lock_guard()
+            .await
+            .net
+            .peers_pausing_block_requests(now)
+            .contains(&peer_address));
+        assert!(state_lock
+            .lock_guard()
+            .await
+            .net
+            .peers_pausing_block_requests(now + Duration::from_secs(61))
+            .is_empty());
+
+        // Block batches are not requested from a peer whose announced quota
+        // is used up, which would only get the requests throttled.
+        state_lock
+            .lock_mut(|s| {
+                s.net
+                    .peer_map
+                    .get_mut(&peer_address)
+                    .unwrap()
+                    .statistics
+                    .block_requests_paused_until = None
+            })
+            .await;
+        peer_state
+            .received_blocks
+            .record(time::Instant::now(), 1000);
+        let genesis_digest = state_lock.lock_guard().await.chain.light_state().hash();
+        let mut mock_without_requests = Mock::new(vec![]);
+        peer_loop_handler
+            .handle_main_thread_message(
+                MainToPeerThread::RequestBlockBatch(vec![genesis_digest], peer_address),
+                &mut mock_without_requests,
+                &mut peer_state,
+            )
+            .await?;
+        assert!(state_lock
+            .lock_guard()
+            .await
+            .net
+            .peers_pausing_block_requests(SystemTime::now())
+            .contains(&peer_address));
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn invalid_block_request_throttles_are_sanctioned_test() -> Result<()> {
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(Network::Alpha, 1).await?;
+        let peer_address = *state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_map
+            .keys()
+            .next()
+            .unwrap();
+        let policy = BlockServingPolicy {
+            max_blocks: 1,
+            max_bytes: 1 << 30,
+            window: Duration::from_secs(600),
+        };
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+
+        // A throttle without an outstanding block request is ignored
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestThrottled {
+                retry_after_in_secs: 60,
+                policy,
+            }),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock),
+                peer_broadcast_tx.subscribe(),
+                &mut peer_state,
+            )
+            .await?;
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(PeerSanctionReason::InvalidBlockRequestThrottle),
+            standing.latest_sanction
+        );
+        assert!(state_lock
+            .lock_guard()
+            .await
+            .net
+            .peers_pausing_block_requests(SystemTime::now())
+            .is_empty());
+        assert!(peer_state.peer_block_serving_policy.is_none());
+
+        // A pause beyond the serving window is cut short, and sanctioned
+        state_lock
+            .lock_guard_mut()
+            .await
+            .net
+            .clear_ip_standing_in_database(peer_address.ip())
+            .await;
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestThrottled {
+                retry_after_in_secs: u64::MAX,
+                policy,
+            }),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        peer_state.block_requested = true;
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock),
+                peer_broadcast_tx.subscribe(),
+                &mut peer_state,
+            )
+            .await?;
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(PeerSanctionReason::InvalidBlockRequestThrottle),
+            standing.latest_sanction
+        );
+        let now = SystemTime::now();
+        let global_state = state_lock.lock_guard().await;
+        assert!(global_state
+            .net
+            .peers_pausing_block_requests(now)
+            .contains(&peer_address));
+        assert!(global_state
+            .net
+            .peers_pausing_block_requests(now + policy.window)
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn repeated_bye_increases_dial_backoff_up_to_cap_test() -> Result<()> {
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(Network::Alpha, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+        assert_eq!(
+            Duration::ZERO,
+            state_lock
+                .lock_guard()
+                .await
+                .net
+                .next_dial_delay(peer_address)
+        );
+
+        let mut delays = vec![];
+        while delays.last() != Some(&DIAL_BACKOFF_CAP) {
+            assert!(delays.len() < 32, "Backoff must reach the cap");
+
+            let mock = Mock::new(vec![Action::Read(PeerMessage::Bye)]);
+            let peer_loop_handler = PeerLoopHandler::new(
+                to_main_tx.clone(),
+                state_lock.clone(),
+                peer_address,
+                hsd.clone(),
+                false,
+                1,
+            );
+            peer_loop_handler
+                .run_wrapper(mock, peer_broadcast_tx.subscribe())
+                .await?;
+
+            let global_state = state_lock.lock_guard().await;
+            assert!(!global_state.net.may_dial(peer_address, SystemTime::now()));
+            delays.push(global_state.net.next_dial_delay(peer_address));
+        }
+
+        assert_eq!(DIAL_BACKOFF_BASE, delays[0]);
+        assert!(delays.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // Another bye does not exceed the cap, and the peer may be redialed
+        // once the backoff expires
+        let mock = Mock::new(vec![Action::Read(PeerMessage::Bye)]);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        peer_loop_handler
+            .run_wrapper(mock, peer_broadcast_tx.subscribe())
+            .await?;
+        let global_state = state_lock.lock_guard().await;
+        assert_eq!(
+            DIAL_BACKOFF_CAP,
+            global_state.net.next_dial_delay(peer_address)
+        );
+        assert!(global_state
+            .net
+            .may_dial(peer_address, SystemTime::now() + DIAL_BACKOFF_CAP));
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_peer_loop_peer_list() -> Result<()> {
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, _hsd) =
+            get_test_genesis_setup(Network::Alpha, 2).await?;
+
+        let mut peer_infos = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_map
+            .clone()
+            .into_values()
+            .collect::<Vec<_>>();
+        peer_infos.sort_by_cached_key(|x| x.connected_address);
+        let (peer_address0, instance_id0) =
+            (peer_infos[0].connected_address, peer_infos[0].instance_id);
+        let (peer_address1, instance_id1) =
+            (peer_infos[1].connected_address, peer_infos[1].instance_id);
+
+        let (hsd2, sa2) = get_dummy_peer_connection_data_genesis(Network::Alpha, 2).await;
+        let expected_response = vec![
+            (peer_address0, instance_id0),
+            (peer_address1, instance_id1),
+            (sa2, hsd2.instance_id),
+        ];
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::PeerListRequest),
+            Action::Write(PeerMessage::PeerListResponse(expected_response)),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let from_main_rx_clone = peer_broadcast_tx.subscribe();
+
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), sa2, hsd2, true, 0);
+        peer_loop_handler
+            .run_wrapper(mock, from_main_rx_clone)
+            .await?;
+
+        assert_eq!(
+            2,
+            state_lock.lock_guard().await.net.peer_map.len(),
+            "peer map must have length 2 after saying goodbye to peer 2"
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn peer_list_response_is_capped_test() -> Result<()> {
+        let (
+            peer_broadcast_tx,
+            _from_main_rx_clone,
+            to_main_tx,
+            _to_main_rx1,
+            mut state_lock,
+            _hsd,
+        ) = get_test_genesis_setup(Network::Alpha, 2).await?;
+        let mut cli = state_lock.cli().clone();
+        cli.max_peer_list_response_length = 1;
+        state_lock.set_cli(cli).await;
+
+        let (hsd2, sa2) = get_dummy_peer_connection_data_genesis(Network::Alpha, 2).await;
+        let mut peer_infos = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_map
+            .clone()
+            .into_values()
+            .map(|peer_info| (peer_info.connected_address, peer_info.instance_id))
+            .chain([(sa2, hsd2.instance_id)])
+            .collect::<Vec<_>>();
+        peer_infos.sort_by_cached_key(|x| x.0);
+        let expected_response = vec![peer_infos[0]];
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::PeerListRequest),
+            Action::Write(PeerMessage::PeerListResponse(expected_response)),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let from_main_rx_clone = peer_broadcast_tx.subscribe();
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), sa2, hsd2, true, 0);
+        peer_loop_handler
+            .run_wrapper(mock, from_main_rx_clone)
+            .await?;
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn captured_peer_traffic_replays_identically_test() -> Result<()> {
+        let network = Network::RegTest;
+        let peer_address = get_dummy_socket_address(2);
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let genesis_header = state_lock
+            .lock_guard()
+            .await
+            .chain
+            .light_state()
+            .kernel
+            .header
+            .clone();
+        let script = vec![
+            Action::Read(PeerMessage::BlockNotificationRequest),
+            Action::Write(PeerMessage::BlockNotification((&genesis_header).into())),
+            Action::Read(PeerMessage::PeerListRequest),
+            Action::Write(PeerMessage::PeerListResponse(vec![(
+                peer_address,
+                hsd.instance_id,
+            )])),
+            Action::Read(PeerMessage::Bye),
+        ];
+
+        // Capture the scripted exchange
+        let data_dir = unit_test_data_directory(network)?;
+        let limits = CaptureLimits {
+            max_total_bytes: 1_000_000,
+            max_connection_bytes: 100_000,
+            max_raw_frame_bytes: 10_000,
+        };
+        let mut capture =
+            PeerTrafficCapture::new(&data_dir.peer_traffic_dir_path(), peer_address, limits)
+                .await?;
+        let transcript_path = capture.path().to_owned();
+        let writer_task = capture.take_writer_task().unwrap();
+        let peer = CapturingPeerStream::new(Mock::new(script), Some(capture));
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx,
+            state_lock.clone(),
+            peer_address,
+            hsd.clone(),
+            true,
+            1,
+        );
+        peer_loop_handler
+            .run_wrapper(peer, peer_broadcast_tx.subscribe())
+            .await?;
+        writer_task.await?;
+
+        let transcript = PeerTrafficCapture::read_transcript(&transcript_path)?;
+        assert_eq!(
+            vec![
+                TrafficDirection::Inbound,
+                TrafficDirection::Outbound,
+                TrafficDirection::Inbound,
+                TrafficDirection::Outbound,
+                TrafficDirection::Inbound,
+            ],
+            transcript
+                .iter()
+                .map(|captured_message| captured_message.direction)
+                .collect::<Vec<_>>()
+        );
+
+        // Replaying the transcript against a fresh node must produce the same
+        // responses, otherwise the mock peer returns an error.
+        let (
+            fresh_peer_broadcast_tx,
+            _fresh_from_main_rx_clone,
+            fresh_to_main_tx,
+            _fresh_to_main_rx1,
+            fresh_state_lock,
+            _,
+        ) = get_test_genesis_setup(network, 0).await?;
+        let replaying_peer_loop_handler = PeerLoopHandler::new(
+            fresh_to_main_tx,
+            fresh_state_lock,
+            peer_address,
+            hsd,
+            true,
+            1,
+        );
+        replaying_peer_loop_handler
+            .run_wrapper(
+                Mock::new(replay_actions(&transcript)?),
+                fresh_peer_broadcast_tx.subscribe(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn different_genesis_test() -> Result<()> {
+        let mut rng = thread_rng();
+        // In this scenario a peer provides another genesis block than what has been
+        // hardcoded. This should lead to the closing of the connection to this peer
+        // and a ban.
+        let network = Network::Alpha;
+        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+
+        // Although the database is empty, `get_latest_block` still returns the genesis block,
+        // since that block is hardcoded.
+        let mut different_genesis_block: Block = state_lock
+            .lock_guard()
+            .await
+            .chain
+            .archival_state()
+            .get_tip()
+            .await;
+        let mut nonce = different_genesis_block.kernel.header.nonce;
+        nonce[2].increment();
+        different_genesis_block.set_header_nonce(nonce);
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (block_1_with_different_genesis, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &different_genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let mock = Mock::new(vec![Action::Read(PeerMessage::Block(Box::new(
+            block_1_with_different_genesis.into(),
+        )))]);
+
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            true,
+            1,
+        );
+        let res = peer_loop_handler
+            .run_wrapper(mock, from_main_rx_clone)
+            .await;
+        assert!(
+            res.is_err(),
+            "run_wrapper must return failure when genesis is different"
+        );
+
+        // Verify that max peer height was sent
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive add of peer block max height"),
+        }
+
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive remove of peer block max height"),
+        }
+
+        // Verify that no futher message was sent to main loop
+        match to_main_rx1.try_recv() {
+            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => (),
+            _ => bail!("Block notification must not be sent for block with invalid PoW"),
+        };
+
+        drop(to_main_tx);
+
+        let peer_standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .get_peer_standing_from_database(peer_address.ip())
+            .await;
+        assert_eq!(-(u16::MAX as i32), peer_standing.standing);
+        assert_eq!(
+            PeerSanctionReason::DifferentGenesis,
+            peer_standing.latest_sanction.unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn block_without_valid_pow_test() -> Result<()> {
+        let mut rng = thread_rng();
+        // In this scenario, a block without a valid PoW is received. This block should be rejected
+        // by the peer loop and a notification should never reach the main loop.
+        let network = Network::Alpha;
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+        let genesis_block: Block = state_lock
+            .lock_guard()
+            .await
+            .chain
+            .archival_state()
+            .get_tip()
+            .await;
+
+        // Make a with hash above what the implied threshold from
+        // `target_difficulty` requires
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (block_without_valid_pow, _, _) = make_mock_block_with_invalid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+
+        // Sending an invalid block will not neccessarily result in a ban. This depends on the peer
+        // tolerance that is set in the client. For this reason, we include a "Bye" here.
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::Block(Box::new(block_without_valid_pow.into()))),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let from_main_rx_clone = peer_broadcast_tx.subscribe();
+
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            true,
+            1,
+        );
+        let res = peer_loop_handler
+            .run_wrapper(mock, from_main_rx_clone)
+            .await;
+        assert!(
+            res.is_err(),
+            "run_wrapper must return error when peer sends back block and is banned"
+        );
+
+        // Verify that max peer height was sent
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive add of peer block max height"),
+        }
+
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive remove of peer block max height"),
+        }
+
+        // Verify that no futher message was sent to main loop
+        match to_main_rx1.try_recv() {
+            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => (),
+            _ => bail!("Block notification must not be sent for block with invalid PoW"),
+        };
+
+        // We need to have the transmitter in scope until we have received from it
+        // otherwise the receiver will report the disconnected error when we attempt
+        // to read from it. And the purpose is to verify that the channel is empty,
+        // not that it has been closed.
+        drop(to_main_tx);
+
+        // Verify that peer standing was stored in database
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert!(
+            standing.standing < 0,
+            "Peer must be sanctioned for sending a bad block"
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn block_with_far_future_timestamp_is_sanctioned_test() -> Result<()> {
+        // A block with valid PoW, but timestamped far beyond the allowed clock
+        // drift, must be rejected and its sender sanctioned.
+        let network = Network::Alpha;
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+        let genesis_block: Block = state_lock
+            .lock_guard()
+            .await
+            .chain
+            .archival_state()
+            .get_tip()
+            .await;
+
+        let far_future = Timestamp::now() + Timestamp::days(2);
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (block_from_the_future, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            Some(far_future),
+            a_recipient_address,
+            thread_rng().gen(),
+        );
+
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::Block(Box::new(block_from_the_future.into()))),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            true,
+            1,
+        );
+        assert!(peer_loop_handler
+            .run_wrapper(mock, peer_broadcast_tx.subscribe())
+            .await
+            .is_err());
+
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive add of peer block max height"),
+        }
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive remove of peer block max height"),
+        }
+        match to_main_rx1.try_recv() {
+            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => (),
+            _ => bail!("Block notification must not be sent for block from the future"),
+        };
+        drop(to_main_tx);
+
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(PeerSanctionReason::InvalidBlockTimestamp(
+                BlockHeight::from(1u64)
+            )),
+            standing.latest_sanction
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sanitize_peer_list_test() {
+        let instance_id = 42u128;
+        let public_a: SocketAddr = "123.45.67.89:9798".parse().unwrap();
+        let public_b: SocketAddr = "[2a01:4f8::1]:9798".parse().unwrap();
+        let loopback: SocketAddr = "127.0.0.1:9798".parse().unwrap();
+        let private: SocketAddr = "192.168.0.17:9798".parse().unwrap();
+        let link_local: SocketAddr = "[fe80::1]:9798".parse().unwrap();
+        let mapped_private: SocketAddr = "[::ffff:10.0.0.1]:9798".parse().unwrap();
+        let poisoned_list = vec![
+            (public_a, instance_id),
+            (loopback, instance_id),
+            (public_a, instance_id),
+            (private, instance_id),
+            (link_local, instance_id),
+            (mapped_private, instance_id),
+            (public_b, instance_id),
+            (public_b, instance_id),
+        ];
+
+        assert_eq!(
+            vec![(public_a, instance_id), (public_b, instance_id)],
+            sanitize_peer_list(poisoned_list.clone(), 10, Network::Main)
+        );
+        assert_eq!(
+            vec![(public_a, instance_id)],
+            sanitize_peer_list(poisoned_list.clone(), 1, Network::Main)
+        );
+
+        // Local addresses are legitimate on test networks
+        assert_eq!(
+            vec![
+                (public_a, instance_id),
+                (loopback, instance_id),
+                (private, instance_id),
+                (link_local, instance_id),
+                (mapped_private, instance_id),
+                (public_b, instance_id),
+            ],
+            sanitize_peer_list(poisoned_list, 10, Network::RegTest)
+        );
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn oversized_peer_list_response_is_capped_and_sanctioned_test() -> Result<()> {
+        let network = Network::RegTest;
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+        let max_length = state_lock.cli().max_peer_list_response_length;
+
+        // Every address is reported twice, and there are more than allowed
+        let oversized_list = (0..2 * max_length)
+            .flat_map(|i| {
+                let address = get_dummy_socket_address(i as u8 + 1);
+                [(address, i as u128), (address, i as u128)]
+            })
+            .collect_vec();
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::PeerListResponse(oversized_list.clone())),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let from_main_rx_clone = peer_broadcast_tx.subscribe();
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            true,
+            1,
+        );
+        peer_loop_handler
+            .run_wrapper(mock, from_main_rx_clone)
+            .await?;
+
+        // Verify that only the capped, deduplicated list reached the main loop
+        let expected_peers = oversized_list
+            .into_iter()
+            .step_by(2)
+            .take(max_length)
+            .collect_vec();
+        loop {
+            match to_main_rx1.recv().await {
+                Some(PeerThreadToMain::PeerDiscoveryAnswer((peers, sender, distance))) => {
+                    assert_eq!(expected_peers, peers);
+                    assert_eq!(peer_address, sender);
+                    assert_eq!(2, distance);
+                    break;
+                }
+                Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
+                _ => bail!("Must receive peer discovery answer"),
+            }
+        }
+        drop(to_main_tx);
+
+        // Verify that peer standing was stored in database
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert!(
+            standing.standing < 0,
+            "Peer must be sanctioned for sending an oversized peer list"
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_peer_loop_block_with_block_in_db() -> Result<()> {
+        let mut rng = thread_rng();
+        // The scenario tested here is that a client receives a block that is already
+        // known and stored. The expected behavior is to ignore the block and not send
+        // a message to the main thread.
+        let network = Network::Alpha;
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let mut global_state_mut = state_lock.lock_guard_mut().await;
+        let peer_address = get_dummy_socket_address(0);
+        let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
+
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        global_state_mut.set_new_tip(block_1.clone()).await?;
+        drop(global_state_mut);
+
+        let mock_peer_messages = Mock::new(vec![
+            Action::Read(PeerMessage::Block(Box::new(block_1.into()))),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let from_main_rx_clone = peer_broadcast_tx.subscribe();
+
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            false,
+            1,
+        );
+        peer_loop_handler
+            .run_wrapper(mock_peer_messages, from_main_rx_clone)
+            .await?;
+
+        // Verify that no block was sent to main loop
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive add of peer block max height"),
+        }
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive remove of peer block max height"),
+        }
+        match to_main_rx1.try_recv() {
+            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => (),
+            _ => bail!("Block notification must not be sent for block with invalid PoW"),
+        };
+        drop(to_main_tx);
+
+        if !state_lock.lock_guard().await.net.peer_map.is_empty() {
+            bail!("peer map must be empty after closing connection gracefully");
+        }
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn own_mined_block_echoed_back_is_ignored_test() -> Result<()> {
+        let mut rng = thread_rng();
+        // Scenario: this node mines a block, and a peer sends it back after
+        // it was broadcast. The block must be recognized as already known.
+        let network = Network::RegTest;
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+        let mut global_state_mut = state_lock.lock_guard_mut().await;
+        let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
+        let own_spending_key = global_state_mut
+            .wallet_state
+            .wallet_secret
+            .nth_generation_spending_key(0);
+        let (block_1, coinbase_utxo, coinbase_output_randomness) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            own_spending_key.to_address(),
+            rng.gen(),
+        );
+        global_state_mut
+            .set_new_self_mined_tip(
+                block_1.clone(),
+                ExpectedUtxo::new(
+                    coinbase_utxo,
+                    coinbase_output_randomness,
+                    own_spending_key.privacy_preimage,
+                    UtxoNotifier::OwnMiner,
+                ),
+            )
+            .await?;
+        assert!(global_state_mut.net.is_recently_mined_block(block_1.hash()));
+        drop(global_state_mut);
+
+        let mock_peer_messages = Mock::new(vec![
+            Action::Read(PeerMessage::Block(Box::new(block_1.clone().into()))),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            false,
+            1,
+        );
+        peer_loop_handler
+            .run_wrapper(mock_peer_messages, peer_broadcast_tx.subscribe())
+            .await?;
+
+        // Verify that the block was not sent to the main loop
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive add of peer block max height"),
+        }
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive remove of peer block max height"),
+        }
+        match to_main_rx1.try_recv() {
+            Err(TryRecvError::Empty) => (),
+            _ => bail!("Block notification must not be sent for own mined block"),
+        };
+        assert!(logs_contain("mined by this node; already known"));
+        assert_eq!(
+            block_1.hash(),
+            state_lock.lock_guard().await.chain.light_state().hash()
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn block_request_batch_in_order_test() -> Result<()> {
+        // Scenario: A fork began at block 2, node knows two blocks of height 2 and two of height 3.
+        // A peer requests a batch of blocks starting from block 1. Ensure that the correct blocks
+        // are returned.
+
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let mut global_state_mut = state_lock.lock_guard_mut().await;
+        let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
+        let peer_address = get_dummy_socket_address(0);
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_2_a, _, _) =
+            make_mock_block_with_valid_pow(network, &block_1, None, a_recipient_address, rng.gen());
+        let (block_3_a, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2_a,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        ); // <--- canonical
+        let (block_2_b, _, _) =
+            make_mock_block_with_valid_pow(network, &block_1, None, a_recipient_address, rng.gen());
+        let (block_3_b, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2_b,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+
+        global_state_mut.set_new_tip(block_1.clone()).await?;
+        global_state_mut.set_new_tip(block_2_a.clone()).await?;
+        global_state_mut.set_new_tip(block_2_b.clone()).await?;
+        global_state_mut.set_new_tip(block_3_b.clone()).await?;
+        global_state_mut.set_new_tip(block_3_a.clone()).await?;
+
+        drop(global_state_mut);
+
+        let mut mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestBatch(
+                vec![genesis_block.hash()],
+                14,
+            )),
+            Action::Write(PeerMessage::BlockResponseBatch(vec![
+                block_1.clone().into(),
+                block_2_a.clone().into(),
+                block_3_a.clone().into(),
+            ])),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let peer_loop_handler_1 = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd.clone(),
+            false,
+            1,
+        );
+
+        peer_loop_handler_1
+            .run_wrapper(mock, from_main_rx_clone.resubscribe())
+            .await?;
+
+        // Peer knows block 2_b, verify that canonical chain with 2_a is returned
+        mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestBatch(
+                vec![block_2_b.hash(), block_1.hash(), genesis_block.hash()],
+                14,
+            )),
+            Action::Write(PeerMessage::BlockResponseBatch(vec![
+                block_2_a.into(),
+                block_3_a.into(),
+            ])),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let peer_loop_handler_2 = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            false,
+            1,
+        );
+
+        peer_loop_handler_2
+            .run_wrapper(mock, from_main_rx_clone)
+            .await?;
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn block_request_batch_out_of_order_test() -> Result<()> {
+        // Scenario: A fork began at block 2, node knows two blocks of height 2 and two of height 3.
+        // A peer requests a batch of blocks starting from block 1,  but the peer supplies their
+        // hashes in a wrong order. Ensure that the correct blocks are returned, in the right order.
+
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let mut global_state_mut = state_lock.lock_guard_mut().await;
+        let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
+        let peer_address = get_dummy_socket_address(0);
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_2_a, _, _) =
+            make_mock_block_with_valid_pow(network, &block_1, None, a_recipient_address, rng.gen());
+        let (block_3_a, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2_a,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        ); // <--- canonical
+        let (block_2_b, _, _) =
+            make_mock_block_with_valid_pow(network, &block_1, None, a_recipient_address, rng.gen());
+        let (block_3_b, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2_b,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+
+        global_state_mut.set_new_tip(block_1.clone()).await?;
+        global_state_mut.set_new_tip(block_2_a.clone()).await?;
+        global_state_mut.set_new_tip(block_2_b.clone()).await?;
+        global_state_mut.set_new_tip(block_3_b.clone()).await?;
+        global_state_mut.set_new_tip(block_3_a.clone()).await?;
+
+        drop(global_state_mut);
+
+        // Peer knows block 2_b, verify that canonical chain with 2_a is returned
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestBatch(
+                vec![block_2_b.hash(), genesis_block.hash(), block_1.hash()],
+                14,
+            )),
+            Action::Write(PeerMessage::BlockResponseBatch(vec![
+                block_2_a.into(),
+                block_3_a.into(),
+            ])),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let peer_loop_handler_2 = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            false,
+            1,
+        );
+
+        peer_loop_handler_2
+            .run_wrapper(mock, from_main_rx_clone)
+            .await?;
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn find_canonical_chain_when_multiple_blocks_at_same_height_test() -> Result<()> {
+        // Scenario: A fork began at block 2, node knows two blocks of height 2 and two of height 3.
+        // A peer requests a block at height 2. Verify that the correct block at height 2 is
+        // returned.
+
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let mut global_state_mut = state_lock.lock_guard_mut().await;
+        let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
+        let peer_address = get_dummy_socket_address(0);
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_2_a, _, _) =
+            make_mock_block_with_valid_pow(network, &block_1, None, a_recipient_address, rng.gen());
+        let (block_3_a, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2_a,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        ); // <--- canonical
+        let (block_2_b, _, _) =
+            make_mock_block_with_valid_pow(network, &block_1, None, a_recipient_address, rng.gen());
+        let (block_3_b, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2_b,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+
+        global_state_mut.set_new_tip(block_1.clone()).await?;
+        global_state_mut.set_new_tip(block_2_a.clone()).await?;
+        global_state_mut.set_new_tip(block_2_b.clone()).await?;
+        global_state_mut.set_new_tip(block_3_b.clone()).await?;
+        global_state_mut.set_new_tip(block_3_a.clone()).await?;
+
+        drop(global_state_mut);
+
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestByHeight(2.into())),
+            Action::Write(PeerMessage::Block(Box::new(block_2_a.into()))),
+            Action::Read(PeerMessage::BlockRequestByHeight(3.into())),
+            Action::Write(PeerMessage::Block(Box::new(block_3_a.into()))),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            false,
+            1,
+        );
+
+        // This will return error if seen read/write order does not match that of the
+        // mocked object.
+        peer_loop_handler
+            .run_wrapper(mock, from_main_rx_clone)
+            .await?;
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn block_request_batch_by_height_returns_canonical_chain_test() -> Result<()> {
+        // Scenario: A fork began at block 2, node knows two blocks of height 2 and two of height 3.
+        // A peer requests a range of blocks by height. Verify that only canonical blocks are
+        // returned, and that the number of returned blocks is clamped.
+
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, mut state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let mut global_state_mut = state_lock.lock_guard_mut().await;
+        let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
+        let peer_address = get_dummy_socket_address(0);
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_2_a, _, _) =
+            make_mock_block_with_valid_pow(network, &block_1, None, a_recipient_address, rng.gen());
+        let (block_3_a, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2_a,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        ); // <--- canonical
+        let (block_2_b, _, _) =
+            make_mock_block_with_valid_pow(network, &block_1, None, a_recipient_address, rng.gen());
+        let (block_3_b, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2_b,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+
+        global_state_mut.set_new_tip(block_1.clone()).await?;
+        global_state_mut.set_new_tip(block_2_a.clone()).await?;
+        global_state_mut.set_new_tip(block_2_b.clone()).await?;
+        global_state_mut.set_new_tip(block_3_b.clone()).await?;
+        global_state_mut.set_new_tip(block_3_a.clone()).await?;
+
+        drop(global_state_mut);
+
+        // Requests reaching beyond the tip are answered with the blocks up to the tip
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestBatchByHeight {
+                start_height: 2.into(),
+                count: 14,
+            }),
+            Action::Write(PeerMessage::BlockResponseBatch(vec![
+                block_2_a.clone().into(),
+                block_3_a.clone().into(),
+            ])),
+            Action::Read(PeerMessage::BlockRequestBatchByHeight {
+                start_height: 0.into(),
+                count: 2,
+            }),
+            Action::Write(PeerMessage::BlockResponseBatch(vec![
+                genesis_block.clone().into(),
+                block_1.clone().into(),
+            ])),
+            Action::Read(PeerMessage::Bye),
+        ]);
 
-    use super::*;
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd.clone(),
+            false,
+            1,
+        );
+        peer_loop_handler
+            .run_wrapper(mock, peer_broadcast_tx.subscribe())
+            .await?;
 
-    #[traced_test]
-    #[tokio::test]
-    async fn test_peer_loop_bye() -> Result<()> {
-        let mock = Mock::new(vec![Action::Read(PeerMessage::Bye)]);
+        // Requested count is clamped to the configured maximum
+        let mut cli = state_lock.cli().clone();
+        cli.max_block_batch_response_length = 2;
+        state_lock.set_cli(cli).await;
 
-        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
-            get_test_genesis_setup(Network::Alpha, 2).await?;
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestBatchByHeight {
+                start_height: 1.into(),
+                count: u16::MAX,
+            }),
+            Action::Write(PeerMessage::BlockResponseBatch(vec![
+                block_1.clone().into(),
+                block_2_a.into(),
+            ])),
+            Action::Read(PeerMessage::Bye),
+        ]);
 
-        let peer_address = get_dummy_socket_address(2);
-        let from_main_rx_clone = peer_broadcast_tx.subscribe();
-        let peer_loop_handler =
-            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, true, 1);
-        peer_loop_handler
-            .run_wrapper(mock, from_main_rx_clone)
+        let peer_loop_handler_2 = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd.clone(),
+            false,
+            1,
+        );
+        peer_loop_handler_2
+            .run_wrapper(mock, peer_broadcast_tx.subscribe())
             .await?;
 
-        assert_eq!(
-            2,
-            state_lock.lock_guard().await.net.peer_map.len(),
-            "peer map length must be back to 2 after goodbye"
+        // Responses are cut short by the size budget, but never below the
+        // minimum batch size
+        let mut size_budget_cli = state_lock.cli().clone();
+        size_budget_cli.max_block_batch_response_length = 50;
+        size_budget_cli.max_block_batch_response_size = bytesize::ByteSize::b(1);
+        state_lock.set_cli(size_budget_cli).await;
+
+        let size_budget_mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockRequestBatchByHeight {
+                start_height: 0.into(),
+                count: 4,
+            }),
+            Action::Write(PeerMessage::BlockResponseBatch(vec![
+                genesis_block.into(),
+                block_1.into(),
+            ])),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let peer_loop_handler_3 = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            false,
+            1,
         );
+        peer_loop_handler_3
+            .run_wrapper(size_budget_mock, peer_broadcast_tx.subscribe())
+            .await?;
 
         Ok(())
     }
 
     #[traced_test]
     #[tokio::test]
-    async fn test_peer_loop_peer_list() -> Result<()> {
-        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, _hsd) =
-            get_test_genesis_setup(Network::Alpha, 2).await?;
+    async fn block_response_batch_must_form_a_chain_test() -> Result<()> {
+        // Scenario: while syncing, a peer returns a batch of blocks in which a block does not
+        // build on its predecessor. The batch must be rejected and the peer sanctioned.
 
-        let mut peer_infos = state_lock
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let genesis_block: Block = state_lock
             .lock_guard()
             .await
-            .net
-            .peer_map
-            .clone()
-            .into_values()
-            .collect::<Vec<_>>();
-        peer_infos.sort_by_cached_key(|x| x.connected_address);
-        let (peer_address0, instance_id0) =
-            (peer_infos[0].connected_address, peer_infos[0].instance_id);
-        let (peer_address1, instance_id1) =
-            (peer_infos[1].connected_address, peer_infos[1].instance_id);
+            .chain
+            .archival_state()
+            .get_tip()
+            .await;
+        let peer_address = get_dummy_socket_address(0);
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_2, _, _) =
+            make_mock_block_with_valid_pow(network, &block_1, None, a_recipient_address, rng.gen());
+        let (block_3, _, _) =
+            make_mock_block_with_valid_pow(network, &block_2, None, a_recipient_address, rng.gen());
 
-        let (hsd2, sa2) = get_dummy_peer_connection_data_genesis(Network::Alpha, 2).await;
-        let expected_response = vec![
-            (peer_address0, instance_id0),
-            (peer_address1, instance_id1),
-            (sa2, hsd2.instance_id),
-        ];
+        state_lock.lock_guard_mut().await.net.syncing = true;
+
+        // Block 2 is missing from the batch
         let mock = Mock::new(vec![
-            Action::Read(PeerMessage::PeerListRequest),
-            Action::Write(PeerMessage::PeerListResponse(expected_response)),
+            Action::Read(PeerMessage::BlockResponseBatch(vec![
+                block_1.into(),
+                block_3.into(),
+            ])),
             Action::Read(PeerMessage::Bye),
         ]);
 
-        let from_main_rx_clone = peer_broadcast_tx.subscribe();
-
-        let peer_loop_handler =
-            PeerLoopHandler::new(to_main_tx, state_lock.clone(), sa2, hsd2, true, 0);
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            peer_address,
+            hsd,
+            false,
+            1,
+        );
         peer_loop_handler
-            .run_wrapper(mock, from_main_rx_clone)
+            .run_wrapper(mock, peer_broadcast_tx.subscribe())
             .await?;
 
+        let global_state = state_lock.lock_guard().await;
         assert_eq!(
-            2,
-            state_lock.lock_guard().await.net.peer_map.len(),
-            "peer map must have length 2 after saying goodbye to peer 2"
+            genesis_block.hash(),
+            global_state.chain.light_state().hash(),
+            "Disconnected batch must not change the tip"
+        );
+        let standing = global_state
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(PeerSanctionReason::BatchBlocksNotConnected),
+            standing.latest_sanction
         );
 
         Ok(())
@@ -1271,85 +4796,245 @@ mod peer_loop_tests {
 
     #[traced_test]
     #[tokio::test]
-    async fn different_genesis_test() -> Result<()> {
+    async fn block_batches_are_requested_by_height_test() -> Result<()> {
+        // Scenario: a peer serving blocks by height is asked for the blocks
+        // following our tip. Its chain forked off below our tip, so the
+        // returned blocks do not descend from it, and the blocks are requested
+        // by digest instead.
+
         let mut rng = thread_rng();
-        // In this scenario a peer provides another genesis block than what has been
-        // hardcoded. This should lead to the closing of the connection to this peer
-        // and a ban.
         let network = Network::Alpha;
-        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+        let (_peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
             get_test_genesis_setup(network, 0).await?;
-        let peer_address = get_dummy_socket_address(0);
-
-        // Although the database is empty, `get_latest_block` still returns the genesis block,
-        // since that block is hardcoded.
-        let mut different_genesis_block: Block = state_lock
+        assert!(hsd.features.contains(PeerFeatures::BLOCK_BATCH_BY_HEIGHT));
+        let genesis_block: Block = state_lock
             .lock_guard()
             .await
             .chain
             .archival_state()
             .get_tip()
             .await;
-        let mut nonce = different_genesis_block.kernel.header.nonce;
-        nonce[2].increment();
-        different_genesis_block.set_header_nonce(nonce);
-        let a_wallet_secret = WalletSecret::new_random();
-        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
-        let (block_1_with_different_genesis, _, _) = make_mock_block_with_valid_pow(
-            &different_genesis_block,
+        let peer_address = get_dummy_socket_address(0);
+        let a_recipient_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (block_1_a, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
             None,
             a_recipient_address,
             rng.gen(),
         );
-        let mock = Mock::new(vec![Action::Read(PeerMessage::Block(Box::new(
-            block_1_with_different_genesis.into(),
-        )))]);
-
-        let peer_loop_handler = PeerLoopHandler::new(
-            to_main_tx.clone(),
-            state_lock.clone(),
-            peer_address,
-            hsd,
-            true,
-            1,
+        let (block_1_b, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
         );
-        let res = peer_loop_handler
-            .run_wrapper(mock, from_main_rx_clone)
-            .await;
-        assert!(
-            res.is_err(),
-            "run_wrapper must return failure when genesis is different"
+        let (block_2_b, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1_b,
+            None,
+            a_recipient_address,
+            rng.gen(),
         );
-
-        // Verify that max peer height was sent
-        match to_main_rx1.recv().await {
-            Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
-            _ => bail!("Must receive add of peer block max height"),
+        let (block_3_b, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2_b,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        {
+            let mut global_state_mut = state_lock.lock_guard_mut().await;
+            global_state_mut.set_new_tip(block_1_a.clone()).await?;
+            global_state_mut.net.syncing = true;
         }
 
-        match to_main_rx1.recv().await {
-            Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
-            _ => bail!("Must receive remove of peer block max height"),
-        }
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(block_1_a.kernel.header.height);
+        let most_canonical_digests = vec![block_1_a.hash(), genesis_block.hash()];
+        let request_batch_size = cmp::min(
+            STANDARD_BLOCK_BATCH_SIZE,
+            state_lock.cli().max_number_of_blocks_before_syncing,
+        );
 
-        // Verify that no futher message was sent to main loop
-        match to_main_rx1.try_recv() {
-            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => (),
-            _ => bail!("Block notification must not be sent for block with invalid PoW"),
-        };
+        let mut mock = Mock::new(vec![Action::Write(
+            PeerMessage::BlockRequestBatchByHeight {
+                start_height: 2.into(),
+                count: request_batch_size as u16,
+            },
+        )]);
+        peer_loop_handler
+            .handle_main_thread_message(
+                MainToPeerThread::RequestBlockBatch(most_canonical_digests.clone(), peer_address),
+                &mut mock,
+                &mut peer_state,
+            )
+            .await?;
+        assert_eq!(1, peer_state.pending_block_batches.len());
 
-        drop(to_main_tx);
+        let mut mock = Mock::new(vec![Action::Write(PeerMessage::BlockRequestBatch(
+            most_canonical_digests,
+            request_batch_size,
+        ))]);
+        peer_loop_handler
+            .handle_peer_message(
+                PeerMessage::BlockResponseBatch(vec![block_2_b.into(), block_3_b.into()]),
+                &mut mock,
+                &mut peer_state,
+            )
+            .await?;
+        assert_eq!(1, peer_state.pending_block_batches.len());
+        assert!(peer_state.pending_block_batches[0].start_height.is_none());
 
-        let peer_standing = state_lock
+        let global_state = state_lock.lock_guard().await;
+        assert_eq!(block_1_a.hash(), global_state.chain.light_state().hash());
+        assert!(
+            global_state
+                .net
+                .peer_databases
+                .peer_standings
+                .get(peer_address.ip())
+                .await
+                .and_then(|standing| standing.latest_sanction)
+                .is_none(),
+            "A peer on another branch must not be sanctioned"
+        );
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn block_batch_window_downloads_several_batches_per_round_trip_test() -> Result<()> {
+        // Scenario: a peer serving blocks by height has a full window of
+        // batches more than us. All requests of the window are sent at once,
+        // so the blocks are downloaded within a single round trip to the peer
+        // instead of one round trip per batch. Batches that arrive before the
+        // main loop stored the batch they build on are held back until it did.
+
+        let mut rng = thread_rng();
+        let network = Network::Alpha;
+        let (_peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let mut cli = state_lock.cli().clone();
+        cli.max_number_of_blocks_before_syncing = MINIMUM_BLOCK_BATCH_SIZE;
+        state_lock.set_cli(cli).await;
+        let genesis_block: Block = state_lock
             .lock_guard()
             .await
-            .net
-            .get_peer_standing_from_database(peer_address.ip())
+            .chain
+            .archival_state()
+            .get_tip()
             .await;
-        assert_eq!(-(u16::MAX as i32), peer_standing.unwrap().standing);
+        let peer_address = get_dummy_socket_address(0);
+        let a_recipient_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let mut blocks: Vec<Block> = vec![];
+        let mut parent = genesis_block.clone();
+        for _ in 0..BLOCK_BATCH_WINDOW_SIZE * MINIMUM_BLOCK_BATCH_SIZE {
+            let (block, _, _) = make_mock_block_with_valid_pow(
+                network,
+                &parent,
+                None,
+                a_recipient_address,
+                rng.gen(),
+            );
+            blocks.push(block.clone());
+            parent = block;
+        }
+        state_lock.lock_guard_mut().await.net.syncing = true;
+
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(parent.kernel.header.height);
+
+        let mut mock = Mock::new(
+            (0..BLOCK_BATCH_WINDOW_SIZE)
+                .map(|i| {
+                    Action::Write(PeerMessage::BlockRequestBatchByHeight {
+                        start_height: (i * MINIMUM_BLOCK_BATCH_SIZE + 1).into(),
+                        count: MINIMUM_BLOCK_BATCH_SIZE as u16,
+                    })
+                })
+                .collect(),
+        );
+        peer_loop_handler
+            .handle_main_thread_message(
+                MainToPeerThread::RequestBlockBatch(vec![genesis_block.hash()], peer_address),
+                &mut mock,
+                &mut peer_state,
+            )
+            .await?;
         assert_eq!(
-            PeerSanctionReason::DifferentGenesis,
-            peer_standing.unwrap().latest_sanction.unwrap()
+            BLOCK_BATCH_WINDOW_SIZE,
+            peer_state.pending_block_batches.len()
+        );
+
+        // Requests of the main loop while the window is downloading are
+        // already covered by it
+        let mut mock_without_requests = Mock::new(vec![]);
+        peer_loop_handler
+            .handle_main_thread_message(
+                MainToPeerThread::RequestBlockBatch(vec![genesis_block.hash()], peer_address),
+                &mut mock_without_requests,
+                &mut peer_state,
+            )
+            .await?;
+
+        // All responses arrive before the main loop stored any block
+        let batches = blocks.chunks(MINIMUM_BLOCK_BATCH_SIZE).collect_vec();
+        for batch in batches.iter() {
+            let transfer_blocks = batch
+                .iter()
+                .map(|block| TransferBlock::from(block.clone()))
+                .collect_vec();
+            peer_loop_handler
+                .handle_peer_message(
+                    PeerMessage::BlockResponseBatch(transfer_blocks),
+                    &mut mock_without_requests,
+                    &mut peer_state,
+                )
+                .await?;
+        }
+        assert!(peer_state.pending_block_batches.is_empty());
+        assert_eq!(
+            BLOCK_BATCH_WINDOW_SIZE - 1,
+            peer_state.block_batches_awaiting_parent.len()
+        );
+
+        // Store the batches like the main loop does, which passes on the batch
+        // held back for it
+        for batch in batches {
+            match to_main_rx1.recv().await {
+                Some(PeerThreadToMain::NewBlocks(received_blocks)) => {
+                    assert_eq!(batch, received_blocks)
+                }
+                _ => bail!("Must receive the next batch of blocks"),
+            }
+            let batch_tip = batch[batch.len() - 1].clone();
+            {
+                let mut global_state_mut = state_lock.lock_guard_mut().await;
+                for block in batch {
+                    global_state_mut.set_new_tip(block.clone()).await?;
+                }
+            }
+            peer_loop_handler
+                .handle_main_thread_message(
+                    MainToPeerThread::Block(Box::new(batch_tip)),
+                    &mut mock_without_requests,
+                    &mut peer_state,
+                )
+                .await?;
+        }
+        assert!(peer_state.block_batches_awaiting_parent.is_empty());
+        assert_eq!(
+            parent.hash(),
+            state_lock.lock_guard().await.chain.light_state().hash()
         );
 
         Ok(())
@@ -1357,14 +5042,15 @@ mod peer_loop_tests {
 
     #[traced_test]
     #[tokio::test]
-    async fn block_without_valid_pow_test() -> Result<()> {
+    async fn headers_first_sync_downloads_only_the_winning_branch_test() -> Result<()> {
+        // Scenario: while syncing, a peer sends the headers of two branches
+        // that fork off genesis. Only the bodies of the branch with the most
+        // proof-of-work are accepted afterwards.
+
         let mut rng = thread_rng();
-        // In this scenario, a block without a valid PoW is received. This block should be rejected
-        // by the peer loop and a notification should never reach the main loop.
-        let network = Network::Alpha;
+        let network = Network::RegTest;
         let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
             get_test_genesis_setup(network, 0).await?;
-        let peer_address = get_dummy_socket_address(0);
         let genesis_block: Block = state_lock
             .lock_guard()
             .await
@@ -1372,63 +5058,157 @@ mod peer_loop_tests {
             .archival_state()
             .get_tip()
             .await;
+        let peer_address = get_dummy_socket_address(0);
+        let a_recipient_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let mut losing_branch: Vec<Block> = vec![];
+        let mut winning_branch: Vec<Block> = vec![];
+        for (branch, length) in [(&mut losing_branch, 2), (&mut winning_branch, 3)] {
+            for _ in 0..length {
+                let previous_block = branch.last().unwrap_or(&genesis_block);
+                let (block, _, _) = make_mock_block_with_valid_pow(
+                    network,
+                    previous_block,
+                    None,
+                    a_recipient_address,
+                    rng.gen(),
+                );
+                branch.push(block);
+            }
+        }
+        let headers_of = |branch: &[Block]| {
+            branch
+                .iter()
+                .map(|block| (block.kernel.header.clone(), block.kernel.body.mast_hash()))
+                .collect_vec()
+        };
+        let transfer_blocks_of = |branch: &[Block]| {
+            branch
+                .iter()
+                .map(|block| TransferBlock::from(block.clone()))
+                .collect_vec()
+        };
 
-        // Make a with hash above what the implied threshold from
-        // `target_difficulty` requires
-        let a_wallet_secret = WalletSecret::new_random();
-        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
-        let (block_without_valid_pow, _, _) =
-            make_mock_block_with_invalid_pow(&genesis_block, None, a_recipient_address, rng.gen());
-
-        // Sending an invalid block will not neccessarily result in a ban. This depends on the peer
-        // tolerance that is set in the client. For this reason, we include a "Bye" here.
-        let mock = Mock::new(vec![
-            Action::Read(PeerMessage::Block(Box::new(block_without_valid_pow.into()))),
-            Action::Read(PeerMessage::Bye),
-        ]);
-
-        let from_main_rx_clone = peer_broadcast_tx.subscribe();
-
+        state_lock.lock_guard_mut().await.net.syncing = true;
         let peer_loop_handler = PeerLoopHandler::new(
             to_main_tx.clone(),
             state_lock.clone(),
             peer_address,
             hsd,
-            true,
+            false,
             1,
         );
-        let res = peer_loop_handler
-            .run_wrapper(mock, from_main_rx_clone)
-            .await;
-        assert!(
-            res.is_err(),
-            "run_wrapper must return error when peer sends back block and is banned"
-        );
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
 
-        // Verify that max peer height was sent
+        // Headers are served from the canonical chain
+        let mut mock = Mock::new(vec![Action::Write(PeerMessage::BlockHeadersResponse(
+            headers_of(&[genesis_block.clone()]),
+        ))]);
+        peer_loop_handler
+            .handle_peer_message(
+                PeerMessage::BlockHeaderRequest(BlockHeight::genesis()..BlockHeight::from(10u64)),
+                &mut mock,
+                &mut peer_state,
+            )
+            .await?;
+
+        // The response may overlap with known blocks
+        let mut mock_without_requests = Mock::new(vec![]);
+        for headers in [
+            [
+                headers_of(&[genesis_block.clone()]),
+                headers_of(&losing_branch),
+            ]
+            .concat(),
+            headers_of(&winning_branch),
+        ] {
+            peer_state
+                .pending_header_requests
+                .push_back(PendingHeaderRequest::HeaderChain);
+            peer_loop_handler
+                .handle_peer_message(
+                    PeerMessage::BlockHeadersResponse(headers),
+                    &mut mock_without_requests,
+                    &mut peer_state,
+                )
+                .await?;
+        }
+
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockResponseBatch(transfer_blocks_of(
+                &losing_branch,
+            ))),
+            Action::Read(PeerMessage::BlockResponseBatch(transfer_blocks_of(
+                &winning_branch,
+            ))),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        peer_loop_handler
+            .run_wrapper(mock, peer_broadcast_tx.subscribe())
+            .await?;
+
+        {
+            let global_state = state_lock.lock_guard().await;
+            assert_eq!(5, global_state.net.header_chain.len());
+            assert_eq!(
+                winning_branch.iter().map(Block::hash).collect_vec(),
+                global_state.net.header_chain.best_chain()
+            );
+            let standing = global_state
+                .net
+                .peer_databases
+                .peer_standings
+                .get(peer_address.ip())
+                .await
+                .unwrap();
+            assert_eq!(
+                Some(PeerSanctionReason::BatchBlocksNotInHeaderChain),
+                standing.latest_sanction
+            );
+        }
+
+        // Only the winning branch is passed on to the main loop
         match to_main_rx1.recv().await {
             Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
             _ => bail!("Must receive add of peer block max height"),
         }
-
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::NewBlocks(blocks)) => assert_eq!(winning_branch, blocks),
+            _ => bail!("Must receive the blocks of the winning branch"),
+        }
         match to_main_rx1.recv().await {
             Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
             _ => bail!("Must receive remove of peer block max height"),
         }
 
-        // Verify that no futher message was sent to main loop
-        match to_main_rx1.try_recv() {
-            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => (),
-            _ => bail!("Block notification must not be sent for block with invalid PoW"),
-        };
+        Ok(())
+    }
 
-        // We need to have the transmitter in scope until we have received from it
-        // otherwise the receiver will report the disconnected error when we attempt
-        // to read from it. And the purpose is to verify that the channel is empty,
-        // not that it has been closed.
-        drop(to_main_tx);
+    #[traced_test]
+    #[tokio::test]
+    async fn unrequested_block_headers_are_sanctioned_test() -> Result<()> {
+        let network = Network::RegTest;
+        let (_peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let genesis_block = Block::genesis_block(network);
+        let peer_address = get_dummy_socket_address(0);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+
+        let mut mock = Mock::new(vec![]);
+        peer_loop_handler
+            .handle_peer_message(
+                PeerMessage::BlockHeadersResponse(vec![(
+                    genesis_block.kernel.header.clone(),
+                    genesis_block.kernel.body.mast_hash(),
+                )]),
+                &mut mock,
+                &mut peer_state,
+            )
+            .await?;
 
-        // Verify that peer standing was stored in database
         let standing = state_lock
             .lock_guard()
             .await
@@ -1438,9 +5218,9 @@ mod peer_loop_tests {
             .get(peer_address.ip())
             .await
             .unwrap();
-        assert!(
-            standing.standing < 0,
-            "Peer must be sanctioned for sending a bad block"
+        assert_eq!(
+            Some(PeerSanctionReason::UnrequestedMessage),
+            standing.latest_sanction
         );
 
         Ok(())
@@ -1448,32 +5228,35 @@ mod peer_loop_tests {
 
     #[traced_test]
     #[tokio::test]
-    async fn test_peer_loop_block_with_block_in_db() -> Result<()> {
+    async fn test_peer_loop_receival_of_first_block() -> Result<()> {
+        let network = Network::RegTest;
         let mut rng = thread_rng();
-        // The scenario tested here is that a client receives a block that is already
-        // known and stored. The expected behavior is to ignore the block and not send
-        // a message to the main thread.
-        let network = Network::Alpha;
-        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+        // Scenario: client only knows genesis block. Then receives block 1.
+        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
             get_test_genesis_setup(network, 0).await?;
-        let mut global_state_mut = state_lock.lock_guard_mut().await;
-        let peer_address = get_dummy_socket_address(0);
-        let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
-
         let a_wallet_secret = WalletSecret::new_random();
         let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
-        let (block_1, _, _) =
-            make_mock_block_with_valid_pow(&genesis_block, None, a_recipient_address, rng.gen());
-        global_state_mut.set_new_tip(block_1.clone()).await?;
-        drop(global_state_mut);
+        let peer_address = get_dummy_socket_address(0);
+        let genesis_block: Block = state_lock
+            .lock_guard()
+            .await
+            .chain
+            .archival_state()
+            .get_tip()
+            .await;
 
-        let mock_peer_messages = Mock::new(vec![
-            Action::Read(PeerMessage::Block(Box::new(block_1.into()))),
+        let (mock_block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::Block(Box::new(mock_block_1.into()))),
             Action::Read(PeerMessage::Bye),
         ]);
 
-        let from_main_rx_clone = peer_broadcast_tx.subscribe();
-
         let peer_loop_handler = PeerLoopHandler::new(
             to_main_tx.clone(),
             state_lock.clone(),
@@ -1483,23 +5266,25 @@ mod peer_loop_tests {
             1,
         );
         peer_loop_handler
-            .run_wrapper(mock_peer_messages, from_main_rx_clone)
+            .run_wrapper(mock, from_main_rx_clone)
             .await?;
 
-        // Verify that no block was sent to main loop
+        // Verify that peer max block height was sent
         match to_main_rx1.recv().await {
             Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
             _ => bail!("Must receive add of peer block max height"),
         }
+
+        // Verify that a block was sent to `main_loop`
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::NewBlocks(_block)) => (),
+            _ => bail!("Did not find msg sent to main thread"),
+        };
+
         match to_main_rx1.recv().await {
             Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
             _ => bail!("Must receive remove of peer block max height"),
         }
-        match to_main_rx1.try_recv() {
-            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => (),
-            _ => bail!("Block notification must not be sent for block with invalid PoW"),
-        };
-        drop(to_main_tx);
 
         if !state_lock.lock_guard().await.net.peer_map.is_empty() {
             bail!("peer map must be empty after closing connection gracefully");
@@ -1510,53 +5295,31 @@ mod peer_loop_tests {
 
     #[traced_test]
     #[tokio::test]
-    async fn block_request_batch_in_order_test() -> Result<()> {
-        // Scenario: A fork began at block 2, node knows two blocks of height 2 and two of height 3.
-        // A peer requests a batch of blocks starting from block 1. Ensure that the correct blocks
-        // are returned.
-
+    async fn blocks_are_deferred_while_quiesced_test() -> Result<()> {
+        // Scenario: client only knows genesis block, and is quiesced. Block 1
+        // is ignored until the client resumes.
+        let network = Network::RegTest;
         let mut rng = thread_rng();
-        let network = Network::Alpha;
-        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+        let (peer_broadcast_tx, from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
             get_test_genesis_setup(network, 0).await?;
-        let mut global_state_mut = state_lock.lock_guard_mut().await;
-        let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
-        let peer_address = get_dummy_socket_address(0);
         let a_wallet_secret = WalletSecret::new_random();
         let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
-        let (block_1, _, _) =
-            make_mock_block_with_valid_pow(&genesis_block, None, a_recipient_address, rng.gen());
-        let (block_2_a, _, _) =
-            make_mock_block_with_valid_pow(&block_1, None, a_recipient_address, rng.gen());
-        let (block_3_a, _, _) =
-            make_mock_block_with_valid_pow(&block_2_a, None, a_recipient_address, rng.gen()); // <--- canonical
-        let (block_2_b, _, _) =
-            make_mock_block_with_valid_pow(&block_1, None, a_recipient_address, rng.gen());
-        let (block_3_b, _, _) =
-            make_mock_block_with_valid_pow(&block_2_b, None, a_recipient_address, rng.gen());
-
-        global_state_mut.set_new_tip(block_1.clone()).await?;
-        global_state_mut.set_new_tip(block_2_a.clone()).await?;
-        global_state_mut.set_new_tip(block_2_b.clone()).await?;
-        global_state_mut.set_new_tip(block_3_b.clone()).await?;
-        global_state_mut.set_new_tip(block_3_a.clone()).await?;
-
-        drop(global_state_mut);
+        let peer_address = get_dummy_socket_address(0);
+        let genesis_block = Block::genesis_block(network);
+        let (mock_block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
 
-        let mut mock = Mock::new(vec![
-            Action::Read(PeerMessage::BlockRequestBatch(
-                vec![genesis_block.hash()],
-                14,
-            )),
-            Action::Write(PeerMessage::BlockResponseBatch(vec![
-                block_1.clone().into(),
-                block_2_a.clone().into(),
-                block_3_a.clone().into(),
-            ])),
+        state_lock.lock_mut(|s| s.net.quiesced = true).await;
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::Block(Box::new(mock_block_1.clone().into()))),
             Action::Read(PeerMessage::Bye),
         ]);
-
-        let peer_loop_handler_1 = PeerLoopHandler::new(
+        let peer_loop_handler = PeerLoopHandler::new(
             to_main_tx.clone(),
             state_lock.clone(),
             peer_address,
@@ -1564,25 +5327,25 @@ mod peer_loop_tests {
             false,
             1,
         );
-
-        peer_loop_handler_1
-            .run_wrapper(mock, from_main_rx_clone.resubscribe())
+        peer_loop_handler
+            .run_wrapper(mock, from_main_rx_clone)
             .await?;
 
-        // Peer knows block 2_b, verify that canonical chain with 2_a is returned
-        mock = Mock::new(vec![
-            Action::Read(PeerMessage::BlockRequestBatch(
-                vec![block_2_b.hash(), block_1.hash(), genesis_block.hash()],
-                14,
-            )),
-            Action::Write(PeerMessage::BlockResponseBatch(vec![
-                block_2_a.into(),
-                block_3_a.into(),
-            ])),
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive add of peer block max height"),
+        }
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
+            _ => bail!("Block must not be sent to main thread while quiesced"),
+        }
+
+        assert!(state_lock.lock_mut(|s| s.resume()).await);
+        let resumed_mock = Mock::new(vec![
+            Action::Read(PeerMessage::Block(Box::new(mock_block_1.into()))),
             Action::Read(PeerMessage::Bye),
         ]);
-
-        let peer_loop_handler_2 = PeerLoopHandler::new(
+        let resumed_peer_loop_handler = PeerLoopHandler::new(
             to_main_tx.clone(),
             state_lock.clone(),
             peer_address,
@@ -1590,197 +5353,511 @@ mod peer_loop_tests {
             false,
             1,
         );
+        resumed_peer_loop_handler
+            .run_wrapper(resumed_mock, peer_broadcast_tx.subscribe())
+            .await?;
+
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive add of peer block max height"),
+        }
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::NewBlocks(_block)) => (),
+            _ => bail!("Block must be sent to main thread after resuming"),
+        };
+
+        Ok(())
+    }
+
+    /// Make a block 1 whose transaction merges a coinbase transaction with
+    /// `transaction_count` other transactions. Returns the block, the coinbase
+    /// transaction, and the other transactions, in the order they were merged.
+    fn make_block_1_with_mempool_transactions(
+        network: Network,
+        genesis_block: &Block,
+        transaction_count: usize,
+    ) -> Result<(Block, Transaction, Vec<Transaction>)> {
+        let mut rng = thread_rng();
+        let a_wallet_secret = WalletSecret::new_random();
+        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
+
+        // Take the coinbase transaction from a mock block, and merge it with
+        // the other transactions.
+        let now = Timestamp::now();
+        let (mock_block_1, _, _) = make_mock_block(
+            network,
+            genesis_block,
+            Some(now),
+            a_recipient_address,
+            rng.gen(),
+        );
+        let coinbase = mock_block_1.kernel.body.transaction.clone();
+        let mempool_transactions = (0..transaction_count)
+            .map(|_| {
+                let mut transaction =
+                    make_mock_transaction(vec![], vec![commit(rng.gen(), rng.gen(), rng.gen())]);
+                transaction.kernel.timestamp = now;
+                transaction.kernel.mutator_set_hash =
+                    genesis_block.kernel.body.mutator_set_accumulator.hash();
+                transaction
+            })
+            .collect_vec();
+
+        let merged_transaction = mempool_transactions
+            .iter()
+            .cloned()
+            .fold(coinbase.clone(), Transaction::merge_with);
+        let mut block_1 = Block::new(
+            mock_block_1.kernel.header.clone(),
+            make_block_body(genesis_block, merged_transaction)?,
+            Block::mk_std_block_type(None),
+        );
+        while !block_1.has_proof_of_work(genesis_block) {
+            block_1.set_header_nonce(rng.gen());
+        }
+
+        Ok((block_1, coinbase, mempool_transactions))
+    }
+
+    /// Run a peer loop on the given actions, with a client that only knows
+    /// the genesis block and holds `known_transactions` in its mempool, and
+    /// return the blocks that it sends to `main_loop`.
+    async fn blocks_from_compact_block_exchange(
+        known_transactions: &[Transaction],
+        actions: Vec<Action<PeerMessage>>,
+    ) -> Result<Vec<Block>> {
+        let network = Network::RegTest;
+        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        for transaction in known_transactions {
+            state_lock
+                .lock_guard_mut()
+                .await
+                .mempool
+                .insert(transaction)
+                .unwrap();
+        }
+
+        let mock = Mock::new(actions);
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx.clone(),
+            state_lock.clone(),
+            get_dummy_socket_address(0),
+            hsd,
+            false,
+            1,
+        );
+        peer_loop_handler
+            .run_wrapper(mock, from_main_rx_clone)
+            .await?;
+
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive add of peer block max height"),
+        }
+
+        let blocks = match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::NewBlocks(blocks)) => blocks,
+            _ => bail!("Did not find msg sent to main thread"),
+        };
+
+        match to_main_rx1.recv().await {
+            Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
+            _ => bail!("Must receive remove of peer block max height"),
+        }
 
-        peer_loop_handler_2
-            .run_wrapper(mock, from_main_rx_clone)
-            .await?;
+        Ok(blocks)
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn compact_block_is_reconstructed_from_mempool_test() -> Result<()> {
+        // Scenario: client only knows genesis block, and holds all mempool
+        // transactions of block 1. Then receives block 1 as a compact block,
+        // which it rebuilds without requesting anything from the peer.
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let (block_1, coinbase, mempool_transactions) =
+            make_block_1_with_mempool_transactions(network, &genesis_block, 2)?;
+
+        // Any request for transactions would not match the mocked stream
+        let blocks = blocks_from_compact_block_exchange(
+            &mempool_transactions,
+            vec![
+                Action::Read(PeerMessage::CompactBlock {
+                    digest: block_1.hash(),
+                    header: block_1.kernel.header.clone(),
+                    txids: mempool_transactions.iter().map(Hash::hash).collect(),
+                    coinbase: Box::new(coinbase.clone()),
+                }),
+                Action::Read(PeerMessage::Bye),
+            ],
+        )
+        .await?;
+
+        // Verify that the reconstructed block was sent to `main_loop`
+        assert_eq!(1, blocks.len());
+        assert_eq!(block_1.hash(), blocks[0].hash());
 
         Ok(())
     }
 
     #[traced_test]
     #[tokio::test]
-    async fn block_request_batch_out_of_order_test() -> Result<()> {
-        // Scenario: A fork began at block 2, node knows two blocks of height 2 and two of height 3.
-        // A peer requests a batch of blocks starting from block 1,  but the peer supplies their
-        // hashes in a wrong order. Ensure that the correct blocks are returned, in the right order.
+    async fn compact_block_missing_transactions_are_requested_test() -> Result<()> {
+        // Scenario: client only knows genesis block, and holds one of the two
+        // mempool transactions of block 1. Only the other one is requested.
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let (block_1, coinbase, mempool_transactions) =
+            make_block_1_with_mempool_transactions(network, &genesis_block, 2)?;
+        let missing_transaction = mempool_transactions[1].clone();
+
+        let blocks = blocks_from_compact_block_exchange(
+            &mempool_transactions[..1],
+            vec![
+                Action::Read(PeerMessage::CompactBlock {
+                    digest: block_1.hash(),
+                    header: block_1.kernel.header.clone(),
+                    txids: mempool_transactions.iter().map(Hash::hash).collect(),
+                    coinbase: Box::new(coinbase.clone()),
+                }),
+                Action::Write(PeerMessage::GetBlockTransactions(vec![Hash::hash(
+                    &missing_transaction,
+                )])),
+                Action::Read(PeerMessage::BlockTransactions(vec![missing_transaction])),
+                Action::Read(PeerMessage::Bye),
+            ],
+        )
+        .await?;
 
-        let mut rng = thread_rng();
-        let network = Network::Alpha;
-        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
-            get_test_genesis_setup(network, 0).await?;
-        let mut global_state_mut = state_lock.lock_guard_mut().await;
-        let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
-        let peer_address = get_dummy_socket_address(0);
-        let a_wallet_secret = WalletSecret::new_random();
-        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
-        let (block_1, _, _) =
-            make_mock_block_with_valid_pow(&genesis_block, None, a_recipient_address, rng.gen());
-        let (block_2_a, _, _) =
-            make_mock_block_with_valid_pow(&block_1, None, a_recipient_address, rng.gen());
-        let (block_3_a, _, _) =
-            make_mock_block_with_valid_pow(&block_2_a, None, a_recipient_address, rng.gen()); // <--- canonical
-        let (block_2_b, _, _) =
-            make_mock_block_with_valid_pow(&block_1, None, a_recipient_address, rng.gen());
-        let (block_3_b, _, _) =
-            make_mock_block_with_valid_pow(&block_2_b, None, a_recipient_address, rng.gen());
+        assert_eq!(1, blocks.len());
+        assert_eq!(block_1.hash(), blocks[0].hash());
 
-        global_state_mut.set_new_tip(block_1.clone()).await?;
-        global_state_mut.set_new_tip(block_2_a.clone()).await?;
-        global_state_mut.set_new_tip(block_2_b.clone()).await?;
-        global_state_mut.set_new_tip(block_3_b.clone()).await?;
-        global_state_mut.set_new_tip(block_3_a.clone()).await?;
+        Ok(())
+    }
 
-        drop(global_state_mut);
+    #[traced_test]
+    #[tokio::test]
+    async fn unreconstructable_compact_block_falls_back_to_full_block_test() -> Result<()> {
+        // Scenario: client only knows genesis block, and holds none of the
+        // mempool transactions of block 1. The peer does not provide them
+        // either, so the client requests the full block instead. The peer is
+        // punished, since it announced the block it mined as a compact block.
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let (block_1, coinbase, mempool_transactions) =
+            make_block_1_with_mempool_transactions(network, &genesis_block, 2)?;
+
+        let blocks = blocks_from_compact_block_exchange(
+            &[],
+            vec![
+                Action::Read(PeerMessage::CompactBlock {
+                    digest: block_1.hash(),
+                    header: block_1.kernel.header.clone(),
+                    txids: mempool_transactions.iter().map(Hash::hash).collect(),
+                    coinbase: Box::new(coinbase.clone()),
+                }),
+                Action::Write(PeerMessage::GetBlockTransactions(
+                    mempool_transactions.iter().map(Hash::hash).collect(),
+                )),
+                Action::Read(PeerMessage::BlockTransactions(vec![])),
+                Action::Write(PeerMessage::BlockRequestByHash(block_1.hash())),
+                Action::Read(PeerMessage::Block(Box::new(block_1.clone().into()))),
+                Action::Read(PeerMessage::Bye),
+            ],
+        )
+        .await?;
 
-        // Peer knows block 2_b, verify that canonical chain with 2_a is returned
-        let mock = Mock::new(vec![
-            Action::Read(PeerMessage::BlockRequestBatch(
-                vec![block_2_b.hash(), genesis_block.hash(), block_1.hash()],
-                14,
-            )),
-            Action::Write(PeerMessage::BlockResponseBatch(vec![
-                block_2_a.into(),
-                block_3_a.into(),
-            ])),
-            Action::Read(PeerMessage::Bye),
-        ]);
+        assert_eq!(1, blocks.len());
+        assert_eq!(block_1.hash(), blocks[0].hash());
 
-        let peer_loop_handler_2 = PeerLoopHandler::new(
-            to_main_tx.clone(),
-            state_lock.clone(),
-            peer_address,
-            hsd,
-            false,
-            1,
-        );
+        Ok(())
+    }
 
-        peer_loop_handler_2
-            .run_wrapper(mock, from_main_rx_clone)
+    #[traced_test]
+    #[tokio::test]
+    async fn too_long_block_transactions_request_is_punished_test() -> Result<()> {
+        let network = Network::RegTest;
+        let (_peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+
+        // Nothing is sent in response to a request that is too long
+        let mut rng = thread_rng();
+        let txids = (0..=MAX_BLOCK_TRANSACTIONS_REQUEST_LENGTH)
+            .map(|_| rng.gen())
+            .collect_vec();
+        let mut mock = Mock::new(vec![]);
+        peer_loop_handler
+            .handle_peer_message(
+                PeerMessage::GetBlockTransactions(txids),
+                &mut mock,
+                &mut peer_state,
+            )
             .await?;
 
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(PeerSanctionReason::TooLongBlockTransactionsRequest),
+            standing.latest_sanction
+        );
+
         Ok(())
     }
 
     #[traced_test]
     #[tokio::test]
-    async fn find_canonical_chain_when_multiple_blocks_at_same_height_test() -> Result<()> {
-        // Scenario: A fork began at block 2, node knows two blocks of height 2 and two of height 3.
-        // A peer requests a block at height 2. Verify that the correct block at height 2 is
-        // returned.
-
-        let mut rng = thread_rng();
-        let network = Network::Alpha;
-        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+    async fn blocks_not_matching_their_digest_are_sanctioned_test() -> Result<()> {
+        let network = Network::RegTest;
+        let genesis_block = Block::genesis_block(network);
+        let (block_1, coinbase, mempool_transactions) =
+            make_block_1_with_mempool_transactions(network, &genesis_block, 1)?;
+        let (_peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
             get_test_genesis_setup(network, 0).await?;
-        let mut global_state_mut = state_lock.lock_guard_mut().await;
-        let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
+        state_lock
+            .lock_guard_mut()
+            .await
+            .mempool
+            .insert(&mempool_transactions[0])
+            .unwrap();
         let peer_address = get_dummy_socket_address(0);
-        let a_wallet_secret = WalletSecret::new_random();
-        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
-        let (block_1, _, _) =
-            make_mock_block_with_valid_pow(&genesis_block, None, a_recipient_address, rng.gen());
-        let (block_2_a, _, _) =
-            make_mock_block_with_valid_pow(&block_1, None, a_recipient_address, rng.gen());
-        let (block_3_a, _, _) =
-            make_mock_block_with_valid_pow(&block_2_a, None, a_recipient_address, rng.gen()); // <--- canonical
-        let (block_2_b, _, _) =
-            make_mock_block_with_valid_pow(&block_1, None, a_recipient_address, rng.gen());
-        let (block_3_b, _, _) =
-            make_mock_block_with_valid_pow(&block_2_b, None, a_recipient_address, rng.gen());
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+        let mut rng = thread_rng();
+        let mismatch = PeerSanctionReason::BlockDigestMismatch(block_1.kernel.header.height);
 
-        global_state_mut.set_new_tip(block_1.clone()).await?;
-        global_state_mut.set_new_tip(block_2_a.clone()).await?;
-        global_state_mut.set_new_tip(block_2_b.clone()).await?;
-        global_state_mut.set_new_tip(block_3_b.clone()).await?;
-        global_state_mut.set_new_tip(block_3_a.clone()).await?;
+        // A compact block that does not rebuild to the announced block
+        let mut mock = Mock::new(vec![]);
+        peer_loop_handler
+            .handle_peer_message(
+                PeerMessage::CompactBlock {
+                    digest: rng.gen(),
+                    header: block_1.kernel.header.clone(),
+                    txids: mempool_transactions.iter().map(Hash::hash).collect(),
+                    coinbase: Box::new(coinbase),
+                },
+                &mut mock,
+                &mut peer_state,
+            )
+            .await?;
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(Some(mismatch), standing.latest_sanction);
+        assert_eq!(-i32::from(mismatch.to_severity()), standing.standing);
 
-        drop(global_state_mut);
+        // A block other than the one requested by hash
+        peer_state.block_requested = true;
+        peer_state.requested_block_digest = Some(rng.gen());
+        peer_loop_handler
+            .handle_peer_message(
+                PeerMessage::Block(Box::new(block_1.into())),
+                &mut mock,
+                &mut peer_state,
+            )
+            .await?;
+        let standing_after_block = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(
+            -2 * i32::from(mismatch.to_severity()),
+            standing_after_block.standing
+        );
+        assert!(peer_state.requested_block_digest.is_none());
 
-        let mock = Mock::new(vec![
-            Action::Read(PeerMessage::BlockRequestByHeight(2.into())),
-            Action::Write(PeerMessage::Block(Box::new(block_2_a.into()))),
-            Action::Read(PeerMessage::BlockRequestByHeight(3.into())),
-            Action::Write(PeerMessage::Block(Box::new(block_3_a.into()))),
-            Action::Read(PeerMessage::Bye),
-        ]);
+        // Neither block was passed on to the main loop
+        assert!(matches!(to_main_rx1.try_recv(), Err(TryRecvError::Empty)));
 
-        let peer_loop_handler = PeerLoopHandler::new(
-            to_main_tx.clone(),
-            state_lock.clone(),
-            peer_address,
-            hsd,
-            false,
-            1,
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn frequent_mempool_inventory_requests_are_limited_test() -> Result<()> {
+        let network = Network::RegTest;
+        let (_peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+
+        // The first request is answered, the second one is sanctioned
+        let mut mock = Mock::new(vec![Action::Write(PeerMessage::MempoolInventoryResponse(
+            vec![],
+        ))]);
+        peer_loop_handler
+            .handle_peer_message(
+                PeerMessage::MempoolInventoryRequest,
+                &mut mock,
+                &mut peer_state,
+            )
+            .await?;
+        let mut mock_without_answer = Mock::new(vec![]);
+        peer_loop_handler
+            .handle_peer_message(
+                PeerMessage::MempoolInventoryRequest,
+                &mut mock_without_answer,
+                &mut peer_state,
+            )
+            .await?;
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(PeerSanctionReason::FloodMempoolInventoryRequest),
+            standing.latest_sanction
         );
 
-        // This will return error if seen read/write order does not match that of the
-        // mocked object.
+        // Nor is this node sending requests more often
+        let mut mock = Mock::new(vec![Action::Write(PeerMessage::MempoolInventoryRequest)]);
         peer_loop_handler
-            .run_wrapper(mock, from_main_rx_clone)
+            .handle_main_thread_message(
+                MainToPeerThread::RequestMempoolInventory(peer_address),
+                &mut mock,
+                &mut peer_state,
+            )
+            .await?;
+        peer_state.mempool_inventory_requested = false;
+        peer_loop_handler
+            .handle_main_thread_message(
+                MainToPeerThread::RequestMempoolInventory(peer_address),
+                &mut mock_without_answer,
+                &mut peer_state,
+            )
             .await?;
+        assert!(!peer_state.mempool_inventory_requested);
 
         Ok(())
     }
 
     #[traced_test]
     #[tokio::test]
-    async fn test_peer_loop_receival_of_first_block() -> Result<()> {
+    async fn unconfirmable_transaction_is_orphan_only_if_peer_is_ahead_test() -> Result<()> {
         let network = Network::RegTest;
-        let mut rng = thread_rng();
-        // Scenario: client only knows genesis block. Then receives block 1.
-        let (_peer_broadcast_tx, from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+        let (_peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
             get_test_genesis_setup(network, 0).await?;
-        let a_wallet_secret = WalletSecret::new_random();
-        let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
         let peer_address = get_dummy_socket_address(0);
-        let genesis_block: Block = state_lock
-            .lock_guard()
-            .await
-            .chain
-            .archival_state()
-            .get_tip()
-            .await;
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
 
-        let (mock_block_1, _, _) =
-            make_mock_block_with_valid_pow(&genesis_block, None, a_recipient_address, rng.gen());
-        let mock = Mock::new(vec![
-            Action::Read(PeerMessage::Block(Box::new(mock_block_1.into()))),
-            Action::Read(PeerMessage::Bye),
-        ]);
+        // The transaction spends an input that is unknown at genesis
+        let transaction = make_mock_transaction(vec![random_removal_record()], vec![]);
 
-        let peer_loop_handler = PeerLoopHandler::new(
-            to_main_tx.clone(),
-            state_lock.clone(),
-            peer_address,
-            hsd,
-            false,
-            1,
-        );
+        // A peer that is ahead may know the block the transaction was built
+        // against
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis().next());
         peer_loop_handler
-            .run_wrapper(mock, from_main_rx_clone)
+            .handle_peer_message(
+                PeerMessage::Transaction(Box::new(transaction.clone())),
+                &mut Mock::new(vec![]),
+                &mut peer_state,
+            )
             .await?;
+        match to_main_rx1.try_recv() {
+            Ok(PeerThreadToMain::OrphanTransaction(orphan)) => assert_eq!(transaction, *orphan),
+            _ => bail!("Must relay transaction as orphan"),
+        }
 
-        // Verify that peer max block height was sent
-        match to_main_rx1.recv().await {
-            Some(PeerThreadToMain::AddPeerMaxBlockHeight(_)) => (),
-            _ => bail!("Must receive add of peer block max height"),
+        // A peer that is not ahead cannot, so the transaction is stale
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+        let transaction = make_mock_transaction(vec![random_removal_record()], vec![]);
+        peer_loop_handler
+            .handle_peer_message(
+                PeerMessage::Transaction(Box::new(transaction)),
+                &mut Mock::new(vec![]),
+                &mut peer_state,
+            )
+            .await?;
+        match to_main_rx1.try_recv() {
+            Err(TryRecvError::Empty) => (),
+            _ => bail!("Must not relay stale transaction"),
         }
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(PeerSanctionReason::UnconfirmableTransaction),
+            standing.latest_sanction
+        );
 
-        // Verify that a block was sent to `main_loop`
-        match to_main_rx1.recv().await {
-            Some(PeerThreadToMain::NewBlocks(_block)) => (),
-            _ => bail!("Did not find msg sent to main thread"),
-        };
+        Ok(())
+    }
 
-        match to_main_rx1.recv().await {
-            Some(PeerThreadToMain::RemovePeerMaxBlockHeight(_)) => (),
-            _ => bail!("Must receive remove of peer block max height"),
-        }
+    #[traced_test]
+    #[tokio::test]
+    async fn transaction_with_negative_fee_is_punished_and_not_relayed_test() -> Result<()> {
+        let network = Network::RegTest;
+        let (_peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 0).await?;
+        let peer_address = get_dummy_socket_address(0);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
 
-        if !state_lock.lock_guard().await.net.peer_map.is_empty() {
-            bail!("peer map must be empty after closing connection gracefully");
+        let mut transaction = make_mock_transaction(vec![], vec![]);
+        transaction.kernel.fee = -NeptuneCoins::new(1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+        peer_loop_handler
+            .handle_peer_message(
+                PeerMessage::Transaction(Box::new(transaction)),
+                &mut Mock::new(vec![]),
+                &mut peer_state,
+            )
+            .await?;
+        match to_main_rx1.try_recv() {
+            Err(TryRecvError::Empty) => (),
+            _ => bail!("Must not relay transaction with negative fee"),
         }
+        let standing = state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_databases
+            .peer_standings
+            .get(peer_address.ip())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(PeerSanctionReason::NegativeFeeTransaction),
+            standing.latest_sanction
+        );
 
         Ok(())
     }
@@ -1804,10 +5881,20 @@ mod peer_loop_tests {
             .await;
         let a_wallet_secret = WalletSecret::new_random();
         let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
-        let (block_1, _, _) =
-            make_mock_block_with_valid_pow(&genesis_block, None, a_recipient_address, rng.gen());
-        let (block_2, _, _) =
-            make_mock_block_with_valid_pow(&block_1.clone(), None, a_recipient_address, rng.gen());
+        let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
 
         let mock = Mock::new(vec![
             Action::Read(PeerMessage::Block(Box::new(block_2.clone().into()))),
@@ -1889,24 +5976,28 @@ mod peer_loop_tests {
             .nth_generation_spending_key(0)
             .to_address();
         let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis_block.clone(),
             None,
             own_recipient_address,
             rng.gen(),
         );
         let (block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
             &block_1.clone(),
             None,
             own_recipient_address,
             rng.gen(),
         );
         let (block_3, _, _) = make_mock_block_with_valid_pow(
+            network,
             &block_2.clone(),
             None,
             own_recipient_address,
             rng.gen(),
         );
         let (block_4, _, _) = make_mock_block_with_valid_pow(
+            network,
             &block_3.clone(),
             None,
             own_recipient_address,
@@ -1959,7 +6050,6 @@ mod peer_loop_tests {
             .net
             .get_peer_standing_from_database(peer_address1.ip())
             .await
-            .unwrap()
             .standing
             .is_negative());
 
@@ -1981,17 +6071,33 @@ mod peer_loop_tests {
         let a_wallet_secret = WalletSecret::new_random();
         let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
         let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis_block.clone(),
             None,
             a_recipient_address,
             rng.gen(),
         );
-        let (block_2, _, _) =
-            make_mock_block_with_valid_pow(&block_1.clone(), None, a_recipient_address, rng.gen());
-        let (block_3, _, _) =
-            make_mock_block_with_valid_pow(&block_2.clone(), None, a_recipient_address, rng.gen());
-        let (block_4, _, _) =
-            make_mock_block_with_valid_pow(&block_3.clone(), None, a_recipient_address, rng.gen());
+        let (block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_3, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_4, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_3.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
         global_state_mut.set_new_tip(block_1.clone()).await?;
         drop(global_state_mut);
 
@@ -2064,15 +6170,26 @@ mod peer_loop_tests {
         let a_wallet_secret = WalletSecret::new_random();
         let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
         let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis_block.clone(),
             None,
             a_recipient_address,
             rng.gen(),
         );
-        let (block_2, _, _) =
-            make_mock_block_with_valid_pow(&block_1.clone(), None, a_recipient_address, rng.gen());
-        let (block_3, _, _) =
-            make_mock_block_with_valid_pow(&block_2.clone(), None, a_recipient_address, rng.gen());
+        let (block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_3, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
         drop(global_state);
 
         let mock = Mock::new(vec![
@@ -2128,6 +6245,186 @@ mod peer_loop_tests {
         Ok(())
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn false_proof_of_work_claim_is_sanctioned_test() -> Result<()> {
+        let network = Network::Alpha;
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 1).await?;
+        let peer_address = *state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_map
+            .keys()
+            .next()
+            .unwrap();
+        let genesis_block: Block = state_lock
+            .lock_guard()
+            .await
+            .chain
+            .archival_state()
+            .get_tip()
+            .await;
+        let a_recipient_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (block_1, _, _) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            thread_rng().gen(),
+        );
+        state_lock.lock_guard_mut().await.net.syncing = true;
+
+        // The peer claims far more proof-of-work for block 1 than its header
+        // holds, which would keep this node in sync mode.
+        let false_claim = PeerBlockNotification {
+            proof_of_work_family: U32s::new([0, 0, 0, 0, 1]),
+            ..(&block_1).into()
+        };
+        let header_of =
+            |block: &Block| (block.kernel.header.clone(), block.kernel.body.mast_hash());
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockNotification(false_claim)),
+            Action::Write(PeerMessage::BlockHeaderRequest(
+                BlockHeight::genesis()..block_1.kernel.header.height.next(),
+            )),
+            Action::Read(PeerMessage::BlockHeadersResponse(vec![
+                header_of(&genesis_block),
+                header_of(&block_1),
+            ])),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock),
+                peer_broadcast_tx.subscribe(),
+                &mut peer_state,
+            )
+            .await?;
+
+        // The claim never reaches the sync state, and the peer's claimed tip is
+        // dropped from it, such that the main loop leaves sync mode.
+        match to_main_rx1.try_recv() {
+            Ok(PeerThreadToMain::RemovePeerMaxBlockHeight(address)) => {
+                assert_eq!(peer_address, address)
+            }
+            _ => bail!("Must receive remove of peer block max height"),
+        }
+        assert!(matches!(to_main_rx1.try_recv(), Err(TryRecvError::Empty)));
+
+        let standing = state_lock.lock_guard().await.net.peer_map[&peer_address].standing;
+        assert!(standing.standing < 0);
+        assert_eq!(
+            Some(PeerSanctionReason::FalseProofOfWorkClaim(
+                block_1.kernel.header.height
+            )),
+            standing.latest_sanction
+        );
+        assert!(peer_state.pending_header_requests.is_empty());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn spot_check_requires_headers_anchored_to_known_block_test() -> Result<()> {
+        let network = Network::Alpha;
+        let (peer_broadcast_tx, _from_main_rx_clone, to_main_tx, mut to_main_rx1, state_lock, hsd) =
+            get_test_genesis_setup(network, 1).await?;
+        let peer_address = *state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_map
+            .keys()
+            .next()
+            .unwrap();
+        let genesis_block: Block = state_lock
+            .lock_guard()
+            .await
+            .chain
+            .archival_state()
+            .get_tip()
+            .await;
+        let a_recipient_address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+        let (block_1, _, _) = make_mock_block(
+            network,
+            &genesis_block,
+            None,
+            a_recipient_address,
+            thread_rng().gen(),
+        );
+
+        // The peer invents a parent with far more proof-of-work than the
+        // chain, and mines a valid child on top of it. Checked against its
+        // parent alone, the child's claim holds up.
+        let mut fabricated_header = block_1.kernel.header.clone();
+        fabricated_header.proof_of_work_family = U32s::new([0, 0, 0, 0, 1]);
+        let fabricated_parent = Block::new(
+            fabricated_header,
+            block_1.kernel.body.clone(),
+            Block::mk_std_block_type(None),
+        );
+        let (block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &fabricated_parent,
+            None,
+            a_recipient_address,
+            thread_rng().gen(),
+        );
+        let claim: PeerBlockNotification = (&block_2).into();
+        state_lock.lock_guard_mut().await.net.syncing = true;
+
+        let header_of =
+            |block: &Block| (block.kernel.header.clone(), block.kernel.body.mast_hash());
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::BlockNotification(claim)),
+            Action::Write(PeerMessage::BlockHeaderRequest(
+                BlockHeight::genesis()..block_2.kernel.header.height.next(),
+            )),
+            Action::Read(PeerMessage::BlockHeadersResponse(vec![
+                header_of(&fabricated_parent),
+                header_of(&block_2),
+            ])),
+            Action::Read(PeerMessage::Bye),
+        ]);
+        let peer_loop_handler =
+            PeerLoopHandler::new(to_main_tx, state_lock.clone(), peer_address, hsd, false, 1);
+        let mut peer_state = MutablePeerState::new(BlockHeight::genesis());
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock),
+                peer_broadcast_tx.subscribe(),
+                &mut peer_state,
+            )
+            .await?;
+
+        // The headers do not start at a block this node knows
+        match to_main_rx1.try_recv() {
+            Ok(PeerThreadToMain::RemovePeerMaxBlockHeight(address)) => {
+                assert_eq!(peer_address, address)
+            }
+            _ => bail!("Must receive remove of peer block max height"),
+        }
+        let standing = state_lock.lock_guard().await.net.peer_map[&peer_address].standing;
+        assert_eq!(
+            Some(PeerSanctionReason::FalseProofOfWorkClaim(
+                block_2.kernel.header.height
+            )),
+            standing.latest_sanction
+        );
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn test_block_reconciliation_interrupted_by_block_notification() -> Result<()> {
@@ -2145,19 +6442,40 @@ mod peer_loop_tests {
         let peer_socket_address: SocketAddr = get_dummy_socket_address(0);
         let genesis_block: Block = global_state_mut.chain.archival_state().get_tip().await;
         let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis_block.clone(),
             None,
             a_recipient_address,
             rng.gen(),
         );
-        let (block_2, _, _) =
-            make_mock_block_with_valid_pow(&block_1.clone(), None, a_recipient_address, rng.gen());
-        let (block_3, _, _) =
-            make_mock_block_with_valid_pow(&block_2.clone(), None, a_recipient_address, rng.gen());
-        let (block_4, _, _) =
-            make_mock_block_with_valid_pow(&block_3.clone(), None, a_recipient_address, rng.gen());
-        let (block_5, _, _) =
-            make_mock_block_with_valid_pow(&block_4.clone(), None, a_recipient_address, rng.gen());
+        let (block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_3, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_4, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_3.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_5, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_4.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
         global_state_mut.set_new_tip(block_1.clone()).await?;
         drop(global_state_mut);
 
@@ -2253,17 +6571,33 @@ mod peer_loop_tests {
         let a_wallet_secret = WalletSecret::new_random();
         let a_recipient_address = a_wallet_secret.nth_generation_spending_key(0).to_address();
         let (block_1, _, _) = make_mock_block_with_valid_pow(
+            network,
             &genesis_block.clone(),
             None,
             a_recipient_address,
             rng.gen(),
         );
-        let (block_2, _, _) =
-            make_mock_block_with_valid_pow(&block_1.clone(), None, a_recipient_address, rng.gen());
-        let (block_3, _, _) =
-            make_mock_block_with_valid_pow(&block_2.clone(), None, a_recipient_address, rng.gen());
-        let (block_4, _, _) =
-            make_mock_block_with_valid_pow(&block_3.clone(), None, a_recipient_address, rng.gen());
+        let (block_2, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_1.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_3, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_2.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
+        let (block_4, _, _) = make_mock_block_with_valid_pow(
+            network,
+            &block_3.clone(),
+            None,
+            a_recipient_address,
+            rng.gen(),
+        );
         global_state_mut.set_new_tip(block_1.clone()).await?;
         drop(global_state_mut);
 
@@ -2372,7 +6706,11 @@ mod peer_loop_tests {
             "Mempool must be empty at init"
         );
         peer_loop_handler
-            .run(mock, from_main_rx_clone, &mut peer_state)
+            .run(
+                CountingPeerStream::new(mock),
+                from_main_rx_clone,
+                &mut peer_state,
+            )
             .await?;
 
         // Transaction must be sent to `main_loop`. The transaction is stored to the mempool
@@ -2385,6 +6723,53 @@ mod peer_loop_tests {
         Ok(())
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn transaction_fee_is_announced_only_to_peers_understanding_it_test() -> Result<()> {
+        let (_peer_broadcast_tx, _from_main_rx_clone, to_main_tx, _to_main_rx1, state_lock, _hsd) =
+            get_test_genesis_setup(Network::Alpha, 0).await?;
+        let transaction = make_mock_transaction(vec![], vec![]);
+        let notification: TransactionNotificationWithFee = transaction.into();
+
+        let (legacy_handshake, _) = get_dummy_peer_connection_data_genesis(Network::Alpha, 1).await;
+        let fee_handshake = HandshakeData {
+            features: legacy_handshake
+                .features
+                .with(PeerFeatures::TRANSACTION_FEE_NOTIFICATION),
+            ..legacy_handshake.clone()
+        };
+        for (handshake, expected_message) in [
+            (
+                legacy_handshake,
+                PeerMessage::TransactionNotification(notification.into()),
+            ),
+            (
+                fee_handshake,
+                PeerMessage::TransactionNotificationWithFee(notification),
+            ),
+        ] {
+            let mut peer_state = MutablePeerState::new(handshake.tip_header.height);
+            let peer_loop_handler = PeerLoopHandler::new(
+                to_main_tx.clone(),
+                state_lock.clone(),
+                get_dummy_socket_address(1),
+                handshake,
+                true,
+                1,
+            );
+            let mut mock = Mock::new(vec![Action::Write(expected_message)]);
+            peer_loop_handler
+                .handle_main_thread_message(
+                    MainToPeerThread::TransactionNotification(notification),
+                    &mut mock,
+                    &mut peer_state,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn populated_mempool_request_tx_test() -> Result<()> {
@@ -2420,14 +6805,19 @@ mod peer_loop_tests {
             .lock_guard_mut()
             .await
             .mempool
-            .insert(&transaction_1);
+            .insert(&transaction_1)
+            .unwrap();
         assert!(
             !state_lock.lock_guard().await.mempool.is_empty(),
             "Mempool must be non-empty after insertion"
         );
 
         peer_loop_handler
-            .run(mock, from_main_rx_clone, &mut peer_state)
+            .run(
+                CountingPeerStream::new(mock),
+                from_main_rx_clone,
+                &mut peer_state,
+            )
             .await?;
 
         // nothing may be sent to `main_loop`
@@ -2439,4 +6829,104 @@ mod peer_loop_tests {
 
         Ok(())
     }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn mempool_inventory_is_exchanged_and_fetched_up_to_cap_test() -> Result<()> {
+        let (
+            _peer_broadcast_tx,
+            from_main_rx_clone,
+            to_main_tx,
+            _to_main_rx1,
+            mut state_lock,
+            _hsd,
+        ) = get_test_genesis_setup(Network::Alpha, 1).await?;
+        let mut cli = state_lock.cli().clone();
+        cli.max_mempool_sync_transactions = 2;
+        state_lock.set_cli(cli).await;
+
+        let known_transaction = make_mock_transaction(vec![], vec![]);
+        state_lock
+            .lock_guard_mut()
+            .await
+            .mempool
+            .insert(&known_transaction)
+            .unwrap();
+        let own_inventory = vec![(
+            Hash::hash(&known_transaction),
+            known_transaction.kernel.fee,
+            known_transaction.serialized_size(),
+        )];
+
+        // The peer holds the known transaction and three unknown ones, of which
+        // only as many as the cap allows are requested
+        let peer_transactions = (0..3)
+            .map(|_| make_mock_transaction(vec![], vec![]))
+            .collect_vec();
+        let peer_inventory = [known_transaction]
+            .iter()
+            .chain(peer_transactions.iter())
+            .map(|transaction| {
+                (
+                    Hash::hash(transaction),
+                    transaction.kernel.fee,
+                    transaction.serialized_size(),
+                )
+            })
+            .collect_vec();
+        let mock = Mock::new(vec![
+            Action::Read(PeerMessage::MempoolInventoryRequest),
+            Action::Write(PeerMessage::MempoolInventoryResponse(own_inventory)),
+            Action::Read(PeerMessage::MempoolInventoryResponse(
+                peer_inventory.clone(),
+            )),
+            Action::Write(PeerMessage::TransactionRequest(Hash::hash(
+                &peer_transactions[0],
+            ))),
+            Action::Write(PeerMessage::TransactionRequest(Hash::hash(
+                &peer_transactions[1],
+            ))),
+            // An unsolicited inventory is ignored, and sanctioned
+            Action::Read(PeerMessage::MempoolInventoryResponse(peer_inventory)),
+            Action::Read(PeerMessage::Bye),
+        ]);
+
+        let peer_address = *state_lock
+            .lock_guard()
+            .await
+            .net
+            .peer_map
+            .keys()
+            .next()
+            .unwrap();
+        let (hsd_1, _sa_1) = get_dummy_peer_connection_data_genesis(Network::Alpha, 1).await;
+        let peer_loop_handler = PeerLoopHandler::new(
+            to_main_tx,
+            state_lock.clone(),
+            peer_address,
+            hsd_1.clone(),
+            true,
+            1,
+        );
+        let mut peer_state = MutablePeerState::new(hsd_1.tip_header.height);
+        peer_state.mempool_inventory_requested = true;
+
+        peer_loop_handler
+            .run(
+                CountingPeerStream::new(mock),
+                from_main_rx_clone,
+                &mut peer_state,
+            )
+            .await?;
+        assert!(!peer_state.mempool_inventory_requested);
+        let standing = state_lock
+            .lock(|s| s.net.peer_map[&peer_address].standing)
+            .await;
+        assert_eq!(
+            Some(PeerSanctionReason::UnrequestedMessage),
+            standing.latest_sanction
+        );
+
+        Ok(())
+    }
 }
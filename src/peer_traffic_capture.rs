@@ -0,0 +1,555 @@
+//! Opt-in capture of the messages exchanged with peers, for debugging.
+//!
+//! When enabled with `--capture-peer-traffic`, every connection gets its own
+//! transcript file under [`DataDirectory::peer_traffic_dir_path`]. A transcript
+//! consists of one JSON-encoded [`CapturedPeerMessage`] per line. Disk usage is
+//! bounded both per connection and in total; the oldest transcripts are deleted
+//! when a new connection would otherwise exceed the total limit. Transcripts
+//! are written by a task per connection, such that recording a message never
+//! blocks the connection on disk I/O.
+
+use crate::config_models::cli_args::Args;
+use crate::config_models::data_directory::DataDirectory;
+use crate::models::consensus::timestamp::Timestamp;
+use crate::models::peer::PeerMessage;
+
+use anyhow::Result;
+use futures::sink::Sink;
+use futures::stream::{Stream, TryStream, TryStreamExt};
+use futures::task::{Context, Poll};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::SystemTime;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+pub const PEER_TRAFFIC_DIRECTORY_NAME: &str = "peer_traffic";
+const TRANSCRIPT_FILE_EXTENSION: &str = "jsonl";
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TrafficDirection {
+    /// Sent by the peer, received by us
+    Inbound,
+
+    /// Sent by us to the peer
+    Outbound,
+}
+
+/// One line of a peer traffic transcript
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CapturedPeerMessage {
+    pub timestamp: Timestamp,
+    pub direction: TrafficDirection,
+    pub message_type: String,
+    pub summary: String,
+
+    /// Set if the message carries a payload marked sensitive, in which case the
+    /// summary does not describe the payload and no raw frame is recorded.
+    pub redacted: bool,
+
+    /// The bincode encoding of the message, if raw frame capture is enabled
+    /// and the encoding fits within the configured size cap.
+    pub raw_frame: Option<Vec<u8>>,
+}
+
+/// Bounds on the disk usage of peer traffic capture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptureLimits {
+    /// Max combined size of all transcripts in the capture directory
+    pub max_total_bytes: u64,
+
+    /// Max size of the transcript of a single connection
+    pub max_connection_bytes: u64,
+
+    /// Max size of a raw frame to record. Zero disables raw frame capture.
+    pub max_raw_frame_bytes: u64,
+}
+
+impl CaptureLimits {
+    pub fn from_cli(cli: &Args) -> Self {
+        Self {
+            max_total_bytes: cli.capture_peer_traffic_max_size.as_u64(),
+            max_connection_bytes: cli.capture_peer_traffic_max_connection_size.as_u64(),
+            max_raw_frame_bytes: cli.capture_peer_traffic_raw_frame_size.as_u64(),
+        }
+    }
+}
+
+/// Writes the transcript of a single connection
+#[derive(Debug)]
+pub struct PeerTrafficCapture {
+    path: PathBuf,
+
+    // Lines to append to the transcript, consumed by the writer task. `None`
+    // once the per-connection cap is reached, or after a write error.
+    lines_tx: Option<mpsc::UnboundedSender<String>>,
+    writer_task: Option<JoinHandle<()>>,
+    bytes_written: u64,
+    limits: CaptureLimits,
+}
+
+impl PeerTrafficCapture {
+    /// Start the capture of a new connection, creating the capture directory if
+    /// needed and deleting the oldest transcripts to make room for this one.
+    pub async fn new(
+        directory: &Path,
+        peer_address: SocketAddr,
+        limits: CaptureLimits,
+    ) -> Result<Self> {
+        tokio::fs::create_dir_all(directory).await?;
+        Self::prune_directory(directory, limits).await?;
+
+        let unix_millis = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis();
+        let file_name = format!(
+            "{}_{}_{unix_millis}.{TRANSCRIPT_FILE_EXTENSION}",
+            peer_address.ip(),
+            peer_address.port()
+        )
+        .replace(':', "-");
+        let path = directory.join(file_name);
+        let file = tokio::fs::File::create(&path).await?;
+        debug!(
+            "Capturing traffic with {peer_address} to {}",
+            path.display()
+        );
+
+        let (lines_tx, lines_rx) = mpsc::unbounded_channel();
+        let writer_task = tokio::spawn(Self::write_transcript(path.clone(), file, lines_rx));
+
+        Ok(Self {
+            path,
+            lines_tx: Some(lines_tx),
+            writer_task: Some(writer_task),
+            bytes_written: 0,
+            limits,
+        })
+    }
+
+    /// Append the received lines to the transcript until the capture is
+    /// dropped. The transcript is flushed whenever the backlog is written, so
+    /// it survives a crash without a flush per message.
+    async fn write_transcript(
+        path: PathBuf,
+        file: tokio::fs::File,
+        mut lines_rx: mpsc::UnboundedReceiver<String>,
+    ) {
+        let mut writer = BufWriter::new(file);
+        while let Some(line) = lines_rx.recv().await {
+            let mut result = writer.write_all(line.as_bytes()).await;
+            while result.is_ok() {
+                let Ok(next_line) = lines_rx.try_recv() else {
+                    break;
+                };
+                result = writer.write_all(next_line.as_bytes()).await;
+            }
+            if result.is_ok() {
+                result = writer.flush().await;
+            }
+
+            if let Err(err) = result {
+                warn!(
+                    "Could not write peer traffic transcript {}: {err}. Stopping capture.",
+                    path.display()
+                );
+                return;
+            }
+        }
+    }
+
+    /// Start the capture of a new connection in the data directory, with the
+    /// limits configured on the command line. Returns `None` if capture is
+    /// disabled or could not be started.
+    pub async fn from_cli(cli: &Args, peer_address: SocketAddr) -> Option<Self> {
+        if !cli.capture_peer_traffic {
+            return None;
+        }
+
+        match Self::start_from_cli(cli, peer_address).await {
+            Ok(capture) => Some(capture),
+            Err(err) => {
+                warn!("Could not capture traffic with {peer_address}: {err}");
+                None
+            }
+        }
+    }
+
+    async fn start_from_cli(cli: &Args, peer_address: SocketAddr) -> Result<Self> {
+        let data_dir = DataDirectory::get(cli.data_dir.clone(), cli.network)?;
+        Self::new(
+            &data_dir.peer_traffic_dir_path(),
+            peer_address,
+            CaptureLimits::from_cli(cli),
+        )
+        .await
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Take the handle of the task writing the transcript. The task completes
+    /// once the capture is dropped and all recorded messages are written.
+    pub fn take_writer_task(&mut self) -> Option<JoinHandle<()>> {
+        self.writer_task.take()
+    }
+
+    /// Delete the oldest transcripts until a new transcript of maximal size fits
+    /// within the total limit.
+    async fn prune_directory(directory: &Path, limits: CaptureLimits) -> Result<()> {
+        let mut transcripts = vec![];
+        let mut entries = tokio::fs::read_dir(directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                transcripts.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        transcripts.sort();
+
+        let mut total_bytes = transcripts
+            .iter()
+            .fold(0u64, |total, (_, size, _)| total.saturating_add(*size));
+        for (_, size, path) in transcripts {
+            if total_bytes.saturating_add(limits.max_connection_bytes) <= limits.max_total_bytes {
+                break;
+            }
+            debug!("Deleting old peer traffic transcript {}", path.display());
+            tokio::fs::remove_file(path).await?;
+            total_bytes = total_bytes.saturating_sub(size);
+        }
+
+        Ok(())
+    }
+
+    /// Describe a message without exposing payloads marked sensitive.
+    pub fn summarize(message: &PeerMessage) -> String {
+        if message.is_sensitive() {
+            return message.get_type();
+        }
+
+        match message {
+            PeerMessage::Block(block) => format!("height {}", block.header.height),
+            PeerMessage::BlockNotification(notification) => {
+                format!("height {}", notification.height)
+            }
+            PeerMessage::BlockRequestByHeight(height) => format!("height {height}"),
+            PeerMessage::BlockRequestByHash(digest) => format!("digest {digest}"),
+            PeerMessage::BlockRequestBatch(digests, max_response_len) => format!(
+                "{} known digests, max response length {max_response_len}",
+                digests.len()
+            ),
+            PeerMessage::BlockResponseBatch(blocks) => format!("{} blocks", blocks.len()),
+            PeerMessage::BlockRequestBatchByHeight {
+                start_height,
+                count,
+            } => format!("{count} blocks from height {start_height}"),
+            PeerMessage::TransactionNotification(notification) => {
+                format!("digest {}", notification.transaction_digest)
+            }
+            PeerMessage::TransactionNotificationWithFee(notification) => format!(
+                "digest {}, fee {}",
+                notification.transaction_digest, notification.fee
+            ),
+            PeerMessage::TransactionRequest(digest) => format!("digest {digest}"),
+            PeerMessage::GetBlockTransactions(txids) => format!("{} transactions", txids.len()),
+            PeerMessage::MempoolInventoryResponse(inventory) => {
+                format!("{} transactions", inventory.len())
+            }
+            PeerMessage::BlockHeaderRequest(heights) => {
+                format!("heights {} to {}", heights.start, heights.end)
+            }
+            PeerMessage::BlockHeadersResponse(headers) => format!("{} headers", headers.len()),
+            PeerMessage::PeerListResponse(peers) => format!("{} peers", peers.len()),
+            PeerMessage::ConnectionStatus(status) => format!("{status:?}"),
+            _ => message.get_type(),
+        }
+    }
+
+    /// Record a message. Stops recording, without failing, once the
+    /// per-connection cap is reached or if the transcript cannot be written.
+    pub fn record(&mut self, direction: TrafficDirection, message: &PeerMessage) {
+        let Some(lines_tx) = self.lines_tx.as_ref() else {
+            return;
+        };
+
+        let redacted = message.is_sensitive();
+        let raw_frame = if redacted || self.limits.max_raw_frame_bytes == 0 {
+            None
+        } else {
+            bincode::serialize(message)
+                .ok()
+                .filter(|frame| frame.len() as u64 <= self.limits.max_raw_frame_bytes)
+        };
+        let captured_message = CapturedPeerMessage {
+            timestamp: Timestamp::now(),
+            direction,
+            message_type: message.get_type(),
+            summary: Self::summarize(message),
+            redacted,
+            raw_frame,
+        };
+
+        let mut line = match serde_json::to_string(&captured_message) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("Could not encode captured peer message: {err}");
+                return;
+            }
+        };
+        line.push('\n');
+
+        let line_len = line.len() as u64;
+        if self.bytes_written.saturating_add(line_len) > self.limits.max_connection_bytes {
+            warn!(
+                "Peer traffic transcript {} reached its size limit. Stopping capture.",
+                self.path.display()
+            );
+            self.lines_tx = None;
+            return;
+        }
+
+        // The writer task only hangs up after a write error, which it reports
+        match lines_tx.send(line) {
+            Ok(()) => self.bytes_written += line_len,
+            Err(_) => self.lines_tx = None,
+        }
+    }
+
+    /// Read a transcript written by [`PeerTrafficCapture`].
+    pub fn read_transcript(path: &Path) -> Result<Vec<CapturedPeerMessage>> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        let mut transcript = vec![];
+        for line in reader.lines() {
+            transcript.push(serde_json::from_str(&line?)?);
+        }
+
+        Ok(transcript)
+    }
+}
+
+/// Wraps the connection to a peer and records all messages passing through it.
+/// Without a capture, messages are passed through untouched.
+#[derive(Debug)]
+pub struct CapturingPeerStream<S> {
+    inner: S,
+    capture: Option<PeerTrafficCapture>,
+}
+
+impl<S> CapturingPeerStream<S> {
+    pub fn new(inner: S, capture: Option<PeerTrafficCapture>) -> Self {
+        Self { inner, capture }
+    }
+
+    pub fn capture(&self) -> Option<&PeerTrafficCapture> {
+        self.capture.as_ref()
+    }
+}
+
+impl<S> Stream for CapturingPeerStream<S>
+where
+    S: TryStream<Ok = PeerMessage> + Unpin,
+{
+    type Item = Result<PeerMessage, S::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.try_poll_next_unpin(cx);
+        if let (Poll::Ready(Some(Ok(message))), Some(capture)) = (&poll, self.capture.as_mut()) {
+            capture.record(TrafficDirection::Inbound, message);
+        }
+
+        poll
+    }
+}
+
+impl<S> Sink<PeerMessage> for CapturingPeerStream<S>
+where
+    S: Sink<PeerMessage> + Unpin,
+{
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: PeerMessage) -> Result<(), Self::Error> {
+        if let Some(capture) = self.capture.as_mut() {
+            capture.record(TrafficDirection::Outbound, &item);
+        }
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod peer_traffic_capture_tests {
+    use super::*;
+
+    use crate::config_models::network::Network;
+    use crate::tests::shared::{
+        get_dummy_socket_address, make_mock_transaction, replay_actions, unit_test_data_directory,
+        Action,
+    };
+
+    fn test_limits() -> CaptureLimits {
+        CaptureLimits {
+            max_total_bytes: 10_000,
+            max_connection_bytes: 1_000,
+            max_raw_frame_bytes: 1_000,
+        }
+    }
+
+    /// Drop the capture and wait for the transcript to be written
+    async fn finish(mut capture: PeerTrafficCapture) -> PathBuf {
+        let writer_task = capture.take_writer_task().unwrap();
+        let path = capture.path().to_owned();
+        drop(capture);
+        writer_task.await.unwrap();
+
+        path
+    }
+
+    #[tokio::test]
+    async fn per_connection_cap_is_respected() {
+        let data_dir = unit_test_data_directory(Network::RegTest).unwrap();
+        let mut capture = PeerTrafficCapture::new(
+            &data_dir.peer_traffic_dir_path(),
+            get_dummy_socket_address(0),
+            test_limits(),
+        )
+        .await
+        .unwrap();
+
+        for _ in 0..100 {
+            capture.record(TrafficDirection::Inbound, &PeerMessage::PeerListRequest);
+        }
+
+        let path = finish(capture).await;
+        let transcript_size = fs::metadata(&path).unwrap().len();
+        assert!(transcript_size <= test_limits().max_connection_bytes);
+        let transcript = PeerTrafficCapture::read_transcript(&path).unwrap();
+        assert!(!transcript.is_empty());
+        assert!(transcript.len() < 100);
+    }
+
+    #[tokio::test]
+    async fn oldest_transcripts_are_deleted() {
+        let data_dir = unit_test_data_directory(Network::RegTest).unwrap();
+        let directory = data_dir.peer_traffic_dir_path();
+        let limits = CaptureLimits {
+            max_total_bytes: 2_000,
+            ..test_limits()
+        };
+
+        let mut first_paths = vec![];
+        for i in 0..5 {
+            let mut capture =
+                PeerTrafficCapture::new(&directory, get_dummy_socket_address(i), limits)
+                    .await
+                    .unwrap();
+            for _ in 0..100 {
+                capture.record(TrafficDirection::Outbound, &PeerMessage::Bye);
+            }
+            first_paths.push(finish(capture).await);
+        }
+
+        let total_bytes: u64 = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum();
+        assert!(total_bytes <= limits.max_total_bytes);
+        assert!(!first_paths[0].exists());
+        assert!(first_paths[4].exists());
+    }
+
+    #[tokio::test]
+    async fn unbounded_limits_do_not_overflow() {
+        let data_dir = unit_test_data_directory(Network::RegTest).unwrap();
+        let directory = data_dir.peer_traffic_dir_path();
+        let limits = CaptureLimits {
+            max_raw_frame_bytes: u64::MAX,
+            max_connection_bytes: u64::MAX,
+            max_total_bytes: u64::MAX,
+        };
+
+        let mut capture = PeerTrafficCapture::new(&directory, get_dummy_socket_address(0), limits)
+            .await
+            .unwrap();
+        capture.record(TrafficDirection::Outbound, &PeerMessage::Bye);
+        let first_path = finish(capture).await;
+
+        // A non-empty directory must not make the pruning arithmetic overflow
+        let capture = PeerTrafficCapture::new(&directory, get_dummy_socket_address(1), limits)
+            .await
+            .unwrap();
+        finish(capture).await;
+        assert_eq!(
+            1,
+            PeerTrafficCapture::read_transcript(&first_path)
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[tokio::test]
+    async fn sensitive_payloads_are_redacted() {
+        let data_dir = unit_test_data_directory(Network::RegTest).unwrap();
+        let mut capture = PeerTrafficCapture::new(
+            &data_dir.peer_traffic_dir_path(),
+            get_dummy_socket_address(0),
+            CaptureLimits {
+                max_raw_frame_bytes: u64::MAX,
+                max_connection_bytes: u64::MAX,
+                max_total_bytes: u64::MAX,
+            },
+        )
+        .await
+        .unwrap();
+
+        let transaction = make_mock_transaction(vec![], vec![]);
+        capture.record(
+            TrafficDirection::Inbound,
+            &PeerMessage::Transaction(Box::new(transaction.clone())),
+        );
+        capture.record(TrafficDirection::Outbound, &PeerMessage::PeerListRequest);
+        capture.record(
+            TrafficDirection::Outbound,
+            &PeerMessage::Transaction(Box::new(transaction)),
+        );
+
+        let transcript = PeerTrafficCapture::read_transcript(&finish(capture).await).unwrap();
+        assert!(transcript[0].redacted);
+        assert!(transcript[0].raw_frame.is_none());
+        assert!(!transcript[1].redacted);
+        assert_eq!(
+            PeerMessage::PeerListRequest,
+            bincode::deserialize(transcript[1].raw_frame.as_ref().unwrap()).unwrap()
+        );
+
+        // Redacted messages cannot be replayed verbatim: inbound ones are
+        // skipped, and outbound ones are matched by type
+        let actions = replay_actions(&transcript).unwrap();
+        assert_eq!(2, actions.len());
+        assert!(matches!(
+            &actions[0],
+            Action::Write(PeerMessage::PeerListRequest)
+        ));
+        assert!(
+            matches!(&actions[1], Action::WriteOfType(message_type) if *message_type == transcript[2].message_type)
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Counting of the messages exchanged with a peer, for the statistics that the
+//! peer thread publishes in the peer map.
+
+use crate::models::peer::{PeerMessage, PeerTraffic};
+
+use futures::sink::Sink;
+use futures::stream::{Stream, TryStream, TryStreamExt};
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Size of the encoded messages exchanged with a peer, excluding framing.
+/// Counted by the [`PeerMessageCodec`](crate::peer_codec::PeerMessageCodec)
+/// that encodes and decodes them anyway, and shared with the peer thread.
+#[derive(Clone, Debug, Default)]
+pub struct PeerByteCounts {
+    sent: Arc<AtomicU64>,
+    received: Arc<AtomicU64>,
+}
+
+impl PeerByteCounts {
+    pub fn record_sent(&self, byte_count: usize) {
+        self.sent.fetch_add(byte_count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_received(&self, byte_count: usize) {
+        self.received
+            .fetch_add(byte_count as u64, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
+/// Wraps the connection to a peer and counts all messages passing through it.
+#[derive(Debug)]
+pub struct CountingPeerStream<S> {
+    inner: S,
+    traffic: PeerTraffic,
+}
+
+impl<S> CountingPeerStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            traffic: PeerTraffic::default(),
+        }
+    }
+
+    pub fn traffic(&self) -> &PeerTraffic {
+        &self.traffic
+    }
+}
+
+impl<S> Stream for CountingPeerStream<S>
+where
+    S: TryStream<Ok = PeerMessage> + Unpin,
+{
+    type Item = Result<PeerMessage, S::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.try_poll_next_unpin(cx);
+        if let Poll::Ready(Some(Ok(message))) = &poll {
+            self.traffic.record_received(message);
+        }
+
+        poll
+    }
+}
+
+impl<S> Sink<PeerMessage> for CountingPeerStream<S>
+where
+    S: Sink<PeerMessage> + Unpin,
+{
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: PeerMessage) -> Result<(), Self::Error> {
+        self.traffic.record_sent(&item);
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
@@ -1,41 +1,61 @@
 use crate::models::blockchain::type_scripts::neptune_coins::NeptuneCoins;
 use crate::models::consensus::timestamp::Timestamp;
+use crate::models::consensus::WitnessType;
 use crate::models::state::wallet::coin_with_possible_timelock::CoinWithPossibleTimeLock;
+use crate::models::state::wallet::encrypted_wallet_secret::EncryptedWalletSecret;
 use crate::prelude::twenty_first;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use get_size::GetSize;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::path::{Component, PathBuf};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 use tarpc::context;
+use thiserror::Error;
 use tokio::sync::mpsc::error::SendError;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use twenty_first::math::digest::Digest;
 use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 
+use crate::config_models::data_directory::DataDirectory;
 use crate::config_models::network::Network;
 use crate::models::blockchain::block::block_header::BlockHeader;
 use crate::models::blockchain::block::block_height::BlockHeight;
 use crate::models::blockchain::block::block_info::BlockInfo;
 use crate::models::blockchain::block::block_selector::BlockSelector;
+use crate::models::blockchain::block::transfer_block::TransferBlock;
 use crate::models::blockchain::shared::Hash;
 use crate::models::blockchain::transaction::utxo::Utxo;
-use crate::models::channel::RPCServerToMain;
+use crate::models::blockchain::transaction::Transaction;
+use crate::models::channel::{MinerRecoveries, RPCServerToMain};
+use crate::models::database::DbStats;
 use crate::models::peer::InstanceId;
 use crate::models::peer::PeerInfo;
 use crate::models::peer::PeerStanding;
+use crate::models::state::chain_split_detector::ChainSplitAlert;
+use crate::models::state::digest_lookup::{HexPrefix, ResolvedDigest};
+use crate::models::state::mempool::{MempoolStats, MempoolTransactionInfo};
+use crate::models::state::storage_scrubber::StorageScrubReport;
 use crate::models::state::wallet::address::generation_address;
+use crate::models::state::wallet::monitored_utxo::{
+    ChunkDictionaryStatistics, MonitoredUtxoInfo, ProofAuditReport, UnspentUtxoInfo,
+};
+use crate::models::state::wallet::transaction_label::LabelTarget;
 use crate::models::state::wallet::wallet_status::WalletStatus;
+use crate::models::state::wallet::watched_address::WatchedAddress;
 use crate::models::state::{GlobalStateLock, UtxoReceiverData};
+use crate::util_types::mutator_set::archival_mutator_set::MutatorSetStatistics;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct DashBoardOverviewDataFromClient {
     pub tip_digest: Digest,
     pub tip_header: BlockHeader,
     pub syncing: bool,
+    pub quiesced: bool,
     pub available_balance: NeptuneCoins,
     pub timelocked_balance: NeptuneCoins,
     pub mempool_size: usize,
@@ -52,6 +72,44 @@ pub struct DashBoardOverviewDataFromClient {
     pub confirmations: Option<BlockHeight>,
 }
 
+/// Identifies a block in the canonical chain, either by its height or as the
+/// last block whose timestamp is not later than the given one.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HeightOrTimestamp {
+    Height(BlockHeight),
+    Timestamp(Timestamp),
+}
+
+/// Returned when a transaction submitted with `send_raw_transaction` is
+/// rejected.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Error)]
+pub enum SendRawTransactionError {
+    #[error("Transaction must be fully proven; raw witnesses and decompositions are not accepted")]
+    UnsupportedWitness,
+
+    #[error("Transaction is invalid")]
+    Invalid,
+
+    #[error("Transaction is synced to mutator set {transaction}, but the tip's is {tip}")]
+    StaleMutatorSet { transaction: Digest, tip: Digest },
+
+    #[error("Transaction spends an input that cannot be removed from the mutator set")]
+    UnspendableInput,
+
+    #[error("Transaction could not be handed to the main loop")]
+    Unavailable,
+}
+
+/// Returned when the wallet secret cannot be exported or imported.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Error)]
+pub enum SpendingKeyTransferError {
+    #[error("Exporting and importing the wallet secret is disabled on this node")]
+    Disabled,
+
+    #[error("{0}")]
+    Failed(String),
+}
+
 #[tarpc::service]
 pub trait RPC {
     /******** READ DATA ********/
@@ -91,6 +149,16 @@ pub trait RPC {
     /// Return the digest for the specified block if found
     async fn block_digest(block_selector: BlockSelector) -> Option<Digest>;
 
+    /// Return the block at the given height in the canonical chain, if any.
+    /// The genesis block cannot be transferred and is never returned.
+    async fn get_block_by_height(height: BlockHeight) -> Option<TransferBlock>;
+
+    /// Return the stored block with the given hash, if any. Unlike
+    /// `get_block_by_height`, this also returns blocks that are not part of
+    /// the canonical chain, e.g. those abandoned in a reorganization. The
+    /// genesis block cannot be transferred and is never returned.
+    async fn get_block_by_hash(hash: Digest) -> Option<TransferBlock>;
+
     /// Return the digest for the specified UTXO leaf index if found
     async fn utxo_digest(leaf_index: u64) -> Option<Digest>;
 
@@ -100,8 +168,28 @@ pub trait RPC {
     /// Get sum of unspent UTXOs.
     async fn synced_balance() -> NeptuneCoins;
 
-    /// Get the client's wallet transaction history
-    async fn history() -> Vec<(Digest, BlockHeight, Timestamp, NeptuneCoins)>;
+    /// Get the client's wallet transaction history, with the labels of the
+    /// transactions that were labeled in this wallet
+    async fn history() -> Vec<(Digest, BlockHeight, Timestamp, NeptuneCoins, Option<String>)>;
+
+    /// Get the entries of the client's wallet transaction history whose label
+    /// contains the given substring
+    async fn find_history(
+        label_substring: String,
+    ) -> Vec<(Digest, BlockHeight, Timestamp, NeptuneCoins, Option<String>)>;
+
+    /// Find the blocks, mempool transactions, and wallet transactions and
+    /// UTXOs whose digest starts with the given hex prefix, e.g. a digest
+    /// shortened in a log. Returns several candidates if the prefix is
+    /// ambiguous, and none if it is empty or not hex.
+    async fn resolve_digest(hex_prefix: String) -> Vec<ResolvedDigest>;
+
+    /// Get the wallet's confirmed balance as of the given block in the current
+    /// canonical chain, along with the height of that block. Returns None if
+    /// the block does not exist.
+    async fn get_historical_balance(
+        height_or_timestamp: HeightOrTimestamp,
+    ) -> Option<(BlockHeight, NeptuneCoins)>;
 
     /// Return information about funds in the wallet
     async fn wallet_status() -> WalletStatus;
@@ -115,6 +203,17 @@ pub trait RPC {
     // TODO: Change to return current size and max size
     async fn mempool_size() -> usize;
 
+    /// Return summaries of the mempool transactions with positions in the
+    /// range `[offset, offset + limit)`, in descending order of fee density.
+    async fn mempool_list(offset: u64, limit: u64) -> Vec<MempoolTransactionInfo>;
+
+    /// Return the mempool transaction with the given ID, if any
+    async fn mempool_get(transaction_id: Digest) -> Option<Transaction>;
+
+    /// Return the number, total size, and total fees of the transactions in
+    /// the mempool, along with the spread of their fee densities
+    async fn mempool_stats() -> MempoolStats;
+
     /// Return the information used on the dashboard's overview tab
     async fn dashboard_overview_data() -> DashBoardOverviewDataFromClient;
 
@@ -133,6 +232,52 @@ pub trait RPC {
     /// Generate a report of all owned and unspent coins, whether time-locked or not.
     async fn list_own_coins() -> Vec<CoinWithPossibleTimeLock>;
 
+    /// Return details about the wallet's monitored UTXOs with indices in the
+    /// range `[offset, offset + limit)`.
+    async fn list_monitored_utxos(offset: u64, limit: u64) -> Vec<MonitoredUtxoInfo>;
+
+    /// Return the wallet's unspent UTXOs, with the block that confirmed them
+    /// and whether their membership proofs are synced to the current tip.
+    async fn list_unspent() -> Vec<UnspentUtxoInfo>;
+
+    /// Return summarized statistics about the archival mutator set, and the
+    /// block height at which they were computed. The statistics are cached and
+    /// only recomputed every `MUTATOR_SET_STATISTICS_REFRESH_INTERVAL` blocks.
+    async fn mutator_set_statistics() -> (BlockHeight, MutatorSetStatistics);
+
+    /// Return statistics about the size of the chunk dictionaries in the
+    /// membership proofs of the wallet's unspent UTXOs.
+    async fn chunk_dictionary_statistics() -> ChunkDictionaryStatistics;
+
+    /// Verify the membership proof of every unspent monitored UTXO against
+    /// the current tip, and return the failures, each classified and with a
+    /// suggested repair.
+    async fn verify_all_proofs() -> ProofAuditReport;
+
+    /// Return the third-party addresses watched by the wallet, along with the
+    /// payments to them that were seen in blocks.
+    async fn watched_addresses() -> Vec<WatchedAddress>;
+
+    /// Return the details of the suspected chain split, i.e. which peers
+    /// announce which tips outside the canonical chain, if any.
+    async fn chain_split_alert() -> Option<ChainSplitAlert>;
+
+    /// Return the time since which this node has been without peers, if it
+    /// has been for longer than `--peerless-warning-threshold`.
+    async fn peerless_alert() -> Option<SystemTime>;
+
+    /// Return how often the miner recovered from not getting the expected
+    /// confirmation of a block it found.
+    async fn miner_recoveries() -> MinerRecoveries;
+
+    /// Return the outcome of the background verification of stored blocks
+    /// and mutator set chunks, including any records found to be corrupt.
+    async fn storage_scrub_report() -> StorageScrubReport;
+
+    /// Return the number of entries in each peer database, and the outcome
+    /// of their garbage collection on startup.
+    async fn db_stats() -> DbStats;
+
     /******** CHANGE THINGS ********/
     // Place all things that change state here
 
@@ -142,22 +287,77 @@ pub trait RPC {
     /// Clears standing for ip, whether connected or not
     async fn clear_standing_by_ip(ip: IpAddr);
 
-    /// Send coins
+    /// Send coins, optionally labeling the transaction in the wallet. The
+    /// label is never broadcast.
     async fn send(
         amount: NeptuneCoins,
         address: generation_address::ReceivingAddress,
         fee: NeptuneCoins,
+        label: Option<String>,
     ) -> Option<Digest>;
 
+    /// Broadcast a transaction that was built elsewhere, e.g. signed offline.
+    /// The transaction must be valid and synced to the current tip. Returns
+    /// the transaction ID once it is handed to the main loop, which inserts it
+    /// into the mempool and notifies peers.
+    async fn send_raw_transaction(
+        transaction: Transaction,
+    ) -> Result<Digest, SendRawTransactionError>;
+
+    /// Return the wallet secret, encrypted under a key derived from the given
+    /// passphrase, for migrating it to another wallet. Every export is logged
+    /// and announced as a wallet event.
+    async fn export_spending_key(
+        export_passphrase: String,
+    ) -> Result<EncryptedWalletSecret, SpendingKeyTransferError>;
+
+    /// Decrypt a wallet secret exported with `export_spending_key` and
+    /// initialize the wallet directory of the given data directory with it.
+    /// The data directory is relative to this node's data directory, and must
+    /// not hold a wallet yet. A node started on it rescans the blocks it
+    /// already holds and finds the wallet's UTXOs. Returns the path of the
+    /// wallet directory.
+    async fn import_spending_key(
+        encrypted_secret: EncryptedWalletSecret,
+        passphrase: String,
+        data_directory: PathBuf,
+    ) -> Result<PathBuf, SpendingKeyTransferError>;
+
+    /// Attach a label to a transaction created by this wallet, or to a
+    /// monitored UTXO. Returns false if the target is unknown or the label is
+    /// invalid.
+    async fn set_label(target: LabelTarget, label: String) -> bool;
+
     /// Stop miner if running
     async fn pause_miner();
 
     /// Start miner if not running
     async fn restart_miner();
 
+    /// Quiesce the node for maintenance, e.g. before taking a disk snapshot.
+    /// Stops the miner, defers peer messages that would change the state,
+    /// pauses the periodic jobs, and flushes all databases. Returns false if
+    /// the block being applied did not complete within `timeout`, or if
+    /// flushing failed.
+    async fn quiesce(timeout: Duration) -> bool;
+
+    /// Resume the node after `quiesce`. Returns false if it was not quiesced.
+    async fn resume() -> bool;
+
     /// mark MUTXOs as abandoned
     async fn prune_abandoned_monitored_utxos() -> usize;
 
+    /// Track payments to a third-party address, or relabel it if it is
+    /// tracked already. Returns true if the address was not tracked before.
+    async fn add_watch_address(
+        address: generation_address::ReceivingAddress,
+        label: String,
+    ) -> bool;
+
+    /// Stop tracking payments to a third-party address. Returns true if the
+    /// address was tracked.
+    async fn remove_watch_address(address: generation_address::ReceivingAddress) -> bool;
+
     /// Gracious shutdown.
     async fn shutdown() -> bool;
 }
@@ -170,6 +370,24 @@ pub struct NeptuneRPCServer {
 }
 
 impl NeptuneRPCServer {
+    fn sort_history_for_display(
+        history: Vec<(Digest, Timestamp, BlockHeight, NeptuneCoins, Option<String>)>,
+    ) -> Vec<(Digest, BlockHeight, Timestamp, NeptuneCoins, Option<String>)> {
+        let mut display_history: Vec<(
+            Digest,
+            BlockHeight,
+            Timestamp,
+            NeptuneCoins,
+            Option<String>,
+        )> = history
+            .into_iter()
+            .map(|(h, t, bh, a, l)| (h, bh, t, a, l))
+            .collect::<Vec<_>>();
+        display_history.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        display_history
+    }
+
     async fn confirmations_internal(&self) -> Option<BlockHeight> {
         let state = self.state.lock_guard().await;
 
@@ -224,10 +442,10 @@ impl RPC for NeptuneRPCServer {
 
     async fn utxo_digest(self, _: context::Context, leaf_index: u64) -> Option<Digest> {
         let state = self.state.lock_guard().await;
-        let aocl = &state.chain.archival_state().archival_mutator_set.ams().aocl;
+        let ams = state.chain.archival_state().archival_mutator_set.ams();
 
-        match leaf_index > 0 && leaf_index < aocl.count_leaves().await {
-            true => Some(aocl.get_leaf_async(leaf_index).await),
+        match leaf_index > 0 && leaf_index < ams.aocl_leaf_count().await {
+            true => Some(ams.get_aocl_leaf(leaf_index).await),
             false => None,
         }
     }
@@ -261,9 +479,51 @@ impl RPC for NeptuneRPCServer {
             &block,
             archival_state.genesis_block().hash(),
             state.chain.light_state().hash(),
+            state.cli().network,
         ))
     }
 
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn get_block_by_height(
+        self,
+        _: context::Context,
+        height: BlockHeight,
+    ) -> Option<TransferBlock> {
+        if height.is_genesis() {
+            return None;
+        }
+
+        let state = self.state.lock_guard().await;
+        let digest = BlockSelector::Height(height).as_digest(&state).await?;
+        let block = match state.chain.archival_state().get_block(digest).await {
+            Ok(block) => block?,
+            Err(err) => {
+                error!("Failed to read block {digest}: {err}");
+                return None;
+            }
+        };
+        Some(block.into())
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn get_block_by_hash(self, _: context::Context, hash: Digest) -> Option<TransferBlock> {
+        let state = self.state.lock_guard().await;
+        let block = match state.chain.archival_state().get_block(hash).await {
+            Ok(block) => block?,
+            Err(err) => {
+                error!("Failed to read block {hash}: {err}");
+                return None;
+            }
+        };
+        if block.kernel.header.height.is_genesis() {
+            return None;
+        }
+
+        Some(block.into())
+    }
+
     async fn latest_tip_digests(self, _context: tarpc::context::Context, n: usize) -> Vec<Digest> {
         let state = self.state.lock_guard().await;
 
@@ -417,21 +677,95 @@ impl RPC for NeptuneRPCServer {
         self.state.lock_guard().await.mempool.get_size()
     }
 
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn mempool_list(
+        self,
+        _context: tarpc::context::Context,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<MempoolTransactionInfo> {
+        self.state
+            .lock_guard()
+            .await
+            .mempool
+            .list(offset as usize, limit as usize)
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn mempool_get(
+        self,
+        _context: tarpc::context::Context,
+        transaction_id: Digest,
+    ) -> Option<Transaction> {
+        self.state
+            .lock_guard()
+            .await
+            .mempool
+            .get(transaction_id)
+            .cloned()
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn mempool_stats(self, _context: tarpc::context::Context) -> MempoolStats {
+        self.state.lock_guard().await.mempool.stats()
+    }
+
     async fn history(
         self,
         _context: tarpc::context::Context,
-    ) -> Vec<(Digest, BlockHeight, Timestamp, NeptuneCoins)> {
+    ) -> Vec<(Digest, BlockHeight, Timestamp, NeptuneCoins, Option<String>)> {
         let history = self.state.lock_guard().await.get_balance_history().await;
 
-        // sort
-        let mut display_history: Vec<(Digest, BlockHeight, Timestamp, NeptuneCoins)> = history
-            .iter()
-            .map(|(h, t, bh, a)| (*h, *bh, *t, *a))
-            .collect::<Vec<_>>();
-        display_history.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        Self::sort_history_for_display(history)
+    }
 
-        // return
-        display_history
+    async fn find_history(
+        self,
+        _context: tarpc::context::Context,
+        label_substring: String,
+    ) -> Vec<(Digest, BlockHeight, Timestamp, NeptuneCoins, Option<String>)> {
+        let history = self
+            .state
+            .lock_guard()
+            .await
+            .find_history(&label_substring)
+            .await;
+
+        Self::sort_history_for_display(history)
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn resolve_digest(
+        self,
+        _context: tarpc::context::Context,
+        hex_prefix: String,
+    ) -> Vec<ResolvedDigest> {
+        let Some(prefix) = HexPrefix::parse(&hex_prefix) else {
+            return vec![];
+        };
+
+        self.state.lock_guard().await.resolve_digest(&prefix).await
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn get_historical_balance(
+        self,
+        _context: tarpc::context::Context,
+        height_or_timestamp: HeightOrTimestamp,
+    ) -> Option<(BlockHeight, NeptuneCoins)> {
+        let state = self.state.lock_guard().await;
+        match height_or_timestamp {
+            HeightOrTimestamp::Height(height) => state
+                .balance_at_height(height)
+                .await
+                .map(|balance| (height, balance)),
+            HeightOrTimestamp::Timestamp(timestamp) => state.balance_at_timestamp(timestamp).await,
+        }
     }
 
     async fn dashboard_overview_data(
@@ -444,6 +778,7 @@ impl RPC for NeptuneRPCServer {
         let tip_header = state.chain.light_state().header().clone();
         let wallet_status = state.get_wallet_status_for_tip().await;
         let syncing = state.net.syncing;
+        let quiesced = state.net.quiesced;
         let mempool_size = state.mempool.get_size();
         let mempool_tx_count = state.mempool.len();
 
@@ -458,6 +793,7 @@ impl RPC for NeptuneRPCServer {
             tip_digest,
             tip_header,
             syncing,
+            quiesced,
             available_balance: wallet_status.synced_unspent_available_amount(now),
             timelocked_balance: wallet_status.synced_unspent_timelocked_amount(now),
             mempool_size,
@@ -481,7 +817,7 @@ impl RPC for NeptuneRPCServer {
                 peerinfo.standing.clear_standing();
             });
 
-        // iterates and modifies standing field for all connected peers
+        // Also forgets the stored standings of all peers, connected or not
         global_state_mut.net.clear_all_standings_in_database().await;
 
         global_state_mut
@@ -521,6 +857,7 @@ impl RPC for NeptuneRPCServer {
         amount: NeptuneCoins,
         address: generation_address::ReceivingAddress,
         fee: NeptuneCoins,
+        label: Option<String>,
     ) -> Option<Digest> {
         let span = tracing::debug_span!("Constructing transaction objects");
         let _enter = span.enter();
@@ -537,10 +874,28 @@ impl RPC for NeptuneRPCServer {
         let state = self.state.lock_guard().await;
         let block_height = state.chain.light_state().header().height;
         let receiver_privacy_digest = address.privacy_digest;
-        let sender_randomness = state
-            .wallet_state
-            .wallet_secret
-            .generate_sender_randomness(block_height, receiver_privacy_digest);
+
+        // Sending to the same address twice within one block would reuse the
+        // sender randomness, so bump the derivation nonce until it is fresh.
+        let mut nonce = 0;
+        let sender_randomness = loop {
+            let sender_randomness = state
+                .wallet_state
+                .wallet_secret
+                .generate_sender_randomness_with_nonce(
+                    block_height,
+                    receiver_privacy_digest,
+                    nonce,
+                );
+            if !state
+                .wallet_state
+                .sender_randomness_was_used(sender_randomness)
+                .await
+            {
+                break sender_randomness;
+            }
+            nonce += 1;
+        };
         drop(state);
 
         // 1. Build transaction object
@@ -580,7 +935,7 @@ impl RPC for NeptuneRPCServer {
             .state
             .lock_guard_mut()
             .await
-            .create_transaction(receiver_data, fee, now)
+            .create_transaction_with_label(receiver_data, fee, now, label)
             .await;
 
         let transaction = match transaction_result {
@@ -614,6 +969,58 @@ impl RPC for NeptuneRPCServer {
         }
     }
 
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn send_raw_transaction(
+        self,
+        _ctx: context::Context,
+        transaction: Transaction,
+    ) -> Result<Digest, SendRawTransactionError> {
+        if !matches!(
+            transaction.witness.vast.witness_type,
+            WitnessType::Faith | WitnessType::Proof(_)
+        ) {
+            return Err(SendRawTransactionError::UnsupportedWitness);
+        }
+        if !transaction.is_valid() {
+            return Err(SendRawTransactionError::Invalid);
+        }
+
+        {
+            let state = self.state.lock_guard().await;
+            let tip_mutator_set_accumulator = &state
+                .chain
+                .light_state()
+                .kernel
+                .body
+                .mutator_set_accumulator;
+            let tip_mutator_set_hash = tip_mutator_set_accumulator.hash();
+            if transaction.kernel.mutator_set_hash != tip_mutator_set_hash {
+                return Err(SendRawTransactionError::StaleMutatorSet {
+                    transaction: transaction.kernel.mutator_set_hash,
+                    tip: tip_mutator_set_hash,
+                });
+            }
+            if !transaction
+                .kernel
+                .inputs
+                .iter()
+                .all(|input| tip_mutator_set_accumulator.can_remove(input))
+            {
+                return Err(SendRawTransactionError::UnspendableInput);
+            }
+        }
+
+        let transaction_id = Hash::hash(&transaction);
+        info!("Broadcasting raw transaction {transaction_id}");
+        self.rpc_server_to_main_tx
+            .send(RPCServerToMain::Send(Box::new(transaction)))
+            .await
+            .map_err(|_| SendRawTransactionError::Unavailable)?;
+
+        Ok(transaction_id)
+    }
+
     async fn shutdown(self, _: context::Context) -> bool {
         // 1. Send shutdown message to main
         let response = self
@@ -647,6 +1054,53 @@ impl RPC for NeptuneRPCServer {
         }
     }
 
+    async fn quiesce(self, _context: tarpc::context::Context, timeout: Duration) -> bool {
+        // Blocks are applied under the write lock, so none is in flight once
+        // it is acquired.
+        let Ok(mut global_state_mut) =
+            tokio::time::timeout(timeout, self.state.lock_guard_mut()).await
+        else {
+            warn!("Could not quiesce within {timeout:?}");
+            return false;
+        };
+        let quiesce_result = global_state_mut.quiesce().await;
+        drop(global_state_mut);
+
+        // The main loop stops the miner
+        let _ = self
+            .rpc_server_to_main_tx
+            .send(RPCServerToMain::Quiesce)
+            .await;
+
+        match quiesce_result {
+            Ok(()) => {
+                info!("Quiesced");
+                true
+            }
+            Err(err) => {
+                error!("Failed to flush while quiescing: {err}");
+                false
+            }
+        }
+    }
+
+    async fn resume(self, _context: tarpc::context::Context) -> bool {
+        let was_quiesced = self.state.lock_mut(|s| s.resume()).await;
+        if !was_quiesced {
+            info!("Cannot resume since the node was not quiesced");
+            return false;
+        }
+
+        // Wakes the main loop, which restarts the miner
+        let _ = self
+            .rpc_server_to_main_tx
+            .send(RPCServerToMain::Resume)
+            .await;
+        info!("Resumed");
+
+        true
+    }
+
     async fn prune_abandoned_monitored_utxos(self, _context: tarpc::context::Context) -> usize {
         let mut global_state_mut = self.state.lock_guard_mut().await;
         const DEFAULT_MUTXO_PRUNE_DEPTH: usize = 200;
@@ -684,6 +1138,252 @@ impl RPC for NeptuneRPCServer {
             .get_all_own_coins_with_possible_timelocks()
             .await
     }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn list_monitored_utxos(
+        self,
+        _context: tarpc::context::Context,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<MonitoredUtxoInfo> {
+        self.state
+            .lock_guard()
+            .await
+            .list_monitored_utxos(offset, limit)
+            .await
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn list_unspent(self, _context: tarpc::context::Context) -> Vec<UnspentUtxoInfo> {
+        self.state.lock_guard().await.list_unspent().await
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn chain_split_alert(self, _context: tarpc::context::Context) -> Option<ChainSplitAlert> {
+        self.state
+            .lock_guard()
+            .await
+            .net
+            .chain_split_detector
+            .alert()
+            .cloned()
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn storage_scrub_report(self, _context: tarpc::context::Context) -> StorageScrubReport {
+        self.state
+            .lock_guard()
+            .await
+            .chain
+            .archival_state()
+            .storage_scrub_report()
+            .clone()
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn db_stats(self, _context: tarpc::context::Context) -> DbStats {
+        self.state.lock_guard().await.net.db_stats()
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn peerless_alert(self, _context: tarpc::context::Context) -> Option<SystemTime> {
+        self.state
+            .lock_guard()
+            .await
+            .peerless_alert(SystemTime::now())
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn miner_recoveries(self, _context: tarpc::context::Context) -> MinerRecoveries {
+        self.state.lock(|s| s.miner_recoveries).await
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn chunk_dictionary_statistics(
+        self,
+        _context: tarpc::context::Context,
+    ) -> ChunkDictionaryStatistics {
+        self.state
+            .lock_guard()
+            .await
+            .wallet_state
+            .chunk_dictionary_statistics()
+            .await
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read, once per batch of monitored UTXOs
+    async fn verify_all_proofs(self, _context: tarpc::context::Context) -> ProofAuditReport {
+        self.state.audit_wallet_proofs().await
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    ///   * acquires `global_state_lock` for write, if the statistics cache is refreshed
+    async fn mutator_set_statistics(
+        self,
+        _context: tarpc::context::Context,
+    ) -> (BlockHeight, MutatorSetStatistics) {
+        self.state.mutator_set_statistics().await
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read, and then for write
+    async fn export_spending_key(
+        self,
+        _context: tarpc::context::Context,
+        export_passphrase: String,
+    ) -> Result<EncryptedWalletSecret, SpendingKeyTransferError> {
+        if self.state.cli().disable_spending_key_export {
+            return Err(SpendingKeyTransferError::Disabled);
+        }
+
+        // The key derivation takes seconds, so it must not hold the lock or
+        // block the runtime
+        let wallet_secret = self
+            .state
+            .lock_guard()
+            .await
+            .wallet_state
+            .wallet_secret
+            .clone();
+        let encrypted_secret = tokio::task::spawn_blocking(move || {
+            EncryptedWalletSecret::encrypt(&wallet_secret, &export_passphrase)
+        })
+        .await
+        .map_err(|err| SpendingKeyTransferError::Failed(err.to_string()))?
+        .map_err(|err| SpendingKeyTransferError::Failed(err.to_string()))?;
+        self.state
+            .lock_guard_mut()
+            .await
+            .wallet_state
+            .record_wallet_secret_export(Timestamp::now());
+
+        Ok(encrypted_secret)
+    }
+
+    async fn import_spending_key(
+        self,
+        _context: tarpc::context::Context,
+        encrypted_secret: EncryptedWalletSecret,
+        passphrase: String,
+        data_directory: PathBuf,
+    ) -> Result<PathBuf, SpendingKeyTransferError> {
+        if self.state.cli().disable_spending_key_export {
+            return Err(SpendingKeyTransferError::Disabled);
+        }
+
+        let cli = self.state.cli().clone();
+        let import = move || -> Result<PathBuf> {
+            // Clients may only write below this node's data directory
+            if data_directory.as_os_str().is_empty()
+                || !data_directory
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+            {
+                bail!(
+                    "Data directory {} must be relative to the node's data directory, without `..`",
+                    data_directory.display()
+                );
+            }
+            let own_data_directory = DataDirectory::get(cli.data_dir, cli.network)?;
+            let wallet_directory_path = DataDirectory::get(
+                Some(own_data_directory.root_dir_path().join(data_directory)),
+                cli.network,
+            )?
+            .wallet_directory_path();
+
+            let wallet_secret = encrypted_secret.decrypt(&passphrase)?;
+            wallet_secret.initialize_wallet_directory(&wallet_directory_path)?;
+
+            Ok(wallet_directory_path)
+        };
+
+        // Decryption derives the key just like the export does
+        let wallet_directory_path = tokio::task::spawn_blocking(import)
+            .await
+            .map_err(|err| SpendingKeyTransferError::Failed(err.to_string()))?
+            .map_err(|err| SpendingKeyTransferError::Failed(err.to_string()))?;
+        info!(
+            "Imported wallet secret into {}",
+            wallet_directory_path.display()
+        );
+
+        Ok(wallet_directory_path)
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for write
+    async fn set_label(
+        self,
+        _context: tarpc::context::Context,
+        target: LabelTarget,
+        label: String,
+    ) -> bool {
+        match self
+            .state
+            .lock_guard_mut()
+            .await
+            .set_label(target, label)
+            .await
+        {
+            Ok(was_set) => was_set,
+            Err(err) => {
+                error!("Could not set label: {err}");
+                false
+            }
+        }
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for read
+    async fn watched_addresses(self, _context: tarpc::context::Context) -> Vec<WatchedAddress> {
+        self.state
+            .lock_guard()
+            .await
+            .wallet_state
+            .get_watched_addresses()
+            .await
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for write
+    async fn add_watch_address(
+        self,
+        _context: tarpc::context::Context,
+        address: generation_address::ReceivingAddress,
+        label: String,
+    ) -> bool {
+        self.state
+            .lock_guard_mut()
+            .await
+            .wallet_state
+            .add_watch_address(address, label)
+            .await
+    }
+
+    /// Locking:
+    ///   * acquires `global_state_lock` for write
+    async fn remove_watch_address(
+        self,
+        _context: tarpc::context::Context,
+        address: generation_address::ReceivingAddress,
+    ) -> bool {
+        self.state
+            .lock_guard_mut()
+            .await
+            .wallet_state
+            .remove_watch_address(&address)
+            .await
+    }
 }
 
 #[cfg(test)]
@@ -691,14 +1391,21 @@ mod rpc_server_tests {
     use super::*;
     use crate::Block;
     use crate::{
-        config_models::network::Network,
-        models::{peer::PeerSanctionReason, state::wallet::WalletSecret},
+        config_models::{cli_args, network::Network},
+        models::{
+            peer::PeerSanctionReason,
+            state::wallet::{watched_address::WalletEvent, WalletSecret},
+        },
         rpc_server::NeptuneRPCServer,
-        tests::shared::mock_genesis_global_state,
+        tests::shared::{
+            make_mock_block, make_mock_transaction, mock_genesis_global_state,
+            unit_test_data_directory,
+        },
         RPC_CHANNEL_CAPACITY,
     };
     use anyhow::Result;
     use num_traits::{One, Zero};
+    use rand::{thread_rng, Rng};
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
     use strum::IntoEnumIterator;
     use tracing_test::traced_test;
@@ -759,14 +1466,67 @@ mod rpc_server_tests {
             .clone()
             .block_digest(ctx, BlockSelector::Digest(Digest::default()))
             .await;
+        let _ = rpc_server
+            .clone()
+            .get_block_by_height(ctx, BlockHeight::genesis())
+            .await;
+        let _ = rpc_server
+            .clone()
+            .get_block_by_hash(ctx, Digest::default())
+            .await;
         let _ = rpc_server.clone().utxo_digest(ctx, 0).await;
         let _ = rpc_server.clone().synced_balance(ctx).await;
         let _ = rpc_server.clone().history(ctx).await;
+        let _ = rpc_server
+            .clone()
+            .find_history(ctx, "label".to_string())
+            .await;
+        let _ = rpc_server
+            .clone()
+            .resolve_digest(ctx, "ab".to_string())
+            .await;
+        let _ = rpc_server
+            .clone()
+            .get_historical_balance(ctx, HeightOrTimestamp::Height(BlockHeight::genesis()))
+            .await;
+        let _ = rpc_server
+            .clone()
+            .get_historical_balance(ctx, HeightOrTimestamp::Timestamp(Timestamp::now()))
+            .await;
         let _ = rpc_server.clone().wallet_status(ctx).await;
         let own_receiving_address = rpc_server.clone().own_receiving_address(ctx).await;
         let _ = rpc_server.clone().mempool_tx_count(ctx).await;
         let _ = rpc_server.clone().mempool_size(ctx).await;
+        let _ = rpc_server.clone().mempool_list(ctx, 0, 10).await;
+        let _ = rpc_server.clone().mempool_get(ctx, Digest::default()).await;
+        let _ = rpc_server.clone().mempool_stats(ctx).await;
         let _ = rpc_server.clone().dashboard_overview_data(ctx).await;
+        let _ = rpc_server.clone().list_monitored_utxos(ctx, 0, 10).await;
+        let _ = rpc_server.clone().list_unspent(ctx).await;
+        let _ = rpc_server.clone().mutator_set_statistics(ctx).await;
+        let _ = rpc_server.clone().chunk_dictionary_statistics(ctx).await;
+        let _ = rpc_server.clone().verify_all_proofs(ctx).await;
+        let _ = rpc_server.clone().watched_addresses(ctx).await;
+        let _ = rpc_server.clone().chain_split_alert(ctx).await;
+        let _ = rpc_server.clone().peerless_alert(ctx).await;
+        let _ = rpc_server.clone().miner_recoveries(ctx).await;
+        let _ = rpc_server.clone().storage_scrub_report(ctx).await;
+        let _ = rpc_server.clone().db_stats(ctx).await;
+        if let Ok(encrypted_secret) = rpc_server
+            .clone()
+            .export_spending_key(ctx, "passphrase".to_string())
+            .await
+        {
+            let _ = rpc_server
+                .clone()
+                .import_spending_key(
+                    ctx,
+                    encrypted_secret,
+                    "wrong passphrase".to_string(),
+                    unit_test_data_directory(network)?.root_dir_path(),
+                )
+                .await;
+        }
         let _ = rpc_server
             .clone()
             .validate_address(ctx, "Not a valid address".to_owned(), Network::Testnet)
@@ -783,14 +1543,36 @@ mod rpc_server_tests {
                 NeptuneCoins::one(),
                 own_receiving_address,
                 NeptuneCoins::one(),
+                Some("label".to_string()),
             )
             .await;
+        let _ = rpc_server
+            .clone()
+            .send_raw_transaction(ctx, make_mock_transaction(vec![], vec![]))
+            .await;
+        let _ = rpc_server
+            .clone()
+            .set_label(ctx, LabelTarget::MonitoredUtxo(0), "label".to_string())
+            .await;
         let _ = rpc_server.clone().pause_miner(ctx).await;
         let _ = rpc_server.clone().restart_miner(ctx).await;
+        let _ = rpc_server
+            .clone()
+            .quiesce(ctx, Duration::from_secs(1))
+            .await;
+        let _ = rpc_server.clone().resume(ctx).await;
         let _ = rpc_server
             .clone()
             .prune_abandoned_monitored_utxos(ctx)
             .await;
+        let _ = rpc_server
+            .clone()
+            .add_watch_address(ctx, own_receiving_address, "self".to_string())
+            .await;
+        let _ = rpc_server
+            .clone()
+            .remove_watch_address(ctx, own_receiving_address)
+            .await;
         let _ = rpc_server.shutdown(ctx).await;
 
         Ok(())
@@ -869,11 +1651,9 @@ mod rpc_server_tests {
             let mut global_state_mut = state_lock.lock_guard_mut().await;
 
             global_state_mut
-                .net
                 .write_peer_standing_on_decrease(peer_address_0.ip(), standing_0)
                 .await;
             global_state_mut
-                .net
                 .write_peer_standing_on_decrease(peer_address_1.ip(), standing_1)
                 .await;
         }
@@ -896,14 +1676,14 @@ mod rpc_server_tests {
                 .net
                 .get_peer_standing_from_database(peer_address_0.ip())
                 .await;
-            assert_ne!(0, peer_standing_0.unwrap().standing);
-            assert_ne!(None, peer_standing_0.unwrap().latest_sanction);
+            assert_ne!(0, peer_standing_0.standing);
+            assert_ne!(None, peer_standing_0.latest_sanction);
             let peer_standing_1 = global_state
                 .net
                 .get_peer_standing_from_database(peer_address_1.ip())
                 .await;
-            assert_ne!(0, peer_standing_1.unwrap().standing);
-            assert_ne!(None, peer_standing_1.unwrap().latest_sanction);
+            assert_ne!(0, peer_standing_1.standing);
+            assert_ne!(None, peer_standing_1.latest_sanction);
             drop(global_state);
 
             // Clear standing of #0
@@ -920,14 +1700,14 @@ mod rpc_server_tests {
                 .net
                 .get_peer_standing_from_database(peer_address_0.ip())
                 .await;
-            assert_eq!(0, peer_standing_0.unwrap().standing);
-            assert_eq!(None, peer_standing_0.unwrap().latest_sanction);
+            assert_eq!(0, peer_standing_0.standing);
+            assert_eq!(None, peer_standing_0.latest_sanction);
             let peer_standing_1 = global_state
                 .net
                 .get_peer_standing_from_database(peer_address_1.ip())
                 .await;
-            assert_ne!(0, peer_standing_1.unwrap().standing);
-            assert_ne!(None, peer_standing_1.unwrap().latest_sanction);
+            assert_ne!(0, peer_standing_1.standing);
+            assert_ne!(None, peer_standing_1.latest_sanction);
 
             // Verify expected resulting conditions in peer map
             let peer_standing_0_from_memory = global_state.net.peer_map[&peer_address_0].clone();
@@ -949,6 +1729,257 @@ mod rpc_server_tests {
         Ok(())
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn export_and_import_spending_key_test() -> Result<()> {
+        let network = Network::RegTest;
+        let wallet_secret = WalletSecret::new_random();
+        let (rpc_server, mut global_state_lock) =
+            test_rpc_server(network, wallet_secret.clone(), 2).await;
+        let ctx = context::current();
+        let mut wallet_events = global_state_lock
+            .lock_guard()
+            .await
+            .wallet_state
+            .subscribe_to_wallet_events();
+
+        // Every export is announced
+        let encrypted_secret = rpc_server
+            .clone()
+            .export_spending_key(ctx, "correct horse".to_string())
+            .await?;
+        assert!(matches!(
+            wallet_events.try_recv(),
+            Ok(WalletEvent::WalletSecretExported {
+                export_count: 1,
+                ..
+            })
+        ));
+
+        // Imports are written below the node's data directory
+        let own_data_directory = unit_test_data_directory(network)?;
+        let cli = cli_args::Args {
+            data_dir: Some(own_data_directory.root_dir_path()),
+            ..global_state_lock.cli().clone()
+        };
+        global_state_lock.set_cli(cli).await;
+        let rpc_server = NeptuneRPCServer {
+            state: global_state_lock.clone(),
+            ..rpc_server
+        };
+        let relative_path = PathBuf::from("imported");
+        let data_directory = DataDirectory::get(
+            Some(own_data_directory.root_dir_path().join(&relative_path)),
+            network,
+        )?;
+
+        // Paths outside of the node's data directory are rejected
+        for outside_path in [
+            unit_test_data_directory(network)?.root_dir_path(),
+            PathBuf::from("../imported"),
+            PathBuf::new(),
+        ] {
+            assert!(rpc_server
+                .clone()
+                .import_spending_key(
+                    ctx,
+                    encrypted_secret.clone(),
+                    "correct horse".to_string(),
+                    outside_path,
+                )
+                .await
+                .is_err());
+        }
+
+        // A wrong passphrase does not touch the data directory
+        assert!(rpc_server
+            .clone()
+            .import_spending_key(
+                ctx,
+                encrypted_secret.clone(),
+                "battery staple".to_string(),
+                relative_path.clone(),
+            )
+            .await
+            .is_err());
+        assert!(!data_directory.wallet_directory_path().exists());
+
+        // The right passphrase initializes the wallet directory, once
+        let wallet_directory_path = rpc_server
+            .clone()
+            .import_spending_key(
+                ctx,
+                encrypted_secret.clone(),
+                "correct horse".to_string(),
+                relative_path.clone(),
+            )
+            .await?;
+        assert_eq!(
+            data_directory.wallet_directory_path(),
+            wallet_directory_path
+        );
+        let (imported_secret, _) = WalletSecret::read_from_file_or_create(&wallet_directory_path)?;
+        assert_eq!(wallet_secret, imported_secret);
+        assert!(rpc_server
+            .clone()
+            .import_spending_key(
+                ctx,
+                encrypted_secret.clone(),
+                "correct horse".to_string(),
+                relative_path,
+            )
+            .await
+            .is_err());
+
+        // Hardened nodes refuse both
+        let cli = cli_args::Args {
+            disable_spending_key_export: true,
+            ..global_state_lock.cli().clone()
+        };
+        global_state_lock.set_cli(cli).await;
+        let hardened_rpc_server = NeptuneRPCServer {
+            state: global_state_lock.clone(),
+            ..rpc_server
+        };
+        assert_eq!(
+            Err(SpendingKeyTransferError::Disabled),
+            hardened_rpc_server
+                .clone()
+                .export_spending_key(ctx, "correct horse".to_string())
+                .await
+        );
+        assert_eq!(
+            Err(SpendingKeyTransferError::Disabled),
+            hardened_rpc_server
+                .import_spending_key(
+                    ctx,
+                    encrypted_secret,
+                    "correct horse".to_string(),
+                    PathBuf::from("hardened"),
+                )
+                .await
+        );
+        assert!(wallet_events.try_recv().is_err());
+
+        Ok(())
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn send_raw_transaction_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::new_random()).await;
+        let (rpc_server_to_main_tx, mut rpc_server_to_main_rx) =
+            tokio::sync::mpsc::channel::<RPCServerToMain>(RPC_CHANNEL_CAPACITY);
+        let rpc_server = NeptuneRPCServer {
+            socket_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080),
+            state: global_state_lock.clone(),
+            rpc_server_to_main_tx,
+        };
+        let tip_mutator_set_hash = global_state_lock
+            .lock_guard()
+            .await
+            .chain
+            .light_state()
+            .kernel
+            .body
+            .mutator_set_accumulator
+            .hash();
+
+        // A transaction synced to another mutator set is rejected
+        let mut transaction = make_mock_transaction(vec![], vec![]);
+        let stale_mutator_set_hash = transaction.kernel.mutator_set_hash;
+        assert_ne!(tip_mutator_set_hash, stale_mutator_set_hash);
+        assert_eq!(
+            Err(SendRawTransactionError::StaleMutatorSet {
+                transaction: stale_mutator_set_hash,
+                tip: tip_mutator_set_hash,
+            }),
+            rpc_server
+                .clone()
+                .send_raw_transaction(context::current(), transaction.clone())
+                .await
+        );
+        assert!(rpc_server_to_main_rx.try_recv().is_err());
+
+        // Once synced to the tip, it is handed to the main loop
+        transaction.kernel.mutator_set_hash = tip_mutator_set_hash;
+        assert_eq!(
+            Ok(Hash::hash(&transaction)),
+            rpc_server
+                .send_raw_transaction(context::current(), transaction.clone())
+                .await
+        );
+        match rpc_server_to_main_rx.try_recv() {
+            Ok(RPCServerToMain::Send(sent)) => assert_eq!(transaction, *sent),
+            _ => panic!("Transaction must be sent to the main loop"),
+        }
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn quiesce_and_resume_test() {
+        let network = Network::RegTest;
+        let global_state_lock =
+            mock_genesis_global_state(network, 2, WalletSecret::new_random()).await;
+        let (rpc_server_to_main_tx, mut rpc_server_to_main_rx) =
+            tokio::sync::mpsc::channel::<RPCServerToMain>(RPC_CHANNEL_CAPACITY);
+        let rpc_server = NeptuneRPCServer {
+            socket_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080),
+            state: global_state_lock.clone(),
+            rpc_server_to_main_tx,
+        };
+        let ctx = context::current();
+
+        // Quiescing waits for the state, e.g. a block being applied
+        let global_state_mut = global_state_lock.lock_guard_mut().await;
+        assert!(
+            !rpc_server
+                .clone()
+                .quiesce(ctx, Duration::from_millis(10))
+                .await
+        );
+        drop(global_state_mut);
+        assert!(!global_state_lock.lock(|s| s.net.quiesced).await);
+        assert!(rpc_server_to_main_rx.try_recv().is_err());
+
+        assert!(
+            rpc_server
+                .clone()
+                .quiesce(ctx, Duration::from_secs(10))
+                .await
+        );
+        assert!(matches!(
+            rpc_server_to_main_rx.try_recv(),
+            Ok(RPCServerToMain::Quiesce)
+        ));
+        assert!(
+            rpc_server
+                .clone()
+                .dashboard_overview_data(ctx)
+                .await
+                .quiesced
+        );
+
+        assert!(rpc_server.clone().resume(ctx).await);
+        assert!(matches!(
+            rpc_server_to_main_rx.try_recv(),
+            Ok(RPCServerToMain::Resume)
+        ));
+        assert!(
+            !rpc_server
+                .clone()
+                .dashboard_overview_data(ctx)
+                .await
+                .quiesced
+        );
+
+        // Resuming twice is a no-op
+        assert!(!rpc_server.resume(ctx).await);
+        assert!(rpc_server_to_main_rx.try_recv().is_err());
+    }
+
     #[allow(clippy::shadow_unrelated)]
     #[traced_test]
     #[tokio::test]
@@ -974,11 +2005,9 @@ mod rpc_server_tests {
         };
 
         state
-            .net
             .write_peer_standing_on_decrease(peer_address_0.ip(), standing_0)
             .await;
         state
-            .net
             .write_peer_standing_on_decrease(peer_address_1.ip(), standing_1)
             .await;
 
@@ -992,8 +2021,8 @@ mod rpc_server_tests {
                 .net
                 .get_peer_standing_from_database(peer_address_0.ip())
                 .await;
-            assert_ne!(0, peer_standing_0.unwrap().standing);
-            assert_ne!(None, peer_standing_0.unwrap().latest_sanction);
+            assert_ne!(0, peer_standing_0.standing);
+            assert_ne!(None, peer_standing_0.latest_sanction);
         }
 
         {
@@ -1003,8 +2032,8 @@ mod rpc_server_tests {
                 .net
                 .get_peer_standing_from_database(peer_address_1.ip())
                 .await;
-            assert_ne!(0, peer_standing_1.unwrap().standing);
-            assert_ne!(None, peer_standing_1.unwrap().latest_sanction);
+            assert_ne!(0, peer_standing_1.standing);
+            assert_ne!(None, peer_standing_1.latest_sanction);
         }
 
         // Verify expected reading through an RPC call
@@ -1029,8 +2058,8 @@ mod rpc_server_tests {
                 .net
                 .get_peer_standing_from_database(peer_address_0.ip())
                 .await;
-            assert_eq!(0, peer_standing_0.unwrap().standing);
-            assert_eq!(None, peer_standing_0.unwrap().latest_sanction);
+            assert_eq!(0, peer_standing_0.standing);
+            assert_eq!(None, peer_standing_0.latest_sanction);
         }
 
         {
@@ -1038,8 +2067,8 @@ mod rpc_server_tests {
                 .net
                 .get_peer_standing_from_database(peer_address_1.ip())
                 .await;
-            assert_eq!(0, peer_still_standing_1.unwrap().standing);
-            assert_eq!(None, peer_still_standing_1.unwrap().latest_sanction);
+            assert_eq!(0, peer_still_standing_1.standing);
+            assert_eq!(None, peer_still_standing_1.latest_sanction);
         }
 
         // Verify expected resulting conditions in peer map
@@ -1074,8 +2103,7 @@ mod rpc_server_tests {
             .archival_state()
             .archival_mutator_set
             .ams()
-            .aocl
-            .count_leaves()
+            .aocl_leaf_count()
             .await;
 
         debug_assert!(aocl_leaves > 0);
@@ -1108,12 +2136,14 @@ mod rpc_server_tests {
             global_state.chain.archival_state().genesis_block(),
             genesis_hash,
             tip_hash,
+            network,
         );
 
         let tip_block_info = BlockInfo::from_block_and_digests(
             global_state.chain.light_state(),
             genesis_hash,
             tip_hash,
+            network,
         );
 
         // should find genesis block by Genesis selector
@@ -1236,4 +2266,80 @@ mod rpc_server_tests {
             .await
             .is_none());
     }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn get_block_by_height_and_hash_test() -> Result<()> {
+        let mut rng = thread_rng();
+        let network = Network::RegTest;
+        let (rpc_server, state_lock) =
+            test_rpc_server(network, WalletSecret::new_random(), 2).await;
+        let ctx = context::current();
+        let genesis_block = Block::genesis_block(network);
+        let address = WalletSecret::new_random()
+            .nth_generation_spending_key(0)
+            .to_address();
+
+        // Block 1a is abandoned in favor of blocks 1b and 2b
+        let (block_1a, _, _) = make_mock_block(network, &genesis_block, None, address, rng.gen());
+        let (block_1b, _, _) = make_mock_block(network, &genesis_block, None, address, rng.gen());
+        let (block_2b, _, _) = make_mock_block(network, &block_1b, None, address, rng.gen());
+        {
+            let mut global_state = state_lock.lock_guard_mut().await;
+            for block in [&block_1a, &block_1b, &block_2b] {
+                global_state.set_new_tip(block.clone()).await?;
+            }
+        }
+
+        // Canonical blocks are found by height and by hash
+        for block in [&block_1b, &block_2b] {
+            let expected = Some(TransferBlock::from(block.clone()));
+            assert_eq!(
+                expected,
+                rpc_server
+                    .clone()
+                    .get_block_by_height(ctx, block.kernel.header.height)
+                    .await
+            );
+            assert_eq!(
+                expected,
+                rpc_server
+                    .clone()
+                    .get_block_by_hash(ctx, block.hash())
+                    .await
+            );
+        }
+
+        // The abandoned block is only found by hash
+        assert_eq!(
+            Some(TransferBlock::from(block_1a.clone())),
+            rpc_server
+                .clone()
+                .get_block_by_hash(ctx, block_1a.hash())
+                .await
+        );
+
+        // Nonexistent blocks, and the genesis block, are not found
+        assert!(rpc_server
+            .clone()
+            .get_block_by_height(ctx, BlockHeight::from(3u64))
+            .await
+            .is_none());
+        assert!(rpc_server
+            .clone()
+            .get_block_by_hash(ctx, Digest::default())
+            .await
+            .is_none());
+        assert!(rpc_server
+            .clone()
+            .get_block_by_height(ctx, BlockHeight::genesis())
+            .await
+            .is_none());
+        assert!(rpc_server
+            .get_block_by_hash(ctx, genesis_block.hash())
+            .await
+            .is_none());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,422 @@
+//! The client side of the SOCKS5 protocol (RFC 1928), such that outbound peer
+//! connections can be made through a proxy, e.g. to connect over Tor. Only
+//! the CONNECT command without authentication is supported.
+
+use crate::models::blockchain::shared::Hash;
+use crate::prelude::twenty_first;
+
+use std::fmt;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use twenty_first::math::b_field_element::BFieldElement;
+use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
+
+/// Time a proxy has to complete the SOCKS5 exchange, including its
+/// connection to the target.
+pub const SOCKS5_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SOCKS_VERSION: u8 = 0x05;
+const NO_AUTHENTICATION: u8 = 0x00;
+const CONNECT_COMMAND: u8 = 0x01;
+const RESERVED: u8 = 0x00;
+const SUCCEEDED: u8 = 0x00;
+
+const ADDRESS_TYPE_IPV4: u8 = 0x01;
+const ADDRESS_TYPE_DOMAIN_NAME: u8 = 0x03;
+const ADDRESS_TYPE_IPV6: u8 = 0x04;
+
+/// The OnionCat prefix, under which peers known by domain name get their
+/// stand-in addresses. See [Socks5Target::stand_in_address].
+const STAND_IN_ADDRESS_PREFIX: [u8; 6] = [0xfd, 0x87, 0xd8, 0x7e, 0xeb, 0x43];
+
+/// The destination of a connection through the proxy. Domain names are
+/// resolved by the proxy, which is what makes e.g. onion services reachable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Socks5Target {
+    Address(SocketAddr),
+    DomainName(String, u16),
+}
+
+impl Socks5Target {
+    pub fn port(&self) -> u16 {
+        match self {
+            Socks5Target::Address(address) => address.port(),
+            Socks5Target::DomainName(_name, port) => *port,
+        }
+    }
+
+    /// The address under which the peer at this target is tracked, e.g. in
+    /// the peer map and the peer standings. Targets given by domain name have
+    /// no address of their own, so they are tracked under a unique local IPv6
+    /// address derived from the name, in the range used by OnionCat.
+    pub fn stand_in_address(&self) -> SocketAddr {
+        match self {
+            Socks5Target::Address(address) => *address,
+            Socks5Target::DomainName(name, port) => {
+                let name_as_elements = name
+                    .to_ascii_lowercase()
+                    .bytes()
+                    .map(|byte| BFieldElement::new(byte.into()))
+                    .collect::<Vec<_>>();
+                let name_digest = Hash::hash_varlen(&name_as_elements).values();
+                let mut octets = [0u8; 16];
+                octets[..6].copy_from_slice(&STAND_IN_ADDRESS_PREFIX);
+                octets[6..14].copy_from_slice(&name_digest[0].value().to_be_bytes());
+                octets[14..].copy_from_slice(&name_digest[1].value().to_be_bytes()[6..]);
+                SocketAddr::new(Ipv6Addr::from(octets).into(), *port)
+            }
+        }
+    }
+}
+
+/// Whether `address` is the stand-in address of a peer known by domain name,
+/// which only this node knows how to dial.
+pub fn is_stand_in_address(address: &SocketAddr) -> bool {
+    match address {
+        SocketAddr::V6(address) => address.ip().octets()[..6] == STAND_IN_ADDRESS_PREFIX,
+        SocketAddr::V4(_) => false,
+    }
+}
+
+impl fmt::Display for Socks5Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Socks5Target::Address(address) => write!(f, "{address}"),
+            Socks5Target::DomainName(name, port) => write!(f, "{name}:{port}"),
+        }
+    }
+}
+
+impl FromStr for Socks5Target {
+    type Err = String;
+
+    /// Parse a socket address, or a domain name and a port separated by a
+    /// colon, e.g. `8.8.8.8:9798` or `example.onion:9798`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if let Ok(address) = input.parse::<SocketAddr>() {
+            return Ok(Socks5Target::Address(address));
+        }
+
+        let Some((name, port)) = input.rsplit_once(':') else {
+            return Err(format!("Failed to parse {input} as host:port"));
+        };
+        let port = port
+            .parse()
+            .map_err(|_| format!("Failed to parse the port of {input}"))?;
+        if name.is_empty() || name.contains(':') {
+            return Err(format!("Failed to parse the host of {input}"));
+        }
+
+        Ok(Socks5Target::DomainName(name.to_string(), port))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Socks5Error {
+    #[error("SOCKS5 proxy connection failed: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Proxy does not speak SOCKS5, it replied with version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Proxy requires authentication, which is not supported")]
+    AuthenticationRejected,
+
+    #[error("Proxy could not connect to the target, reply code {0}")]
+    ConnectFailed(u8),
+
+    #[error("Domain name is {0} bytes long; at most 255 bytes are allowed")]
+    DomainNameTooLong(usize),
+
+    #[error("Proxy replied with unknown address type {0}")]
+    UnknownAddressType(u8),
+
+    #[error("Proxy did not complete the SOCKS5 exchange within {0:?}")]
+    Timeout(Duration),
+}
+
+/// Open a TCP connection to `target` through the SOCKS5 proxy at `proxy`.
+pub async fn connect(proxy: SocketAddr, target: &Socks5Target) -> Result<TcpStream, Socks5Error> {
+    let mut stream = TcpStream::connect(proxy).await?;
+    handshake(&mut stream, target).await?;
+
+    Ok(stream)
+}
+
+/// Ask the proxy at the other end of `stream` to connect to `target`. Once
+/// this returns, `stream` carries the connection to `target`. Fails if the
+/// proxy does not answer within [SOCKS5_HANDSHAKE_TIMEOUT].
+pub async fn handshake<S>(stream: &mut S, target: &Socks5Target) -> Result<(), Socks5Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::time::timeout(SOCKS5_HANDSHAKE_TIMEOUT, request_connection(stream, target))
+        .await
+        .map_err(|_| Socks5Error::Timeout(SOCKS5_HANDSHAKE_TIMEOUT))?
+}
+
+async fn request_connection<S>(stream: &mut S, target: &Socks5Target) -> Result<(), Socks5Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Offer no authentication as the only method
+    stream
+        .write_all(&[SOCKS_VERSION, 1, NO_AUTHENTICATION])
+        .await?;
+    let mut method_selection = [0u8; 2];
+    stream.read_exact(&mut method_selection).await?;
+    if method_selection[0] != SOCKS_VERSION {
+        return Err(Socks5Error::UnsupportedVersion(method_selection[0]));
+    }
+    if method_selection[1] != NO_AUTHENTICATION {
+        return Err(Socks5Error::AuthenticationRejected);
+    }
+
+    let mut request = vec![SOCKS_VERSION, CONNECT_COMMAND, RESERVED];
+    match target {
+        Socks5Target::Address(SocketAddr::V4(address)) => {
+            request.push(ADDRESS_TYPE_IPV4);
+            request.extend(address.ip().octets());
+        }
+        Socks5Target::Address(SocketAddr::V6(address)) => {
+            request.push(ADDRESS_TYPE_IPV6);
+            request.extend(address.ip().octets());
+        }
+        Socks5Target::DomainName(name, _port) => {
+            let length =
+                u8::try_from(name.len()).map_err(|_| Socks5Error::DomainNameTooLong(name.len()))?;
+            request.push(ADDRESS_TYPE_DOMAIN_NAME);
+            request.push(length);
+            request.extend(name.as_bytes());
+        }
+    }
+    request.extend(target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    let [version, reply_code, _reserved, address_type] = reply;
+    if version != SOCKS_VERSION {
+        return Err(Socks5Error::UnsupportedVersion(version));
+    }
+    if reply_code != SUCCEEDED {
+        return Err(Socks5Error::ConnectFailed(reply_code));
+    }
+
+    // The reply ends with the address that the proxy bound, which is of no
+    // interest, but must be consumed
+    let bound_address_length = match address_type {
+        ADDRESS_TYPE_IPV4 => 4,
+        ADDRESS_TYPE_IPV6 => 16,
+        ADDRESS_TYPE_DOMAIN_NAME => stream.read_u8().await? as usize,
+        _ => return Err(Socks5Error::UnknownAddressType(address_type)),
+    };
+    let mut bound_address_and_port = vec![0u8; bound_address_length + 2];
+    stream.read_exact(&mut bound_address_and_port).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod socks5_tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+    use tokio_test::io::Builder;
+
+    const GREETING: [u8; 3] = [SOCKS_VERSION, 1, NO_AUTHENTICATION];
+
+    #[tokio::test]
+    async fn connect_through_mock_proxy_test() -> anyhow::Result<()> {
+        // A proxy that accepts the request for an onion service and then
+        // echoes what it receives
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let proxy = listener.local_addr()?;
+        let proxy_task = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await?;
+            assert_eq!(GREETING, greeting);
+            stream
+                .write_all(&[SOCKS_VERSION, NO_AUTHENTICATION])
+                .await?;
+
+            let target = b"example.onion";
+            let mut request = vec![0u8; 5 + target.len() + 2];
+            stream.read_exact(&mut request).await?;
+            assert_eq!(
+                [
+                    SOCKS_VERSION,
+                    CONNECT_COMMAND,
+                    RESERVED,
+                    ADDRESS_TYPE_DOMAIN_NAME,
+                    target.len() as u8
+                ],
+                request[..5]
+            );
+            assert_eq!(target, &request[5..5 + target.len()]);
+            assert_eq!(9798u16.to_be_bytes(), request[5 + target.len()..]);
+            stream
+                .write_all(&[
+                    SOCKS_VERSION,
+                    SUCCEEDED,
+                    RESERVED,
+                    ADDRESS_TYPE_IPV4,
+                    127,
+                    0,
+                    0,
+                    1,
+                    0x1f,
+                    0x90,
+                ])
+                .await?;
+
+            let mut payload = [0u8; 5];
+            stream.read_exact(&mut payload).await?;
+            stream.write_all(&payload).await?;
+            anyhow::Ok(())
+        });
+
+        let mut stream = connect(
+            proxy,
+            &Socks5Target::DomainName("example.onion".to_string(), 9798),
+        )
+        .await?;
+        stream.write_all(b"hello").await?;
+        let mut echo = [0u8; 5];
+        stream.read_exact(&mut echo).await?;
+        assert_eq!(b"hello", &echo);
+        proxy_task.await??;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ipv6_target_is_sent_as_address_test() {
+        let target: SocketAddr = "[2001:db8::1]:9798".parse().unwrap();
+        let mut request = vec![SOCKS_VERSION, CONNECT_COMMAND, RESERVED, ADDRESS_TYPE_IPV6];
+        request.extend(
+            "2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        request.extend(9798u16.to_be_bytes());
+        let mut mock = Builder::new()
+            .write(&GREETING)
+            .read(&[SOCKS_VERSION, NO_AUTHENTICATION])
+            .write(&request)
+            .read(&[
+                SOCKS_VERSION,
+                SUCCEEDED,
+                RESERVED,
+                ADDRESS_TYPE_DOMAIN_NAME,
+                3,
+            ])
+            .read(b"tor")
+            .read(&[0, 0])
+            .build();
+
+        handshake(&mut mock, &Socks5Target::Address(target))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn stalled_proxy_times_out_test() {
+        tokio::time::pause();
+        let mut mock = Builder::new()
+            .write(&GREETING)
+            .wait(SOCKS5_HANDSHAKE_TIMEOUT + Duration::from_secs(1))
+            .build();
+        let target = Socks5Target::Address("10.0.0.1:9798".parse().unwrap());
+
+        assert!(matches!(
+            handshake(&mut mock, &target).await,
+            Err(Socks5Error::Timeout(_))
+        ));
+    }
+
+    #[test]
+    fn target_parses_from_address_or_host_and_port_test() {
+        let address: SocketAddr = "[2001:db8::1]:9798".parse().unwrap();
+        assert_eq!(
+            Ok(Socks5Target::Address(address)),
+            "[2001:db8::1]:9798".parse()
+        );
+        let onion = Socks5Target::DomainName("example.onion".to_string(), 9798);
+        assert_eq!(Ok(onion.clone()), "example.onion:9798".parse());
+        assert_eq!(Ok(onion.clone()), onion.to_string().parse());
+        for invalid in [
+            "example.onion",
+            ":9798",
+            "example.onion:port",
+            "2001:db8::1:9798",
+        ] {
+            assert!(invalid.parse::<Socks5Target>().is_err(), "{invalid}");
+        }
+
+        // Domain names are tracked under a stable address of their own
+        assert_eq!(address, Socks5Target::Address(address).stand_in_address());
+        let stand_in = onion.stand_in_address();
+        assert_eq!(stand_in, onion.stand_in_address());
+        assert_eq!(9798, stand_in.port());
+        assert_ne!(
+            stand_in,
+            Socks5Target::DomainName("other.onion".to_string(), 9798).stand_in_address()
+        );
+        assert!(is_stand_in_address(&stand_in));
+        assert!(!is_stand_in_address(&address));
+    }
+
+    #[tokio::test]
+    async fn proxy_requiring_authentication_is_rejected_test() {
+        let mut mock = Builder::new()
+            .write(&GREETING)
+            .read(&[SOCKS_VERSION, 0xff])
+            .build();
+        let target = Socks5Target::Address("10.0.0.1:9798".parse().unwrap());
+
+        assert!(matches!(
+            handshake(&mut mock, &target).await,
+            Err(Socks5Error::AuthenticationRejected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn refused_connection_is_reported_test() {
+        let connection_refused = 0x05;
+        let mut mock = Builder::new()
+            .write(&GREETING)
+            .read(&[SOCKS_VERSION, NO_AUTHENTICATION])
+            .write(&[
+                SOCKS_VERSION,
+                CONNECT_COMMAND,
+                RESERVED,
+                ADDRESS_TYPE_IPV4,
+                10,
+                0,
+                0,
+                1,
+                0x26,
+                0x46,
+            ])
+            .read(&[
+                SOCKS_VERSION,
+                connection_refused,
+                RESERVED,
+                ADDRESS_TYPE_IPV4,
+            ])
+            .build();
+        let target = Socks5Target::Address("10.0.0.1:9798".parse().unwrap());
+
+        assert!(matches!(
+            handshake(&mut mock, &target).await,
+            Err(Socks5Error::ConnectFailed(reply_code)) if reply_code == connection_refused
+        ));
+    }
+}
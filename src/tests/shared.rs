@@ -28,11 +28,14 @@ use rand::SeedableRng;
 use std::path::Path;
 use std::path::PathBuf;
 use std::time::SystemTime;
-use std::{collections::HashMap, env, net::SocketAddr, pin::Pin, str::FromStr};
+use std::{
+    collections::HashMap, collections::HashSet, env, net::SocketAddr, pin::Pin, str::FromStr,
+};
 use tasm_lib::twenty_first::util_types::mmr::mmr_accumulator::MmrAccumulator;
 use tokio::sync::{broadcast, mpsc};
 use tokio_serde::{formats::SymmetricalBincode, Serializer};
 use tokio_util::codec::{Encoder, LengthDelimitedCodec};
+use tracing::warn;
 use twenty_first::math::bfield_codec::BFieldCodec;
 use twenty_first::math::digest::Digest;
 use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
@@ -46,7 +49,6 @@ use crate::config_models::network::Network;
 use crate::database::NeptuneLevelDb;
 use crate::models::blockchain::block::block_body::BlockBody;
 use crate::models::blockchain::block::block_header::BlockHeader;
-use crate::models::blockchain::block::block_header::TARGET_BLOCK_INTERVAL;
 use crate::models::blockchain::block::{block_height::BlockHeight, Block};
 use crate::models::blockchain::transaction::primitive_witness::PrimitiveWitness;
 use crate::models::blockchain::transaction::transaction_kernel::pseudorandom_option;
@@ -62,8 +64,12 @@ use crate::models::channel::{MainToPeerThread, PeerThreadToMain};
 use crate::models::database::BlockIndexKey;
 use crate::models::database::BlockIndexValue;
 use crate::models::database::PeerDatabases;
-use crate::models::peer::{HandshakeData, PeerInfo, PeerMessage, PeerStanding};
+use crate::models::peer::{
+    HandshakeData, PeerFeatures, PeerInfo, PeerMessage, PeerStanding, PeerStatistics,
+    MIN_SUPPORTED_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
 use crate::models::state::archival_state::ArchivalState;
+use crate::models::state::block_cache::DEFAULT_BLOCK_CACHE_CAPACITY;
 use crate::models::state::blockchain_state::{BlockchainArchivalState, BlockchainState};
 use crate::models::state::light_state::LightState;
 use crate::models::state::mempool::Mempool;
@@ -73,6 +79,7 @@ use crate::models::state::wallet::wallet_state::WalletState;
 use crate::models::state::wallet::WalletSecret;
 use crate::models::state::GlobalStateLock;
 use crate::models::state::UtxoReceiverData;
+use crate::peer_traffic_capture::{CapturedPeerMessage, TrafficDirection};
 use crate::util_types::mutator_set::addition_record::pseudorandom_addition_record;
 use crate::util_types::mutator_set::addition_record::AdditionRecord;
 use crate::util_types::mutator_set::chunk_dictionary::pseudorandom_chunk_dictionary;
@@ -123,8 +130,10 @@ pub fn get_dummy_peer(address: SocketAddr) -> PeerInfo {
         last_seen: SystemTime::now(),
         standing: PeerStanding::default(),
         version: get_dummy_version(),
+        protocol_version: PROTOCOL_VERSION,
         port_for_incoming_connections: Some(8080),
         is_archival_node: true,
+        statistics: PeerStatistics::default(),
     }
 }
 
@@ -140,7 +149,13 @@ pub async fn get_dummy_handshake_data_for_genesis(network: Network) -> Handshake
         listen_port: Some(8080),
         network,
         version: get_dummy_version(),
-        is_archival_node: true,
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_SUPPORTED_PROTOCOL_VERSION,
+        features: PeerFeatures::ARCHIVAL
+            .with(PeerFeatures::TRANSACTION_RELAY)
+            .with(PeerFeatures::PING)
+            .with(PeerFeatures::BLOCK_SERVING_QUOTA)
+            .with(PeerFeatures::BLOCK_BATCH_BY_HEIGHT),
     }
 }
 
@@ -325,12 +340,26 @@ impl std::error::Error for MockError {}
 pub enum Action<Item> {
     Read(Item),
     Write(Item),
+    /// Expect a write of an item of the given type, see [`MockItemType`]
+    WriteOfType(String),
     // Todo: Some tests with these things
     // Wait(Duration),
     // ReadError(Option<Arc<io::Error>>),
     // WriteError(Option<Arc<io::Error>>),
 }
 
+/// Items that a [`Mock`] can match by type only, for when the exact item to
+/// expect is unknown.
+pub trait MockItemType {
+    fn item_type(&self) -> String;
+}
+
+impl MockItemType for PeerMessage {
+    fn item_type(&self) -> String {
+        self.get_type()
+    }
+}
+
 impl<Item> Mock<Item> {
     pub fn new(actions: Vec<Action<Item>>) -> Mock<Item> {
         Mock {
@@ -339,7 +368,7 @@ impl<Item> Mock<Item> {
     }
 }
 
-impl<Item: PartialEq> sink::Sink<Item> for Mock<Item> {
+impl<Item: PartialEq + MockItemType> sink::Sink<Item> for Mock<Item> {
     type Error = MockError;
 
     fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -350,6 +379,8 @@ impl<Item: PartialEq> sink::Sink<Item> for Mock<Item> {
         match (self.actions.pop(), item) {
             (Some(Action::Write(a)), item) if item == a => Ok(()),
             (Some(Action::Write(_)), _) => Err(MockError::WrongSend),
+            (Some(Action::WriteOfType(t)), item) if item.item_type() == t => Ok(()),
+            (Some(Action::WriteOfType(_)), _) => Err(MockError::WrongSend),
             _ => Err(MockError::UnexpectedSend),
         }
     }
@@ -378,6 +409,57 @@ impl<Item> stream::Stream for Mock<Item> {
     }
 }
 
+/// Convert a captured peer traffic transcript into the script of a [`Mock`]
+/// peer, such that the captured exchange can be replayed against a peer loop.
+/// Messages received from the peer become reads, and messages sent to it
+/// become expected writes. Messages captured without a raw frame, because they
+/// were redacted or too large, cannot be reconstructed: inbound ones are
+/// skipped, and outbound ones are only matched by type.
+pub fn replay_actions(transcript: &[CapturedPeerMessage]) -> Result<Vec<Action<PeerMessage>>> {
+    let mut actions = vec![];
+    for captured_message in transcript {
+        let Some(raw_frame) = &captured_message.raw_frame else {
+            match captured_message.direction {
+                TrafficDirection::Inbound => warn!(
+                    "Captured {} message has no raw frame. Skipping it in replay.",
+                    captured_message.message_type
+                ),
+                TrafficDirection::Outbound => {
+                    actions.push(Action::WriteOfType(captured_message.message_type.clone()))
+                }
+            }
+            continue;
+        };
+        let message: PeerMessage = bincode::deserialize(raw_frame)?;
+        actions.push(match captured_message.direction {
+            TrafficDirection::Inbound => Action::Read(message),
+            TrafficDirection::Outbound => Action::Write(message),
+        });
+    }
+
+    Ok(actions)
+}
+
+/// Panic if two of the given outputs share both sender randomness and
+/// receiver digest. The check only runs in debug builds.
+pub fn assert_no_sender_randomness_reuse<'a>(
+    receiver_data: impl IntoIterator<Item = &'a UtxoReceiverData>,
+) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let mut seen = HashSet::new();
+    for rd in receiver_data {
+        assert!(
+            seen.insert((rd.sender_randomness, rd.receiver_privacy_digest)),
+            "Sender randomness {} reused for receiver {}",
+            rd.sender_randomness,
+            rd.receiver_privacy_digest
+        );
+    }
+}
+
 pub fn pseudorandom_utxo(seed: [u8; 32]) -> Utxo {
     let mut rng: StdRng = SeedableRng::from_seed(seed);
     Utxo {
@@ -533,158 +615,6 @@ pub fn random_option<T>(thing: T) -> Option<T> {
 //     block.header.block_body_merkle_root = Hash::hash(&block.body);
 // }
 
-/// Add an unsigned (incorrectly signed) devnet input to a transaction
-/// Membership proofs and removal records must be valid against `previous_mutator_set_accumulator`,
-/// not against `next_mutator_set_accumulator`.
-// pub fn add_unsigned_dev_net_input_to_block_transaction(
-//     block: &mut Block,
-//     input_utxo: Utxo,
-//     membership_proof: MsMembershipProof<Hash>,
-//     removal_record: RemovalRecord<Hash>,
-// ) {
-//     let mut tx = block.body.transaction.clone();
-//     let new_devnet_input = DevNetInput {
-//         utxo: input_utxo,
-//         membership_proof: membership_proof.into(),
-//         removal_record: removal_record.clone(),
-//         // We're just using a dummy signature here to type-check. The caller should apply a correct signature to the transaction
-//         signature: Some(ecdsa::Signature::from_str("3044022012048b6ac38277642e24e012267cf91c22326c3b447d6b4056698f7c298fb36202201139039bb4090a7cfb63c57ecc60d0ec8b7483bf0461a468743022759dc50124").unwrap()),
-//     };
-//     tx.kernel.inputs.push(new_devnet_input);
-//     block.body.transaction = tx;
-
-//     // add removal record for this spending
-//     block.body.mutator_set_update.removals.push(removal_record);
-
-//     // Update block mutator set accumulator. We have to apply *all* elements in the `mutator_set_update`
-//     // to the previous mutator set accumulator here, as the removal records need to be updated throughout
-//     // this process. This means that the input membership proof and removal records are expected to be
-//     // valid against `block.body.previous_mutator_set_accumulator`, not against
-//     // `block.body.next_mutator_set_accumulator`
-//     let mut next_mutator_set_accumulator = block.body.previous_mutator_set_accumulator.clone();
-//     block
-//         .body
-//         .mutator_set_update
-//         .apply(&mut next_mutator_set_accumulator)
-//         .expect("MS update application must work");
-//     block.body.next_mutator_set_accumulator = next_mutator_set_accumulator;
-
-//     // update header fields
-//     block.header.mutator_set_hash = block.body.next_mutator_set_accumulator.hash();
-//     block.header.block_body_merkle_root = Hash::hash(&block.body);
-// }
-
-// pub fn add_unsigned_input_to_block(
-//     block: &mut Block,
-//     consumed_utxo: Utxo,
-//     membership_proof: MsMembershipProof<Hash>,
-// ) {
-//     let item = Hash::hash(&consumed_utxo);
-//     let input_removal_record = block
-//         .body
-//         .previous_mutator_set_accumulator
-//         .drop(item, membership_proof);
-//     add_unsigned_dev_net_input_to_block_transaction(
-//         block,
-//         consumed_utxo,
-//         membership_proof,
-//         input_removal_record,
-//     );
-// }
-
-/// Helper function to add an unsigned input to a block's transaction
-// pub async fn add_unsigned_input_to_block_ams(
-//     block: &mut Block,
-//     consumed_utxo: Utxo,
-//     randomness: Digest,
-//     ams: &Arc<tokio::sync::Mutex<RustyArchivalMutatorSet<Hash>>>,
-//     aocl_leaf_index: u64,
-// ) {
-//     let item = Hash::hash(&consumed_utxo);
-//     let input_membership_proof = ams
-//         .lock()
-//         .await
-//         .ams
-//         .restore_membership_proof(&item, &randomness, aocl_leaf_index)
-//         .unwrap();
-
-//     // Sanity check that restored membership proof agrees with AMS
-//     assert!(
-//         ams.lock().await.ams().verify(item, &input_membership_proof),
-//         "Restored MS membership proof must validate against own AMS"
-//     );
-
-//     // Sanity check that restored membership proof agree with block
-//     assert!(
-//         block
-//             .body
-//             .previous_mutator_set_accumulator
-//             .verify(item, &input_membership_proof),
-//         "Restored MS membership proof must validate against input block"
-//     );
-
-//     let input_removal_record = ams
-//         .lock()
-//         .await
-//         .ams
-//         .kernel
-//         .drop(item, &input_membership_proof);
-//     add_unsigned_dev_net_input_to_block_transaction(
-//         block,
-//         consumed_utxo,
-//         input_membership_proof,
-//         input_removal_record,
-//     );
-// }
-
-// /// Create a mock `DevNetInput`
-// ///
-// /// This mock currently contains a lot of things that don't pass block validation.
-// pub fn make_mock_unsigned_devnet_input(amount: Amount, wallet: &WalletSecret) -> DevNetInput {
-//     let mut rng = thread_rng();
-//     let mock_mmr_membership_proof = MmrMembershipProof::new(0, vec![]);
-//     let sender_randomness: Digest = rng.gen();
-//     let receiver_preimage: Digest = rng.gen();
-//     let mock_ms_membership_proof = MsMembershipProof {
-//         sender_randomness,
-//         receiver_preimage,
-//         auth_path_aocl: mock_mmr_membership_proof,
-//         target_chunks: ChunkDictionary::default(),
-//     };
-//     let mut mock_ms_acc = MutatorSetAccumulator::default();
-//     let mock_removal_record = mock_ms_acc.drop(sender_randomness, &mock_ms_membership_proof);
-
-//     let utxo = Utxo {
-//         amount,
-//         public_key: wallet.get_public_key(),
-//     };
-
-//     DevNetInput {
-//         utxo,
-//         membership_proof: mock_ms_membership_proof.into(),
-//         removal_record: mock_removal_record,
-//         // We're just using a dummy signature here to type-check. The caller should apply a correct signature to the transaction
-//         signature: Some(ecdsa::Signature::from_str("3044022012048b6ac38277642e24e012267cf91c22326c3b447d6b4056698f7c298fb36202201139039bb4090a7cfb63c57ecc60d0ec8b7483bf0461a468743022759dc50124").unwrap()),
-//     }
-// }
-
-// pub fn make_mock_signed_valid_tx() -> Transaction {
-//     // Build a transaction
-//     let wallet_1 = new_random_wallet();
-//     let output_amount_1: Amount = 42.into();
-//     let output_1 = Utxo {
-//         amount: output_amount_1,
-//         public_key: wallet_1.get_public_key(),
-//     };
-//     let randomness: Digest = Digest::new(random_elements_array());
-
-//     let input_1 = make_mock_unsigned_devnet_input(42.into(), &wallet_1);
-//     let mut transaction_1 = make_mock_transaction(vec![input_1], vec![(output_1, randomness)]);
-//     transaction_1.sign(&wallet_1);
-
-//     transaction_1
-// }
-
 // TODO: Consider moving this to to the appropriate place in global state,
 // keep fn interface. Can be helper function to `create_transaction`.
 pub async fn make_mock_transaction_with_generation_key(
@@ -764,8 +694,8 @@ pub async fn make_mock_transaction_with_generation_key(
     }
 }
 
-// `make_mock_transaction`, in contrast to `make_mock_transaction2`, assumes you
-// already have created `DevNetInput`s.
+// `make_mock_transaction` wraps the given removal and addition records in a
+// transaction without a valid witness.
 pub fn make_mock_transaction(
     inputs: Vec<RemovalRecord>,
     outputs: Vec<AdditionRecord>,
@@ -825,6 +755,7 @@ pub fn make_mock_transaction_with_wallet(
 ///
 /// Returns (block, coinbase UTXO, Coinbase output randomness)
 pub fn make_mock_block(
+    network: Network,
     previous_block: &Block,
     // target_difficulty: Option<U32s<TARGET_DIFFICULTY_U32_SIZE>>,
     block_timestamp: Option<Timestamp>,
@@ -832,13 +763,30 @@ pub fn make_mock_block(
     seed: [u8; 32],
 ) -> (Block, Utxo, Digest) {
     let mut rng: StdRng = SeedableRng::from_seed(seed);
+    make_mock_block_with_coinbase_randomness(
+        network,
+        previous_block,
+        block_timestamp,
+        coinbase_beneficiary,
+        rng.gen(),
+    )
+}
+
+/// Like [`make_mock_block`], but with the given sender randomness for the
+/// coinbase UTXO, e.g. to mimic the coinbase of a block mined by a wallet.
+pub fn make_mock_block_with_coinbase_randomness(
+    network: Network,
+    previous_block: &Block,
+    block_timestamp: Option<Timestamp>,
+    coinbase_beneficiary: generation_address::ReceivingAddress,
+    coinbase_output_randomness: Digest,
+) -> (Block, Utxo, Digest) {
     let new_block_height: BlockHeight = previous_block.kernel.header.height.next();
 
     // Build coinbase UTXO and associated data
     let lock_script = coinbase_beneficiary.lock_script();
-    let coinbase_amount = Block::get_mining_reward(new_block_height);
+    let coinbase_amount = Block::get_mining_reward(new_block_height, network);
     let coinbase_utxo = Utxo::new(lock_script, coinbase_amount.to_native_coins());
-    let coinbase_output_randomness: Digest = rng.gen();
     let receiver_digest: Digest = coinbase_beneficiary.privacy_digest;
 
     let mut next_mutator_set = previous_block.kernel.body.mutator_set_accumulator.clone();
@@ -853,7 +801,9 @@ pub fn make_mock_block(
 
     let block_timestamp = match block_timestamp {
         Some(ts) => ts,
-        None => previous_block.kernel.header.timestamp + Timestamp::millis(TARGET_BLOCK_INTERVAL),
+        // Mock blocks arrive on target, which keeps chains of RegTest blocks,
+        // whose genesis block is recent, from running ahead of the clock
+        None => previous_block.kernel.header.timestamp + network.target_block_interval(),
     };
 
     let tx_kernel = TransactionKernel {
@@ -896,14 +846,19 @@ pub fn make_mock_block(
     let pow_line = previous_block.kernel.header.proof_of_work_line + block_target_difficulty;
     let pow_family = pow_line;
     let zero = BFieldElement::zero();
-    let target_difficulty = Block::difficulty_control(previous_block, block_timestamp);
+    let target_difficulty = Block::difficulty_control(
+        previous_block,
+        block_timestamp,
+        network.target_block_interval(),
+        network.max_difficulty_adjustment_factor(),
+    );
     let block_header = BlockHeader {
         version: zero,
         height: new_block_height,
         prev_block_digest: previous_block.hash(),
         timestamp: block_body.transaction.kernel.timestamp,
         nonce: [zero, zero, zero],
-        max_block_size: 1_000_000,
+        max_block_size: network.max_block_size(),
         proof_of_work_line: pow_family,
         proof_of_work_family: pow_family,
         difficulty: target_difficulty,
@@ -917,6 +872,7 @@ pub fn make_mock_block(
 }
 
 pub fn make_mock_block_with_valid_pow(
+    network: Network,
     previous_block: &Block,
     block_timestamp: Option<Timestamp>,
     coinbase_beneficiary: generation_address::ReceivingAddress,
@@ -924,6 +880,7 @@ pub fn make_mock_block_with_valid_pow(
 ) -> (Block, Utxo, Digest) {
     let mut rng: StdRng = SeedableRng::from_seed(seed);
     let (mut block, mut utxo, mut digest) = make_mock_block(
+        network,
         previous_block,
         block_timestamp,
         coinbase_beneficiary,
@@ -931,6 +888,7 @@ pub fn make_mock_block_with_valid_pow(
     );
     while !block.has_proof_of_work(previous_block) {
         let (block_new, utxo_new, digest_new) = make_mock_block(
+            network,
             previous_block,
             block_timestamp,
             coinbase_beneficiary,
@@ -944,6 +902,7 @@ pub fn make_mock_block_with_valid_pow(
 }
 
 pub fn make_mock_block_with_invalid_pow(
+    network: Network,
     previous_block: &Block,
     block_timestamp: Option<Timestamp>,
     coinbase_beneficiary: generation_address::ReceivingAddress,
@@ -951,6 +910,7 @@ pub fn make_mock_block_with_invalid_pow(
 ) -> (Block, Utxo, Digest) {
     let mut rng: StdRng = SeedableRng::from_seed(seed);
     let (mut block, mut utxo, mut digest) = make_mock_block(
+        network,
         previous_block,
         block_timestamp,
         coinbase_beneficiary,
@@ -958,6 +918,7 @@ pub fn make_mock_block_with_invalid_pow(
     );
     while block.has_proof_of_work(previous_block) {
         let (block_new, utxo_new, digest_new) = make_mock_block(
+            network,
             previous_block,
             block_timestamp,
             coinbase_beneficiary,
@@ -995,7 +956,14 @@ pub async fn mock_genesis_archival_state(
         .await
         .unwrap();
 
-    let archival_state = ArchivalState::new(data_dir.clone(), block_index_db, ams, network).await;
+    let archival_state = ArchivalState::new(
+        data_dir.clone(),
+        block_index_db,
+        ams,
+        network,
+        DEFAULT_BLOCK_CACHE_CAPACITY,
+    )
+    .await;
 
     (archival_state, peer_db, data_dir)
 }
@@ -1,3 +1,10 @@
+// The mutator set is on the hot path of block validation, so it must log
+// through `tracing` rather than write to stdout or stderr.
+#![cfg_attr(
+    not(test),
+    deny(clippy::print_stdout, clippy::print_stderr, clippy::dbg_macro)
+)]
+
 use std::{error::Error, fmt};
 
 use itertools::Itertools;
@@ -85,9 +92,9 @@ pub fn commit(item: Digest, sender_randomness: Digest, receiver_digest: Digest)
 }
 
 #[cfg(test)]
-mod accumulation_scheme_tests {
-    use accumulation_scheme_tests::ms_membership_proof::MsMembershipProof;
-    use accumulation_scheme_tests::removal_record::RemovalRecord;
+mod mutator_set_tests {
+    use super::ms_membership_proof::MsMembershipProof;
+    use super::removal_record::RemovalRecord;
     use rand::prelude::*;
     use rand::Rng;
     use tasm_lib::twenty_first::util_types::mmr::mmr_trait::Mmr;
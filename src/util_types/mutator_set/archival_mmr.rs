@@ -194,6 +194,13 @@ impl<H: AlgebraicHasher, Storage: StorageVec<Digest>> ArchivalMmr<H, Storage> {
         self.digests.get(node_index).await
     }
 
+    /// Get a leaf from the MMR, or an error if the index is out of range or
+    /// the leaf cannot be read from storage
+    pub async fn try_get_leaf_async(&self, leaf_index: u64) -> anyhow::Result<Digest> {
+        let node_index = shared_advanced::leaf_index_to_node_index(leaf_index);
+        self.digests.try_get(node_index).await
+    }
+
     /// Return membership proof
     pub async fn prove_membership_async(&self, leaf_index: u64) -> MmrMembershipProof<H> {
         // A proof consists of an authentication path
@@ -237,6 +244,12 @@ impl<H: AlgebraicHasher, Storage: StorageVec<Digest>> ArchivalMmr<H, Storage> {
 
         Some(ret)
     }
+
+    /// Remove all leafs from the archival MMR
+    pub async fn clear(&mut self) {
+        self.digests.clear().await;
+        self.fix_dummy_async().await;
+    }
 }
 
 #[cfg(test)]
@@ -253,9 +266,9 @@ pub(crate) mod mmr_test {
     use crate::database::storage::storage_schema::SimpleRustyStorage;
     use crate::database::storage::storage_vec::OrdinaryVec;
     use crate::database::NeptuneLevelDb;
+    use crate::models::blockchain::shared::Hash;
     use twenty_first::math::b_field_element::BFieldElement;
     use twenty_first::math::other::*;
-    use twenty_first::math::tip5::Tip5;
     use twenty_first::util_types::merkle_tree::*;
     use twenty_first::util_types::merkle_tree_maker::MerkleTreeMaker;
     use twenty_first::util_types::mmr::mmr_accumulator::MmrAccumulator;
@@ -295,7 +308,7 @@ pub(crate) mod mmr_test {
         #[derive(Debug, Clone, test_strategy::Arbitrary)]
         pub(crate) struct MerkleTreeToTest {
             #[strategy(arb())]
-            pub tree: MerkleTree<Tip5>,
+            pub tree: MerkleTree<Hash>,
         }
     }
 
@@ -325,20 +338,20 @@ pub(crate) mod mmr_test {
     /// A block can contain an empty list of addition or removal records.
     #[test]
     fn computing_mmr_root_for_no_leaves_produces_some_digest() {
-        root_from_arbitrary_number_of_digests::<Tip5>(&[]);
+        root_from_arbitrary_number_of_digests::<Hash>(&[]);
     }
 
     #[proptest(cases = 30)]
     fn mmr_root_of_arbitrary_number_of_leaves_is_merkle_root_when_number_of_leaves_is_a_power_of_two(
         test_tree: test_tree::MerkleTreeToTest,
     ) {
-        let root = root_from_arbitrary_number_of_digests::<Tip5>(test_tree.tree.leaves());
+        let root = root_from_arbitrary_number_of_digests::<Hash>(test_tree.tree.leaves());
         assert_eq!(test_tree.tree.root(), root);
     }
 
     #[tokio::test]
     async fn empty_mmr_behavior_test() {
-        type H = Tip5;
+        type H = Hash;
 
         let mut archival_mmr: ArchivalMmr<H, Storage> = mock::get_empty_ammr().await;
         let mut accumulator_mmr: MmrAccumulator<H> = MmrAccumulator::<H>::new(vec![]);
@@ -415,7 +428,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn verify_against_correct_peak_test() {
-        type H = Tip5;
+        type H = Hash;
 
         // This test addresses a bug that was discovered late in the development process
         // where it was possible to fake a verification proof by providing a valid leaf
@@ -454,7 +467,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn mutate_leaf_archival_test() {
-        type H = Tip5;
+        type H = Hash;
 
         // Create ArchivalMmr
 
@@ -555,13 +568,13 @@ pub(crate) mod mmr_test {
     }
 
     #[tokio::test]
-    async fn bag_peaks_tip5_test() {
-        bag_peaks_gen::<Tip5>().await;
+    async fn bag_peaks_hash_test() {
+        bag_peaks_gen::<Hash>().await;
     }
 
     #[tokio::test]
     async fn compare_batch_and_individual_leaf_mutation() {
-        type H = Tip5;
+        type H = Hash;
         use rand::seq::SliceRandom;
 
         let mut rng = thread_rng();
@@ -602,7 +615,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn accumulator_mmr_mutate_leaf_test() {
-        type H = Tip5;
+        type H = Hash;
 
         // Verify that upating leafs in archival and in accumulator MMR results in the same peaks
         // and verify that updating all leafs in an MMR results in the expected MMR
@@ -631,7 +644,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn mmr_prove_verify_leaf_mutation_test() {
-        type H = Tip5;
+        type H = Hash;
 
         for size in 1..150 {
             let new_leaf: Digest = random();
@@ -679,7 +692,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn mmr_append_test() {
-        type H = Tip5;
+        type H = Hash;
 
         // Verify that building an MMR iteratively or in *one* function call results in the same MMR
         for size in 1..260 {
@@ -740,7 +753,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn one_input_mmr_test() {
-        type H = Tip5;
+        type H = Hash;
 
         let input_hash = H::hash(&BFieldElement::new(14));
         let new_input_hash = H::hash(&BFieldElement::new(201));
@@ -810,7 +823,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn two_input_mmr_test() {
-        type H = Tip5;
+        type H = Hash;
 
         let num_leaves: u64 = 3;
         let input_digests: Vec<Digest> = random_elements(num_leaves as usize);
@@ -858,7 +871,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn variable_size_tip5_mmr_test() {
-        type H = Tip5;
+        type H = Hash;
 
         let leaf_counts: Vec<u64> = (1..34).collect();
         let node_counts: Vec<u64> = vec![
@@ -920,7 +933,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn remove_last_leaf_test() {
-        type H = Tip5;
+        type H = Hash;
 
         let input_digests: Vec<Digest> = random_elements(12);
         let mut mmr: ArchivalMmr<H, Storage> =
@@ -956,7 +969,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn remove_last_leaf_pbt() {
-        type H = Tip5;
+        type H = Hash;
 
         let small_size: usize = 100;
         let big_size: usize = 350;
@@ -980,7 +993,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn variable_size_tip5_mmr_test2() {
-        type H = Tip5;
+        type H = Hash;
 
         let node_counts: Vec<u64> = vec![
             1, 3, 4, 7, 8, 10, 11, 15, 16, 18, 19, 22, 23, 25, 26, 31, 32, 34, 35, 38, 39, 41, 42,
@@ -1061,7 +1074,7 @@ pub(crate) mod mmr_test {
 
     #[tokio::test]
     async fn leveldb_persist_storage_schema_test() {
-        type H = Tip5;
+        type H = Hash;
 
         let db = NeptuneLevelDb::open_new_test_database(true, None, None, None)
             .await
@@ -5,8 +5,11 @@ use crate::util_types::mutator_set::{get_swbf_indices, MutatorSetError};
 
 use std::collections::{BTreeSet, HashMap};
 use std::error::Error;
+use std::path::Path;
 
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncWriteExt, BufWriter};
 use twenty_first::math::tip5::Digest;
 use twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
 use twenty_first::util_types::mmr;
@@ -20,17 +23,138 @@ use super::chunk_dictionary::ChunkDictionary;
 use super::ms_membership_proof::MsMembershipProof;
 use super::mutator_set_accumulator::MutatorSetAccumulator;
 use super::removal_record::RemovalRecord;
-use super::shared::{BATCH_SIZE, CHUNK_SIZE};
+use super::shared::{BATCH_SIZE, CHUNK_SIZE, NUM_TRIALS, WINDOW_SIZE};
 
+/// The mutator set with the full history of its commitments and chunks, as
+/// kept by archival nodes. Its parts are private, such that the invariants
+/// between them are maintained by the methods below.
 pub struct ArchivalMutatorSet<MmrStorage, ChunkStorage>
 where
     MmrStorage: StorageVec<Digest> + Send + Sync,
     ChunkStorage: StorageVec<Chunk> + Send + Sync,
 {
-    pub aocl: ArchivalMmr<Hash, MmrStorage>,
-    pub swbf_inactive: ArchivalMmr<Hash, MmrStorage>,
-    pub swbf_active: ActiveWindow,
-    pub chunks: ChunkStorage,
+    aocl: ArchivalMmr<Hash, MmrStorage>,
+    swbf_inactive: ArchivalMmr<Hash, MmrStorage>,
+    swbf_active: ActiveWindow,
+    chunks: ChunkStorage,
+}
+
+/// Summarized statistics about the contents of an archival mutator set.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MutatorSetStatistics {
+    /// Number of leafs in the append-only commitment list, i.e. the number of
+    /// items ever added.
+    pub aocl_leaf_count: u64,
+
+    /// Current batch index, see [`ArchivalMutatorSet::get_batch_index_async`].
+    pub batch_index: u64,
+
+    /// Number of chunks in the inactive part of the sliding-window Bloom filter.
+    pub chunk_count: u64,
+
+    /// Number of set indices, counted with multiplicity, across all inactive
+    /// chunks.
+    pub inactive_set_bits: u64,
+
+    /// Largest number of set indices found in any single inactive chunk.
+    pub max_chunk_set_bits: u64,
+
+    /// Number of set indices, counted with multiplicity, in the active window.
+    pub active_window_set_bits: u64,
+}
+
+impl MutatorSetStatistics {
+    /// Estimate the probability that a removal record for an item that was
+    /// never added, or was already removed, verifies anyway, i.e. that all of
+    /// its [`NUM_TRIALS`] indices land on set bits. The fullest of the active
+    /// window and the inactive chunks determines the estimate. Since set bits
+    /// are counted with multiplicity, the estimate errs on the high side.
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        let active_window_density = self.active_window_set_bits as f64 / WINDOW_SIZE as f64;
+        let max_chunk_density = self.max_chunk_set_bits as f64 / CHUNK_SIZE as f64;
+        let density = active_window_density.max(max_chunk_density).min(1.0);
+
+        density.powi(NUM_TRIALS as i32)
+    }
+}
+
+/// Number of additions and removals that were applied while the mutator set
+/// had a given batch index.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchIndexTotals {
+    pub batch_index: u64,
+    pub additions: u64,
+    pub removals: u64,
+}
+
+/// The output format of [`ArchivalMutatorSet::export_statistics`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatisticsExportFormat {
+    Csv,
+
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl StatisticsExportFormat {
+    /// Determine the export format from the file extension: `.json` and
+    /// `.jsonl` produce JSON lines, anything else produces CSV.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") | Some("jsonl") => Self::JsonLines,
+            _ => Self::Csv,
+        }
+    }
+}
+
+/// A single line of a statistics export.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum StatisticsRecord {
+    Chunk {
+        index: u64,
+        set_bits: u64,
+        distinct_set_bits: u64,
+    },
+    ActiveWindowBucket {
+        index: u64,
+        set_bits: u64,
+        distinct_set_bits: u64,
+    },
+    Batch(BatchIndexTotals),
+    Summary(MutatorSetStatistics),
+}
+
+impl StatisticsRecord {
+    pub const CSV_HEADER: &'static str =
+        "record,index,set_bits,distinct_set_bits,additions,removals";
+
+    /// Format this record as a CSV row matching [`Self::CSV_HEADER`]. Cells
+    /// that do not apply to the record type are left empty. The summary is
+    /// written as a `total` row, with the AOCL leaf count as its additions.
+    pub fn to_csv_row(&self) -> String {
+        match self {
+            StatisticsRecord::Chunk {
+                index,
+                set_bits,
+                distinct_set_bits,
+            } => format!("chunk,{index},{set_bits},{distinct_set_bits},,"),
+            StatisticsRecord::ActiveWindowBucket {
+                index,
+                set_bits,
+                distinct_set_bits,
+            } => format!("active_window_bucket,{index},{set_bits},{distinct_set_bits},,"),
+            StatisticsRecord::Batch(totals) => format!(
+                "batch,{},,,{},{}",
+                totals.batch_index, totals.additions, totals.removals
+            ),
+            StatisticsRecord::Summary(statistics) => format!(
+                "total,,{},,{},",
+                statistics.inactive_set_bits + statistics.active_window_set_bits,
+                statistics.aocl_leaf_count
+            ),
+        }
+    }
 }
 
 impl<MmrStorage, ChunkStorage> ArchivalMutatorSet<MmrStorage, ChunkStorage>
@@ -141,6 +265,31 @@ where
     MmrStorage: StorageVec<Digest> + Send + Sync,
     ChunkStorage: StorageVec<Chunk> + StorageVecStream<Chunk> + Send + Sync,
 {
+    /// Wrap storage that may already hold a mutator set. The active window,
+    /// which is stored separately, is empty until restored, see
+    /// [Self::restore].
+    pub async fn from_storage(
+        aocl: MmrStorage,
+        swbf_inactive: MmrStorage,
+        chunks: ChunkStorage,
+    ) -> Self {
+        Self {
+            aocl: ArchivalMmr::new(aocl).await,
+            swbf_inactive: ArchivalMmr::new(swbf_inactive).await,
+            swbf_active: ActiveWindow::new(),
+            chunks,
+        }
+    }
+
+    /// Prepare a mutator set read from storage for use: both MMRs get the
+    /// dummy digest they need owing to 1-indexation if they are new, and the
+    /// active window is set to the given one.
+    pub async fn restore(&mut self, active_window_sbf: Vec<u32>) {
+        self.aocl.fix_dummy_async().await;
+        self.swbf_inactive.fix_dummy_async().await;
+        self.swbf_active.sbf = active_window_sbf;
+    }
+
     pub async fn new_empty(
         aocl: MmrStorage,
         swbf_inactive: MmrStorage,
@@ -159,6 +308,125 @@ where
         }
     }
 
+    /// Compute summarized statistics of the mutator set. Chunks are streamed
+    /// from storage one at a time.
+    pub async fn statistics(&self) -> MutatorSetStatistics {
+        let mut statistics = self.statistics_without_chunks().await;
+
+        let stream = self.chunks.stream_values().await;
+        pin_mut!(stream); // needed for iteration
+
+        while let Some(chunk) = stream.next().await {
+            let set_bits = chunk.relative_indices.len() as u64;
+            statistics.chunk_count += 1;
+            statistics.inactive_set_bits += set_bits;
+            statistics.max_chunk_set_bits = statistics.max_chunk_set_bits.max(set_bits);
+        }
+
+        statistics
+    }
+
+    /// Write per-chunk set-bit counts, an occupancy histogram of the active
+    /// window (one bucket per chunk-sized slice), the supplied per-batch-index
+    /// totals, and a summary to the file at `path`. The format is determined
+    /// from the file extension, see [`StatisticsExportFormat::from_path`].
+    ///
+    /// Chunks are streamed from storage and written as they are read, so the
+    /// inactive part of the SWBF is never held in memory in its entirety.
+    pub async fn export_statistics(
+        &self,
+        path: &Path,
+        batch_totals: &[BatchIndexTotals],
+    ) -> std::io::Result<MutatorSetStatistics> {
+        let format = StatisticsExportFormat::from_path(path);
+        let file = tokio::fs::File::create(path).await?;
+        let mut writer = BufWriter::new(file);
+
+        if format == StatisticsExportFormat::Csv {
+            writer
+                .write_all(format!("{}\n", StatisticsRecord::CSV_HEADER).as_bytes())
+                .await?;
+        }
+
+        let mut statistics = self.statistics_without_chunks().await;
+
+        let stream = self.chunks.stream().await;
+        pin_mut!(stream); // needed for iteration
+
+        while let Some((index, chunk)) = stream.next().await {
+            let set_bits = chunk.relative_indices.len() as u64;
+            statistics.chunk_count += 1;
+            statistics.inactive_set_bits += set_bits;
+            statistics.max_chunk_set_bits = statistics.max_chunk_set_bits.max(set_bits);
+
+            let record = StatisticsRecord::Chunk {
+                index,
+                set_bits,
+                distinct_set_bits: chunk.relative_indices.iter().unique().count() as u64,
+            };
+            Self::write_statistics_record(&mut writer, format, &record).await?;
+        }
+
+        for record in self.active_window_histogram() {
+            Self::write_statistics_record(&mut writer, format, &record).await?;
+        }
+
+        for totals in batch_totals {
+            let record = StatisticsRecord::Batch(*totals);
+            Self::write_statistics_record(&mut writer, format, &record).await?;
+        }
+
+        let record = StatisticsRecord::Summary(statistics.clone());
+        Self::write_statistics_record(&mut writer, format, &record).await?;
+        writer.flush().await?;
+
+        Ok(statistics)
+    }
+
+    /// The statistics that can be computed without reading any chunks.
+    async fn statistics_without_chunks(&self) -> MutatorSetStatistics {
+        MutatorSetStatistics {
+            aocl_leaf_count: self.aocl.count_leaves().await,
+            batch_index: self.get_batch_index_async().await as u64,
+            chunk_count: 0,
+            inactive_set_bits: 0,
+            max_chunk_set_bits: 0,
+            active_window_set_bits: self.swbf_active.sbf.len() as u64,
+        }
+    }
+
+    /// Occupancy of the active window, bucketed into chunk-sized slices.
+    fn active_window_histogram(&self) -> Vec<StatisticsRecord> {
+        let num_buckets = WINDOW_SIZE / CHUNK_SIZE;
+        let mut buckets = vec![vec![]; num_buckets as usize];
+        for location in self.swbf_active.sbf.iter() {
+            buckets[(*location / CHUNK_SIZE) as usize].push(*location);
+        }
+
+        buckets
+            .into_iter()
+            .enumerate()
+            .map(|(index, locations)| StatisticsRecord::ActiveWindowBucket {
+                index: index as u64,
+                set_bits: locations.len() as u64,
+                distinct_set_bits: locations.iter().unique().count() as u64,
+            })
+            .collect()
+    }
+
+    async fn write_statistics_record(
+        writer: &mut BufWriter<tokio::fs::File>,
+        format: StatisticsExportFormat,
+        record: &StatisticsRecord,
+    ) -> std::io::Result<()> {
+        let line = match format {
+            StatisticsExportFormat::Csv => record.to_csv_row(),
+            StatisticsExportFormat::JsonLines => serde_json::to_string(record)?,
+        };
+        writer.write_all(line.as_bytes()).await?;
+        writer.write_all(b"\n").await
+    }
+
     /// Returns an authentication path for an element in the append-only commitment list
     pub async fn get_aocl_authentication_path(
         &self,
@@ -199,6 +467,98 @@ where
         Ok((chunk_auth_path, chunk))
     }
 
+    /// Number of items ever added, i.e. the number of leafs in the
+    /// append-only commitment list.
+    pub async fn aocl_leaf_count(&self) -> u64 {
+        self.aocl.count_leaves().await
+    }
+
+    /// The leaf of the append-only commitment list with the given index, i.e.
+    /// the canonical commitment of the addition record. Panics if the index is
+    /// out of range.
+    pub async fn get_aocl_leaf(&self, leaf_index: u64) -> Digest {
+        self.aocl.get_leaf_async(leaf_index).await
+    }
+
+    /// Number of chunks in the inactive part of the sliding-window Bloom
+    /// filter.
+    pub async fn chunk_count(&self) -> u64 {
+        self.chunks.len().await
+    }
+
+    /// The chunk of the inactive part of the sliding-window Bloom filter with
+    /// the given index. Panics if the index is out of range.
+    pub async fn get_chunk(&self, chunk_index: u64) -> Chunk {
+        self.chunks.get(chunk_index).await
+    }
+
+    /// Only for tests to corrupt a chunk without updating its commitment.
+    #[cfg(test)]
+    pub(crate) async fn set_chunk(&mut self, chunk_index: u64, chunk: Chunk) {
+        self.chunks.set(chunk_index, chunk).await
+    }
+
+    /// The active part of the sliding-window Bloom filter.
+    pub fn active_window(&self) -> &ActiveWindow {
+        &self.swbf_active
+    }
+
+    /// The leafs of the append-only commitment list and the chunks of the
+    /// inactive part of the sliding-window Bloom filter, which along with the
+    /// active window determine the mutator set. All of them are read into
+    /// memory.
+    pub async fn contents(&self) -> (Vec<Digest>, Vec<Chunk>) {
+        let mut aocl_leafs = vec![];
+        for leaf_index in 0..self.aocl.count_leaves().await {
+            aocl_leafs.push(self.aocl.get_leaf_async(leaf_index).await);
+        }
+
+        (aocl_leafs, self.chunks.get_all().await)
+    }
+
+    /// Replace the contents of the mutator set with the given leafs of the
+    /// append-only commitment list, chunks and active window, e.g. to start
+    /// from a snapshot rather than from genesis. The inactive part of the
+    /// sliding-window Bloom filter is rebuilt from the chunks.
+    pub async fn replace_contents(
+        &mut self,
+        aocl_leafs: &[Digest],
+        chunks: Vec<Chunk>,
+        swbf_active: ActiveWindow,
+    ) {
+        self.aocl.clear().await;
+        for leaf in aocl_leafs {
+            self.aocl.append(*leaf).await;
+        }
+
+        self.swbf_inactive.clear().await;
+        self.chunks.clear().await;
+        for chunk in chunks {
+            self.swbf_inactive.append(Hash::hash(&chunk)).await;
+            self.chunks.push(chunk).await;
+        }
+
+        self.swbf_active = swbf_active;
+    }
+
+    /// Verify that the stored chunk with the given index hashes to the leaf
+    /// of the inactive SWBF MMR that commits to it. Used to detect chunks that
+    /// were corrupted on disk, so chunks or leafs that cannot be decoded
+    /// result in an error rather than a panic. Chunks without a commitment
+    /// fail verification.
+    pub async fn verify_chunk(&self, chunk_index: u64) -> anyhow::Result<()> {
+        if chunk_index >= self.swbf_inactive.count_leaves().await {
+            anyhow::bail!("Chunk {chunk_index} has no commitment");
+        }
+        let chunk = self.chunks.try_get(chunk_index).await?;
+        let commitment = self.swbf_inactive.try_get_leaf_async(chunk_index).await?;
+        if Hash::hash(&chunk) != commitment {
+            anyhow::bail!("Chunk {chunk_index} does not match its commitment");
+        }
+
+        Ok(())
+    }
+
     /// Restore membership_proof. If called on someone else's UTXO, this leaks privacy. In this case,
     /// caller is better off using `get_aocl_authentication_path` and `get_chunk_and_auth_path` for the
     /// relevant indices.
@@ -482,6 +842,7 @@ mod archival_mutator_set_tests {
     use rand::rngs::StdRng;
     use rand::{Rng, SeedableRng};
 
+    use crate::tests::shared::random_addition_record;
     use crate::util_types::mutator_set::commit;
     use crate::util_types::mutator_set::removal_record::AbsoluteIndexSet;
     use crate::util_types::mutator_set::shared::{BATCH_SIZE, NUM_TRIALS};
@@ -979,6 +1340,180 @@ mod archival_mutator_set_tests {
         }
     }
 
+    #[tokio::test]
+    async fn mutator_set_rebuilt_from_contents_matches_original_test() {
+        let mut original_rms = empty_rusty_mutator_set().await;
+        let original = original_rms.ams_mut();
+
+        let mut membership_proofs: Vec<MsMembershipProof> = vec![];
+        let mut items: Vec<Digest> = vec![];
+        for _ in 0..130 {
+            let (item, sender_randomness, receiver_preimage) = make_item_and_randomnesses();
+
+            let addition_record = commit(item, sender_randomness, receiver_preimage.hash::<Hash>());
+            let membership_proof = original
+                .prove(item, sender_randomness, receiver_preimage)
+                .await;
+
+            MsMembershipProof::batch_update_from_addition(
+                &mut membership_proofs.iter_mut().collect::<Vec<_>>(),
+                &items,
+                &original.accumulator().await,
+                &addition_record,
+            )
+            .expect("MS membership update must work");
+
+            original.add(&addition_record).await;
+
+            membership_proofs.push(membership_proof);
+            items.push(item);
+        }
+
+        let mut removal_records: Vec<RemovalRecord> = vec![];
+        for (mp, &item) in membership_proofs.iter().zip_eq(items.iter()).take(50) {
+            removal_records.push(original.drop(item, mp).await);
+        }
+        original.batch_remove(removal_records, &mut []).await;
+
+        let (aocl_leafs, chunks) = original.contents().await;
+        assert_eq!(original.aocl_leaf_count().await, aocl_leafs.len() as u64);
+        assert_eq!(original.chunk_count().await, chunks.len() as u64);
+
+        // Replace the contents of a mutator set that already holds an item
+        let mut rebuilt_rms = empty_rusty_mutator_set().await;
+        let rebuilt = rebuilt_rms.ams_mut();
+        rebuilt.add(&random_addition_record()).await;
+        rebuilt
+            .replace_contents(&aocl_leafs, chunks, original.active_window().clone())
+            .await;
+        assert_eq!(original.accumulator().await, rebuilt.accumulator().await);
+        for chunk_index in 0..rebuilt.chunk_count().await {
+            rebuilt.verify_chunk(chunk_index).await.unwrap();
+        }
+
+        // Both evolve alike
+        let addition_record = random_addition_record();
+        original.add(&addition_record).await;
+        rebuilt.add(&addition_record).await;
+        assert_eq!(original.hash().await, rebuilt.hash().await);
+    }
+
+    #[tokio::test]
+    async fn statistics_match_additions_and_removals_test() {
+        let mut rms = empty_rusty_mutator_set().await;
+        let archival_mutator_set = rms.ams_mut();
+
+        let num_additions = 130;
+        let num_removals = 50;
+
+        let mut membership_proofs: Vec<MsMembershipProof> = vec![];
+        let mut items: Vec<Digest> = vec![];
+
+        for _ in 0..num_additions {
+            let (item, sender_randomness, receiver_preimage) = make_item_and_randomnesses();
+
+            let addition_record = commit(item, sender_randomness, receiver_preimage.hash::<Hash>());
+            let membership_proof = archival_mutator_set
+                .prove(item, sender_randomness, receiver_preimage)
+                .await;
+
+            MsMembershipProof::batch_update_from_addition(
+                &mut membership_proofs.iter_mut().collect::<Vec<_>>(),
+                &items,
+                &archival_mutator_set.accumulator().await,
+                &addition_record,
+            )
+            .expect("MS membership update must work");
+
+            archival_mutator_set.add(&addition_record).await;
+
+            membership_proofs.push(membership_proof);
+            items.push(item);
+        }
+
+        let mut removal_records: Vec<RemovalRecord> = vec![];
+        for (mp, &item) in membership_proofs
+            .iter()
+            .zip_eq(items.iter())
+            .take(num_removals)
+        {
+            removal_records.push(archival_mutator_set.drop(item, mp).await);
+        }
+        archival_mutator_set
+            .batch_remove(removal_records, &mut [])
+            .await;
+
+        let statistics = archival_mutator_set.statistics().await;
+        assert_eq!(num_additions as u64, statistics.aocl_leaf_count);
+        assert_eq!(
+            archival_mutator_set.chunks.len().await,
+            statistics.chunk_count
+        );
+        assert_eq!(
+            (num_removals * NUM_TRIALS as usize) as u64,
+            statistics.inactive_set_bits + statistics.active_window_set_bits,
+            "Every removal must set exactly NUM_TRIALS indices"
+        );
+
+        // Export in both formats and verify that the files agree with the summary
+        let batch_totals = vec![BatchIndexTotals {
+            batch_index: statistics.batch_index,
+            additions: num_additions as u64,
+            removals: num_removals as u64,
+        }];
+        let mut rng = rand::thread_rng();
+        let export_dir = std::env::temp_dir().join("neptune-unit-tests");
+        tokio::fs::create_dir_all(&export_dir).await.unwrap();
+
+        let csv_path = export_dir.join(format!("ms-statistics-{}.csv", rng.gen::<u64>()));
+        let exported_statistics = archival_mutator_set
+            .export_statistics(&csv_path, &batch_totals)
+            .await
+            .unwrap();
+        assert_eq!(statistics, exported_statistics);
+        let csv = tokio::fs::read_to_string(&csv_path).await.unwrap();
+        let rows = csv.lines().collect_vec();
+        assert_eq!(StatisticsRecord::CSV_HEADER, rows[0]);
+        assert_eq!(
+            statistics.chunk_count as usize,
+            rows.iter().filter(|row| row.starts_with("chunk,")).count()
+        );
+        assert_eq!(
+            (WINDOW_SIZE / CHUNK_SIZE) as usize,
+            rows.iter()
+                .filter(|row| row.starts_with("active_window_bucket,"))
+                .count()
+        );
+        assert_eq!(
+            StatisticsRecord::Summary(statistics.clone()).to_csv_row(),
+            *rows.last().unwrap()
+        );
+
+        let json_path = export_dir.join(format!("ms-statistics-{}.json", rng.gen::<u64>()));
+        archival_mutator_set
+            .export_statistics(&json_path, &batch_totals)
+            .await
+            .unwrap();
+        let json = tokio::fs::read_to_string(&json_path).await.unwrap();
+        let records: Vec<StatisticsRecord> = json
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let mut set_bits = 0;
+        for record in records.iter() {
+            match record {
+                StatisticsRecord::Chunk { set_bits: s, .. } => set_bits += s,
+                StatisticsRecord::ActiveWindowBucket { set_bits: s, .. } => set_bits += s,
+                StatisticsRecord::Batch(totals) => assert_eq!(batch_totals[0], *totals),
+                StatisticsRecord::Summary(summary) => assert_eq!(statistics, *summary),
+            }
+        }
+        assert_eq!((num_removals * NUM_TRIALS as usize) as u64, set_bits);
+
+        tokio::fs::remove_file(csv_path).await.unwrap();
+        tokio::fs::remove_file(json_path).await.unwrap();
+    }
+
     #[tokio::test]
     async fn archival_set_batch_remove_dynamic_test() {
         let mut rms = empty_rusty_mutator_set().await;
@@ -122,14 +122,14 @@ mod chunk_dict_tests {
     use super::super::archival_mmr::mmr_test::mock;
     use tasm_lib::twenty_first::util_types::algebraic_hasher::AlgebraicHasher;
     use twenty_first::math::other::random_elements;
-    use twenty_first::math::tip5::{Digest, Tip5};
+    use twenty_first::math::tip5::Digest;
     use twenty_first::util_types::mmr::mmr_membership_proof::MmrMembershipProof;
 
     use super::*;
 
     #[tokio::test]
     async fn hash_test() {
-        type H = Tip5;
+        type H = Hash;
 
         let chunkdict0 = ChunkDictionary::default();
         let chunkdict00 = ChunkDictionary::default();
@@ -204,7 +204,7 @@ mod chunk_dict_tests {
         // TODO: You could argue that this test doesn't belong here, as it tests the behavior of
         // an imported library. I included it here, though, because the setup seems a bit clumsy
         // to me so far.
-        type H = Tip5;
+        type H = Hash;
         let s_empty: ChunkDictionary = ChunkDictionary::new(HashMap::new());
         let json = serde_json::to_string(&s_empty).unwrap();
         println!("json = {}", json);
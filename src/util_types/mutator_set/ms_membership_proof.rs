@@ -30,7 +30,8 @@ use super::removal_record::AbsoluteIndexSet;
 use super::removal_record::RemovalRecord;
 use super::shared::{
     get_batch_mutation_argument_for_removal_record,
-    prepare_authenticated_batch_modification_for_removal_record_reversion, BATCH_SIZE, CHUNK_SIZE,
+    prepare_authenticated_batch_modification_for_removal_record_reversion, BatchMutationArgument,
+    BATCH_SIZE, CHUNK_SIZE, WINDOW_SIZE,
 };
 impl Error for MembershipProofError {}
 
@@ -68,6 +69,36 @@ impl MsMembershipProof {
         ))
     }
 
+    /// Check that the membership proof is structurally consistent, without
+    /// verifying it against a mutator set. The authentication paths must fit
+    /// an MMR with at most 2^64 leafs, and the chunk dictionary may only hold
+    /// chunks within the window of the item, each stored under the index of
+    /// the MMR leaf that authenticates it and containing only indices within
+    /// the chunk size.
+    pub fn is_well_formed(&self) -> bool {
+        let path_fits = |mmr_mp: &MmrMembershipProof<Hash>| {
+            mmr_mp.authentication_path.len() < u64::BITS as usize
+        };
+        if !path_fits(&self.auth_path_aocl) {
+            return false;
+        }
+
+        let window_start = self.auth_path_aocl.leaf_index / BATCH_SIZE as u64;
+        let window_end = window_start + (WINDOW_SIZE / CHUNK_SIZE) as u64;
+        self.target_chunks
+            .dictionary
+            .iter()
+            .all(|(chunk_index, (mmr_mp, chunk))| {
+                (window_start..window_end).contains(chunk_index)
+                    && mmr_mp.leaf_index == *chunk_index
+                    && path_fits(mmr_mp)
+                    && chunk
+                        .relative_indices
+                        .iter()
+                        .all(|relative_index| *relative_index < CHUNK_SIZE)
+            })
+    }
+
     /// Update a list of membership proofs in anticipation of an addition. If successful,
     /// return (wrapped in an Ok) a vector of all indices of updated membership proofs.
     pub fn batch_update_from_addition(
@@ -394,8 +425,10 @@ impl MsMembershipProof {
             .iter_mut()
             .map(|mp| &mut mp.target_chunks)
             .collect();
-        let (mutated_chunks_by_mp_indices, mutation_argument) =
-            get_batch_mutation_argument_for_removal_record(removal_record, &mut chunk_dictionaries);
+        let BatchMutationArgument {
+            mutated_chunk_dictionaries: mutated_chunks_by_mp_indices,
+            leaf_mutations,
+        } = get_batch_mutation_argument_for_removal_record(removal_record, &mut chunk_dictionaries);
 
         // Collect all the MMR membership proofs from the chunk dictionaries.
         // Also keep track of which MS membership proof they came from, so the
@@ -416,7 +449,7 @@ impl MsMembershipProof {
         let mutated_mmr_mps =
             mmr::mmr_membership_proof::MmrMembershipProof::batch_update_from_batch_leaf_mutation(
                 &mut own_mmr_mps,
-                mutation_argument,
+                leaf_mutations,
             );
 
         // Keep track of which MS membership proofs that were mutated. This is all those membership
@@ -443,8 +476,10 @@ impl MsMembershipProof {
         // Set all chunk values to the new values and calculate the mutation argument
         // for the batch updating of the MMR membership proofs.
         let mut chunk_dictionaries = vec![&mut self.target_chunks];
-        let (mutated_chunk_dictionary_index, mutation_argument) =
-            get_batch_mutation_argument_for_removal_record(removal_record, &mut chunk_dictionaries);
+        let BatchMutationArgument {
+            mutated_chunk_dictionaries,
+            leaf_mutations,
+        } = get_batch_mutation_argument_for_removal_record(removal_record, &mut chunk_dictionaries);
 
         // update membership proofs
         // Note that *all* membership proofs must be updated. It's not sufficient to update
@@ -463,10 +498,10 @@ impl MsMembershipProof {
         let mutated_mmr_mp_indices: Vec<usize> =
             mmr::mmr_membership_proof::MmrMembershipProof::batch_update_from_batch_leaf_mutation(
                 &mut chunk_mmr_mps,
-                mutation_argument,
+                leaf_mutations,
             );
 
-        Ok(!mutated_mmr_mp_indices.is_empty() || !mutated_chunk_dictionary_index.is_empty())
+        Ok(!mutated_mmr_mp_indices.is_empty() || !mutated_chunk_dictionaries.is_empty())
     }
 
     /// Resets a membership proof to its state prior to updating it
@@ -482,11 +517,13 @@ impl MsMembershipProof {
         // Set all chunk values to the old values and prepare
         // for batch updating of the MMR membership proofs.
         let mut chunk_dictionaries = vec![&mut self.target_chunks];
-        let (mutated_chunk_dictionary_index, batch_membership) =
-            prepare_authenticated_batch_modification_for_removal_record_reversion(
-                removal_record,
-                &mut chunk_dictionaries,
-            );
+        let BatchMutationArgument {
+            mutated_chunk_dictionaries,
+            leaf_mutations,
+        } = prepare_authenticated_batch_modification_for_removal_record_reversion(
+            removal_record,
+            &mut chunk_dictionaries,
+        );
 
         // update MMR membership proofs
         // Note that *all* MMR membership proofs must be updated. It's not sufficient to update
@@ -502,10 +539,10 @@ impl MsMembershipProof {
         let mutated_mmr_mp_indices: Vec<usize> =
             mmr::mmr_membership_proof::MmrMembershipProof::batch_update_from_batch_leaf_mutation(
                 &mut chunk_mmr_mps,
-                batch_membership,
+                leaf_mutations,
             );
 
-        Ok(!mutated_mmr_mp_indices.is_empty() || !mutated_chunk_dictionary_index.is_empty())
+        Ok(!mutated_mmr_mp_indices.is_empty() || !mutated_chunk_dictionaries.is_empty())
     }
 }
 
@@ -557,6 +594,63 @@ mod ms_proof_tests {
     use twenty_first::math::other::random_elements;
     use twenty_first::util_types::mmr::mmr_membership_proof::MmrMembershipProof;
 
+    #[test]
+    fn malformed_membership_proofs_are_detected_test() {
+        let (_item, sender_randomness, receiver_preimage) = make_item_and_randomnesses();
+        let aocl_leaf_index = 20 * BATCH_SIZE as u64;
+        let chunk_index = aocl_leaf_index / BATCH_SIZE as u64;
+        let well_formed = MsMembershipProof {
+            sender_randomness,
+            receiver_preimage,
+            auth_path_aocl: MmrMembershipProof::<Hash>::new(aocl_leaf_index, random_elements(10)),
+            target_chunks: ChunkDictionary::new(HashMap::from([(
+                chunk_index,
+                (
+                    MmrMembershipProof::<Hash>::new(chunk_index, random_elements(5)),
+                    Chunk::from_indices(&[0, CHUNK_SIZE - 1]),
+                ),
+            )])),
+        };
+        assert!(well_formed.is_well_formed());
+
+        let mut too_long_aocl_path = well_formed.clone();
+        too_long_aocl_path.auth_path_aocl.authentication_path = random_elements(64);
+        assert!(!too_long_aocl_path.is_well_formed());
+
+        let mut chunk_before_window = well_formed.clone();
+        let entry = chunk_before_window
+            .target_chunks
+            .dictionary
+            .remove(&chunk_index)
+            .unwrap();
+        chunk_before_window
+            .target_chunks
+            .dictionary
+            .insert(chunk_index - 1, entry);
+        assert!(!chunk_before_window.is_well_formed());
+
+        let mut mismatched_leaf_index = well_formed.clone();
+        mismatched_leaf_index
+            .target_chunks
+            .dictionary
+            .get_mut(&chunk_index)
+            .unwrap()
+            .0
+            .leaf_index += 1;
+        assert!(!mismatched_leaf_index.is_well_formed());
+
+        let mut index_outside_chunk = well_formed;
+        index_outside_chunk
+            .target_chunks
+            .dictionary
+            .get_mut(&chunk_index)
+            .unwrap()
+            .1
+            .relative_indices
+            .push(CHUNK_SIZE);
+        assert!(!index_outside_chunk.is_well_formed());
+    }
+
     #[test]
     fn mp_equality_test() {
         let mut rng = thread_rng();
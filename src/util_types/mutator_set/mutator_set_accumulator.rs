@@ -211,6 +211,18 @@ impl MutatorSetAccumulator {
 impl MutatorSetAccumulator {
     /// Generates a membership proof that will the valid when the item
     /// is added to the mutator set.
+    ///
+    /// The returned proof has an empty chunk dictionary. This is correct even
+    /// if adding the item slides the window: the item gets AOCL index
+    /// `n = self.aocl.count_leaves()`, its Bloom filter indices live in the
+    /// window of batch `n / BATCH_SIZE`, and after adding it the active window
+    /// is exactly that window. So none of the item's indices are inactive
+    /// right after it was added, and the proof verifies without any updates.
+    ///
+    /// From then on, the holder must apply `update_from_addition` for every
+    /// *subsequent* addition, which adds chunk dictionary entries when the
+    /// window slides past the item's indices, and `update_from_remove` for
+    /// every removal. The addition of the item itself must not be applied.
     pub fn prove(
         &self,
         item: Digest,
@@ -499,6 +511,80 @@ mod ms_accumulator_tests {
 
     use super::*;
 
+    #[test]
+    fn prove_at_window_slide_boundaries_test() {
+        let bsize = BATCH_SIZE as u64;
+        for aocl_index in [
+            bsize - 1,
+            bsize,
+            bsize + 1,
+            2 * bsize - 1,
+            2 * bsize,
+            2 * bsize + 1,
+        ] {
+            let mut accumulator = MutatorSetAccumulator::default();
+            for _ in 0..aocl_index {
+                let (other_item, other_sender_randomness, other_receiver_preimage) =
+                    make_item_and_randomnesses();
+                accumulator.add(&commit(
+                    other_item,
+                    other_sender_randomness,
+                    other_receiver_preimage.hash::<Hash>(),
+                ));
+            }
+
+            // Prove and add, then verify without applying any updates
+            let (item, sender_randomness, receiver_preimage) = make_item_and_randomnesses();
+            let addition_record = commit(item, sender_randomness, receiver_preimage.hash::<Hash>());
+            let mut membership_proof =
+                accumulator.prove(item, sender_randomness, receiver_preimage);
+            accumulator.add(&addition_record);
+
+            assert_eq!(aocl_index, membership_proof.auth_path_aocl.leaf_index);
+            assert!(membership_proof.target_chunks.dictionary.is_empty());
+            assert_eq!(aocl_index / bsize, accumulator.get_batch_index());
+
+            // The empty chunk dictionary is only correct because all indices
+            // of the item are still in the active window
+            let window_start = accumulator.get_batch_index() as u128 * CHUNK_SIZE as u128;
+            assert!(
+                membership_proof
+                    .compute_indices(item)
+                    .to_vec()
+                    .into_iter()
+                    .all(|index| index >= window_start),
+                "Indices of the item just added must be in the active window"
+            );
+            assert!(accumulator.verify(item, &membership_proof));
+
+            // Apply the next addition, which may slide the window past some of
+            // the item's indices
+            let (next_item, next_sender_randomness, next_receiver_preimage) =
+                make_item_and_randomnesses();
+            let next_addition_record = commit(
+                next_item,
+                next_sender_randomness,
+                next_receiver_preimage.hash::<Hash>(),
+            );
+            membership_proof
+                .update_from_addition(item, &accumulator, &next_addition_record)
+                .unwrap();
+            accumulator.add(&next_addition_record);
+
+            let next_window_start = accumulator.get_batch_index() as u128 * CHUNK_SIZE as u128;
+            let has_inactive_indices = membership_proof
+                .compute_indices(item)
+                .to_vec()
+                .into_iter()
+                .any(|index| index < next_window_start);
+            assert_eq!(
+                has_inactive_indices,
+                !membership_proof.target_chunks.dictionary.is_empty()
+            );
+            assert!(accumulator.verify(item, &membership_proof));
+        }
+    }
+
     #[tokio::test]
     async fn mutator_set_batch_remove_accumulator_test() {
         // Test the batch-remove function for mutator set accumulator
@@ -256,11 +256,11 @@ impl RemovalRecord {
             .iter_mut()
             .map(|mp| &mut mp.target_chunks)
             .collect();
-        let (_mutated_chunks_by_rr_indices, mutation_argument) =
-            get_batch_mutation_argument_for_removal_record(
-                applied_removal_record,
-                &mut chunk_dictionaries,
-            );
+        let leaf_mutations = get_batch_mutation_argument_for_removal_record(
+            applied_removal_record,
+            &mut chunk_dictionaries,
+        )
+        .leaf_mutations;
 
         // Collect all the MMR membership proofs from the chunk dictionaries.
         let mut own_mmr_mps: Vec<&mut mmr::mmr_membership_proof::MmrMembershipProof<Hash>> = vec![];
@@ -273,7 +273,7 @@ impl RemovalRecord {
         // Perform the batch mutation of the MMR membership proofs
         mmr::mmr_membership_proof::MmrMembershipProof::batch_update_from_batch_leaf_mutation(
             &mut own_mmr_mps,
-            mutation_argument,
+            leaf_mutations,
         );
     }
 
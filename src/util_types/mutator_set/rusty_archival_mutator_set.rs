@@ -3,14 +3,10 @@ use crate::database::storage::storage_schema::{
 };
 use crate::database::NeptuneLevelDb;
 use crate::prelude::twenty_first;
-use crate::Hash;
 
 use twenty_first::math::tip5::Digest;
 
-use super::{
-    active_window::ActiveWindow, archival_mmr::ArchivalMmr,
-    archival_mutator_set::ArchivalMutatorSet, chunk::Chunk,
-};
+use super::{archival_mutator_set::ArchivalMutatorSet, chunk::Chunk};
 
 type AmsMmrStorage = DbtVec<Digest>;
 type AmsChunkStorage = DbtVec<Chunk>;
@@ -38,12 +34,7 @@ impl RustyArchivalMutatorSet {
             .await;
         let sync_label = storage.schema.new_singleton::<Digest>("sync_label").await;
 
-        let ams = ArchivalMutatorSet::<AmsMmrStorage, AmsChunkStorage> {
-            chunks,
-            aocl: ArchivalMmr::<Hash, AmsMmrStorage>::new(aocl).await,
-            swbf_inactive: ArchivalMmr::<Hash, AmsMmrStorage>::new(swbfi).await,
-            swbf_active: ActiveWindow::new(),
-        };
+        let ams = ArchivalMutatorSet::from_storage(aocl, swbfi, chunks).await;
 
         Self {
             ams,
@@ -75,19 +66,17 @@ impl RustyArchivalMutatorSet {
 
     pub async fn restore_or_new(&mut self) {
         // The field `digests` of ArchivalMMR should always have at
-        // least one element (a dummy digest), owing to 1-indexation.
-        self.ams_mut().aocl.fix_dummy_async().await;
-        self.ams_mut().swbf_inactive.fix_dummy_async().await;
-
-        // populate active window
-        self.ams_mut().swbf_active.sbf = self.active_window_storage.get().await;
+        // least one element (a dummy digest), owing to 1-indexation, and the
+        // active window is stored separately.
+        let active_window_sbf = self.active_window_storage.get().await;
+        self.ams_mut().restore(active_window_sbf).await;
     }
 }
 
 impl StorageWriter for RustyArchivalMutatorSet {
     async fn persist(&mut self) {
         self.active_window_storage
-            .set(self.ams().swbf_active.sbf.clone())
+            .set(self.ams().active_window().sbf.clone())
             .await;
 
         self.storage.persist().await;
@@ -98,19 +87,20 @@ impl StorageWriter for RustyArchivalMutatorSet {
 mod tests {
     use itertools::Itertools;
     use rand::{random, thread_rng, RngCore};
-    use twenty_first::math::tip5::Tip5;
 
+    use crate::tests::shared::random_addition_record;
     use crate::util_types::mutator_set::commit;
     use crate::util_types::mutator_set::{
         ms_membership_proof::MsMembershipProof, shared::BATCH_SIZE,
     };
     use crate::util_types::test_shared::mutator_set::*;
+    use crate::Hash;
 
     use super::*;
 
     #[tokio::test]
     async fn persist_test() {
-        type H = Tip5;
+        type H = Hash;
 
         let num_additions = 150 + 2 * BATCH_SIZE as usize;
         let num_removals = 50usize;
@@ -131,7 +121,7 @@ mod tests {
 
         println!(
             "before additions mutator set contains {} elements",
-            rusty_mutator_set.ams().aocl.count_leaves().await
+            rusty_mutator_set.ams().aocl_leaf_count().await
         );
 
         for _ in 0..num_additions {
@@ -157,7 +147,7 @@ mod tests {
 
         println!(
             "after additions mutator set contains {} elements",
-            rusty_mutator_set.ams().aocl.count_leaves().await
+            rusty_mutator_set.ams().aocl_leaf_count().await
         );
 
         // Verify membership
@@ -196,13 +186,13 @@ mod tests {
 
         println!(
             "at persistence mutator set aocl contains {} elements",
-            rusty_mutator_set.ams().aocl.count_leaves().await
+            rusty_mutator_set.ams().aocl_leaf_count().await
         );
 
         // persist and drop
         rusty_mutator_set.persist().await;
 
-        let active_window_before = rusty_mutator_set.ams().swbf_active.clone();
+        let active_window_before = rusty_mutator_set.ams().active_window().clone();
 
         drop(rusty_mutator_set); // Drop DB
 
@@ -217,7 +207,7 @@ mod tests {
         // Verify memberships
         println!(
             "restored mutator set contains {} elements",
-            new_rusty_mutator_set.ams().aocl.count_leaves().await
+            new_rusty_mutator_set.ams().aocl_leaf_count().await
         );
         for (index, (mp, &item)) in mps.iter().zip(items.iter()).enumerate() {
             assert!(
@@ -237,8 +227,43 @@ mod tests {
         let retrieved_sync_label = new_rusty_mutator_set.get_sync_label().await;
         assert_eq!(sync_label, retrieved_sync_label);
 
-        let active_window_after = new_rusty_mutator_set.ams().swbf_active.clone();
+        let active_window_after = new_rusty_mutator_set.ams().active_window().clone();
 
         assert_eq!(active_window_before, active_window_after);
     }
+
+    #[tokio::test]
+    async fn undecodable_chunks_fail_verification_test() {
+        let mut db = NeptuneLevelDb::open_new_test_database(true, None, None, None)
+            .await
+            .unwrap();
+        let mut rusty_mutator_set = RustyArchivalMutatorSet::connect(db.clone()).await;
+        rusty_mutator_set.restore_or_new().await;
+        for _ in 0..4 * BATCH_SIZE {
+            rusty_mutator_set
+                .ams_mut()
+                .add(&random_addition_record())
+                .await;
+        }
+        rusty_mutator_set.persist().await;
+        drop(rusty_mutator_set);
+
+        // Overwrite two chunks with garbage, bypassing the storage vector. The
+        // chunks are the third table of the schema, see `connect`.
+        let chunk_key = |chunk_index: u64| -> RustyKey {
+            (RustyKey::from(2u8), RustyKey::from(chunk_index)).into()
+        };
+        db.put_u8(bincode::serialize(&chunk_key(0)).unwrap(), vec![0xff; 16])
+            .await;
+        db.put(chunk_key(1), RustyValue(vec![0xff; 3])).await;
+
+        // A fresh connection does not have the chunks cached
+        let mut rusty_mutator_set = RustyArchivalMutatorSet::connect(db).await;
+        rusty_mutator_set.restore_or_new().await;
+        let ams = rusty_mutator_set.ams();
+        assert!(ams.chunk_count().await >= 3);
+        assert!(ams.verify_chunk(0).await.is_err());
+        assert!(ams.verify_chunk(1).await.is_err());
+        assert!(ams.verify_chunk(2).await.is_ok());
+    }
 }
@@ -10,11 +10,48 @@ use twenty_first::util_types::mmr::mmr_membership_proof::MmrMembershipProof;
 use super::chunk_dictionary::ChunkDictionary;
 use super::removal_record::RemovalRecord;
 
+/// Number of indices in the active window of the sliding-window Bloom filter.
 pub const WINDOW_SIZE: u32 = 1 << 20;
+
+/// Number of indices in a chunk, i.e. the distance the window slides per batch.
 pub const CHUNK_SIZE: u32 = 1 << 12;
+
+/// Number of additions after which the window slides.
 pub const BATCH_SIZE: u32 = 1 << 3;
+
+/// Number of Bloom filter indices set by each removal.
 pub const NUM_TRIALS: u32 = 45;
 
+/// Whether the parameters satisfy the relationships the implementation relies
+/// on. The window slides one chunk at a time, so it must consist of whole
+/// chunks, and indices are sampled with `AlgebraicHasher::sample_indices`,
+/// which requires the window size to be a power of two.
+const fn parameters_are_consistent(
+    window_size: u32,
+    chunk_size: u32,
+    batch_size: u32,
+    num_trials: u32,
+) -> bool {
+    window_size.is_power_of_two()
+        && chunk_size > 0
+        && chunk_size <= window_size
+        && window_size % chunk_size == 0
+        && batch_size > 0
+        && num_trials > 0
+}
+
+// These parameters are also compiled into the consensus programs, e.g.
+// `ComputeIndices`, so they are checked at compile time.
+const _: () = assert!(parameters_are_consistent(
+    WINDOW_SIZE,
+    CHUNK_SIZE,
+    BATCH_SIZE,
+    NUM_TRIALS
+));
+
+/// Group absolute Bloom filter indices by the index of the chunk they fall
+/// into. Indices are not deduplicated, and within each group they appear in
+/// the order in which they appear in `all_indices`.
 pub fn indices_to_hash_map(all_indices: &[u128; NUM_TRIALS as usize]) -> HashMap<u64, Vec<u128>> {
     all_indices
         .iter()
@@ -25,6 +62,26 @@ pub fn indices_to_hash_map(all_indices: &[u128; NUM_TRIALS as usize]) -> HashMap
         })
 }
 
+/// The authenticated batch modification that results from applying (or
+/// reverting) a removal record to a collection of chunk dictionaries.
+///
+/// The chunks of the chunk dictionaries are mutated in place when the batch
+/// mutation argument is computed, but their MMR membership proofs are not.
+/// Those must be updated by the caller, by applying the `leaf_mutations` with
+/// [`MmrMembershipProof::batch_update_from_batch_leaf_mutation`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchMutationArgument {
+    /// Indices into the input slice of chunk dictionaries of those
+    /// dictionaries that contain at least one chunk that was mutated in place.
+    pub mutated_chunk_dictionaries: HashSet<usize>,
+
+    /// One leaf mutation per modified chunk in the inactive part of the
+    /// sliding window Bloom filter, consisting of the chunk's MMR membership
+    /// proof *before* the modification and the digest of the chunk *after*
+    /// it. Chunks in the active window are not included.
+    pub leaf_mutations: Vec<(MmrMembershipProof<Hash>, Digest)>,
+}
+
 /// Prepare a batch-modification with necessary authentication data
 /// to update the chunk dictionaries of mutator set membership proofs
 /// under application of a removal record.
@@ -35,24 +92,28 @@ pub fn indices_to_hash_map(all_indices: &[u128; NUM_TRIALS as usize]) -> HashMap
 ///  - `chunk_dictionaries`: a slice of (mutable references to)
 ///    the chunk dictionaries into which the new indices are added.
 ///
-/// Returns:
-///  - 0: A hash set of indices, showing which indices are into the chunk dictionaries
-/// which have modified chunks.
-///  - 1: A list of (old membership proof, new digest) where the membership proof
-/// is how it looks before applying the removal record, and the digest is how
-/// it looks after applying the removal record.
+/// Every chunk in `chunk_dictionaries` that is targeted by the removal
+/// record gets the removal record's indices inserted in place, and the
+/// index of its dictionary is recorded in
+/// [`BatchMutationArgument::mutated_chunk_dictionaries`]. The same chunk may
+/// occur in several dictionaries, in which case all of them are updated but
+/// only one leaf mutation is produced for it. Chunks that are targeted by the
+/// removal record but absent from all `chunk_dictionaries` are taken from the
+/// removal record; they contribute a leaf mutation but are not added to any
+/// dictionary. If `chunk_dictionaries` is empty, there is nothing to update
+/// and no leaf mutations are produced. The MMR membership proofs in `chunk_dictionaries` are *not*
+/// updated; that must be handled by the caller.
 ///
-/// This function updates the chunks that are present in the `chunk_dictionaries`
-/// input argument, but not the associated membership proofs. That must be handled
-/// by the caller.
+/// This function assumes that all MMR membership proofs in
+/// `chunk_dictionaries` and in the removal record are valid relative to the
+/// same MMR.
 ///
 /// This function is factored out because it is shared by `update_from_remove`
 /// and `batch_update_from_remove`.
-#[allow(clippy::type_complexity)]
 pub fn get_batch_mutation_argument_for_removal_record(
     removal_record: &RemovalRecord,
     chunk_dictionaries: &mut [&mut ChunkDictionary],
-) -> (HashSet<usize>, Vec<(MmrMembershipProof<Hash>, Digest)>) {
+) -> BatchMutationArgument {
     // chunk index -> (mmr mp, chunk hash)
     let mut batch_modification_hash_map: HashMap<u64, (MmrMembershipProof<Hash>, Digest)> =
         HashMap::new();
@@ -95,7 +156,7 @@ pub fn get_batch_mutation_argument_for_removal_record(
                         Some((mp, chunk)) => {
                             // Since the chunk does not exist in the membership proof, we do not need
                             // to update any chunk value. We only need the new chunk value for the
-                            // mutation argument (2nd element of returned tuple), so we only need to
+                            // leaf mutations of the batch mutation argument, so we only need to
                             // calculate it once.
                             if !batch_modification_hash_map.contains_key(chunk_index) {
                                 let mut target_chunk = chunk.to_owned();
@@ -117,10 +178,10 @@ pub fn get_batch_mutation_argument_for_removal_record(
         }
     }
 
-    (
+    BatchMutationArgument {
         mutated_chunk_dictionaries,
-        batch_modification_hash_map.into_values().collect(),
-    )
+        leaf_mutations: batch_modification_hash_map.into_values().collect(),
+    }
 }
 
 /// Prepare a batch-modification with necessary authentication data
@@ -133,25 +194,19 @@ pub fn get_batch_mutation_argument_for_removal_record(
 ///  - `chunk_dictionaries`: a slice of the chunk dictionaries from
 ///    which the new indices are *removed*.
 ///
-/// Returns:
-///  - 0: A hash set of indices, showing which indices are into the chunk dictionaries
-/// which have modified chunks.
-///  - 1: A list of (old membership proof, new digest) where the membership proof
-/// is how it looks before applying the removal record, and the digest is how
-/// it looks after applying the removal record.
-///
-/// This function updates the chunks that are present in the `chunk_dictionaries`
-/// input argument.
-/// It does not update the associated membership proofs. That must be handled
-/// by the caller also.
+/// Every chunk in `chunk_dictionaries` that is targeted by the removal
+/// record gets the removal record's indices removed in place. Chunks that are
+/// targeted by the removal record but absent from all `chunk_dictionaries`
+/// are taken from the removal record as they are, since those are the values
+/// to revert to. The MMR membership proofs in `chunk_dictionaries` are *not*
+/// updated; that must be handled by the caller.
 ///
 /// This function is factored out because it is shared by
 /// `revert_update_from_remove` and `batch_revert_update_from_remove`.
-#[allow(clippy::type_complexity)]
 pub fn prepare_authenticated_batch_modification_for_removal_record_reversion(
     removal_record: &RemovalRecord,
     chunk_dictionaries: &mut [&mut ChunkDictionary],
-) -> (HashSet<usize>, Vec<(MmrMembershipProof<Hash>, Digest)>) {
+) -> BatchMutationArgument {
     // chunk index -> (mmr mp, chunk hash)
     let mut batch_modification_hash_map: HashMap<u64, (MmrMembershipProof<Hash>, Digest)> =
         HashMap::new();
@@ -193,7 +248,7 @@ pub fn prepare_authenticated_batch_modification_for_removal_record_reversion(
                         Some((mp, chunk)) => {
                             // Since the chunk does not exist in the membership proof, we do not need
                             // to update any chunk value. We only need the new chunk value for the
-                            // mutation argument (2nd element of returned tuple), so we only need to
+                            // leaf mutations of the batch mutation argument, so we only need to
                             // calculate it once.
                             if !batch_modification_hash_map.contains_key(chunk_index) {
                                 let target_chunk = chunk.to_owned();
@@ -216,8 +271,354 @@ pub fn prepare_authenticated_batch_modification_for_removal_record_reversion(
         }
     }
 
-    (
+    BatchMutationArgument {
         mutated_chunk_dictionaries,
-        batch_modification_hash_map.into_values().collect(),
-    )
+        leaf_mutations: batch_modification_hash_map.into_values().collect(),
+    }
+}
+
+#[cfg(test)]
+mod shared_tests {
+    use itertools::Itertools;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest_arbitrary_interop::arb;
+    use test_strategy::proptest;
+    use twenty_first::util_types::mmr::mmr_trait::Mmr;
+
+    use super::super::archival_mmr::mmr_test::mock;
+    use super::super::chunk::Chunk;
+    use super::super::mmra_and_membership_proofs::MmraAndMembershipProofs;
+    use super::super::removal_record::AbsoluteIndexSet;
+    use super::*;
+
+    #[test]
+    fn small_parameters_are_consistent_only_within_bounds_test() {
+        assert!(parameters_are_consistent(16, 4, 2, 3));
+
+        // The window may consist of a single chunk, but not of less than one
+        assert!(parameters_are_consistent(16, 16, 2, 3));
+        assert!(!parameters_are_consistent(16, 32, 2, 3));
+        assert!(!parameters_are_consistent(16, 0, 2, 3));
+
+        // The window must be a power of two, and consist of whole chunks
+        assert!(!parameters_are_consistent(12, 4, 2, 3));
+        assert!(!parameters_are_consistent(16, 3, 2, 3));
+        assert!(!parameters_are_consistent(16, 6, 2, 3));
+        assert!(parameters_are_consistent(1, 1, 1, 1));
+        assert!(!parameters_are_consistent(0, 1, 1, 1));
+
+        // Every batch and every removal involve something
+        assert!(!parameters_are_consistent(16, 4, 0, 3));
+        assert!(!parameters_are_consistent(16, 4, 2, 0));
+    }
+
+    /// Return `num_chunks` distinct chunks with valid MMR membership proofs
+    /// relative to an MMR of their digests. The chunk index is the leaf index.
+    async fn chunks_and_membership_proofs(
+        num_chunks: u64,
+    ) -> HashMap<u64, (MmrMembershipProof<Hash>, Chunk)> {
+        let chunks = (0..num_chunks)
+            .map(|chunk_index| Chunk::from_indices(&[chunk_index as u32]))
+            .collect_vec();
+        let leafs = chunks.iter().map(Hash::hash).collect_vec();
+        let archival_mmr = mock::get_ammr_from_digests::<Hash>(leafs).await;
+
+        let mut chunks_and_mps = HashMap::new();
+        for (chunk_index, chunk) in (0..num_chunks).zip(chunks) {
+            let mp = archival_mmr.prove_membership_async(chunk_index).await;
+            chunks_and_mps.insert(chunk_index, (mp, chunk));
+        }
+
+        chunks_and_mps
+    }
+
+    /// Return a removal record whose absolute indices are spread over the
+    /// targeted chunks, and whose target chunks are those of the targeted
+    /// chunks that are present in `chunks_and_mps`.
+    fn removal_record_targeting(
+        targeted_chunk_indices: &[u64],
+        relative_indices: &[u32],
+        chunks_and_mps: &HashMap<u64, (MmrMembershipProof<Hash>, Chunk)>,
+    ) -> RemovalRecord {
+        let absolute_indices: [u128; NUM_TRIALS as usize] = (0..NUM_TRIALS as usize)
+            .map(|i| {
+                let chunk_index = targeted_chunk_indices[i % targeted_chunk_indices.len()];
+                let relative_index = relative_indices[i % relative_indices.len()];
+                chunk_index as u128 * CHUNK_SIZE as u128 + relative_index as u128
+            })
+            .collect_vec()
+            .try_into()
+            .unwrap();
+        let target_chunks = targeted_chunk_indices
+            .iter()
+            .filter_map(|chunk_index| {
+                chunks_and_mps
+                    .get(chunk_index)
+                    .map(|mp_and_chunk| (*chunk_index, mp_and_chunk.clone()))
+            })
+            .collect();
+
+        RemovalRecord {
+            absolute_indices: AbsoluteIndexSet::new(&absolute_indices),
+            target_chunks: ChunkDictionary::new(target_chunks),
+        }
+    }
+
+    fn chunk_dictionary_with(
+        chunk_indices: &[u64],
+        chunks_and_mps: &HashMap<u64, (MmrMembershipProof<Hash>, Chunk)>,
+    ) -> ChunkDictionary {
+        ChunkDictionary::new(
+            chunk_indices
+                .iter()
+                .map(|chunk_index| (*chunk_index, chunks_and_mps[chunk_index].clone()))
+                .collect(),
+        )
+    }
+
+    /// Return the chunk with the given index after application of the removal
+    /// record.
+    fn updated_chunk(chunk_index: u64, chunk: &Chunk, removal_record: &RemovalRecord) -> Chunk {
+        let mut updated_chunk = chunk.clone();
+        for index in removal_record.get_chunkidx_to_indices_dict()[&chunk_index].iter() {
+            updated_chunk.insert((index % CHUNK_SIZE as u128) as u32);
+        }
+
+        updated_chunk
+    }
+
+    fn leaf_mutations_sorted_by_leaf_index(
+        batch_mutation_argument: &BatchMutationArgument,
+    ) -> Vec<(MmrMembershipProof<Hash>, Digest)> {
+        batch_mutation_argument
+            .leaf_mutations
+            .iter()
+            .cloned()
+            .sorted_by_key(|(mp, _new_leaf)| mp.leaf_index)
+            .collect_vec()
+    }
+
+    #[test]
+    fn indices_to_hash_map_groups_indices_by_chunk_test() {
+        let all_indices: [u128; NUM_TRIALS as usize] = (0..NUM_TRIALS as u128)
+            .map(|i| (i % 3) * CHUNK_SIZE as u128 + i)
+            .collect_vec()
+            .try_into()
+            .unwrap();
+        let chunk_index_to_indices = indices_to_hash_map(&all_indices);
+
+        assert_eq!(3, chunk_index_to_indices.len());
+        for (chunk_index, indices) in chunk_index_to_indices {
+            assert_eq!(NUM_TRIALS as usize / 3, indices.len());
+            assert!(indices
+                .iter()
+                .all(|index| index / CHUNK_SIZE as u128 == chunk_index as u128));
+            assert!(indices.iter().tuple_windows().all(|(a, b)| a < b));
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_shared_by_several_chunk_dictionaries_are_mutated_in_all_of_them_test() {
+        let chunks_and_mps = chunks_and_membership_proofs(5).await;
+        let removal_record = removal_record_targeting(&[0, 1, 2], &[100, 200], &chunks_and_mps);
+
+        // Chunk 1 is in both the first and the second dictionary, and the
+        // third dictionary only contains chunks that are not targeted.
+        let mut chunk_dictionaries = [
+            chunk_dictionary_with(&[0, 1], &chunks_and_mps),
+            chunk_dictionary_with(&[1, 2], &chunks_and_mps),
+            chunk_dictionary_with(&[3, 4], &chunks_and_mps),
+        ];
+        let mut chunk_dictionary_refs = chunk_dictionaries.iter_mut().collect_vec();
+        let batch_mutation_argument = get_batch_mutation_argument_for_removal_record(
+            &removal_record,
+            &mut chunk_dictionary_refs,
+        );
+
+        assert_eq!(
+            HashSet::from([0, 1]),
+            batch_mutation_argument.mutated_chunk_dictionaries
+        );
+        for chunk_dictionary in chunk_dictionaries.iter() {
+            for (chunk_index, (mp, chunk)) in chunk_dictionary.dictionary.iter() {
+                let (old_mp, old_chunk) = &chunks_and_mps[chunk_index];
+                assert_eq!(old_mp, mp, "MMR membership proofs must not be updated");
+                let expected_chunk = if *chunk_index < 3 {
+                    updated_chunk(*chunk_index, old_chunk, &removal_record)
+                } else {
+                    old_chunk.clone()
+                };
+                assert_eq!(&expected_chunk, chunk);
+            }
+        }
+
+        // One leaf mutation per targeted chunk, even if it occurs in several dictionaries
+        let leaf_mutations = leaf_mutations_sorted_by_leaf_index(&batch_mutation_argument);
+        assert_eq!(3, leaf_mutations.len());
+        for (chunk_index, (mp, new_leaf)) in (0..3).zip(leaf_mutations) {
+            let (old_mp, old_chunk) = &chunks_and_mps[&chunk_index];
+            assert_eq!(old_mp, &mp);
+            assert_eq!(
+                Hash::hash(&updated_chunk(chunk_index, old_chunk, &removal_record)),
+                new_leaf
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_absent_from_chunk_dictionaries_are_taken_from_removal_record_test() {
+        let chunks_and_mps = chunks_and_membership_proofs(4).await;
+
+        // Chunk 7 is not in the MMR, so its indices live in the active window
+        let removal_record = removal_record_targeting(&[0, 1, 7], &[300], &chunks_and_mps);
+        assert_eq!(2, removal_record.target_chunks.dictionary.len());
+
+        let mut chunk_dictionaries = [
+            chunk_dictionary_with(&[0], &chunks_and_mps),
+            ChunkDictionary::default(),
+            chunk_dictionary_with(&[3], &chunks_and_mps),
+        ];
+        let mut chunk_dictionary_refs = chunk_dictionaries.iter_mut().collect_vec();
+        let batch_mutation_argument = get_batch_mutation_argument_for_removal_record(
+            &removal_record,
+            &mut chunk_dictionary_refs,
+        );
+
+        assert_eq!(
+            HashSet::from([0]),
+            batch_mutation_argument.mutated_chunk_dictionaries
+        );
+        assert_eq!(chunks_and_mps[&0].0, chunk_dictionaries[0].dictionary[&0].0);
+        assert_eq!(
+            updated_chunk(0, &chunks_and_mps[&0].1, &removal_record),
+            chunk_dictionaries[0].dictionary[&0].1
+        );
+        assert!(
+            !chunk_dictionaries[0].dictionary.contains_key(&1),
+            "Chunks from the removal record must not be added to chunk dictionaries"
+        );
+        assert!(chunk_dictionaries[1].dictionary.is_empty());
+        assert_eq!(
+            chunk_dictionary_with(&[3], &chunks_and_mps),
+            chunk_dictionaries[2]
+        );
+
+        // Chunk 1 is mutated as found in the removal record, and chunk 7 is
+        // not mutated in the MMR at all.
+        let leaf_mutations = leaf_mutations_sorted_by_leaf_index(&batch_mutation_argument);
+        assert_eq!(2, leaf_mutations.len());
+        for (chunk_index, (mp, new_leaf)) in (0..2).zip(leaf_mutations) {
+            let (old_mp, old_chunk) = &removal_record.target_chunks.dictionary[&chunk_index];
+            assert_eq!(old_mp, &mp);
+            assert_eq!(
+                Hash::hash(&updated_chunk(chunk_index, old_chunk, &removal_record)),
+                new_leaf
+            );
+        }
+    }
+
+    fn chunk_indices_strategy(max: u64, num: usize) -> BoxedStrategy<Vec<u64>> {
+        vec(0u64..max, num)
+            .prop_filter("chunk indices must all be unique", |chunk_indices| {
+                chunk_indices.iter().all_unique()
+            })
+            .boxed()
+    }
+
+    #[proptest(cases = 20)]
+    fn batch_mutation_argument_agrees_with_sequential_leaf_mutations(
+        #[strategy(1usize..5)] _num_chunks: usize,
+        #[strategy(#_num_chunks as u64..1000)] _total_leaf_count: u64,
+        #[strategy(chunk_indices_strategy(#_total_leaf_count, #_num_chunks))] chunk_indices: Vec<
+            u64,
+        >,
+        #[strategy(vec(arb::<Chunk>(), #_num_chunks))] chunks: Vec<Chunk>,
+        #[strategy(MmraAndMembershipProofs::arbitrary_with((
+            #chunk_indices.iter().copied().zip(#chunks.iter().map(Hash::hash)).collect_vec(),
+            #_total_leaf_count,
+        )))]
+        mmra_and_membership_proofs: MmraAndMembershipProofs,
+        #[strategy(vec(0u32..CHUNK_SIZE, 1..=NUM_TRIALS as usize))] relative_indices: Vec<u32>,
+        #[strategy(vec(vec(any::<bool>(), #_num_chunks), 1..4))] dictionary_membership: Vec<
+            Vec<bool>,
+        >,
+    ) {
+        let MmraAndMembershipProofs {
+            mmra,
+            membership_proofs,
+        } = mmra_and_membership_proofs;
+        let chunks_and_mps: HashMap<u64, (MmrMembershipProof<Hash>, Chunk)> = chunk_indices
+            .iter()
+            .copied()
+            .zip(membership_proofs.into_iter().zip(chunks))
+            .collect();
+        let removal_record =
+            removal_record_targeting(&chunk_indices, &relative_indices, &chunks_and_mps);
+
+        // Every chunk dictionary contains an arbitrary subset of the targeted chunks
+        let mut chunk_dictionaries = dictionary_membership
+            .iter()
+            .map(|is_member| {
+                let members = chunk_indices
+                    .iter()
+                    .zip(is_member)
+                    .filter(|(_chunk_index, is_member)| **is_member)
+                    .map(|(chunk_index, _is_member)| *chunk_index)
+                    .collect_vec();
+                chunk_dictionary_with(&members, &chunks_and_mps)
+            })
+            .collect_vec();
+        let mut chunk_dictionary_refs = chunk_dictionaries.iter_mut().collect_vec();
+        let BatchMutationArgument {
+            mutated_chunk_dictionaries,
+            leaf_mutations,
+        } = get_batch_mutation_argument_for_removal_record(
+            &removal_record,
+            &mut chunk_dictionary_refs,
+        );
+
+        for (i, chunk_dictionary) in chunk_dictionaries.iter().enumerate() {
+            prop_assert_eq!(
+                !chunk_dictionary.dictionary.is_empty(),
+                mutated_chunk_dictionaries.contains(&i)
+            );
+            for (chunk_index, (_mp, chunk)) in chunk_dictionary.dictionary.iter() {
+                let old_chunk = &chunks_and_mps[chunk_index].1;
+                prop_assert_eq!(
+                    &updated_chunk(*chunk_index, old_chunk, &removal_record),
+                    chunk
+                );
+            }
+        }
+
+        // Mutate the leafs one by one, keeping the membership proofs of the
+        // remaining leafs in sync
+        let mut sequentially_mutated_mmra = mmra.clone();
+        let mut remaining_mutations = chunks_and_mps
+            .iter()
+            .map(|(chunk_index, (mp, chunk))| {
+                let new_leaf = Hash::hash(&updated_chunk(*chunk_index, chunk, &removal_record));
+                (mp.clone(), new_leaf)
+            })
+            .collect_vec();
+        while let Some((mp, new_leaf)) = remaining_mutations.pop() {
+            sequentially_mutated_mmra.mutate_leaf(&mp, new_leaf);
+            let mut remaining_mps = remaining_mutations
+                .iter_mut()
+                .map(|(remaining_mp, _new_leaf)| remaining_mp)
+                .collect_vec();
+            MmrMembershipProof::batch_update_from_batch_leaf_mutation(
+                &mut remaining_mps,
+                vec![(mp, new_leaf)],
+            );
+        }
+
+        let mut batch_mutated_mmra = mmra;
+        batch_mutated_mmra.batch_mutate_leaf_and_update_mps(&mut [], leaf_mutations);
+        prop_assert_eq!(
+            sequentially_mutated_mmra.get_peaks(),
+            batch_mutated_mmra.get_peaks()
+        );
+    }
 }
@@ -45,13 +45,13 @@ pub async fn get_all_indices_with_duplicates<
 ) -> Vec<u128> {
     let mut ret: Vec<u128> = vec![];
 
-    for index in archival_mutator_set.swbf_active.sbf.iter() {
+    for index in archival_mutator_set.active_window().sbf.iter() {
         ret.push(*index as u128);
     }
 
-    let chunk_count = archival_mutator_set.chunks.len().await;
+    let chunk_count = archival_mutator_set.chunk_count().await;
     for chunk_index in 0..chunk_count {
-        let chunk = archival_mutator_set.chunks.get(chunk_index).await;
+        let chunk = archival_mutator_set.get_chunk(chunk_index).await;
         for index in chunk.relative_indices.iter() {
             ret.push(*index as u128 + CHUNK_SIZE as u128 * chunk_index as u128);
         }